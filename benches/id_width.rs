@@ -0,0 +1,93 @@
+//! Compares multiplexing with u16 and u64 request ids.
+//!
+//! Frames carrying narrower ids are smaller, so more of them fit in the
+//! cache lines the queues of a connection and its in-flight bookkeeping go
+//! through.
+
+#[macro_use]
+extern crate bencher;
+extern crate tokio_proto;
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::mem;
+
+use bencher::{black_box, Bencher};
+use tokio_proto::streaming::multiplex::{Counter, Frame, RequestIdSource};
+
+// Small messages, as most frames of a busy connection are
+type SmallFrame<Id> = Frame<Id, u32, u32, ()>;
+
+const FRAMES: usize = 4096;
+
+fn frames<Id>() -> VecDeque<SmallFrame<Id>>
+    where Id: Default,
+          Counter<Id>: RequestIdSource<Id, u32>,
+{
+    let mut counter = Counter::new();
+
+    (0..FRAMES as u32).map(|message| {
+        Frame::Message {
+            id: counter.next(&message),
+            message: message,
+            body: false,
+            solo: false,
+        }
+    }).collect()
+}
+
+// Moves the frames through a queue, as the dispatcher does between the
+// transport and the services
+fn queue<Id>(b: &mut Bencher)
+    where Id: Default,
+          Counter<Id>: RequestIdSource<Id, u32>,
+{
+    let mut input = frames::<Id>();
+    let mut output = VecDeque::with_capacity(FRAMES);
+
+    b.bytes = (FRAMES * mem::size_of::<SmallFrame<Id>>()) as u64;
+    b.iter(|| {
+        output.extend(input.drain(..));
+        mem::swap(&mut input, &mut output);
+        black_box(&input);
+    });
+}
+
+// Looks up the exchange of each response among those in flight
+fn in_flight<Id>(b: &mut Bencher)
+    where Id: Default + Hash + Eq + Copy,
+          Counter<Id>: RequestIdSource<Id, u32>,
+{
+    let mut counter = Counter::<Id>::new();
+    let ids: Vec<Id> = (0..FRAMES as u32).map(|i| counter.next(&i)).collect();
+    let in_flight: HashMap<Id, u32> = ids.iter().map(|id| (*id, 0)).collect();
+
+    b.iter(|| {
+        let mut found = 0;
+
+        for id in &ids {
+            found += in_flight[id];
+        }
+
+        black_box(found)
+    });
+}
+
+fn queue_u16(b: &mut Bencher) {
+    queue::<u16>(b)
+}
+
+fn queue_u64(b: &mut Bencher) {
+    queue::<u64>(b)
+}
+
+fn in_flight_u16(b: &mut Bencher) {
+    in_flight::<u16>(b)
+}
+
+fn in_flight_u64(b: &mut Bencher) {
+    in_flight::<u64>(b)
+}
+
+benchmark_group!(benches, queue_u16, queue_u64, in_flight_u16, in_flight_u64);
+benchmark_main!(benches);
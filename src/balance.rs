@@ -0,0 +1,381 @@
+//! Weighted balancing of requests across endpoints.
+//!
+//! A `Balancer` spreads requests across a set of endpoint services, e.g. a
+//! `pool::Client` per backend address, in proportion to their weights. An
+//! endpoint of weight 5 next to one of weight 95 receives 5% of the
+//! requests, as is common for a canary. Endpoints are picked with smooth
+//! weighted round-robin: the picks are interleaved as evenly as the weights
+//! allow rather than drawn at random, so the shares hold over short windows
+//! as well.
+//!
+//! Weights are given when adding the endpoints and can be adjusted at any
+//! time through a `BalancerHandle`, which can be sent to other threads, so
+//! that deployment tooling can shift traffic gradually. An endpoint of
+//! weight 0 receives no requests; calls fail if every endpoint has weight 0.
+//!
+//! In deployments spanning several zones, e.g. availability zones, endpoints
+//! can be added along with their zone. Once the zone of the balancer itself
+//! is set with `local_zone`, requests stay within that zone as long as one
+//! of its endpoints is available, avoiding the latency and the cost of
+//! crossing zones. An endpoint is unavailable while it is saturated, having
+//! `max_in_flight` requests in flight, and for a while after a call to it
+//! failed. When none of the local endpoints is available, requests spill
+//! over to the available endpoints of the other zones, and to every
+//! endpoint once none is available anywhere.
+
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
+use futures::future::{self, Either, FutureResult};
+use tokio_service::Service;
+
+/// How many seconds an endpoint is avoided after a failed call, by default.
+pub const DEFAULT_EJECT_SECS: u64 = 5;
+
+/// A client service balancing requests across weighted endpoints.
+///
+/// See the module documentation for details.
+pub struct Balancer<K, S> {
+    endpoints: Vec<S>,
+    state: Arc<Mutex<State<K>>>,
+}
+
+/// Adjusts the weights of the endpoints of a `Balancer`.
+///
+/// Handles can be cloned and sent to other threads.
+pub struct BalancerHandle<K> {
+    state: Arc<Mutex<State<K>>>,
+}
+
+/// Response future of a `Balancer`.
+pub struct BalancerFuture<K, F: Future> {
+    inner: Either<F, FutureResult<F::Item, F::Error>>,
+    // The balancer and the index of the endpoint called, until the call is
+    // over
+    call: Option<(Arc<Mutex<State<K>>>, usize)>,
+}
+
+// Weights of the endpoints, indexed like the endpoint services
+struct State<K> {
+    weights: Vec<Weight<K>>,
+    local_zone: Option<String>,
+    max_in_flight: Option<usize>,
+    eject_for: Duration,
+}
+
+struct Weight<K> {
+    key: K,
+    weight: u32,
+    // Credit accumulated by smooth weighted round-robin
+    current: i64,
+    zone: Option<String>,
+    in_flight: usize,
+    // Set after a failed call, until the endpoint is tried again
+    ejected_until: Option<Instant>,
+}
+
+impl<K: PartialEq, S> Balancer<K, S> {
+    /// Create a new `Balancer` without endpoints.
+    pub fn new() -> Balancer<K, S> {
+        Balancer {
+            endpoints: Vec::new(),
+            state: Arc::new(Mutex::new(State {
+                weights: Vec::new(),
+                local_zone: None,
+                max_in_flight: None,
+                eject_for: Duration::from_secs(DEFAULT_EJECT_SECS),
+            })),
+        }
+    }
+
+    /// Add the endpoint `service`, identified by `key`, receiving requests
+    /// in proportion to `weight`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an endpoint with the same key was already added.
+    pub fn endpoint(self, key: K, service: S, weight: u32) -> Balancer<K, S> {
+        self.add(key, None, service, weight)
+    }
+
+    /// Add the endpoint `service` of zone `zone`, identified by `key`,
+    /// receiving requests in proportion to `weight`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an endpoint with the same key was already added.
+    pub fn endpoint_in_zone(self, key: K, zone: &str, service: S, weight: u32) -> Balancer<K, S> {
+        self.add(key, Some(zone.to_string()), service, weight)
+    }
+
+    /// Set the zone the balancer runs in, keeping requests within the zone
+    /// while any of its endpoints is available.
+    ///
+    /// See the module documentation for details. By default endpoints are
+    /// picked regardless of their zone.
+    pub fn local_zone(self, zone: &str) -> Balancer<K, S> {
+        self.state.lock().unwrap().local_zone = Some(zone.to_string());
+        self
+    }
+
+    /// Set the number of requests in flight at which an endpoint is
+    /// saturated, spilling further requests over to the other zones.
+    ///
+    /// Only applies with a local zone. Defaults to no limit.
+    pub fn max_in_flight(self, max: usize) -> Balancer<K, S> {
+        self.state.lock().unwrap().max_in_flight = Some(max);
+        self
+    }
+
+    /// Set how long an endpoint is avoided after a failed call.
+    ///
+    /// Only applies with a local zone. Defaults to `DEFAULT_EJECT_SECS`
+    /// seconds.
+    pub fn eject_for(self, duration: Duration) -> Balancer<K, S> {
+        self.state.lock().unwrap().eject_for = duration;
+        self
+    }
+
+    /// Returns a handle adjusting the weights of the endpoints.
+    pub fn handle(&self) -> BalancerHandle<K> {
+        BalancerHandle { state: self.state.clone() }
+    }
+
+    fn add(mut self, key: K, zone: Option<String>, service: S, weight: u32) -> Balancer<K, S> {
+        {
+            let mut state = self.state.lock().unwrap();
+
+            assert!(state.position(&key).is_none(), "endpoint added twice");
+
+            state.weights.push(Weight {
+                key: key,
+                weight: weight,
+                current: 0,
+                zone: zone,
+                in_flight: 0,
+                ejected_until: None,
+            });
+        }
+
+        self.endpoints.push(service);
+        self
+    }
+
+    // Returns the index of the next endpoint to call, if any has a weight
+    fn pick(&self) -> Option<usize> {
+        let mut state = self.state.lock().unwrap();
+        let candidates = state.candidates(Instant::now());
+        let mut total = 0;
+        let mut best: Option<(usize, i64)> = None;
+
+        for (i, weight) in state.weights.iter_mut().enumerate() {
+            if weight.weight == 0 || !candidates[i] {
+                continue;
+            }
+
+            weight.current += weight.weight as i64;
+            total += weight.weight as i64;
+
+            let better = match best {
+                Some((_, current)) => weight.current > current,
+                None => true,
+            };
+
+            if better {
+                best = Some((i, weight.current));
+            }
+        }
+
+        best.map(|(i, _)| {
+            state.weights[i].current -= total;
+            state.weights[i].in_flight += 1;
+            i
+        })
+    }
+}
+
+impl<K: PartialEq, S> Default for Balancer<K, S> {
+    fn default() -> Balancer<K, S> {
+        Balancer::new()
+    }
+}
+
+impl<K: PartialEq + Clone> BalancerHandle<K> {
+    /// Set the weight of the endpoint identified by `key`.
+    ///
+    /// Takes effect on the next request. Returns false if there is no such
+    /// endpoint.
+    pub fn set_weight(&self, key: &K, weight: u32) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        match state.position(key) {
+            Some(i) => {
+                state.weights[i].weight = weight;
+
+                // Start over, so that the new weights apply evenly
+                for weight in &mut state.weights {
+                    weight.current = 0;
+                }
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the weight of the endpoint identified by `key`.
+    pub fn weight(&self, key: &K) -> Option<u32> {
+        let state = self.state.lock().unwrap();
+        state.position(key).map(|i| state.weights[i].weight)
+    }
+
+    /// Returns the keys and weights of all endpoints.
+    pub fn weights(&self) -> Vec<(K, u32)> {
+        let state = self.state.lock().unwrap();
+        state.weights.iter().map(|w| (w.key.clone(), w.weight)).collect()
+    }
+}
+
+impl<K> Clone for BalancerHandle<K> {
+    fn clone(&self) -> BalancerHandle<K> {
+        BalancerHandle { state: self.state.clone() }
+    }
+}
+
+impl<K: PartialEq> State<K> {
+    fn position(&self, key: &K) -> Option<usize> {
+        self.weights.iter().position(|w| w.key == *key)
+    }
+}
+
+impl<K> State<K> {
+    // Flags the endpoints to pick from: the available endpoints of the local
+    // zone, else the available endpoints of the other zones, else all
+    fn candidates(&self, now: Instant) -> Vec<bool> {
+        let local = match self.local_zone {
+            Some(ref zone) => zone,
+            None => return vec![true; self.weights.len()],
+        };
+
+        let available = self.weights.iter()
+            .map(|w| w.weight > 0 && self.is_available(w, now))
+            .collect::<Vec<_>>();
+
+        let in_zone = self.weights.iter()
+            .zip(&available)
+            .map(|(w, &available)| available && w.zone.as_ref() == Some(local))
+            .collect::<Vec<_>>();
+
+        if in_zone.contains(&true) {
+            in_zone
+        } else if available.contains(&true) {
+            trace!("no local endpoint available; spilling over");
+            available
+        } else {
+            vec![true; self.weights.len()]
+        }
+    }
+
+    fn is_available(&self, weight: &Weight<K>, now: Instant) -> bool {
+        if let Some(max) = self.max_in_flight {
+            if weight.in_flight >= max {
+                return false;
+            }
+        }
+
+        match weight.ejected_until {
+            Some(until) => until <= now,
+            None => true,
+        }
+    }
+
+    // Records the end of a call to endpoint `i`, if known whether it failed
+    fn finish(&mut self, i: usize, failed: Option<bool>) {
+        let eject_for = self.eject_for;
+        let weight = &mut self.weights[i];
+
+        weight.in_flight -= 1;
+
+        match failed {
+            Some(true) => weight.ejected_until = Some(Instant::now() + eject_for),
+            Some(false) => weight.ejected_until = None,
+            None => {}
+        }
+    }
+}
+
+impl<K, S> Service for Balancer<K, S>
+    where K: PartialEq,
+          S: Service,
+          S::Error: From<io::Error>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BalancerFuture<K, S::Future>;
+
+    fn call(&self, req: S::Request) -> Self::Future {
+        match self.pick() {
+            Some(i) => {
+                BalancerFuture {
+                    inner: Either::A(self.endpoints[i].call(req)),
+                    call: Some((self.state.clone(), i)),
+                }
+            }
+            None => {
+                let err = io::Error::new(io::ErrorKind::Other, "no endpoint with a weight");
+
+                BalancerFuture {
+                    inner: Either::B(future::err(err.into())),
+                    call: None,
+                }
+            }
+        }
+    }
+}
+
+impl<K, F: Future> BalancerFuture<K, F> {
+    fn finish(&mut self, failed: Option<bool>) {
+        if let Some((state, i)) = self.call.take() {
+            state.lock().unwrap().finish(i, failed);
+        }
+    }
+}
+
+impl<K, F: Future> Future for BalancerFuture<K, F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        match self.inner.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(res)) => {
+                self.finish(Some(false));
+                Ok(Async::Ready(res))
+            }
+            Err(e) => {
+                self.finish(Some(true));
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<K, F: Future> Drop for BalancerFuture<K, F> {
+    fn drop(&mut self) {
+        self.finish(None);
+    }
+}
+
+impl<K: fmt::Debug, S> fmt::Debug for Balancer<K, S> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+
+        fmt.debug_map()
+           .entries(state.weights.iter().map(|w| (&w.key, w.weight)))
+           .finish()
+    }
+}
@@ -32,6 +32,12 @@ impl<S: Sink> BufferOne<S> {
         &mut self.sink
     }
 
+    /// Consumes `self`, returning the inner sink. A buffered item is
+    /// dropped.
+    pub fn into_inner(self) -> S {
+        self.sink
+    }
+
     pub fn poll_ready(&mut self) -> Async<()> {
         if self.buf.is_none() {
             return Async::Ready(());
@@ -0,0 +1,219 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io;
+use std::rc::Rc;
+
+use futures::{Async, Future, Poll};
+use futures::future::{MapErr, Shared};
+use tokio_service::Service;
+
+/// Shares a single exchange between identical requests in flight.
+///
+/// Every request is given a key by a user provided function. While a
+/// request with the same key is waiting on its response, the request is not
+/// sent again: the caller joins the exchange in flight instead, and every
+/// caller is resolved with a clone of its response. Once the response is
+/// in, the next request with that key is sent to the inner service again,
+/// so no response is ever cached.
+///
+/// Request storms for the same hot key, common in front of read-heavy
+/// caches, thereby cost one round trip instead of one per caller. Requests
+/// that must not be shared, e.g. writes, are given no key.
+///
+/// Errors are shared as well, with every caller getting an `io::Error` of
+/// the same kind and message.
+pub struct Coalesce<S: Service, K, F> {
+    inner: S,
+    key: F,
+    state: Rc<RefCell<State<K, S::Future>>>,
+}
+
+/// Response future of a `Coalesce` service.
+pub struct CoalesceFuture<K: Hash + Eq, F: Future> {
+    future: Shared<Exchange<F>>,
+    // Key of the joined exchange and its id, until resolved
+    key: Option<(K, u64)>,
+    state: Rc<RefCell<State<K, F>>>,
+}
+
+type Exchange<F> = MapErr<F, fn(<F as Future>::Error) -> io::Error>;
+
+struct State<K, F: Future> {
+    next_id: u64,
+    coalesced: u64,
+    in_flight: HashMap<K, Entry<F>>,
+}
+
+struct Entry<F: Future> {
+    // Tells the exchange apart from later ones with the same key
+    id: u64,
+    future: Shared<Exchange<F>>,
+    waiters: usize,
+}
+
+impl<S: Service, K, F> Coalesce<S, K, F>
+    where K: Hash + Eq,
+          F: Fn(&S::Request) -> Option<K>,
+{
+    /// Create a new `Coalesce` sharing the exchanges of `inner` between the
+    /// requests `key` maps to the same key. Requests mapped to `None` are
+    /// always sent.
+    pub fn new(inner: S, key: F) -> Coalesce<S, K, F> {
+        Coalesce {
+            inner: inner,
+            key: key,
+            state: Rc::new(RefCell::new(State {
+                next_id: 0,
+                coalesced: 0,
+                in_flight: HashMap::new(),
+            })),
+        }
+    }
+}
+
+impl<S: Service, K, F> Coalesce<S, K, F> {
+    /// Returns the number of exchanges in flight that can be joined.
+    pub fn in_flight(&self) -> usize {
+        self.state.borrow().in_flight.len()
+    }
+
+    /// Returns the number of requests that joined an exchange in flight
+    /// instead of being sent.
+    pub fn coalesced(&self) -> u64 {
+        self.state.borrow().coalesced
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Consumes the middleware, returning the inner service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn send(&self, req: S::Request) -> Shared<Exchange<S::Future>>
+        where S::Error: Into<io::Error>,
+    {
+        let into: fn(S::Error) -> io::Error = Into::into;
+        self.inner.call(req).map_err(into).shared()
+    }
+}
+
+impl<S, K, F> Service for Coalesce<S, K, F>
+    where S: Service,
+          S::Response: Clone,
+          S::Error: Into<io::Error>,
+          K: Hash + Eq + Clone,
+          F: Fn(&S::Request) -> Option<K>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = io::Error;
+    type Future = CoalesceFuture<K, S::Future>;
+
+    fn call(&self, req: S::Request) -> Self::Future {
+        let key = match (self.key)(&req) {
+            Some(key) => key,
+            None => {
+                return CoalesceFuture {
+                    future: self.send(req),
+                    key: None,
+                    state: self.state.clone(),
+                };
+            }
+        };
+
+        {
+            let mut state = self.state.borrow_mut();
+            let state = &mut *state;
+
+            if let Some(entry) = state.in_flight.get_mut(&key) {
+                trace!("joining exchange in flight; id={}", entry.id);
+                entry.waiters += 1;
+                state.coalesced += 1;
+
+                return CoalesceFuture {
+                    future: entry.future.clone(),
+                    key: Some((key, entry.id)),
+                    state: self.state.clone(),
+                };
+            }
+        }
+
+        // Not borrowing the state while calling into the inner service
+        let future = self.send(req);
+
+        let mut state = self.state.borrow_mut();
+        let id = state.next_id;
+        state.next_id += 1;
+
+        state.in_flight.insert(key.clone(), Entry {
+            id: id,
+            future: future.clone(),
+            waiters: 1,
+        });
+
+        CoalesceFuture {
+            future: future,
+            key: Some((key, id)),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<K, F> CoalesceFuture<K, F>
+    where K: Hash + Eq,
+          F: Future,
+{
+    // Leaves the exchange, which can no longer be joined once resolved or
+    // once no caller is waiting on it
+    fn leave(&mut self, resolved: bool) {
+        let (key, id) = match self.key.take() {
+            Some(key) => key,
+            None => return,
+        };
+
+        let mut state = self.state.borrow_mut();
+
+        let remove = match state.in_flight.get_mut(&key) {
+            Some(entry) if entry.id == id => {
+                entry.waiters -= 1;
+                resolved || entry.waiters == 0
+            }
+            _ => false,
+        };
+
+        if remove {
+            state.in_flight.remove(&key);
+        }
+    }
+}
+
+impl<K, F> Future for CoalesceFuture<K, F>
+    where K: Hash + Eq,
+          F: Future,
+          F::Item: Clone,
+{
+    type Item = F::Item;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, io::Error> {
+        let res = match self.future.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(item)) => Ok(Async::Ready((*item).clone())),
+            Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+        };
+
+        self.leave(true);
+        res
+    }
+}
+
+impl<K: Hash + Eq, F: Future> Drop for CoalesceFuture<K, F> {
+    fn drop(&mut self) {
+        self.leave(false);
+    }
+}
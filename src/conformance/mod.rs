@@ -0,0 +1,145 @@
+//! Conformance suites validating protocol implementations.
+//!
+//! A protocol author implements the client and server halves of a protocol,
+//! along with a fixture telling the suite how to build requests and how to
+//! check what comes back. The suite binds both halves over an in-memory
+//! `Pipe` and runs a battery of exchanges through the dispatchers, reporting
+//! the cases the protocol got wrong:
+//!
+//! ```rust,ignore
+//! #[test]
+//! fn conformance() {
+//!     let report = tokio_proto::conformance::multiplex::run(LineFixture);
+//!     assert!(report.is_ok(), "{}", report);
+//! }
+//! ```
+//!
+//! The `multiplex` and `pipeline` modules hold the suites of streaming
+//! multiplexed and pipelined protocols respectively. Every case runs on a
+//! connection of its own. A case whose exchanges do not
+//! complete within a few seconds fails instead of hanging the suite.
+
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use futures::Future;
+use tokio_core::reactor::{Core, Timeout};
+
+mod pipe;
+pub use self::pipe::{pipe, Pipe};
+
+pub mod multiplex;
+pub mod pipeline;
+
+// Time every step of a case is given to complete, in seconds
+const STEP_TIMEOUT: u64 = 5;
+
+/// The outcome of the cases run by a conformance suite.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    cases: Vec<(&'static str, Outcome)>,
+}
+
+/// The outcome of a single case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The protocol behaved as expected.
+    Passed,
+
+    /// The case was not run, as the fixture does not support it.
+    Skipped,
+
+    /// The protocol misbehaved, as described.
+    Failed(String),
+}
+
+impl Report {
+    /// Returns true if no case failed.
+    pub fn is_ok(&self) -> bool {
+        self.failures().is_empty()
+    }
+
+    /// Returns the name and outcome of every case, in the order they ran.
+    pub fn cases(&self) -> &[(&'static str, Outcome)] {
+        &self.cases
+    }
+
+    /// Returns the name and failure description of the failed cases.
+    pub fn failures(&self) -> Vec<(&'static str, &str)> {
+        self.cases.iter()
+            .filter_map(|&(name, ref outcome)| {
+                match *outcome {
+                    Outcome::Failed(ref reason) => Some((name, &reason[..])),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the outcome of the case `name`, if it is part of the report.
+    pub fn outcome(&self, name: &str) -> Option<&Outcome> {
+        self.cases.iter().find(|case| case.0 == name).map(|case| &case.1)
+    }
+
+    fn run<F>(&mut self, name: &'static str, case: F)
+        where F: FnOnce() -> Result<(), String>,
+    {
+        let outcome = match case() {
+            Ok(()) => Outcome::Passed,
+            Err(reason) => {
+                debug!("conformance case failed; case={}; reason={}", name, reason);
+                Outcome::Failed(reason)
+            }
+        };
+
+        self.cases.push((name, outcome));
+    }
+
+    fn skip(&mut self, name: &'static str) {
+        self.cases.push((name, Outcome::Skipped));
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for &(name, ref outcome) in &self.cases {
+            match *outcome {
+                Outcome::Passed => try!(writeln!(fmt, "{}: ok", name)),
+                Outcome::Skipped => try!(writeln!(fmt, "{}: skipped", name)),
+                Outcome::Failed(ref reason) => try!(writeln!(fmt, "{}: FAILED, {}", name, reason)),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn new_core() -> Result<Core, String> {
+    Core::new().map_err(|e| format!("failed to create event loop: {}", e))
+}
+
+// Runs `future` on `core`, failing once `STEP_TIMEOUT` passed
+fn wait<F: Future>(core: &mut Core, future: F, step: &str)
+                   -> Result<Result<F::Item, F::Error>, String>
+{
+    let timeout = try!(Timeout::new(Duration::from_secs(STEP_TIMEOUT), &core.handle())
+                           .map_err(|e| format!("failed to set a timeout: {}", e)));
+
+    let future = future.then(|res| Ok::<_, io::Error>(Some(res)))
+                       .select(timeout.map(|_| None));
+
+    match core.run(future) {
+        Ok((Some(res), _)) => Ok(res),
+        Ok((None, _)) => Err(format!("{} timed out", step)),
+        Err((e, _)) => Err(format!("{} failed: {}", step, e)),
+    }
+}
+
+// Runs `future` on `core`, failing unless it succeeds in time
+fn wait_ok<F: Future>(core: &mut Core, future: F, step: &str) -> Result<F::Item, String> {
+    match try!(wait(core, future, step)) {
+        Ok(item) => Ok(item),
+        Err(_) => Err(format!("{} failed", step)),
+    }
+}
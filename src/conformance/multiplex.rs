@@ -0,0 +1,453 @@
+//! Conformance suite of streaming, multiplexed protocols.
+//!
+//! See the parent module for an overview. The suite runs these cases:
+//!
+//! * `concurrent exchanges`: many requests in flight at once, each answered
+//!   with its own response.
+//! * `interleaved bodies`: several requests streaming their bodies at once,
+//!   the chunks of the bodies interleaved on the wire. The server echoes
+//!   every chunk into the body of its response.
+//! * `cancellation`: the caller gives up on a request the server never
+//!   answers, and the connection keeps serving other requests.
+//! * `error frames`: the server fails a request, which fails on the client
+//!   while the requests around it succeed.
+//! * `large ids`: requests identified by the ids of `Fixture::large_id`.
+//!   Skipped unless the fixture provides such ids.
+//! * `keepalive`: the connection is left idle for `Fixture::keepalive_idle`
+//!   before being used again, e.g. letting the pings of a `KeepAlive`
+//!   transport flow. Skipped unless the fixture gives an idle time.
+
+use std::cell::Cell;
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::{future, Future, Sink, Stream};
+use tokio_core::reactor::{Core, Timeout};
+use tokio_service::Service;
+
+use {BindClient, BindServer};
+use streaming::{Body, Encodings, Message};
+use streaming::multiplex::{ClientProto, MultiplexConfig, RequestIdSource, ServerProto, StreamingMultiplex};
+use util::client_proxy::ClientProxy;
+use super::{new_core, wait, wait_ok, Pipe, Report};
+
+// Requests in flight at once in the `concurrent exchanges` case
+const CONCURRENT: u64 = 16;
+
+// Requests with a body, and chunks per body, of the `interleaved bodies` case
+const BODIES: u64 = 4;
+const CHUNKS: u64 = 3;
+
+/// Describes how to exercise a protocol.
+///
+/// The suite numbers the requests of every case from 0, and asks the fixture
+/// for the request and body chunks of a given number. On the server, every
+/// request is answered through `respond`, and every request body chunk is
+/// echoed into the response body through `echo_chunk`. The client then asks
+/// the fixture whether the response and the response body chunks it got
+/// match the request and chunks of the same numbers.
+pub trait Fixture: 'static {
+    /// The client half of the protocol.
+    type Client: ClientProto<Pipe>;
+
+    /// The server half of the protocol.
+    type Server: ServerProto<Pipe>;
+
+    /// Returns the client half of the protocol.
+    fn client(&self) -> Self::Client;
+
+    /// Returns the server half of the protocol.
+    fn server(&self) -> Self::Server;
+
+    /// Returns the request numbered `n`.
+    fn request(&self, n: u64) -> ClientRequest<Self>;
+
+    /// Returns the request body chunk numbered `n`.
+    fn request_chunk(&self, n: u64) -> ClientRequestBody<Self>;
+
+    /// Returns the response of the server to `request`.
+    fn respond(&self, request: ServerRequest<Self>) -> ServerResponse<Self>;
+
+    /// Returns the response body chunk echoing the request body chunk
+    /// `chunk`.
+    fn echo_chunk(&self, chunk: ServerRequestBody<Self>) -> ServerResponseBody<Self>;
+
+    /// Returns true if `response` answers the request numbered `n`.
+    fn check_response(&self, n: u64, response: &ClientResponse<Self>) -> bool;
+
+    /// Returns true if `chunk` echoes the request body chunk numbered `n`.
+    fn check_chunk(&self, n: u64, chunk: &ClientResponseBody<Self>) -> bool;
+
+    /// Returns the id of the request numbered `n` of the `large ids` case,
+    /// e.g. ids close to the largest the protocol can encode.
+    ///
+    /// Returning `None` for the first request, the default, skips the case.
+    /// Requests the fixture has no id for are given one by the client's
+    /// `RequestIdSource`.
+    fn large_id(&self, n: u64) -> Option<ClientRequestId<Self>> {
+        let _ = n;
+        None
+    }
+
+    /// Returns how long the `keepalive` case leaves the connection idle.
+    ///
+    /// Defaults to `None`, skipping the case.
+    fn keepalive_idle(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Request headers of the client of a fixture.
+pub type ClientRequest<F> = <<F as Fixture>::Client as ClientProto<Pipe>>::Request;
+
+/// Request body chunks of the client of a fixture.
+pub type ClientRequestBody<F> = <<F as Fixture>::Client as ClientProto<Pipe>>::RequestBody;
+
+/// Response headers of the client of a fixture.
+pub type ClientResponse<F> = <<F as Fixture>::Client as ClientProto<Pipe>>::Response;
+
+/// Response body chunks of the client of a fixture.
+pub type ClientResponseBody<F> = <<F as Fixture>::Client as ClientProto<Pipe>>::ResponseBody;
+
+/// Request ids of the client of a fixture.
+pub type ClientRequestId<F> = <<F as Fixture>::Client as ClientProto<Pipe>>::RequestId;
+
+/// Request headers of the server of a fixture.
+pub type ServerRequest<F> = <<F as Fixture>::Server as ServerProto<Pipe>>::Request;
+
+/// Request body chunks of the server of a fixture.
+pub type ServerRequestBody<F> = <<F as Fixture>::Server as ServerProto<Pipe>>::RequestBody;
+
+/// Response headers of the server of a fixture.
+pub type ServerResponse<F> = <<F as Fixture>::Server as ServerProto<Pipe>>::Response;
+
+/// Response body chunks of the server of a fixture.
+pub type ServerResponseBody<F> = <<F as Fixture>::Server as ServerProto<Pipe>>::ResponseBody;
+
+type ServerError<F> = <<F as Fixture>::Server as ServerProto<Pipe>>::Error;
+
+type ResponseStream<F> = Box<Stream<Item = ServerResponseBody<F>, Error = ServerError<F>>>;
+
+type Client<P> = ClientProxy<Message<<P as ClientProto<Pipe>>::Request,
+                                    Body<<P as ClientProto<Pipe>>::RequestBody,
+                                         <P as ClientProto<Pipe>>::Error>>,
+                            Message<<P as ClientProto<Pipe>>::Response,
+                                    Body<<P as ClientProto<Pipe>>::ResponseBody,
+                                         <P as ClientProto<Pipe>>::Error>>,
+                            <P as ClientProto<Pipe>>::Error>;
+
+/// Runs the suite against the protocol of `fixture`.
+pub fn run<F: Fixture>(fixture: F) -> Report {
+    let fixture = Rc::new(fixture);
+    let mut report = Report::default();
+
+    report.run("concurrent exchanges", || concurrent_exchanges(&fixture));
+    report.run("interleaved bodies", || interleaved_bodies(&fixture));
+    report.run("cancellation", || cancellation(&fixture));
+    report.run("error frames", || error_frames(&fixture));
+
+    if fixture.large_id(0).is_some() {
+        report.run("large ids", || large_ids(&fixture));
+    } else {
+        report.skip("large ids");
+    }
+
+    match fixture.keepalive_idle() {
+        Some(idle) => report.run("keepalive", || keepalive(&fixture, idle)),
+        None => report.skip("keepalive"),
+    }
+
+    report
+}
+
+fn concurrent_exchanges<F: Fixture>(fixture: &Rc<F>) -> Result<(), String> {
+    let mut core = try!(new_core());
+    let client = connect(fixture, &fixture.client(), &core, |_| Behavior::Respond);
+
+    exchange(fixture, &mut core, &client, 0..CONCURRENT)
+}
+
+fn interleaved_bodies<F: Fixture>(fixture: &Rc<F>) -> Result<(), String> {
+    let mut core = try!(new_core());
+    let client = connect(fixture, &fixture.client(), &core, |_| Behavior::Respond);
+
+    let mut senders = vec![];
+    let mut calls = vec![];
+
+    for n in 0..BODIES {
+        let (tx, body) = Body::pair();
+        senders.push(tx);
+        calls.push(client.call(Message::WithBody(fixture.request(n), body)));
+    }
+
+    // Feed the bodies a chunk at a time, taking turns
+    for k in 0..CHUNKS {
+        let mut next = vec![];
+
+        for (n, tx) in senders.into_iter().enumerate() {
+            let chunk = fixture.request_chunk(n as u64 * CHUNKS + k);
+            next.push(try!(wait_ok(&mut core, tx.send(Ok(chunk)), "sending a request body chunk")));
+        }
+
+        senders = next;
+    }
+
+    // Ends the bodies
+    drop(senders);
+
+    let responses = try!(wait_ok(&mut core, future::join_all(calls), "requests with a body"));
+    let mut bodies = vec![];
+
+    for (n, mut response) in responses.into_iter().enumerate() {
+        try!(check_response(&**fixture, n as u64, &response));
+
+        match response.take_body() {
+            Some(body) => bodies.push(body.collect()),
+            None => return Err(format!("response to request {} has no body", n)),
+        }
+    }
+
+    let bodies = try!(wait_ok(&mut core, future::join_all(bodies), "response bodies"));
+
+    for (n, chunks) in bodies.iter().enumerate() {
+        if chunks.len() as u64 != CHUNKS {
+            return Err(format!("response body of request {} has {} chunks, expected {}",
+                               n, chunks.len(), CHUNKS));
+        }
+
+        for (k, chunk) in chunks.iter().enumerate() {
+            let i = n as u64 * CHUNKS + k as u64;
+
+            if !fixture.check_chunk(i, chunk) {
+                return Err(format!("wrong echo of request body chunk {}", i));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn cancellation<F: Fixture>(fixture: &Rc<F>) -> Result<(), String> {
+    let mut core = try!(new_core());
+    let client = connect(fixture, &fixture.client(), &core, |n| {
+        if n == 0 { Behavior::Hang } else { Behavior::Respond }
+    });
+
+    let hung = client.call(Message::WithoutBody(fixture.request(0)));
+
+    // Written after the hung request, so that one reached the server too
+    try!(exchange(fixture, &mut core, &client, 1..2));
+
+    drop(hung);
+
+    exchange(fixture, &mut core, &client, 2..4)
+}
+
+fn error_frames<F: Fixture>(fixture: &Rc<F>) -> Result<(), String> {
+    let mut core = try!(new_core());
+    let client = connect(fixture, &fixture.client(), &core, |n| {
+        if n == 1 { Behavior::Fail } else { Behavior::Respond }
+    });
+
+    let calls = (0..3)
+        .map(|n| client.call(Message::WithoutBody(fixture.request(n))))
+        .collect::<Vec<_>>();
+
+    for (n, call) in calls.into_iter().enumerate() {
+        let n = n as u64;
+
+        match try!(wait(&mut core, call, "request")) {
+            Ok(_) if n == 1 => return Err("request failed by the server succeeded".to_string()),
+            Ok(response) => try!(check_response(&**fixture, n, &response)),
+            Err(_) if n == 1 => {}
+            Err(_) => return Err(format!("request {} failed", n)),
+        }
+    }
+
+    exchange(fixture, &mut core, &client, 3..4)
+}
+
+fn large_ids<F: Fixture>(fixture: &Rc<F>) -> Result<(), String> {
+    let mut core = try!(new_core());
+    let proto = LargeIds { proto: fixture.client(), fixture: fixture.clone() };
+    let client = connect(fixture, &proto, &core, |_| Behavior::Respond);
+
+    exchange(fixture, &mut core, &client, 0..4)
+}
+
+fn keepalive<F: Fixture>(fixture: &Rc<F>, idle: Duration) -> Result<(), String> {
+    let mut core = try!(new_core());
+    let client = connect(fixture, &fixture.client(), &core, |_| Behavior::Respond);
+
+    try!(exchange(fixture, &mut core, &client, 0..1));
+
+    let timeout = try!(Timeout::new(idle, &core.handle())
+                           .map_err(|e| format!("failed to set a timeout: {}", e)));
+    try!(core.run(timeout).map_err(|e| format!("idling failed: {}", e)));
+
+    exchange(fixture, &mut core, &client, 1..3)
+}
+
+// Sends the requests numbered `ns` at once, checking their responses
+fn exchange<F: Fixture, I>(fixture: &Rc<F>,
+                           core: &mut Core,
+                           client: &Client<F::Client>,
+                           ns: I)
+                           -> Result<(), String>
+    where I: Iterator<Item = u64> + Clone,
+{
+    let calls = ns.clone()
+        .map(|n| client.call(Message::WithoutBody(fixture.request(n))))
+        .collect::<Vec<_>>();
+
+    let responses = try!(wait_ok(core, future::join_all(calls), "requests"));
+
+    for (n, response) in ns.zip(&responses) {
+        try!(check_response(&**fixture, n, response));
+    }
+
+    Ok(())
+}
+
+fn check_response<F: Fixture, B>(fixture: &F, n: u64, response: &Message<ClientResponse<F>, B>)
+                                 -> Result<(), String>
+{
+    if fixture.check_response(n, response.get_ref()) {
+        Ok(())
+    } else {
+        Err(format!("wrong response to request {}", n))
+    }
+}
+
+// Binds the server of `fixture` and the client `proto` over a new pipe
+fn connect<F, P>(fixture: &Rc<F>, proto: &P, core: &Core, behavior: fn(u64) -> Behavior) -> Client<P>
+    where F: Fixture,
+          P: ClientProto<Pipe>,
+{
+    let (client_io, server_io) = super::pipe();
+    let handle = core.handle();
+
+    let service = Responder {
+        fixture: fixture.clone(),
+        received: Cell::new(0),
+        behavior: behavior,
+    };
+
+    BindServer::<StreamingMultiplex<ResponseStream<F>>, Pipe>::bind_server(
+        &fixture.server(), &handle, server_io, service);
+
+    BindClient::<StreamingMultiplex<Body<P::RequestBody, P::Error>>, Pipe>::bind_client(
+        proto, &handle, client_io)
+}
+
+// What the server does with the request it received in the given position
+#[derive(Debug, Clone, Copy)]
+enum Behavior {
+    Respond,
+    Fail,
+    Hang,
+}
+
+// The service of the server
+struct Responder<F> {
+    fixture: Rc<F>,
+    received: Cell<u64>,
+    behavior: fn(u64) -> Behavior,
+}
+
+impl<F: Fixture> Service for Responder<F> {
+    type Request = Message<ServerRequest<F>, Body<ServerRequestBody<F>, ServerError<F>>>;
+    type Response = Message<ServerResponse<F>, ResponseStream<F>>;
+    type Error = ServerError<F>;
+    type Future = Box<Future<Item = Self::Response, Error = Self::Error>>;
+
+    fn call(&self, request: Self::Request) -> Self::Future {
+        let n = self.received.get();
+        self.received.set(n + 1);
+
+        match (self.behavior)(n) {
+            Behavior::Respond => {}
+            Behavior::Fail => {
+                let err = io::Error::new(io::ErrorKind::Other, "failed by the conformance suite");
+                return Box::new(future::err(err.into()));
+            }
+            Behavior::Hang => return Box::new(future::empty()),
+        }
+
+        let response = match request {
+            Message::WithoutBody(request) => Message::WithoutBody(self.fixture.respond(request)),
+            Message::WithBody(request, body) => {
+                let fixture = self.fixture.clone();
+                let body = body.map(move |chunk| fixture.echo_chunk(chunk));
+                Message::WithBody(self.fixture.respond(request), Box::new(body) as ResponseStream<F>)
+            }
+        };
+
+        Box::new(future::ok(response))
+    }
+}
+
+// The client of a fixture, identifying requests with its large ids
+struct LargeIds<F: Fixture> {
+    proto: F::Client,
+    fixture: Rc<F>,
+}
+
+struct LargeIdSource<F: Fixture> {
+    fixture: Rc<F>,
+    next: u64,
+    fallback: <F::Client as ClientProto<Pipe>>::RequestIdSource,
+}
+
+impl<F: Fixture> ClientProto<Pipe> for LargeIds<F> {
+    type Request = ClientRequest<F>;
+    type RequestBody = ClientRequestBody<F>;
+    type Response = ClientResponse<F>;
+    type ResponseBody = ClientResponseBody<F>;
+    type RequestId = ClientRequestId<F>;
+    type Error = <F::Client as ClientProto<Pipe>>::Error;
+    type Transport = <F::Client as ClientProto<Pipe>>::Transport;
+    type BindTransport = <F::Client as ClientProto<Pipe>>::BindTransport;
+    type RequestIdSource = LargeIdSource<F>;
+
+    fn requestid_source(&self) -> LargeIdSource<F> {
+        LargeIdSource {
+            fixture: self.fixture.clone(),
+            next: 0,
+            fallback: self.proto.requestid_source(),
+        }
+    }
+
+    fn bind_transport(&self, io: Pipe) -> Self::BindTransport {
+        self.proto.bind_transport(io)
+    }
+
+    fn config(&self) -> MultiplexConfig {
+        self.proto.config()
+    }
+
+    fn encodings(&self) -> Encodings {
+        self.proto.encodings()
+    }
+
+    fn request_timeout(&self) -> Option<Duration> {
+        self.proto.request_timeout()
+    }
+
+    fn cancel(transport: &mut Self::Transport, request_id: Self::RequestId) -> io::Result<()> {
+        <F::Client as ClientProto<Pipe>>::cancel(transport, request_id)
+    }
+}
+
+impl<F: Fixture> RequestIdSource<ClientRequestId<F>, ClientRequest<F>> for LargeIdSource<F> {
+    fn next(&mut self, msg: &ClientRequest<F>) -> ClientRequestId<F> {
+        let n = self.next;
+        self.next += 1;
+
+        match self.fixture.large_id(n) {
+            Some(id) => id,
+            None => self.fallback.next(msg),
+        }
+    }
+}
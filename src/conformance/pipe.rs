@@ -0,0 +1,144 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use futures::Async;
+use futures::task::{self, Task};
+use tokio_core::io::Io;
+
+/// One end of an in-memory, bidirectional byte stream.
+///
+/// Created in pairs by `pipe`: the bytes written to one end are read from the
+/// other. Once an end is dropped, the other end reads the remaining bytes
+/// followed by the end of the stream, and its writes fail with `BrokenPipe`.
+pub struct Pipe {
+    read: Arc<Mutex<Buffer>>,
+    write: Arc<Mutex<Buffer>>,
+}
+
+// The bytes flowing in one direction
+struct Buffer {
+    data: VecDeque<u8>,
+    // Set once either end is dropped
+    closed: bool,
+    // Task waiting for bytes to read
+    reader: Option<Task>,
+}
+
+// Closes both directions of a pipe without dropping it, as if its peer went
+// away
+pub struct Closer {
+    read: Arc<Mutex<Buffer>>,
+    write: Arc<Mutex<Buffer>>,
+}
+
+/// Creates a pair of connected `Pipe`s.
+pub fn pipe() -> (Pipe, Pipe) {
+    let a = Arc::new(Mutex::new(Buffer::new()));
+    let b = Arc::new(Mutex::new(Buffer::new()));
+
+    let one = Pipe { read: a.clone(), write: b.clone() };
+    let other = Pipe { read: b, write: a };
+
+    (one, other)
+}
+
+// Returns a `Closer` for `pipe`, usable once the pipe was handed to a
+// transport
+pub fn closer(pipe: &Pipe) -> Closer {
+    Closer {
+        read: pipe.read.clone(),
+        write: pipe.write.clone(),
+    }
+}
+
+impl Closer {
+    pub fn close(&self) {
+        self.read.lock().unwrap().close();
+        self.write.lock().unwrap().close();
+    }
+}
+
+impl Buffer {
+    fn new() -> Buffer {
+        Buffer {
+            data: VecDeque::new(),
+            closed: false,
+            reader: None,
+        }
+    }
+
+    fn close(&mut self) {
+        self.closed = true;
+
+        if let Some(task) = self.reader.take() {
+            task.unpark();
+        }
+    }
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut buffer = self.read.lock().unwrap();
+
+        if buffer.data.is_empty() {
+            if buffer.closed {
+                return Ok(0);
+            }
+
+            buffer.reader = Some(task::park());
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "pipe empty"));
+        }
+
+        let n = cmp::min(buf.len(), buffer.data.len());
+
+        for (dst, src) in buf.iter_mut().zip(buffer.data.drain(..n)) {
+            *dst = src;
+        }
+
+        Ok(n)
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut buffer = self.write.lock().unwrap();
+
+        if buffer.closed {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "pipe closed"));
+        }
+
+        buffer.data.extend(buf);
+
+        if let Some(task) = buffer.reader.take() {
+            task.unpark();
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Io for Pipe {
+    fn poll_read(&mut self) -> Async<()> {
+        let mut buffer = self.read.lock().unwrap();
+
+        if !buffer.data.is_empty() || buffer.closed {
+            Async::Ready(())
+        } else {
+            buffer.reader = Some(task::park());
+            Async::NotReady
+        }
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        self.read.lock().unwrap().close();
+        self.write.lock().unwrap().close();
+    }
+}
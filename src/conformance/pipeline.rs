@@ -0,0 +1,363 @@
+//! Conformance suite of streaming, pipelined protocols.
+//!
+//! See the parent module for an overview. The suite runs these cases:
+//!
+//! * `pipelining depth`: many requests written before the first response is
+//!   read, each answered with its own response.
+//! * `ordering`: the server completes the responses to pipelined requests in
+//!   reverse order, which are still read in the order of the requests.
+//! * `bodies`: several pipelined requests streaming a body. The server
+//!   echoes every chunk into the body of its response.
+//! * `error frames`: the server fails a request, which fails on the client.
+//!   The request written before it succeeds, and the one written after it
+//!   either succeeds or fails, as the client may give up on the connection
+//!   once it read an error frame, but does not hang.
+//! * `eof`: the connection closes while a request waits for its response,
+//!   which fails, as do the requests made afterwards.
+
+use std::cell::Cell;
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::{future, Future, Sink, Stream};
+use tokio_core::reactor::{Core, Handle, Timeout};
+use tokio_service::Service;
+
+use {BindClient, BindServer};
+use streaming::{Body, Message};
+use streaming::pipeline::{ClientProto, ServerProto, StreamingPipeline};
+use util::client_proxy::ClientProxy;
+use super::pipe::{self, Closer};
+use super::{new_core, wait, wait_ok, Pipe, Report};
+
+// Requests in flight at once in the `pipelining depth` case
+const DEPTH: u64 = 16;
+
+// Requests of the `ordering` case, and the delay between their responses
+const ORDERED: u64 = 4;
+const ORDER_DELAY_MS: u64 = 10;
+
+// Requests with a body, and chunks per body, of the `bodies` case
+const BODIES: u64 = 3;
+const CHUNKS: u64 = 3;
+
+/// Describes how to exercise a protocol.
+///
+/// Works like `multiplex::Fixture`: the suite numbers the requests of every
+/// case from 0, the server answers them through `respond` and `echo_chunk`,
+/// and the client checks what it got against the request and chunks of the
+/// same numbers.
+pub trait Fixture: 'static {
+    /// The client half of the protocol.
+    type Client: ClientProto<Pipe>;
+
+    /// The server half of the protocol.
+    type Server: ServerProto<Pipe>;
+
+    /// Returns the client half of the protocol.
+    fn client(&self) -> Self::Client;
+
+    /// Returns the server half of the protocol.
+    fn server(&self) -> Self::Server;
+
+    /// Returns the request numbered `n`.
+    fn request(&self, n: u64) -> ClientRequest<Self>;
+
+    /// Returns the request body chunk numbered `n`.
+    fn request_chunk(&self, n: u64) -> ClientRequestBody<Self>;
+
+    /// Returns the response of the server to `request`.
+    fn respond(&self, request: ServerRequest<Self>) -> ServerResponse<Self>;
+
+    /// Returns the response body chunk echoing the request body chunk
+    /// `chunk`.
+    fn echo_chunk(&self, chunk: ServerRequestBody<Self>) -> ServerResponseBody<Self>;
+
+    /// Returns true if `response` answers the request numbered `n`.
+    fn check_response(&self, n: u64, response: &ClientResponse<Self>) -> bool;
+
+    /// Returns true if `chunk` echoes the request body chunk numbered `n`.
+    fn check_chunk(&self, n: u64, chunk: &ClientResponseBody<Self>) -> bool;
+}
+
+/// Request headers of the client of a fixture.
+pub type ClientRequest<F> = <<F as Fixture>::Client as ClientProto<Pipe>>::Request;
+
+/// Request body chunks of the client of a fixture.
+pub type ClientRequestBody<F> = <<F as Fixture>::Client as ClientProto<Pipe>>::RequestBody;
+
+/// Response headers of the client of a fixture.
+pub type ClientResponse<F> = <<F as Fixture>::Client as ClientProto<Pipe>>::Response;
+
+/// Response body chunks of the client of a fixture.
+pub type ClientResponseBody<F> = <<F as Fixture>::Client as ClientProto<Pipe>>::ResponseBody;
+
+/// Request headers of the server of a fixture.
+pub type ServerRequest<F> = <<F as Fixture>::Server as ServerProto<Pipe>>::Request;
+
+/// Request body chunks of the server of a fixture.
+pub type ServerRequestBody<F> = <<F as Fixture>::Server as ServerProto<Pipe>>::RequestBody;
+
+/// Response headers of the server of a fixture.
+pub type ServerResponse<F> = <<F as Fixture>::Server as ServerProto<Pipe>>::Response;
+
+/// Response body chunks of the server of a fixture.
+pub type ServerResponseBody<F> = <<F as Fixture>::Server as ServerProto<Pipe>>::ResponseBody;
+
+type ServerError<F> = <<F as Fixture>::Server as ServerProto<Pipe>>::Error;
+
+type ResponseStream<F> = Box<Stream<Item = ServerResponseBody<F>, Error = ServerError<F>>>;
+
+type Client<P> = ClientProxy<Message<<P as ClientProto<Pipe>>::Request,
+                                    Body<<P as ClientProto<Pipe>>::RequestBody,
+                                         <P as ClientProto<Pipe>>::Error>>,
+                            Message<<P as ClientProto<Pipe>>::Response,
+                                    Body<<P as ClientProto<Pipe>>::ResponseBody,
+                                         <P as ClientProto<Pipe>>::Error>>,
+                            <P as ClientProto<Pipe>>::Error>;
+
+/// Runs the suite against the protocol of `fixture`.
+pub fn run<F: Fixture>(fixture: F) -> Report {
+    let fixture = Rc::new(fixture);
+    let mut report = Report::default();
+
+    report.run("pipelining depth", || pipelining_depth(&fixture));
+    report.run("ordering", || ordering(&fixture));
+    report.run("bodies", || bodies(&fixture));
+    report.run("error frames", || error_frames(&fixture));
+    report.run("eof", || eof(&fixture));
+
+    report
+}
+
+fn pipelining_depth<F: Fixture>(fixture: &Rc<F>) -> Result<(), String> {
+    let mut core = try!(new_core());
+    let client = connect(fixture, &core, |_| Behavior::Respond);
+
+    exchange(fixture, &mut core, &client, 0..DEPTH)
+}
+
+fn ordering<F: Fixture>(fixture: &Rc<F>) -> Result<(), String> {
+    let mut core = try!(new_core());
+    let client = connect(fixture, &core, |n| {
+        if n < ORDERED {
+            Behavior::Delay(Duration::from_millis((ORDERED - n) * ORDER_DELAY_MS))
+        } else {
+            Behavior::Respond
+        }
+    });
+
+    exchange(fixture, &mut core, &client, 0..ORDERED)
+}
+
+fn bodies<F: Fixture>(fixture: &Rc<F>) -> Result<(), String> {
+    let mut core = try!(new_core());
+    let client = connect(fixture, &core, |_| Behavior::Respond);
+
+    let mut senders = vec![];
+    let mut calls = vec![];
+
+    for n in 0..BODIES {
+        let (tx, body) = Body::pair();
+        senders.push(tx);
+        calls.push(client.call(Message::WithBody(fixture.request(n), body)));
+    }
+
+    // Bodies are written one after the other, so feed them in turn
+    for (n, mut tx) in senders.into_iter().enumerate() {
+        for k in 0..CHUNKS {
+            let chunk = fixture.request_chunk(n as u64 * CHUNKS + k);
+            tx = try!(wait_ok(&mut core, tx.send(Ok(chunk)), "sending a request body chunk"));
+        }
+    }
+
+    // Response bodies are read one after the other too, so each one is
+    // consumed before waiting for the next response
+    for (n, call) in calls.into_iter().enumerate() {
+        let mut response = try!(wait_ok(&mut core, call, "request with a body"));
+        try!(check_response(&**fixture, n as u64, &response));
+
+        let body = match response.take_body() {
+            Some(body) => body,
+            None => return Err(format!("response to request {} has no body", n)),
+        };
+
+        let chunks = try!(wait_ok(&mut core, body.collect(), "response body"));
+
+        if chunks.len() as u64 != CHUNKS {
+            return Err(format!("response body of request {} has {} chunks, expected {}",
+                               n, chunks.len(), CHUNKS));
+        }
+
+        for (k, chunk) in chunks.iter().enumerate() {
+            let i = n as u64 * CHUNKS + k as u64;
+
+            if !fixture.check_chunk(i, chunk) {
+                return Err(format!("wrong echo of request body chunk {}", i));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn error_frames<F: Fixture>(fixture: &Rc<F>) -> Result<(), String> {
+    let mut core = try!(new_core());
+    let client = connect(fixture, &core, |n| {
+        if n == 1 { Behavior::Fail } else { Behavior::Respond }
+    });
+
+    let calls = (0..3)
+        .map(|n| client.call(Message::WithoutBody(fixture.request(n))))
+        .collect::<Vec<_>>();
+
+    for (n, call) in calls.into_iter().enumerate() {
+        let n = n as u64;
+
+        match try!(wait(&mut core, call, "request")) {
+            Ok(_) if n == 1 => return Err("request failed by the server succeeded".to_string()),
+            Ok(response) => try!(check_response(&**fixture, n, &response)),
+            Err(_) if n == 0 => return Err("request before the failed one failed".to_string()),
+            Err(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn eof<F: Fixture>(fixture: &Rc<F>) -> Result<(), String> {
+    let mut core = try!(new_core());
+    let client = connect(fixture, &core, |n| {
+        if n == 1 { Behavior::Close } else { Behavior::Respond }
+    });
+
+    try!(exchange(fixture, &mut core, &client, 0..1));
+
+    let call = client.call(Message::WithoutBody(fixture.request(1)));
+
+    if try!(wait(&mut core, call, "request on a closing connection")).is_ok() {
+        return Err("request on a closed connection succeeded".to_string());
+    }
+
+    let call = client.call(Message::WithoutBody(fixture.request(2)));
+
+    if try!(wait(&mut core, call, "request after the connection closed")).is_ok() {
+        return Err("request after the connection closed succeeded".to_string());
+    }
+
+    Ok(())
+}
+
+// Sends the requests numbered `ns` at once, checking their responses
+fn exchange<F: Fixture, I>(fixture: &Rc<F>,
+                           core: &mut Core,
+                           client: &Client<F::Client>,
+                           ns: I)
+                           -> Result<(), String>
+    where I: Iterator<Item = u64> + Clone,
+{
+    let calls = ns.clone()
+        .map(|n| client.call(Message::WithoutBody(fixture.request(n))))
+        .collect::<Vec<_>>();
+
+    let responses = try!(wait_ok(core, future::join_all(calls), "requests"));
+
+    for (n, response) in ns.zip(&responses) {
+        try!(check_response(&**fixture, n, response));
+    }
+
+    Ok(())
+}
+
+fn check_response<F: Fixture, B>(fixture: &F, n: u64, response: &Message<ClientResponse<F>, B>)
+                                 -> Result<(), String>
+{
+    if fixture.check_response(n, response.get_ref()) {
+        Ok(())
+    } else {
+        Err(format!("wrong response to request {}", n))
+    }
+}
+
+// Binds the server and client of `fixture` over a new pipe
+fn connect<F: Fixture>(fixture: &Rc<F>, core: &Core, behavior: fn(u64) -> Behavior) -> Client<F::Client> {
+    let (client_io, server_io) = super::pipe();
+    let handle = core.handle();
+
+    let service = Responder {
+        fixture: fixture.clone(),
+        handle: handle.clone(),
+        closer: pipe::closer(&server_io),
+        received: Cell::new(0),
+        behavior: behavior,
+    };
+
+    BindServer::<StreamingPipeline<ResponseStream<F>>, Pipe>::bind_server(
+        &fixture.server(), &handle, server_io, service);
+
+    BindClient::<StreamingPipeline<Body<ClientRequestBody<F>, <F::Client as ClientProto<Pipe>>::Error>>, Pipe>
+        ::bind_client(&fixture.client(), &handle, client_io)
+}
+
+// What the server does with the request it received in the given position
+#[derive(Debug, Clone, Copy)]
+enum Behavior {
+    Respond,
+    Delay(Duration),
+    Fail,
+    Close,
+}
+
+// The service of the server
+struct Responder<F> {
+    fixture: Rc<F>,
+    handle: Handle,
+    closer: Closer,
+    received: Cell<u64>,
+    behavior: fn(u64) -> Behavior,
+}
+
+impl<F: Fixture> Service for Responder<F> {
+    type Request = Message<ServerRequest<F>, Body<ServerRequestBody<F>, ServerError<F>>>;
+    type Response = Message<ServerResponse<F>, ResponseStream<F>>;
+    type Error = ServerError<F>;
+    type Future = Box<Future<Item = Self::Response, Error = Self::Error>>;
+
+    fn call(&self, request: Self::Request) -> Self::Future {
+        let n = self.received.get();
+        self.received.set(n + 1);
+
+        let delay = match (self.behavior)(n) {
+            Behavior::Respond => None,
+            Behavior::Delay(delay) => Some(delay),
+            Behavior::Fail => {
+                let err = io::Error::new(io::ErrorKind::Other, "failed by the conformance suite");
+                return Box::new(future::err(err.into()));
+            }
+            Behavior::Close => {
+                self.closer.close();
+                return Box::new(future::empty());
+            }
+        };
+
+        let response = match request {
+            Message::WithoutBody(request) => Message::WithoutBody(self.fixture.respond(request)),
+            Message::WithBody(request, body) => {
+                let fixture = self.fixture.clone();
+                let body = body.map(move |chunk| fixture.echo_chunk(chunk));
+                Message::WithBody(self.fixture.respond(request), Box::new(body) as ResponseStream<F>)
+            }
+        };
+
+        match delay {
+            Some(delay) => {
+                match Timeout::new(delay, &self.handle) {
+                    Ok(timeout) => Box::new(timeout.from_err().map(move |_| response)),
+                    Err(e) => Box::new(future::err(e.into())),
+                }
+            }
+            None => Box::new(future::ok(response)),
+        }
+    }
+}
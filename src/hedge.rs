@@ -0,0 +1,282 @@
+//! Hedged requests.
+//!
+//! A few slow responses, e.g. those of a backend pausing for garbage
+//! collection, make up the tail latency of a client. A `Hedge` cuts it short
+//! by sending a second copy of a request once the first one has been pending
+//! for longer than most requests take, answering with whichever response
+//! arrives first. The other request is dropped, which cancels it: the
+//! multiplex clients of this crate tell the transport, so the peer can stop
+//! working on it.
+//!
+//! The delay before hedging is the given percentile of the latencies of the
+//! latest responses. With the 95th percentile, about one request in twenty is
+//! hedged, at the cost of that much extra load on the backends. Requests are
+//! not hedged until a window of latencies has been recorded.
+//!
+//! Only idempotent requests should be hedged, as both copies may be
+//! processed. A request failing before its hedge is sent is not retried; once
+//! both copies are in flight, a failed copy leaves the answer to the other
+//! one.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_service::Service;
+
+/// Number of latencies the hedging delay is computed from, by default.
+pub const DEFAULT_WINDOW: usize = 100;
+
+/// A client service hedging requests that take longer than most.
+///
+/// See the module documentation for details.
+pub struct Hedge<S> {
+    inner: Rc<S>,
+    handle: Handle,
+    percentile: f64,
+    state: Rc<RefCell<State>>,
+}
+
+/// Response future of a `Hedge`.
+pub struct HedgeFuture<S: Service> {
+    inner: Rc<S>,
+    state: Rc<RefCell<State>>,
+    primary: Option<S::Future>,
+    hedge: Option<S::Future>,
+    // When the primary was sent, which latencies are measured from whichever
+    // copy answers
+    start: Instant,
+    // Fires once it is time to send the hedge
+    timer: Option<Timeout>,
+    // Copy of the request, sent as the hedge
+    request: Option<S::Request>,
+}
+
+/// Counts of the requests made through a `Hedge`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HedgeStats {
+    /// Number of requests made.
+    pub requests: u64,
+
+    /// Number of requests a hedge was sent for.
+    pub hedged: u64,
+
+    /// Number of requests answered by their hedge.
+    pub hedges_won: u64,
+}
+
+struct State {
+    // Latencies of the latest successful responses, from the primary being
+    // sent until either copy answered, oldest first
+    latencies: VecDeque<Duration>,
+    window: usize,
+    stats: HedgeStats,
+}
+
+impl<S> Hedge<S> {
+    /// Create a new `Hedge` sending a second copy of a request to `inner`
+    /// once it has been pending for longer than the `percentile` of the
+    /// latencies of the latest responses, e.g. `0.95`.
+    ///
+    /// The timers are registered with the event loop of `handle`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `percentile` is not between 0 and 1.
+    pub fn new(inner: S, percentile: f64, handle: &Handle) -> Hedge<S> {
+        assert!((0.0..=1.0).contains(&percentile),
+                "percentile must be between 0 and 1");
+
+        Hedge {
+            inner: Rc::new(inner),
+            handle: handle.clone(),
+            percentile: percentile,
+            state: Rc::new(RefCell::new(State {
+                latencies: VecDeque::new(),
+                window: DEFAULT_WINDOW,
+                stats: HedgeStats::default(),
+            })),
+        }
+    }
+
+    /// Compute the hedging delay from the latest `window` latencies.
+    ///
+    /// Defaults to `DEFAULT_WINDOW`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window` is zero.
+    pub fn window(self, window: usize) -> Hedge<S> {
+        assert!(window > 0, "window must not be empty");
+
+        self.state.borrow_mut().window = window;
+        self
+    }
+
+    /// Returns the counts of the requests made so far.
+    pub fn stats(&self) -> HedgeStats {
+        self.state.borrow().stats
+    }
+
+    /// Returns a reference to the hedged service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    // Returns how long a request may be pending before it is hedged, once
+    // enough latencies are known
+    fn delay(&self) -> Option<Duration> {
+        let state = self.state.borrow();
+
+        if state.latencies.len() < state.window {
+            return None;
+        }
+
+        let mut latencies = state.latencies.iter().cloned().collect::<Vec<_>>();
+        latencies.sort();
+
+        let rank = (latencies.len() as f64 * self.percentile).ceil() as usize;
+        Some(latencies[rank.saturating_sub(1).min(latencies.len() - 1)])
+    }
+}
+
+impl<S> Clone for Hedge<S> {
+    fn clone(&self) -> Hedge<S> {
+        Hedge {
+            inner: self.inner.clone(),
+            handle: self.handle.clone(),
+            percentile: self.percentile,
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<S> Service for Hedge<S>
+    where S: Service,
+          S::Request: Clone,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = HedgeFuture<S>;
+
+    fn call(&self, req: S::Request) -> HedgeFuture<S> {
+        self.state.borrow_mut().stats.requests += 1;
+
+        let timer = self.delay().and_then(|delay| Timeout::new(delay, &self.handle).ok());
+        let request = timer.as_ref().map(|_| req.clone());
+
+        HedgeFuture {
+            inner: self.inner.clone(),
+            state: self.state.clone(),
+            primary: Some(self.inner.call(req)),
+            hedge: None,
+            start: Instant::now(),
+            timer: timer,
+            request: request,
+        }
+    }
+}
+
+impl<S: Service> HedgeFuture<S> {
+    // Sends the hedge once the timer fired
+    fn poll_timer(&mut self) {
+        let fired = match self.timer {
+            Some(ref mut timer) => match timer.poll() {
+                Ok(Async::NotReady) => return,
+                Ok(Async::Ready(())) => true,
+                Err(_) => false,
+            },
+            None => return,
+        };
+
+        self.timer = None;
+
+        if let (true, Some(req)) = (fired, self.request.take()) {
+            trace!("hedging request");
+            self.state.borrow_mut().stats.hedged += 1;
+            self.hedge = Some(self.inner.call(req));
+        }
+    }
+
+    // Records the latency of the request, dropping the copy not answering
+    fn answer(&mut self, hedge: bool) {
+        let latency = self.start.elapsed();
+        let mut state = self.state.borrow_mut();
+
+        if state.latencies.len() == state.window {
+            state.latencies.pop_front();
+        }
+
+        state.latencies.push_back(latency);
+
+        if hedge {
+            state.stats.hedges_won += 1;
+        }
+
+        self.primary = None;
+        self.hedge = None;
+        self.timer = None;
+        self.request = None;
+    }
+}
+
+impl<S: Service> Future for HedgeFuture<S> {
+    type Item = S::Response;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<S::Response, S::Error> {
+        if let Some(res) = poll_request(&mut self.primary) {
+            match res {
+                Ok(response) => {
+                    self.answer(false);
+                    return Ok(Async::Ready(response));
+                }
+                // The hedge, if sent, answers instead
+                Err(e) => {
+                    if self.hedge.is_none() {
+                        return Err(e);
+                    }
+                }
+            }
+        } else {
+            self.poll_timer();
+        }
+
+        if let Some(res) = poll_request(&mut self.hedge) {
+            match res {
+                Ok(response) => {
+                    self.answer(true);
+                    return Ok(Async::Ready(response));
+                }
+                Err(e) => {
+                    if self.primary.is_none() {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+// Polls the request, if any, returning its outcome once done
+fn poll_request<F: Future>(request: &mut Option<F>)
+                           -> Option<Result<F::Item, F::Error>>
+{
+    let res = match *request {
+        Some(ref mut future) => match future.poll() {
+            Ok(Async::NotReady) => return None,
+            Ok(Async::Ready(item)) => Ok(item),
+            Err(e) => Err(e),
+        },
+        None => return None,
+    };
+
+    *request = None;
+    Some(res)
+}
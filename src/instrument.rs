@@ -0,0 +1,302 @@
+//! Instrumentation of connections.
+//!
+//! `Instrumented` wraps an I/O object, counting the reads and writes made on
+//! it and timing a sample of them. The numbers are aggregated into an
+//! `IoMetrics` handle, which may be shared by any number of connections, and
+//! read back with `IoMetrics::snapshot`.
+//!
+//! Comparing the latencies of the underlying reads and writes with those of
+//! the requests helps telling slow networks apart from slow services.
+//! `TcpServer::instrument` and `TcpClient::instrument` install the wrapper
+//! on every connection.
+//!
+//! A `ConnectionObserver` looks at the connections from the other end: it is
+//! told about the frames, requests and errors of every connection driven by
+//! the streaming dispatchers, e.g. to export them to a metrics system. It is
+//! installed on the connections of a server or client with
+//! `TcpServer::observe` and `TcpClient::observe`, on a single connection
+//! through the `observer` field of `BindConfig`, or by an advanced dispatcher
+//! through its `observer` hook.
+
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::Async;
+use tokio_core::io::Io;
+
+use streaming::ConnectionId;
+
+/// Aggregated metrics of instrumented I/O objects.
+///
+/// Cloning the handle shares the metrics.
+#[derive(Clone)]
+pub struct IoMetrics {
+    inner: Arc<Shared>,
+}
+
+struct Shared {
+    sample_every: u64,
+    snapshot: Mutex<IoSnapshot>,
+}
+
+/// A point in time copy of `IoMetrics`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoSnapshot {
+    /// Reads from the I/O objects.
+    pub read: OpStats,
+
+    /// Writes to the I/O objects.
+    pub write: OpStats,
+}
+
+/// Statistics of one kind of operation, either reads or writes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpStats {
+    /// Number of calls that completed, including those hitting the end of
+    /// the stream.
+    pub calls: u64,
+
+    /// Number of calls that would have blocked.
+    pub would_block: u64,
+
+    /// Number of calls that failed with any other error.
+    pub errors: u64,
+
+    /// Number of bytes transferred.
+    pub bytes: u64,
+
+    /// Largest number of bytes transferred by a single call.
+    pub max_bytes: u64,
+
+    /// Number of completed calls that were timed.
+    pub sampled: u64,
+
+    /// Time spent in the timed calls.
+    pub sampled_time: Duration,
+
+    /// Longest of the timed calls.
+    pub max_latency: Duration,
+}
+
+/// An I/O object recording its reads and writes into `IoMetrics`.
+pub struct Instrumented<T> {
+    io: T,
+    metrics: IoMetrics,
+    reads: u64,
+    writes: u64,
+}
+
+/// Callbacks on the activity of connections.
+///
+/// Every callback is given the id of the connection, which is also handed
+/// to the transport in `on_connection`. Callbacks are made from the task
+/// driving the connection, so they should be cheap, e.g. bumping counters.
+/// All of them do nothing by default.
+///
+/// The frames and requests are counted from the point of view of the
+/// dispatcher: a request is started when its first message is read or
+/// written, whichever comes first, and completed once the exchange is over
+/// or aborted.
+pub trait ConnectionObserver: Send + Sync + 'static {
+    /// Called when a frame was read from the transport.
+    fn on_frame_read(&self, conn: ConnectionId) {
+        let _ = conn;
+    }
+
+    /// Called when a frame was accepted by the transport.
+    fn on_frame_written(&self, conn: ConnectionId) {
+        let _ = conn;
+    }
+
+    /// Called when a request starts.
+    fn on_request_started(&self, conn: ConnectionId) {
+        let _ = conn;
+    }
+
+    /// Called when a request completes, `latency` after it started.
+    fn on_request_completed(&self, conn: ConnectionId, latency: Duration) {
+        let _ = (conn, latency);
+    }
+
+    /// Called when the connection fails with `error`.
+    fn on_error(&self, conn: ConnectionId, error: &io::Error) {
+        let _ = (conn, error);
+    }
+
+    /// Called when the number of requests in flight on the connection
+    /// changed to `depth`.
+    fn on_queue_depth(&self, conn: ConnectionId, depth: usize) {
+        let _ = (conn, depth);
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Op {
+    Read,
+    Write,
+}
+
+impl IoMetrics {
+    /// Returns new, empty metrics, timing every read and write.
+    pub fn new() -> IoMetrics {
+        IoMetrics::sample_every(1)
+    }
+
+    /// Returns new, empty metrics, timing every `n`th read and write of
+    /// each I/O object.
+    ///
+    /// All calls are counted regardless; sampling bounds the cost of reading
+    /// the clock on busy connections.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero.
+    pub fn sample_every(n: u64) -> IoMetrics {
+        assert!(n > 0, "sampling interval must be positive");
+
+        IoMetrics {
+            inner: Arc::new(Shared {
+                sample_every: n,
+                snapshot: Mutex::new(IoSnapshot::default()),
+            }),
+        }
+    }
+
+    /// Returns a copy of the metrics collected so far.
+    pub fn snapshot(&self) -> IoSnapshot {
+        *self.inner.snapshot.lock().unwrap()
+    }
+
+    fn is_sampled(&self, count: u64) -> bool {
+        count.is_multiple_of(self.inner.sample_every)
+    }
+
+    fn record(&self, op: Op, res: &io::Result<usize>, start: Option<Instant>) {
+        let elapsed = start.map(|start| start.elapsed());
+
+        let mut snapshot = self.inner.snapshot.lock().unwrap();
+        let stats = match op {
+            Op::Read => &mut snapshot.read,
+            Op::Write => &mut snapshot.write,
+        };
+
+        match *res {
+            Ok(n) => {
+                let n = n as u64;
+
+                stats.calls += 1;
+                stats.bytes += n;
+
+                if n > stats.max_bytes {
+                    stats.max_bytes = n;
+                }
+
+                if let Some(elapsed) = elapsed {
+                    stats.sampled += 1;
+                    stats.sampled_time += elapsed;
+
+                    if elapsed > stats.max_latency {
+                        stats.max_latency = elapsed;
+                    }
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => stats.would_block += 1,
+            Err(_) => stats.errors += 1,
+        }
+    }
+}
+
+impl Default for IoMetrics {
+    fn default() -> IoMetrics {
+        IoMetrics::new()
+    }
+}
+
+impl OpStats {
+    /// Returns the mean latency of the timed calls, if any.
+    pub fn mean_latency(&self) -> Option<Duration> {
+        if self.sampled == 0 {
+            return None;
+        }
+
+        let nanos = self.sampled_time.as_nanos() / self.sampled as u128;
+        Some(Duration::from_nanos(nanos as u64))
+    }
+}
+
+impl<T> Instrumented<T> {
+    /// Wraps `io`, recording its reads and writes into `metrics`.
+    pub fn new(io: T, metrics: &IoMetrics) -> Instrumented<T> {
+        Instrumented {
+            io: io,
+            metrics: metrics.clone(),
+            reads: 0,
+            writes: 0,
+        }
+    }
+
+    /// Returns the metrics the I/O object records into.
+    pub fn metrics(&self) -> &IoMetrics {
+        &self.metrics
+    }
+
+    /// Returns a reference to the wrapped I/O object.
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+
+    /// Returns a mutable reference to the wrapped I/O object.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.io
+    }
+
+    /// Consumes the wrapper, returning the wrapped I/O object.
+    pub fn into_inner(self) -> T {
+        self.io
+    }
+}
+
+impl<T: Read> Read for Instrumented<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = if self.metrics.is_sampled(self.reads) {
+            Some(Instant::now())
+        } else {
+            None
+        };
+        self.reads += 1;
+
+        let res = self.io.read(buf);
+        self.metrics.record(Op::Read, &res, start);
+        res
+    }
+}
+
+impl<T: Write> Write for Instrumented<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let start = if self.metrics.is_sampled(self.writes) {
+            Some(Instant::now())
+        } else {
+            None
+        };
+        self.writes += 1;
+
+        let res = self.io.write(buf);
+        self.metrics.record(Op::Write, &res, start);
+        res
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<T: Io> Io for Instrumented<T> {
+    fn poll_read(&mut self) -> Async<()> {
+        self.io.poll_read()
+    }
+
+    fn poll_write(&mut self) -> Async<()> {
+        self.io.poll_write()
+    }
+}
@@ -0,0 +1,377 @@
+//! Keep-alive pings and idle timeouts of connections.
+//!
+//! `KeepAlive` wraps the transport of a pipelined or multiplexed streaming
+//! protocol. Every `interval`, it calls the `ping` method of the transport,
+//! which writes whatever keep-alive frame the protocol uses, keeping the
+//! connection busy for the peer and for middleboxes that drop idle flows.
+//! Once no frame has been read from the transport for `idle_timeout`, the
+//! next read fails with `ErrorKind::TimedOut`, closing the connection.
+//!
+//! Only frames the transport yields count as activity. A transport consuming
+//! the replies to its pings itself, without yielding them, should use an
+//! idle timeout long enough to cover the periods the peer has nothing to
+//! send.
+//!
+//! The timers are registered with the event loop driving the connection,
+//! which the bind functions of the streaming protocols hand to the transport
+//! with `on_handle`. Until then, no ping is sent and the connection never
+//! times out.
+//!
+//! ```rust,ignore
+//! fn bind_transport(&self, io: T) -> Self::BindTransport {
+//!     let transport = io.framed(LineCodec);
+//!     Ok(KeepAlive::new(transport, Duration::from_secs(10), Duration::from_secs(30)))
+//! }
+//! ```
+
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll, Sink, StartSend, Stream};
+use tokio_core::reactor::{Handle, Timeout};
+
+use streaming::{multiplex, pipeline, BufferProvider, ConnectionId, Encodings, Stats};
+use streaming::multiplex::GoAway;
+
+/// A transport pinging its peer periodically and failing once idle for too
+/// long.
+pub struct KeepAlive<T> {
+    inner: T,
+    interval: Duration,
+    idle_timeout: Duration,
+    // Armed once the transport receives a handle to the event loop
+    timers: Option<Timers>,
+    // Last time a frame was read
+    last_seen: Instant,
+    // Set when pinging failed, reported by the next read
+    error: Option<io::Error>,
+}
+
+struct Timers {
+    ping: Timeout,
+    idle: Timeout,
+}
+
+impl<T> KeepAlive<T> {
+    /// Wraps `transport`, pinging the peer every `interval` and failing the
+    /// connection once no frame was read for `idle_timeout`.
+    pub fn new(transport: T, interval: Duration, idle_timeout: Duration) -> KeepAlive<T> {
+        KeepAlive {
+            inner: transport,
+            interval: interval,
+            idle_timeout: idle_timeout,
+            timers: None,
+            last_seen: Instant::now(),
+            error: None,
+        }
+    }
+
+    /// Returns the interval between pings.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns the time the connection may go without reading a frame.
+    pub fn idle_timeout(&self) -> Duration {
+        self.idle_timeout
+    }
+
+    /// Returns a reference to the wrapped transport.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the wrapped transport.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Consumes the wrapper, returning the wrapped transport.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn arm(&mut self, handle: &Handle) {
+        let now = Instant::now();
+
+        let timers = Timeout::new_at(now + self.interval, handle).and_then(|ping| {
+            let idle = try!(Timeout::new_at(now + self.idle_timeout, handle));
+            Ok(Timers { ping: ping, idle: idle })
+        });
+
+        self.last_seen = now;
+
+        match timers {
+            Ok(timers) => self.timers = Some(timers),
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    /// Returns true each time a ping is due
+    fn poll_ping(&mut self) -> io::Result<bool> {
+        let timers = match self.timers {
+            Some(ref mut timers) => timers,
+            None => return Ok(false),
+        };
+
+        if try!(timers.ping.poll()).is_ready() {
+            timers.ping.reset(Instant::now() + self.interval);
+
+            // Get woken up for the next ping
+            try!(timers.ping.poll());
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    /// Fails once no frame was read for the idle timeout
+    fn poll_idle(&mut self) -> io::Result<()> {
+        let timers = match self.timers {
+            Some(ref mut timers) => timers,
+            None => return Ok(()),
+        };
+
+        let deadline = self.last_seen + self.idle_timeout;
+
+        // The timer was set before the last frame was read, push it back
+        // until it fires for the current deadline
+        while try!(timers.idle.poll()).is_ready() {
+            if Instant::now() >= deadline {
+                debug!("connection idle for {:?}", self.idle_timeout);
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "connection idle"));
+            }
+
+            timers.idle.reset(deadline);
+        }
+
+        Ok(())
+    }
+
+    fn poll_keepalive<F>(&mut self, ping: F) where F: FnOnce(&mut T) -> io::Result<()> {
+        if self.error.is_some() {
+            return;
+        }
+
+        let res = match self.poll_ping() {
+            Ok(true) => {
+                trace!("sending keep-alive ping");
+                ping(&mut self.inner)
+            }
+            Ok(false) => Ok(()),
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = res {
+            self.error = Some(e);
+        }
+    }
+}
+
+impl<T> Stream for KeepAlive<T>
+    where T: Stream<Error = io::Error>,
+{
+    type Item = T::Item;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<T::Item>, io::Error> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+
+        match try!(self.inner.poll()) {
+            Async::Ready(frame) => {
+                self.last_seen = Instant::now();
+                Ok(Async::Ready(frame))
+            }
+            Async::NotReady => {
+                try!(self.poll_idle());
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+impl<T> Sink for KeepAlive<T>
+    where T: Sink<SinkError = io::Error>,
+{
+    type SinkItem = T::SinkItem;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, item: T::SinkItem) -> StartSend<T::SinkItem, io::Error> {
+        self.inner.start_send(item)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        self.inner.poll_complete()
+    }
+}
+
+impl<T> pipeline::Transport for KeepAlive<T>
+    where T: pipeline::Transport,
+{
+    fn tick(&mut self) {
+        self.inner.tick();
+        self.poll_keepalive(|inner| inner.ping());
+    }
+
+    fn cancel(&mut self) -> io::Result<()> {
+        self.inner.cancel()
+    }
+
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.inner.shutdown_write()
+    }
+
+    fn resynchronize(&mut self, error: &io::Error) -> bool {
+        self.inner.resynchronize(error)
+    }
+
+    fn on_connection(&mut self, id: ConnectionId) {
+        self.inner.on_connection(id)
+    }
+
+    fn on_handle(&mut self, handle: &Handle) {
+        self.arm(handle);
+        self.inner.on_handle(handle)
+    }
+
+    fn on_buffer_provider(&mut self, provider: Arc<BufferProvider>) {
+        self.inner.on_buffer_provider(provider)
+    }
+
+    fn ping(&mut self) -> io::Result<()> {
+        self.inner.ping()
+    }
+
+    fn on_stats(&mut self, stats: Stats) {
+        self.inner.on_stats(stats)
+    }
+
+    fn advertise_encodings(&mut self, encodings: &Encodings) {
+        self.inner.advertise_encodings(encodings)
+    }
+
+    fn peer_encodings(&mut self) -> Option<Encodings> {
+        self.inner.peer_encodings()
+    }
+
+    fn on_encoding(&mut self, encoding: Option<&str>) {
+        self.inner.on_encoding(encoding)
+    }
+
+    fn coalesce_body(&mut self, buffered: &mut T::SinkItem, next: T::SinkItem)
+                     -> Option<T::SinkItem>
+    {
+        self.inner.coalesce_body(buffered, next)
+    }
+
+    fn max_body_frame_len(&self) -> Option<usize> {
+        self.inner.max_body_frame_len()
+    }
+
+    fn split_body(&mut self, frame: &mut T::SinkItem, max_len: usize)
+                  -> Option<T::SinkItem>
+    {
+        self.inner.split_body(frame, max_len)
+    }
+
+    fn pending_read_len(&self) -> Option<usize> {
+        self.inner.pending_read_len()
+    }
+}
+
+impl<T, RequestId, ReadBody> multiplex::Transport<RequestId, ReadBody> for KeepAlive<T>
+    where T: multiplex::Transport<RequestId, ReadBody>,
+{
+    fn tick(&mut self) {
+        self.inner.tick();
+        self.poll_keepalive(|inner| inner.ping());
+    }
+
+    fn cancel(&mut self, request_id: RequestId) -> io::Result<()> {
+        self.inner.cancel(request_id)
+    }
+
+    fn poll_write_body(&mut self, id: RequestId) -> Async<()> {
+        self.inner.poll_write_body(id)
+    }
+
+    fn pause_body(&mut self, id: RequestId) {
+        self.inner.pause_body(id)
+    }
+
+    fn resume_body(&mut self, id: RequestId) {
+        self.inner.resume_body(id)
+    }
+
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.inner.shutdown_write()
+    }
+
+    fn resynchronize(&mut self, error: &io::Error) -> bool {
+        self.inner.resynchronize(error)
+    }
+
+    fn on_connection(&mut self, id: ConnectionId) {
+        self.inner.on_connection(id)
+    }
+
+    fn on_handle(&mut self, handle: &Handle) {
+        self.arm(handle);
+        self.inner.on_handle(handle)
+    }
+
+    fn on_buffer_provider(&mut self, provider: Arc<BufferProvider>) {
+        self.inner.on_buffer_provider(provider)
+    }
+
+    fn ping(&mut self) -> io::Result<()> {
+        self.inner.ping()
+    }
+
+    fn on_stats(&mut self, stats: Stats) {
+        self.inner.on_stats(stats)
+    }
+
+    fn advertise_encodings(&mut self, encodings: &Encodings) {
+        self.inner.advertise_encodings(encodings)
+    }
+
+    fn peer_encodings(&mut self) -> Option<Encodings> {
+        self.inner.peer_encodings()
+    }
+
+    fn peer_going_away(&mut self) -> Option<GoAway<RequestId>> {
+        self.inner.peer_going_away()
+    }
+
+    fn on_encoding(&mut self, encoding: Option<&str>) {
+        self.inner.on_encoding(encoding)
+    }
+
+    fn dispatching_body(&mut self, id: RequestId, body: &ReadBody) {
+        self.inner.dispatching_body(id, body)
+    }
+
+    fn coalesce_body(&mut self, buffered: &mut T::SinkItem, next: T::SinkItem)
+                     -> Option<T::SinkItem>
+    {
+        self.inner.coalesce_body(buffered, next)
+    }
+
+    fn max_body_frame_len(&self) -> Option<usize> {
+        self.inner.max_body_frame_len()
+    }
+
+    fn split_body(&mut self, frame: &mut T::SinkItem, max_len: usize)
+                  -> Option<T::SinkItem>
+    {
+        self.inner.split_body(frame, max_len)
+    }
+
+    fn pending_read_len(&self) -> Option<usize> {
+        self.inner.pending_read_len()
+    }
+}
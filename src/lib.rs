@@ -175,13 +175,21 @@
 #![deny(warnings, missing_docs)]
 #![allow(deprecated)] // TODO remove this
 
+#[cfg(feature = "bytes")]
+extern crate bytes;
 extern crate net2;
+#[cfg(feature = "rand")]
 extern crate rand;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
 extern crate slab;
 extern crate smallvec;
 extern crate take;
 extern crate tokio_core;
 extern crate tokio_service;
+#[cfg(all(unix, feature = "unix"))]
+extern crate tokio_uds;
 
 #[macro_use]
 extern crate futures;
@@ -190,17 +198,61 @@ extern crate futures;
 extern crate log;
 
 mod simple;
-pub use simple::{pipeline, multiplex};
+pub use simple::{pipeline, multiplex, negotiate};
 
+pub mod balance;
+pub mod conformance;
+pub mod hedge;
+pub mod instrument;
+pub mod keepalive;
+pub mod pool;
+pub mod protos;
+pub mod reissue;
+pub mod resume;
+pub mod retry;
 pub mod streaming;
+pub mod timeout;
+pub mod udp;
 pub mod util;
+pub mod wrap;
 
 mod tcp_client;
-pub use tcp_client::{TcpClient, Connect};
+pub use tcp_client::{TcpClient, Connect, LazyClient, LazyResponse, ConnectionEvents};
+pub use tcp_client::{ConnectMultipath, Multipath, MultipathClient};
 
 mod tcp_server;
-pub use tcp_server::TcpServer;
+pub use tcp_server::{TcpServer, AtCapacity, NewConnectionService, PerConnection, Route};
+pub use udp::{UdpServer, UdpClient};
+
+mod tags;
+pub use tags::{Tags, Tagged};
+
+mod resubscribe;
+pub use resubscribe::Resubscribe;
+
+mod mirror;
+pub use mirror::{Mirror, MirrorStats, MirrorFuture, Compare};
+
+mod coalesce;
+pub use coalesce::{Coalesce, CoalesceFuture};
+
+#[cfg(all(unix, feature = "unix"))]
+mod unix;
+#[cfg(all(unix, feature = "unix"))]
+pub use unix::{UnixServer, UnixClient};
 
+mod unwind;
+#[cfg(feature = "catch-unwind")]
+pub use unwind::Panic;
+
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::Future;
+use instrument::ConnectionObserver;
+use streaming::multiplex::MultiplexConfig;
+use streaming::pipeline::PipelineConfig;
 use tokio_core::reactor::Handle;
 use tokio_service::Service;
 
@@ -222,7 +274,7 @@ mod buffer_one;
 /// The `Kind` parameter, in particular, is a zero-sized type used to allow
 /// blanket implementation from the various protocol traits. Any additional
 /// implementations of this trait should use their own zero-sized kind type to
-/// distinguish them.
+/// distinguish them; see `ProtocolKind`.
 pub trait BindServer<Kind, T: 'static>: 'static {
     /// The request type for the service.
     type ServiceRequest;
@@ -241,6 +293,117 @@ pub trait BindServer<Kind, T: 'static>: 'static {
         where S: Service<Request = Self::ServiceRequest,
                          Response = Self::ServiceResponse,
                          Error = Self::ServiceError> + 'static;
+
+    /// Bind the service, giving up on the connection unless its transport is
+    /// bound within `timeout`.
+    ///
+    /// Binding may involve a handshake, which a peer can stall forever. Once
+    /// `timeout` passes, binding is aborted and the I/O object dropped,
+    /// closing the connection.
+    ///
+    /// The protocol traits of this crate implement the timeout; the default
+    /// implementation ignores it.
+    fn bind_server_timeout<S>(&self, handle: &Handle, io: T, service: S, timeout: Duration)
+        where S: Service<Request = Self::ServiceRequest,
+                         Response = Self::ServiceResponse,
+                         Error = Self::ServiceError> + 'static
+    {
+        let _ = timeout;
+        self.bind_server(handle, io, service)
+    }
+
+    /// Bind the service with the settings of `config`, holding on to `guard`
+    /// until the transport is bound or binding failed.
+    ///
+    /// Dropping `guard` signals the end of binding, e.g. to track how many
+    /// connections are in the middle of a handshake.
+    ///
+    /// The protocol traits of this crate implement this method; the default
+    /// implementation drops `guard` right away and only applies the timeout
+    /// of `config`.
+    fn bind_server_guarded<S, G>(&self, handle: &Handle, io: T, service: S,
+                                 config: &BindConfig, guard: G)
+        where S: Service<Request = Self::ServiceRequest,
+                         Response = Self::ServiceResponse,
+                         Error = Self::ServiceError> + 'static,
+              G: 'static,
+    {
+        drop(guard);
+
+        match config.timeout {
+            Some(timeout) => self.bind_server_timeout(handle, io, service, timeout),
+            None => self.bind_server(handle, io, service),
+        }
+    }
+}
+
+/// Settings of a connection bound by `BindServer` or `BindClient`, on top of
+/// those of the protocol.
+///
+/// Servers and clients such as `TcpServer` and `TcpClient` hand their
+/// settings to `BindServer::bind_server_guarded` and
+/// `BindClient::bind_client_with` for every connection they bind.
+#[derive(Clone, Default)]
+pub struct BindConfig {
+    /// Max time binding the transport may take, including any handshake done
+    /// by the protocol. Defaults to `None`, no limit.
+    pub timeout: Option<Duration>,
+
+    /// Observer told about the activity of the connection. Defaults to
+    /// `None`, leaving the connection unobserved.
+    pub observer: Option<Arc<ConnectionObserver>>,
+
+    /// Max number of frames the connection processes in a single poll, on
+    /// top of the `max_frames_per_poll` of the protocol's config; the lower
+    /// of the two applies. Must not be zero. Defaults to `None`, leaving the
+    /// protocol's limit in place.
+    pub max_frames_per_poll: Option<usize>,
+
+    /// Config of a pipelined connection, used instead of the one returned by
+    /// the `config` method of the protocol. Defaults to `None`, using the
+    /// protocol's.
+    pub pipeline: Option<PipelineConfig>,
+
+    /// Config of a multiplexed connection, used instead of the one returned
+    /// by the `config` method of the protocol. Defaults to `None`, using the
+    /// protocol's.
+    pub multiplex: Option<MultiplexConfig>,
+}
+
+/// The config of a kind of protocol, `PipelineConfig` or `MultiplexConfig`.
+///
+/// Lets `TcpServer::config` and `TcpClient::config` take either, overriding
+/// the config of the protocol for the connections they bind.
+pub trait KindConfig {
+    /// Set the config on `binding`, replacing the one of its kind, if any.
+    fn set(self, binding: &mut BindConfig);
+}
+
+/// A kind of protocol, such as streaming and pipelined.
+///
+/// Kinds are the zero-sized types used as the `Kind` parameter of
+/// `BindServer` and `BindClient`. This trait ties a kind to the machinery
+/// driving its connections, given their dispatch `D`: the glue between the
+/// transport and a service, or a client, which also determines the frames
+/// exchanged with the transport.
+///
+/// The kinds of this crate implement it for the `advanced::Dispatch` traits of
+/// the `streaming::pipeline` and `streaming::multiplex` modules. A new kind,
+/// e.g. a datagram based one, brings its own dispatch trait, frame type and
+/// driver, and implements `BindServer` and `BindClient` on top of them.
+pub trait ProtocolKind<D> {
+    /// Frames written to the transport.
+    type Frame;
+
+    /// Tuning knobs of the connections.
+    type Config: Default;
+
+    /// Task driving a connection.
+    type Driver: Future<Item = (), Error = io::Error>;
+
+    /// Returns the task driving a connection with the given dispatch,
+    /// completing once the connection is done.
+    fn drive(dispatch: D, config: &Self::Config) -> Self::Driver;
 }
 
 /// Binds an I/O object as a client of a service.
@@ -258,7 +421,7 @@ pub trait BindServer<Kind, T: 'static>: 'static {
 /// The `Kind` parameter, in particular, is a zero-sized type used to allow
 /// blanket implementation from the various protocol traits. Any additional
 /// implementations of this trait should use their own zero-sized kind type to
-/// distinguish them.
+/// distinguish them; see `ProtocolKind`.
 pub trait BindClient<Kind, T: 'static>: 'static {
     /// The request type for the service.
     type ServiceRequest;
@@ -276,4 +439,28 @@ pub trait BindClient<Kind, T: 'static>: 'static {
 
     /// Bind an I/O object as a service.
     fn bind_client(&self, handle: &Handle, io: T) -> Self::BindClient;
+
+    /// Bind an I/O object as a service, giving up on the connection unless
+    /// its transport is bound within `timeout`.
+    ///
+    /// Once `timeout` passes, binding is aborted and the I/O object dropped,
+    /// closing the connection with an `ErrorKind::TimedOut` error.
+    ///
+    /// The protocol traits of this crate implement the timeout; the default
+    /// implementation ignores it.
+    fn bind_client_timeout(&self, handle: &Handle, io: T, timeout: Duration) -> Self::BindClient {
+        let _ = timeout;
+        self.bind_client(handle, io)
+    }
+
+    /// Bind an I/O object as a service, with the settings of `config`.
+    ///
+    /// The protocol traits of this crate implement this method; the default
+    /// implementation only applies the timeout of `config`.
+    fn bind_client_with(&self, handle: &Handle, io: T, config: &BindConfig) -> Self::BindClient {
+        match config.timeout {
+            Some(timeout) => self.bind_client_timeout(handle, io, timeout),
+            None => self.bind_client(handle, io),
+        }
+    }
 }
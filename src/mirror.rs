@@ -0,0 +1,302 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
+use tokio_core::reactor::Handle;
+use tokio_service::Service;
+
+/// Duplicates a fraction of the requests of a client to a shadow service.
+///
+/// Every request is answered by the primary service. A configurable fraction
+/// of them is also sent to the shadow service, e.g. a new backend being
+/// tested with the shape of production traffic. The responses of the shadow
+/// are discarded, only their latency and whether they failed is recorded in
+/// `MirrorStats`.
+///
+/// Mirrored requests are picked evenly rather than at random: with a
+/// fraction of `0.25`, every fourth request is mirrored.
+///
+/// With `compare`, the responses of both services to a mirrored request are
+/// checked against each other once both completed successfully, flagging
+/// the divergences of the shadow when validating a migration.
+pub struct Mirror<S, M, C = ()> {
+    primary: S,
+    shadow: M,
+    handle: Handle,
+    fraction: f64,
+    compare: Rc<C>,
+    state: Rc<RefCell<State>>,
+}
+
+/// Comparator of the responses of a `Mirror`, see `Mirror::compare`.
+pub struct Compare<F>(F);
+
+/// Response future of a `Mirror` comparing responses.
+pub struct MirrorFuture<F: Future, B> {
+    future: F,
+    pair: Option<Rc<RefCell<Pair<F::Item, B>>>>,
+}
+
+// Responses to a mirrored request, compared once both are in
+struct Pair<A, B> {
+    primary: Option<Option<A>>,
+    shadow: Option<Option<B>>,
+    compare: Box<Fn(&A, &B) -> bool>,
+    state: Rc<RefCell<State>>,
+}
+
+/// Outcomes of the requests sent to the shadow service of a `Mirror`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MirrorStats {
+    /// Number of requests sent to the shadow service.
+    pub mirrored: u64,
+
+    /// Number of mirrored requests the shadow service answered.
+    pub succeeded: u64,
+
+    /// Number of mirrored requests the shadow service failed.
+    pub failed: u64,
+
+    /// Sum of the latencies of the completed mirrored requests.
+    pub total_latency: Duration,
+
+    /// Largest latency of a completed mirrored request.
+    pub max_latency: Duration,
+
+    /// Number of mirrored requests both services answered and whose
+    /// responses were compared.
+    pub compared: u64,
+
+    /// Number of compared responses found to differ.
+    pub diverged: u64,
+}
+
+struct State {
+    // Fraction of a request accumulated towards the next mirrored one
+    credit: f64,
+    stats: MirrorStats,
+}
+
+impl<S, M> Mirror<S, M> {
+    /// Create a new `Mirror` sending `fraction` of the requests made to
+    /// `primary` to `shadow` as well.
+    ///
+    /// The responses of the shadow service are awaited on the given event
+    /// loop.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fraction` is not between 0 and 1.
+    pub fn new(primary: S, shadow: M, fraction: f64, handle: &Handle) -> Mirror<S, M> {
+        assert!((0.0..=1.0).contains(&fraction),
+                "fraction must be between 0 and 1");
+
+        Mirror {
+            primary: primary,
+            shadow: shadow,
+            handle: handle.clone(),
+            fraction: fraction,
+            compare: Rc::new(()),
+            state: Rc::new(RefCell::new(State {
+                credit: 0.0,
+                stats: MirrorStats::default(),
+            })),
+        }
+    }
+
+    /// Compare the responses to mirrored requests with `f`.
+    ///
+    /// `f` is called with the responses of the primary and of the shadow
+    /// service once both completed successfully, and returns false if they
+    /// diverge. Divergences are counted in `MirrorStats::diverged`; `f` may
+    /// log the details. Comparing requires the primary responses to be
+    /// cloned.
+    pub fn compare<F>(self, f: F) -> Mirror<S, M, Compare<F>> {
+        Mirror {
+            primary: self.primary,
+            shadow: self.shadow,
+            handle: self.handle,
+            fraction: self.fraction,
+            compare: Rc::new(Compare(f)),
+            state: self.state,
+        }
+    }
+}
+
+impl<S, M, C> Mirror<S, M, C> {
+    /// Returns the outcomes of the mirrored requests so far.
+    pub fn stats(&self) -> MirrorStats {
+        self.state.borrow().stats
+    }
+
+    /// Returns a reference to the primary service.
+    pub fn get_ref(&self) -> &S {
+        &self.primary
+    }
+
+    /// Consumes the mirror, returning the primary service.
+    pub fn into_inner(self) -> S {
+        self.primary
+    }
+
+    fn should_mirror(&self) -> bool {
+        let mut state = self.state.borrow_mut();
+        state.credit += self.fraction;
+
+        if state.credit >= 1.0 {
+            state.credit -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Awaits the response of the shadow service on the event loop, recording
+    // its outcome before handing it to `done`
+    fn spawn_shadow<T, G>(&self, shadow: T, done: G)
+        where T: Future + 'static,
+              G: FnOnce(Result<T::Item, T::Error>) + 'static,
+    {
+        let state = self.state.clone();
+        let start = Instant::now();
+
+        state.borrow_mut().stats.mirrored += 1;
+
+        let shadow = shadow.then(move |res| {
+            let latency = start.elapsed();
+
+            {
+                let mut state = state.borrow_mut();
+
+                if res.is_ok() {
+                    state.stats.succeeded += 1;
+                } else {
+                    debug!("mirrored request failed");
+                    state.stats.failed += 1;
+                }
+
+                state.stats.total_latency += latency;
+
+                if latency > state.stats.max_latency {
+                    state.stats.max_latency = latency;
+                }
+            }
+
+            done(res);
+            Ok(())
+        });
+
+        self.handle.spawn(shadow);
+    }
+}
+
+impl<S, M> Service for Mirror<S, M>
+    where S: Service,
+          S::Request: Clone,
+          M: Service<Request = S::Request>,
+          M::Future: 'static,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn call(&self, req: S::Request) -> S::Future {
+        if self.should_mirror() {
+            let shadow = self.shadow.call(req.clone());
+            self.spawn_shadow(shadow, |_| {});
+        }
+
+        self.primary.call(req)
+    }
+}
+
+impl<S, M, F> Service for Mirror<S, M, Compare<F>>
+    where S: Service,
+          S::Request: Clone,
+          S::Response: Clone + 'static,
+          M: Service<Request = S::Request>,
+          M::Response: 'static,
+          M::Future: 'static,
+          F: Fn(&S::Response, &M::Response) -> bool + 'static,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = MirrorFuture<S::Future, M::Response>;
+
+    fn call(&self, req: S::Request) -> Self::Future {
+        let mut pair = None;
+
+        if self.should_mirror() {
+            let compare = self.compare.clone();
+
+            let shared = Rc::new(RefCell::new(Pair {
+                primary: None,
+                shadow: None,
+                compare: Box::new(move |a: &S::Response, b: &M::Response| (compare.0)(a, b)),
+                state: self.state.clone(),
+            }));
+
+            let shadow = self.shadow.call(req.clone());
+            let shadow_pair = shared.clone();
+
+            self.spawn_shadow(shadow, move |res| {
+                let mut pair = shadow_pair.borrow_mut();
+                pair.shadow = Some(res.ok());
+                pair.try_compare();
+            });
+
+            pair = Some(shared);
+        }
+
+        MirrorFuture {
+            future: self.primary.call(req),
+            pair: pair,
+        }
+    }
+}
+
+impl<F, B> Future for MirrorFuture<F, B>
+    where F: Future,
+          F::Item: Clone,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        let res = match self.future.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(item)) => Ok(item),
+            Err(e) => Err(e),
+        };
+
+        if let Some(pair) = self.pair.take() {
+            let mut pair = pair.borrow_mut();
+            pair.primary = Some(res.as_ref().ok().cloned());
+            pair.try_compare();
+        }
+
+        res.map(Async::Ready)
+    }
+}
+
+impl<A, B> Pair<A, B> {
+    fn try_compare(&mut self) {
+        let (primary, shadow) = match (&self.primary, &self.shadow) {
+            (&Some(Some(ref primary)), &Some(Some(ref shadow))) => (primary, shadow),
+            _ => return,
+        };
+
+        let same = (self.compare)(primary, shadow);
+        let mut state = self.state.borrow_mut();
+
+        state.stats.compared += 1;
+
+        if !same {
+            debug!("mirrored response diverged");
+            state.stats.diverged += 1;
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Pools of client connections.
+//!
+//! A `Client` keeps several connections to the same address and spreads
+//! requests across them round-robin, like `Multipath`. Unlike `Multipath`,
+//! the connections are established lazily and re-established once they die,
+//! so the pool survives the server closing connections or restarting.
+//!
+//! Every connection of the pool is a `LazyClient`: it connects on its first
+//! request and queues the requests made meanwhile. A connection counts as
+//! dead once a request on it fails with an I/O error such as a broken pipe or
+//! a reset connection. That request fails, but the next request assigned to
+//! the connection reconnects it.
+//!
+//! Requests beyond the queue of a connecting connection are rejected with an
+//! error, unless `Client::busy_response` configured a response to answer
+//! them with.
+
+use std::cell::Cell;
+use std::error::Error;
+use std::io;
+use std::net::SocketAddr;
+use std::rc::Rc;
+
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Handle;
+use tokio_service::Service;
+
+use {BindClient, ConnectionEvents, LazyClient, LazyResponse, TcpClient};
+
+/// A client service striping requests across a pool of connections,
+/// reconnecting the ones that die.
+///
+/// See the module documentation for details.
+pub struct Client<Kind, P> where P: BindClient<Kind, TcpStream> {
+    connections: Vec<LazyClient<Kind, P>>,
+    next: Cell<usize>,
+}
+
+impl<Kind, P> Client<Kind, P>
+    where P: BindClient<Kind, TcpStream>,
+{
+    /// Create a pool of `connections` connections to the given address,
+    /// established with `client`.
+    ///
+    /// Up to `max_queued` requests per connection are queued while it is
+    /// being established; see `TcpClient::lazy`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `connections` is zero.
+    pub fn new(client: &TcpClient<Kind, P>,
+               addr: &SocketAddr,
+               handle: &Handle,
+               connections: usize,
+               max_queued: usize) -> Client<Kind, P> {
+        assert!(connections > 0, "at least one connection is required");
+
+        let connections = (0..connections).map(|_| {
+            client.lazy(addr, handle, max_queued)
+        }).collect();
+
+        Client {
+            connections: connections,
+            next: Cell::new(0),
+        }
+    }
+
+    /// Register callbacks for the connections of the pool being established
+    /// or lost.
+    ///
+    /// The callbacks are shared by all connections; see
+    /// `LazyClient::watch`.
+    pub fn watch<E>(&self, events: E)
+        where E: ConnectionEvents<P::BindClient> + Clone + 'static,
+    {
+        for connection in &self.connections {
+            connection.watch(events.clone());
+        }
+    }
+}
+
+impl<Kind, P> Client<Kind, P> where P: BindClient<Kind, TcpStream> {
+    /// Answer the calls rejected for lack of capacity with the response
+    /// returned by `busy`, instead of failing them.
+    ///
+    /// A call is rejected once the connection it is assigned to has
+    /// `max_queued` requests queued while being established; see
+    /// `LazyClient::busy_response`.
+    pub fn busy_response<F>(&self, busy: F)
+        where F: Fn() -> P::ServiceResponse + 'static,
+    {
+        let busy = Rc::new(busy);
+
+        for connection in &self.connections {
+            let busy = busy.clone();
+            connection.busy_response(move || busy());
+        }
+    }
+
+    /// Returns the connections of the pool.
+    pub fn get_ref(&self) -> &[LazyClient<Kind, P>] {
+        &self.connections
+    }
+}
+
+impl<Kind, P> Service for Client<Kind, P>
+    where Kind: 'static,
+          P: BindClient<Kind, TcpStream>,
+          P::ServiceError: Error + From<io::Error> + 'static,
+{
+    type Request = P::ServiceRequest;
+    type Response = P::ServiceResponse;
+    type Error = P::ServiceError;
+    type Future = LazyResponse<Kind, P>;
+
+    fn call(&self, req: P::ServiceRequest) -> LazyResponse<Kind, P> {
+        let idx = self.next.get();
+        self.next.set((idx + 1) % self.connections.len());
+
+        self.connections[idx].call(req)
+    }
+}
+
+impl<Kind, P> Clone for Client<Kind, P> where P: BindClient<Kind, TcpStream> {
+    fn clone(&self) -> Client<Kind, P> {
+        Client {
+            connections: self.connections.clone(),
+            next: Cell::new(self.next.get()),
+        }
+    }
+}
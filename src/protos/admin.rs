@@ -0,0 +1,225 @@
+//! A line-based admin protocol reporting the metrics of a server as JSON.
+//!
+//! `AdminProto` is meant to be bound on a port of its own, next to the
+//! server it reports on, so that operators can inspect a running server with
+//! nothing more than `nc`. The `Admin` service answers every command line
+//! with a single line of JSON, built from the `IoMetrics` and `Tags` handles
+//! registered with it:
+//!
+//! ```no_run
+//! use std::thread;
+//! use tokio_proto::{TcpServer, Tags};
+//! use tokio_proto::instrument::IoMetrics;
+//! use tokio_proto::protos::admin::{Admin, AdminProto};
+//! use tokio_proto::protos::echo::{Echo, PipelineProto};
+//!
+//! let metrics = IoMetrics::new();
+//! let tags: Tags<String> = Tags::new();
+//! let admin = Admin::new().io_metrics("echo", &metrics).connections("tenants", &tags);
+//!
+//! thread::spawn(move || {
+//!     let addr = "127.0.0.1:12346".parse().unwrap();
+//!     TcpServer::new(AdminProto, addr).serve(move || Ok(admin.clone()));
+//! });
+//!
+//! let addr = "0.0.0.0:12345".parse().unwrap();
+//! TcpServer::new(PipelineProto, addr).instrument(&metrics).serve(|| Ok(Echo));
+//! ```
+//!
+//! The commands are:
+//!
+//! * `metrics`: a snapshot of every registered `IoMetrics`, by name, e.g.
+//!   `{"echo":{"read":{"calls":2,...},"write":{...}}}`. Durations are in
+//!   microseconds.
+//! * `connections`: the number of open connections per tag of every
+//!   registered `Tags`, by name, e.g. `{"tenants":{"a":3,"b":1}}`.
+//! * `stats`: both of the above, as `{"metrics":{...},"connections":{...}}`.
+//!
+//! Anything else is answered with `{"error":"unknown command"}`.
+
+use std::fmt::{Display, Write};
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future;
+use tokio_core::io::{Io, Framed};
+use tokio_service::Service;
+
+use Tags;
+use instrument::{IoMetrics, OpStats};
+use pipeline;
+use protos::text_line::LineCodec;
+
+/// Command lines longer than this are rejected, closing the connection.
+pub const MAX_COMMAND_LEN: usize = 256;
+
+/// The pipelined admin protocol, exchanging a command line for a line of
+/// JSON.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AdminProto;
+
+/// The service answering admin commands.
+///
+/// Cloning the service shares the registered metrics.
+#[derive(Clone, Default)]
+pub struct Admin {
+    io_metrics: Vec<(String, IoMetrics)>,
+    connections: Vec<(String, Arc<Fn() -> Vec<(String, usize)> + Send + Sync>)>,
+}
+
+impl Admin {
+    /// Creates a service reporting nothing yet.
+    pub fn new() -> Admin {
+        Admin::default()
+    }
+
+    /// Reports the snapshots of `metrics` under `name`.
+    pub fn io_metrics(mut self, name: &str, metrics: &IoMetrics) -> Admin {
+        self.io_metrics.push((name.to_string(), metrics.clone()));
+        self
+    }
+
+    /// Reports the number of open connections per tag of `tags` under
+    /// `name`, with the tags formatted with `Display`.
+    pub fn connections<T>(mut self, name: &str, tags: &Tags<T>) -> Admin
+        where T: PartialEq + Clone + Display + Send + 'static,
+    {
+        let tags = tags.clone();
+        let counts = move || {
+            tags.counts().into_iter().map(|(tag, n)| (tag.to_string(), n)).collect()
+        };
+
+        self.connections.push((name.to_string(), Arc::new(counts)));
+        self
+    }
+
+    /// Returns the JSON answer to `command`.
+    pub fn answer(&self, command: &str) -> String {
+        let mut out = String::new();
+
+        match command.trim() {
+            "metrics" => self.write_metrics(&mut out),
+            "connections" => self.write_connections(&mut out),
+            "stats" => {
+                out.push_str("{\"metrics\":");
+                self.write_metrics(&mut out);
+                out.push_str(",\"connections\":");
+                self.write_connections(&mut out);
+                out.push('}');
+            }
+            _ => out.push_str("{\"error\":\"unknown command\"}"),
+        }
+
+        out
+    }
+
+    fn write_metrics(&self, out: &mut String) {
+        out.push('{');
+
+        for (i, (name, metrics)) in self.io_metrics.iter().enumerate() {
+            let snapshot = metrics.snapshot();
+
+            if i > 0 {
+                out.push(',');
+            }
+
+            write_str(out, name);
+            out.push_str(":{\"read\":");
+            write_op_stats(out, &snapshot.read);
+            out.push_str(",\"write\":");
+            write_op_stats(out, &snapshot.write);
+            out.push('}');
+        }
+
+        out.push('}');
+    }
+
+    fn write_connections(&self, out: &mut String) {
+        out.push('{');
+
+        for (i, (name, counts)) in self.connections.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+
+            write_str(out, name);
+            out.push_str(":{");
+
+            for (j, (tag, n)) in counts().into_iter().enumerate() {
+                if j > 0 {
+                    out.push(',');
+                }
+
+                write_str(out, &tag);
+                let _ = write!(out, ":{}", n);
+            }
+
+            out.push('}');
+        }
+
+        out.push('}');
+    }
+}
+
+fn write_op_stats(out: &mut String, stats: &OpStats) {
+    let _ = write!(out,
+                   "{{\"calls\":{},\"would_block\":{},\"errors\":{},\"bytes\":{},\
+                    \"max_bytes\":{},\"sampled\":{},\"sampled_time_us\":{},\
+                    \"max_latency_us\":{}}}",
+                   stats.calls,
+                   stats.would_block,
+                   stats.errors,
+                   stats.bytes,
+                   stats.max_bytes,
+                   stats.sampled,
+                   micros(stats.sampled_time),
+                   micros(stats.max_latency));
+}
+
+fn micros(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000 + duration.subsec_nanos() as u64 / 1_000
+}
+
+// Writes `s` as a JSON string
+fn write_str(out: &mut String, s: &str) {
+    out.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+}
+
+impl<T: Io + 'static> pipeline::ServerProto<T> for AdminProto {
+    type Request = String;
+    type Response = String;
+    type Transport = Framed<T, LineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(LineCodec::new().max_line_len(MAX_COMMAND_LEN)))
+    }
+}
+
+impl Service for Admin {
+    type Request = String;
+    type Response = String;
+    type Error = io::Error;
+    type Future = future::FutureResult<String, io::Error>;
+
+    fn call(&self, command: String) -> Self::Future {
+        future::ok(self.answer(&command))
+    }
+}
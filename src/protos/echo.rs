@@ -0,0 +1,188 @@
+//! An echo protocol over length-delimited frames.
+//!
+//! Every frame is a 4 byte big-endian payload length followed by the payload.
+//! In the multiplexed variant the payload is preceded by an 8 byte big-endian
+//! request id. `Echo` is a service responding with its requests, so a server
+//! is as simple as:
+//!
+//! ```no_run
+//! use tokio_proto::TcpServer;
+//! use tokio_proto::protos::echo::{Echo, PipelineProto};
+//!
+//! let addr = "0.0.0.0:12345".parse().unwrap();
+//! TcpServer::new(PipelineProto, addr).serve(|| Ok(Echo));
+//! ```
+
+use std::io;
+
+use futures::future;
+use tokio_core::io::{Io, Codec, Framed, EasyBuf};
+use tokio_service::Service;
+
+use {pipeline, multiplex};
+use streaming::multiplex::Counter;
+
+/// Frames larger than this are rejected, 8 MiB.
+pub const MAX_FRAME_LEN: usize = 8 * 1024 * 1024;
+
+/// Codec for length-delimited frames.
+#[derive(Debug, Default)]
+pub struct LengthCodec;
+
+/// Codec for length-delimited frames carrying a request id.
+#[derive(Debug, Default)]
+pub struct MultiplexCodec;
+
+/// The pipelined echo protocol, usable as both client and server.
+#[derive(Debug, Default)]
+pub struct PipelineProto;
+
+/// The multiplexed echo protocol, usable as both client and server.
+#[derive(Debug, Default)]
+pub struct MultiplexProto;
+
+/// A service responding with its requests.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Echo;
+
+fn read_u32(buf: &[u8]) -> u32 {
+    buf.iter().take(4).fold(0, |n, &b| (n << 8) | b as u32)
+}
+
+fn read_u64(buf: &[u8]) -> u64 {
+    buf.iter().take(8).fold(0, |n, &b| (n << 8) | b as u64)
+}
+
+fn write_u32(n: u32, buf: &mut Vec<u8>) {
+    buf.extend((0..4).rev().map(|i| (n >> (i * 8)) as u8));
+}
+
+fn write_u64(n: u64, buf: &mut Vec<u8>) {
+    buf.extend((0..8).rev().map(|i| (n >> (i * 8)) as u8));
+}
+
+impl Codec for LengthCodec {
+    type In = Vec<u8>;
+    type Out = Vec<u8>;
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<Vec<u8>>> {
+        if buf.len() < 4 {
+            return Ok(None);
+        }
+
+        let len = read_u32(buf.as_slice()) as usize;
+
+        if len > MAX_FRAME_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too large"));
+        }
+
+        if buf.len() < 4 + len {
+            return Ok(None);
+        }
+
+        buf.drain_to(4);
+        Ok(Some(buf.drain_to(len).as_slice().to_vec()))
+    }
+
+    fn encode(&mut self, msg: Vec<u8>, buf: &mut Vec<u8>) -> io::Result<()> {
+        if msg.len() > MAX_FRAME_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "frame too large"));
+        }
+
+        write_u32(msg.len() as u32, buf);
+        buf.extend_from_slice(&msg);
+        Ok(())
+    }
+}
+
+impl Codec for MultiplexCodec {
+    type In = (u64, Vec<u8>);
+    type Out = (u64, Vec<u8>);
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<(u64, Vec<u8>)>> {
+        if buf.len() < 8 {
+            return Ok(None);
+        }
+
+        let id = read_u64(buf.as_slice());
+        let mut rest = buf.clone();
+        rest.drain_to(8);
+
+        match try!(LengthCodec.decode(&mut rest)) {
+            Some(msg) => {
+                *buf = rest;
+                Ok(Some((id, msg)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn encode(&mut self, (id, msg): (u64, Vec<u8>), buf: &mut Vec<u8>) -> io::Result<()> {
+        write_u64(id, buf);
+        LengthCodec.encode(msg, buf)
+    }
+}
+
+impl<T: Io + 'static> pipeline::ServerProto<T> for PipelineProto {
+    type Request = Vec<u8>;
+    type Response = Vec<u8>;
+    type Transport = Framed<T, LengthCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(LengthCodec))
+    }
+}
+
+impl<T: Io + 'static> pipeline::ClientProto<T> for PipelineProto {
+    type Request = Vec<u8>;
+    type Response = Vec<u8>;
+    type Transport = Framed<T, LengthCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(LengthCodec))
+    }
+}
+
+impl<T: Io + 'static> multiplex::ServerProto<T> for MultiplexProto {
+    type Request = Vec<u8>;
+    type Response = Vec<u8>;
+    type RequestId = u64;
+    type Error = io::Error;
+    type Transport = Framed<T, MultiplexCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(MultiplexCodec))
+    }
+}
+
+impl<T: Io + 'static> multiplex::ClientProto<T> for MultiplexProto {
+    type Request = Vec<u8>;
+    type Response = Vec<u8>;
+    type RequestId = u64;
+    type Error = io::Error;
+    type Transport = Framed<T, MultiplexCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+    type RequestIdSource = Counter;
+
+    fn requestid_source(&self) -> Counter {
+        Counter::new()
+    }
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(MultiplexCodec))
+    }
+}
+
+impl Service for Echo {
+    type Request = Vec<u8>;
+    type Response = Vec<u8>;
+    type Error = io::Error;
+    type Future = future::FutureResult<Vec<u8>, io::Error>;
+
+    fn call(&self, req: Vec<u8>) -> Self::Future {
+        future::ok(req)
+    }
+}
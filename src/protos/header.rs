@@ -0,0 +1,369 @@
+//! Messages made of headers and a payload.
+//!
+//! `HeaderFrame` is a list of name / value headers followed by an opaque
+//! payload, a shared starting point for HTTP-like and STOMP-like protocols.
+//! On the wire, every header is a `name: value` line, the headers end with an
+//! empty line and the payload length is carried in a `content-length` header:
+//!
+//! ```text
+//! destination: /queue/a\r\n
+//! content-length: 5\r\n
+//! \r\n
+//! hello
+//! ```
+//!
+//! `HeaderProto` exchanges one frame per request and response.
+//! `MultiplexHeaderProto` multiplexes frames by a numeric request id header.
+
+use std::io;
+use std::str;
+
+use tokio_core::io::{Io, Codec, Framed, EasyBuf};
+
+use {pipeline, multiplex};
+use streaming::multiplex::Counter;
+
+const CONTENT_LENGTH: &str = "content-length";
+
+/// Header sections larger than this are rejected, 64 KiB.
+pub const MAX_HEADER_LEN: usize = 64 * 1024;
+
+/// Payloads larger than this are rejected, 8 MiB.
+pub const MAX_PAYLOAD_LEN: usize = 8 * 1024 * 1024;
+
+/// A message made of headers and a payload.
+///
+/// Header names are matched ASCII case-insensitively and a name may occur
+/// more than once. The `content-length` header is reserved for framing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderFrame {
+    headers: Vec<(String, String)>,
+    payload: Vec<u8>,
+}
+
+impl HeaderFrame {
+    /// Create a new `HeaderFrame` without headers and with an empty payload.
+    pub fn new() -> HeaderFrame {
+        HeaderFrame::default()
+    }
+
+    /// Create a new `HeaderFrame` without headers, carrying `payload`.
+    pub fn with_payload(payload: Vec<u8>) -> HeaderFrame {
+        HeaderFrame {
+            headers: Vec::new(),
+            payload: payload,
+        }
+    }
+
+    /// Returns the value of the first header called `name`.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|h| h.0.eq_ignore_ascii_case(name))
+            .map(|h| &h.1[..])
+    }
+
+    /// Returns an iterator over all headers, in order.
+    pub fn headers<'a>(&'a self) -> Box<Iterator<Item = (&'a str, &'a str)> + 'a> {
+        Box::new(self.headers.iter().map(|h| (&h.0[..], &h.1[..])))
+    }
+
+    /// Add a header, keeping any others of the same name.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is `content-length` or empty, or if either contains
+    /// characters that cannot be encoded, such as line breaks or a colon in
+    /// the name.
+    pub fn append(&mut self, name: &str, value: &str) {
+        assert!(is_valid_name(name), "invalid header name: {:?}", name);
+        assert!(is_valid_value(value), "invalid header value: {:?}", value);
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    /// Set a header, replacing any others of the same name.
+    ///
+    /// # Panics
+    ///
+    /// Panics on invalid names and values, see `append`.
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    /// Remove all headers called `name`, returning the value of the first.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let mut removed = None;
+
+        while let Some(i) = self.headers.iter().position(|h| h.0.eq_ignore_ascii_case(name)) {
+            let (_, value) = self.headers.remove(i);
+            removed = removed.or(Some(value));
+        }
+
+        removed
+    }
+
+    /// Returns the payload.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Returns a mutable reference to the payload.
+    pub fn payload_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.payload
+    }
+
+    /// Consumes the frame, returning the payload.
+    pub fn into_payload(self) -> Vec<u8> {
+        self.payload
+    }
+
+    /// Returns the number of bytes `encode` writes for this frame.
+    pub fn encoded_len(&self) -> usize {
+        let headers = self.headers.iter().map(|h| h.0.len() + h.1.len() + 4).sum::<usize>();
+
+        let length = if self.payload.is_empty() {
+            0
+        } else {
+            CONTENT_LENGTH.len() + self.payload.len().to_string().len() + 4
+        };
+
+        headers + length + 2 + self.payload.len()
+    }
+
+    /// Encode the frame into the buffer.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.reserve(self.encoded_len());
+
+        for header in &self.headers {
+            write_header(&header.0, &header.1, buf);
+        }
+
+        if !self.payload.is_empty() {
+            write_header(CONTENT_LENGTH, &self.payload.len().to_string(), buf);
+        }
+
+        buf.extend_from_slice(b"\r\n");
+        buf.extend_from_slice(&self.payload);
+    }
+
+    /// Attempt to decode a frame from the buffer.
+    ///
+    /// Returns `Ok(None)` if the buffer does not yet hold a complete frame.
+    pub fn decode(buf: &mut EasyBuf) -> io::Result<Option<HeaderFrame>> {
+        let end = if buf.as_slice().starts_with(b"\r\n") {
+            0
+        } else {
+            match buf.as_slice().windows(4).position(|w| w == b"\r\n\r\n") {
+                Some(end) => end + 2,
+                None if buf.len() > MAX_HEADER_LEN => {
+                    return Err(invalid_data("header section too long"));
+                }
+                None => return Ok(None),
+            }
+        };
+
+        if end > MAX_HEADER_LEN {
+            return Err(invalid_data("header section too long"));
+        }
+
+        let mut frame = HeaderFrame::new();
+        let mut payload_len = 0;
+
+        {
+            let section = try!(str::from_utf8(&buf.as_slice()[..end])
+                               .map_err(|_| invalid_data("headers are not UTF-8")));
+
+            for line in section.split("\r\n").filter(|line| !line.is_empty()) {
+                let colon = try!(line.find(':').ok_or_else(|| invalid_data("header without colon")));
+                let name = &line[..colon];
+                let value = line[colon + 1..].trim_left_matches(' ');
+
+                if name.eq_ignore_ascii_case(CONTENT_LENGTH) {
+                    payload_len = try!(value.parse().map_err(|_| invalid_data("invalid content-length")));
+                } else if is_valid_name(name) {
+                    frame.headers.push((name.to_string(), value.to_string()));
+                } else {
+                    return Err(invalid_data("invalid header name"));
+                }
+            }
+        }
+
+        if payload_len > MAX_PAYLOAD_LEN {
+            return Err(invalid_data("payload too long"));
+        }
+
+        if buf.len() < end + 2 + payload_len {
+            return Ok(None);
+        }
+
+        buf.drain_to(end + 2);
+        frame.payload = buf.drain_to(payload_len).as_slice().to_vec();
+
+        Ok(Some(frame))
+    }
+}
+
+fn write_header(name: &str, value: &str, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(name.as_bytes());
+    buf.extend_from_slice(b": ");
+    buf.extend_from_slice(value.as_bytes());
+    buf.extend_from_slice(b"\r\n");
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() &&
+        !name.eq_ignore_ascii_case(CONTENT_LENGTH) &&
+        !name.contains(&[':', '\r', '\n'][..])
+}
+
+fn is_valid_value(value: &str) -> bool {
+    !value.contains(&['\r', '\n'][..])
+}
+
+/// Codec for `HeaderFrame`s.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeaderCodec;
+
+impl Codec for HeaderCodec {
+    type In = HeaderFrame;
+    type Out = HeaderFrame;
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<HeaderFrame>> {
+        HeaderFrame::decode(buf)
+    }
+
+    fn encode(&mut self, msg: HeaderFrame, buf: &mut Vec<u8>) -> io::Result<()> {
+        msg.encode(buf);
+        Ok(())
+    }
+}
+
+/// Codec for `HeaderFrame`s carrying a numeric request id header.
+///
+/// The id header is removed from decoded frames and added to encoded ones.
+#[derive(Debug, Clone)]
+pub struct MultiplexHeaderCodec {
+    id_header: String,
+}
+
+impl MultiplexHeaderCodec {
+    /// Create a new `MultiplexHeaderCodec` carrying request ids in the
+    /// `id_header` header.
+    pub fn new(id_header: &str) -> MultiplexHeaderCodec {
+        assert!(is_valid_name(id_header), "invalid header name: {:?}", id_header);
+        MultiplexHeaderCodec { id_header: id_header.to_string() }
+    }
+}
+
+impl Codec for MultiplexHeaderCodec {
+    type In = (u64, HeaderFrame);
+    type Out = (u64, HeaderFrame);
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<(u64, HeaderFrame)>> {
+        let mut frame = match try!(HeaderFrame::decode(buf)) {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+
+        let id = try!(frame.remove(&self.id_header)
+                      .and_then(|id| id.parse().ok())
+                      .ok_or_else(|| invalid_data("missing or invalid request id")));
+
+        Ok(Some((id, frame)))
+    }
+
+    fn encode(&mut self, (id, mut frame): (u64, HeaderFrame), buf: &mut Vec<u8>) -> io::Result<()> {
+        frame.insert(&self.id_header, &id.to_string());
+        frame.encode(buf);
+        Ok(())
+    }
+}
+
+/// Pipelined protocol exchanging `HeaderFrame`s, usable as both client and
+/// server.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeaderProto;
+
+impl<T: Io + 'static> pipeline::ServerProto<T> for HeaderProto {
+    type Request = HeaderFrame;
+    type Response = HeaderFrame;
+    type Transport = Framed<T, HeaderCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(HeaderCodec))
+    }
+}
+
+impl<T: Io + 'static> pipeline::ClientProto<T> for HeaderProto {
+    type Request = HeaderFrame;
+    type Response = HeaderFrame;
+    type Transport = Framed<T, HeaderCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(HeaderCodec))
+    }
+}
+
+/// Multiplexed protocol exchanging `HeaderFrame`s, usable as both client and
+/// server.
+///
+/// Request ids are carried in a header, `request-id` by default.
+#[derive(Debug, Clone)]
+pub struct MultiplexHeaderProto {
+    codec: MultiplexHeaderCodec,
+}
+
+impl MultiplexHeaderProto {
+    /// Create a new `MultiplexHeaderProto` carrying request ids in the
+    /// `id_header` header.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id_header` is not a valid header name.
+    pub fn new(id_header: &str) -> MultiplexHeaderProto {
+        MultiplexHeaderProto { codec: MultiplexHeaderCodec::new(id_header) }
+    }
+}
+
+impl Default for MultiplexHeaderProto {
+    fn default() -> MultiplexHeaderProto {
+        MultiplexHeaderProto::new("request-id")
+    }
+}
+
+impl<T: Io + 'static> multiplex::ServerProto<T> for MultiplexHeaderProto {
+    type Request = HeaderFrame;
+    type Response = HeaderFrame;
+    type RequestId = u64;
+    type Error = io::Error;
+    type Transport = Framed<T, MultiplexHeaderCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(self.codec.clone()))
+    }
+}
+
+impl<T: Io + 'static> multiplex::ClientProto<T> for MultiplexHeaderProto {
+    type Request = HeaderFrame;
+    type Response = HeaderFrame;
+    type RequestId = u64;
+    type Error = io::Error;
+    type Transport = Framed<T, MultiplexHeaderCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+    type RequestIdSource = Counter;
+
+    fn requestid_source(&self) -> Counter {
+        Counter::new()
+    }
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(self.codec.clone()))
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
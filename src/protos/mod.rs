@@ -0,0 +1,11 @@
+//! Ready made protocols.
+//!
+//! These are small but complete protocols, usable in tests, benchmarks and
+//! smoke tests of deployments, as well as building blocks for protocols of
+//! your own.
+
+pub mod admin;
+pub mod echo;
+pub mod header;
+pub mod text_line;
+pub mod netstring;
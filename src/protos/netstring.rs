@@ -0,0 +1,308 @@
+//! Netstring framing.
+//!
+//! A netstring is a payload prefixed with its length in decimal and a colon,
+//! followed by a comma, e.g. `5:hello,`. `NetstringCodec` parses them either
+//! strictly, or leniently to cope with peers allowing leading zeros in the
+//! length and whitespace between netstrings.
+//!
+//! `NetstringProto` exchanges one netstring per request and response.
+//! `MultiplexNetstringProto` multiplexes requests over a connection when the
+//! payloads carry a request id, found by a user supplied closure.
+
+use std::io;
+use std::sync::Arc;
+
+use tokio_core::io::{Io, Codec, Framed, EasyBuf};
+
+use {pipeline, multiplex};
+use util::framed::DecodeHint;
+use multiplex::{RequestId, RequestIdSource};
+
+/// How strictly netstrings are parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Only accept netstrings exactly as specified.
+    Strict,
+
+    /// Accept leading zeros in lengths and skip ASCII whitespace between
+    /// netstrings.
+    Lenient,
+}
+
+/// Codec for netstrings.
+///
+/// Defaults to strict parsing of payloads of at most 1 MiB.
+#[derive(Debug, Clone)]
+pub struct NetstringCodec {
+    mode: Mode,
+    max_len: usize,
+}
+
+impl NetstringCodec {
+    /// Create a new `NetstringCodec` with the default settings.
+    pub fn new() -> NetstringCodec {
+        NetstringCodec {
+            mode: Mode::Strict,
+            max_len: 1024 * 1024,
+        }
+    }
+
+    /// Set how strictly netstrings are parsed.
+    pub fn mode(mut self, mode: Mode) -> NetstringCodec {
+        self.mode = mode;
+        self
+    }
+
+    /// Set the maximum payload length.
+    ///
+    /// Receiving or sending a longer payload is an error.
+    pub fn max_len(mut self, max_len: usize) -> NetstringCodec {
+        self.max_len = max_len;
+        self
+    }
+
+    // Parses the length prefix, returning the payload length and the offset
+    // of the payload
+    fn decode_len(&self, buf: &[u8]) -> io::Result<Option<(usize, usize)>> {
+        let mut len = 0usize;
+
+        for (i, &b) in buf.iter().enumerate() {
+            match b {
+                b'0'..=b'9' => {
+                    if self.mode == Mode::Strict && i == 1 && buf[0] == b'0' {
+                        return Err(invalid_data("leading zero in netstring length"));
+                    }
+
+                    len = len.saturating_mul(10).saturating_add((b - b'0') as usize);
+
+                    if len > self.max_len {
+                        return Err(invalid_data("netstring too long"));
+                    }
+                }
+                b':' if i > 0 => return Ok(Some((len, i + 1))),
+                _ => return Err(invalid_data("invalid netstring length")),
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl Default for NetstringCodec {
+    fn default() -> NetstringCodec {
+        NetstringCodec::new()
+    }
+}
+
+impl Codec for NetstringCodec {
+    type In = Vec<u8>;
+    type Out = Vec<u8>;
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<Vec<u8>>> {
+        if self.mode == Mode::Lenient {
+            let ws = buf.as_slice().iter().take_while(|b| b.is_ascii_whitespace()).count();
+            buf.drain_to(ws);
+        }
+
+        let (len, start) = match try!(self.decode_len(buf.as_slice())) {
+            Some(prefix) => prefix,
+            None => return Ok(None),
+        };
+
+        if buf.len() < start + len + 1 {
+            return Ok(None);
+        }
+
+        if buf.as_slice()[start + len] != b',' {
+            return Err(invalid_data("netstring not terminated by a comma"));
+        }
+
+        buf.drain_to(start);
+        let payload = buf.drain_to(len);
+        buf.drain_to(1);
+
+        Ok(Some(payload.as_slice().to_vec()))
+    }
+
+    fn encode(&mut self, msg: Vec<u8>, buf: &mut Vec<u8>) -> io::Result<()> {
+        if msg.len() > self.max_len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "netstring too long"));
+        }
+
+        buf.extend_from_slice(msg.len().to_string().as_bytes());
+        buf.push(b':');
+        buf.extend_from_slice(&msg);
+        buf.push(b',');
+        Ok(())
+    }
+}
+
+impl DecodeHint for NetstringCodec {
+    fn message_len(&self, buf: &EasyBuf) -> Option<usize> {
+        match self.decode_len(buf.as_slice()) {
+            Ok(Some((len, start))) => Some(start + len + 1),
+            _ => None,
+        }
+    }
+}
+
+/// Pipelined netstring protocol, usable as both client and server.
+#[derive(Debug, Clone, Default)]
+pub struct NetstringProto {
+    codec: NetstringCodec,
+}
+
+impl NetstringProto {
+    /// Create a new `NetstringProto` framing payloads with the given codec.
+    pub fn new(codec: NetstringCodec) -> NetstringProto {
+        NetstringProto { codec: codec }
+    }
+}
+
+impl<T: Io + 'static> pipeline::ServerProto<T> for NetstringProto {
+    type Request = Vec<u8>;
+    type Response = Vec<u8>;
+    type Transport = Framed<T, NetstringCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(self.codec.clone()))
+    }
+}
+
+impl<T: Io + 'static> pipeline::ClientProto<T> for NetstringProto {
+    type Request = Vec<u8>;
+    type Response = Vec<u8>;
+    type Transport = Framed<T, NetstringCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(self.codec.clone()))
+    }
+}
+
+/// Codec for netstrings whose payloads carry a request id.
+///
+/// Decoding fails for payloads without an id. Encoding checks that the
+/// payload carries the id it is sent with, as the peer has no other way of
+/// finding it.
+pub struct IdCodec<F> {
+    codec: NetstringCodec,
+    extract: Arc<F>,
+}
+
+impl<F, I> Codec for IdCodec<F>
+    where F: Fn(&[u8]) -> Option<I>,
+          I: PartialEq,
+{
+    type In = (I, Vec<u8>);
+    type Out = (I, Vec<u8>);
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<(I, Vec<u8>)>> {
+        match try!(self.codec.decode(buf)) {
+            Some(payload) => {
+                match (self.extract)(&payload) {
+                    Some(id) => Ok(Some((id, payload))),
+                    None => Err(invalid_data("netstring without request id")),
+                }
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn encode(&mut self, (id, msg): (I, Vec<u8>), buf: &mut Vec<u8>) -> io::Result<()> {
+        if (self.extract)(&msg) != Some(id) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "netstring does not carry its request id"));
+        }
+
+        self.codec.encode(msg, buf)
+    }
+}
+
+/// `RequestIdSource` looking up request ids in the payloads.
+pub struct ExtractId<F> {
+    extract: Arc<F>,
+}
+
+impl<F, I> RequestIdSource<I, Vec<u8>> for ExtractId<F>
+    where F: Fn(&[u8]) -> Option<I> + 'static,
+{
+    /// # Panics
+    ///
+    /// Panics if the request carries no id.
+    fn next(&mut self, msg: &Vec<u8>) -> I {
+        (self.extract)(msg).expect("netstring request without request id")
+    }
+}
+
+/// Multiplexed netstring protocol, usable as both client and server.
+///
+/// The request id of every payload is found by the `extract` closure, so
+/// both requests and responses need to carry one. Calling a client with a
+/// request without an id panics.
+pub struct MultiplexNetstringProto<F> {
+    codec: NetstringCodec,
+    extract: Arc<F>,
+}
+
+impl<F> MultiplexNetstringProto<F> {
+    /// Create a new `MultiplexNetstringProto` framing payloads with the given
+    /// codec and finding their request ids with `extract`.
+    pub fn new(codec: NetstringCodec, extract: F) -> MultiplexNetstringProto<F> {
+        MultiplexNetstringProto {
+            codec: codec,
+            extract: Arc::new(extract),
+        }
+    }
+
+    fn id_codec(&self) -> IdCodec<F> {
+        IdCodec {
+            codec: self.codec.clone(),
+            extract: self.extract.clone(),
+        }
+    }
+}
+
+impl<T, F, I> multiplex::ServerProto<T> for MultiplexNetstringProto<F>
+    where T: Io + 'static,
+          F: Fn(&[u8]) -> Option<I> + 'static,
+          I: RequestId,
+{
+    type Request = Vec<u8>;
+    type Response = Vec<u8>;
+    type RequestId = I;
+    type Error = io::Error;
+    type Transport = Framed<T, IdCodec<F>>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(self.id_codec()))
+    }
+}
+
+impl<T, F, I> multiplex::ClientProto<T> for MultiplexNetstringProto<F>
+    where T: Io + 'static,
+          F: Fn(&[u8]) -> Option<I> + 'static,
+          I: RequestId + Send,
+{
+    type Request = Vec<u8>;
+    type Response = Vec<u8>;
+    type RequestId = I;
+    type Error = io::Error;
+    type Transport = Framed<T, IdCodec<F>>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+    type RequestIdSource = ExtractId<F>;
+
+    fn requestid_source(&self) -> ExtractId<F> {
+        ExtractId { extract: self.extract.clone() }
+    }
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(self.id_codec()))
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,327 @@
+//! Line-delimited text protocols.
+//!
+//! `LineCodec` splits the byte stream on a configurable delimiter, bounds the
+//! length of lines and validates their charset, which covers the framing of
+//! protocols in the style of SMTP, IMAP or inline Redis commands.
+//!
+//! `TextLineProto` is a pipelined protocol exchanging one line per request
+//! and response. `TaggedLineProto` multiplexes requests by starting every
+//! line with a tag token, such as `A1 NOOP`, used as the request id.
+
+use std::io;
+
+use tokio_core::io::{Io, Codec, Framed, EasyBuf};
+
+use {pipeline, multiplex};
+use multiplex::RequestIdSource;
+
+/// Character sets lines may be restricted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    /// Any valid UTF-8.
+    Utf8,
+
+    /// 7-bit ASCII only.
+    Ascii,
+
+    /// ISO-8859-1, every byte maps to the char of the same value.
+    Latin1,
+}
+
+/// Codec for lines of text.
+///
+/// Defaults to `\n` delimited UTF-8 lines of at most 8 KiB, not counting the
+/// delimiter.
+#[derive(Debug, Clone)]
+pub struct LineCodec {
+    delimiter: Vec<u8>,
+    max_line_len: usize,
+    charset: Charset,
+}
+
+impl LineCodec {
+    /// Create a new `LineCodec` with the default settings.
+    pub fn new() -> LineCodec {
+        LineCodec {
+            delimiter: b"\n".to_vec(),
+            max_line_len: 8 * 1024,
+            charset: Charset::Utf8,
+        }
+    }
+
+    /// Set the byte sequence terminating lines, such as `\r\n`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `delimiter` is empty.
+    pub fn delimiter(mut self, delimiter: &[u8]) -> LineCodec {
+        assert!(!delimiter.is_empty(), "line delimiter cannot be empty");
+        self.delimiter = delimiter.to_vec();
+        self
+    }
+
+    /// Set the maximum length of a line in bytes, not counting the
+    /// delimiter.
+    ///
+    /// Receiving a longer line is an error.
+    pub fn max_line_len(mut self, max_line_len: usize) -> LineCodec {
+        self.max_line_len = max_line_len;
+        self
+    }
+
+    /// Set the character set lines are restricted to.
+    pub fn charset(mut self, charset: Charset) -> LineCodec {
+        self.charset = charset;
+        self
+    }
+
+    fn find_delimiter(&self, buf: &[u8]) -> Option<usize> {
+        buf.windows(self.delimiter.len()).position(|w| w == &self.delimiter[..])
+    }
+
+    fn decode_line(&self, line: &[u8]) -> io::Result<String> {
+        match self.charset {
+            Charset::Utf8 | Charset::Ascii => {
+                if self.charset == Charset::Ascii && !line.is_ascii() {
+                    return Err(invalid_data("line is not ASCII"));
+                }
+
+                String::from_utf8(line.to_vec()).map_err(|_| invalid_data("line is not UTF-8"))
+            }
+            Charset::Latin1 => Ok(line.iter().map(|&b| b as char).collect()),
+        }
+    }
+}
+
+impl Default for LineCodec {
+    fn default() -> LineCodec {
+        LineCodec::new()
+    }
+}
+
+impl Codec for LineCodec {
+    type In = String;
+    type Out = String;
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<String>> {
+        // Don't search further than the longest allowed line
+        let search_len = ::std::cmp::min(buf.len(), self.max_line_len + self.delimiter.len());
+
+        match self.find_delimiter(&buf.as_slice()[..search_len]) {
+            Some(i) => {
+                let line = buf.drain_to(i);
+                buf.drain_to(self.delimiter.len());
+                self.decode_line(line.as_slice()).map(Some)
+            }
+            None if buf.len() >= self.max_line_len + self.delimiter.len() => {
+                Err(invalid_data("line too long"))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn encode(&mut self, msg: String, buf: &mut Vec<u8>) -> io::Result<()> {
+        let start = buf.len();
+
+        match self.charset {
+            Charset::Utf8 => buf.extend_from_slice(msg.as_bytes()),
+            Charset::Ascii => {
+                if !msg.is_ascii() {
+                    return Err(invalid_input("line is not ASCII"));
+                }
+
+                buf.extend_from_slice(msg.as_bytes());
+            }
+            Charset::Latin1 => {
+                for c in msg.chars() {
+                    if c as u32 > 0xff {
+                        buf.truncate(start);
+                        return Err(invalid_input("line is not Latin-1"));
+                    }
+
+                    buf.push(c as u8);
+                }
+            }
+        }
+
+        if self.find_delimiter(&buf[start..]).is_some() {
+            buf.truncate(start);
+            return Err(invalid_input("line contains the delimiter"));
+        }
+
+        buf.extend_from_slice(&self.delimiter);
+        Ok(())
+    }
+}
+
+/// Codec for lines starting with a tag token, separated by a space.
+///
+/// Lines without a space are all tag, with an empty remainder.
+#[derive(Debug, Clone, Default)]
+pub struct TaggedCodec {
+    lines: LineCodec,
+}
+
+impl TaggedCodec {
+    /// Create a new `TaggedCodec` framing lines with the given codec.
+    pub fn new(lines: LineCodec) -> TaggedCodec {
+        TaggedCodec { lines: lines }
+    }
+}
+
+impl Codec for TaggedCodec {
+    type In = (String, String);
+    type Out = (String, String);
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<(String, String)>> {
+        let line = match try!(self.lines.decode(buf)) {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+
+        let mut parts = line.splitn(2, ' ');
+        let tag = parts.next().unwrap_or("").to_string();
+        let rest = parts.next().unwrap_or("").to_string();
+
+        Ok(Some((tag, rest)))
+    }
+
+    fn encode(&mut self, (tag, rest): (String, String), buf: &mut Vec<u8>) -> io::Result<()> {
+        if tag.is_empty() || tag.contains(' ') {
+            return Err(invalid_input("invalid tag"));
+        }
+
+        if rest.is_empty() {
+            self.lines.encode(tag, buf)
+        } else {
+            self.lines.encode(format!("{} {}", tag, rest), buf)
+        }
+    }
+}
+
+/// Pipelined protocol exchanging lines, usable as both client and server.
+#[derive(Debug, Clone, Default)]
+pub struct TextLineProto {
+    codec: LineCodec,
+}
+
+impl TextLineProto {
+    /// Create a new `TextLineProto` framing lines with the given codec.
+    pub fn new(codec: LineCodec) -> TextLineProto {
+        TextLineProto { codec: codec }
+    }
+}
+
+impl<T: Io + 'static> pipeline::ServerProto<T> for TextLineProto {
+    type Request = String;
+    type Response = String;
+    type Transport = Framed<T, LineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(self.codec.clone()))
+    }
+}
+
+impl<T: Io + 'static> pipeline::ClientProto<T> for TextLineProto {
+    type Request = String;
+    type Response = String;
+    type Transport = Framed<T, LineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(self.codec.clone()))
+    }
+}
+
+/// Multiplexed protocol using the leading tag token of lines as request id,
+/// usable as both client and server.
+///
+/// Clients tag requests with a fixed prefix followed by a counter, e.g. `A1`,
+/// `A2` and so on.
+#[derive(Debug, Clone)]
+pub struct TaggedLineProto {
+    codec: LineCodec,
+    prefix: String,
+}
+
+impl TaggedLineProto {
+    /// Create a new `TaggedLineProto` framing lines with the given codec and
+    /// tagging client requests with `prefix`.
+    pub fn new(codec: LineCodec, prefix: &str) -> TaggedLineProto {
+        TaggedLineProto {
+            codec: codec,
+            prefix: prefix.to_string(),
+        }
+    }
+}
+
+impl Default for TaggedLineProto {
+    fn default() -> TaggedLineProto {
+        TaggedLineProto::new(LineCodec::new(), "A")
+    }
+}
+
+/// `RequestIdSource` generating tags made of a prefix and a counter.
+#[derive(Debug, Clone)]
+pub struct TagSource {
+    prefix: String,
+    next: u64,
+}
+
+impl TagSource {
+    /// Create a new `TagSource`, starting with `{prefix}1`.
+    pub fn new(prefix: &str) -> TagSource {
+        TagSource {
+            prefix: prefix.to_string(),
+            next: 1,
+        }
+    }
+}
+
+impl<T> RequestIdSource<String, T> for TagSource {
+    fn next(&mut self, _: &T) -> String {
+        let tag = format!("{}{}", self.prefix, self.next);
+        self.next += 1;
+        tag
+    }
+}
+
+impl<T: Io + 'static> multiplex::ServerProto<T> for TaggedLineProto {
+    type Request = String;
+    type Response = String;
+    type RequestId = String;
+    type Error = io::Error;
+    type Transport = Framed<T, TaggedCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(TaggedCodec::new(self.codec.clone())))
+    }
+}
+
+impl<T: Io + 'static> multiplex::ClientProto<T> for TaggedLineProto {
+    type Request = String;
+    type Response = String;
+    type RequestId = String;
+    type Error = io::Error;
+    type Transport = Framed<T, TaggedCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+    type RequestIdSource = TagSource;
+
+    fn requestid_source(&self) -> TagSource {
+        TagSource::new(&self.prefix)
+    }
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(TaggedCodec::new(self.codec.clone())))
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
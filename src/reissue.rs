@@ -0,0 +1,133 @@
+//! Re-issuing the requests a server going away did not process.
+//!
+//! A server shutting down gracefully announces it to its multiplex clients,
+//! telling them the last request it processed, see `multiplex::GoAway`. The
+//! later requests fail with a `NotProcessed` error. They never reached the
+//! server's service, so they can be sent again on another connection without
+//! risk of being processed twice, even if they are not idempotent.
+
+use std::error::Error;
+use std::io;
+use std::rc::Rc;
+
+use futures::{Async, Future, Poll};
+use tokio_service::Service;
+
+use streaming::multiplex::NotProcessed;
+
+/// Number of times a request is sent again, by default.
+pub const DEFAULT_MAX_REISSUES: usize = 3;
+
+/// Sends the requests a server going away did not process again.
+///
+/// Multiplex clients fail the requests a draining server announced it did
+/// not process with a `NotProcessed` error, see `multiplex::GoAway`. Such
+/// requests never reached the server's service, so a `Reissue` sends them
+/// again, transparently to the caller. The inner service is expected to
+/// establish a fresh connection for them, like a `pool::Client` or a watched
+/// `LazyClient` does: the error counts as the loss of the connection.
+///
+/// Requests failing with any other error are not sent again.
+pub struct Reissue<S> {
+    inner: Rc<S>,
+    max_reissues: usize,
+}
+
+/// Response future of a `Reissue` service.
+pub struct ReissueFuture<S: Service> {
+    inner: Rc<S>,
+    future: S::Future,
+    // Copy of the request, sent again if it was not processed
+    request: S::Request,
+    reissues_left: usize,
+}
+
+impl<S> Reissue<S> {
+    /// Create a new `Reissue` sending the requests of `inner` the server did
+    /// not process again, up to `DEFAULT_MAX_REISSUES` times.
+    pub fn new(inner: S) -> Reissue<S> {
+        Reissue {
+            inner: Rc::new(inner),
+            max_reissues: DEFAULT_MAX_REISSUES,
+        }
+    }
+
+    /// Send a request again at most `max_reissues` times, after which its
+    /// `NotProcessed` error is returned to the caller.
+    ///
+    /// Defaults to `DEFAULT_MAX_REISSUES`.
+    pub fn max_reissues(self, max_reissues: usize) -> Reissue<S> {
+        Reissue {
+            inner: self.inner,
+            max_reissues: max_reissues,
+        }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S> Clone for Reissue<S> {
+    fn clone(&self) -> Reissue<S> {
+        Reissue {
+            inner: self.inner.clone(),
+            max_reissues: self.max_reissues,
+        }
+    }
+}
+
+impl<S> Service for Reissue<S>
+    where S: Service,
+          S::Request: Clone,
+          S::Error: Error + 'static,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ReissueFuture<S>;
+
+    fn call(&self, req: S::Request) -> ReissueFuture<S> {
+        ReissueFuture {
+            inner: self.inner.clone(),
+            future: self.inner.call(req.clone()),
+            request: req,
+            reissues_left: self.max_reissues,
+        }
+    }
+}
+
+impl<S> Future for ReissueFuture<S>
+    where S: Service,
+          S::Request: Clone,
+          S::Error: Error + 'static,
+{
+    type Item = S::Response;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<S::Response, S::Error> {
+        loop {
+            let err = match self.future.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(response)) => return Ok(Async::Ready(response)),
+                Err(err) => err,
+            };
+
+            if self.reissues_left == 0 || !is_not_processed(&err) {
+                return Err(err);
+            }
+
+            trace!("reissuing request not processed by the server");
+            self.reissues_left -= 1;
+            self.future = self.inner.call(self.request.clone());
+        }
+    }
+}
+
+fn is_not_processed<E: Error + 'static>(err: &E) -> bool {
+    match (err as &Error).downcast_ref::<io::Error>().and_then(|err| err.get_ref()) {
+        Some(err) => err.is::<NotProcessed>(),
+        None => false,
+    }
+}
@@ -0,0 +1,86 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use futures::Future;
+use tokio_core::reactor::Handle;
+use tokio_service::Service;
+
+use ConnectionEvents;
+
+/// Replays session setup requests on every new connection.
+///
+/// Requests registered here, such as `AUTH` or `SUBSCRIBE` commands, are sent
+/// on each connection a `LazyClient` establishes, ahead of any other traffic.
+/// Register the requests before the first call to have them sent on the
+/// initial connection too.
+///
+/// Clones share the registered requests, so a clone can be kept around to
+/// register more after handing one to `LazyClient::watch`.
+pub struct Resubscribe<R> {
+    requests: Rc<RefCell<Vec<R>>>,
+    handle: Handle,
+}
+
+impl<R> Resubscribe<R> {
+    /// Create a new `Resubscribe` without any requests.
+    ///
+    /// Responses to the replayed requests are awaited on the given event
+    /// loop, failures are logged.
+    pub fn new(handle: &Handle) -> Resubscribe<R> {
+        Resubscribe {
+            requests: Rc::new(RefCell::new(Vec::new())),
+            handle: handle.clone(),
+        }
+    }
+
+    /// Register a request to be sent on every new connection.
+    ///
+    /// Requests are replayed in the order they were registered.
+    pub fn register(&self, request: R) {
+        self.requests.borrow_mut().push(request);
+    }
+
+    /// Forget every registered request.
+    pub fn clear(&self) {
+        self.requests.borrow_mut().clear();
+    }
+
+    /// Returns the number of registered requests.
+    pub fn len(&self) -> usize {
+        self.requests.borrow().len()
+    }
+
+    /// Returns true if no requests are registered.
+    pub fn is_empty(&self) -> bool {
+        self.requests.borrow().is_empty()
+    }
+}
+
+impl<R> Clone for Resubscribe<R> {
+    fn clone(&self) -> Resubscribe<R> {
+        Resubscribe {
+            requests: self.requests.clone(),
+            handle: self.handle.clone(),
+        }
+    }
+}
+
+impl<S> ConnectionEvents<S> for Resubscribe<S::Request>
+    where S: Service,
+          S::Request: Clone,
+          S::Future: 'static,
+{
+    fn on_connect(&self, client: &S) {
+        for (i, request) in self.requests.borrow().iter().enumerate() {
+            let response = client.call(request.clone()).then(move |res| {
+                if res.is_err() {
+                    debug!("session setup request failed; index={}", i);
+                }
+
+                Ok(())
+            });
+
+            self.handle.spawn(response);
+        }
+    }
+}
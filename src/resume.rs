@@ -0,0 +1,408 @@
+//! Resuming interrupted uploads on a new connection.
+//!
+//! A request streaming a large body fails as a whole when its connection is
+//! lost, and sending it again restarts the upload from the first byte.
+//! Protocols supporting resumable uploads identify each upload with a token
+//! and acknowledge the bytes of its body the server received, so that an
+//! interrupted upload can carry on from the last acknowledged offset
+//! instead.
+//!
+//! A `Resume` client keeps the chunks of a body it sent until they are
+//! acknowledged. The transports of its connections report acknowledgements
+//! on a shared `Acks` handle as they decode them. When the request fails,
+//! the `Resumable` hook of the client builds the head of a request resuming
+//! the upload from the acknowledged offset, which is sent along with the
+//! unacknowledged chunks and the rest of the body. The inner service is
+//! expected to re-establish its connection as needed, like a `pool::Client`
+//! or a watched `LazyClient` does.
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::io;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use futures::{Async, AsyncSink, Future, Poll, Sink, Stream};
+use tokio_service::Service;
+
+use streaming::{Body, BodySender, Chunk, Message};
+
+/// Number of times an upload is resumed, by default.
+pub const DEFAULT_MAX_RESUMES: usize = 3;
+
+/// Identifies resumable uploads and builds the requests resuming them.
+pub trait Resumable {
+    /// The head of the requests, e.g. a request line and headers.
+    type Head: Clone;
+
+    /// Token identifying an upload across connections.
+    type Token: Hash + Eq + Clone;
+
+    /// Returns the token of the upload started by a request, or `None` if
+    /// the request cannot be resumed.
+    ///
+    /// Tokens must be unique among the uploads in progress.
+    fn token(&self, head: &Self::Head) -> Option<Self::Token>;
+
+    /// Returns the head of a request resuming the upload started by `head`,
+    /// whose body is sent from `offset` onwards.
+    fn resume(&self, head: &Self::Head, offset: u64) -> Self::Head;
+}
+
+/// Acknowledged offsets of the uploads in progress, shared between a
+/// `Resume` client and the transports of its connections.
+///
+/// Transports call `ack` as they decode acknowledgements from the peer.
+/// Handles can be cloned and sent to other threads.
+pub struct Acks<K> {
+    inner: Arc<Mutex<HashMap<K, u64>>>,
+}
+
+impl<K: Hash + Eq> Acks<K> {
+    /// Create a new `Acks` without any upload in progress.
+    pub fn new() -> Acks<K> {
+        Acks { inner: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    /// Record that the peer received the first `offset` bytes of the body
+    /// of upload `token`.
+    ///
+    /// Offsets below the one already recorded, and those of uploads not in
+    /// progress, are ignored.
+    pub fn ack(&self, token: &K, offset: u64) {
+        if let Some(acked) = self.inner.lock().unwrap().get_mut(token) {
+            if offset > *acked {
+                *acked = offset;
+            }
+        }
+    }
+
+    /// Returns the acknowledged offset of upload `token`, or `None` if it is
+    /// not in progress.
+    pub fn acked(&self, token: &K) -> Option<u64> {
+        self.inner.lock().unwrap().get(token).cloned()
+    }
+
+    fn start(&self, token: K) {
+        self.inner.lock().unwrap().insert(token, 0);
+    }
+
+    fn finish(&self, token: &K) {
+        self.inner.lock().unwrap().remove(token);
+    }
+}
+
+impl<K: Hash + Eq> Default for Acks<K> {
+    fn default() -> Acks<K> {
+        Acks::new()
+    }
+}
+
+impl<K> Clone for Acks<K> {
+    fn clone(&self) -> Acks<K> {
+        Acks { inner: self.inner.clone() }
+    }
+}
+
+/// A client service resuming the uploads of failed requests.
+///
+/// See the module documentation for details.
+pub struct Resume<S, R: Resumable> {
+    inner: Rc<S>,
+    resumable: Rc<R>,
+    acks: Acks<R::Token>,
+    max_resumes: usize,
+}
+
+/// Response future of a `Resume` service.
+pub struct ResumeFuture<S: Service, R: Resumable, C, E> {
+    inner: Rc<S>,
+    resumable: Rc<R>,
+    acks: Acks<R::Token>,
+    future: S::Future,
+    // State of the upload, if the request can be resumed
+    upload: Option<Upload<R::Head, R::Token, C, E>>,
+    resumes_left: usize,
+}
+
+struct Upload<T, K, C, E> {
+    head: T,
+    token: K,
+    source: Source<C, E>,
+    // Body of the request in flight, until it has been sent
+    tx: Option<BodySender<C, E>>,
+    // Chunks sent and not acknowledged yet, the first one at offset `base`
+    unacked: VecDeque<C>,
+    base: u64,
+    // Index in `unacked` of the next chunk to send on the current attempt
+    next: usize,
+}
+
+// The body of the original request
+enum Source<C, E> {
+    Reading(Body<C, E>),
+    Ended,
+    // The error was handed to the request in flight, the upload can't be
+    // completed by resuming it
+    Failed,
+}
+
+impl<S, R: Resumable> Resume<S, R> {
+    /// Create a new `Resume` resuming the uploads `resumable` identifies,
+    /// from the offsets the transports of `inner` report on `acks`.
+    ///
+    /// Uploads are resumed up to `DEFAULT_MAX_RESUMES` times.
+    pub fn new(inner: S, resumable: R, acks: Acks<R::Token>) -> Resume<S, R> {
+        Resume {
+            inner: Rc::new(inner),
+            resumable: Rc::new(resumable),
+            acks: acks,
+            max_resumes: DEFAULT_MAX_RESUMES,
+        }
+    }
+
+    /// Resume an upload at most `max_resumes` times, after which the error
+    /// of the request is returned to the caller.
+    ///
+    /// Defaults to `DEFAULT_MAX_RESUMES`.
+    pub fn max_resumes(self, max_resumes: usize) -> Resume<S, R> {
+        Resume {
+            inner: self.inner,
+            resumable: self.resumable,
+            acks: self.acks,
+            max_resumes: max_resumes,
+        }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, R: Resumable> Clone for Resume<S, R> {
+    fn clone(&self) -> Resume<S, R> {
+        Resume {
+            inner: self.inner.clone(),
+            resumable: self.resumable.clone(),
+            acks: self.acks.clone(),
+            max_resumes: self.max_resumes,
+        }
+    }
+}
+
+impl<S, R, C, E> Service for Resume<S, R>
+    where S: Service<Request = Message<R::Head, Body<C, E>>, Error = io::Error>,
+          R: Resumable,
+          C: Chunk + Clone,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = io::Error;
+    type Future = ResumeFuture<S, R, C, E>;
+
+    fn call(&self, req: S::Request) -> ResumeFuture<S, R, C, E> {
+        let (head, body) = match req {
+            Message::WithBody(head, body) => (head, body),
+            req => return self.future(self.inner.call(req), None),
+        };
+
+        let token = match self.resumable.token(&head) {
+            Some(token) => token,
+            None => return self.future(self.inner.call(Message::WithBody(head, body)), None),
+        };
+
+        let (tx, pumped) = Body::channel();
+        let future = self.inner.call(Message::WithBody(head.clone(), pumped));
+
+        self.acks.start(token.clone());
+
+        self.future(future, Some(Upload {
+            head: head,
+            token: token,
+            source: Source::Reading(body),
+            tx: Some(tx),
+            unacked: VecDeque::new(),
+            base: 0,
+            next: 0,
+        }))
+    }
+}
+
+impl<S, R> Resume<S, R>
+    where S: Service,
+          R: Resumable,
+{
+    fn future<C, E>(&self,
+                    future: S::Future,
+                    upload: Option<Upload<R::Head, R::Token, C, E>>)
+                    -> ResumeFuture<S, R, C, E> {
+        ResumeFuture {
+            inner: self.inner.clone(),
+            resumable: self.resumable.clone(),
+            acks: self.acks.clone(),
+            future: future,
+            upload: upload,
+            resumes_left: self.max_resumes,
+        }
+    }
+}
+
+impl<S, R, C, E> Future for ResumeFuture<S, R, C, E>
+    where S: Service<Request = Message<R::Head, Body<C, E>>, Error = io::Error>,
+          R: Resumable,
+          C: Chunk + Clone,
+{
+    type Item = S::Response;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<S::Response, io::Error> {
+        loop {
+            if let Some(ref mut upload) = self.upload {
+                upload.pump(&self.acks);
+            }
+
+            let err = match self.future.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(response)) => return Ok(Async::Ready(response)),
+                Err(err) => err,
+            };
+
+            let request = match self.upload {
+                Some(ref mut upload) if self.resumes_left > 0 => {
+                    match upload.restart(&self.acks) {
+                        Some(body) => {
+                            debug!("resuming upload; offset={}; err={}", upload.base, err);
+                            let head = self.resumable.resume(&upload.head, upload.base);
+                            Message::WithBody(head, body)
+                        }
+                        None => return Err(err),
+                    }
+                }
+                _ => return Err(err),
+            };
+
+            self.resumes_left -= 1;
+            self.future = self.inner.call(request);
+        }
+    }
+}
+
+impl<S: Service, R: Resumable, C, E> Drop for ResumeFuture<S, R, C, E> {
+    fn drop(&mut self) {
+        if let Some(ref upload) = self.upload {
+            self.acks.finish(&upload.token);
+        }
+    }
+}
+
+impl<T, K, C, E> Upload<T, K, C, E>
+    where K: Hash + Eq,
+          C: Chunk + Clone,
+{
+    /// Send chunks to the request in flight for as long as it takes them
+    fn pump(&mut self, acks: &Acks<K>) {
+        if let Some(acked) = acks.acked(&self.token) {
+            self.trim(acked);
+        }
+
+        loop {
+            let chunk = {
+                let tx = match self.tx {
+                    Some(ref mut tx) => tx,
+                    None => return,
+                };
+
+                match tx.poll_ready() {
+                    Ok(Async::Ready(())) => {}
+                    Ok(Async::NotReady) => return,
+                    // The request is gone, its response will tell why
+                    Err(_) => {
+                        self.tx = None;
+                        return;
+                    }
+                }
+
+                if self.next < self.unacked.len() {
+                    Ok(self.unacked[self.next].clone())
+                } else {
+                    let res = match self.source {
+                        Source::Reading(ref mut body) => body.poll(),
+                        _ => Ok(Async::Ready(None)),
+                    };
+
+                    match res {
+                        Ok(Async::Ready(Some(chunk))) => {
+                            self.unacked.push_back(chunk.clone());
+                            Ok(chunk)
+                        }
+                        Ok(Async::Ready(None)) => {
+                            // Dropping the sender ends the body
+                            if let Source::Reading(_) = self.source {
+                                self.source = Source::Ended;
+                            }
+                            self.tx = None;
+                            return;
+                        }
+                        Ok(Async::NotReady) => return,
+                        Err(e) => {
+                            self.source = Source::Failed;
+                            Err(e)
+                        }
+                    }
+                }
+            };
+
+            let failed = chunk.is_err();
+
+            match self.tx.as_mut().unwrap().start_send(chunk) {
+                Ok(AsyncSink::Ready) if !failed => self.next += 1,
+                // Chunks are kept until acknowledged, it is sent again on
+                // the next pump
+                Ok(AsyncSink::NotReady(_)) if !failed => return,
+                _ => self.tx = None,
+            }
+        }
+    }
+
+    /// Returns the body of a request resuming the upload from the
+    /// acknowledged offset, or `None` if it can't be resumed
+    fn restart(&mut self, acks: &Acks<K>) -> Option<Body<C, E>> {
+        if let Source::Failed = self.source {
+            return None;
+        }
+
+        if let Some(acked) = acks.acked(&self.token) {
+            self.trim(acked);
+        }
+
+        let (tx, body) = Body::channel();
+        // The chunks of the previous attempt are sent again on the next pump
+        self.tx = Some(tx);
+        self.next = 0;
+
+        Some(body)
+    }
+
+    /// Forget the chunks the peer received
+    fn trim(&mut self, acked: u64) {
+        while let Some(len) = self.unacked.front().map(|chunk| chunk.len() as u64) {
+            if acked <= self.base {
+                return;
+            }
+
+            if acked < self.base + len {
+                // Keep the bytes of the chunk the peer did not receive
+                let chunk = self.unacked.front_mut().unwrap();
+                let rest = chunk.split_off((acked - self.base) as usize);
+                *chunk = rest;
+                self.base = acked;
+                return;
+            }
+
+            self.unacked.pop_front();
+            self.base += len;
+            self.next = self.next.saturating_sub(1);
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! Retrying failed requests.
+//!
+//! Whether a failed request can be sent again depends on whether the server
+//! may have processed it, which callers of a client cannot tell from the
+//! error alone. The clients of this crate can: a call whose request was
+//! never written to the transport, e.g. because the connection was already
+//! closed or failed to connect, fails with a `NotSent` error, and a
+//! multiplexed request a server going away did not process fails with a
+//! `NotProcessed` error. Both are safe to send again, while other requests
+//! should only be retried if they are idempotent.
+//!
+//! A `Retry` client asks a `Policy` which failed requests to send again and
+//! how long to wait before doing so. The inner service is expected to
+//! re-establish its connection as needed, like a `pool::Client` or a watched
+//! `LazyClient` does.
+
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::{Async, Future, Poll};
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_service::Service;
+
+use streaming::multiplex::NotProcessed;
+use util::client_proxy::NotSent;
+
+/// Decides which failed requests are retried, and when.
+pub trait Policy<R> {
+    /// Returns true if `request`, which failed with `error`, can be sent
+    /// again.
+    fn is_retryable(&self, request: &R, error: &io::Error) -> bool;
+
+    /// Returns the delay before sending a request again for the `retry`th
+    /// time, starting from 1, or `None` to give up and return the error.
+    fn backoff(&self, retry: usize) -> Option<Duration>;
+}
+
+/// Returns true if `error` tells the request never reached the peer's
+/// service, so it can be sent again even if it is not idempotent.
+///
+/// That is the case of requests that were not written to the transport,
+/// see `NotSent`, and of those a peer going away did not process, see
+/// `multiplex::NotProcessed`.
+pub fn is_unprocessed(error: &io::Error) -> bool {
+    match error.get_ref() {
+        Some(err) => err.is::<NotSent>() || err.is::<NotProcessed>(),
+        None => false,
+    }
+}
+
+/// A `Policy` retrying with exponential backoff.
+///
+/// Requests that never reached the peer's service are retried, see
+/// `is_unprocessed`, as well as any failed request classified as
+/// idempotent.
+pub struct Backoff<R> {
+    base: Duration,
+    max_retries: usize,
+    idempotent: Option<Box<Fn(&R) -> bool>>,
+}
+
+impl<R> Backoff<R> {
+    /// Create a new `Backoff` retrying a request up to `max_retries` times,
+    /// waiting `base` before the first retry and twice as long before each
+    /// of the next ones.
+    pub fn new(base: Duration, max_retries: usize) -> Backoff<R> {
+        Backoff {
+            base: base,
+            max_retries: max_retries,
+            idempotent: None,
+        }
+    }
+
+    /// Also retry the requests for which `idempotent` returns true, whatever
+    /// they failed with.
+    ///
+    /// Such requests may be processed more than once, which is harmless
+    /// only if processing them again has no further effect, e.g. reads.
+    pub fn idempotent<F>(self, idempotent: F) -> Backoff<R>
+        where F: Fn(&R) -> bool + 'static,
+    {
+        Backoff {
+            base: self.base,
+            max_retries: self.max_retries,
+            idempotent: Some(Box::new(idempotent)),
+        }
+    }
+}
+
+impl<R> Policy<R> for Backoff<R> {
+    fn is_retryable(&self, request: &R, error: &io::Error) -> bool {
+        if is_unprocessed(error) {
+            return true;
+        }
+
+        match self.idempotent {
+            Some(ref idempotent) => idempotent(request),
+            None => false,
+        }
+    }
+
+    fn backoff(&self, retry: usize) -> Option<Duration> {
+        if retry == 0 || retry > self.max_retries {
+            return None;
+        }
+
+        // Keep the factor from overflowing
+        let factor = 1 << ((retry - 1).min(16) as u32);
+        Some(self.base * factor)
+    }
+}
+
+/// A client service sending failed requests again, as told by a `Policy`.
+///
+/// See the module documentation for details.
+pub struct Retry<S, P> {
+    inner: Rc<S>,
+    policy: Rc<P>,
+    handle: Handle,
+}
+
+/// Response future of a `Retry` service.
+pub struct RetryFuture<S: Service, P> {
+    inner: Rc<S>,
+    policy: Rc<P>,
+    handle: Handle,
+    state: State<S::Future>,
+    // Copy of the request, sent again if it fails
+    request: S::Request,
+    retries: usize,
+}
+
+enum State<F> {
+    Calling(F),
+    // Waiting for the backoff delay before retrying
+    Waiting(Timeout),
+}
+
+impl<S, P> Retry<S, P> {
+    /// Create a new `Retry` sending the requests of `inner` the `policy`
+    /// tells retryable again.
+    ///
+    /// The backoff timers are registered with the event loop of `handle`.
+    pub fn new(inner: S, policy: P, handle: &Handle) -> Retry<S, P> {
+        Retry {
+            inner: Rc::new(inner),
+            policy: Rc::new(policy),
+            handle: handle.clone(),
+        }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, P> Clone for Retry<S, P> {
+    fn clone(&self) -> Retry<S, P> {
+        Retry {
+            inner: self.inner.clone(),
+            policy: self.policy.clone(),
+            handle: self.handle.clone(),
+        }
+    }
+}
+
+impl<S, P> Service for Retry<S, P>
+    where S: Service<Error = io::Error>,
+          S::Request: Clone,
+          P: Policy<S::Request>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = io::Error;
+    type Future = RetryFuture<S, P>;
+
+    fn call(&self, req: S::Request) -> RetryFuture<S, P> {
+        RetryFuture {
+            inner: self.inner.clone(),
+            policy: self.policy.clone(),
+            handle: self.handle.clone(),
+            state: State::Calling(self.inner.call(req.clone())),
+            request: req,
+            retries: 0,
+        }
+    }
+}
+
+impl<S, P> Future for RetryFuture<S, P>
+    where S: Service<Error = io::Error>,
+          S::Request: Clone,
+          P: Policy<S::Request>,
+{
+    type Item = S::Response;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<S::Response, io::Error> {
+        loop {
+            let next = match self.state {
+                State::Calling(ref mut future) => {
+                    let err = match future.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(response)) => return Ok(Async::Ready(response)),
+                        Err(err) => err,
+                    };
+
+                    if !self.policy.is_retryable(&self.request, &err) {
+                        return Err(err);
+                    }
+
+                    let delay = match self.policy.backoff(self.retries + 1) {
+                        Some(delay) => delay,
+                        None => return Err(err),
+                    };
+
+                    debug!("retrying request; retry={}; delay={:?}; err={}",
+                           self.retries + 1, delay, err);
+                    self.retries += 1;
+
+                    State::Waiting(try!(Timeout::new(delay, &self.handle)))
+                }
+                State::Waiting(ref mut timer) => {
+                    try_ready!(timer.poll());
+                    State::Calling(self.inner.call(self.request.clone()))
+                }
+            };
+
+            self.state = next;
+        }
+    }
+}
@@ -1,5 +1,18 @@
 pub mod pipeline;
 pub mod multiplex;
+pub mod negotiate;
+
+use std::io;
+
+use futures::Future;
+use futures::future::AndThen;
+
+/// A future running the handshake of a simple protocol, returned by the
+/// `handshake` functions of `ServerProto` and `ClientProto`.
+pub type HandshakeFuture<T> = Box<Future<Item = T, Error = io::Error>>;
+
+// Binds the transport of a lifted protocol, then runs its handshake
+type BindHandshake<F, T> = AndThen<F, HandshakeFuture<T>, fn(T) -> HandshakeFuture<T>>;
 
 // A utility struct to enable "lifting" from an RPC to a streaming proto, which
 // is how RPC protos are implemented under the hood. Unfortunately:
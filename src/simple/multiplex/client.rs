@@ -1,15 +1,19 @@
-use BindClient;
-use super::{Multiplex, RequestIdSource, RequestId};
-use super::lift::{LiftBind, LiftTransport};
-use simple::LiftProto;
+use {BindClient, BindConfig};
+use super::{GoAway, Multiplex, RequestIdSource, RequestId};
+use super::lift::{LiftBind, LiftTransport, write_no_errors};
+use simple::{BindHandshake, HandshakeFuture, LiftProto};
 
 use std::io;
+use std::marker::PhantomData;
+use std::time::Duration;
+use std::rc::Rc;
 
-use streaming::{self, Message};
-use streaming::multiplex::StreamingMultiplex;
+use streaming::{self, Body, Message, StreamingView};
+use streaming::multiplex::{StreamingMultiplex, MultiplexConfig};
+use util::client_proxy::{self, ClientProxy, OutstandingInfo};
 use tokio_core::reactor::Handle;
 use tokio_service::Service;
-use futures::{stream, Stream, Sink, Future, IntoFuture, Poll};
+use futures::{future, stream, Async, Stream, Sink, Future, IntoFuture, Poll};
 
 type MyStream<E> = stream::Empty<(), E>;
 
@@ -29,7 +33,16 @@ pub trait ClientProto<T: 'static>: 'static {
     type Response: 'static;
 
     /// The type of request ids to used to correlate requests to responses
-    type RequestId: RequestId;
+    ///
+    /// They are `Send` as the client lists them, see
+    /// `ClientService::outstanding`.
+    type RequestId: RequestId + Send;
+
+    /// Errors calls fail with.
+    ///
+    /// Use `io::Error` unless the protocol has error responses of its own;
+    /// see `response_error`.
+    type Error: From<io::Error> + 'static;
 
     /// The message transport, which usually take `T` as a parameter.
     ///
@@ -58,21 +71,117 @@ pub trait ClientProto<T: 'static>: 'static {
     /// An easy way to build a transport is to use `tokio_core::io::Framed`
     /// together with a `Codec`; in that case, `bind_transport` is just
     /// `io.framed(YourCodec)`. See the crate docs for an example.
+    ///
+    /// If a handshake has read from `io` already, build the transport with
+    /// `util::framed::framed_with_buffer` instead, so the bytes it read
+    /// ahead reach the codec.
     fn bind_transport(&self, io: T) -> Self::BindTransport;
+
+    /// Runs the handshake of the protocol on a freshly bound transport,
+    /// before the dispatcher takes over, e.g. to negotiate a version or to
+    /// authenticate.
+    ///
+    /// The handshake exchanges messages with the peer through the transport,
+    /// so it needs no parsing of its own, and resolves to the transport the
+    /// requests are then sent on. A transport wrapping the codec can keep what was
+    /// negotiated for later use. Failing the handshake closes the
+    /// connection, and the bind timeout, if any, covers it. Defaults to
+    /// using the transport as is.
+    fn handshake(transport: Self::Transport) -> HandshakeFuture<Self::Transport> {
+        Box::new(future::ok(transport))
+    }
+
+    /// Tuning knobs applied to every connection bound by this protocol.
+    ///
+    /// Defaults to `MultiplexConfig::default()`.
+    fn config(&self) -> MultiplexConfig {
+        MultiplexConfig::default()
+    }
+
+    /// Time a request waits for its response before it is given up on.
+    ///
+    /// Once the timeout passes, the response future fails with a `TimedOut`
+    /// error and the exchange is canceled on the transport. Defaults to
+    /// `None`, waiting forever.
+    fn request_timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Time the id of a canceled or timed out request stays reserved for
+    /// the late response.
+    ///
+    /// Once the timeout passes, the id is released and a response still
+    /// arriving for it fails the connection. Defaults to 30 seconds.
+    fn late_response_timeout(&self) -> Duration {
+        Duration::from_secs(30)
+    }
+
+    /// Cancel the exchange `request_id` on the transport.
+    ///
+    /// Called once the caller lost interest in the response, because the
+    /// response future was dropped before completing or the request timed
+    /// out. Protocols supporting cancellation on the wire, such as 9P's
+    /// `Tflush`, can write a cancel frame for the exchange. A response
+    /// arriving for it later is discarded. By default nothing is written.
+    fn cancel(transport: &mut Self::Transport, request_id: Self::RequestId) -> io::Result<()> {
+        let _ = (transport, request_id);
+        Ok(())
+    }
+
+    /// Tell the responses carrying an error apart, failing their call with
+    /// the error.
+    ///
+    /// Protocols with error responses decode them into `Self::Error`, so that
+    /// callers can match on it. Defaults to succeeding every call with its
+    /// response.
+    fn response_error(response: Self::Response) -> Result<Self::Response, Self::Error> {
+        Ok(response)
+    }
+
+    /// Returns the announcement of the server going away, once read from
+    /// `transport`.
+    ///
+    /// Transports of protocols with such an announcement, like HTTP/2's
+    /// `GOAWAY`, keep it aside when reading it and hand it out here. The
+    /// requests the server did not process then fail with `NotProcessed`,
+    /// as do the requests made afterwards; see `GoAway`. Defaults to `None`.
+    fn peer_going_away(transport: &mut Self::Transport) -> Option<GoAway<Self::RequestId>> {
+        let _ = transport;
+        None
+    }
 }
 
 impl<T: 'static, P: ClientProto<T>> BindClient<Multiplex, T> for P {
     type ServiceRequest = P::Request;
     type ServiceResponse = P::Response;
-    type ServiceError = io::Error;
+    type ServiceError = P::Error;
 
     type BindClient = ClientService<T, P>;
 
     fn bind_client(&self, handle: &Handle, io: T) -> Self::BindClient {
         ClientService {
-            inner: BindClient::<StreamingMultiplex<MyStream<io::Error>>, T>::bind_client(
+            inner: BindClient::<StreamingMultiplex<MyStream<P::Error>>, T>::bind_client(
                 LiftProto::from_ref(self), handle, io
-            )
+            ),
+            _local: PhantomData,
+        }
+    }
+
+    fn bind_client_timeout(&self, handle: &Handle, io: T, timeout: Duration) -> Self::BindClient {
+        ClientService {
+            inner: BindClient::<StreamingMultiplex<MyStream<P::Error>>, T>::bind_client_timeout(
+                LiftProto::from_ref(self), handle, io, timeout
+            ),
+            _local: PhantomData,
+        }
+    }
+
+    fn bind_client_with(&self, handle: &Handle, io: T, config: &BindConfig) -> Self::BindClient {
+        ClientService {
+            inner: BindClient::<StreamingMultiplex<MyStream<P::Error>>, T>::bind_client_with(
+                LiftProto::from_ref(self), handle, io, config
+            ),
+            _local: PhantomData,
         }
     }
 }
@@ -87,10 +196,12 @@ impl<T, P> streaming::multiplex::ClientProto<T> for LiftProto<P> where
     type ResponseBody = ();
     type RequestId = P::RequestId;
 
-    type Error = io::Error;
+    type Error = P::Error;
 
-    type Transport = LiftTransport<P::Transport, io::Error>;
-    type BindTransport = LiftBind<T, <P::BindTransport as IntoFuture>::Future, io::Error>;
+    type Transport = LiftTransport<P::Transport, P::Error, P::Response, P::Request>;
+    type BindTransport = LiftBind<T, BindHandshake<<P::BindTransport as IntoFuture>::Future,
+                                                    P::Transport>, P::Error,
+                                  P::Response, P::Request>;
     type RequestIdSource = P::RequestIdSource;
 
     fn requestid_source(&self) -> Self::RequestIdSource {
@@ -98,19 +209,117 @@ impl<T, P> streaming::multiplex::ClientProto<T> for LiftProto<P> where
     }
 
     fn bind_transport(&self, io: T) -> Self::BindTransport {
-        LiftBind::lift(ClientProto::bind_transport(self.lower(), io).into_future())
+        let handshake = <P as ClientProto<T>>::handshake as fn(_) -> _;
+        LiftBind::lift(ClientProto::bind_transport(self.lower(), io).into_future().and_then(handshake),
+                       P::response_error, write_no_errors)
+    }
+
+    fn config(&self) -> MultiplexConfig {
+        P::config(self.lower())
+    }
+
+    fn request_timeout(&self) -> Option<Duration> {
+        P::request_timeout(self.lower())
+    }
+
+    fn late_response_timeout(&self) -> Duration {
+        P::late_response_timeout(self.lower())
+    }
+
+    fn cancel(transport: &mut Self::Transport, request_id: P::RequestId) -> io::Result<()> {
+        P::cancel(&mut transport.inner, request_id)
+    }
+
+    fn peer_going_away(transport: &mut Self::Transport) -> Option<GoAway<P::RequestId>> {
+        P::peer_going_away(&mut transport.inner)
     }
 }
 
 /// Client `Service` for simple multiplex protocols
+///
+/// A `ClientService` is local to the thread running the event loop it was
+/// bound on and can not be sent to other threads. Use `remote` to obtain a
+/// handle that can.
 pub struct ClientService<T, P> where T: 'static, P: ClientProto<T> {
-    inner: <LiftProto<P> as BindClient<StreamingMultiplex<MyStream<io::Error>>, T>>::BindClient
+    inner: <LiftProto<P> as BindClient<StreamingMultiplex<MyStream<P::Error>>, T>>::BindClient,
+    _local: PhantomData<Rc<()>>,
+}
+
+/// Client `Service` for simple multiplex protocols usable from any thread
+///
+/// Requests are sent over a channel to the task driving the connection on
+/// its event loop, so the handle is `Send` whenever the request and response
+/// types are.
+pub struct RemoteClientService<T, P> where T: 'static, P: ClientProto<T> {
+    inner: ClientProxy<Message<P::Request, MyStream<P::Error>>,
+                       Message<P::Response, Body<(), P::Error>>,
+                       P::Error>,
+    _marker: PhantomData<fn() -> (T, P)>,
+}
+
+impl<T, P> ClientService<T, P> where T: 'static, P: ClientProto<T> {
+    /// Returns a handle to the same connection that may be sent to, and used
+    /// from, other threads.
+    pub fn remote(&self) -> RemoteClientService<T, P> {
+        RemoteClientService {
+            inner: self.inner.clone(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a stream of the results of calls detached from now on.
+    ///
+    /// See `ClientFuture::detach`. The stream is shared by all handles to the
+    /// connection, remote ones included; calling `detached` again replaces
+    /// it.
+    pub fn detached(&self) -> Detached<T, P> {
+        Detached {
+            inner: self.inner.detached(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a stream of the errors that fail the connection as a whole.
+    ///
+    /// See `util::client_proxy::ClientProxy::errors` for details. The stream is shared with the
+    /// remote handles to the connection.
+    pub fn errors(&self) -> client_proxy::Errors {
+        self.inner.errors()
+    }
+
+    /// Returns the requests awaiting their response on the connection.
+    ///
+    /// See `util::client_proxy::ClientProxy::outstanding`.
+    pub fn outstanding(&self) -> Vec<OutstandingInfo> {
+        self.inner.outstanding()
+    }
+
+    /// Cancel the requests awaiting their response that match `predicate`,
+    /// returning how many were canceled.
+    ///
+    /// Their calls fail, the exchanges are canceled on the transport and the
+    /// connection stays up for the other requests. See
+    /// `util::client_proxy::ClientProxy::cancel_where`.
+    pub fn cancel_where<F>(&self, predicate: F) -> usize
+        where F: FnMut(&OutstandingInfo) -> bool,
+    {
+        self.inner.cancel_where(predicate)
+    }
+
+    /// Returns a view of the client as a streaming service, for callers
+    /// written against streaming protocols.
+    ///
+    /// Requests must not have a body and responses never have one. See
+    /// `streaming::StreamingView`.
+    pub fn into_streaming_service<B, C>(self) -> StreamingView<Self, B, C> {
+        StreamingView::new(self)
+    }
 }
 
 impl<T, P> Service for ClientService<T, P> where T: 'static, P: ClientProto<T> {
     type Request = P::Request;
     type Response = P::Response;
-    type Error = io::Error;
+    type Error = P::Error;
     type Future = ClientFuture<T, P>;
 
     fn call(&self, req: P::Request) -> Self::Future {
@@ -124,18 +333,57 @@ impl<T, P> Clone for ClientService<T, P> where T: 'static, P: ClientProto<T> {
     fn clone(&self) -> Self {
         ClientService {
             inner: self.inner.clone(),
+            _local: PhantomData,
         }
     }
 }
 
+impl<T, P> Service for RemoteClientService<T, P> where T: 'static, P: ClientProto<T> {
+    type Request = P::Request;
+    type Response = P::Response;
+    type Error = P::Error;
+    type Future = ClientFuture<T, P>;
+
+    fn call(&self, req: P::Request) -> Self::Future {
+        ClientFuture {
+            inner: self.inner.call(Message::WithoutBody(req))
+        }
+    }
+}
+
+impl<T, P> Clone for RemoteClientService<T, P> where T: 'static, P: ClientProto<T> {
+    fn clone(&self) -> Self {
+        RemoteClientService {
+            inner: self.inner.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// The future returned by calling a client service.
+///
+/// Dropping the future cancels the request: if it has not been written yet,
+/// it never will be. Once written, the exchange is canceled on the transport
+/// and its response discarded when it arrives. Use `detach` to let the
+/// exchange complete in the background instead.
 pub struct ClientFuture<T, P> where T: 'static, P: ClientProto<T> {
-    inner: <<LiftProto<P> as BindClient<StreamingMultiplex<MyStream<io::Error>>, T>>::BindClient
+    inner: <<LiftProto<P> as BindClient<StreamingMultiplex<MyStream<P::Error>>, T>>::BindClient
             as Service>::Future
 }
 
+impl<T, P> ClientFuture<T, P> where T: 'static, P: ClientProto<T> {
+    /// Let the exchange complete in the background, without canceling it.
+    ///
+    /// The result is delivered on the service's `detached` stream, if any,
+    /// and discarded otherwise.
+    pub fn detach(self) {
+        self.inner.detach()
+    }
+}
+
 impl<T, P> Future for ClientFuture<T, P>  where T: 'static, P: ClientProto<T> {
     type Item = P::Response;
-    type Error = io::Error;
+    type Error = P::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         match try_ready!(self.inner.poll()) {
@@ -144,3 +392,26 @@ impl<T, P> Future for ClientFuture<T, P>  where T: 'static, P: ClientProto<T> {
         }
     }
 }
+
+/// Stream of the results of detached calls, see `ClientService::detached`.
+///
+/// Calls dropped by the connection without a response yield a `BrokenPipe`
+/// error. The stream ends once the service and all detached calls are gone.
+pub struct Detached<T, P> where T: 'static, P: ClientProto<T> {
+    inner: client_proxy::Detached<Message<P::Response, Body<(), P::Error>>, P::Error>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, P> Stream for Detached<T, P> where T: 'static, P: ClientProto<T> {
+    type Item = Result<P::Response, P::Error>;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, ()> {
+        match try_ready!(self.inner.poll()) {
+            Some(Ok(Message::WithoutBody(msg))) => Ok(Async::Ready(Some(Ok(msg)))),
+            Some(Ok(Message::WithBody(..))) => panic!("bodies not supported"),
+            Some(Err(e)) => Ok(Async::Ready(Some(Err(e)))),
+            None => Ok(Async::Ready(None)),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::io;
+
+use futures::{Poll, Sink, StartSend, Stream};
+
+/// Looks up the request id a response answers from the response itself.
+///
+/// The counterpart of `RequestIdSource` for protocols where the server
+/// copies an opaque correlation token of the request into its response.
+/// Rather than having the codec split the token off and return
+/// `(RequestId, Response)` tuples, wrap its transport in `ExtractIds`.
+///
+/// Implemented for closures and `fn` pointers taking a response reference.
+pub trait ResponseIdExtractor<R>: 'static {
+    /// The type of request ids
+    type Id;
+
+    /// Returns the id of the request `response` answers
+    fn extract(&mut self, response: &R) -> Self::Id;
+}
+
+impl<Id, R, F> ResponseIdExtractor<R> for F
+    where F: FnMut(&R) -> Id + 'static,
+{
+    type Id = Id;
+
+    fn extract(&mut self, response: &R) -> Id {
+        self(response)
+    }
+}
+
+/// A transport tagging the responses read from `T` with the request id a
+/// `ResponseIdExtractor` finds in them.
+///
+/// Requests are written as `(RequestId, Request)` tuples as usual, so the
+/// codec still decides how the id goes on the wire, while its decoding half
+/// yields bare responses.
+pub struct ExtractIds<T, X> {
+    inner: T,
+    extractor: X,
+}
+
+impl<T, X> ExtractIds<T, X> {
+    /// Wraps `transport`, looking up the ids of responses with `extractor`.
+    pub fn new(transport: T, extractor: X) -> ExtractIds<T, X> {
+        ExtractIds {
+            inner: transport,
+            extractor: extractor,
+        }
+    }
+
+    /// Returns a reference to the wrapped transport.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the wrapped transport.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Consumes the wrapper, returning the wrapped transport.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T, X> Stream for ExtractIds<T, X>
+    where T: Stream<Error = io::Error>,
+          X: ResponseIdExtractor<T::Item>,
+{
+    type Item = (X::Id, T::Item);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, io::Error> {
+        let response = match try_ready!(self.inner.poll()) {
+            Some(response) => response,
+            None => return Ok(None.into()),
+        };
+
+        let id = self.extractor.extract(&response);
+        Ok(Some((id, response)).into())
+    }
+}
+
+impl<T, X> Sink for ExtractIds<T, X>
+    where T: Sink<SinkError = io::Error>,
+{
+    type SinkItem = T::SinkItem;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, item: T::SinkItem) -> StartSend<T::SinkItem, io::Error> {
+        self.inner.start_send(item)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        self.inner.poll_complete()
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::VecDeque;
+
+use futures::{Future, Stream, Poll, Async};
+use tokio_service::Service;
+
+/// Fans a logical request out as several exchanges on `service`, yielding
+/// the responses in the order of `segments`.
+///
+/// All segments are requested at once, so with a multiplexed client they run
+/// concurrently on the same connection. A failed segment is requested again
+/// up to `retries` times before its error is yielded in its place.
+pub fn gather<S, I>(service: S, segments: I, retries: usize) -> Gather<S>
+    where S: Service,
+          S::Request: Clone,
+          I: IntoIterator<Item = S::Request>,
+{
+    let segments = segments.into_iter().map(|request| {
+        let response = service.call(request.clone());
+
+        Segment {
+            request: request,
+            attempts: 1,
+            state: State::Pending(response),
+        }
+    }).collect();
+
+    Gather {
+        service: service,
+        segments: segments,
+        retries: retries,
+    }
+}
+
+/// A stream of segment responses, returned by `gather`.
+pub struct Gather<S: Service> {
+    service: S,
+    segments: VecDeque<Segment<S>>,
+    retries: usize,
+}
+
+struct Segment<S: Service> {
+    request: S::Request,
+    attempts: usize,
+    state: State<S::Future, S::Response, S::Error>,
+}
+
+enum State<F, T, E> {
+    Pending(F),
+    Done(Option<T>),
+    Failed(Option<E>),
+}
+
+impl<S: Service> Gather<S> {
+    /// Returns the number of segments not yet yielded.
+    pub fn remaining(&self) -> usize {
+        self.segments.len()
+    }
+}
+
+impl<S> Stream for Gather<S>
+    where S: Service,
+          S::Request: Clone,
+{
+    type Item = S::Response;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Response>, S::Error> {
+        // Drive every segment, so later ones complete (or get retried) while
+        // waiting on earlier ones
+        for (i, segment) in self.segments.iter_mut().enumerate() {
+            loop {
+                let res = match segment.state {
+                    State::Pending(ref mut response) => response.poll(),
+                    _ => break,
+                };
+
+                match res {
+                    Ok(Async::Ready(response)) => {
+                        segment.state = State::Done(Some(response));
+                    }
+                    Ok(Async::NotReady) => break,
+                    Err(e) => {
+                        if segment.attempts > self.retries {
+                            debug!("segment failed; segment={}; attempts={}", i, segment.attempts);
+                            segment.state = State::Failed(Some(e));
+                            break;
+                        }
+
+                        trace!("retrying segment; segment={}; attempts={}", i, segment.attempts);
+                        segment.attempts += 1;
+                        segment.state = State::Pending(self.service.call(segment.request.clone()));
+                    }
+                }
+            }
+        }
+
+        let res = match self.segments.front_mut() {
+            Some(&mut Segment { state: State::Done(ref mut response), .. }) => {
+                Ok(Async::Ready(response.take()))
+            }
+            Some(&mut Segment { state: State::Failed(ref mut error), .. }) => {
+                Err(error.take().expect("segment error already taken"))
+            }
+            Some(_) => return Ok(Async::NotReady),
+            None => return Ok(Async::Ready(None)),
+        };
+
+        self.segments.pop_front();
+        res
+    }
+}
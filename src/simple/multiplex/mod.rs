@@ -4,12 +4,28 @@
 
 mod client;
 pub use self::client::ClientProto;
-pub use self::client::ClientService;
+pub use self::client::{ClientService, RemoteClientService, ClientFuture, Detached};
 
 mod server;
-pub use self::server::ServerProto;
+pub use self::server::{ServerProto, server_task};
 
-pub use streaming::multiplex::{RequestIdSource, RequestId};
+mod streaming_response;
+pub use self::streaming_response::{StreamingResponseProto, ResponseFrame, StreamingResponse};
+pub use self::streaming_response::{StreamingResponseService, StreamingResponseFuture};
+
+mod extract;
+pub use self::extract::{ResponseIdExtractor, ExtractIds};
+
+mod gather;
+pub use self::gather::{gather, Gather};
+
+pub use streaming::multiplex::{RequestIdSource, RequestId, RequestIdValidator, AnyRequestId, Violation};
+pub use streaming::multiplex::{GoAway, MultiplexConfig, NotProcessed, Push};
+pub use streaming::{CommitFuture, Committer};
+pub use simple::HandshakeFuture;
+
+use ProtocolKind;
+use streaming::multiplex::{advanced, Frame};
 
 /// A marker used to flag protocols as being multiplexed RPC.
 ///
@@ -17,6 +33,17 @@ pub use streaming::multiplex::{RequestIdSource, RequestId};
 /// implement the `ClientProto` or `ServerProto` traits in this module.
 pub struct Multiplex;
 
+// RPC protocols are driven like streaming ones, with empty bodies
+impl<T> ProtocolKind<T> for Multiplex where T: advanced::Dispatch {
+    type Frame = Frame<T::RequestId, T::In, T::BodyIn, T::Error>;
+    type Config = MultiplexConfig;
+    type Driver = advanced::Multiplex<T>;
+
+    fn drive(dispatch: T, config: &MultiplexConfig) -> advanced::Multiplex<T> {
+        advanced::Multiplex::with_config(dispatch, config)
+    }
+}
+
 // This is a submodule so that `LiftTransport` can be marked `pub`, to satisfy
 // the no-private-in-public checker.
 mod lift {
@@ -26,91 +53,131 @@ mod lift {
     use streaming::multiplex::{Frame, Transport};
     use futures::{Future, Stream, Sink, StartSend, Poll, Async, AsyncSink};
 
-    // Lifts an implementation of RPC-style transport to streaming-style transport
-    pub struct LiftTransport<T, E>(pub T, pub PhantomData<E>);
+    // Lifts an implementation of RPC-style transport to streaming-style
+    // transport, mapping error frames to and from messages with the hooks of
+    // the protocol
+    pub struct LiftTransport<T, E, In, Out> {
+        pub inner: T,
+        // Tells read messages carrying an error apart, on clients
+        pub read: fn(In) -> Result<In, E>,
+        // Turns errors into the messages written for them, on servers
+        pub write: fn(E) -> io::Result<Out>,
+    }
 
     // Lifts the Bind from the underlying transport
-    pub struct LiftBind<A, F, E> {
+    pub struct LiftBind<A, F, E, In, Out> {
         fut: F,
-        marker: PhantomData<(A, E)>,
+        read: fn(In) -> Result<In, E>,
+        write: fn(E) -> io::Result<Out>,
+        marker: PhantomData<A>,
+    }
+
+    // The hooks of the side not reading, or not writing, errors
+    pub fn read_no_errors<In, E>(msg: In) -> Result<In, E> {
+        Ok(msg)
     }
 
-    impl<T, RequestId, InnerItem, E> Stream for LiftTransport<T, E> where
+    pub fn write_no_errors<E, Out>(_: E) -> io::Result<Out> {
+        Err(io::Error::new(io::ErrorKind::Other, "no support for error frames"))
+    }
+
+    impl<T, RequestId, In, Out, E> Stream for LiftTransport<T, E, In, Out> where
         E: 'static,
-        T: Stream<Item = (RequestId, InnerItem), Error = io::Error>,
+        T: Stream<Item = (RequestId, In), Error = io::Error>,
     {
-        type Item = Frame<RequestId, InnerItem, (), E>;
+        type Item = Frame<RequestId, In, (), E>;
         type Error = io::Error;
 
         fn poll(&mut self) -> Poll<Option<Self::Item>, io::Error> {
-            let (id, msg) = match try_ready!(self.0.poll()) {
+            let (id, msg) = match try_ready!(self.inner.poll()) {
                 Some(msg) => msg,
                 None => return Ok(None.into()),
             };
-            Ok(Some(Frame::Message {
-                message: msg,
-                body: false,
-                solo: false,
-                id: id,
-            }).into())
+
+            let frame = match (self.read)(msg) {
+                Ok(msg) => {
+                    Frame::Message {
+                        message: msg,
+                        body: false,
+                        solo: false,
+                        id: id,
+                    }
+                }
+                Err(e) => Frame::Error { id: id, error: e },
+            };
+
+            Ok(Some(frame).into())
         }
     }
 
-    impl<T, RequestId, InnerSink, E> Sink for LiftTransport<T, E> where
+    impl<T, RequestId, In, Out, E> Sink for LiftTransport<T, E, In, Out> where
         E: 'static,
-        T: Sink<SinkItem = (RequestId, InnerSink), SinkError = io::Error>
+        T: Sink<SinkItem = (RequestId, Out), SinkError = io::Error>
     {
-        type SinkItem = Frame<RequestId, InnerSink, (), E>;
+        type SinkItem = Frame<RequestId, Out, (), E>;
         type SinkError = io::Error;
 
         fn start_send(&mut self, request: Self::SinkItem)
                       -> StartSend<Self::SinkItem, io::Error> {
-            if let Frame::Message { message, id, body, solo } = request {
-                if !body && !solo {
-                    match try!(self.0.start_send((id, message))) {
-                        AsyncSink::Ready => return Ok(AsyncSink::Ready),
-                        AsyncSink::NotReady((id, msg)) => {
-                            let msg = Frame::Message {
-                                message: msg,
-                                id: id,
-                                body: false,
-                                solo: false,
-                            };
-                            return Ok(AsyncSink::NotReady(msg))
-                        }
-                    }
+            let (id, message, solo) = match request {
+                // Solo messages are pushed ones, written like responses
+                Frame::Message { message, id, body: false, solo } => (id, message, solo),
+                Frame::Error { id, error } => (id, try!((self.write)(error)), false),
+                _ => return Err(io::Error::new(io::ErrorKind::Other, "no support for streaming")),
+            };
+
+            match try!(self.inner.start_send((id, message))) {
+                AsyncSink::Ready => Ok(AsyncSink::Ready),
+                AsyncSink::NotReady((id, msg)) => {
+                    // Errors come back as the message written for them
+                    let msg = Frame::Message {
+                        message: msg,
+                        id: id,
+                        body: false,
+                        solo: solo,
+                    };
+                    Ok(AsyncSink::NotReady(msg))
                 }
             }
-            Err(io::Error::new(io::ErrorKind::Other, "no support for streaming"))
         }
 
         fn poll_complete(&mut self) -> Poll<(), io::Error> {
-            self.0.poll_complete()
+            self.inner.poll_complete()
         }
     }
 
-    impl<T, RequestId, InnerItem, InnerSink, E> Transport<RequestId, ()> for LiftTransport<T, E> where
+    impl<T, RequestId, In, Out, E> Transport<RequestId, ()> for LiftTransport<T, E, In, Out> where
         E: 'static,
+        In: 'static,
+        Out: 'static,
         T: 'static,
-        T: Stream<Item = (RequestId, InnerItem), Error = io::Error>,
-        T: Sink<SinkItem = (RequestId, InnerSink), SinkError = io::Error>
+        T: Stream<Item = (RequestId, In), Error = io::Error>,
+        T: Sink<SinkItem = (RequestId, Out), SinkError = io::Error>
     {}
 
-    impl<A, F, E> LiftBind<A, F, E> {
-        pub fn lift(f: F) -> LiftBind<A, F, E> {
+    impl<A, F, E, In, Out> LiftBind<A, F, E, In, Out> {
+        pub fn lift(f: F, read: fn(In) -> Result<In, E>, write: fn(E) -> io::Result<Out>)
+                    -> LiftBind<A, F, E, In, Out>
+        {
             LiftBind {
                 fut: f,
+                read: read,
+                write: write,
                 marker: PhantomData,
             }
         }
     }
 
-    impl<A, F, E> Future for LiftBind<A, F, E> where F: Future<Error = io::Error> {
-        type Item = LiftTransport<F::Item, E>;
+    impl<A, F, E, In, Out> Future for LiftBind<A, F, E, In, Out> where F: Future<Error = io::Error> {
+        type Item = LiftTransport<F::Item, E, In, Out>;
         type Error = io::Error;
 
         fn poll(&mut self) -> Poll<Self::Item, io::Error> {
-            Ok(Async::Ready(LiftTransport(try_ready!(self.fut.poll()), PhantomData)))
+            Ok(Async::Ready(LiftTransport {
+                inner: try_ready!(self.fut.poll()),
+                read: self.read,
+                write: self.write,
+            }))
         }
     }
 }
@@ -1,16 +1,17 @@
 use std::io;
 use std::marker;
+use std::time::Duration;
 
-use BindServer;
+use {BindConfig, BindServer};
 use super::Multiplex;
-use super::lift::{LiftBind, LiftTransport};
-use simple::LiftProto;
+use super::lift::{LiftBind, LiftTransport, read_no_errors};
+use simple::{BindHandshake, HandshakeFuture, LiftProto};
 
-use streaming::{self, Message};
-use streaming::multiplex::{StreamingMultiplex, RequestId};
+use streaming::{self, Committer, Message};
+use streaming::multiplex::{StreamingMultiplex, RequestId, RequestIdValidator, AnyRequestId, MultiplexConfig, Push};
 use tokio_core::reactor::Handle;
 use tokio_service::Service;
-use futures::{stream, Stream, Sink, Future, IntoFuture, Poll};
+use futures::{future, stream, Stream, Sink, Future, IntoFuture, Poll};
 
 type MyStream<E> = stream::Empty<(), E>;
 
@@ -32,6 +33,12 @@ pub trait ServerProto<T: 'static>: 'static {
     /// The type of request ids to used to correlate requests to responses
     type RequestId: RequestId;
 
+    /// Errors the service fails requests with.
+    ///
+    /// Use `io::Error` unless the protocol has error responses of its own;
+    /// see `error_response`.
+    type Error: From<io::Error> + 'static;
+
     /// The message transport, which usually take `T` as a parameter.
     ///
     /// An easy way to build a transport is to use `tokio_core::io::Framed`
@@ -52,23 +59,169 @@ pub trait ServerProto<T: 'static>: 'static {
     /// An easy way to build a transport is to use `tokio_core::io::Framed`
     /// together with a `Codec`; in that case, `bind_transport` is just
     /// `io.framed(YourCodec)`. See the crate docs for an example.
+    ///
+    /// If a handshake has read from `io` already, build the transport with
+    /// `util::framed::framed_with_buffer` instead, so the bytes it read
+    /// ahead reach the codec.
     fn bind_transport(&self, io: T) -> Self::BindTransport;
+
+    /// Runs the handshake of the protocol on a freshly bound transport,
+    /// before the dispatcher takes over, e.g. to negotiate a version or to
+    /// authenticate.
+    ///
+    /// The handshake exchanges messages with the peer through the transport,
+    /// so it needs no parsing of its own, and resolves to the transport the
+    /// requests are then served on. A transport wrapping the codec can keep what was
+    /// negotiated for later use. Failing the handshake closes the
+    /// connection, and the bind timeout, if any, covers it. Defaults to
+    /// using the transport as is.
+    fn handshake(transport: Self::Transport) -> HandshakeFuture<Self::Transport> {
+        Box::new(future::ok(transport))
+    }
+
+    /// Create a `RequestIdValidator` used to check the ids of requests
+    /// received on a single connection before they are dispatched.
+    ///
+    /// By default every request id is accepted.
+    fn request_id_validator(&self) -> Box<RequestIdValidator<Self::RequestId>> {
+        Box::new(AnyRequestId)
+    }
+
+    /// Tuning knobs applied to every connection bound by this protocol.
+    ///
+    /// Defaults to `MultiplexConfig::default()`.
+    fn config(&self) -> MultiplexConfig {
+        MultiplexConfig::default()
+    }
+
+    /// Lets the transport answer `request` itself, without dispatching it to
+    /// the service.
+    ///
+    /// Called for every request read from the transport. Returning a
+    /// response writes it in place of one from the service, keeping protocol
+    /// plumbing such as PINGs out of services. Defaults to dispatching every
+    /// request.
+    fn answer_inline(transport: &mut Self::Transport, request: &Self::Request)
+                     -> Option<Self::Response>
+    {
+        let _ = (transport, request);
+        None
+    }
+
+    /// Returns true if the connection ends with `response`, e.g. the answer
+    /// to a QUIT command.
+    ///
+    /// Once a response closes the connection, no more requests are read; the
+    /// requests in flight are answered and the connection is closed.
+    /// Defaults to keeping the connection open.
+    fn closes_connection(response: &Self::Response) -> bool {
+        let _ = response;
+        false
+    }
+
+    /// Returns the key of `request` if its response must not overtake those
+    /// to earlier requests with the same key.
+    ///
+    /// Responses sharing a key are written in the order their requests were
+    /// read, the others as soon as they are ready. Defaults to none.
+    fn ordering_key(request: &Self::Request) -> Option<u64> {
+        let _ = request;
+        None
+    }
+
+    /// Returns the time the service has to answer `request`, overriding the
+    /// `request_timeout` of the config.
+    ///
+    /// Once it expires, the request fails with a `TimedOut` error, written
+    /// with `error_response`. Defaults to none.
+    fn request_timeout(request: &Self::Request) -> Option<Duration> {
+        let _ = request;
+        None
+    }
+
+    /// Create the `Committer` holding back the responses of a connection
+    /// until they are confirmed, e.g. durable.
+    ///
+    /// A failed commit, or one exceeding the `commit_timeout` of the config,
+    /// fails the request instead, its error written with `error_response`.
+    /// Defaults to none.
+    fn committer(&self) -> Option<Box<Committer<Self::Response>>> {
+        None
+    }
+
+    /// Turn `error`, which the service failed a request with, into the
+    /// response written for it.
+    ///
+    /// Protocols with error responses encode `error` into one, so that the
+    /// client can match on it. Returning an error fails the connection, as
+    /// does the default.
+    fn error_response(error: Self::Error) -> io::Result<Self::Response> {
+        let _ = error;
+        Err(io::Error::new(io::ErrorKind::Other, "no support for error frames"))
+    }
+
+    /// Receives the `Push` handle of a connection, called once for every
+    /// connection, before its transport is bound.
+    ///
+    /// Messages pushed through the handle are written as `(RequestId,
+    /// Response)` pairs like responses are, so the protocol needs to tell
+    /// them apart by their id. By default the handle is dropped.
+    fn on_bind(&self, push: Push<Self::RequestId, Self::Response>) {
+        let _ = push;
+    }
 }
 
 impl<T: 'static, P: ServerProto<T>> BindServer<Multiplex, T> for P {
     type ServiceRequest = P::Request;
     type ServiceResponse = P::Response;
-    type ServiceError = io::Error;
+    type ServiceError = P::Error;
 
     fn bind_server<S>(&self, handle: &Handle, io: T, service: S)
         where S: Service<Request = Self::ServiceRequest,
                          Response = Self::ServiceResponse,
                          Error = Self::ServiceError> + 'static
     {
-        BindServer::<StreamingMultiplex<MyStream<io::Error>>, T>::bind_server(
+        BindServer::<StreamingMultiplex<MyStream<P::Error>>, T>::bind_server(
             LiftProto::from_ref(self), handle, io, LiftService(service)
         )
     }
+
+    fn bind_server_timeout<S>(&self, handle: &Handle, io: T, service: S, timeout: Duration)
+        where S: Service<Request = Self::ServiceRequest,
+                         Response = Self::ServiceResponse,
+                         Error = Self::ServiceError> + 'static
+    {
+        BindServer::<StreamingMultiplex<MyStream<P::Error>>, T>::bind_server_timeout(
+            LiftProto::from_ref(self), handle, io, LiftService(service), timeout
+        )
+    }
+
+    fn bind_server_guarded<S, G>(&self, handle: &Handle, io: T, service: S,
+                                 config: &BindConfig, guard: G)
+        where S: Service<Request = Self::ServiceRequest,
+                         Response = Self::ServiceResponse,
+                         Error = Self::ServiceError> + 'static,
+              G: 'static,
+    {
+        BindServer::<StreamingMultiplex<MyStream<P::Error>>, T>::bind_server_guarded(
+            LiftProto::from_ref(self), handle, io, LiftService(service), config, guard
+        )
+    }
+}
+
+/// Returns the task serving `io` with `service`, like
+/// `BindServer::bind_server` binds it, but without spawning it.
+///
+/// See `streaming::multiplex::server_task`.
+pub fn server_task<P, T, S>(proto: &P, handle: &Handle, io: T, service: S)
+                            -> Box<Future<Item = (), Error = io::Error>>
+    where P: ServerProto<T>,
+          T: 'static,
+          S: Service<Request = P::Request,
+                     Response = P::Response,
+                     Error = P::Error> + 'static,
+{
+    streaming::multiplex::server_task(LiftProto::from_ref(proto), handle, io, LiftService(service))
 }
 
 impl<T, P> streaming::multiplex::ServerProto<T> for LiftProto<P> where
@@ -81,13 +234,51 @@ impl<T, P> streaming::multiplex::ServerProto<T> for LiftProto<P> where
     type ResponseBody = ();
     type RequestId = P::RequestId;
 
-    type Error = io::Error;
+    type Error = P::Error;
 
-    type Transport = LiftTransport<P::Transport, io::Error>;
-    type BindTransport = LiftBind<T, <P::BindTransport as IntoFuture>::Future, io::Error>;
+    type Transport = LiftTransport<P::Transport, P::Error, P::Request, P::Response>;
+    type BindTransport = LiftBind<T, BindHandshake<<P::BindTransport as IntoFuture>::Future,
+                                                    P::Transport>, P::Error,
+                                  P::Request, P::Response>;
 
     fn bind_transport(&self, io: T) -> Self::BindTransport {
-        LiftBind::lift(ServerProto::bind_transport(self.lower(), io).into_future())
+        let handshake = <P as ServerProto<T>>::handshake as fn(_) -> _;
+        LiftBind::lift(ServerProto::bind_transport(self.lower(), io).into_future().and_then(handshake),
+                       read_no_errors, P::error_response)
+    }
+
+    fn request_id_validator(&self) -> Box<RequestIdValidator<Self::RequestId>> {
+        ServerProto::request_id_validator(self.lower())
+    }
+
+    fn config(&self) -> MultiplexConfig {
+        ServerProto::config(self.lower())
+    }
+
+    fn answer_inline(transport: &mut Self::Transport, request: &P::Request)
+                     -> Option<P::Response>
+    {
+        P::answer_inline(&mut transport.inner, request)
+    }
+
+    fn closes_connection(response: &P::Response) -> bool {
+        P::closes_connection(response)
+    }
+
+    fn ordering_key(request: &P::Request) -> Option<u64> {
+        P::ordering_key(request)
+    }
+
+    fn request_timeout(request: &P::Request) -> Option<Duration> {
+        P::request_timeout(request)
+    }
+
+    fn committer(&self) -> Option<Box<Committer<P::Response>>> {
+        ServerProto::committer(self.lower())
+    }
+
+    fn on_bind(&self, push: Push<P::RequestId, P::Response>) {
+        ServerProto::on_bind(self.lower(), push)
     }
 }
 
@@ -0,0 +1,359 @@
+use {BindClient, BindConfig};
+use ProtocolKind;
+use super::{RequestIdSource, RequestId};
+
+use std::io;
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::time::Duration;
+
+use streaming::{self, Body, Message};
+use streaming::multiplex::{advanced, Frame, StreamingMultiplex, MultiplexConfig, Transport};
+use util::client_proxy::ClientProxy;
+use tokio_core::reactor::Handle;
+use tokio_service::Service;
+use futures::{stream, Async, AsyncSink, Future, IntoFuture, Poll, Sink, StartSend, Stream};
+
+type MyStream<E> = stream::Empty<(), E>;
+
+/// A multiplexed client protocol whose responses may stream a body.
+///
+/// Like `ClientProto`, requests are plain messages, but the transport may
+/// follow a response with body chunks, which the caller receives as a
+/// `Body` stream instead of having to go full streaming. Responses without a
+/// body come with an empty one.
+///
+/// The `T` parameter is used for the I/O object used to communicate, which is
+/// supplied in `bind_transport`.
+pub trait StreamingResponseProto<T: 'static>: 'static {
+    /// Request messages.
+    type Request: 'static;
+
+    /// Response messages.
+    type Response: 'static;
+
+    /// Response body chunks.
+    type ResponseBody: 'static;
+
+    /// The type of request ids to used to correlate requests to responses
+    ///
+    /// They are `Send` as the client lists them, see
+    /// `ClientService::outstanding`.
+    type RequestId: RequestId + Send;
+
+    /// The message transport, which usually take `T` as a parameter.
+    ///
+    /// Responses and their body chunks are read as `ResponseFrame`s, tagged
+    /// with the id of their request.
+    type Transport: 'static +
+        Stream<Item = (Self::RequestId, ResponseFrame<Self::Response, Self::ResponseBody>),
+               Error = io::Error> +
+        Sink<SinkItem = (Self::RequestId, Self::Request), SinkError = io::Error>;
+
+    /// A future for initializing a transport from an I/O object.
+    ///
+    /// In simple cases, `Result<Self::Transport, Self::Error>` often suffices.
+    type BindTransport: IntoFuture<Item = Self::Transport, Error = io::Error>;
+
+    /// The `RequestIdSource` to use.
+    type RequestIdSource: RequestIdSource<Self::RequestId, Self::Request>;
+
+    /// Create a `RequestIdSource` to generate ids for requests, used both on the wire and
+    /// internally to correlate responses to requests.
+    fn requestid_source(&self) -> Self::RequestIdSource;
+
+    /// Build a transport from the given I/O object, using `self` for any
+    /// configuration.
+    fn bind_transport(&self, io: T) -> Self::BindTransport;
+
+    /// Tuning knobs applied to every connection bound by this protocol.
+    ///
+    /// Defaults to `MultiplexConfig::default()`.
+    fn config(&self) -> MultiplexConfig {
+        MultiplexConfig::default()
+    }
+
+    /// Time a request waits for its response before it is given up on.
+    ///
+    /// See `ClientProto::request_timeout`. Defaults to `None`, waiting
+    /// forever.
+    fn request_timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Time the id of a canceled or timed out request stays reserved for
+    /// the late response.
+    ///
+    /// See `ClientProto::late_response_timeout`. Defaults to 30 seconds.
+    fn late_response_timeout(&self) -> Duration {
+        Duration::from_secs(30)
+    }
+
+    /// Cancel the exchange `request_id` on the transport.
+    ///
+    /// See `ClientProto::cancel`. By default nothing is written.
+    fn cancel(transport: &mut Self::Transport, request_id: Self::RequestId) -> io::Result<()> {
+        let _ = (transport, request_id);
+        Ok(())
+    }
+}
+
+/// A frame read by the transport of a `StreamingResponseProto`.
+#[derive(Debug, Clone)]
+pub enum ResponseFrame<T, B> {
+    /// A response.
+    Message {
+        /// The message value
+        message: T,
+        /// Set to true when body frames will follow for the same request.
+        body: bool,
+    },
+    /// Body frame.
+    Body {
+        /// Body chunk. Setting to `None` indicates that the body is done
+        /// streaming.
+        chunk: Option<B>,
+    },
+}
+
+/// A marker used to flag protocols as being multiplexed RPC with streamed
+/// response bodies.
+///
+/// This is an implementation detail; to actually implement a protocol,
+/// implement the `StreamingResponseProto` trait in this module.
+pub struct StreamingResponse;
+
+impl<T> ProtocolKind<T> for StreamingResponse where T: advanced::Dispatch {
+    type Frame = Frame<T::RequestId, T::In, T::BodyIn, T::Error>;
+    type Config = MultiplexConfig;
+    type Driver = advanced::Multiplex<T>;
+
+    fn drive(dispatch: T, config: &MultiplexConfig) -> advanced::Multiplex<T> {
+        advanced::Multiplex::with_config(dispatch, config)
+    }
+}
+
+impl<T: 'static, P: StreamingResponseProto<T>> BindClient<StreamingResponse, T> for P {
+    type ServiceRequest = P::Request;
+    type ServiceResponse = (P::Response, Body<P::ResponseBody, io::Error>);
+    type ServiceError = io::Error;
+
+    type BindClient = StreamingResponseService<T, P>;
+
+    fn bind_client(&self, handle: &Handle, io: T) -> Self::BindClient {
+        StreamingResponseService {
+            inner: BindClient::<StreamingMultiplex<MyStream<io::Error>>, T>::bind_client(
+                LiftResponseProto::from_ref(self), handle, io
+            ),
+            _local: PhantomData,
+        }
+    }
+
+    fn bind_client_timeout(&self, handle: &Handle, io: T, timeout: Duration) -> Self::BindClient {
+        StreamingResponseService {
+            inner: BindClient::<StreamingMultiplex<MyStream<io::Error>>, T>::bind_client_timeout(
+                LiftResponseProto::from_ref(self), handle, io, timeout
+            ),
+            _local: PhantomData,
+        }
+    }
+
+    fn bind_client_with(&self, handle: &Handle, io: T, config: &BindConfig) -> Self::BindClient {
+        StreamingResponseService {
+            inner: BindClient::<StreamingMultiplex<MyStream<io::Error>>, T>::bind_client_with(
+                LiftResponseProto::from_ref(self), handle, io, config
+            ),
+            _local: PhantomData,
+        }
+    }
+}
+
+// Lifts a `StreamingResponseProto` to a streaming proto, see `LiftProto`
+struct LiftResponseProto<P>(P);
+
+impl<P> LiftResponseProto<P> {
+    fn from_ref(proto: &P) -> &LiftResponseProto<P> {
+        unsafe { ::std::mem::transmute(proto) }
+    }
+
+    fn lower(&self) -> &P {
+        &self.0
+    }
+}
+
+impl<T, P> streaming::multiplex::ClientProto<T> for LiftResponseProto<P> where
+    T: 'static, P: StreamingResponseProto<T>
+{
+    type Request = P::Request;
+    type RequestBody = ();
+
+    type Response = P::Response;
+    type ResponseBody = P::ResponseBody;
+    type RequestId = P::RequestId;
+
+    type Error = io::Error;
+
+    type Transport = LiftResponseTransport<P::Transport>;
+    type BindTransport = LiftResponseBind<<P::BindTransport as IntoFuture>::Future>;
+    type RequestIdSource = P::RequestIdSource;
+
+    fn requestid_source(&self) -> Self::RequestIdSource {
+        P::requestid_source(self.lower())
+    }
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        LiftResponseBind(P::bind_transport(self.lower(), io).into_future())
+    }
+
+    fn config(&self) -> MultiplexConfig {
+        P::config(self.lower())
+    }
+
+    fn request_timeout(&self) -> Option<Duration> {
+        P::request_timeout(self.lower())
+    }
+
+    fn late_response_timeout(&self) -> Duration {
+        P::late_response_timeout(self.lower())
+    }
+
+    fn cancel(transport: &mut Self::Transport, request_id: P::RequestId) -> io::Result<()> {
+        P::cancel(&mut transport.0, request_id)
+    }
+}
+
+// Lifts the transport of a `StreamingResponseProto` to a streaming transport
+pub struct LiftResponseTransport<T>(T);
+
+pub struct LiftResponseBind<F>(F);
+
+impl<T, RequestId, R, B> Stream for LiftResponseTransport<T> where
+    T: Stream<Item = (RequestId, ResponseFrame<R, B>), Error = io::Error>,
+{
+    type Item = Frame<RequestId, R, B, io::Error>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, io::Error> {
+        let frame = match try_ready!(self.0.poll()) {
+            Some((id, ResponseFrame::Message { message, body })) => {
+                Frame::Message { id: id, message: message, body: body, solo: false }
+            }
+            Some((id, ResponseFrame::Body { chunk })) => {
+                Frame::Body { id: id, chunk: chunk }
+            }
+            None => return Ok(None.into()),
+        };
+
+        Ok(Some(frame).into())
+    }
+}
+
+impl<T, RequestId, R> Sink for LiftResponseTransport<T> where
+    T: Sink<SinkItem = (RequestId, R), SinkError = io::Error>,
+{
+    type SinkItem = Frame<RequestId, R, (), io::Error>;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, request: Self::SinkItem)
+                  -> StartSend<Self::SinkItem, io::Error> {
+        if let Frame::Message { message, id, body: false, solo: false } = request {
+            return match try!(self.0.start_send((id, message))) {
+                AsyncSink::Ready => Ok(AsyncSink::Ready),
+                AsyncSink::NotReady((id, message)) => {
+                    Ok(AsyncSink::NotReady(Frame::Message {
+                        message: message,
+                        id: id,
+                        body: false,
+                        solo: false,
+                    }))
+                }
+            };
+        }
+
+        Err(io::Error::new(io::ErrorKind::Other, "no support for streaming requests"))
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        self.0.poll_complete()
+    }
+}
+
+impl<T, RequestId, R, B, Q> Transport<RequestId, B> for LiftResponseTransport<T> where
+    T: 'static,
+    T: Stream<Item = (RequestId, ResponseFrame<R, B>), Error = io::Error>,
+    T: Sink<SinkItem = (RequestId, Q), SinkError = io::Error>,
+{}
+
+impl<F> Future for LiftResponseBind<F> where F: Future<Error = io::Error> {
+    type Item = LiftResponseTransport<F::Item>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, io::Error> {
+        Ok(Async::Ready(LiftResponseTransport(try_ready!(self.0.poll()))))
+    }
+}
+
+/// Client `Service` for multiplex protocols with streamed response bodies
+///
+/// Like `ClientService`, it is local to the thread running the event loop
+/// it was bound on.
+pub struct StreamingResponseService<T, P> where T: 'static, P: StreamingResponseProto<T> {
+    inner: ClientProxy<Message<P::Request, MyStream<io::Error>>,
+                       Message<P::Response, Body<P::ResponseBody, io::Error>>,
+                       io::Error>,
+    _local: PhantomData<Rc<()>>,
+}
+
+impl<T, P> Service for StreamingResponseService<T, P> where T: 'static, P: StreamingResponseProto<T> {
+    type Request = P::Request;
+    type Response = (P::Response, Body<P::ResponseBody, io::Error>);
+    type Error = io::Error;
+    type Future = StreamingResponseFuture<T, P>;
+
+    fn call(&self, req: P::Request) -> Self::Future {
+        StreamingResponseFuture {
+            inner: self.inner.call(Message::WithoutBody(req)),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, P> Clone for StreamingResponseService<T, P> where T: 'static, P: StreamingResponseProto<T> {
+    fn clone(&self) -> Self {
+        StreamingResponseService {
+            inner: self.inner.clone(),
+            _local: PhantomData,
+        }
+    }
+}
+
+/// The future returned by calling a `StreamingResponseService`.
+///
+/// Resolves once the response message arrives, with its body still
+/// streaming. Dropping the future before then cancels the exchange like it
+/// does for `ClientFuture`.
+pub struct StreamingResponseFuture<T, P> where T: 'static, P: StreamingResponseProto<T> {
+    inner: <ClientProxy<Message<P::Request, MyStream<io::Error>>,
+                        Message<P::Response, Body<P::ResponseBody, io::Error>>,
+                        io::Error> as Service>::Future,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, P> StreamingResponseFuture<T, P> where T: 'static, P: StreamingResponseProto<T> {
+    /// Let the exchange complete in the background, without canceling it.
+    pub fn detach(self) {
+        self.inner.detach()
+    }
+}
+
+impl<T, P> Future for StreamingResponseFuture<T, P> where T: 'static, P: StreamingResponseProto<T> {
+    type Item = (P::Response, Body<P::ResponseBody, io::Error>);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match try_ready!(self.inner.poll()) {
+            Message::WithoutBody(msg) => Ok((msg, Body::empty()).into()),
+            Message::WithBody(msg, body) => Ok((msg, body).into()),
+        }
+    }
+}
@@ -0,0 +1,280 @@
+//! Protocols running either pipelined or multiplexed, per connection.
+//!
+//! Some protocols can run pipelined or multiplexed depending on what both
+//! peers support. `Negotiate` runs a handshake on each new connection and
+//! binds either the pipeline or the multiplex dispatcher based on its
+//! outcome, so that applications get a single client service and server
+//! type regardless of the mode.
+
+use std::io;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use {BindClient, BindConfig, BindServer};
+use super::{pipeline, multiplex};
+use timeout::Deadline;
+use util::client_proxy::{self, ClientProxy, ErrorSink, Errors, Receiver};
+use futures::{Future, IntoFuture, Stream};
+use tokio_core::reactor::Handle;
+use tokio_service::Service;
+
+/// The dispatching mode agreed on by a handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Use the pipeline dispatcher
+    Pipeline,
+
+    /// Use the multiplex dispatcher
+    Multiplex,
+}
+
+/// Negotiates the dispatching mode of a connection.
+///
+/// Implemented for closures returning a future of the I/O object and the
+/// agreed on mode.
+pub trait Handshake<T: 'static>: 'static {
+    /// Future completing the handshake
+    type Future: Future<Item = (T, Mode), Error = io::Error>;
+
+    /// Run the handshake on the given I/O object
+    fn handshake(&self, io: T) -> Self::Future;
+}
+
+impl<T, F, R> Handshake<T> for F
+    where T: 'static,
+          F: Fn(T) -> R + 'static,
+          R: IntoFuture<Item = (T, Mode), Error = io::Error>,
+{
+    type Future = R::Future;
+
+    fn handshake(&self, io: T) -> R::Future {
+        self(io).into_future()
+    }
+}
+
+/// A marker used to flag protocols negotiating their dispatching mode.
+///
+/// This is an implementation detail; to use a negotiated protocol, see
+/// `Negotiate`.
+pub struct Negotiated;
+
+/// A protocol negotiating between pipelining and multiplexing.
+///
+/// `P` is the pipelined variant of the protocol and `M` the multiplexed one.
+/// Both need to agree on the request and response types.
+pub struct Negotiate<H, P, M> {
+    handshake: Arc<H>,
+    pipeline: Arc<P>,
+    multiplex: Arc<M>,
+}
+
+impl<H, P, M> Negotiate<H, P, M> {
+    /// Create a new `Negotiate` protocol.
+    ///
+    /// `handshake` decides which of the two protocols is used for each
+    /// connection.
+    pub fn new(handshake: H, pipeline: P, multiplex: M) -> Negotiate<H, P, M> {
+        Negotiate {
+            handshake: Arc::new(handshake),
+            pipeline: Arc::new(pipeline),
+            multiplex: Arc::new(multiplex),
+        }
+    }
+}
+
+impl<T, H, P, M> BindServer<Negotiated, T> for Negotiate<H, P, M>
+    where T: 'static,
+          H: Handshake<T>,
+          P: pipeline::ServerProto<T>,
+          M: multiplex::ServerProto<T, Request = P::Request, Response = P::Response, Error = io::Error>,
+{
+    type ServiceRequest = P::Request;
+    type ServiceResponse = P::Response;
+    type ServiceError = io::Error;
+
+    fn bind_server<S>(&self, handle: &Handle, io: T, service: S)
+        where S: Service<Request = P::Request,
+                         Response = P::Response,
+                         Error = io::Error> + 'static
+    {
+        self.bind_server_within(handle, io, service, &BindConfig::default(), ())
+    }
+
+    fn bind_server_timeout<S>(&self, handle: &Handle, io: T, service: S, timeout: Duration)
+        where S: Service<Request = P::Request,
+                         Response = P::Response,
+                         Error = io::Error> + 'static
+    {
+        self.bind_server_within(handle, io, service, &BindConfig { timeout: Some(timeout), ..BindConfig::default() }, ())
+    }
+
+    fn bind_server_guarded<S, G>(&self, handle: &Handle, io: T, service: S,
+                                 config: &BindConfig, guard: G)
+        where S: Service<Request = P::Request,
+                         Response = P::Response,
+                         Error = io::Error> + 'static,
+              G: 'static,
+    {
+        self.bind_server_within(handle, io, service, config, guard)
+    }
+}
+
+impl<H, P, M> Negotiate<H, P, M> {
+    // Binds the server, with the timeout covering both the handshake and
+    // binding the negotiated transport
+    fn bind_server_within<T, S, G>(&self, handle: &Handle, io: T, service: S,
+                                   config: &BindConfig, guard: G)
+        where T: 'static,
+              H: Handshake<T>,
+              P: pipeline::ServerProto<T>,
+              M: multiplex::ServerProto<T, Request = P::Request, Response = P::Response, Error = io::Error>,
+              S: Service<Request = P::Request,
+                         Response = P::Response,
+                         Error = io::Error> + 'static,
+              G: 'static,
+    {
+        let pipeline = self.pipeline.clone();
+        let multiplex = self.multiplex.clone();
+        let bind_handle = handle.clone();
+        let mut config = config.clone();
+        let deadline = config.timeout.map(|timeout| Instant::now() + timeout);
+
+        let handshake = Deadline::new(self.handshake.handshake(io), config.timeout, handle);
+
+        let task = handshake.map(move |(io, mode)| {
+            trace!("negotiated server mode; mode={:?}", mode);
+
+            config.timeout = remaining(deadline);
+
+            match mode {
+                Mode::Pipeline => {
+                    BindServer::<pipeline::Pipeline, T>::bind_server_guarded(
+                        &*pipeline, &bind_handle, io, service, &config, guard)
+                }
+                Mode::Multiplex => {
+                    BindServer::<multiplex::Multiplex, T>::bind_server_guarded(
+                        &*multiplex, &bind_handle, io, service, &config, guard)
+                }
+            }
+        }).map_err(|e| {
+            debug!("handshake failed; err={}", e);
+        });
+
+        handle.spawn(task)
+    }
+}
+
+impl<T, H, P, M> BindClient<Negotiated, T> for Negotiate<H, P, M>
+    where T: 'static,
+          H: Handshake<T>,
+          P: pipeline::ClientProto<T>,
+          M: multiplex::ClientProto<T, Request = P::Request, Response = P::Response, Error = io::Error>,
+{
+    type ServiceRequest = P::Request;
+    type ServiceResponse = P::Response;
+    type ServiceError = io::Error;
+
+    type BindClient = ClientProxy<P::Request, P::Response, io::Error>;
+
+    fn bind_client(&self, handle: &Handle, io: T) -> Self::BindClient {
+        self.bind_client_within(handle, io, &BindConfig::default())
+    }
+
+    fn bind_client_timeout(&self, handle: &Handle, io: T, timeout: Duration) -> Self::BindClient {
+        self.bind_client_within(handle, io, &BindConfig { timeout: Some(timeout), ..BindConfig::default() })
+    }
+
+    fn bind_client_with(&self, handle: &Handle, io: T, config: &BindConfig) -> Self::BindClient {
+        self.bind_client_within(handle, io, config)
+    }
+}
+
+impl<H, P, M> Negotiate<H, P, M> {
+    // Binds the client, with the timeout covering both the handshake and
+    // binding the negotiated transport
+    fn bind_client_within<T>(&self, handle: &Handle, io: T, config: &BindConfig)
+                             -> ClientProxy<P::Request, P::Response, io::Error>
+        where T: 'static,
+              H: Handshake<T>,
+              P: pipeline::ClientProto<T>,
+              M: multiplex::ClientProto<T, Request = P::Request, Response = P::Response, Error = io::Error>,
+    {
+        let (client, rx) = client_proxy::pair();
+        let errors = Rc::new(client.error_sink());
+        let handshake_errors = errors.clone();
+
+        let pipeline = self.pipeline.clone();
+        let multiplex = self.multiplex.clone();
+        let bind_handle = handle.clone();
+        let mut config = config.clone();
+        let deadline = config.timeout.map(|timeout| Instant::now() + timeout);
+
+        let handshake = Deadline::new(self.handshake.handshake(io), config.timeout, handle);
+
+        // Requests are buffered in `rx` until the handshake completes, then
+        // forwarded to the client of the negotiated dispatcher.
+        let task = handshake.and_then(move |(io, mode)| {
+            trace!("negotiated client mode; mode={:?}", mode);
+
+            config.timeout = remaining(deadline);
+
+            match mode {
+                Mode::Pipeline => {
+                    let service = BindClient::<pipeline::Pipeline, T>::bind_client_with(
+                        &*pipeline, &bind_handle, io, &config);
+                    report(&bind_handle, service.errors(), errors);
+                    forward(bind_handle, service, rx)
+                }
+                Mode::Multiplex => {
+                    let service = BindClient::<multiplex::Multiplex, T>::bind_client_with(
+                        &*multiplex, &bind_handle, io, &config);
+                    report(&bind_handle, service.errors(), errors);
+                    forward(bind_handle, service, rx)
+                }
+            }
+        }).map_err(move |e| {
+            debug!("negotiated client failed; err={}", e);
+            handshake_errors.report(e);
+        });
+
+        handle.spawn(task);
+
+        client
+    }
+}
+
+// Time left until the given deadline, if any
+fn remaining(deadline: Option<Instant>) -> Option<Duration> {
+    deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()))
+}
+
+// Pass on the errors of the negotiated connection to the client proxy
+fn report(handle: &Handle, errors: Errors, sink: Rc<ErrorSink>) {
+    handle.spawn(errors.for_each(move |e| {
+        sink.report(e);
+        Ok(())
+    }));
+}
+
+// Dispatch requests received from the client proxy on the given service
+fn forward<S>(handle: Handle, service: S, rx: Receiver<S::Request, S::Response, io::Error>)
+              -> Box<Future<Item = (), Error = io::Error>>
+    where S: Service<Error = io::Error> + 'static,
+          S::Request: 'static,
+          S::Response: 'static,
+          S::Future: 'static,
+{
+    let requests = rx.map_err(|_| io::Error::new(io::ErrorKind::Other, "request channel failed"));
+
+    Box::new(requests.for_each(move |request| {
+        let (request, complete) = try!(request);
+
+        handle.spawn(service.call(request).then(move |res| {
+            complete.complete(res);
+            Ok(())
+        }));
+
+        Ok(())
+    }))
+}
@@ -0,0 +1,106 @@
+use std::collections::VecDeque;
+
+use futures::{Async, Future, Poll, Stream};
+use tokio_service::Service;
+
+/// Feeds a stream of requests to a pipelined client, yielding the responses in
+/// request order.
+///
+/// At most `max_in_flight` requests are outstanding at any time; the request
+/// stream is only polled when a slot is free. Each response is yielded as a
+/// `Result` so that a single failed request does not end the batch. Errors
+/// from the request stream itself are passed through as stream errors.
+///
+/// # Panics
+///
+/// Panics if `max_in_flight` is zero.
+pub fn pipeline_all<S, R>(service: S, requests: R, max_in_flight: usize) -> PipelineAll<S, R>
+    where S: Service,
+          R: Stream<Item = S::Request>,
+{
+    assert!(max_in_flight > 0, "max_in_flight must be at least 1");
+
+    PipelineAll {
+        service: service,
+        requests: Some(requests),
+        in_flight: VecDeque::with_capacity(max_in_flight),
+        max_in_flight: max_in_flight,
+    }
+}
+
+/// Stream of responses returned by `pipeline_all`.
+#[must_use = "streams do nothing unless polled"]
+pub struct PipelineAll<S: Service, R> {
+    service: S,
+    // `None` once the request stream is exhausted
+    requests: Option<R>,
+    in_flight: VecDeque<InFlight<S::Future>>,
+    max_in_flight: usize,
+}
+
+enum InFlight<F: Future> {
+    Active(F),
+    Done(Result<F::Item, F::Error>),
+}
+
+impl<S, R> Stream for PipelineAll<S, R>
+    where S: Service,
+          R: Stream<Item = S::Request>,
+{
+    type Item = Result<S::Response, S::Error>;
+    type Error = R::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, R::Error> {
+        // Issue as many requests as the window allows
+        while self.in_flight.len() < self.max_in_flight {
+            let request = match self.requests {
+                Some(ref mut requests) => try!(requests.poll()),
+                None => break,
+            };
+
+            match request {
+                Async::NotReady => break,
+                Async::Ready(Some(request)) => {
+                    let response = self.service.call(request);
+                    self.in_flight.push_back(InFlight::Active(response));
+                }
+                Async::Ready(None) => {
+                    trace!("pipeline_all: request stream exhausted");
+                    self.requests = None;
+                }
+            }
+        }
+
+        for slot in self.in_flight.iter_mut() {
+            slot.poll();
+        }
+
+        match self.in_flight.front() {
+            Some(&InFlight::Done(_)) => {}
+            Some(&InFlight::Active(_)) => return Ok(Async::NotReady),
+            None if self.requests.is_none() => return Ok(Async::Ready(None)),
+            None => return Ok(Async::NotReady),
+        }
+
+        match self.in_flight.pop_front() {
+            Some(InFlight::Done(res)) => Ok(Async::Ready(Some(res))),
+            _ => panic!(),
+        }
+    }
+}
+
+impl<F: Future> InFlight<F> {
+    fn poll(&mut self) {
+        let res = match *self {
+            InFlight::Active(ref mut f) => {
+                match f.poll() {
+                    Ok(Async::Ready(e)) => Ok(e),
+                    Err(e) => Err(e),
+                    Ok(Async::NotReady) => return,
+                }
+            }
+            _ => return,
+        };
+        *self = InFlight::Done(res);
+    }
+}
@@ -1,14 +1,17 @@
-use BindClient;
+use {BindClient, BindConfig};
 use super::Pipeline;
 use super::lift::{LiftBind, LiftTransport};
-use simple::LiftProto;
+use simple::{BindHandshake, HandshakeFuture, LiftProto};
 
-use streaming::{self, Message};
-use streaming::pipeline::StreamingPipeline;
+use streaming::{self, Body, Message, StreamingView};
+use streaming::pipeline::{StreamingPipeline, PipelineConfig};
+use util::client_proxy;
 use tokio_core::reactor::Handle;
 use tokio_service::Service;
-use futures::{stream, Stream, Sink, Future, Poll, IntoFuture};
+use futures::{future, stream, Async, Stream, Sink, Future, Poll, IntoFuture};
 use std::io;
+use std::marker::PhantomData;
+use std::time::Duration;
 
 type MyStream<E> = stream::Empty<(), E>;
 
@@ -47,7 +50,32 @@ pub trait ClientProto<T: 'static>: 'static {
     /// An easy way to build a transport is to use `tokio_core::io::Framed`
     /// together with a `Codec`; in that case, `bind_transport` is just
     /// `io.framed(YourCodec)`. See the crate docs for an example.
+    ///
+    /// If a handshake has read from `io` already, build the transport with
+    /// `util::framed::framed_with_buffer` instead, so the bytes it read
+    /// ahead reach the codec.
     fn bind_transport(&self, io: T) -> Self::BindTransport;
+
+    /// Runs the handshake of the protocol on a freshly bound transport,
+    /// before the dispatcher takes over, e.g. to negotiate a version or to
+    /// authenticate.
+    ///
+    /// The handshake exchanges messages with the peer through the transport,
+    /// so it needs no parsing of its own, and resolves to the transport the
+    /// requests are then sent on. A transport wrapping the codec can keep what was
+    /// negotiated for later use. Failing the handshake closes the
+    /// connection, and the bind timeout, if any, covers it. Defaults to
+    /// using the transport as is.
+    fn handshake(transport: Self::Transport) -> HandshakeFuture<Self::Transport> {
+        Box::new(future::ok(transport))
+    }
+
+    /// Tuning knobs applied to every connection bound by this protocol.
+    ///
+    /// Defaults to `PipelineConfig::default()`.
+    fn config(&self) -> PipelineConfig {
+        PipelineConfig::default()
+    }
 }
 
 impl<T: 'static, P: ClientProto<T>> BindClient<Pipeline, T> for P {
@@ -64,6 +92,22 @@ impl<T: 'static, P: ClientProto<T>> BindClient<Pipeline, T> for P {
             )
         }
     }
+
+    fn bind_client_timeout(&self, handle: &Handle, io: T, timeout: Duration) -> Self::BindClient {
+        ClientService {
+            inner: BindClient::<StreamingPipeline<MyStream<io::Error>>, T>::bind_client_timeout(
+                LiftProto::from_ref(self), handle, io, timeout
+            )
+        }
+    }
+
+    fn bind_client_with(&self, handle: &Handle, io: T, config: &BindConfig) -> Self::BindClient {
+        ClientService {
+            inner: BindClient::<StreamingPipeline<MyStream<io::Error>>, T>::bind_client_with(
+                LiftProto::from_ref(self), handle, io, config
+            )
+        }
+    }
 }
 
 impl<T, P> streaming::pipeline::ClientProto<T> for LiftProto<P> where
@@ -78,10 +122,16 @@ impl<T, P> streaming::pipeline::ClientProto<T> for LiftProto<P> where
     type Error = io::Error;
 
     type Transport = LiftTransport<P::Transport, io::Error>;
-    type BindTransport = LiftBind<T, <P::BindTransport as IntoFuture>::Future, io::Error>;
+    type BindTransport = LiftBind<T, BindHandshake<<P::BindTransport as IntoFuture>::Future,
+                                                    P::Transport>, io::Error>;
 
     fn bind_transport(&self, io: T) -> Self::BindTransport {
-        LiftBind::lift(ClientProto::bind_transport(self.lower(), io).into_future())
+        let handshake = <P as ClientProto<T>>::handshake as fn(_) -> _;
+        LiftBind::lift(ClientProto::bind_transport(self.lower(), io).into_future().and_then(handshake))
+    }
+
+    fn config(&self) -> PipelineConfig {
+        ClientProto::config(self.lower())
     }
 }
 
@@ -98,6 +148,35 @@ impl<T, P> Clone for ClientService<T, P> where T: 'static, P: ClientProto<T> {
     }
 }
 
+impl<T, P> ClientService<T, P> where T: 'static, P: ClientProto<T> {
+    /// Returns a stream of the results of calls detached from now on.
+    ///
+    /// See `ClientFuture::detach`. The stream is shared by all clones of the
+    /// service; calling `detached` again replaces it.
+    pub fn detached(&self) -> Detached<T, P> {
+        Detached {
+            inner: self.inner.detached(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a stream of the errors that fail the connection as a whole.
+    ///
+    /// See `util::client_proxy::ClientProxy::errors` for details.
+    pub fn errors(&self) -> client_proxy::Errors {
+        self.inner.errors()
+    }
+
+    /// Returns a view of the client as a streaming service, for callers
+    /// written against streaming protocols.
+    ///
+    /// Requests must not have a body and responses never have one. See
+    /// `streaming::StreamingView`.
+    pub fn into_streaming_service<B, C>(self) -> StreamingView<Self, B, C> {
+        StreamingView::new(self)
+    }
+}
+
 impl<T, P> Service for ClientService<T, P> where T: 'static, P: ClientProto<T> {
     type Request = P::Request;
     type Response = P::Response;
@@ -111,11 +190,27 @@ impl<T, P> Service for ClientService<T, P> where T: 'static, P: ClientProto<T> {
     }
 }
 
+/// The future returned by calling a client service.
+///
+/// Dropping the future cancels the request: if it has not been written yet,
+/// it never will be. Once written, its response is still read and discarded,
+/// as pipelined responses arrive in order. Use `detach` to let the exchange
+/// complete in the background instead.
 pub struct ClientFuture<T, P> where T: 'static, P: ClientProto<T> {
     inner: <<LiftProto<P> as BindClient<StreamingPipeline<MyStream<io::Error>>, T>>::BindClient
             as Service>::Future
 }
 
+impl<T, P> ClientFuture<T, P> where T: 'static, P: ClientProto<T> {
+    /// Let the exchange complete in the background, without canceling it.
+    ///
+    /// The result is delivered on the service's `detached` stream, if any,
+    /// and discarded otherwise.
+    pub fn detach(self) {
+        self.inner.detach()
+    }
+}
+
 impl<T, P> Future for ClientFuture<T, P> where P: ClientProto<T> {
     type Item = P::Response;
     type Error = io::Error;
@@ -127,3 +222,26 @@ impl<T, P> Future for ClientFuture<T, P> where P: ClientProto<T> {
         }
     }
 }
+
+/// Stream of the results of detached calls, see `ClientService::detached`.
+///
+/// Calls dropped by the connection without a response yield a `BrokenPipe`
+/// error. The stream ends once the service and all detached calls are gone.
+pub struct Detached<T, P> where T: 'static, P: ClientProto<T> {
+    inner: client_proxy::Detached<Message<P::Response, Body<(), io::Error>>, io::Error>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, P> Stream for Detached<T, P> where T: 'static, P: ClientProto<T> {
+    type Item = io::Result<P::Response>;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, ()> {
+        match try_ready!(self.inner.poll()) {
+            Some(Ok(Message::WithoutBody(msg))) => Ok(Async::Ready(Some(Ok(msg)))),
+            Some(Ok(Message::WithBody(..))) => panic!("bodies not supported"),
+            Some(Err(e)) => Ok(Async::Ready(Some(Err(e)))),
+            None => Ok(Async::Ready(None)),
+        }
+    }
+}
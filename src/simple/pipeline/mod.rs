@@ -4,17 +4,38 @@
 
 mod client;
 pub use self::client::ClientProto;
-pub use self::client::ClientService;
+pub use self::client::{ClientService, ClientFuture, Detached};
 
 mod server;
 pub use self::server::ServerProto;
 
+mod batch;
+pub use self::batch::{pipeline_all, PipelineAll};
+
+pub use streaming::pipeline::PipelineConfig;
+pub use streaming::{CommitFuture, Committer};
+pub use simple::HandshakeFuture;
+
+use ProtocolKind;
+use streaming::pipeline::{advanced, Frame};
+
 /// A marker used to flag protocols as being pipelined RPC.
 ///
 /// This is an implementation detail; to actually implement a protocol,
 /// implement the `ClientProto` or `ServerProto` traits in this module.
 pub struct Pipeline;
 
+// RPC protocols are driven like streaming ones, with empty bodies
+impl<T> ProtocolKind<T> for Pipeline where T: advanced::Dispatch {
+    type Frame = Frame<T::In, T::BodyIn, T::Error>;
+    type Config = PipelineConfig;
+    type Driver = advanced::Pipeline<T>;
+
+    fn drive(dispatch: T, config: &PipelineConfig) -> advanced::Pipeline<T> {
+        advanced::Pipeline::with_config(dispatch, config)
+    }
+}
+
 // This is a submodule so that `LiftTransport` can be marked `pub`, to satisfy
 // the no-private-in-public checker.
 mod lift {
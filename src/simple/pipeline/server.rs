@@ -1,16 +1,17 @@
 use std::io;
 use std::marker;
+use std::time::Duration;
 
-use BindServer;
+use {BindConfig, BindServer};
 use super::Pipeline;
 use super::lift::{LiftBind, LiftTransport};
-use simple::LiftProto;
+use simple::{BindHandshake, HandshakeFuture, LiftProto};
 
-use streaming::{self, Message};
-use streaming::pipeline::StreamingPipeline;
+use streaming::{self, Committer, Message};
+use streaming::pipeline::{StreamingPipeline, PipelineConfig};
 use tokio_core::reactor::Handle;
 use tokio_service::Service;
-use futures::{stream, Stream, Sink, Future, IntoFuture, Poll};
+use futures::{future, stream, Stream, Sink, Future, IntoFuture, Poll};
 
 type MyStream<E> = stream::Empty<(), E>;
 
@@ -49,7 +50,68 @@ pub trait ServerProto<T: 'static>: 'static {
     /// An easy way to build a transport is to use `tokio_core::io::Framed`
     /// together with a `Codec`; in that case, `bind_transport` is just
     /// `io.framed(YourCodec)`. See the crate docs for an example.
+    ///
+    /// If a handshake has read from `io` already, build the transport with
+    /// `util::framed::framed_with_buffer` instead, so the bytes it read
+    /// ahead reach the codec.
     fn bind_transport(&self, io: T) -> Self::BindTransport;
+
+    /// Runs the handshake of the protocol on a freshly bound transport,
+    /// before the dispatcher takes over, e.g. to negotiate a version or to
+    /// authenticate.
+    ///
+    /// The handshake exchanges messages with the peer through the transport,
+    /// so it needs no parsing of its own, and resolves to the transport the
+    /// requests are then served on. A transport wrapping the codec can keep what was
+    /// negotiated for later use. Failing the handshake closes the
+    /// connection, and the bind timeout, if any, covers it. Defaults to
+    /// using the transport as is.
+    fn handshake(transport: Self::Transport) -> HandshakeFuture<Self::Transport> {
+        Box::new(future::ok(transport))
+    }
+
+    /// Tuning knobs applied to every connection bound by this protocol.
+    ///
+    /// Defaults to `PipelineConfig::default()`.
+    fn config(&self) -> PipelineConfig {
+        PipelineConfig::default()
+    }
+
+    /// Lets the transport answer `request` itself, without dispatching it to
+    /// the service.
+    ///
+    /// Called for every request read from the transport. Returning a
+    /// response writes it in place of one from the service, keeping protocol
+    /// plumbing such as PINGs out of services. Defaults to dispatching every
+    /// request.
+    fn answer_inline(transport: &mut Self::Transport, request: &Self::Request)
+                     -> Option<Self::Response>
+    {
+        let _ = (transport, request);
+        None
+    }
+
+    /// Returns true if the connection ends with `response`, e.g. the answer
+    /// to a QUIT command.
+    ///
+    /// Once a response closes the connection, the requests read after it are
+    /// dropped, the response is written out and the connection is closed.
+    /// Defaults to keeping the connection open.
+    fn closes_connection(response: &Self::Response) -> bool {
+        let _ = response;
+        false
+    }
+
+    /// Create the `Committer` holding back the responses of a connection
+    /// until they are confirmed, e.g. durable.
+    ///
+    /// Responses are still written in order, so one waiting for its commit
+    /// holds back the next ones. As a single request can't be failed, a
+    /// failed commit, or one exceeding the `commit_timeout` of the config,
+    /// closes the connection. Defaults to none.
+    fn committer(&self) -> Option<Box<Committer<Self::Response>>> {
+        None
+    }
 }
 
 impl<T: 'static, P: ServerProto<T>> BindServer<Pipeline, T> for P {
@@ -66,6 +128,28 @@ impl<T: 'static, P: ServerProto<T>> BindServer<Pipeline, T> for P {
             LiftProto::from_ref(self), handle, io, LiftService(service)
         )
     }
+
+    fn bind_server_timeout<S>(&self, handle: &Handle, io: T, service: S, timeout: Duration)
+        where S: Service<Request = Self::ServiceRequest,
+                         Response = Self::ServiceResponse,
+                         Error = io::Error> + 'static
+    {
+        BindServer::<StreamingPipeline<MyStream<io::Error>>, T>::bind_server_timeout(
+            LiftProto::from_ref(self), handle, io, LiftService(service), timeout
+        )
+    }
+
+    fn bind_server_guarded<S, G>(&self, handle: &Handle, io: T, service: S,
+                                 config: &BindConfig, guard: G)
+        where S: Service<Request = Self::ServiceRequest,
+                         Response = Self::ServiceResponse,
+                         Error = Self::ServiceError> + 'static,
+              G: 'static,
+    {
+        BindServer::<StreamingPipeline<MyStream<io::Error>>, T>::bind_server_guarded(
+            LiftProto::from_ref(self), handle, io, LiftService(service), config, guard
+        )
+    }
 }
 
 impl<T, P> streaming::pipeline::ServerProto<T> for LiftProto<P> where
@@ -80,10 +164,30 @@ impl<T, P> streaming::pipeline::ServerProto<T> for LiftProto<P> where
     type Error = io::Error;
 
     type Transport = LiftTransport<P::Transport, io::Error>;
-    type BindTransport = LiftBind<T, <P::BindTransport as IntoFuture>::Future, io::Error>;
+    type BindTransport = LiftBind<T, BindHandshake<<P::BindTransport as IntoFuture>::Future,
+                                                    P::Transport>, io::Error>;
 
     fn bind_transport(&self, io: T) -> Self::BindTransport {
-        LiftBind::lift(ServerProto::bind_transport(self.lower(), io).into_future())
+        let handshake = <P as ServerProto<T>>::handshake as fn(_) -> _;
+        LiftBind::lift(ServerProto::bind_transport(self.lower(), io).into_future().and_then(handshake))
+    }
+
+    fn config(&self) -> PipelineConfig {
+        ServerProto::config(self.lower())
+    }
+
+    fn answer_inline(transport: &mut Self::Transport, request: &P::Request)
+                     -> Option<P::Response>
+    {
+        P::answer_inline(&mut transport.0, request)
+    }
+
+    fn closes_connection(response: &P::Response) -> bool {
+        P::closes_connection(response)
+    }
+
+    fn committer(&self) -> Option<Box<Committer<P::Response>>> {
+        ServerProto::committer(self.lower())
     }
 }
 
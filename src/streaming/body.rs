@@ -1,11 +1,72 @@
 use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use futures::{Async, Poll, Stream};
-use futures::sync::mpsc;
+use futures::{Async, Future, IntoFuture, Poll, Sink, StartSend, Stream};
+use futures::sync::{mpsc, oneshot};
+use futures::task::{self, Task};
 
 /// Body stream
 pub struct Body<T, E> {
     inner: Inner<T, E>,
+    control: Option<BodyControl>,
+    complete: Option<Complete>,
+    progress: Option<Box<FnMut(&T) + Send>>,
+}
+
+/// The producing end of a body stream, created by `Body::channel`.
+///
+/// Chunks are handed to the body one at a time: once a chunk has been sent,
+/// `poll_ready` is not ready until the consumer of the body, e.g. the
+/// dispatcher writing it to a transport, has taken it. Producers that check
+/// `poll_ready` before generating the next chunk are thereby throttled to the
+/// pace of the consumer, with no chunks buffered on the way. Likewise, as a
+/// `Sink`, sending a chunk completes once the chunk has been taken.
+pub struct BodySender<T, E> {
+    tx: mpsc::Sender<Result<T, E>>,
+}
+
+/// A body stream pulling its chunks from a producer with explicit fetch
+/// calls, such as a database cursor.
+///
+/// Where a `BodySender` is pushed chunks at the pace of the consumer, the
+/// producer of a `FetchBody` is asked for them: each time the consumer of the
+/// body, e.g. the dispatcher writing out a response, is ready for another
+/// chunk, `fetch` is called and the future it returns resolves to the chunk,
+/// or to `None` at the end of the body. Nothing is fetched ahead of the
+/// consumer, so a throttled connection leaves the producer idle.
+pub struct FetchBody<F, R: IntoFuture> {
+    fetch: F,
+    // The fetch in progress, if any
+    pending: Option<R::Future>,
+    done: bool,
+}
+
+/// A future resolving once a body has been fully written out.
+///
+/// Returned by `Body::completion`. Fails if the body is dropped before its
+/// end was reached, e.g. because the connection failed.
+pub struct BodyComplete {
+    inner: oneshot::Receiver<()>,
+}
+
+struct Complete {
+    tx: Option<oneshot::Sender<()>>,
+    // True once the end of the body has been polled
+    ended: bool,
+}
+
+/// Pause state shared between a `Body` and the dispatcher feeding it.
+#[derive(Clone)]
+pub struct BodyControl {
+    inner: Arc<Control>,
+}
+
+struct Control {
+    paused: AtomicBool,
+    // Dispatcher task to notify when the body is paused or resumed
+    task: Mutex<Option<Task>>,
 }
 
 enum Inner<T, E> {
@@ -17,15 +78,197 @@ enum Inner<T, E> {
 impl<T, E> Body<T, E> {
     /// Return an empty body stream
     pub fn empty() -> Body<T, E> {
-        Body { inner: Inner::Empty }
+        Body { inner: Inner::Empty, control: None, complete: None, progress: None }
     }
 
     /// Return a body stream with an associated sender half
     pub fn pair() -> (mpsc::Sender<Result<T, E>>, Body<T, E>) {
         let (tx, rx) = mpsc::channel(0);
-        let rx = Body { inner: Inner::Stream(rx) };
+        let rx = Body::from(rx);
         (tx, rx)
     }
+
+    /// Return a body stream with an associated `BodySender`, for producers
+    /// implementing flow control.
+    pub fn channel() -> (BodySender<T, E>, Body<T, E>) {
+        let (tx, rx) = Body::pair();
+        (BodySender { tx: tx }, rx)
+    }
+
+    /// Return a body stream with an associated sender half and a handle
+    /// reporting whether the body has been paused.
+    pub fn pair_with_control() -> (mpsc::Sender<Result<T, E>>, Body<T, E>, BodyControl) {
+        let (tx, mut rx) = Body::pair();
+        let control = BodyControl::new();
+        rx.control = Some(control.clone());
+        (tx, rx, control)
+    }
+
+    /// Ask the producer of the body to temporarily stop sending chunks.
+    ///
+    /// Unlike plain backpressure, a paused body also tells the dispatcher to
+    /// stop granting the peer write credit for the exchange, without tearing
+    /// the exchange down. Has no effect on bodies that are not fed by a
+    /// dispatcher.
+    pub fn pause(&self) {
+        if let Some(ref control) = self.control {
+            control.set_paused(true);
+        }
+    }
+
+    /// Resume a body previously paused with `pause`.
+    pub fn resume(&self) {
+        if let Some(ref control) = self.control {
+            control.set_paused(false);
+        }
+    }
+
+    /// Returns true if the body is currently paused.
+    pub fn is_paused(&self) -> bool {
+        match self.control {
+            Some(ref control) => control.inner.paused.load(Ordering::SeqCst),
+            None => false,
+        }
+    }
+
+    /// Returns a future resolving once the body has been fully written out.
+    ///
+    /// When the body is sent as part of a message, the dispatcher holds on
+    /// to it until the final chunk has been flushed to the transport, so the
+    /// future resolves once the whole body has left the process, e.g. to
+    /// report upload progress. Transports don't report acknowledgements by
+    /// the peer, so whether the peer has consumed the body is not known.
+    ///
+    /// Only the future returned by the last call is resolved.
+    pub fn completion(&mut self) -> BodyComplete {
+        let (tx, rx) = oneshot::channel();
+        self.complete = Some(Complete { tx: Some(tx), ended: false });
+        BodyComplete { inner: rx }
+    }
+
+    /// Call `f` with every chunk the body yields.
+    ///
+    /// Tracks the progress of a transfer without wrapping the body, e.g. to
+    /// render a progress bar or to account for the bytes of an exchange: on
+    /// a body being sent, `f` sees the chunks as the dispatcher writes them,
+    /// on a body being received, as they are consumed. Replaces the callback
+    /// of any previous call.
+    pub fn on_chunk<F>(&mut self, f: F)
+        where F: FnMut(&T) + Send + 'static,
+    {
+        self.progress = Some(Box::new(f));
+    }
+}
+
+impl<T, E> BodySender<T, E> {
+    /// Returns `Ready` once the body can take the next chunk.
+    ///
+    /// While the previous chunk has not been taken, the current task is
+    /// notified once it is. Fails once the body has been dropped.
+    pub fn poll_ready(&mut self) -> Poll<(), io::Error> {
+        self.tx.poll_ready().map_err(|_| body_dropped())
+    }
+
+    /// Returns true once the body has been dropped, e.g. because the
+    /// exchange was canceled.
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+}
+
+impl<T, E> Sink for BodySender<T, E> {
+    type SinkItem = Result<T, E>;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, chunk: Result<T, E>) -> StartSend<Result<T, E>, io::Error> {
+        self.tx.start_send(chunk).map_err(|_| body_dropped())
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        self.tx.poll_complete().map_err(|_| body_dropped())
+    }
+}
+
+impl<F, R, T> FetchBody<F, R>
+    where F: FnMut() -> R,
+          R: IntoFuture<Item = Option<T>>,
+{
+    /// Returns a body fetching its chunks by calling `fetch`, one chunk at a
+    /// time.
+    pub fn new(fetch: F) -> FetchBody<F, R> {
+        FetchBody {
+            fetch: fetch,
+            pending: None,
+            done: false,
+        }
+    }
+}
+
+impl<F, R, T> Stream for FetchBody<F, R>
+    where F: FnMut() -> R,
+          R: IntoFuture<Item = Option<T>>,
+{
+    type Item = T;
+    type Error = R::Error;
+
+    fn poll(&mut self) -> Poll<Option<T>, R::Error> {
+        if self.done {
+            return Ok(Async::Ready(None));
+        }
+
+        if self.pending.is_none() {
+            self.pending = Some((self.fetch)().into_future());
+        }
+
+        let res = self.pending.as_mut().unwrap().poll();
+
+        match res {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(None)) | Err(_) => self.done = true,
+            Ok(Async::Ready(Some(_))) => {}
+        }
+
+        self.pending = None;
+        res
+    }
+}
+
+impl<F, R: IntoFuture> fmt::Debug for FetchBody<F, R> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "FetchBody {{ pending: {}, done: {} }}", self.pending.is_some(), self.done)
+    }
+}
+
+fn body_dropped() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "body dropped")
+}
+
+impl BodyControl {
+    fn new() -> BodyControl {
+        BodyControl {
+            inner: Arc::new(Control {
+                paused: AtomicBool::new(false),
+                task: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Returns true if the body is paused. Either way, the current task is
+    /// notified once the body is paused or resumed.
+    pub fn poll_paused(&self) -> bool {
+        *self.inner.task.lock().unwrap() = Some(task::park());
+
+        // Checked once the task was stored, so that no change goes unnoticed
+        self.inner.paused.load(Ordering::SeqCst)
+    }
+
+    fn set_paused(&self, paused: bool) {
+        self.inner.paused.store(paused, Ordering::SeqCst);
+
+        if let Some(task) = self.inner.task.lock().unwrap().take() {
+            task.unpark();
+        }
+    }
 }
 
 impl<T, E> Stream for Body<T, E> {
@@ -33,6 +276,28 @@ impl<T, E> Stream for Body<T, E> {
     type Error = E;
 
     fn poll(&mut self) -> Poll<Option<T>, E> {
+        let res = self.poll_inner();
+
+        match res {
+            Ok(Async::Ready(Some(ref chunk))) => {
+                if let Some(ref mut progress) = self.progress {
+                    progress(chunk);
+                }
+            }
+            Ok(Async::Ready(None)) => {
+                if let Some(ref mut complete) = self.complete {
+                    complete.ended = true;
+                }
+            }
+            _ => {}
+        }
+
+        res
+    }
+}
+
+impl<T, E> Body<T, E> {
+    fn poll_inner(&mut self) -> Poll<Option<T>, E> {
         match self.inner {
             Inner::Once(ref mut val) => Ok(Async::Ready(val.take())),
             Inner::Stream(ref mut s) => {
@@ -48,15 +313,35 @@ impl<T, E> Stream for Body<T, E> {
     }
 }
 
+impl Drop for Complete {
+    fn drop(&mut self) {
+        // Dropping the sender without completing it fails the future
+        if self.ended {
+            self.tx.take().unwrap().complete(());
+        }
+    }
+}
+
+impl Future for BodyComplete {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        self.inner.poll().map_err(|_| {
+            io::Error::new(io::ErrorKind::BrokenPipe, "body dropped before being written out")
+        })
+    }
+}
+
 impl<T, E> From<mpsc::Receiver<Result<T, E>>> for Body<T, E> {
     fn from(src: mpsc::Receiver<Result<T, E>>) -> Body<T, E> {
-        Body { inner: Inner::Stream(src) }
+        Body { inner: Inner::Stream(src), control: None, complete: None, progress: None }
     }
 }
 
 impl<T, E> From<T> for Body<T, E> {
     fn from(val: T) -> Body<T, E> {
-        Body { inner: Inner::Once(Some(val)) }
+        Body { inner: Inner::Once(Some(val)), control: None, complete: None, progress: None }
     }
 }
 
@@ -65,3 +350,9 @@ impl<T, E> fmt::Debug for Body<T, E> {
         write!(fmt, "Body {{ [stream of values] }}")
     }
 }
+
+impl<T, E> fmt::Debug for BodySender<T, E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "BodySender {{ closed: {} }}", self.is_closed())
+    }
+}
@@ -0,0 +1,75 @@
+use std::cmp;
+
+use futures::task;
+
+/// Applies the cap of the bind config on top of the limit of the protocol's
+/// config, the lower of the two winning.
+pub fn capped(limit: Option<usize>, max: Option<usize>) -> Option<usize> {
+    match (limit, max) {
+        (Some(limit), Some(max)) => Some(cmp::min(limit, max)),
+        (limit, max) => limit.or(max),
+    }
+}
+
+/// Bounds the number of frames a dispatcher processes in a single poll.
+///
+/// Once the budget is spent, the dispatcher stops reading and writing frames
+/// and yields, re-scheduling itself so that the remaining work is picked up
+/// after the other tasks on the reactor had their turn.
+pub struct Budget {
+    limit: Option<usize>,
+    remaining: usize,
+    exhausted: bool,
+}
+
+impl Budget {
+    pub fn new(limit: Option<usize>) -> Budget {
+        assert!(limit != Some(0), "per poll frame budget must be positive");
+
+        Budget {
+            limit: limit,
+            remaining: limit.unwrap_or(0),
+            exhausted: false,
+        }
+    }
+
+    /// Refills the budget, called at the start of every poll
+    pub fn reset(&mut self) {
+        self.remaining = self.limit.unwrap_or(0);
+        self.exhausted = false;
+    }
+
+    /// Returns true if another frame may be processed in this poll
+    pub fn has_remaining(&mut self) -> bool {
+        if self.limit.is_none() {
+            return true;
+        }
+
+        if self.remaining == 0 {
+            self.exhausted = true;
+        }
+
+        !self.exhausted
+    }
+
+    /// Accounts for a processed frame
+    pub fn spend(&mut self) {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+        }
+    }
+
+    /// Returns true if the poll stopped short because of the budget
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted
+    }
+
+    /// Re-schedules the current task if the budget ran out, as nothing else
+    /// is going to wake it up for the work left behind.
+    pub fn yield_if_exhausted(&self) {
+        if self.exhausted {
+            trace!("per poll frame budget exhausted; yielding");
+            task::park().unpark();
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::fmt;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+/// Source of the buffers body chunks are decoded into.
+///
+/// Every dispatcher hands a provider to its transport through the
+/// `on_buffer_provider` hook. Instead of allocating a fresh `Vec` per body
+/// chunk, a codec takes a buffer from the provider, decodes the payload into
+/// it and wraps it in a `PooledChunk`. Once the service drops the chunk, the
+/// buffer goes back to the provider and is handed out again for a later
+/// chunk.
+///
+/// The dispatchers supply a `BufferPool` sized by the `max_pooled_buffers`
+/// field of their configuration.
+pub trait BufferProvider: Send + Sync {
+    /// Returns an empty buffer able to hold at least `capacity` bytes.
+    fn take(&self, capacity: usize) -> Vec<u8>;
+
+    /// Takes back a buffer whose chunk has been dropped.
+    fn recycle(&self, buf: Vec<u8>);
+}
+
+/// A `BufferProvider` keeping a bounded number of buffers for reuse.
+///
+/// Buffers recycled while the pool is full are freed.
+pub struct BufferPool {
+    max_buffers: usize,
+    buffers: Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    /// Create a pool keeping up to `max_buffers` buffers for reuse.
+    pub fn new(max_buffers: usize) -> BufferPool {
+        BufferPool {
+            max_buffers: max_buffers,
+            buffers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the number of buffers waiting to be reused.
+    pub fn pooled(&self) -> usize {
+        self.buffers.lock().unwrap().len()
+    }
+}
+
+impl BufferProvider for BufferPool {
+    fn take(&self, capacity: usize) -> Vec<u8> {
+        let mut buf = self.buffers.lock().unwrap().pop().unwrap_or_default();
+        buf.reserve(capacity);
+        buf
+    }
+
+    fn recycle(&self, mut buf: Vec<u8>) {
+        let mut buffers = self.buffers.lock().unwrap();
+
+        if buffers.len() < self.max_buffers {
+            buf.clear();
+            buffers.push(buf);
+        }
+    }
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("BufferPool")
+            .field("max_buffers", &self.max_buffers)
+            .field("pooled", &self.pooled())
+            .finish()
+    }
+}
+
+/// A body chunk whose buffer returns to its `BufferProvider` once dropped.
+pub struct PooledChunk {
+    buf: Vec<u8>,
+    provider: Option<Arc<BufferProvider>>,
+}
+
+impl PooledChunk {
+    /// Wraps `buf`, taken from `provider`, as a chunk.
+    pub fn new(buf: Vec<u8>, provider: Arc<BufferProvider>) -> PooledChunk {
+        PooledChunk {
+            buf: buf,
+            provider: Some(provider),
+        }
+    }
+
+    /// Copies `data` into a buffer taken from `provider`.
+    pub fn copy_from(data: &[u8], provider: Arc<BufferProvider>) -> PooledChunk {
+        let mut buf = provider.take(data.len());
+        buf.extend_from_slice(data);
+        PooledChunk::new(buf, provider)
+    }
+
+    /// Consumes the chunk, returning its buffer instead of recycling it.
+    pub fn into_vec(mut self) -> Vec<u8> {
+        self.provider = None;
+        mem::take(&mut self.buf)
+    }
+}
+
+impl Deref for PooledChunk {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledChunk {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buf
+    }
+}
+
+impl AsRef<[u8]> for PooledChunk {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl fmt::Debug for PooledChunk {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_tuple("PooledChunk").field(&self.buf).finish()
+    }
+}
+
+impl Drop for PooledChunk {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take() {
+            provider.recycle(mem::take(&mut self.buf));
+        }
+    }
+}
@@ -0,0 +1,60 @@
+#[cfg(feature = "bytes")]
+use bytes::Bytes;
+use tokio_core::io::EasyBuf;
+
+/// Body chunks of bytes which can be split.
+///
+/// Frames carrying chunks that implement `Chunk` get a `split_chunk` method,
+/// which transports can implement `split_body` with. `EasyBuf` chunks, e.g.
+/// as decoded by `StreamingCodec`, and `Bytes` chunks with the `bytes`
+/// feature enabled are split without copying: both halves keep pointing at
+/// the buffer the chunk was read into.
+pub trait Chunk: Sized {
+    /// Returns the number of bytes in the chunk.
+    fn len(&self) -> usize;
+
+    /// Returns true if the chunk holds no bytes.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Splits the chunk in two at `at`, keeping the bytes before it and
+    /// returning the rest.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `at > len`.
+    fn split_off(&mut self, at: usize) -> Self;
+}
+
+impl Chunk for EasyBuf {
+    fn len(&self) -> usize {
+        EasyBuf::len(self)
+    }
+
+    fn split_off(&mut self, at: usize) -> EasyBuf {
+        EasyBuf::split_off(self, at)
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl Chunk for Bytes {
+    fn len(&self) -> usize {
+        Bytes::len(self)
+    }
+
+    fn split_off(&mut self, at: usize) -> Bytes {
+        Bytes::split_off(self, at)
+    }
+}
+
+/// Copies the bytes after `at` into a new vector
+impl Chunk for Vec<u8> {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn split_off(&mut self, at: usize) -> Vec<u8> {
+        Vec::split_off(self, at)
+    }
+}
@@ -0,0 +1,71 @@
+use std::io;
+use std::time::Duration;
+
+use futures::{Async, Future, Poll};
+use tokio_core::reactor::{Handle, Timeout};
+
+/// Future confirming a response may be written, see `Committer`.
+pub type CommitFuture = Box<Future<Item = (), Error = io::Error>>;
+
+/// `Committer` holds back the responses of a server until they are
+/// confirmed, e.g. once the write-ahead log entry a response acknowledges
+/// has been synced to disk.
+///
+/// A committer is created per connection by the `committer` method of the
+/// server protocol traits. The dispatcher holds a response until its commit
+/// future resolves, then writes it. A failed commit, or one taking longer
+/// than the `commit_timeout` of the connection config, fails the request
+/// with that error instead, so the client is never told about a change that
+/// may not be durable.
+pub trait Committer<R>: 'static {
+    /// Returns the future confirming `response` may be written, or `None`
+    /// to write it right away.
+    fn commit(&mut self, response: &R) -> Option<CommitFuture>;
+}
+
+impl<R, F> Committer<R> for F
+    where F: FnMut(&R) -> Option<CommitFuture> + 'static
+{
+    fn commit(&mut self, response: &R) -> Option<CommitFuture> {
+        self(response)
+    }
+}
+
+/// A commit in progress, bounded by the commit timeout
+pub struct Commit {
+    future: CommitFuture,
+    timeout: Option<Timeout>,
+}
+
+impl Commit {
+    pub fn new(future: CommitFuture, timeout: Option<Duration>, handle: &Handle) -> io::Result<Commit> {
+        let timeout = match timeout {
+            Some(dur) => Some(try!(Timeout::new(dur, handle))),
+            None => None,
+        };
+
+        Ok(Commit {
+            future: future,
+            timeout: timeout,
+        })
+    }
+}
+
+impl Future for Commit {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        if let Async::Ready(()) = try!(self.future.poll()) {
+            return Ok(Async::Ready(()));
+        }
+
+        if let Some(ref mut timeout) = self.timeout {
+            if let Async::Ready(()) = try!(timeout.poll()) {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "response commit timed out"));
+            }
+        }
+
+        Ok(Async::NotReady)
+    }
+}
@@ -0,0 +1,48 @@
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Identifies a connection driven by a dispatcher.
+///
+/// Every dispatcher draws a fresh id when it is created and hands it to its
+/// transport through the `on_connection` hook. Errors originating in the
+/// dispatcher mention it, as well as the request id when one is known, so
+/// that logs written by the transport, the dispatcher and the service can be
+/// correlated.
+///
+/// Ids are allocated from a process-wide counter, so they increase
+/// monotonically across the connections of a server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnectionId(u64);
+
+impl ConnectionId {
+    /// Allocates the next connection id.
+    pub fn next() -> ConnectionId {
+        ConnectionId(NEXT_ID.fetch_add(1, Ordering::Relaxed) as u64)
+    }
+
+    /// Returns the id as an integer.
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.0)
+    }
+}
+
+/// Tags an error originating in the dispatcher of connection `conn`, keeping
+/// its kind.
+pub fn annotate(conn: ConnectionId, err: io::Error) -> io::Error {
+    io::Error::new(err.kind(), format!("{}; conn={}", err, conn))
+}
+
+/// Tags an error originating in the dispatcher of connection `conn` with the
+/// id of the request it concerns, keeping its kind.
+pub fn annotate_request<Id: fmt::Debug>(conn: ConnectionId, id: &Id, err: io::Error) -> io::Error {
+    io::Error::new(err.kind(), format!("{}; conn={}, id={:?}", err, conn, id))
+}
@@ -0,0 +1,98 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use futures::task::{self, Task};
+
+/// Where a connection driven by a pipeline or multiplex dispatcher is at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// The dispatcher has not completed yet.
+    Running,
+    /// The dispatcher completed, the connection was shut down cleanly.
+    Done,
+    /// The dispatcher failed with an error of the given kind.
+    Failed(io::ErrorKind),
+}
+
+/// Handle to a connection driven by an `advanced::Pipeline` or
+/// `advanced::Multiplex` dispatcher, see their `control` method.
+///
+/// Lets connection managers close a connection from outside of the task
+/// polling it, and find out how it ended. Handles can be cloned and sent to
+/// other threads.
+#[derive(Clone)]
+pub struct ConnectionControl {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    close: AtomicBool,
+    state: Mutex<ConnectionState>,
+    task: Mutex<Option<Task>>,
+}
+
+impl ConnectionControl {
+    /// Close the connection gracefully.
+    ///
+    /// The dispatcher stops reading frames and completes once the exchanges
+    /// in flight are done, as if the peer had closed its end.
+    pub fn close(&self) {
+        self.inner.close.store(true, Ordering::SeqCst);
+
+        if let Some(task) = self.inner.task.lock().unwrap().take() {
+            task.unpark();
+        }
+    }
+
+    /// Returns the state of the connection.
+    pub fn state(&self) -> ConnectionState {
+        *self.inner.state.lock().unwrap()
+    }
+}
+
+/// The dispatcher end of the `ConnectionControl` handles of a connection
+pub struct Controlled {
+    inner: Arc<Inner>,
+}
+
+impl Controlled {
+    pub fn new() -> Controlled {
+        Controlled {
+            inner: Arc::new(Inner {
+                close: AtomicBool::new(false),
+                state: Mutex::new(ConnectionState::Running),
+                task: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Returns a new handle to the connection
+    pub fn handle(&self) -> ConnectionControl {
+        ConnectionControl { inner: self.inner.clone() }
+    }
+
+    /// Returns true if the connection was closed through a handle. Otherwise
+    /// the current task is notified once it is.
+    pub fn poll_close(&self) -> bool {
+        if self.inner.close.load(Ordering::SeqCst) {
+            return true;
+        }
+
+        *self.inner.task.lock().unwrap() = Some(task::park());
+
+        // Check again in case the connection was closed before the task was
+        // stored
+        self.inner.close.load(Ordering::SeqCst)
+    }
+
+    /// Returns the state of the connection
+    pub fn state(&self) -> ConnectionState {
+        *self.inner.state.lock().unwrap()
+    }
+
+    /// Records how the dispatcher ended
+    pub fn terminate(&self, state: ConnectionState) {
+        *self.inner.state.lock().unwrap() = state;
+    }
+}
@@ -0,0 +1,95 @@
+use std::slice;
+
+/// Content encodings supported by one end of a connection, most preferred
+/// first.
+///
+/// Streaming protocols advertise them through the `encodings` method of
+/// their `ClientProto` or `ServerProto`. Encodings are identified by name,
+/// e.g. `"gzip"`; what a name stands for is up to the protocol.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Encodings {
+    names: Vec<String>,
+}
+
+impl Encodings {
+    /// Creates an empty list, leaving connections unencoded.
+    pub fn new() -> Encodings {
+        Encodings { names: Vec::new() }
+    }
+
+    /// Appends `name`, preferred less than the encodings already listed.
+    pub fn with<S: Into<String>>(mut self, name: S) -> Encodings {
+        self.names.push(name.into());
+        self
+    }
+
+    /// Returns true if no encoding is listed.
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Returns true if `name` is listed.
+    pub fn contains(&self, name: &str) -> bool {
+        self.names.iter().any(|n| n == name)
+    }
+
+    /// Iterates over the names of the encodings, most preferred first.
+    pub fn iter<'a>(&'a self) -> slice::Iter<'a, String> {
+        self.names.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Encodings {
+    type Item = &'a String;
+    type IntoIter = slice::Iter<'a, String>;
+
+    fn into_iter(self) -> slice::Iter<'a, String> {
+        self.iter()
+    }
+}
+
+/// The content encoding negotiation of a connection.
+///
+/// Handed to the dispatcher by `Dispatch::negotiation`. The dispatcher gives
+/// the transport the encodings of this end to advertise through its
+/// `advertise_encodings` hook, polls `peer_encodings` until the peer's
+/// advertisement was read, and records the agreed encoding with
+/// `on_encoding`.
+///
+/// Both ends agree on the same encoding without another round trip: the
+/// encoding the server prefers most among those the client supports, or
+/// none if they have none in common.
+#[derive(Debug, Clone)]
+pub struct Negotiation {
+    server: bool,
+    ours: Encodings,
+}
+
+impl Negotiation {
+    /// Negotiation for the client end of a connection.
+    pub fn client(ours: Encodings) -> Negotiation {
+        Negotiation { server: false, ours: ours }
+    }
+
+    /// Negotiation for the server end of a connection.
+    pub fn server(ours: Encodings) -> Negotiation {
+        Negotiation { server: true, ours: ours }
+    }
+
+    /// Returns the encodings this end advertises.
+    pub fn encodings(&self) -> &Encodings {
+        &self.ours
+    }
+
+    /// Returns the encoding agreed on given the encodings the peer
+    /// advertised, or `None` to leave the connection unencoded.
+    pub fn agree(&self, peer: &Encodings) -> Option<String> {
+        let (server, client) = if self.server {
+            (&self.ours, peer)
+        } else {
+            (peer, &self.ours)
+        };
+
+        server.iter().find(|name| client.contains(name)).cloned()
+    }
+}
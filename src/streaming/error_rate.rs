@@ -0,0 +1,53 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Bounds the number of error frames a dispatcher writes per time window.
+///
+/// A peer flooding a connection with malformed requests would otherwise get
+/// an error frame back for each of them. Once the limit is exceeded, the
+/// connection is failed instead, closing it.
+pub struct ErrorRate {
+    limit: Option<usize>,
+    window: Duration,
+    window_start: Option<Instant>,
+    count: usize,
+}
+
+impl ErrorRate {
+    pub fn new(limit: Option<usize>, window: Duration) -> ErrorRate {
+        ErrorRate {
+            limit: limit,
+            window: window,
+            window_start: None,
+            count: 0,
+        }
+    }
+
+    /// Accounts for an error frame about to be written, failing once there
+    /// are too many of them in the current window.
+    pub fn check(&mut self) -> io::Result<()> {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+
+        let now = Instant::now();
+
+        match self.window_start {
+            Some(start) if now.duration_since(start) < self.window => {}
+            _ => {
+                self.window_start = Some(now);
+                self.count = 0;
+            }
+        }
+
+        if self.count == limit {
+            debug!("too many error frames; limit={}", limit);
+            return Err(io::Error::new(io::ErrorKind::Other,
+                                      "too many error frames, closing connection"));
+        }
+
+        self.count += 1;
+        Ok(())
+    }
+}
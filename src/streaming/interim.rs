@@ -0,0 +1,77 @@
+use std::io;
+
+use futures::{Async, Stream};
+use futures::sync::mpsc;
+
+/// Sends interim responses to a request ahead of its final response, e.g.
+/// resource hints or progress updates.
+///
+/// Handed to the `on_interim` hook of the streaming server protocol traits
+/// for every request dispatched to the service. Interim responses are
+/// written as `Progress` frames, which clients surface on the `progress`
+/// stream of the response instead of mistaking them for the response.
+/// Those sent once the final response has been written are discarded.
+///
+/// Handles can be cloned and sent to other threads.
+pub struct Interim<T> {
+    tx: mpsc::UnboundedSender<(u64, T)>,
+    key: u64,
+}
+
+impl<T> Interim<T> {
+    /// Queue `message` to be written ahead of the final response.
+    ///
+    /// Fails once the connection has been closed.
+    pub fn send(&self, message: T) -> io::Result<()> {
+        self.tx.unbounded_send((self.key, message)).map_err(|_| {
+            io::Error::new(io::ErrorKind::BrokenPipe, "connection closed")
+        })
+    }
+}
+
+impl<T> Clone for Interim<T> {
+    fn clone(&self) -> Interim<T> {
+        Interim {
+            tx: self.tx.clone(),
+            key: self.key,
+        }
+    }
+}
+
+/// The interim responses of the requests of a connection, as received by
+/// its dispatcher
+pub struct Interims<T> {
+    tx: mpsc::UnboundedSender<(u64, T)>,
+    rx: mpsc::UnboundedReceiver<(u64, T)>,
+    next_key: u64,
+}
+
+impl<T> Interims<T> {
+    pub fn new() -> Interims<T> {
+        let (tx, rx) = mpsc::unbounded();
+
+        Interims {
+            tx: tx,
+            rx: rx,
+            next_key: 0,
+        }
+    }
+
+    /// Returns the handle of a new request, along with the key its interim
+    /// responses are received with
+    pub fn handle(&mut self) -> (u64, Interim<T>) {
+        let key = self.next_key;
+        self.next_key += 1;
+
+        (key, Interim { tx: self.tx.clone(), key: key })
+    }
+
+    /// Returns the next interim response received, if any
+    pub fn poll(&mut self) -> Option<(u64, T)> {
+        // The dispatcher keeps a sender, the stream never ends
+        match self.rx.poll() {
+            Ok(Async::Ready(next)) => next,
+            _ => None,
+        }
+    }
+}
@@ -9,7 +9,43 @@ pub mod pipeline;
 pub mod multiplex;
 
 mod body;
-pub use self::body::Body;
+pub use self::body::{Body, BodyComplete, BodyControl, BodySender, FetchBody};
+
+mod buffers;
+pub use self::buffers::{BufferProvider, BufferPool, PooledChunk};
+
+mod budget;
+
+mod chunk;
+pub use self::chunk::Chunk;
+
+mod commit;
+pub use self::commit::{CommitFuture, Committer};
+
+mod control;
+pub use self::control::{ConnectionControl, ConnectionState};
+
+mod conn_id;
+pub use self::conn_id::ConnectionId;
+
+mod encoding;
+pub use self::encoding::{Encodings, Negotiation};
+
+mod error_rate;
+
+mod interim;
+pub use self::interim::Interim;
 
 mod message;
 pub use self::message::Message;
+
+mod observe;
+
+mod profile;
+pub use self::profile::{BufferProfile, ParseBufferProfileError};
+
+mod stats;
+pub use self::stats::Stats;
+
+mod view;
+pub use self::view::{SimpleView, SimpleViewFuture, StreamingView, StreamingViewFuture};
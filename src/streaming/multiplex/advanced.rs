@@ -4,16 +4,33 @@
 //! Server}` instead. But for some advanced protocols in which the client and
 //! servers have more of a peer relationship, it's useful to work directly with
 //! these implementation details.
-
-use streaming::{Message, Body};
+//!
+//! `Multiplex` can also be used on its own, e.g. by connection managers: it
+//! is a `Future` driving a `Dispatch` which owns the transport, and needs no
+//! `Handle`, so it can be polled on any executor. Its `ConnectionControl`
+//! closes the connection from elsewhere and tells how it ended, and
+//! `into_inner` gives the dispatch back once done.
+
+use streaming::{Message, Body, BodyControl, BufferPool, ConnectionControl, ConnectionState,
+                Negotiation, Stats};
+use streaming::budget::Budget;
+use streaming::conn_id::{self, ConnectionId};
+use streaming::control::Controlled;
+use streaming::error_rate::ErrorRate;
+use streaming::observe::{InFlight, Observe};
 use futures::sync::mpsc;
 use futures::{Future, Poll, Async, Stream, Sink, AsyncSink, StartSend};
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, VecDeque};
 use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use super::frame_buf::{FrameBuf, FrameDeque};
-use super::{Frame, RequestId, Transport};
+use super::{Frame, RequestId, StreamingMultiplex, Transport, MultiplexConfig, SpillPolicy};
+use super::violation;
 use buffer_one::BufferOne;
+use instrument::ConnectionObserver;
+use ProtocolKind;
 
 /*
  * TODO:
@@ -29,18 +46,15 @@ use buffer_one::BufferOne;
  *
  */
 
-/// The max number of buffered frames that the connection can support. Once
-/// this number is reached.
-///
-/// See module docs for more detail
-const MAX_BUFFERED_FRAMES: usize = 128;
-
 /// Task that drives multiplexed protocols
 ///
 /// Provides protocol multiplexing functionality in a generic way over clients
 /// and servers. Used internally by `multiplex::Client` and
-/// `multiplex::Server`.
+/// `multiplex::Server`, or standalone, see the module docs.
 pub struct Multiplex<T> where T: Dispatch {
+    // Identifies the connection in errors and to the transport
+    id: ConnectionId,
+
     // True as long as the connection has more request frames to read.
     run: bool,
 
@@ -62,18 +76,58 @@ pub struct Multiplex<T> where T: Dispatch {
     // True when the transport is fully flushed
     is_flushed: bool,
 
+    // True once the dispatch has no more in messages to write
+    in_done: bool,
+
+    // True once the write half of the transport has been shut down
+    is_write_shutdown: bool,
+
+    // Set when a flush could not complete immediately
+    flush_started: Option<Instant>,
+
+    // Latency of the last completed flush, reported to the transport
+    flush_latency: Option<Duration>,
+
     // RequestIds of exchanges that have not yet been dispatched
     dispatch_deque: VecDeque<T::RequestId>,
 
     // Storage for buffered frames
     frame_buf: FrameBuf<Option<Result<T::BodyOut, T::Error>>>,
 
+    // What to do once the frame buffer is full
+    spill_policy: SpillPolicy,
+
+    // Whether violations by the peer close the connection instead of
+    // panicking
+    strict: bool,
+
     // Temporary storage for RequestIds...
     scratch: Vec<T::RequestId>,
+
+    // Fully written bodies, dropped once the transport has been flushed so
+    // that their completion is only signaled after the final chunk left
+    flushed_bodies: Vec<T::Stream>,
+
+    // Frames left to process before yielding to other tasks
+    budget: Budget,
+    // Error frames written recently
+    error_rate: ErrorRate,
+    // Longest message read before the connection fails
+    max_message_len: Option<usize>,
+
+    // Set until the content encoding of the connection is agreed on
+    negotiation: Option<Negotiation>,
+
+    observe: Observe,
+
+    // Closes the connection on request of its handles, told how it ended
+    control: Controlled,
 }
 
 struct DispatchSink<T> {
     inner: T,
+    // Reports the frames accepted by the transport
+    observe: Observe,
 }
 
 type BodySender<B, E> = mpsc::Sender<Result<B, E>>;
@@ -97,9 +151,19 @@ struct Exchange<T: Dispatch> {
     // The outbound body stream sender
     out_body: Option<BodySender<T::BodyOut, T::Error>>,
 
+    // Tracks whether the receiving end paused the outbound body
+    out_control: Option<BodyControl>,
+
+    // True when the transport has been told the outbound body is paused
+    out_paused: bool,
+
     // Buffers outbound body chunks until the sender is ready
     out_deque: FrameDeque<Option<Result<T::BodyOut, T::Error>>>,
 
+    // Fails the outbound body once the sender is ready, set when the frame
+    // buffer overflowed. Further chunks are discarded.
+    out_error: Option<T::Error>,
+
     // Tracks if the sender is ready. This value is computed on each tick when
     // the senders are flushed and before new frames are read.
     //
@@ -111,8 +175,17 @@ struct Exchange<T: Dispatch> {
 
     // The inbound body stream receiver
     in_body: Option<T::Stream>,
+
+    // Polled from `in_body` but not merged into the previous body frame
+    in_body_next: Option<Result<Option<T::BodyIn>, T::Error>>,
+
+    // Reports the exchange completed once dropped
+    _in_flight: Option<InFlight>,
 }
 
+// Returned when an outbound body chunk doesn't fit the frame buffer
+struct Spilled;
+
 enum Request<T: Dispatch> {
     In, // TODO: Handle inbound message buffering?
     Out(Option<Message<T::Out, Body<T::BodyOut, T::Error>>>),
@@ -180,6 +253,98 @@ pub trait Dispatch {
 
     /// Cancel interest in the exchange identified by RequestId
     fn cancel(&mut self, request_id: Self::RequestId) -> io::Result<()>;
+
+    /// Poll for the exchanges the dispatch lost interest in since the last
+    /// call, for example because the caller dropped the response future.
+    ///
+    /// The multiplexer stops writing the request bodies of the exchanges and
+    /// cancels them on the transport. A response arriving later is still
+    /// passed to `dispatch`. By default nothing is ever canceled.
+    fn poll_canceled(&mut self) -> Vec<Self::RequestId> {
+        Vec::new()
+    }
+
+    /// Cancel an exchange returned by `poll_canceled` on the transport.
+    ///
+    /// Defaults to `Transport::cancel`.
+    fn cancel_transport(&mut self, request_id: Self::RequestId) -> io::Result<()> {
+        self.transport().cancel(request_id)
+    }
+
+    /// Process an `Ack` frame read from the transport, acknowledging the
+    /// exchange identified by RequestId ahead of its response.
+    ///
+    /// By default acks are not expected and fail the connection.
+    fn ack(&mut self, request_id: Self::RequestId) -> io::Result<()> {
+        let _ = request_id;
+        Err(io::Error::new(io::ErrorKind::Other, "unexpected ack frame"))
+    }
+
+    /// Process a `Progress` frame read from the transport, an interim
+    /// response to the exchange identified by RequestId.
+    ///
+    /// By default progress updates are not expected and fail the connection.
+    fn progress(&mut self, request_id: Self::RequestId, message: Self::Out) -> io::Result<()> {
+        let _ = (request_id, message);
+        Err(io::Error::new(io::ErrorKind::Other, "unexpected progress frame"))
+    }
+
+    /// Poll for an exchange to acknowledge to the peer with an `Ack` frame.
+    ///
+    /// Acks are written before any message polled afterwards, so a request
+    /// acknowledged here is acknowledged ahead of its response. By default
+    /// nothing is acknowledged.
+    fn poll_ack(&mut self) -> Option<Self::RequestId> {
+        None
+    }
+
+    /// Poll for an interim response to write as a `Progress` frame.
+    ///
+    /// Interim responses are written before any message polled afterwards,
+    /// so the exchange they belong to must still be in flight. By default
+    /// there are none.
+    fn poll_progress(&mut self) -> Option<(Self::RequestId, Self::In)> {
+        None
+    }
+
+    /// Content encoding negotiation of the connection, taken once when the
+    /// dispatcher is created.
+    ///
+    /// When it advertises any encoding, the dispatcher hands them to the
+    /// transport and records the agreed encoding once the peer's
+    /// advertisement was read. By default connections are left unencoded.
+    fn negotiation(&mut self) -> Option<Negotiation> {
+        None
+    }
+
+    /// Observer of the connection, taken once when the dispatcher is
+    /// created.
+    ///
+    /// The protocol traits of this crate return the observer of the
+    /// `BindConfig` the connection was bound with. Defaults to `None`,
+    /// leaving the connection unobserved.
+    fn observer(&self) -> Option<Arc<ConnectionObserver>> {
+        None
+    }
+
+    /// Returns true once the dispatch wants the connection closed, e.g.
+    /// after answering a request that ends the session.
+    ///
+    /// The dispatcher then stops reading, completes the exchanges still in
+    /// flight and closes the connection. Defaults to false.
+    fn is_closing(&self) -> bool {
+        false
+    }
+
+    /// Poll for the peer going away, returning the ids of the exchanges it
+    /// did not process.
+    ///
+    /// Called after reading frames. The multiplexer forgets the returned
+    /// exchanges, as their responses will never arrive; the dispatch is
+    /// expected to have failed them already. By default nothing is returned.
+    fn poll_going_away(&mut self) -> Vec<Self::RequestId> {
+        Vec::new()
+    }
 }
 
 /*
@@ -192,15 +357,48 @@ impl<T> Multiplex<T> where T: Dispatch {
     /// Create a new pipeline `Multiplex` dispatcher with the given service and
     /// transport
     pub fn new(dispatch: T) -> Multiplex<T> {
+        Multiplex::with_config(dispatch, &MultiplexConfig::default())
+    }
+
+    /// Create a new `Multiplex` dispatcher tuned by the given configuration
+    pub fn with_config(dispatch: T, config: &MultiplexConfig) -> Multiplex<T> {
+        let mut dispatch = dispatch;
+
+        let id = ConnectionId::next();
+        dispatch.transport().on_connection(id);
+
+        let buffers = BufferPool::new(config.max_pooled_buffers);
+        dispatch.transport().on_buffer_provider(Arc::new(buffers));
+
+        let negotiation = dispatch.negotiation().and_then(|negotiation| {
+            if negotiation.encodings().is_empty() {
+                return None;
+            }
+
+            dispatch.transport().advertise_encodings(negotiation.encodings());
+            Some(negotiation)
+        });
+
+        let observe = Observe::new(id, dispatch.observer());
+
         // Add `Sink` impl for `Dispatch`
-        let dispatch = DispatchSink { inner: dispatch };
+        let dispatch = DispatchSink {
+            inner: dispatch,
+            observe: observe.clone(),
+        };
 
         // Add a single slot buffer for the sink
         let dispatch = BufferOne::new(dispatch);
 
-        let frame_buf = FrameBuf::with_capacity(MAX_BUFFERED_FRAMES);
+        assert!(config.max_buffered_frames > 0 &&
+                config.max_buffered_frames_per_exchange != Some(0),
+                "frame buffer capacity must be positive");
+
+        let frame_buf = FrameBuf::with_deque_capacity(config.max_buffered_frames,
+                                                      config.max_buffered_frames_per_exchange);
 
         Multiplex {
+            id: id,
             run: true,
             made_progress: false,
             blocked_on_dispatch: false,
@@ -208,12 +406,57 @@ impl<T> Multiplex<T> where T: Dispatch {
             dispatch: dispatch,
             exchanges: HashMap::new(),
             is_flushed: true,
+            in_done: false,
+            is_write_shutdown: false,
+            flush_started: None,
+            flush_latency: None,
             dispatch_deque: VecDeque::new(),
             frame_buf: frame_buf,
+            spill_policy: config.spill_policy,
+            strict: config.strict,
             scratch: vec![],
+            flushed_bodies: vec![],
+            budget: Budget::new(config.max_frames_per_poll),
+            error_rate: ErrorRate::new(config.max_error_frames, config.error_frame_window),
+            max_message_len: config.max_message_len,
+            negotiation: negotiation,
+            observe: observe,
+            control: Controlled::new(),
         }
     }
 
+    /// Returns the id of the connection driven by this multiplexer
+    pub fn connection_id(&self) -> ConnectionId {
+        self.id
+    }
+
+    /// Returns a handle to close the connection and find out how it ended
+    pub fn control(&self) -> ConnectionControl {
+        self.control.handle()
+    }
+
+    /// Returns the state of the connection; once the dispatcher completed,
+    /// whether it did so cleanly
+    pub fn state(&self) -> ConnectionState {
+        self.control.state()
+    }
+
+    /// Returns a reference to the dispatch
+    pub fn get_ref(&self) -> &T {
+        &self.dispatch.get_ref().inner
+    }
+
+    /// Returns a mutable reference to the dispatch
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.dispatch.get_mut().inner
+    }
+
+    /// Consumes the dispatcher, returning the dispatch and with it the
+    /// transport, e.g. to reuse the connection once the dispatcher completed
+    pub fn into_inner(self) -> T {
+        self.dispatch.into_inner().inner
+    }
+
     /// Returns true if the multiplexer has nothing left to do
     fn is_done(&self) -> bool {
         !self.run && self.is_flushed && self.exchanges.len() == 0
@@ -235,12 +478,13 @@ impl<T> Multiplex<T> where T: Dispatch {
 
             if let Some(message) = exchange.take_buffered_out_request() {
                 let message = MultiplexMessage {
-                    id: id,
+                    id: id.clone(),
                     message: Ok(message),
                     solo: exchange.responded,
                 };
 
-                try!(self.dispatch.get_mut().inner.dispatch(message));
+                try!(self.dispatch.get_mut().inner.dispatch(message)
+                         .map_err(|e| conn_id::annotate_request(self.id, &id, e)));
             }
         }
 
@@ -258,7 +502,25 @@ impl<T> Multiplex<T> where T: Dispatch {
 
         for (id, exchange) in self.exchanges.iter_mut() {
             trace!("   --> request={:?}", id);
-            try!(exchange.flush_out_body());
+
+            if try!(exchange.flush_out_body()) {
+                // The body receiver went away, stop the peer from sending any
+                // more of the body.
+                trace!("   --> out body dropped; canceling; id={:?}", id);
+                try!(self.dispatch.get_mut().inner.transport().cancel(id.clone()));
+            }
+
+            match exchange.poll_pause_change() {
+                Some(true) => {
+                    trace!("   --> out body paused; id={:?}", id);
+                    self.dispatch.get_mut().inner.transport().pause_body(id.clone());
+                }
+                Some(false) => {
+                    trace!("   --> out body resumed; id={:?}", id);
+                    self.dispatch.get_mut().inner.transport().resume_body(id.clone());
+                }
+                None => {}
+            }
 
             // If the exchange is complete, track it for removal
             if exchange.is_complete() {
@@ -275,21 +537,91 @@ impl<T> Multiplex<T> where T: Dispatch {
         Ok(())
     }
 
+    /// Cancel the exchanges the dispatch lost interest in
+    fn cancel_exchanges(&mut self) -> io::Result<()> {
+        for id in self.dispatch.get_mut().inner.poll_canceled() {
+            trace!("   --> exchange canceled by dispatch; id={:?}", id);
+
+            if let Some(exchange) = self.exchanges.get_mut(&id) {
+                exchange.in_body = None;
+            }
+
+            try!(self.dispatch.get_mut().inner.cancel_transport(id));
+        }
+
+        Ok(())
+    }
+
     /// Read and process frames from transport
     fn read_out_frames(&mut self) -> io::Result<()> {
         while self.run {
-            // TODO: Only read frames if there is available space in the frame
-            // buffer
-            if let Async::Ready(frame) = try!(self.dispatch.get_mut().inner.transport().poll()) {
-                try!(self.process_out_frame(frame));
-            } else {
+            if !self.budget.has_remaining() {
+                break;
+            }
+
+            // A message is waiting for the dispatch to free up a slot, which
+            // doesn't keep the frames of the exchanges in progress from being
+            // read. Once another new message queues up behind it, leave
+            // further frames with the transport, pushing back on the peer
+            // instead of buffering without bounds.
+            if self.dispatch_deque.len() > 1 {
+                trace!("   --> dispatch not ready; pausing reads");
                 break;
             }
+
+            // Likewise, leave further frames with the transport until the
+            // slow body consumers drained the frame buffer
+            if self.spill_policy == SpillPolicy::Block && self.is_frame_buf_full() {
+                trace!("   --> frame buffer full; pausing reads");
+                break;
+            }
+
+            let frame = match self.dispatch.get_mut().inner.transport().poll() {
+                Ok(Async::Ready(frame)) => frame,
+                Ok(Async::NotReady) => {
+                    try!(self.check_pending_read());
+                    break;
+                }
+                Err(e) => {
+                    // Give the transport a chance to skip past the bad frame
+                    if self.dispatch.get_mut().inner.transport().resynchronize(&e) {
+                        debug!("resynchronized after read error; conn={}; err={}", self.id, e);
+                        self.budget.spend();
+                        continue;
+                    }
+
+                    return Err(e);
+                }
+            };
+
+            self.budget.spend();
+
+            if frame.is_some() {
+                self.observe.frame_read();
+            }
+
+            try!(self.process_out_frame(frame));
         }
 
         Ok(())
     }
 
+    // Fails the connection once the message being read outgrows the limit
+    fn check_pending_read(&mut self) -> io::Result<()> {
+        let max = match self.max_message_len {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+
+        match self.dispatch.get_mut().inner.transport().pending_read_len() {
+            Some(len) if len > max => {
+                debug!("message too large; conn={}; len={}; max={}", self.id, len, max);
+                Err(io::Error::new(io::ErrorKind::InvalidData, "message too large"))
+            }
+            _ => Ok(()),
+        }
+    }
+
     /// Process outbound frame
     fn process_out_frame(&mut self,
                          frame: Option<Frame<T::RequestId, T::Out, T::BodyOut, T::Error>>)
@@ -299,23 +631,41 @@ impl<T> Multiplex<T> where T: Dispatch {
         match frame {
             Some(Frame::Message { id, message, body, solo }) => {
                 if body {
-                    let (tx, rx) = Body::pair();
+                    let (tx, rx, control) = Body::pair_with_control();
                     let message = Message::WithBody(message, rx);
 
-                    try!(self.process_out_message(id, message, Some(tx), solo));
+                    try!(self.process_out_message(id, message, Some((tx, control)), solo));
                 } else {
                     let message = Message::WithoutBody(message);
 
                     try!(self.process_out_message(id, message, None, solo));
                 }
             }
+            Some(Frame::Inline { id, message, chunk, solo }) => {
+                // The whole body is at hand, no body channel is needed
+                let message = Message::WithBody(message, Body::from(chunk));
+
+                try!(self.process_out_message(id, message, None, solo));
+            }
             Some(Frame::Body { id, chunk }) => {
                 trace!("   --> read out body chunk");
-                self.process_out_body_chunk(id, Ok(chunk));
+                try!(self.process_out_body_chunk(id, Ok(chunk)));
             }
             Some(Frame::Error { id, error }) => {
                 try!(self.process_out_err(id, error));
             }
+            Some(Frame::Ack { id }) => {
+                trace!("   --> read ack; id={:?}", id);
+                let conn = self.id;
+                try!(self.dispatch.get_mut().inner.ack(id.clone())
+                         .map_err(|e| conn_id::annotate_request(conn, &id, e)));
+            }
+            Some(Frame::Progress { id, message }) => {
+                trace!("   --> read progress; id={:?}", id);
+                let conn = self.id;
+                try!(self.dispatch.get_mut().inner.progress(id.clone(), message)
+                         .map_err(|e| conn_id::annotate_request(conn, &id, e)));
+            }
             None => {
                 trace!("read None");
                 // TODO: Ensure all bodies have been completed
@@ -330,32 +680,38 @@ impl<T> Multiplex<T> where T: Dispatch {
     fn process_out_message(&mut self,
                            id: T::RequestId,
                            message: Message<T::Out, Body<T::BodyOut, T::Error>>,
-                           body: Option<mpsc::Sender<Result<T::BodyOut, T::Error>>>,
+                           body: Option<(BodySender<T::BodyOut, T::Error>, BodyControl)>,
                            solo: bool)
                            -> io::Result<()>
     {
         trace!("   --> process message; body={:?}", body.is_some());
 
+        let conn = self.id;
+
         match self.exchanges.entry(id.clone()) {
             Entry::Occupied(mut e) => {
-                assert!(!e.get().responded, "invalid exchange state");
-                assert!(e.get().is_inbound());
+                // Only a response may share the id of an exchange in progress
+                if e.get().responded || !e.get().is_inbound() {
+                    let description = format!("message reuses the id of an exchange in progress; \
+                                               conn={}, id={:?}", conn, id);
+                    return Err(violation(self.strict, description));
+                }
 
                 // Dispatch the message. The dispatcher is not checked for
                 // readiness in this case. This is because the message is a
                 // response to a request initiated by the dispatch. It is
                 // assumed that dispatcher can always process responses.
                 try!(self.dispatch.get_mut().inner.dispatch(MultiplexMessage {
-                    id: id,
+                    id: id.clone(),
                     message: Ok(message),
                     solo: solo,
-                }));
+                }).map_err(|e| conn_id::annotate_request(conn, &id, e)));
 
                 // Track that the exchange has been responded to
                 e.get_mut().responded = true;
 
                 // Set the body sender
-                e.get_mut().out_body = body;
+                e.get_mut().set_out_body(body);
 
                 // If the exchange is complete, clean up resources
                 if e.get().is_complete() {
@@ -363,18 +719,18 @@ impl<T> Multiplex<T> where T: Dispatch {
                 }
             }
             Entry::Vacant(e) => {
-                if self.dispatch.get_mut().inner.poll_ready().is_ready() {
+                // Queued messages are dispatched first
+                if self.dispatch_deque.is_empty() &&
+                   self.dispatch.get_mut().inner.poll_ready().is_ready() {
                     trace!("   --> dispatch ready -- dispatching");
 
-                    // Only should be here if there are no queued messages
-                    assert!(self.dispatch_deque.is_empty());
-
                     // Create the exchange state
                     let mut exchange = Exchange::new(
                         Request::Out(None),
-                        self.frame_buf.deque());
+                        self.frame_buf.deque(),
+                        self.observe.request());
 
-                    exchange.out_body = body;
+                    exchange.set_out_body(body);
 
                     // Set expect response
                     exchange.set_expect_response(solo);
@@ -386,10 +742,10 @@ impl<T> Multiplex<T> where T: Dispatch {
 
                     // Dispatch the message
                     try!(self.dispatch.get_mut().inner.dispatch(MultiplexMessage {
-                        id: id,
+                        id: id.clone(),
                         message: Ok(message),
                         solo: solo,
-                    }));
+                    }).map_err(|e| conn_id::annotate_request(conn, &id, e)));
                 } else {
                     trace!("   --> dispatch not ready");
 
@@ -398,9 +754,10 @@ impl<T> Multiplex<T> where T: Dispatch {
                     // Create the exchange state, including the buffered message
                     let mut exchange = Exchange::new(
                         Request::Out(Some(message)),
-                        self.frame_buf.deque());
+                        self.frame_buf.deque(),
+                        self.observe.request());
 
-                    exchange.out_body = body;
+                    exchange.set_out_body(body);
 
                     // Set expect response
                     exchange.set_expect_response(solo);
@@ -423,6 +780,7 @@ impl<T> Multiplex<T> where T: Dispatch {
     fn process_out_err(&mut self, id: T::RequestId, err: T::Error) -> io::Result<()> {
         trace!("   --> process error frame");
 
+        let conn = self.id;
         let mut remove = false;
 
         if let Some(exchange) = self.exchanges.get_mut(&id) {
@@ -431,12 +789,18 @@ impl<T> Multiplex<T> where T: Dispatch {
                 // At this point it is safe to just drop the state
                 remove = true;
 
-                assert!(exchange.out_body.is_none());
+                // The peer may abort the body of a buffered request, which
+                // nobody consumes yet
+                if !self.strict {
+                    assert!(exchange.out_body.is_none());
+                }
+
                 assert!(exchange.in_body.is_none());
             } else if exchange.is_outbound() {
                 // Outbound exchanges can only have errors dispatched via the
-                // body
-                exchange.send_out_chunk(Err(err));
+                // body. The exchange is over either way, so there is nothing
+                // to cancel if the body was dropped.
+                let _ = exchange.send_out_chunk(Err(err));
 
                 // The downstream dispatch has not provided a response to the
                 // exchange, indicate that interest has been canceled.
@@ -449,13 +813,14 @@ impl<T> Multiplex<T> where T: Dispatch {
                 if !exchange.responded {
                     // A response has not been provided yet, send the error via
                     // the dispatch
-                    try!(self.dispatch.get_mut().inner.dispatch(MultiplexMessage::error(id.clone(), err)));
+                    try!(self.dispatch.get_mut().inner.dispatch(MultiplexMessage::error(id.clone(), err))
+                             .map_err(|e| conn_id::annotate_request(conn, &id, e)));
 
                     exchange.responded = true;
                 } else {
                     // A response has already been sent, send the error via the
                     // body stream
-                    exchange.send_out_chunk(Err(err));
+                    let _ = exchange.send_out_chunk(Err(err));
                 }
 
                 remove = exchange.is_complete();
@@ -471,7 +836,7 @@ impl<T> Multiplex<T> where T: Dispatch {
         Ok(())
     }
 
-    fn process_out_body_chunk(&mut self, id: T::RequestId, chunk: Result<Option<T::BodyOut>, T::Error>) {
+    fn process_out_body_chunk(&mut self, id: T::RequestId, chunk: Result<Option<T::BodyOut>, T::Error>) -> io::Result<()> {
         trace!("process out body chunk; id={:?}", id);
 
         {
@@ -479,36 +844,109 @@ impl<T> Multiplex<T> where T: Dispatch {
                 Some(v) => v,
                 _ => {
                     trace!("   --> exchange previously aborted; id={:?}", id);
-                    return;
+                    return Ok(());
+                }
+            };
+
+            let canceled = match exchange.send_out_chunk(chunk) {
+                Ok(canceled) => canceled,
+                Err(Spilled) => {
+                    let err = io::Error::new(io::ErrorKind::Other,
+                                             "frame buffer capacity exceeded");
+
+                    match self.spill_policy {
+                        SpillPolicy::ErrorExchange => {
+                            trace!("   --> frame buffer full; failing out body; id={:?}", id);
+                            exchange.fail_out_body(err.into());
+                            true
+                        }
+                        // Blocking stops reading before the buffer overflows,
+                        // so this only happens when dropping the connection
+                        SpillPolicy::Block | SpillPolicy::DropConnection => {
+                            return Err(conn_id::annotate_request(self.id, &id, err));
+                        }
+                    }
                 }
             };
 
-            exchange.send_out_chunk(chunk);
+            if canceled {
+                // The body receiver went away, stop the peer from sending any
+                // more of the body. Further chunks are discarded.
+                trace!("   --> out body dropped; canceling; id={:?}", id);
+                try!(self.dispatch.get_mut().inner.transport().cancel(id.clone()));
+            }
 
             if !exchange.is_complete() {
-                return;
+                return Ok(());
             }
         }
 
         trace!("dropping out body handle; id={:?}", id);
         self.exchanges.remove(&id);
+
+        Ok(())
     }
 
     fn write_in_frames(&mut self) -> io::Result<()> {
+        try!(self.write_in_acks());
+        try!(self.write_in_progress());
         try!(self.write_in_messages());
         try!(self.write_in_body());
         Ok(())
     }
 
+    fn write_in_acks(&mut self) -> io::Result<()> {
+        while self.dispatch.poll_ready().is_ready() {
+            let id = match self.dispatch.get_mut().inner.poll_ack() {
+                Some(id) => id,
+                None => return Ok(()),
+            };
+
+            trace!("   --> writing ack; id={:?}", id);
+            try!(assert_send(&mut self.dispatch, Frame::Ack { id: id }));
+            self.blocked_on_flush.wrote_frame();
+        }
+
+        trace!("   --> transport not ready");
+        self.blocked_on_flush.transport_not_write_ready();
+
+        Ok(())
+    }
+
+    fn write_in_progress(&mut self) -> io::Result<()> {
+        while self.dispatch.poll_ready().is_ready() {
+            let (id, message) = match self.dispatch.get_mut().inner.poll_progress() {
+                Some(progress) => progress,
+                None => return Ok(()),
+            };
+
+            trace!("   --> writing progress; id={:?}", id);
+            try!(assert_send(&mut self.dispatch, Frame::Progress { id: id, message: message }));
+            self.blocked_on_flush.wrote_frame();
+        }
+
+        trace!("   --> transport not ready");
+        self.blocked_on_flush.transport_not_write_ready();
+
+        Ok(())
+    }
+
     fn write_in_messages(&mut self) -> io::Result<()> {
         trace!("write in messages");
 
         while self.dispatch.poll_ready().is_ready() {
             trace!("   --> polling for in frame");
 
-            match try!(self.dispatch.get_mut().inner.poll()) {
+            if !self.budget.has_remaining() {
+                break;
+            }
+
+            let next = self.dispatch.get_mut().inner.poll();
+
+            match try!(next.map_err(|e| conn_id::annotate(self.id, e))) {
                 Async::Ready(Some(message)) => {
                     self.dispatch_made_progress();
+                    self.budget.spend();
 
                     match message.message {
                         Ok(m) => {
@@ -529,9 +967,9 @@ impl<T> Multiplex<T> where T: Dispatch {
                     // transport should start shutting down.
                     //
                     // However, the `Done` frame should only be written once
-                    // all the in-flight bodies have been written.
-                    //
-                    // For now, do nothing...
+                    // all the in-flight bodies have been written. Track it so
+                    // the write half can be shut down after the drain.
+                    self.in_done = true;
                     break;
                 }
                 // Nothing to dispatch
@@ -551,6 +989,12 @@ impl<T> Multiplex<T> where T: Dispatch {
                         solo: bool)
                         -> io::Result<()>
     {
+        // Solo messages, such as pushed ones, start and end their exchange
+        if solo && self.exchanges.contains_key(&id) {
+            let err = io::Error::new(io::ErrorKind::Other, "solo message for an exchange in flight");
+            return Err(conn_id::annotate_request(self.id, &id, err));
+        }
+
         let (message, body) = match message {
             Message::WithBody(message, rx) => (message, Some(rx)),
             Message::WithoutBody(message) => (message, None),
@@ -568,11 +1012,12 @@ impl<T> Multiplex<T> where T: Dispatch {
         try!(assert_send(&mut self.dispatch, frame));
         self.blocked_on_flush.wrote_frame();
 
-        match self.exchanges.entry(id) {
+        let conn = self.id;
+
+        match self.exchanges.entry(id.clone()) {
             Entry::Occupied(mut e) => {
-                assert!(!e.get().responded, "invalid exchange state");
+                assert!(!e.get().responded, "invalid exchange state; conn={}, id={:?}", conn, id);
                 assert!(e.get().is_outbound());
-                assert!(!solo);
 
                 // Track that the exchange has been responded to
                 e.get_mut().responded = true;
@@ -589,7 +1034,8 @@ impl<T> Multiplex<T> where T: Dispatch {
                 // Create the exchange state
                 let mut exchange = Exchange::new(
                     Request::In,
-                    self.frame_buf.deque());
+                    self.frame_buf.deque(),
+                    self.observe.request());
 
                 // Set the body receiver
                 exchange.in_body = body;
@@ -610,8 +1056,10 @@ impl<T> Multiplex<T> where T: Dispatch {
                       error: T::Error)
                       -> io::Result<()>
     {
+        let conn = self.id;
+
         if let Entry::Occupied(mut e) = self.exchanges.entry(id.clone()) {
-            assert!(!e.get().responded, "exchange already responded");
+            assert!(!e.get().responded, "exchange already responded; conn={}, id={:?}", conn, id);
 
             // TODO: should the outbound body be canceled? In theory, if the
             // consuming end doesn't want it anymore, it should drop interest
@@ -623,6 +1071,7 @@ impl<T> Multiplex<T> where T: Dispatch {
             assert!(e.get().is_complete());
 
             // Write the error frame
+            try!(self.error_rate.check().map_err(|e| conn_id::annotate_request(conn, &id, e)));
             let frame = Frame::Error { id: id, error: error };
             try!(assert_send(&mut self.dispatch, frame));
             self.blocked_on_flush.wrote_frame();
@@ -638,6 +1087,7 @@ impl<T> Multiplex<T> where T: Dispatch {
     fn write_in_body(&mut self) -> io::Result<()> {
         trace!("write in body chunks");
 
+        let conn = self.id;
         self.scratch.clear();
 
         // Now, write the ready streams
@@ -652,13 +1102,34 @@ impl<T> Multiplex<T> where T: Dispatch {
                     break 'outer;
                 }
 
+                if !self.budget.has_remaining() {
+                    break 'outer;
+                }
+
                 let id = id.clone();
 
+                // Leave the body stream alone while the transport throttles it
+                if exchange.in_body.is_some() {
+                    let transport = self.dispatch.get_mut().inner.transport();
+
+                    if !transport.poll_write_body(id.clone()).is_ready() {
+                        trace!("   --> body throttled by transport");
+                        continue 'outer;
+                    }
+                }
+
                 match exchange.try_poll_in_body() {
                     Ok(Async::Ready(Some(chunk))) => {
                         trace!("   --> got chunk");
+                        self.budget.spend();
+
+                        let mut frame = Frame::Body { id: id, chunk: Some(chunk) };
+                        let transport = self.dispatch.get_mut().inner.transport();
+
+                        if !exchange.split_in_body(&mut frame, transport) {
+                            exchange.coalesce_in_body(&mut frame, transport);
+                        }
 
-                        let frame = Frame::Body { id: id, chunk: Some(chunk) };
                         try!(assert_send(&mut self.dispatch, frame));
                         self.blocked_on_flush.wrote_frame();
                     }
@@ -670,13 +1141,17 @@ impl<T> Multiplex<T> where T: Dispatch {
                         self.blocked_on_flush.wrote_frame();
 
                         // in_body is fully written.
-                        exchange.in_body = None;
+                        if let Some(body) = exchange.in_body.take() {
+                            self.flushed_bodies.push(body);
+                        }
                         break;
                     }
                     Err(error) => {
                         trace!("   --> got error");
 
                         // Write the error frame
+                        try!(self.error_rate.check()
+                                 .map_err(|e| conn_id::annotate_request(conn, &id, e)));
                         let frame = Frame::Error { id: id, error: error };
                         try!(assert_send(&mut self.dispatch, frame));
                         self.blocked_on_flush.wrote_frame();
@@ -712,6 +1187,16 @@ impl<T> Multiplex<T> where T: Dispatch {
     fn flush(&mut self) -> io::Result<()> {
         self.is_flushed = try!(self.dispatch.poll_complete()).is_ready();
 
+        if self.is_flushed {
+            self.flushed_bodies.clear();
+
+            if let Some(started) = self.flush_started.take() {
+                self.flush_latency = Some(started.elapsed());
+            }
+        } else if self.flush_started.is_none() {
+            self.flush_started = Some(Instant::now());
+        }
+
         // TODO: Technically, poll_complete needs to be called on the exchange body senders.
         // However, mpsc::Sender doesn't actually need to have poll_complete called as it is
         // currently a no-op. So, I'm just going to punt on figuring out the best way to handle
@@ -724,6 +1209,65 @@ impl<T> Multiplex<T> where T: Dispatch {
         Ok(())
     }
 
+    // Shut down the write half once everything has been written out
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        if !self.in_done || !self.is_flushed || self.is_write_shutdown {
+            return Ok(());
+        }
+
+        if self.exchanges.values().any(|exchange| exchange.in_body.is_some()) {
+            return Ok(());
+        }
+
+        trace!("shutting down write half");
+        self.is_write_shutdown = true;
+        self.dispatch.get_mut().inner.transport().shutdown_write()
+    }
+
+    /// Returns true if another body frame may not fit the frame buffer
+    fn is_frame_buf_full(&self) -> bool {
+        self.frame_buf.is_full() ||
+            self.exchanges.values().any(|exchange| exchange.out_deque.is_full())
+    }
+
+    fn report_stats(&mut self) {
+        let consumer_lag = self.exchanges.values()
+            .map(|exchange| exchange.out_deque.len())
+            .sum();
+
+        let stats = Stats {
+            consumer_lag: consumer_lag,
+            flush_latency: self.flush_latency.take(),
+        };
+
+        self.dispatch.get_mut().inner.transport().on_stats(stats);
+    }
+
+    // Records the agreed content encoding once the peer advertised its own
+    fn negotiate_encoding(&mut self) {
+        let agreed = match self.negotiation {
+            Some(ref negotiation) => {
+                match self.dispatch.get_mut().inner.transport().peer_encodings() {
+                    Some(peer) => negotiation.agree(&peer),
+                    None => return,
+                }
+            }
+            None => return,
+        };
+
+        trace!("negotiated encoding; encoding={:?}", agreed);
+        self.negotiation = None;
+        self.dispatch.get_mut().inner.transport().on_encoding(agreed.as_ref().map(|e| &e[..]));
+    }
+
+    // Drops the exchanges the dispatch reports as not processed by the peer
+    fn forget_unprocessed(&mut self) {
+        for id in self.dispatch.get_mut().inner.poll_going_away() {
+            trace!("   --> exchange not processed by peer; conn={}; id={:?}", self.id, id);
+            self.exchanges.remove(&id);
+        }
+    }
+
     fn reset_flags(&mut self) {
         self.made_progress = false;
         self.blocked_on_dispatch = false;
@@ -737,14 +1281,11 @@ impl<T> Multiplex<T> where T: Dispatch {
     }
 }
 
-impl<T> Future for Multiplex<T>
+impl<T> Multiplex<T>
     where T: Dispatch,
 {
-    type Item = ();
-    type Error = io::Error;
-
     // Tick the pipeline state machine
-    fn poll(&mut self) -> Poll<(), io::Error> {
+    fn tick(&mut self) -> Poll<(), io::Error> {
         trace!("Multiplex::tick ~~~~~~~~~~~~~~~~~~~~~~~~~~~");
 
         // Always tick the transport first
@@ -756,6 +1297,11 @@ impl<T> Future for Multiplex<T>
         // on.
         try!(self.flush_out_bodies());
 
+        // Stop working on exchanges the dispatch is no longer interested in
+        try!(self.cancel_exchanges());
+
+        self.budget.reset();
+
         // Initially set the made_progress flag to true
         self.made_progress = true;
 
@@ -772,13 +1318,37 @@ impl<T> Future for Multiplex<T>
             // First read off data from the socket
             try!(self.read_out_frames());
 
+            // Settle the content encoding once the peer advertised its own
+            self.negotiate_encoding();
+
+            // Forget the exchanges a peer going away did not process
+            self.forget_unprocessed();
+
             // Handle completed responses
             try!(self.write_in_frames());
 
+            // Stop reading once the dispatch is closing the connection
+            if self.run && (self.dispatch.get_ref().inner.is_closing() || self.control.poll_close()) {
+                debug!("closing connection; conn={}", self.id);
+                self.run = false;
+            }
+
             // Try flushing buffered writes
             try!(self.flush());
+
+            // Leave the rest for the next tick
+            if self.budget.is_exhausted() {
+                break;
+            }
         }
 
+        // Signal the end of the request stream if the dispatch is done
+        try!(self.shutdown_write());
+
+        // Give the transport feedback on how the connection is doing
+        self.report_stats();
+        self.observe.queue_depth(self.exchanges.len());
+
         // Clean shutdown of the pipeline server can happen when
         //
         // 1. The server is done running, this is signaled by Transport::poll()
@@ -799,28 +1369,68 @@ impl<T> Future for Multiplex<T>
 
         trace!("tick done; waiting for wake-up");
 
+        // Resume right away if work was left behind
+        self.budget.yield_if_exhausted();
+
         // Tick again later
         Ok(Async::NotReady)
     }
 }
 
-impl<T: Dispatch> Drop for Multiplex<T> {
-    fn drop(&mut self) {
-        if !self.exchanges.is_empty() {
-            warn!("multiplexer dropping with in-flight exchanges");
+impl<T> Future for Multiplex<T>
+    where T: Dispatch,
+{
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        // A panic fails the connection like any other error
+        let res = ::unwind::catch(|| self.tick());
+
+        match res {
+            Ok(Async::Ready(())) => self.control.terminate(ConnectionState::Done),
+            Err(ref e) => {
+                if !self.exchanges.is_empty() {
+                    warn!("multiplexer failed with in-flight exchanges");
+                }
+
+                self.observe.error(e);
+                self.control.terminate(ConnectionState::Failed(e.kind()));
+            }
+            _ => {}
         }
+
+        res
+    }
+}
+
+impl<T, B> ProtocolKind<T> for StreamingMultiplex<B> where T: Dispatch {
+    type Frame = Frame<T::RequestId, T::In, T::BodyIn, T::Error>;
+    type Config = MultiplexConfig;
+    type Driver = Multiplex<T>;
+
+    fn drive(dispatch: T, config: &MultiplexConfig) -> Multiplex<T> {
+        Multiplex::with_config(dispatch, config)
     }
 }
 
 impl<T: Dispatch> Exchange<T> {
-    fn new(request: Request<T>, deque: FrameDeque<Option<Result<T::BodyOut, T::Error>>>) -> Exchange<T> {
+    fn new(request: Request<T>,
+           deque: FrameDeque<Option<Result<T::BodyOut, T::Error>>>,
+           in_flight: Option<InFlight>)
+           -> Exchange<T> {
         Exchange {
             request: request,
             responded: false,
             out_body: None,
+            out_control: None,
+            out_paused: false,
             out_deque: deque,
+            out_error: None,
             out_is_ready: true,
             in_body: None,
+            in_body_next: None,
+            _in_flight: in_flight,
         }
     }
 
@@ -852,6 +1462,39 @@ impl<T: Dispatch> Exchange<T> {
             self.request.is_none()
     }
 
+    fn set_out_body(&mut self, body: Option<(BodySender<T::BodyOut, T::Error>, BodyControl)>) {
+        match body {
+            Some((sender, control)) => {
+                self.out_body = Some(sender);
+                self.out_control = Some(control);
+            }
+            None => {
+                self.out_body = None;
+                self.out_control = None;
+            }
+        }
+    }
+
+    /// Returns `Some` with the new state if the outbound body has been paused
+    /// or resumed since the last call.
+    fn poll_pause_change(&mut self) -> Option<bool> {
+        let paused = self.out_body.is_some() && self.is_out_body_paused();
+
+        if paused == self.out_paused {
+            return None;
+        }
+
+        self.out_paused = paused;
+        Some(paused)
+    }
+
+    fn is_out_body_paused(&self) -> bool {
+        match self.out_control {
+            Some(ref control) => control.poll_paused(),
+            None => false,
+        }
+    }
+
     fn set_expect_response(&mut self, solo: bool) {
         self.responded = solo;
 
@@ -872,7 +1515,13 @@ impl<T: Dispatch> Exchange<T> {
         }
     }
 
-    fn send_out_chunk(&mut self, chunk: Result<Option<T::BodyOut>, T::Error>) {
+    /// Sends the chunk on the out body sender, buffering it if the sender is
+    /// not ready.
+    ///
+    /// Returns true if the body receiver has been dropped, or `Spilled` if the
+    /// chunk had to be buffered but the frame buffer is full. Errors are
+    /// never spilled.
+    fn send_out_chunk(&mut self, chunk: Result<Option<T::BodyOut>, T::Error>) -> Result<bool, Spilled> {
         // Reverse Result & Option
         let chunk = match chunk {
             Ok(Some(v)) => Some(Ok(v)),
@@ -880,36 +1529,47 @@ impl<T: Dispatch> Exchange<T> {
             Err(e) => Some(Err(e)),
         };
 
+        let mut canceled = false;
+
         // Get a reference to the sender
         {
             let sender = match self.out_body {
                 Some(ref mut v) => v,
                 _ =>  {
-                    return;
+                    return Ok(false);
                 }
             };
 
+            if self.out_error.is_some() {
+                trace!("   --> out body failed; discarding chunk");
+                return Ok(false);
+            }
+
             if self.out_is_ready {
                 trace!("   --> send chunk; end-of-stream={:?}", chunk.is_none());
 
                 // If there is a chunk (vs. None which represents end of
                 // stream)
                 if let Some(chunk) = chunk {
+                    let done = chunk.is_err();
+
                     match sender.start_send(chunk) {
                         Ok(AsyncSink::Ready) => {
                             trace!("   --> ready for more");
                             // The sender is ready for another message
-                            return;
+                            return Ok(false);
                         }
                         Ok(AsyncSink::NotReady(chunk)) => {
                             // The sender is not ready for another message
-                            self.out_deque.push(Some(chunk));
                             self.out_is_ready = false;
 
-                            return;
+                            return self.buffer_out_chunk(Some(chunk));
                         }
                         Err(_) => {
-                            // The sender is complete, it should be removed
+                            // The receiving end dropped interest in the body
+                            // stream, the sender should be removed. An error
+                            // terminates the body anyway.
+                            canceled = !done;
                         }
                     }
                 }
@@ -918,33 +1578,143 @@ impl<T: Dispatch> Exchange<T> {
             } else {
                 trace!("   --> queueing chunk");
 
-                self.out_deque.push(chunk);
-                return;
+                return self.buffer_out_chunk(chunk);
             }
         }
 
         self.out_is_ready = false;
         self.out_body = None;
+
+        Ok(canceled)
+    }
+
+    fn buffer_out_chunk(&mut self, chunk: Option<Result<T::BodyOut, T::Error>>) -> Result<bool, Spilled> {
+        match self.out_deque.try_push(chunk) {
+            Ok(()) => Ok(false),
+            // An error ends the body anyway, so it takes the place of the
+            // buffered chunks
+            Err(Some(Err(error))) => {
+                self.fail_out_body(error);
+                Ok(false)
+            }
+            Err(_) => Err(Spilled),
+        }
+    }
+
+    /// Fails the outbound body with the given error, discarding the buffered
+    /// chunks. The error is sent once the sender is ready.
+    fn fail_out_body(&mut self, error: T::Error) {
+        self.out_deque.clear();
+        self.out_error = Some(error);
+        self.out_is_ready = false;
     }
 
     fn try_poll_in_body(&mut self) -> Poll<Option<T::BodyIn>, T::Error> {
         match self.in_body {
-            Some(ref mut b) => b.poll(),
+            Some(ref mut b) => match self.in_body_next.take() {
+                Some(next) => next.map(Async::Ready),
+                None => b.poll(),
+            },
             None => {
                 trace!(" !!! no in body??");
+                self.in_body_next = None;
                 Ok(Async::NotReady)
             }
         }
     }
 
+    /// Split a body frame exceeding the transport's max frame length. The
+    /// remainder is written next. Returns true if the frame was split.
+    fn split_in_body(&mut self,
+                     frame: &mut Frame<T::RequestId, T::In, T::BodyIn, T::Error>,
+                     transport: &mut T::Transport)
+                     -> bool
+    {
+        let max_len = match transport.max_body_frame_len() {
+            Some(max_len) => max_len,
+            None => return false,
+        };
+
+        match transport.split_body(frame, max_len) {
+            Some(Frame::Body { chunk, .. }) => {
+                trace!("   --> split chunk");
+                self.in_body_next = Some(Ok(chunk));
+                true
+            }
+            Some(_) => panic!("transport must split body frames into body frames"),
+            None => false,
+        }
+    }
+
+    /// Merge the chunks of the inbound body that are ready into a single
+    /// frame, as far as the transport allows
+    fn coalesce_in_body(&mut self,
+                        frame: &mut Frame<T::RequestId, T::In, T::BodyIn, T::Error>,
+                        transport: &mut T::Transport)
+    {
+        let id = match *frame {
+            Frame::Body { ref id, .. } => id.clone(),
+            _ => unreachable!(),
+        };
+
+        loop {
+            let next = match self.in_body.as_mut().unwrap().poll() {
+                Ok(Async::Ready(Some(chunk))) => Frame::Body { id: id.clone(), chunk: Some(chunk) },
+                Ok(Async::Ready(None)) => {
+                    self.in_body_next = Some(Ok(None));
+                    break;
+                }
+                Err(e) => {
+                    self.in_body_next = Some(Err(e));
+                    break;
+                }
+                Ok(Async::NotReady) => break,
+            };
+
+            match transport.coalesce_body(frame, next) {
+                None => trace!("   --> coalesced chunk"),
+                Some(Frame::Body { chunk, .. }) => {
+                    self.in_body_next = Some(Ok(chunk));
+                    break;
+                }
+                Some(_) => panic!("transport must return the body frame it did not coalesce"),
+            }
+        }
+    }
+
     /// Write as many buffered body chunks to the sender
-    fn flush_out_body(&mut self) -> io::Result<()> {
+    ///
+    /// Returns true if the body receiver has been dropped.
+    fn flush_out_body(&mut self) -> io::Result<bool> {
+        let mut canceled = false;
+
+        // A failed body is completed with the error, even while paused
+        if let Some(error) = self.out_error.take() {
+            if let Some(ref mut sender) = self.out_body {
+                if let Ok(AsyncSink::NotReady(Err(error))) = sender.start_send(Err(error)) {
+                    self.out_error = Some(error);
+                    return Ok(false);
+                }
+            }
+
+            self.out_is_ready = false;
+            self.out_body = None;
+
+            return Ok(false);
+        }
+
+        if self.out_body.is_some() && self.is_out_body_paused() {
+            // Chunks are buffered until the body is resumed
+            self.out_is_ready = false;
+            return Ok(false);
+        }
+
         {
             let sender = match self.out_body {
                 Some(ref mut sender) => sender,
                 None => {
                     assert!(self.out_deque.is_empty(), "pending out frames but no sender");
-                    return Ok(());
+                    return Ok(false);
                 }
             };
 
@@ -957,7 +1727,7 @@ impl<T: Dispatch> Exchange<T> {
                     Some(None) => break,
                     None => {
                         // No more frames to flush
-                        return Ok(());
+                        return Ok(false);
                     }
                 };
 
@@ -973,16 +1743,15 @@ impl<T: Dispatch> Exchange<T> {
                         self.out_deque.push_front(Some(msg));
                         self.out_is_ready = false;
 
-                        return Ok(());
+                        return Ok(false);
                     }
                     Err(_) => {
                         // The receiving end dropped interest in the body
                         // stream. In this case, the sender and the frame
                         // buffer is dropped. If future body frames are
                         // received, the sender will be gone and the frames
-                        // will be dropped.
-                        //
-                        // TODO: Notify transport
+                        // will be dropped. The caller notifies the transport.
+                        canceled = !done;
                         break;
                     }
                 }
@@ -998,7 +1767,7 @@ impl<T: Dispatch> Exchange<T> {
         self.out_is_ready = false;
         self.out_body = None;
 
-        Ok(())
+        Ok(canceled)
     }
 }
 
@@ -1071,7 +1840,13 @@ impl<T: Dispatch> Sink for DispatchSink<T> {
     fn start_send(&mut self, item: Self::SinkItem)
                   -> StartSend<Self::SinkItem, io::Error>
     {
-        self.inner.transport().start_send(item)
+        let res = try!(self.inner.transport().start_send(item));
+
+        if res.is_ready() {
+            self.observe.frame_written();
+        }
+
+        Ok(res)
     }
 
     fn poll_complete(&mut self) -> Poll<(), io::Error> {
@@ -1,13 +1,19 @@
-use super::{Frame, RequestId, RequestIdSource, StreamingMultiplex, Transport};
-use super::advanced::{Multiplex, MultiplexMessage};
-
-use BindClient;
-use streaming::{Body, Message};
-use util::client_proxy::{self, ClientProxy, Receiver};
-use futures::{Future, IntoFuture, Complete, Poll, Async};
+use super::{Frame, GoAway, NotProcessed, RequestId, RequestIdSource, StreamingMultiplex, Transport, MultiplexConfig};
+use super::advanced::MultiplexMessage;
+use super::violation;
+
+use {BindClient, BindConfig, ProtocolKind};
+use streaming::{budget, Body, Encodings, Message, Negotiation};
+use util::client_proxy::{self, ClientProxy, Complete, Receiver};
+use futures::{Future, IntoFuture, Poll, Async};
 use futures::stream::Stream;
-use tokio_core::reactor::Handle;
+use futures::task;
+use tokio_core::reactor::{Handle, Timeout};
 use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use timeout::Deadline;
+use instrument::ConnectionObserver;
 use std::collections::HashMap;
 
 /// A streaming, multiplexed client protocol.
@@ -32,7 +38,10 @@ pub trait ClientProto<T: 'static>: 'static {
     type ResponseBody: 'static;
 
     /// The type of request ids to used to correlate requests to responses
-    type RequestId: RequestId;
+    ///
+    /// They are `Send` as the client lists them, see
+    /// `ClientService::outstanding`.
+    type RequestId: RequestId + Send;
 
     /// Errors, which are used both for error frames and for the service itself.
     type Error: From<io::Error> + 'static;
@@ -58,6 +67,64 @@ pub trait ClientProto<T: 'static>: 'static {
     /// Build a transport from the given I/O object, using `self` for any
     /// configuration.
     fn bind_transport(&self, io: T) -> Self::BindTransport;
+
+    /// Tuning knobs applied to every connection bound by this protocol.
+    ///
+    /// Defaults to `MultiplexConfig::default()`.
+    fn config(&self) -> MultiplexConfig {
+        MultiplexConfig::default()
+    }
+
+    /// Content encodings this end supports, most preferred first.
+    ///
+    /// When not empty, connections negotiate their content encoding: the
+    /// transport advertises the list to the peer and is told the agreed
+    /// encoding through its `on_encoding` hook. See `Negotiation`. Defaults
+    /// to none, leaving connections unencoded.
+    fn encodings(&self) -> Encodings {
+        Encodings::new()
+    }
+
+    /// Time a request waits for its response before it is given up on.
+    ///
+    /// Once the timeout passes, the response future fails with a `TimedOut`
+    /// error and the exchange is canceled on the transport. As with dropped
+    /// response futures, the request id stays reserved for the late
+    /// response, see `late_response_timeout`. Defaults to `None`, waiting
+    /// forever.
+    fn request_timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Time the id of a canceled or timed out request stays reserved for
+    /// the late response.
+    ///
+    /// A late response is discarded. Once the timeout passes, the id is
+    /// released to the `RequestIdSource` and a response still arriving for
+    /// it is unexpected, failing the connection. Defaults to 30 seconds.
+    fn late_response_timeout(&self) -> Duration {
+        Duration::from_secs(30)
+    }
+
+    /// Cancel the exchange `request_id` on the transport.
+    ///
+    /// Called once the caller lost interest in the response, because the
+    /// response future was dropped before completing or the request timed
+    /// out. Protocols supporting cancellation on the wire can write a cancel
+    /// frame for the exchange. Defaults to `Transport::cancel`.
+    fn cancel(transport: &mut Self::Transport, request_id: Self::RequestId) -> io::Result<()> {
+        transport.cancel(request_id)
+    }
+
+    /// Returns the announcement of the server going away, once read from
+    /// `transport`.
+    ///
+    /// The requests the server did not process fail with `NotProcessed`, as
+    /// do the requests made afterwards; see `GoAway`. Defaults to
+    /// `Transport::peer_going_away`.
+    fn peer_going_away(transport: &mut Self::Transport) -> Option<GoAway<Self::RequestId>> {
+        transport.peer_going_away()
+    }
 }
 
 impl<P, T, B> BindClient<StreamingMultiplex<B>, T> for P where
@@ -72,31 +139,71 @@ impl<P, T, B> BindClient<StreamingMultiplex<B>, T> for P where
     type BindClient = ClientProxy<Self::ServiceRequest, Self::ServiceResponse, Self::ServiceError>;
 
     fn bind_client(&self, handle: &Handle, io: T) -> Self::BindClient {
-        let (client, rx) = client_proxy::pair();
-
-        let rid_src = self.requestid_source();
-
-        let task = self.bind_transport(io).into_future().and_then(|transport| {
-            let dispatch: Dispatch<P, T, B> = Dispatch {
-                transport: transport,
-                requests: rx,
-                in_flight: HashMap::new(),
-                rid_src: rid_src,
-            };
-            Multiplex::new(dispatch)
-        }).map_err(|e| {
-            // TODO: where to punt this error to?
-            debug!("multiplex task failed with error; err={:?}", e);
-        });
+        bind::<P, T, B>(self, handle, io, &BindConfig::default())
+    }
 
-        // Spawn the task
-        handle.spawn(task);
+    fn bind_client_timeout(&self, handle: &Handle, io: T, timeout: Duration) -> Self::BindClient {
+        bind::<P, T, B>(self, handle, io, &BindConfig { timeout: Some(timeout), ..BindConfig::default() })
+    }
 
-        // Return the client
-        client
+    fn bind_client_with(&self, handle: &Handle, io: T, config: &BindConfig) -> Self::BindClient {
+        bind::<P, T, B>(self, handle, io, config)
     }
 }
 
+fn bind<P, T, B>(proto: &P, handle: &Handle, io: T, binding: &BindConfig)
+                 -> ClientProxy<Message<P::Request, B>,
+                                Message<P::Response, Body<P::ResponseBody, P::Error>>,
+                                P::Error>
+    where P: ClientProto<T>,
+          T: 'static,
+          B: Stream<Item = P::RequestBody, Error = P::Error> + 'static,
+{
+    let (client, rx) = client_proxy::pair();
+    let errors = client.error_sink();
+
+    let rid_src = proto.requestid_source();
+    let mut config = binding.multiplex.clone().unwrap_or_else(|| proto.config());
+    config.max_frames_per_poll = budget::capped(config.max_frames_per_poll,
+                                                binding.max_frames_per_poll);
+    let negotiation = Negotiation::client(proto.encodings());
+    let observer = binding.observer.clone();
+    let request_timeout = proto.request_timeout();
+    let late_response_timeout = proto.late_response_timeout();
+    let timer_handle = handle.clone();
+
+    let transport = Deadline::new(proto.bind_transport(io).into_future(), binding.timeout, handle);
+
+    let task = transport.and_then(move |mut transport| {
+        transport.on_handle(&timer_handle);
+
+        let dispatch: Dispatch<P, T, B> = Dispatch {
+            transport: transport,
+            requests: rx,
+            in_flight: HashMap::new(),
+            canceled: HashMap::new(),
+            rid_src: rid_src,
+            handle: timer_handle,
+            request_timeout: request_timeout,
+            late_response_timeout: late_response_timeout,
+            negotiation: Some(negotiation),
+            observer: observer,
+            strict: config.strict,
+            going_away: false,
+        };
+        ::unwind::isolate(StreamingMultiplex::<B>::drive(dispatch, &config))
+    }).map_err(move |e| {
+        debug!("multiplex task failed with error; err={:?}", e);
+        errors.report(e);
+    });
+
+    // Spawn the task
+    handle.spawn(task);
+
+    // Return the client
+    client
+}
+
 struct Dispatch<P, T, B> where
     P: ClientProto<T> + BindClient<StreamingMultiplex<B>, T>,
     T: 'static,
@@ -104,8 +211,29 @@ struct Dispatch<P, T, B> where
 {
     transport: P::Transport,
     requests: Receiver<P::ServiceRequest, P::ServiceResponse, P::Error>,
-    in_flight: HashMap<P::RequestId, Complete<Result<P::ServiceResponse, P::Error>>>,
+    in_flight: HashMap<P::RequestId, InFlight<P::ServiceResponse, P::Error>>,
+    // Exchanges whose response futures were dropped or timed out. Their ids
+    // are held until the response arrives, so that it is not mistaken for
+    // another one, or until the timer gives up on it.
+    canceled: HashMap<P::RequestId, Timeout>,
     rid_src: P::RequestIdSource,
+    handle: Handle,
+    request_timeout: Option<Duration>,
+    late_response_timeout: Duration,
+    negotiation: Option<Negotiation>,
+    // Observer from the bind config
+    observer: Option<Arc<ConnectionObserver>>,
+    // Whether violations by the server close the connection instead of
+    // panicking
+    strict: bool,
+    // Set once the server announced it is going away, requests are failed
+    // from then on
+    going_away: bool,
+}
+
+struct InFlight<R, E> {
+    complete: Complete<R, E>,
+    timeout: Option<Timeout>,
 }
 
 impl<P, T, B> super::advanced::Dispatch for Dispatch<P, T, B> where
@@ -127,13 +255,28 @@ impl<P, T, B> super::advanced::Dispatch for Dispatch<P, T, B> where
         &mut self.transport
     }
 
+    fn negotiation(&mut self) -> Option<Negotiation> {
+        self.negotiation.take()
+    }
+
+    fn observer(&self) -> Option<Arc<ConnectionObserver>> {
+        self.observer.clone()
+    }
+
     fn dispatch(&mut self, message: MultiplexMessage<Self::RequestId, Self::Out, Body<Self::BodyOut, Self::Error>, Self::Error>) -> io::Result<()> {
         let MultiplexMessage { id, message, solo } = message;
 
-        assert!(!solo);
+        // Pushed messages are not supported by the client
+        if solo {
+            return Err(violation(self.strict, format!("solo response; id={:?}", id)));
+        }
 
-        if let Some(complete) = self.in_flight.remove(&id) {
-            complete.complete(message);
+        if let Some(in_flight) = self.in_flight.remove(&id) {
+            self.rid_src.release(&id);
+            in_flight.complete.complete(message);
+        } else if self.canceled.remove(&id).is_some() {
+            trace!("   --> discarding response to canceled request; id={:?}", id);
+            self.rid_src.release(&id);
         } else {
             return Err(io::Error::new(io::ErrorKind::Other, "request / response mismatch"));
         }
@@ -143,48 +286,246 @@ impl<P, T, B> super::advanced::Dispatch for Dispatch<P, T, B> where
 
     fn poll(&mut self) -> Poll<Option<MultiplexMessage<Self::RequestId, Self::In, B, Self::Error>>, io::Error> {
         trace!("Dispatch::poll");
-        // Try to get a new request frame
-        match self.requests.poll() {
-            Ok(Async::Ready(Some(Ok((request, complete))))) => {
-                trace!("   --> received request");
 
-                let request_id = self.rid_src.next(&request);
+        if self.going_away {
+            return self.fail_requests();
+        }
 
-                trace!("   --> assigning request-id={:?}", request_id);
+        loop {
+            // Try to get a new request frame
+            match self.requests.poll() {
+                Ok(Async::Ready(Some(Ok((request, mut complete))))) => {
+                    trace!("   --> received request");
+
+                    // Requests canceled before being written are skipped
+                    if complete.poll_cancel().is_ready() {
+                        trace!("   --> request canceled");
+
+                        if complete.is_canceled_by_client() {
+                            complete.complete(Err(client_proxy::canceled_error().into()));
+                        }
+
+                        continue;
+                    }
+
+                    let request_id = self.rid_src.next(&request);
+
+                    trace!("   --> assigning request-id={:?}", request_id);
+                    complete.set_request_id(request_id.clone());
+                    complete.set_written();
+
+                    let timeout = match self.request_timeout {
+                        Some(dur) => {
+                            complete.set_deadline(Instant::now() + dur);
+                            Some(try!(Timeout::new(dur, &self.handle)))
+                        }
+                        None => None,
+                    };
+
+                    let mut in_flight = InFlight {
+                        complete: complete,
+                        timeout: timeout,
+                    };
+
+                    // Register interest in the timeout, it is checked for in
+                    // `poll_canceled` on the next tick
+                    if in_flight.poll_timeout() {
+                        task::park().unpark();
+                    }
+
+                    // Track complete handle
+                    self.in_flight.insert(request_id.clone(), in_flight);
+
+                    return Ok(Async::Ready(Some(MultiplexMessage::new(request_id, request))));
+                }
+                Ok(Async::Ready(None)) => {
+                    trace!("   --> client dropped");
+                    return Ok(Async::Ready(None));
+                }
+                Ok(Async::Ready(Some(Err(e)))) => {
+                    trace!("   --> error");
+                    // An error on receive can only happen when the other half
+                    // disconnected. In this case, the client needs to be
+                    // shutdown
+                    panic!("unimplemented error handling: {:?}", e);
+                }
+                Ok(Async::NotReady) => {
+                    trace!("   --> not ready");
+                    return Ok(Async::NotReady);
+                }
+                Err(()) => panic!(),
+            }
+        }
+    }
 
-                // Track complete handle
-                self.in_flight.insert(request_id.clone() , complete);
+    fn poll_ready(&self) -> Async<()> {
+        // Not capping the client yet
+        Async::Ready(())
+    }
 
-                Ok(Async::Ready(Some(MultiplexMessage::new(request_id, request))))
+    fn cancel(&mut self, request_id: Self::RequestId) -> io::Result<()> {
+        // The server aborted the exchange, which is over along with its id
+        if let Some(in_flight) = self.in_flight.remove(&request_id) {
+            let error = io::Error::new(io::ErrorKind::Other, "exchange aborted by server");
+            in_flight.complete.complete(Err(error.into()));
+            self.rid_src.release(&request_id);
+        } else if self.canceled.remove(&request_id).is_some() {
+            self.rid_src.release(&request_id);
+        }
 
+        Ok(())
+    }
+
+    fn cancel_transport(&mut self, request_id: Self::RequestId) -> io::Result<()> {
+        P::cancel(&mut self.transport, request_id)
+    }
+
+    fn progress(&mut self, request_id: Self::RequestId, message: Self::Out) -> io::Result<()> {
+        if let Some(in_flight) = self.in_flight.get_mut(&request_id) {
+            in_flight.complete.progress(Message::WithoutBody(message));
+        } else if !self.canceled.contains_key(&request_id) {
+            return Err(io::Error::new(io::ErrorKind::Other, "request / progress mismatch"));
+        }
+
+        Ok(())
+    }
+
+    fn ack(&mut self, request_id: Self::RequestId) -> io::Result<()> {
+        if let Some(in_flight) = self.in_flight.get_mut(&request_id) {
+            in_flight.complete.ack();
+        } else if !self.canceled.contains_key(&request_id) {
+            return Err(io::Error::new(io::ErrorKind::Other, "request / ack mismatch"));
+        }
+
+        Ok(())
+    }
+
+    fn poll_canceled(&mut self) -> Vec<Self::RequestId> {
+        self.expire_canceled();
+
+        let mut canceled = Vec::new();
+
+        for (request_id, in_flight) in self.in_flight.iter_mut() {
+            if in_flight.complete.poll_cancel().is_ready() {
+                // Unlike dropped response futures, the caller still waits
+                let error = if in_flight.complete.is_canceled_by_client() {
+                    Some(client_proxy::canceled_error())
+                } else {
+                    None
+                };
+
+                canceled.push((request_id.clone(), error));
+            } else if in_flight.poll_timeout() {
+                let error = io::Error::new(io::ErrorKind::TimedOut, "request timed out");
+                canceled.push((request_id.clone(), Some(error)));
             }
-            Ok(Async::Ready(None)) => {
-                trace!("   --> client dropped");
-                Ok(Async::Ready(None))
-            }
-            Ok(Async::Ready(Some(Err(e)))) => {
-                trace!("   --> error");
-                // An error on receive can only happen when the other half
-                // disconnected. In this case, the client needs to be
-                // shutdown
-                panic!("unimplemented error handling: {:?}", e);
+        }
+
+        canceled.into_iter().map(|(id, error)| {
+            let in_flight = self.in_flight.remove(&id).unwrap();
+            self.hold(id.clone());
+
+            if let Some(error) = error {
+                debug!("request canceled; id={:?}; err={}", id, error);
+                in_flight.complete.complete(Err(error.into()));
             }
+
+            id
+        }).collect()
+    }
+
+    fn poll_going_away(&mut self) -> Vec<Self::RequestId> {
+        if self.going_away {
+            return Vec::new();
+        }
+
+        let last = match P::peer_going_away(&mut self.transport) {
+            Some(GoAway { last_processed }) => last_processed,
+            None => return Vec::new(),
+        };
+
+        debug!("server going away; last_processed={:?}", last);
+        self.going_away = true;
+
+        let unprocessed = {
+            let rid_src = &self.rid_src;
+
+            self.in_flight.keys().filter(|id| {
+                match last {
+                    Some(ref last) => rid_src.issued_after(id, last),
+                    None => true,
+                }
+            }).cloned().collect::<Vec<_>>()
+        };
+
+        for id in &unprocessed {
+            let in_flight = self.in_flight.remove(id).unwrap();
+            self.rid_src.release(id);
+            in_flight.complete.complete(Err(io::Error::from(NotProcessed).into()));
+        }
+
+        unprocessed
+    }
+}
+
+impl<P, T, B> Dispatch<P, T, B> where
+    P: ClientProto<T> + BindClient<StreamingMultiplex<B>, T>,
+    T: 'static,
+    B: Stream<Item = P::RequestBody, Error = P::Error> + 'static,
+{
+    // Reserves the id of a canceled exchange for its late response
+    fn hold(&mut self, id: P::RequestId) {
+        let mut timeout = match Timeout::new(self.late_response_timeout, &self.handle) {
+            Ok(timeout) => timeout,
+            Err(_) => return self.rid_src.release(&id),
+        };
+
+        // Register interest in the timeout, it is checked for in
+        // `expire_canceled` on a later tick
+        match timeout.poll() {
             Ok(Async::NotReady) => {
-                trace!("   --> not ready");
-                Ok(Async::NotReady)
+                self.canceled.insert(id, timeout);
             }
-            Err(()) => panic!(),
+            _ => self.rid_src.release(&id),
         }
     }
 
-    fn poll_ready(&self) -> Async<()> {
-        // Not capping the client yet
-        Async::Ready(())
+    // Releases the ids of the canceled exchanges whose late response did
+    // not arrive in time
+    fn expire_canceled(&mut self) {
+        let expired = self.canceled.iter_mut().filter_map(|(id, timeout)| {
+            // The reactor timer can not fail once created, treat it as fired
+            // if it does
+            match timeout.poll() {
+                Ok(Async::NotReady) => None,
+                _ => Some(id.clone()),
+            }
+        }).collect::<Vec<_>>();
+
+        for id in expired {
+            debug!("gave up on late response; id={:?}", id);
+            self.canceled.remove(&id);
+            self.rid_src.release(&id);
+        }
     }
 
-    fn cancel(&mut self, _request_id: Self::RequestId) -> io::Result<()> {
-        // TODO: implement
-        Ok(())
+    // Fails the requests made after the server announced it is going away,
+    // without writing them
+    fn fail_requests(&mut self) -> Poll<Option<MultiplexMessage<P::RequestId, P::Request, B, P::Error>>, io::Error> {
+        loop {
+            match self.requests.poll() {
+                Ok(Async::Ready(Some(Ok((_, complete))))) => {
+                    trace!("   --> failing request; server going away");
+                    complete.complete(Err(io::Error::from(NotProcessed).into()));
+                }
+                Ok(Async::Ready(None)) => return Ok(Async::Ready(None)),
+                Ok(Async::Ready(Some(Err(e)))) => {
+                    panic!("unimplemented error handling: {:?}", e);
+                }
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(()) => panic!(),
+            }
+        }
     }
 }
 
@@ -199,8 +540,25 @@ impl<P, T, B> Drop for Dispatch<P, T, B> where
         }
 
         // Complete any pending requests with an error
-        for (_, complete) in self.in_flight.drain() {
-            complete.complete(Err(broken_pipe().into()));
+        for (id, in_flight) in self.in_flight.drain() {
+            in_flight.complete.complete(Err(broken_pipe().into()));
+            self.rid_src.release(&id);
+        }
+
+        for (id, _) in self.canceled.drain() {
+            self.rid_src.release(&id);
+        }
+    }
+}
+
+impl<R, E> InFlight<R, E> {
+    /// Returns true once the request timed out
+    fn poll_timeout(&mut self) -> bool {
+        match self.timeout {
+            // The reactor timer can not fail once created, treat it as fired
+            // if it does
+            Some(ref mut timeout) => timeout.poll().map(|a| a.is_ready()).unwrap_or(true),
+            None => false,
         }
     }
 }
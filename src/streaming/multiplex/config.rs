@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use {BindConfig, KindConfig};
+
+/// Tuning knobs for multiplexed connections.
+///
+/// Returned by the `config` method of the multiplex protocol traits and used
+/// for every connection bound by the protocol, unless the `BindConfig` of
+/// the connection has one, see `TcpServer::config`. With the `serde` feature
+/// enabled the struct can be deserialized, e.g. from a configuration file;
+/// missing fields take their default values.
+///
+/// `BufferProfile` offers presets for the buffering related fields.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct MultiplexConfig {
+    /// Max number of requests a server processes concurrently on a single
+    /// connection. Once reached, the next new request waits until one of the
+    /// requests completes, while the frames of those in progress, e.g. their
+    /// body chunks, keep being read. Once yet another new request arrives
+    /// meanwhile, the connection stops reading from the transport. Defaults
+    /// to 32.
+    pub max_in_flight: usize,
+
+    /// Max number of body frames buffered for slow body consumers across all
+    /// exchanges of a connection. Must not be zero. Defaults to 128.
+    pub max_buffered_frames: usize,
+
+    /// Max number of body frames buffered for the slow consumer of a single
+    /// exchange, keeping one body from taking up the whole
+    /// `max_buffered_frames`. Must not be zero. Defaults to `None`, limiting
+    /// only the frames buffered across all exchanges.
+    pub max_buffered_frames_per_exchange: Option<usize>,
+
+    /// What happens once buffering another body frame would exceed either
+    /// cap. Defaults to `SpillPolicy::Block`.
+    pub spill_policy: SpillPolicy,
+
+    /// Max number of frames a connection reads from and writes to the
+    /// transport in a single poll. Once reached, the connection task yields
+    /// to the other tasks of the event loop and resumes on its next turn,
+    /// bounding the time a busy connection holds the event loop. Must not be
+    /// zero. Defaults to `None`, processing frames for as long as possible.
+    /// A lower cap set by the `BindConfig` of the connection takes
+    /// precedence.
+    pub max_frames_per_poll: Option<usize>,
+
+    /// Max number of error frames a connection writes per
+    /// `error_frame_window`. Once exceeded, the connection is closed rather
+    /// than answering a flood of bad requests with as many errors. Defaults
+    /// to `None`, writing any number of error frames.
+    pub max_error_frames: Option<usize>,
+
+    /// Time window `max_error_frames` applies to. Defaults to one second.
+    pub error_frame_window: Duration,
+
+    /// Max number of body buffers kept for reuse by the `BufferPool` handed
+    /// to the transport. Defaults to 16.
+    pub max_pooled_buffers: usize,
+
+    /// Close the connection with a `Violation` when the peer breaks the
+    /// rules of the protocol in a way the dispatchers otherwise panic on,
+    /// e.g. reusing the id of an exchange in progress or sending a solo
+    /// message the connection can't handle. Servers talking to untrusted
+    /// peers should enable it. Defaults to `false`, panicking as a debugging
+    /// aid.
+    pub strict: bool,
+
+    /// Time a response waits for its commit before the request fails with
+    /// a `TimedOut` error, for protocols holding back responses with a
+    /// `Committer`. Defaults to `None`, waiting for as long as it takes.
+    pub commit_timeout: Option<Duration>,
+
+    /// Time a server's service has to answer a request. Once it expires,
+    /// the response future is dropped and the request fails with a
+    /// `TimedOut` error frame, freeing its slot among the `max_in_flight`
+    /// ones. The `request_timeout` hook of the server protocol can set
+    /// another one per request. Defaults to `None`, waiting for as long as
+    /// it takes.
+    pub request_timeout: Option<Duration>,
+
+    /// Max length of a message read from the transport, for transports
+    /// reporting the length of the message being read with
+    /// `pending_read_len`. A longer message fails the connection with an
+    /// `InvalidData` error as soon as the transport reports it, rather than
+    /// once it was buffered whole. Defaults to `None`, no limit.
+    pub max_message_len: Option<usize>,
+}
+
+impl KindConfig for MultiplexConfig {
+    fn set(self, binding: &mut BindConfig) {
+        binding.multiplex = Some(self);
+    }
+}
+
+impl Default for MultiplexConfig {
+    fn default() -> MultiplexConfig {
+        MultiplexConfig {
+            max_in_flight: 32,
+            max_buffered_frames: 128,
+            max_buffered_frames_per_exchange: None,
+            spill_policy: SpillPolicy::Block,
+            max_frames_per_poll: None,
+            max_error_frames: None,
+            error_frame_window: Duration::from_secs(1),
+            max_pooled_buffers: 16,
+            strict: false,
+            commit_timeout: None,
+            request_timeout: None,
+            max_message_len: None,
+        }
+    }
+}
+
+/// How a multiplexed connection handles body frames its slow consumers can't
+/// keep up with once the frame buffer caps of `MultiplexConfig` are reached.
+///
+/// With the `serde` feature enabled the policy deserializes from its name:
+/// `"block"`, `"drop-connection"` or `"error-exchange"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum SpillPolicy {
+    /// Stop reading from the transport until the consumers catch up, pushing
+    /// back on the peer. A consumer that never catches up stalls every other
+    /// exchange of the connection.
+    Block,
+
+    /// Close the connection with an error.
+    DropConnection,
+
+    /// Fail the body of the exchange whose frame didn't fit with an error,
+    /// discard its buffered frames and cancel the rest of the body with the
+    /// peer. The other exchanges carry on.
+    ErrorExchange,
+}
@@ -1,3 +1,5 @@
+use streaming::Chunk;
+
 /// A multiplexed protocol frame
 #[derive(Debug, Clone)]
 pub enum Frame<RequestId, T, B, E> {
@@ -13,6 +15,23 @@ pub enum Frame<RequestId, T, B, E> {
         /// direction
         solo: bool,
     },
+    /// A message along with its whole body, for protocols where small
+    /// bodies fit in the first frame.
+    ///
+    /// Read from a transport, it is dispatched with a body holding `chunk`
+    /// without setting up a body channel. The dispatchers only write
+    /// messages and body frames, never `Inline` ones.
+    Inline {
+        /// Message exchange identifier
+        id: RequestId,
+        /// The message value
+        message: T,
+        /// The whole body
+        chunk: B,
+        /// Set to `true` when this message does not have a pair in the other
+        /// direction
+        solo: bool,
+    },
     /// Body frame.
     Body {
         /// Message exchange identifier
@@ -29,6 +48,20 @@ pub enum Frame<RequestId, T, B, E> {
         /// Error value
         error: E,
     },
+    /// Acknowledges that the request has been accepted, ahead of its
+    /// response.
+    Ack {
+        /// Message exchange identifier
+        id: RequestId,
+    },
+    /// Interim response reporting the progress of a request, ahead of its
+    /// final response.
+    Progress {
+        /// Message exchange identifier
+        id: RequestId,
+        /// The progress update
+        message: T,
+    },
 }
 
 impl<RequestId: Clone, T, B, E> Frame<RequestId, T, B, E> {
@@ -36,8 +69,11 @@ impl<RequestId: Clone, T, B, E> Frame<RequestId, T, B, E> {
     pub fn request_id(&self) -> &RequestId {
         match *self {
             Frame::Message { ref id, .. } => id,
+            Frame::Inline { ref id, .. } => id,
             Frame::Body { ref id, .. } => id,
             Frame::Error { ref id, .. } => id,
+            Frame::Ack { ref id } => id,
+            Frame::Progress { ref id, .. } => id,
         }
     }
 
@@ -45,8 +81,11 @@ impl<RequestId: Clone, T, B, E> Frame<RequestId, T, B, E> {
     pub fn unwrap_msg(self) -> T {
         match self {
             Frame::Message { message, .. } => message,
+            Frame::Inline { message, .. } => message,
             Frame::Body { .. } => panic!("called `Frame::unwrap_msg()` on a `Body` value"),
             Frame::Error { .. } => panic!("called `Frame::unwrap_msg()` on an `Error` value"),
+            Frame::Ack { .. } => panic!("called `Frame::unwrap_msg()` on an `Ack` value"),
+            Frame::Progress { .. } => panic!("called `Frame::unwrap_msg()` on a `Progress` value"),
         }
     }
 
@@ -55,7 +94,10 @@ impl<RequestId: Clone, T, B, E> Frame<RequestId, T, B, E> {
         match self {
             Frame::Body { chunk, .. } => chunk,
             Frame::Message { .. } => panic!("called `Frame::unwrap_body()` on a `Message` value"),
+            Frame::Inline { .. } => panic!("called `Frame::unwrap_body()` on an `Inline` value"),
             Frame::Error { .. } => panic!("called `Frame::unwrap_body()` on an `Error` value"),
+            Frame::Ack { .. } => panic!("called `Frame::unwrap_body()` on an `Ack` value"),
+            Frame::Progress { .. } => panic!("called `Frame::unwrap_body()` on a `Progress` value"),
         }
     }
 
@@ -65,6 +107,23 @@ impl<RequestId: Clone, T, B, E> Frame<RequestId, T, B, E> {
             Frame::Error { error, .. } => error,
             Frame::Body { .. } => panic!("called `Frame::unwrap_err()` on a `Body` value"),
             Frame::Message { .. } => panic!("called `Frame::unwrap_err()` on a `Message` value"),
+            Frame::Inline { .. } => panic!("called `Frame::unwrap_err()` on an `Inline` value"),
+            Frame::Ack { .. } => panic!("called `Frame::unwrap_err()` on an `Ack` value"),
+            Frame::Progress { .. } => panic!("called `Frame::unwrap_err()` on a `Progress` value"),
+        }
+    }
+}
+
+impl<RequestId: Clone, T, B: Chunk, E> Frame<RequestId, T, B, E> {
+    /// Splits a body frame whose chunk is longer than `max_len`, keeping the
+    /// first `max_len` bytes and returning the rest as a body frame of its
+    /// own, e.g. to implement `Transport::split_body`.
+    pub fn split_chunk(&mut self, max_len: usize) -> Option<Frame<RequestId, T, B, E>> {
+        match *self {
+            Frame::Body { ref id, chunk: Some(ref mut chunk) } if chunk.len() > max_len => {
+                Some(Frame::Body { id: id.clone(), chunk: Some(chunk.split_off(max_len)) })
+            }
+            _ => None,
         }
     }
 }
@@ -25,8 +25,14 @@ const INITIAL_BLOCK_SIZE: usize = 32;
 const MAX_CAPACITY: usize = 1_048_576;
 
 struct Inner<T> {
-    // Max number of elements that can be stored
+    // Max number of elements that can be allocated
     max_capacity: usize,
+    // Max number of elements stored at once
+    max_len: usize,
+    // Max number of elements stored at once by a single deque
+    max_deque_len: Option<usize>,
+    // Number of stored elements
+    len: usize,
     // Number of allocated elements
     allocated: usize,
     // Free slot stack
@@ -42,15 +48,27 @@ struct Slot<T> {
 
 impl<T> FrameBuf<T> {
     /// Return a new `FrameBuf` with the given capacity
+    #[cfg(test)]
     pub fn with_capacity(capacity: usize) -> FrameBuf<T> {
+        FrameBuf::with_deque_capacity(capacity, None)
+    }
+
+    /// Return a new `FrameBuf` with the given capacity, additionally limiting
+    /// the number of frames stored by each deque
+    pub fn with_deque_capacity(capacity: usize, deque_capacity: Option<usize>) -> FrameBuf<T> {
         assert!(capacity < MAX_CAPACITY,
                 "requested frame buffer capacity too large; max={}; requested={}",
                 MAX_CAPACITY, capacity);
 
-        let inner = UnsafeCell::new(Inner::with_capacity(capacity));
+        let inner = UnsafeCell::new(Inner::with_capacity(capacity, deque_capacity));
         FrameBuf { inner: Rc::new(inner) }
     }
 
+    /// Returns true if no more frames can be stored
+    pub fn is_full(&self) -> bool {
+        unsafe { &*self.inner.get() }.is_full()
+    }
+
     #[cfg(test)]
     pub fn capacity(&self) -> usize {
         unsafe { &*self.inner.get() }.max_capacity
@@ -72,12 +90,26 @@ impl<T> FrameBuf<T> {
 }
 
 impl<T> FrameDeque<T> {
+    #[cfg(test)]
     pub fn push(&self, val: T) {
+        if self.try_push(val).is_err() {
+            panic!("FrameBuf out of capacity");
+        }
+    }
+
+    /// Push the value unless either the deque or the shared buffer is full,
+    /// handing the value back otherwise
+    pub fn try_push(&self, val: T) -> Result<(), T> {
+        if self.is_full() {
+            return Err(val);
+        }
+
         unsafe {
             let ptr;
-            let slot = (*self.inner.get())
-                .reserve_slot()
-                .expect("FrameBuf out of capacity");
+            let slot = match (*self.inner.get()).reserve_slot() {
+                Some(slot) => slot,
+                None => return Err(val),
+            };
 
             debug_assert!(slot.next.is_null());
 
@@ -94,6 +126,8 @@ impl<T> FrameDeque<T> {
 
             self.tail.set(ptr);
         }
+
+        Ok(())
     }
 
     pub fn push_front(&self, val: T) {
@@ -138,6 +172,7 @@ impl<T> FrameDeque<T> {
 
                 head.next = inner.free;
                 inner.free = ptr;
+                inner.len -= 1;
 
                 if val.is_none() {
                     assert!(self.len() == 0);
@@ -162,22 +197,51 @@ impl<T> FrameDeque<T> {
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
+
+    /// Returns true if no more frames can be pushed to this deque
+    pub fn is_full(&self) -> bool {
+        let inner = unsafe { &*self.inner.get() };
+
+        match inner.max_deque_len {
+            Some(max) if self.len() >= max => true,
+            _ => inner.is_full(),
+        }
+    }
 }
 
-impl<T> Inner<T> {
-    fn with_capacity(mut capacity: usize) -> Inner<T> {
-        capacity = cmp::max(INITIAL_BLOCK_SIZE, capacity.next_power_of_two());
+impl<T> Drop for FrameDeque<T> {
+    fn drop(&mut self) {
+        // Return the slots to the shared buffer
+        self.clear();
+    }
+}
 
+impl<T> Inner<T> {
+    fn with_capacity(len: usize, deque_len: Option<usize>) -> Inner<T> {
+        let capacity = cmp::max(INITIAL_BLOCK_SIZE, len.next_power_of_two());
 
         Inner {
             max_capacity: capacity,
+            max_len: len,
+            max_deque_len: deque_len,
+            len: 0,
             allocated: 0,
             free: ptr::null_mut(),
             blocks: SmallVec::new(),
         }
     }
 
+    fn is_full(&self) -> bool {
+        self.len >= self.max_len
+    }
+
     fn reserve_slot(&mut self) -> Option<&mut Slot<T>> {
+        if self.is_full() {
+            return None;
+        }
+
+        self.len += 1;
+
         unsafe {
             // If there is a pre-allocated available slot, use that
             if let Some(slot) = self.free.as_mut() {
@@ -196,6 +260,7 @@ impl<T> Inner<T> {
             };
 
             if grow && !self.grow() {
+                self.len -= 1;
                 return None;
             }
 
@@ -313,6 +378,43 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_exact_capacity() {
+        let fb = FrameBuf::with_capacity(33);
+        let d1 = fb.deque();
+        let d2 = fb.deque();
+
+        for i in 0..33 {
+            d1.push(i);
+        }
+
+        assert!(fb.is_full());
+        assert_eq!(Err(33), d2.try_push(33));
+
+        // Dropping a deque returns its frames to the buffer
+        drop(d1);
+        assert!(!fb.is_full());
+        assert_eq!(Ok(()), d2.try_push(33));
+    }
+
+    #[test]
+    fn test_deque_capacity() {
+        let fb = FrameBuf::with_deque_capacity(64, Some(2));
+        let d1 = fb.deque();
+        let d2 = fb.deque();
+
+        assert_eq!(Ok(()), d1.try_push(0));
+        assert_eq!(Ok(()), d1.try_push(1));
+        assert!(d1.is_full());
+        assert_eq!(Err(2), d1.try_push(2));
+
+        assert!(!fb.is_full());
+        assert_eq!(Ok(()), d2.try_push(0));
+
+        assert_eq!(Some(0), d1.pop());
+        assert_eq!(Ok(()), d1.try_push(2));
+    }
+
     #[test]
     fn test_multiple_deque() {
         let fb = FrameBuf::with_capacity(64);
@@ -2,11 +2,16 @@
 //!
 //! See the crate-level docs for an overview.
 
-use std::io;
+use std::{error, fmt, io};
 use std::hash::Hash;
 use std::fmt::Debug;
+#[cfg(feature = "rand")]
+use std::collections::HashSet;
 use futures::{Stream, Sink, Async};
 use tokio_core::io::{Io, Framed, Codec};
+use tokio_core::reactor::Handle;
+use std::sync::Arc;
+use streaming::{BufferProvider, ConnectionId, Encodings, Stats};
 
 mod frame_buf;
 
@@ -14,14 +19,25 @@ mod client;
 pub use self::client::ClientProto;
 
 mod server;
-pub use self::server::ServerProto;
+pub use self::server::{ServerProto, server_task};
 
 mod frame;
 pub use self::frame::Frame;
 
+mod config;
+pub use self::config::{MultiplexConfig, SpillPolicy};
+
+mod push;
+pub use self::push::Push;
+
 pub mod advanced;
 
 /// Identifies a request / response thread
+///
+/// Any type that can be cloned, hashed and compared works, including ones
+/// that are not `Copy`, such as strings or UUIDs. The multiplexer clones ids
+/// as needed, so ids that are expensive to clone should be shared, e.g. in an
+/// `Rc`.
 pub trait RequestId: Clone + Hash + Eq + Debug + 'static {}
 
 impl<T: Clone + Hash + Eq + Debug + 'static> RequestId for T {}
@@ -33,26 +49,218 @@ impl<T: Clone + Hash + Eq + Debug + 'static> RequestId for T {}
 pub trait RequestIdSource<Id, T>: 'static {
     /// Generate the next request id or look it up from the message
     fn next(&mut self, msg: &T) -> Id;
+
+    /// Called by the dispatcher once the exchange identified by `id` has
+    /// completed and the id may be handed out again.
+    fn release(&mut self, id: &Id) {
+        let _ = id;
+    }
+
+    /// Returns true if `id` was handed out after `other`.
+    ///
+    /// Used to tell which requests a server going away did not process, see
+    /// `GoAway`. By default false, so that no request is assumed to be
+    /// unprocessed.
+    fn issued_after(&self, id: &Id, other: &Id) -> bool {
+        let _ = (id, other);
+        false
+    }
 }
 
-/// `RequestIdSource` generated from by an u64 counter
-pub struct Counter(u64);
+/// `RequestIdSource` generated from a counter of the width of the request
+/// ids, u64 by default.
+///
+/// Protocols with narrower ids, e.g. u16, use a counter of that width so that
+/// frames and in-flight bookkeeping do not carry wider ids than the wire.
+/// The counter wraps around once all ids were handed out, which is fine as
+/// long as fewer requests than there are ids are in flight at once.
+#[derive(Clone)]
+pub struct Counter<Id = u64>(Id);
 
-impl Counter {
+impl<Id: Default> Counter<Id> {
     /// Initialize the counter with value 0
     pub fn new() -> Self {
-        Counter(0)
+        Counter(Id::default())
+    }
+}
+
+macro_rules! counter {
+    ($($id:ty => $signed:ty),*) => {$(
+        impl<T> RequestIdSource<$id, T> for Counter<$id> {
+            fn next(&mut self, _: &T) -> $id {
+                let ret = self.0;
+                self.0 = self.0.wrapping_add(1);
+                ret
+            }
+
+            // Compares ids in serial number arithmetic, so that ids handed
+            // out after the counter wrapped around are still found later
+            fn issued_after(&self, id: &$id, other: &$id) -> bool {
+                (id.wrapping_sub(*other) as $signed) > 0
+            }
+        }
+    )*}
+}
+
+counter!(u8 => i8, u16 => i16, u32 => i32, u64 => i64, usize => isize);
+
+/// `RequestIdSource` generating unpredictable u64 ids.
+///
+/// Ids are drawn from a ChaCha generator seeded by the operating system. The
+/// ids of in-flight exchanges are tracked so that a freshly drawn id never
+/// collides with one that is still awaiting its response.
+#[cfg(feature = "rand")]
+pub struct RandomIds {
+    rng: ::rand::ChaChaRng,
+    in_flight: HashSet<u64>,
+}
+
+#[cfg(feature = "rand")]
+impl RandomIds {
+    /// Create a new `RandomIds`, seeded from the operating system's random
+    /// number generator.
+    pub fn new() -> io::Result<Self> {
+        use rand::Rng;
+
+        let mut os = try!(::rand::OsRng::new());
+
+        Ok(RandomIds {
+            rng: os.gen(),
+            in_flight: HashSet::new(),
+        })
     }
 }
 
-impl<T> RequestIdSource<u64, T> for Counter {
+#[cfg(feature = "rand")]
+impl<T> RequestIdSource<u64, T> for RandomIds {
     fn next(&mut self, _: &T) -> u64 {
-        let ret = self.0;
-        self.0 += 1;
-        ret
+        use rand::Rng;
+
+        loop {
+            let id = self.rng.next_u64();
+
+            if self.in_flight.insert(id) {
+                return id;
+            }
+
+            trace!("random request id collision; id={:?}", id);
+        }
+    }
+
+    fn release(&mut self, id: &u64) {
+        self.in_flight.remove(id);
+    }
+}
+
+
+/// `RequestIdValidator` checks the ids of incoming requests against the rules
+/// of the protocol before the request is dispatched to the service.
+///
+/// A validator is created per connection, so it may keep state such as the
+/// last seen id to enforce monotonicity.
+pub trait RequestIdValidator<Id>: 'static {
+    /// Check the request id, returning a `Violation` if the id is not
+    /// acceptable. Requests with rejected ids are answered with an error frame
+    /// and never reach the service.
+    fn validate_request_id(&mut self, id: &Id) -> Result<(), Violation>;
+}
+
+impl<Id, F> RequestIdValidator<Id> for F
+    where F: FnMut(&Id) -> Result<(), Violation> + 'static
+{
+    fn validate_request_id(&mut self, id: &Id) -> Result<(), Violation> {
+        self(id)
+    }
+}
+
+/// `RequestIdValidator` accepting every request id
+pub struct AnyRequestId;
+
+impl<Id> RequestIdValidator<Id> for AnyRequestId {
+    fn validate_request_id(&mut self, _: &Id) -> Result<(), Violation> {
+        Ok(())
+    }
+}
+
+/// A violation of the protocol rules by the peer
+#[derive(Debug, Clone)]
+pub struct Violation {
+    description: String,
+}
+
+impl Violation {
+    /// Create a new `Violation` with the given description
+    pub fn new<S: Into<String>>(description: S) -> Violation {
+        Violation { description: description.into() }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "protocol violation: {}", self.description)
+    }
+}
+
+impl error::Error for Violation {
+    fn description(&self) -> &str {
+        &self.description
+    }
+}
+
+impl From<Violation> for io::Error {
+    fn from(violation: Violation) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, violation)
+    }
+}
+
+/// Announcement of a peer going away, e.g. a server draining before it shuts
+/// down.
+///
+/// The peer processes the requests up to and including `last_processed`, in
+/// the order their ids were handed out by the `RequestIdSource`, and drops
+/// the later ones. Multiplex clients fail the dropped requests with a
+/// `NotProcessed` error, as they can safely be sent again on another
+/// connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GoAway<Id> {
+    /// Id of the last request processed by the peer, if any
+    pub last_processed: Option<Id>,
+}
+
+/// Error failing the requests a peer going away did not process
+///
+/// See `GoAway`. The requests never reached the service of the peer, so they
+/// can be sent again on another connection, e.g. with a `reissue::Reissue` client.
+#[derive(Debug, Clone)]
+pub struct NotProcessed;
+
+impl fmt::Display for NotProcessed {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "request not processed by a peer going away")
+    }
+}
+
+impl error::Error for NotProcessed {
+    fn description(&self) -> &str {
+        "request not processed by a peer going away"
     }
 }
 
+impl From<NotProcessed> for io::Error {
+    fn from(not_processed: NotProcessed) -> io::Error {
+        io::Error::new(io::ErrorKind::ConnectionAborted, not_processed)
+    }
+}
+
+// Reports a violation by the peer, as an error closing the connection in
+// strict mode and as a panic otherwise
+fn violation(strict: bool, description: String) -> io::Error {
+    if !strict {
+        panic!("{}", description);
+    }
+
+    Violation::new(description).into()
+}
 
 /// A marker used to flag protocols as being streaming and multiplexed.
 ///
@@ -81,17 +289,187 @@ pub trait Transport<RequestId, ReadBody>: 'static +
     }
 
     /// Tests to see if this I/O object may accept a body frame for the given
-    /// request ID
+    /// request ID, e.g. as long as the peer granted write credit for the
+    /// exchange.
+    ///
+    /// While not ready, the dispatcher does not poll the body stream of the
+    /// exchange at all, so that its producer is throttled instead of chunks
+    /// piling up (see `BodySender::poll_ready`). The transport must notify
+    /// the current task once it may accept body frames again.
     fn poll_write_body(&mut self, id: RequestId) -> Async<()> {
         drop(id);
         Async::Ready(())
     }
 
+    /// Invoked when the receiving end of the body stream for the given request
+    /// ID pauses the body. Transports supporting flow control should stop
+    /// granting the peer write credit for the exchange until `resume_body` is
+    /// called.
+    fn pause_body(&mut self, id: RequestId) {
+        let _ = id;
+    }
+
+    /// Invoked when a body paused with `pause_body` is resumed.
+    fn resume_body(&mut self, id: RequestId) {
+        let _ = id;
+    }
+
+    /// Shut down the write half of the transport.
+    ///
+    /// Called once by client dispatchers after all handles to the client have
+    /// been dropped and every request, including its body, has been flushed.
+    /// Responses continue to be read until all in-flight exchanges complete.
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Invoked after reading a frame failed with `error`, e.g. because the
+    /// peer sent bytes that don't decode.
+    ///
+    /// Transports able to skip to the next frame boundary, such as those of
+    /// datagram based protocols or protocols marking the start of frames, do
+    /// so and return true to have the multiplexer carry on reading. By
+    /// default false is returned and the connection is closed with the error.
+    fn resynchronize(&mut self, error: &io::Error) -> bool {
+        let _ = error;
+        false
+    }
+
+    /// Receives the id of the connection, called once when the multiplexer
+    /// is created.
+    ///
+    /// Errors originating in the multiplexer mention the same id, so it can be
+    /// used to correlate the transport's own logs with them.
+    fn on_connection(&mut self, id: ConnectionId) {
+        let _ = id;
+    }
+
+    /// Receives a handle to the event loop driving the connection, called
+    /// once by the bind functions of the multiplexed protocols before the multiplexer
+    /// is created.
+    ///
+    /// Lets the transport set up timers, e.g. those of a
+    /// `keepalive::KeepAlive`. By default the handle is ignored.
+    fn on_handle(&mut self, handle: &Handle) {
+        let _ = handle;
+    }
+
+    /// Receives the provider of the buffers body chunks are decoded into,
+    /// called once when the multiplexer is created.
+    ///
+    /// Codecs taking their buffers from the provider and wrapping them in
+    /// `PooledChunk`s get them back for reuse once the service drops the
+    /// chunks. By default the provider is ignored.
+    fn on_buffer_provider(&mut self, provider: Arc<BufferProvider>) {
+        let _ = provider;
+    }
+
+    /// Send a keep-alive ping to the peer.
+    ///
+    /// Called by `keepalive::KeepAlive` at its configured interval. By
+    /// default nothing is sent.
+    fn ping(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Receives statistics observed by the multiplexer, called at the end of
+    /// every tick.
+    fn on_stats(&mut self, stats: Stats) {
+        let _ = stats;
+    }
+
+    /// Receives the content encodings this end advertises, called once when
+    /// the multiplexer is created if the protocol advertises any.
+    ///
+    /// The transport is expected to write them to the peer ahead of any
+    /// other frame. By default nothing is written.
+    fn advertise_encodings(&mut self, encodings: &Encodings) {
+        let _ = encodings;
+    }
+
+    /// Returns the content encodings advertised by the peer, once read.
+    ///
+    /// Polled by the multiplexer after reading frames until it returns `Some`,
+    /// as long as an advertisement is pending. Defaults to `None`.
+    fn peer_encodings(&mut self) -> Option<Encodings> {
+        None
+    }
+
+    /// Returns the announcement of the peer going away, once read.
+    ///
+    /// Polled by multiplex clients after reading frames. Return `Some` once,
+    /// when the announcement was read; the requests the peer did not process
+    /// then fail with `NotProcessed`, as do the requests made afterwards.
+    /// Defaults to `None`.
+    fn peer_going_away(&mut self) -> Option<GoAway<RequestId>> {
+        None
+    }
+
+    /// Receives the content encoding agreed on with the peer, or `None` if
+    /// the connection stays unencoded.
+    ///
+    /// Called once by the multiplexer after `peer_encodings` returned the
+    /// peer's advertisement. Compression wrappers and codecs apply the
+    /// encoding to the frames written afterwards. By default the encoding is
+    /// ignored.
+    fn on_encoding(&mut self, encoding: Option<&str>) {
+        let _ = encoding;
+    }
+
     /// Invoked before the multiplexer dispatches the body chunk to the body
     /// stream.
     fn dispatching_body(&mut self, id: RequestId, body: &ReadBody) {
         drop(id);
-        drop(body);
+        let _ = body;
+    }
+
+    /// Merge the body frame `next` into `buffered`, the body frame about to
+    /// be written for the same exchange, or return `next` unchanged to write
+    /// it as a frame of its own.
+    ///
+    /// The multiplexer offers the chunks of a body that are ready at the same
+    /// time, so that chatty body producers can be written with fewer, larger
+    /// frames; a chunk is never held back waiting for more. Both frames are
+    /// `Frame::Body` frames carrying a chunk, and the size threshold is up to
+    /// the implementation, which should not exceed `max_body_frame_len`. By
+    /// default frames are never merged.
+    fn coalesce_body(&mut self, buffered: &mut Self::SinkItem, next: Self::SinkItem)
+                     -> Option<Self::SinkItem>
+    {
+        let _ = buffered;
+        Some(next)
+    }
+    /// Hint for the largest body chunk the transport writes in a single
+    /// frame. Larger chunks are split with `split_body` before being handed
+    /// to the transport, so that body producers can use buffers of any size.
+    ///
+    /// Defaults to `None`, no limit.
+    fn max_body_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    /// Split the body frame `frame` so that its chunk is at most `max_len`
+    /// long, returning the remainder as a body frame of its own.
+    ///
+    /// The remainder is split again as needed. Return `None` to write the
+    /// frame whole. Transports with `Chunk` bodies can use
+    /// `Frame::split_chunk`. By default frames are never split.
+    fn split_body(&mut self, frame: &mut Self::SinkItem, max_len: usize)
+                  -> Option<Self::SinkItem>
+    {
+        let _ = (frame, max_len);
+        None
+    }
+
+    /// Returns the length of the message being read but not decoded yet, as
+    /// far as it has been buffered, or as announced by the peer if longer.
+    ///
+    /// Checked by the dispatcher against the `max_message_len` of the
+    /// connection config after polling the transport, so that an over-limit
+    /// message fails the connection before it is buffered whole. See
+    /// `util::framed::framed_chunked`. Defaults to `None`, unknown.
+    fn pending_read_len(&self) -> Option<usize> {
+        None
     }
 }
 
@@ -0,0 +1,45 @@
+use std::io;
+
+use futures::sync::mpsc;
+
+/// Sends unsolicited messages from a multiplexed server to its client.
+///
+/// Handed to `ServerProto::on_bind` for every connection bound. Pushed
+/// messages are written as solo `Frame::Message` frames, which answer no
+/// request, so that services can implement notifications and pub/sub on top
+/// of request / response exchanges.
+///
+/// The id of a pushed message must not be in use by an exchange in flight;
+/// protocols usually reserve a range of ids for them. Pushed messages have no
+/// body.
+///
+/// Handles can be cloned and sent to other threads.
+pub struct Push<Id, T> {
+    tx: mpsc::UnboundedSender<(Id, T)>,
+}
+
+/// Receives the messages sent through the `Push` handles of a connection.
+pub type Pushed<Id, T> = mpsc::UnboundedReceiver<(Id, T)>;
+
+/// Creates a `Push` handle and the receiving end handed to the dispatcher.
+pub fn pair<Id, T>() -> (Push<Id, T>, Pushed<Id, T>) {
+    let (tx, rx) = mpsc::unbounded();
+    (Push { tx: tx }, rx)
+}
+
+impl<Id, T> Push<Id, T> {
+    /// Queue `message` to be written to the client with the given id.
+    ///
+    /// Fails once the connection has been closed.
+    pub fn push(&self, id: Id, message: T) -> io::Result<()> {
+        self.tx.unbounded_send((id, message)).map_err(|_| {
+            io::Error::new(io::ErrorKind::BrokenPipe, "connection closed")
+        })
+    }
+}
+
+impl<Id, T> Clone for Push<Id, T> {
+    fn clone(&self) -> Push<Id, T> {
+        Push { tx: self.tx.clone() }
+    }
+}
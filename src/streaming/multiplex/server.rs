@@ -1,13 +1,22 @@
-use super::{Frame, RequestId, Transport};
-use super::advanced::{Multiplex, MultiplexMessage};
-
-use BindServer;
-use streaming::{Message, Body};
+use super::{Frame, RequestId, RequestIdValidator, AnyRequestId, StreamingMultiplex, Transport, MultiplexConfig};
+use super::advanced::MultiplexMessage;
+use super::violation;
+use super::push::{self, Push, Pushed};
+
+use {BindConfig, BindServer, ProtocolKind};
+use streaming::{budget, Message, Body, Committer, Encodings, Negotiation};
+use streaming::commit::Commit;
+use streaming::interim::{Interim, Interims};
 use tokio_service::Service;
-use tokio_core::reactor::Handle;
+use tokio_core::reactor::{Handle, Timeout};
 use futures::{Future, Poll, Async};
 use futures::{IntoFuture, Stream};
+use std::collections::VecDeque;
 use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use timeout::Deadline;
+use instrument::ConnectionObserver;
 
 /// A streaming, multiplexed server protocol.
 ///
@@ -68,6 +77,121 @@ pub trait ServerProto<T: 'static>: 'static {
     /// Build a transport from the given I/O object, using `self` for any
     /// configuration.
     fn bind_transport(&self, io: T) -> Self::BindTransport;
+
+    /// Create a `RequestIdValidator` used to check the ids of requests
+    /// received on a single connection before they are dispatched.
+    ///
+    /// By default every request id is accepted.
+    fn request_id_validator(&self) -> Box<RequestIdValidator<Self::RequestId>> {
+        Box::new(AnyRequestId)
+    }
+
+    /// Tuning knobs applied to every connection bound by this protocol.
+    ///
+    /// Defaults to `MultiplexConfig::default()`.
+    fn config(&self) -> MultiplexConfig {
+        MultiplexConfig::default()
+    }
+
+    /// Content encodings this end supports, most preferred first.
+    ///
+    /// When not empty, connections negotiate their content encoding: the
+    /// transport advertises the list to the peer and is told the agreed
+    /// encoding through its `on_encoding` hook. See `Negotiation`. Defaults
+    /// to none, leaving connections unencoded.
+    fn encodings(&self) -> Encodings {
+        Encodings::new()
+    }
+
+    /// Lets the transport answer `request` itself, without dispatching it to
+    /// the service.
+    ///
+    /// Called for every request read from the transport. Returning a
+    /// response writes it in place of one from the service, keeping protocol
+    /// plumbing such as PINGs out of services. The body of an answered
+    /// request, if any, is dropped. Defaults to dispatching every request.
+    fn answer_inline(transport: &mut Self::Transport, request: &Self::Request)
+                     -> Option<Self::Response>
+    {
+        let _ = (transport, request);
+        None
+    }
+
+    /// Returns true if the connection ends with `response`, e.g. the answer
+    /// to a QUIT command.
+    ///
+    /// Called for every response before it is written. Once one closes the
+    /// connection, no more requests are read and nothing more is pushed; the
+    /// requests in flight are answered, their bodies written out and the
+    /// transport is shut down. Defaults to keeping the connection open.
+    fn closes_connection(response: &Self::Response) -> bool {
+        let _ = response;
+        false
+    }
+
+    /// Returns the key of `request` if its response must not overtake those
+    /// to earlier requests with the same key.
+    ///
+    /// Called for every request read from the transport. Responses sharing
+    /// a key are written in the order their requests were read, holding
+    /// back ready ones as needed, while responses to requests without a key
+    /// or with another one are written as soon as they are ready. Defaults
+    /// to none, leaving responses unordered.
+    fn ordering_key(request: &Self::Request) -> Option<u64> {
+        let _ = request;
+        None
+    }
+
+    /// Returns the time the service has to answer `request`, overriding the
+    /// `request_timeout` of the config.
+    ///
+    /// Called for every request dispatched to the service. Once the time
+    /// expires, the response future is dropped and the request fails with a
+    /// `TimedOut` error frame. Defaults to none, applying the config's.
+    fn request_timeout(request: &Self::Request) -> Option<Duration> {
+        let _ = request;
+        None
+    }
+
+    /// Receives the `Interim` handle of `request`, called for every request
+    /// dispatched to the service.
+    ///
+    /// Keep the handle, e.g. in the request, to write interim responses such
+    /// as resource hints ahead of the final response. By default the handle
+    /// is dropped.
+    fn on_interim(request: &mut Self::Request, interim: Interim<Self::Response>) {
+        let _ = (request, interim);
+    }
+
+    /// Create the `Committer` holding back the responses of a connection
+    /// until they are confirmed, e.g. durable.
+    ///
+    /// Only the responses of the service are committed, not those answered
+    /// by the transport nor pushed messages. Defaults to none, writing
+    /// responses as soon as they are ready.
+    fn committer(&self) -> Option<Box<Committer<Self::Response>>> {
+        None
+    }
+
+    /// Returns true to acknowledge requests with an `Ack` frame as soon as
+    /// they are handed to the service, ahead of their response.
+    ///
+    /// Clients expose the ack through `Response::accepted`. Requests that
+    /// are rejected or answered inline are not acknowledged. Defaults to
+    /// `false`.
+    fn ack_requests(&self) -> bool {
+        false
+    }
+
+    /// Receives the `Push` handle of a connection, called once by the bind
+    /// functions for every connection, before its transport is bound.
+    ///
+    /// Keep the handle to send the client messages it did not ask for, such
+    /// as notifications. Messages pushed before the connection is ready are
+    /// written first. By default the handle is dropped.
+    fn on_bind(&self, push: Push<Self::RequestId, Self::Response>) {
+        let _ = push;
+    }
 }
 
 impl<P, T, B> BindServer<super::StreamingMultiplex<B>, T> for P where
@@ -84,18 +208,120 @@ impl<P, T, B> BindServer<super::StreamingMultiplex<B>, T> for P where
                          Response = Self::ServiceResponse,
                          Error = Self::ServiceError> + 'static
     {
-        let task = self.bind_transport(io).into_future().and_then(|transport| {
-            let dispatch: Dispatch<S, T, P> = Dispatch {
-                service: service,
-                transport: transport,
-                in_flight: vec![],
-            };
-            Multiplex::new(dispatch)
-        }).map_err(|_| ());
+        bind::<P, T, B, S, ()>(self, handle, io, service, &BindConfig::default(), ())
+    }
 
-        // Spawn the multiplex dispatcher
-        handle.spawn(task)
+    fn bind_server_timeout<S>(&self, handle: &Handle, io: T, service: S, timeout: Duration)
+        where S: Service<Request = Self::ServiceRequest,
+                         Response = Self::ServiceResponse,
+                         Error = Self::ServiceError> + 'static
+    {
+        bind::<P, T, B, S, ()>(self, handle, io, service, &BindConfig { timeout: Some(timeout), ..BindConfig::default() }, ())
     }
+
+    fn bind_server_guarded<S, G>(&self, handle: &Handle, io: T, service: S,
+                                 config: &BindConfig, guard: G)
+        where S: Service<Request = Self::ServiceRequest,
+                         Response = Self::ServiceResponse,
+                         Error = Self::ServiceError> + 'static,
+              G: 'static,
+    {
+        bind::<P, T, B, S, G>(self, handle, io, service, config, guard)
+    }
+}
+
+/// Returns the task serving `io` with `service`, like
+/// `BindServer::bind_server` binds it, but without spawning it.
+///
+/// The task completes once the connection was shut down, and fails with the
+/// error failing the connection. A server of a single connection, e.g. one
+/// answering datagrams on a socket, can run it to find out how it ended.
+pub fn server_task<P, T, B, S>(proto: &P, handle: &Handle, io: T, service: S)
+                               -> Box<Future<Item = (), Error = io::Error>>
+    where P: ServerProto<T>,
+          T: 'static,
+          B: Stream<Item = P::ResponseBody, Error = P::Error>,
+          S: Service<Request = Message<P::Request, Body<P::RequestBody, P::Error>>,
+                     Response = Message<P::Response, B>,
+                     Error = P::Error> + 'static,
+{
+    task::<P, T, B, S, ()>(proto, handle, io, service, &BindConfig::default(), ())
+}
+
+fn bind<P, T, B, S, G>(proto: &P, handle: &Handle, io: T, service: S,
+                       binding: &BindConfig, guard: G)
+    where P: ServerProto<T>,
+          T: 'static,
+          B: Stream<Item = P::ResponseBody, Error = P::Error>,
+          S: Service<Request = Message<P::Request, Body<P::RequestBody, P::Error>>,
+                     Response = Message<P::Response, B>,
+                     Error = P::Error> + 'static,
+          G: 'static,
+{
+    let task = task::<P, T, B, S, G>(proto, handle, io, service, binding, guard);
+
+    // Spawn the multiplex dispatcher
+    handle.spawn(task.map_err(|_| ()))
+}
+
+fn task<P, T, B, S, G>(proto: &P, handle: &Handle, io: T, service: S,
+                       binding: &BindConfig, guard: G)
+                       -> Box<Future<Item = (), Error = io::Error>>
+    where P: ServerProto<T>,
+          T: 'static,
+          B: Stream<Item = P::ResponseBody, Error = P::Error>,
+          S: Service<Request = Message<P::Request, Body<P::RequestBody, P::Error>>,
+                     Response = Message<P::Response, B>,
+                     Error = P::Error> + 'static,
+          G: 'static,
+{
+    let validator = proto.request_id_validator();
+    let mut config = binding.multiplex.clone().unwrap_or_else(|| proto.config());
+    config.max_frames_per_poll = budget::capped(config.max_frames_per_poll,
+                                                binding.max_frames_per_poll);
+    let committer = proto.committer();
+    let ack_requests = proto.ack_requests();
+    let negotiation = Negotiation::server(proto.encodings());
+    let observer = binding.observer.clone();
+    let reactor = handle.clone();
+
+    let (push, pushed) = push::pair();
+    proto.on_bind(push);
+
+    let transport = Deadline::new(proto.bind_transport(io).into_future(), binding.timeout, handle);
+
+    // Binding is over either way
+    let transport = transport.then(move |res| {
+        drop(guard);
+        res
+    });
+
+    let task = transport.and_then(move |mut transport| {
+        transport.on_handle(&reactor);
+
+        let dispatch: Dispatch<S, T, P> = Dispatch {
+            service: service,
+            transport: transport,
+            in_flight: vec![],
+            interims: Interims::new(),
+            validator: validator,
+            max_in_flight: config.max_in_flight,
+            committer: committer,
+            commit_timeout: config.commit_timeout,
+            request_timeout: config.request_timeout,
+            handle: reactor.clone(),
+            ack_requests: ack_requests,
+            acks: VecDeque::new(),
+            negotiation: Some(negotiation),
+            observer: observer,
+            pushed: Some(pushed),
+            strict: config.strict,
+            closing: false,
+        };
+        ::unwind::isolate(StreamingMultiplex::<B>::drive(dispatch, &config))
+    });
+
+    Box::new(task)
 }
 
 struct Dispatch<S, T, P> where
@@ -104,16 +330,102 @@ struct Dispatch<S, T, P> where
     // The service handling the connection
     service: S,
     transport: P::Transport,
-    in_flight: Vec<(P::RequestId, InFlight<S::Future>)>,
+    // In the order the requests were read
+    in_flight: Vec<Pending<P::RequestId, S::Future>>,
+    // Interim responses sent by the service
+    interims: Interims<P::Response>,
+    // Checks incoming request ids
+    validator: Box<RequestIdValidator<P::RequestId>>,
+    // The number of requests that can be in flight at once
+    max_in_flight: usize,
+    // Confirms responses before they are written
+    committer: Option<Box<Committer<P::Response>>>,
+    commit_timeout: Option<Duration>,
+    // Time the service has to answer requests, unless the protocol tells
+    // otherwise
+    request_timeout: Option<Duration>,
+    handle: Handle,
+    // True when requests handed to the service are acknowledged
+    ack_requests: bool,
+    // Ids of the requests to acknowledge
+    acks: VecDeque<P::RequestId>,
+    negotiation: Option<Negotiation>,
+    // Observer from the bind config
+    observer: Option<Arc<ConnectionObserver>>,
+    // Messages pushed to the client, until all the `Push` handles are gone
+    pushed: Option<Pushed<P::RequestId, P::Response>>,
+    // Whether violations by the client close the connection instead of
+    // panicking
+    strict: bool,
+    // Set once a response closed the connection
+    closing: bool,
+}
+
+struct Pending<Id, F: Future> {
+    id: Id,
+    ordering_key: Option<u64>,
+    // Key of the interim responses to the request, if handed to the service
+    interim_key: Option<u64>,
+    // Expiry of the time the service has to answer
+    deadline: Option<Timeout>,
+    slot: InFlight<F>,
+}
+
+impl<Id, F: Future> Pending<Id, F> {
+    fn new(id: Id, ordering_key: Option<u64>, interim_key: Option<u64>, slot: InFlight<F>)
+           -> Pending<Id, F>
+    {
+        Pending {
+            id: id,
+            ordering_key: ordering_key,
+            interim_key: interim_key,
+            deadline: None,
+            slot: slot,
+        }
+    }
+
+    // Returns true once the service ran out of time to answer
+    fn poll_deadline(&mut self) -> bool {
+        if let InFlight::Active(_) = self.slot {
+            if let Some(ref mut deadline) = self.deadline {
+                return match deadline.poll() {
+                    Ok(Async::NotReady) => false,
+                    _ => true,
+                };
+            }
+        }
+
+        false
+    }
 }
 
 enum InFlight<F: Future> {
     Active(F),
+    // The response waits for its commit
+    Committing(Option<F::Item>, Commit),
     Done(Result<F::Item, F::Error>),
 }
 
-/// The total number of requests that can be in flight at once.
-const MAX_IN_FLIGHT_REQUESTS: usize = 32;
+impl<S, T, P> Dispatch<S, T, P> where
+    T: 'static, P: ServerProto<T>, S: Service
+{
+    // Polls the next pushed message, if any
+    fn poll_pushed(&mut self) -> Option<(P::RequestId, P::Response)> {
+        let next = match self.pushed {
+            Some(ref mut pushed) => pushed.poll().expect("pushed messages cannot fail"),
+            None => return None,
+        };
+
+        match next {
+            Async::Ready(Some(message)) => Some(message),
+            Async::Ready(None) => {
+                self.pushed = None;
+                None
+            }
+            Async::NotReady => None,
+        }
+    }
+}
 
 impl<P, T, B, S> super::advanced::Dispatch for Dispatch<S, T, P> where
     P: ServerProto<T>,
@@ -136,23 +448,95 @@ impl<P, T, B, S> super::advanced::Dispatch for Dispatch<S, T, P> where
         &mut self.transport
     }
 
+    fn negotiation(&mut self) -> Option<Negotiation> {
+        self.negotiation.take()
+    }
+
+    fn observer(&self) -> Option<Arc<ConnectionObserver>> {
+        self.observer.clone()
+    }
+
     fn poll(&mut self) -> Poll<Option<MultiplexMessage<Self::RequestId, Self::In, B, Self::Error>>, io::Error> {
         trace!("Dispatch::poll");
 
+        if self.closing {
+            // Nothing more is pushed to a closing connection
+            if self.in_flight.is_empty() {
+                return Ok(Async::Ready(None));
+            }
+        } else if let Some((id, message)) = self.poll_pushed() {
+            trace!("   --> pushing; request_id={:?}", id);
+
+            let message = MultiplexMessage {
+                id: id,
+                message: Ok(Message::WithoutBody(message)),
+                solo: true,
+            };
+
+            return Ok(Async::Ready(Some(message)));
+        }
+
         let mut idx = None;
+        // Keys of the earlier responses not written yet
+        let mut pending_keys = vec![];
+
+        let committer = &mut self.committer;
+        let commit_timeout = self.commit_timeout;
+        let handle = &self.handle;
+
+        for (i, pending) in self.in_flight.iter_mut().enumerate() {
+            trace!("   --> poll; request_id={:?}", pending.id);
+            let key = pending.ordering_key;
+            let ordered = match key {
+                Some(key) => !pending_keys.contains(&key),
+                None => true,
+            };
+
+            let mut done = pending.slot.poll(|response| {
+                let future = match *committer {
+                    Some(ref mut committer) => committer.commit(response.get_ref()),
+                    None => None,
+                };
+
+                match future {
+                    Some(future) => Commit::new(future, commit_timeout, handle).map(Some),
+                    None => Ok(None),
+                }
+            });
 
-        for (i, &mut (ref request_id, ref mut slot)) in self.in_flight.iter_mut().enumerate() {
-            trace!("   --> poll; request_id={:?}", request_id);
-            if slot.poll() && idx.is_none() {
+            if !done && pending.poll_deadline() {
+                debug!("request deadline expired; request_id={:?}", pending.id);
+
+                // Dropping the response future frees the slot
+                let err = io::Error::new(io::ErrorKind::TimedOut, "request deadline expired");
+                pending.slot = InFlight::Done(Err(err.into()));
+                done = true;
+            }
+
+            if done && ordered && idx.is_none() {
                 idx = Some(i);
             }
+
+            if let Some(key) = key {
+                pending_keys.push(key);
+            }
         }
 
         if let Some(idx) = idx {
-            let (request_id, message) = self.in_flight.remove(idx);
+            let pending = self.in_flight.remove(idx);
+            let request_id = pending.id;
+            let message = pending.slot.unwrap_done();
+
+            if let Ok(ref response) = message {
+                if P::closes_connection(response.get_ref()) {
+                    trace!("response closes the connection; request_id={:?}", request_id);
+                    self.closing = true;
+                }
+            }
+
             let message = MultiplexMessage {
                 id: request_id,
-                message: message.unwrap_done(),
+                message: message,
                 solo: false,
             };
 
@@ -167,11 +551,47 @@ impl<P, T, B, S> super::advanced::Dispatch for Dispatch<S, T, P> where
 
         let MultiplexMessage { id, message, solo } = message;
 
-        assert!(!solo);
+        // Clients have no way to send a request that expects no response
+        if solo {
+            return Err(violation(self.strict, format!("solo request; id={:?}", id)));
+        }
+
+        if let Ok(mut request) = message {
+            if let Err(violation) = self.validator.validate_request_id(&id) {
+                debug!("rejecting request; id={:?}; err={}", id, violation);
+
+                // Answer with an error frame without involving the service
+                let err = io::Error::from(violation).into();
+                self.in_flight.push(Pending::new(id, None, None, InFlight::Done(Err(err))));
+                return Ok(());
+            }
+
+            let key = P::ordering_key(request.get_ref());
+
+            if let Some(response) = P::answer_inline(&mut self.transport, request.get_ref()) {
+                trace!("request answered by the transport; id={:?}", id);
+                let response = InFlight::Done(Ok(Message::WithoutBody(response)));
+                self.in_flight.push(Pending::new(id, key, None, response));
+                return Ok(());
+            }
+
+            if self.ack_requests {
+                self.acks.push_back(id.clone());
+            }
+
+            let (interim_key, interim) = self.interims.handle();
+            P::on_interim(request.get_mut(), interim);
+
+            let timeout = P::request_timeout(request.get_ref()).or(self.request_timeout);
 
-        if let Ok(request) = message {
             let response = self.service.call(request);
-            self.in_flight.push((id, InFlight::Active(response)));
+            let mut pending = Pending::new(id, key, Some(interim_key), InFlight::Active(response));
+
+            if let Some(timeout) = timeout {
+                pending.deadline = Some(try!(Timeout::new(timeout, &self.handle)));
+            }
+
+            self.in_flight.push(pending);
         }
 
         // TODO: Should the error be handled differently?
@@ -180,7 +600,7 @@ impl<P, T, B, S> super::advanced::Dispatch for Dispatch<S, T, P> where
     }
 
     fn poll_ready(&self) -> Async<()> {
-        if self.in_flight.len() < MAX_IN_FLIGHT_REQUESTS {
+        if self.in_flight.len() < self.max_in_flight {
             Async::Ready(())
         } else {
             Async::NotReady
@@ -191,6 +611,27 @@ impl<P, T, B, S> super::advanced::Dispatch for Dispatch<S, T, P> where
         // TODO: implement
         Ok(())
     }
+
+    fn poll_ack(&mut self) -> Option<Self::RequestId> {
+        self.acks.pop_front()
+    }
+
+    fn poll_progress(&mut self) -> Option<(Self::RequestId, Self::In)> {
+        while let Some((key, message)) = self.interims.poll() {
+            let pending = self.in_flight.iter().find(|pending| pending.interim_key == Some(key));
+
+            // Discard those sent once the final response was written
+            if let Some(pending) = pending {
+                return Some((pending.id.clone(), message));
+            }
+        }
+
+        None
+    }
+
+    fn is_closing(&self) -> bool {
+        self.closing
+    }
 }
 
 /*
@@ -201,15 +642,46 @@ impl<P, T, B, S> super::advanced::Dispatch for Dispatch<S, T, P> where
 
 impl<F> InFlight<F>
     where F: Future,
+          F::Error: From<io::Error>,
 {
-    // Returns true if done
-    fn poll(&mut self) -> bool {
-        let res = match *self {
+    // Polls the response, then its commit if `commit` starts one. Returns
+    // true if done
+    fn poll<C>(&mut self, commit: C) -> bool
+        where C: FnOnce(&F::Item) -> io::Result<Option<Commit>>,
+    {
+        let next = match *self {
             InFlight::Active(ref mut f) => {
                 trace!("   --> polling future");
                 match f.poll() {
-                    Ok(Async::Ready(e)) => Ok(e),
-                    Err(e) => Err(e),
+                    Ok(Async::Ready(response)) => {
+                        match commit(&response) {
+                            Ok(Some(commit)) => InFlight::Committing(Some(response), commit),
+                            Ok(None) => InFlight::Done(Ok(response)),
+                            Err(e) => InFlight::Done(Err(e.into())),
+                        }
+                    }
+                    Err(e) => InFlight::Done(Err(e)),
+                    Ok(Async::NotReady) => return false,
+                }
+            }
+            _ => return self.poll_commit(),
+        };
+
+        *self = next;
+        self.poll_commit()
+    }
+
+    // Returns true once the commit, if any, is over
+    fn poll_commit(&mut self) -> bool {
+        let res = match *self {
+            InFlight::Committing(ref mut response, ref mut commit) => {
+                trace!("   --> polling commit");
+                match commit.poll() {
+                    Ok(Async::Ready(())) => Ok(response.take().unwrap()),
+                    Err(e) => {
+                        debug!("response commit failed; err={}", e);
+                        Err(e.into())
+                    }
                     Ok(Async::NotReady) => return false,
                 }
             }
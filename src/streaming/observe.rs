@@ -0,0 +1,82 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Instant;
+
+use instrument::ConnectionObserver;
+use streaming::ConnectionId;
+
+/// Reports the activity of a connection to its `ConnectionObserver`, if any.
+#[derive(Clone)]
+pub struct Observe {
+    id: ConnectionId,
+    observer: Option<Arc<ConnectionObserver>>,
+    // Last depth reported, to only report changes
+    depth: usize,
+}
+
+/// A request in flight, reported completed when dropped.
+pub struct InFlight {
+    id: ConnectionId,
+    observer: Arc<ConnectionObserver>,
+    started: Instant,
+}
+
+impl Observe {
+    /// Observes connection `id` with `observer`.
+    pub fn new(id: ConnectionId, observer: Option<Arc<ConnectionObserver>>) -> Observe {
+        Observe {
+            id: id,
+            observer: observer,
+            depth: 0,
+        }
+    }
+
+    pub fn frame_read(&self) {
+        if let Some(ref observer) = self.observer {
+            observer.on_frame_read(self.id);
+        }
+    }
+
+    pub fn frame_written(&self) {
+        if let Some(ref observer) = self.observer {
+            observer.on_frame_written(self.id);
+        }
+    }
+
+    pub fn error(&self, error: &io::Error) {
+        if let Some(ref observer) = self.observer {
+            observer.on_error(self.id, error);
+        }
+    }
+
+    pub fn queue_depth(&mut self, depth: usize) {
+        if depth == self.depth {
+            return;
+        }
+
+        self.depth = depth;
+
+        if let Some(ref observer) = self.observer {
+            observer.on_queue_depth(self.id, depth);
+        }
+    }
+
+    /// Starts a request, completed when the returned value is dropped
+    pub fn request(&self) -> Option<InFlight> {
+        self.observer.as_ref().map(|observer| {
+            observer.on_request_started(self.id);
+
+            InFlight {
+                id: self.id,
+                observer: observer.clone(),
+                started: Instant::now(),
+            }
+        })
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.observer.on_request_completed(self.id, self.started.elapsed());
+    }
+}
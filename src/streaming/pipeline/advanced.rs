@@ -4,13 +4,30 @@
 //! Server}` instead. But for some advanced protocols in which the client and
 //! servers have more of a peer relationship, it's useful to work directly with
 //! these implementation details.
+//!
+//! `Pipeline` can also be used on its own, e.g. by connection managers: it is
+//! a `Future` driving a `Dispatch` which owns the transport, and needs no
+//! `Handle`, so it can be polled on any executor. Its `ConnectionControl`
+//! closes the connection from elsewhere and tells how it ended, and
+//! `into_inner` gives the dispatch back once done.
 
 use futures::sync::mpsc;
 use futures::{Future, Poll, Async, Stream, Sink, AsyncSink, StartSend};
+use std::collections::VecDeque;
 use std::io;
-use streaming::{Message, Body};
-use super::{Frame, Transport};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use streaming::{Message, Body, BodyControl, BufferPool, ConnectionControl, ConnectionState,
+                Negotiation, Stats};
+use streaming::budget::Budget;
+use streaming::conn_id::{self, ConnectionId};
+use streaming::control::Controlled;
+use streaming::error_rate::ErrorRate;
+use streaming::observe::{InFlight, Observe};
+use super::{Frame, StreamingPipeline, Transport, PipelineConfig};
 use buffer_one::BufferOne;
+use instrument::ConnectionObserver;
+use ProtocolKind;
 
 // TODO:
 //
@@ -18,8 +35,12 @@ use buffer_one::BufferOne;
 // - Handle request body stream cancellation
 
 /// Provides protocol pipelining functionality in a generic way over clients
-/// and servers. Used internally by `pipeline::Client` and `pipeline::Server`.
+/// and servers. Used internally by `pipeline::Client` and `pipeline::Server`,
+/// or standalone, see the module docs.
 pub struct Pipeline<T> where T: Dispatch {
+    // Identifies the connection in errors and to the transport
+    id: ConnectionId,
+
     // True as long as the connection has more request frames to read.
     run: bool,
 
@@ -29,11 +50,54 @@ pub struct Pipeline<T> where T: Dispatch {
     // The `Sender` for the current request body stream
     out_body: Option<BodySender<T::BodyOut, T::Error>>,
 
+    // Tracks whether the current request body stream has been paused
+    out_control: Option<BodyControl>,
+
     // The response body stream
     in_body: Option<T::Stream>,
 
+    // Polled from `in_body` but not merged into the previous body frame
+    in_body_next: Option<Result<Option<T::BodyIn>, T::Error>>,
+
+    // Fully written bodies, dropped once the transport has been flushed so
+    // that their completion is only signaled after the final chunk left
+    flushed_bodies: Vec<T::Stream>,
+
     // True when the transport is fully flushed
     is_flushed: bool,
+
+    // True once the dispatch has no more in messages to write
+    in_done: bool,
+
+    // True once the write half of the transport has been shut down
+    is_write_shutdown: bool,
+
+    // Set when a flush could not complete immediately
+    flush_started: Option<Instant>,
+
+    // Latency of the last completed flush, reported to the transport
+    flush_latency: Option<Duration>,
+
+    // Frames left to process before yielding to other tasks
+    budget: Budget,
+    // Error frames written recently
+    error_rate: ErrorRate,
+    // Longest message read before the connection fails
+    max_message_len: Option<usize>,
+
+    // Set until the content encoding of the connection is agreed on
+    negotiation: Option<Negotiation>,
+
+    observe: Observe,
+
+    // Exchanges in flight, oldest first, reported completed once dropped
+    exchanges: VecDeque<Option<InFlight>>,
+
+    // True if the exchanges in flight were started by reading a message
+    exchanges_read: bool,
+
+    // Closes the connection on request of its handles, told how it ended
+    control: Controlled,
 }
 
 /// Message used to communicate through the multiplex dispatch
@@ -78,10 +142,69 @@ pub trait Dispatch {
     /// RPC currently in flight
     /// TODO: Get rid of
     fn has_in_flight(&self) -> bool;
+
+    /// Process a `Progress` frame read from the transport, an interim
+    /// response to the exchange at the head of the pipeline.
+    ///
+    /// By default progress updates are not expected and fail the connection.
+    fn progress(&mut self, message: Self::Out) -> io::Result<()> {
+        let _ = message;
+        Err(io::Error::new(io::ErrorKind::Other, "unexpected progress frame"))
+    }
+
+    /// Poll for an interim response to write as a `Progress` frame.
+    ///
+    /// Polled before every message, so interim responses returned here must
+    /// belong to the exchange whose response is written next. By default
+    /// there are none.
+    fn poll_progress(&mut self) -> Option<Self::In> {
+        None
+    }
+
+    /// Returns true if the exchanges in flight still complete once the peer
+    /// stopped sending, as responses computed locally do.
+    ///
+    /// Clients waiting for their responses from the peer return false, so
+    /// that the connection ends, failing the exchanges, instead of waiting
+    /// forever. Defaults to true.
+    fn completes_after_eof(&self) -> bool {
+        true
+    }
+
+    /// Content encoding negotiation of the connection, taken once when the
+    /// dispatcher is created.
+    ///
+    /// When it advertises any encoding, the dispatcher hands them to the
+    /// transport and records the agreed encoding once the peer's
+    /// advertisement was read. By default connections are left unencoded.
+    fn negotiation(&mut self) -> Option<Negotiation> {
+        None
+    }
+
+    /// Observer of the connection, taken once when the dispatcher is
+    /// created.
+    ///
+    /// The protocol traits of this crate return the observer of the
+    /// `BindConfig` the connection was bound with. Defaults to `None`,
+    /// leaving the connection unobserved.
+    fn observer(&self) -> Option<Arc<ConnectionObserver>> {
+        None
+    }
+
+    /// Returns true once the dispatch wants the connection closed, e.g.
+    /// after answering a request that ends the session.
+    ///
+    /// The dispatcher then stops reading, writes out the messages still in
+    /// flight and closes the connection. Defaults to false.
+    fn is_closing(&self) -> bool {
+        false
+    }
 }
 
 struct DispatchSink<T> {
     inner: T,
+    // Reports the frames accepted by the transport
+    observe: Observe,
 }
 
 type BodySender<B, E> = BufferOne<mpsc::Sender<Result<B, E>>>;
@@ -90,24 +213,102 @@ impl<T> Pipeline<T> where T: Dispatch {
     /// Create a new pipeline `Pipeline` dispatcher with the given service and
     /// transport
     pub fn new(dispatch: T) -> Pipeline<T> {
+        Pipeline::with_config(dispatch, &PipelineConfig::default())
+    }
+
+    /// Create a new `Pipeline` dispatcher tuned by the given configuration
+    pub fn with_config(dispatch: T, config: &PipelineConfig) -> Pipeline<T> {
+        let mut dispatch = dispatch;
+
+        let id = ConnectionId::next();
+        dispatch.transport().on_connection(id);
+
+        let buffers = BufferPool::new(config.max_pooled_buffers);
+        dispatch.transport().on_buffer_provider(Arc::new(buffers));
+
+        let negotiation = dispatch.negotiation().and_then(|negotiation| {
+            if negotiation.encodings().is_empty() {
+                return None;
+            }
+
+            dispatch.transport().advertise_encodings(negotiation.encodings());
+            Some(negotiation)
+        });
+
+        let observe = Observe::new(id, dispatch.observer());
+
         // Add `Sink` impl for `Dispatch`
-        let dispatch = DispatchSink { inner: dispatch };
+        let dispatch = DispatchSink {
+            inner: dispatch,
+            observe: observe.clone(),
+        };
 
         // Add a single slot buffer for the sink
         let dispatch = BufferOne::new(dispatch);
 
         Pipeline {
+            id: id,
             run: true,
             dispatch: dispatch,
             out_body: None,
+            out_control: None,
             in_body: None,
+            in_body_next: None,
+            flushed_bodies: vec![],
             is_flushed: true,
+            in_done: false,
+            is_write_shutdown: false,
+            flush_started: None,
+            flush_latency: None,
+            budget: Budget::new(config.max_frames_per_poll),
+            error_rate: ErrorRate::new(config.max_error_frames, config.error_frame_window),
+            max_message_len: config.max_message_len,
+            negotiation: negotiation,
+            observe: observe,
+            exchanges: VecDeque::new(),
+            exchanges_read: false,
+            control: Controlled::new(),
         }
     }
 
+    /// Returns the id of the connection driven by this dispatcher
+    pub fn connection_id(&self) -> ConnectionId {
+        self.id
+    }
+
+    /// Returns a handle to close the connection and find out how it ended
+    pub fn control(&self) -> ConnectionControl {
+        self.control.handle()
+    }
+
+    /// Returns the state of the connection; once the dispatcher completed,
+    /// whether it did so cleanly
+    pub fn state(&self) -> ConnectionState {
+        self.control.state()
+    }
+
+    /// Returns a reference to the dispatch
+    pub fn get_ref(&self) -> &T {
+        &self.dispatch.get_ref().inner
+    }
+
+    /// Returns a mutable reference to the dispatch
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.dispatch.get_mut().inner
+    }
+
+    /// Consumes the dispatcher, returning the dispatch and with it the
+    /// transport, e.g. to reuse the connection once the dispatcher completed
+    pub fn into_inner(self) -> T {
+        self.dispatch.into_inner().inner
+    }
+
     /// Returns true if the pipeline server dispatch has nothing left to do
     fn is_done(&self) -> bool {
-        !self.run && self.is_flushed && !self.has_in_flight()
+        !self.run && self.is_flushed &&
+            (!self.has_in_flight() || !self.dispatch.get_ref().inner.completes_after_eof()) &&
+            // A closing dispatch writes out the body of its last message
+            (self.in_body.is_none() || !self.dispatch.get_ref().inner.is_closing())
     }
 
     fn read_out_frames(&mut self) -> io::Result<()> {
@@ -117,22 +318,69 @@ impl<T> Pipeline<T> where T: Dispatch {
                 break;
             }
 
-            if let Async::Ready(frame) = try!(self.dispatch.get_mut().inner.transport().poll()) {
-                try!(self.process_out_frame(frame));
-            } else {
+            if !self.budget.has_remaining() {
                 break;
             }
+
+            let frame = match self.dispatch.get_mut().inner.transport().poll() {
+                Ok(Async::Ready(frame)) => frame,
+                Ok(Async::NotReady) => {
+                    try!(self.check_pending_read());
+                    break;
+                }
+                Err(e) => {
+                    // Give the transport a chance to skip past the bad frame
+                    if self.dispatch.get_mut().inner.transport().resynchronize(&e) {
+                        debug!("resynchronized after read error; conn={}; err={}", self.id, e);
+                        self.budget.spend();
+                        continue;
+                    }
+
+                    return Err(e);
+                }
+            };
+
+            self.budget.spend();
+
+            if frame.is_some() {
+                self.observe.frame_read();
+            }
+
+            try!(self.process_out_frame(frame));
         }
 
         Ok(())
     }
 
+    // Fails the connection once the message being read outgrows the limit
+    fn check_pending_read(&mut self) -> io::Result<()> {
+        let max = match self.max_message_len {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+
+        match self.dispatch.get_mut().inner.transport().pending_read_len() {
+            Some(len) if len > max => {
+                debug!("message too large; conn={}; len={}; max={}", self.id, len, max);
+                Err(io::Error::new(io::ErrorKind::InvalidData, "message too large"))
+            }
+            _ => Ok(()),
+        }
+    }
+
     fn check_out_body_stream(&mut self) -> bool {
         let body = match self.out_body {
             Some(ref mut body) => body,
             None => return true,
         };
 
+        // A paused body stops the pipeline from reading any further frames
+        if let Some(ref control) = self.out_control {
+            if control.poll_paused() {
+                return false;
+            }
+        }
+
         body.poll_ready().is_ready()
     }
 
@@ -144,21 +392,22 @@ impl<T> Pipeline<T> where T: Dispatch {
         // frame, no matter what it is.
         match frame {
             Some(Frame::Message { message, body }) => {
+                self.exchange_message(true);
+
                 if body {
                     trace!("read out message with body");
 
-                    let (tx, rx) = Body::pair();
+                    let (tx, rx, control) = Body::pair_with_control();
                     let message = Message::WithBody(message, rx);
 
                     // Track the out body sender. If `self.out_body`
                     // currently holds a sender for the previous out body, it
                     // will get dropped. This terminates the stream.
                     self.out_body = Some(BufferOne::new(tx));
+                    self.out_control = Some(control);
 
-                    if let Err(_) = self.dispatch.get_mut().inner.dispatch(Ok(message)) {
-                        // TODO: Should dispatch be infallible
-                        unimplemented!();
-                    }
+                    try!(self.dispatch.get_mut().inner.dispatch(Ok(message))
+                             .map_err(|e| conn_id::annotate(self.id, e)));
                 } else {
                     trace!("read out message");
 
@@ -167,13 +416,26 @@ impl<T> Pipeline<T> where T: Dispatch {
                     // There is no streaming body. Set `out_body` to `None` so that
                     // the previous body stream is dropped.
                     self.out_body = None;
+                    self.out_control = None;
 
-                    if let Err(_) = self.dispatch.get_mut().inner.dispatch(Ok(message)) {
-                        // TODO: Should dispatch be infalliable
-                        unimplemented!();
-                    }
+                    try!(self.dispatch.get_mut().inner.dispatch(Ok(message))
+                             .map_err(|e| conn_id::annotate(self.id, e)));
                 }
             }
+            Some(Frame::Inline { message, chunk }) => {
+                self.exchange_message(true);
+
+                trace!("read out message with inline body");
+
+                // The whole body is at hand, no body channel is needed
+                let message = Message::WithBody(message, Body::from(chunk));
+
+                self.out_body = None;
+                self.out_control = None;
+
+                try!(self.dispatch.get_mut().inner.dispatch(Ok(message))
+                         .map_err(|e| conn_id::annotate(self.id, e)));
+            }
             Some(Frame::Body { chunk }) => {
                 match chunk {
                     Some(chunk) => {
@@ -195,12 +457,18 @@ impl<T> Pipeline<T> where T: Dispatch {
                 // through the read-cycle again.
                 self.run = false;
             }
+            Some(Frame::Progress { message }) => {
+                trace!("read progress");
+                try!(self.dispatch.get_mut().inner.progress(message)
+                         .map_err(|e| conn_id::annotate(self.id, e)));
+            }
             Some(Frame::Error { .. }) => {
                 // At this point, the transport is toast, there
                 // isn't much else that we can do. Killing the task
                 // will cause all in-flight requests to abort, but
                 // they can't be written to the transport anyway...
-                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "An error occurred."));
+                let err = io::Error::new(io::ErrorKind::BrokenPipe, "An error occurred.");
+                return Err(conn_id::annotate(self.id, err));
             }
         }
 
@@ -245,19 +513,36 @@ impl<T> Pipeline<T> where T: Dispatch {
             }
             debug!("write in body done");
 
+            if !self.budget.has_remaining() {
+                break;
+            }
+
+            // Interim responses go ahead of the response they belong to
+            if let Some(message) = self.dispatch.get_mut().inner.poll_progress() {
+                trace!("   --> got progress");
+                self.budget.spend();
+                try!(assert_send(&mut self.dispatch, Frame::Progress { message: message }));
+                continue;
+            }
+
             // Write the next in-flight in message
-            match try!(self.dispatch.get_mut().inner.poll()) {
+            let next = self.dispatch.get_mut().inner.poll();
+
+            match try!(next.map_err(|e| conn_id::annotate(self.id, e))) {
                 Async::Ready(Some(Ok(message))) => {
                     trace!("   --> got message");
+                    self.budget.spend();
                     try!(self.write_in_message(Ok(message)));
                 }
                 Async::Ready(Some(Err(error))) => {
                     trace!("   --> got error");
+                    self.budget.spend();
                     try!(self.write_in_message(Err(error)));
                 }
                 Async::Ready(None) => {
                     trace!("   --> got None");
                     // The service is done with the connection.
+                    self.in_done = true;
                     break;
                 }
                 // Nothing to dispatch
@@ -270,6 +555,8 @@ impl<T> Pipeline<T> where T: Dispatch {
 
     fn write_in_message(&mut self, message: Result<Message<T::In, T::Stream>, T::Error>) -> io::Result<()> {
         trace!("write_in_message");
+        self.exchange_message(false);
+
         match message {
             Ok(Message::WithoutBody(val)) => {
                 trace!("got in_flight value without body");
@@ -295,6 +582,7 @@ impl<T> Pipeline<T> where T: Dispatch {
             }
             Err(e) => {
                 trace!("got in_flight error");
+                try!(self.error_rate.check().map_err(|e| conn_id::annotate(self.id, e)));
                 let msg = Frame::Error { error: e };
                 try!(assert_send(&mut self.dispatch, msg));
             }
@@ -315,10 +603,21 @@ impl<T> Pipeline<T> where T: Dispatch {
                     return Ok(false);
                 }
 
-                match self.in_body.as_mut().unwrap().poll() {
+                if !self.budget.has_remaining() {
+                    return Ok(false);
+                }
+
+                match self.poll_in_body() {
                     Ok(Async::Ready(Some(chunk))) => {
-                        try!(assert_send(&mut self.dispatch,
-                                         Frame::Body { chunk: Some(chunk) }));
+                        self.budget.spend();
+
+                        let mut frame = Frame::Body { chunk: Some(chunk) };
+
+                        if !self.split_in_body(&mut frame) {
+                            self.coalesce_in_body(&mut frame);
+                        }
+
+                        try!(assert_send(&mut self.dispatch, frame));
                     }
                     Ok(Async::Ready(None)) => {
                         try!(assert_send(&mut self.dispatch,
@@ -336,13 +635,82 @@ impl<T> Pipeline<T> where T: Dispatch {
             }
         }
 
-        self.in_body = None;
+        if let Some(body) = self.in_body.take() {
+            self.flushed_bodies.push(body);
+        }
+
         Ok(true)
     }
 
+    fn poll_in_body(&mut self) -> Poll<Option<T::BodyIn>, T::Error> {
+        match self.in_body_next.take() {
+            Some(next) => next.map(Async::Ready),
+            None => self.in_body.as_mut().unwrap().poll(),
+        }
+    }
+
+    // Split a body frame exceeding the transport's max frame length. The
+    // remainder is written next. Returns true if the frame was split.
+    fn split_in_body(&mut self, frame: &mut Frame<T::In, T::BodyIn, T::Error>) -> bool {
+        let transport = self.dispatch.get_mut().inner.transport();
+
+        let max_len = match transport.max_body_frame_len() {
+            Some(max_len) => max_len,
+            None => return false,
+        };
+
+        match transport.split_body(frame, max_len) {
+            Some(Frame::Body { chunk }) => {
+                trace!("split body chunk");
+                self.in_body_next = Some(Ok(chunk));
+                true
+            }
+            Some(_) => panic!("transport must split body frames into body frames"),
+            None => false,
+        }
+    }
+
+    // Merge the chunks of the response body that are ready into a single
+    // frame, as far as the transport allows
+    fn coalesce_in_body(&mut self, frame: &mut Frame<T::In, T::BodyIn, T::Error>) {
+        loop {
+            let next = match self.in_body.as_mut().unwrap().poll() {
+                Ok(Async::Ready(Some(chunk))) => Frame::Body { chunk: Some(chunk) },
+                Ok(Async::Ready(None)) => {
+                    self.in_body_next = Some(Ok(None));
+                    break;
+                }
+                Err(e) => {
+                    self.in_body_next = Some(Err(e));
+                    break;
+                }
+                Ok(Async::NotReady) => break,
+            };
+
+            match self.dispatch.get_mut().inner.transport().coalesce_body(frame, next) {
+                None => trace!("coalesced body chunk"),
+                Some(Frame::Body { chunk }) => {
+                    self.in_body_next = Some(Ok(chunk));
+                    break;
+                }
+                Some(_) => panic!("transport must return the body frame it did not coalesce"),
+            }
+        }
+    }
+
     fn flush(&mut self) -> io::Result<()> {
         self.is_flushed = try!(self.dispatch.poll_complete()).is_ready();
 
+        if self.is_flushed {
+            self.flushed_bodies.clear();
+
+            if let Some(started) = self.flush_started.take() {
+                self.flush_latency = Some(started.elapsed());
+            }
+        } else if self.flush_started.is_none() {
+            self.flush_started = Some(Instant::now());
+        }
+
         if let Some(ref mut out_body) = self.out_body {
             if out_body.poll_complete().is_ok() {
                 return Ok(());
@@ -356,31 +724,104 @@ impl<T> Pipeline<T> where T: Dispatch {
         Ok(())
     }
 
+    // Shut down the write half once everything has been written out
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        if self.in_done && self.in_body.is_none() && self.is_flushed && !self.is_write_shutdown {
+            trace!("shutting down write half");
+            self.is_write_shutdown = true;
+            try!(self.dispatch.get_mut().inner.transport().shutdown_write());
+        }
+
+        Ok(())
+    }
+
+    fn report_stats(&mut self) {
+        // The body sender buffers at most a single chunk
+        let consumer_lag = match self.out_body {
+            Some(ref mut body) => if body.poll_ready().is_ready() { 0 } else { 1 },
+            None => 0,
+        };
+
+        let stats = Stats {
+            consumer_lag: consumer_lag,
+            flush_latency: self.flush_latency.take(),
+        };
+
+        self.dispatch.get_mut().inner.transport().on_stats(stats);
+    }
+
+    // Records the agreed content encoding once the peer advertised its own
+    fn negotiate_encoding(&mut self) {
+        let agreed = match self.negotiation {
+            Some(ref negotiation) => {
+                match self.dispatch.get_mut().inner.transport().peer_encodings() {
+                    Some(peer) => negotiation.agree(&peer),
+                    None => return,
+                }
+            }
+            None => return,
+        };
+
+        trace!("negotiated encoding; encoding={:?}", agreed);
+        self.negotiation = None;
+        self.dispatch.get_mut().inner.transport().on_encoding(agreed.as_ref().map(|e| &e[..]));
+    }
+
+    // Messages of one direction start exchanges, which the messages of the
+    // other direction complete in order
+    fn exchange_message(&mut self, read: bool) {
+        if self.exchanges.is_empty() || self.exchanges_read == read {
+            self.exchanges_read = read;
+            self.exchanges.push_back(self.observe.request());
+        } else {
+            self.exchanges.pop_front();
+        }
+    }
+
     fn has_in_flight(&self) -> bool {
         self.dispatch.get_ref().inner.has_in_flight()
     }
-}
 
-impl<T> Future for Pipeline<T> where T: Dispatch {
-    type Item = ();
-    type Error = io::Error;
+    fn check_closing(&mut self) {
+        if self.run && (self.dispatch.get_ref().inner.is_closing() || self.control.poll_close()) {
+            debug!("closing connection; conn={}", self.id);
+            self.run = false;
+        }
+    }
+}
 
+impl<T> Pipeline<T> where T: Dispatch {
     // Tick the pipeline state machine
-    fn poll(&mut self) -> Poll<(), io::Error> {
+    fn tick(&mut self) -> Poll<(), io::Error> {
         trace!("Pipeline::tick");
 
+        self.budget.reset();
+
         // Always tick the transport first
         self.dispatch.get_mut().inner.transport().tick();
 
         // First read off data from the socket
         try!(self.read_out_frames());
 
+        // Settle the content encoding once the peer advertised its own
+        self.negotiate_encoding();
+
         // Handle completed responses
         try!(self.write_in_frames());
 
+        // Stop reading once the dispatch is closing the connection
+        self.check_closing();
+
         // Try flushing buffered writes
         try!(self.flush());
 
+        // Signal the end of the request stream if the dispatch is done
+        try!(self.shutdown_write());
+
+        // Give the transport feedback on how the connection is doing
+        self.report_stats();
+        self.observe.queue_depth(self.exchanges.len());
+
         // Clean shutdown of the pipeline server can happen when
         //
         // 1. The server is done running, this is signaled by Transport::poll()
@@ -398,11 +839,45 @@ impl<T> Future for Pipeline<T> where T: Dispatch {
             return Ok(().into())
         }
 
+        // Resume right away if work was left behind
+        self.budget.yield_if_exhausted();
+
         // Tick again later
         Ok(Async::NotReady)
     }
 }
 
+impl<T> Future for Pipeline<T> where T: Dispatch {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        // A panic fails the connection like any other error
+        let res = ::unwind::catch(|| self.tick());
+
+        match res {
+            Ok(Async::Ready(())) => self.control.terminate(ConnectionState::Done),
+            Err(ref e) => {
+                self.observe.error(e);
+                self.control.terminate(ConnectionState::Failed(e.kind()));
+            }
+            _ => {}
+        }
+
+        res
+    }
+}
+
+impl<T, B> ProtocolKind<T> for StreamingPipeline<B> where T: Dispatch {
+    type Frame = Frame<T::In, T::BodyIn, T::Error>;
+    type Config = PipelineConfig;
+    type Driver = Pipeline<T>;
+
+    fn drive(dispatch: T, config: &PipelineConfig) -> Pipeline<T> {
+        Pipeline::with_config(dispatch, config)
+    }
+}
+
 impl<T: Dispatch> Sink for DispatchSink<T> {
     type SinkItem = <T::Transport as Sink>::SinkItem;
     type SinkError = io::Error;
@@ -410,7 +885,13 @@ impl<T: Dispatch> Sink for DispatchSink<T> {
     fn start_send(&mut self, item: Self::SinkItem)
                   -> StartSend<Self::SinkItem, io::Error>
     {
-        self.inner.transport().start_send(item)
+        let res = try!(self.inner.transport().start_send(item));
+
+        if res.is_ready() {
+            self.observe.frame_written();
+        }
+
+        Ok(res)
     }
 
     fn poll_complete(&mut self) -> Poll<(), io::Error> {
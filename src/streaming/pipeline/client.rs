@@ -1,13 +1,17 @@
-use BindClient;
-use streaming::{Body, Message};
-use super::{StreamingPipeline, Frame, Transport};
-use super::advanced::{Pipeline, PipelineMessage};
-use util::client_proxy::{self, ClientProxy, Receiver};
+use {BindClient, BindConfig, ProtocolKind};
+use streaming::{budget, Body, Encodings, Message, Negotiation};
+use super::{StreamingPipeline, Frame, Transport, PipelineConfig, RequestIdSource};
+use super::advanced::PipelineMessage;
+use util::client_proxy::{self, ClientProxy, Complete, Receiver};
 use futures::stream::Stream;
-use futures::{Future, IntoFuture, Complete, Poll, Async};
+use futures::{Future, IntoFuture, Poll, Async};
 use tokio_core::reactor::Handle;
 use std::collections::VecDeque;
 use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use timeout::Deadline;
+use instrument::ConnectionObserver;
 
 /// A streaming, pipelined client protocol.
 ///
@@ -46,6 +50,32 @@ pub trait ClientProto<T: 'static>: 'static {
     /// Build a transport from the given I/O object, using `self` for any
     /// configuration.
     fn bind_transport(&self, io: T) -> Self::BindTransport;
+
+    /// Tuning knobs applied to every connection bound by this protocol.
+    ///
+    /// Defaults to `PipelineConfig::default()`.
+    fn config(&self) -> PipelineConfig {
+        PipelineConfig::default()
+    }
+
+    /// Content encodings this end supports, most preferred first.
+    ///
+    /// When not empty, connections negotiate their content encoding: the
+    /// transport advertises the list to the peer and is told the agreed
+    /// encoding through its `on_encoding` hook. See `Negotiation`. Defaults
+    /// to none, leaving connections unencoded.
+    fn encodings(&self) -> Encodings {
+        Encodings::new()
+    }
+
+    /// Create a `RequestIdSource` tagging the requests of a connection, for
+    /// protocols carrying sequence tags.
+    ///
+    /// Defaults to `None`, matching responses to requests by their order
+    /// alone.
+    fn requestid_source(&self) -> Option<Box<RequestIdSource<Self::Request, Self::Response>>> {
+        None
+    }
 }
 
 impl<P, T, B> BindClient<StreamingPipeline<B>, T> for P where
@@ -60,28 +90,64 @@ impl<P, T, B> BindClient<StreamingPipeline<B>, T> for P where
     type BindClient = ClientProxy<Self::ServiceRequest, Self::ServiceResponse, Self::ServiceError>;
 
     fn bind_client(&self, handle: &Handle, io: T) -> Self::BindClient {
-        let (client, rx) = client_proxy::pair();
-
-        let task = self.bind_transport(io).into_future().and_then(|transport| {
-            let dispatch: Dispatch<P, T, B> = Dispatch {
-                transport: transport,
-                requests: rx,
-                in_flight: VecDeque::with_capacity(32),
-            };
-            Pipeline::new(dispatch)
-        }).map_err(|e| {
-            // TODO: where to punt this error to?
-            error!("pipeline error: {}", e);
-        });
+        bind::<P, T, B>(self, handle, io, &BindConfig::default())
+    }
 
-        // Spawn the task
-        handle.spawn(task);
+    fn bind_client_timeout(&self, handle: &Handle, io: T, timeout: Duration) -> Self::BindClient {
+        bind::<P, T, B>(self, handle, io, &BindConfig { timeout: Some(timeout), ..BindConfig::default() })
+    }
 
-        // Return the client
-        client
+    fn bind_client_with(&self, handle: &Handle, io: T, config: &BindConfig) -> Self::BindClient {
+        bind::<P, T, B>(self, handle, io, config)
     }
 }
 
+fn bind<P, T, B>(proto: &P, handle: &Handle, io: T, binding: &BindConfig)
+                 -> ClientProxy<Message<P::Request, B>,
+                                Message<P::Response, Body<P::ResponseBody, P::Error>>,
+                                P::Error>
+    where P: ClientProto<T>,
+          T: 'static,
+          B: Stream<Item = P::RequestBody, Error = P::Error> + 'static,
+{
+    let (client, rx) = client_proxy::pair();
+    let errors = client.error_sink();
+
+    let mut config = binding.pipeline.clone().unwrap_or_else(|| proto.config());
+    config.max_frames_per_poll = budget::capped(config.max_frames_per_poll,
+                                                binding.max_frames_per_poll);
+    let negotiation = Negotiation::client(proto.encodings());
+    let observer = binding.observer.clone();
+    let rid_src = proto.requestid_source();
+    let reactor = handle.clone();
+
+    let transport = Deadline::new(proto.bind_transport(io).into_future(), binding.timeout, handle);
+
+    let task = transport.and_then(move |mut transport| {
+        transport.on_handle(&reactor);
+
+        let dispatch: Dispatch<P, T, B> = Dispatch {
+            transport: transport,
+            requests: rx,
+            in_flight: VecDeque::with_capacity(config.in_flight_capacity),
+            negotiation: Some(negotiation),
+            observer: observer,
+            rid_src: rid_src,
+            tags: VecDeque::new(),
+        };
+        ::unwind::isolate(StreamingPipeline::<B>::drive(dispatch, &config))
+    }).map_err(move |e| {
+        error!("pipeline error: {}", e);
+        errors.report(e);
+    });
+
+    // Spawn the task
+    handle.spawn(task);
+
+    // Return the client
+    client
+}
+
 struct Dispatch<P, T, B> where
     P: ClientProto<T> + BindClient<StreamingPipeline<B>, T>,
     T: 'static,
@@ -89,7 +155,13 @@ struct Dispatch<P, T, B> where
 {
     transport: P::Transport,
     requests: Receiver<P::ServiceRequest, P::ServiceResponse, P::Error>,
-    in_flight: VecDeque<Complete<Result<P::ServiceResponse, P::Error>>>,
+    in_flight: VecDeque<Complete<P::ServiceResponse, P::Error>>,
+    negotiation: Option<Negotiation>,
+    // Observer from the bind config
+    observer: Option<Arc<ConnectionObserver>>,
+    rid_src: Option<Box<RequestIdSource<P::Request, P::Response>>>,
+    // Ids the requests in flight were tagged with, if tagged
+    tags: VecDeque<u64>,
 }
 
 impl<P, T, B> super::advanced::Dispatch for Dispatch<P, T, B> where
@@ -109,16 +181,48 @@ impl<P, T, B> super::advanced::Dispatch for Dispatch<P, T, B> where
         &mut self.transport
     }
 
+    fn negotiation(&mut self) -> Option<Negotiation> {
+        self.negotiation.take()
+    }
+
+    fn observer(&self) -> Option<Arc<ConnectionObserver>> {
+        self.observer.clone()
+    }
+
     fn dispatch(&mut self,
-                response: PipelineMessage<Self::Out, Body<Self::BodyOut, Self::Error>, Self::Error>)
+                mut response: PipelineMessage<Self::Out, Body<Self::BodyOut, Self::Error>, Self::Error>)
                 -> io::Result<()>
     {
-        if let Some(complete) = self.in_flight.pop_front() {
-            complete.complete(response);
-        } else {
-            return Err(io::Error::new(io::ErrorKind::Other, "request / response mismatch"));
+        let complete = match self.in_flight.pop_front() {
+            Some(complete) => complete,
+            None => return Err(io::Error::new(io::ErrorKind::Other, "request / response mismatch")),
+        };
+
+        if let Some(ref mut rid_src) = self.rid_src {
+            let expected = self.tags.pop_front().expect("request in flight was not tagged");
+
+            let tagged = match response {
+                Ok(ref mut message) => rid_src.untag(message.get_mut()).map(Some),
+                Err(_) => Ok(None),
+            };
+
+            let err = match tagged {
+                Ok(Some(id)) if id != expected => {
+                    debug!("out of order response; expected={}, id={}", expected, id);
+                    io::Error::new(io::ErrorKind::InvalidData, "out of order response")
+                }
+                Ok(_) => {
+                    complete.complete(response);
+                    return Ok(());
+                }
+                Err(e) => e,
+            };
+
+            complete.complete(Err(io::Error::new(err.kind(), err.to_string()).into()));
+            return Err(err);
         }
 
+        complete.complete(response);
         Ok(())
     }
 
@@ -126,39 +230,73 @@ impl<P, T, B> super::advanced::Dispatch for Dispatch<P, T, B> where
                                io::Error>
     {
         trace!("Dispatch::poll");
-        // Try to get a new request frame
-        match self.requests.poll() {
-            Ok(Async::Ready(Some(Ok((request, complete))))) => {
-                trace!("   --> received request");
+        loop {
+            // Try to get a new request frame
+            match self.requests.poll() {
+                Ok(Async::Ready(Some(Ok((mut request, mut complete))))) => {
+                    trace!("   --> received request");
 
-                // Track complete handle
-                self.in_flight.push_back(complete);
+                    // Requests canceled before being written are skipped.
+                    // Once written, their responses are still read, in
+                    // order, and discarded.
+                    if complete.poll_cancel().is_ready() {
+                        trace!("   --> request canceled");
 
-                Ok(Async::Ready(Some(Ok(request))))
+                        if complete.is_canceled_by_client() {
+                            complete.complete(Err(client_proxy::canceled_error().into()));
+                        }
 
+                        continue;
+                    }
+
+                    if let Some(ref mut rid_src) = self.rid_src {
+                        let id = rid_src.tag(request.get_mut());
+                        trace!("   --> tagged request; id={}", id);
+                        self.tags.push_back(id);
+                    }
+
+                    // Track complete handle
+                    complete.set_written();
+                    self.in_flight.push_back(complete);
+
+                    return Ok(Async::Ready(Some(Ok(request))));
+                }
+                Ok(Async::Ready(None)) => {
+                    trace!("   --> client dropped");
+                    return Ok(Async::Ready(None));
+                }
+                Ok(Async::Ready(Some(Err(e)))) => {
+                    trace!("   --> error");
+                    // An error on receive can only happen when the other half
+                    // disconnected. In this case, the client needs to be
+                    // shutdown
+                    panic!("unimplemented error handling: {:?}", e);
+                }
+                Ok(Async::NotReady) => {
+                    trace!("   --> not ready");
+                    return Ok(Async::NotReady);
+                }
+                Err(()) => panic!(),
             }
-            Ok(Async::Ready(None)) => {
-                trace!("   --> client dropped");
-                Ok(Async::Ready(None))
-            }
-            Ok(Async::Ready(Some(Err(e)))) => {
-                trace!("   --> error");
-                // An error on receive can only happen when the other half
-                // disconnected. In this case, the client needs to be
-                // shutdown
-                panic!("unimplemented error handling: {:?}", e);
-            }
-            Ok(Async::NotReady) => {
-                trace!("   --> not ready");
-                Ok(Async::NotReady)
-            }
-            Err(()) => panic!(),
         }
     }
 
     fn has_in_flight(&self) -> bool {
         !self.in_flight.is_empty()
     }
+
+    fn progress(&mut self, message: Self::Out) -> io::Result<()> {
+        match self.in_flight.front_mut() {
+            Some(complete) => complete.progress(Message::WithoutBody(message)),
+            None => return Err(io::Error::new(io::ErrorKind::Other, "request / progress mismatch")),
+        }
+
+        Ok(())
+    }
+
+    fn completes_after_eof(&self) -> bool {
+        false
+    }
 }
 
 impl<P, T, B> Drop for Dispatch<P, T, B> where
@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use {BindConfig, KindConfig};
+
+/// Tuning knobs for pipelined connections.
+///
+/// Returned by the `config` method of the pipeline protocol traits and used
+/// for every connection bound by the protocol, unless the `BindConfig` of
+/// the connection has one, see `TcpServer::config`. With the `serde` feature
+/// enabled the struct can be deserialized, e.g. from a configuration file;
+/// missing fields take their default values.
+///
+/// `BufferProfile` offers presets for the buffering related fields.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct PipelineConfig {
+    /// Number of in-flight requests a connection allocates room for up
+    /// front. Defaults to 32.
+    pub in_flight_capacity: usize,
+
+    /// Max number of frames a connection reads from and writes to the
+    /// transport in a single poll. Once reached, the connection task yields
+    /// to the other tasks of the event loop and resumes on its next turn,
+    /// bounding the time a busy connection holds the event loop. Must not be
+    /// zero. Defaults to `None`, processing frames for as long as possible.
+    /// A lower cap set by the `BindConfig` of the connection takes
+    /// precedence.
+    pub max_frames_per_poll: Option<usize>,
+
+    /// Max number of error frames a connection writes per
+    /// `error_frame_window`. Once exceeded, the connection is closed rather
+    /// than answering a flood of bad requests with as many errors. Defaults
+    /// to `None`, writing any number of error frames.
+    pub max_error_frames: Option<usize>,
+
+    /// Time window `max_error_frames` applies to. Defaults to one second.
+    pub error_frame_window: Duration,
+
+    /// Max number of body buffers kept for reuse by the `BufferPool` handed
+    /// to the transport. Defaults to 16.
+    pub max_pooled_buffers: usize,
+
+    /// Time a response waits for its commit before the request fails with
+    /// a `TimedOut` error, for protocols holding back responses with a
+    /// `Committer`. Defaults to `None`, waiting for as long as it takes.
+    pub commit_timeout: Option<Duration>,
+
+    /// Max length of a message read from the transport, for transports
+    /// reporting the length of the message being read with
+    /// `pending_read_len`. A longer message fails the connection with an
+    /// `InvalidData` error as soon as the transport reports it, rather than
+    /// once it was buffered whole. Defaults to `None`, no limit.
+    pub max_message_len: Option<usize>,
+}
+
+impl KindConfig for PipelineConfig {
+    fn set(self, binding: &mut BindConfig) {
+        binding.pipeline = Some(self);
+    }
+}
+
+impl Default for PipelineConfig {
+    fn default() -> PipelineConfig {
+        PipelineConfig {
+            in_flight_capacity: 32,
+            max_frames_per_poll: None,
+            max_error_frames: None,
+            error_frame_window: Duration::from_secs(1),
+            max_pooled_buffers: 16,
+            commit_timeout: None,
+            max_message_len: None,
+        }
+    }
+}
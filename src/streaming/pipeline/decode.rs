@@ -0,0 +1,161 @@
+use std::io;
+use std::marker::PhantomData;
+
+use tokio_core::io::{Codec, EasyBuf};
+
+use super::Frame;
+
+/// How the body following a decoded message head is delimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyLength {
+    /// The message has no body.
+    Empty,
+
+    /// The body is exactly this many bytes long.
+    Fixed(u64),
+
+    /// The body is delimited by the protocol itself, see
+    /// `MessageDecoder::decode_chunk`.
+    Delimited,
+}
+
+/// Decodes message heads, leaving the bodies to be streamed.
+///
+/// Used with `StreamingCodec`, which turns the bytes following each head into
+/// body frames as soon as they are read, so arbitrarily large messages are
+/// never buffered as a whole.
+pub trait MessageDecoder {
+    /// The decoded message head.
+    type Head;
+
+    /// Attempt to decode a message head from the given buffer.
+    ///
+    /// Returns `Ok(None)` if the buffer does not yet hold a complete head.
+    fn decode_head(&mut self, buf: &mut EasyBuf) -> io::Result<Option<(Self::Head, BodyLength)>>;
+
+    /// Attempt to decode the next chunk of a `BodyLength::Delimited` body.
+    ///
+    /// Returns `Ok(Some(None))` once the body is done and `Ok(None)` if more
+    /// bytes are needed. By default delimited bodies are not supported.
+    fn decode_chunk(&mut self, buf: &mut EasyBuf) -> io::Result<Option<Option<EasyBuf>>> {
+        let _ = buf;
+        Err(io::Error::new(io::ErrorKind::InvalidData, "delimited bodies are not supported"))
+    }
+}
+
+/// A `Codec` decoding messages incrementally into a head followed by body
+/// chunks.
+///
+/// Decoded frames are streaming pipeline frames carrying `EasyBuf` chunks, so
+/// the codec can be used with `Io::framed` as a streaming pipeline transport.
+/// Encoding is delegated to the wrapped encoder.
+pub struct StreamingCodec<D, C, E = io::Error> {
+    decoder: D,
+    encoder: C,
+    state: State,
+    _error: PhantomData<E>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Head,
+    Fixed(u64),
+    Delimited,
+}
+
+impl<D, C, E> StreamingCodec<D, C, E> {
+    /// Create a new `StreamingCodec` decoding with `decoder` and encoding
+    /// with `encoder`.
+    pub fn new(decoder: D, encoder: C) -> StreamingCodec<D, C, E> {
+        StreamingCodec {
+            decoder: decoder,
+            encoder: encoder,
+            state: State::Head,
+            _error: PhantomData,
+        }
+    }
+
+    /// Returns true if the codec is in the middle of decoding a body.
+    pub fn is_decoding_body(&self) -> bool {
+        match self.state {
+            State::Head => false,
+            _ => true,
+        }
+    }
+}
+
+impl<D, C, E> Codec for StreamingCodec<D, C, E>
+    where D: MessageDecoder,
+          C: Codec,
+{
+    type In = Frame<D::Head, EasyBuf, E>;
+    type Out = C::Out;
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<Self::In>> {
+        match self.state {
+            State::Head => {
+                let (head, len) = match try!(self.decoder.decode_head(buf)) {
+                    Some(decoded) => decoded,
+                    None => return Ok(None),
+                };
+
+                self.state = match len {
+                    BodyLength::Empty => State::Head,
+                    BodyLength::Fixed(n) => State::Fixed(n),
+                    BodyLength::Delimited => State::Delimited,
+                };
+
+                Ok(Some(Frame::Message {
+                    message: head,
+                    body: len != BodyLength::Empty,
+                }))
+            }
+            State::Fixed(0) => {
+                self.state = State::Head;
+                Ok(Some(Frame::Body { chunk: None }))
+            }
+            State::Fixed(remaining) => {
+                if buf.len() == 0 {
+                    return Ok(None);
+                }
+
+                // Hand out whatever has been read so far, up to the end of
+                // the body
+                let n = if (buf.len() as u64) < remaining {
+                    buf.len()
+                } else {
+                    remaining as usize
+                };
+
+                self.state = State::Fixed(remaining - n as u64);
+                Ok(Some(Frame::Body { chunk: Some(buf.drain_to(n)) }))
+            }
+            State::Delimited => {
+                match try!(self.decoder.decode_chunk(buf)) {
+                    Some(Some(chunk)) => Ok(Some(Frame::Body { chunk: Some(chunk) })),
+                    Some(None) => {
+                        self.state = State::Head;
+                        Ok(Some(Frame::Body { chunk: None }))
+                    }
+                    None => Ok(None),
+                }
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, buf: &mut EasyBuf) -> io::Result<Self::In> {
+        match try!(self.decode(buf)) {
+            Some(frame) => Ok(frame),
+            None if self.is_decoding_body() => {
+                Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stream ended mid-body"))
+            }
+            None => {
+                Err(io::Error::new(io::ErrorKind::Other, "bytes remaining on stream"))
+            }
+        }
+    }
+
+    fn encode(&mut self, msg: C::Out, buf: &mut Vec<u8>) -> io::Result<()> {
+        self.encoder.encode(msg, buf)
+    }
+}
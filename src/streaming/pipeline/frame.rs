@@ -1,3 +1,5 @@
+use streaming::Chunk;
+
 /// A pipelined protocol frame
 #[derive(Debug, Clone)]
 pub enum Frame<T, B, E> {
@@ -8,6 +10,18 @@ pub enum Frame<T, B, E> {
         /// Set to true when body frames will follow
         body: bool,
     },
+    /// A message along with its whole body, for protocols where small
+    /// bodies fit in the first frame.
+    ///
+    /// Read from a transport, it is dispatched with a body holding `chunk`
+    /// without setting up a body channel. The dispatchers only write
+    /// messages and body frames, never `Inline` ones.
+    Inline {
+        /// The message value
+        message: T,
+        /// The whole body
+        chunk: B,
+    },
     /// Body frame. None indicates that the body is done streaming.
     Body {
         /// Body chunk. Setting to `None` indicates that the body is done
@@ -20,6 +34,12 @@ pub enum Frame<T, B, E> {
         /// Error value
         error: E,
     },
+    /// Interim response to the request at the head of the pipeline, ahead
+    /// of its final response.
+    Progress {
+        /// The interim message
+        message: T,
+    },
 }
 
 impl<T, B, E> Frame<T, B, E> {
@@ -27,8 +47,10 @@ impl<T, B, E> Frame<T, B, E> {
     pub fn unwrap_msg(self) -> T {
         match self {
             Frame::Message { message, .. } => message,
+            Frame::Inline { message, .. } => message,
             Frame::Body { .. } => panic!("called `Frame::unwrap_msg()` on a `Body` value"),
             Frame::Error { .. } => panic!("called `Frame::unwrap_msg()` on an `Error` value"),
+            Frame::Progress { .. } => panic!("called `Frame::unwrap_msg()` on a `Progress` value"),
         }
     }
 
@@ -37,7 +59,9 @@ impl<T, B, E> Frame<T, B, E> {
         match self {
             Frame::Body { chunk } => chunk,
             Frame::Message { .. } => panic!("called `Frame::unwrap_body()` on a `Message` value"),
+            Frame::Inline { .. } => panic!("called `Frame::unwrap_body()` on an `Inline` value"),
             Frame::Error { .. } => panic!("called `Frame::unwrap_body()` on an `Error` value"),
+            Frame::Progress { .. } => panic!("called `Frame::unwrap_body()` on a `Progress` value"),
         }
     }
 
@@ -47,6 +71,22 @@ impl<T, B, E> Frame<T, B, E> {
             Frame::Error { error } => error,
             Frame::Body { .. } => panic!("called `Frame::unwrap_err()` on a `Body` value"),
             Frame::Message { .. } => panic!("called `Frame::unwrap_err()` on a `Message` value"),
+            Frame::Inline { .. } => panic!("called `Frame::unwrap_err()` on an `Inline` value"),
+            Frame::Progress { .. } => panic!("called `Frame::unwrap_err()` on a `Progress` value"),
+        }
+    }
+}
+
+impl<T, B: Chunk, E> Frame<T, B, E> {
+    /// Splits a body frame whose chunk is longer than `max_len`, keeping the
+    /// first `max_len` bytes and returning the rest as a body frame of its
+    /// own, e.g. to implement `Transport::split_body`.
+    pub fn split_chunk(&mut self, max_len: usize) -> Option<Frame<T, B, E>> {
+        match *self {
+            Frame::Body { chunk: Some(ref mut chunk) } if chunk.len() > max_len => {
+                Some(Frame::Body { chunk: Some(chunk.split_off(max_len)) })
+            }
+            _ => None,
         }
     }
 }
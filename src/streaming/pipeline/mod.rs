@@ -5,10 +5,19 @@
 use std::io;
 use futures::{Stream, Sink};
 use tokio_core::io::{Io, Framed, Codec};
+use tokio_core::reactor::Handle;
+use std::sync::Arc;
+use streaming::{BufferProvider, ConnectionId, Encodings, Stats};
 
 mod frame;
 pub use self::frame::Frame;
 
+mod config;
+pub use self::config::PipelineConfig;
+
+mod decode;
+pub use self::decode::{BodyLength, MessageDecoder, StreamingCodec};
+
 mod client;
 pub use self::client::ClientProto;
 
@@ -23,6 +32,25 @@ pub mod advanced;
 /// implement the `ClientProto` or `ServerProto` traits in this module.
 pub struct StreamingPipeline<B>(B);
 
+/// Tags the requests of a pipelined protocol carrying sequence tags.
+///
+/// Some protocols are pipelined on the wire, answering requests in order,
+/// yet tag every request so that a response names the request it answers.
+/// Returned by `ClientProto::requestid_source`, a source has the dispatcher
+/// tag every request before writing it and check the tag of every response
+/// against the request it is matched with, failing the connection on an
+/// out of order response instead of handing it to the wrong caller.
+///
+/// Error frames carry no message, so their tags are not checked.
+pub trait RequestIdSource<Req, Resp>: 'static {
+    /// Tag `request` with its id, returning the id.
+    fn tag(&mut self, request: &mut Req) -> u64;
+
+    /// Strip the tag from `response`, returning the id it carried. Fails if
+    /// the response is not tagged.
+    fn untag(&mut self, response: &mut Resp) -> io::Result<u64>;
+}
+
 /// Additional transport details relevant to streaming, pipelined protocols.
 ///
 /// All methods added in this trait have default implementations.
@@ -41,6 +69,149 @@ pub trait Transport: 'static +
     fn cancel(&mut self) -> io::Result<()> {
         Ok(())
     }
+
+    /// Shut down the write half of the transport.
+    ///
+    /// Called once by client dispatchers after all handles to the client have
+    /// been dropped and every request, including its body, has been flushed.
+    /// Responses continue to be read until all in-flight requests complete.
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Invoked after reading a frame failed with `error`, e.g. because the
+    /// peer sent bytes that don't decode.
+    ///
+    /// Transports able to skip to the next frame boundary do so and return
+    /// true to have the pipeline dispatcher carry on reading. Responses are
+    /// matched to requests by their order, so the protocol has to account for
+    /// the skipped frame, e.g. by having the transport read an error frame in
+    /// its place. By default false is returned and the connection is closed
+    /// with the error.
+    fn resynchronize(&mut self, error: &io::Error) -> bool {
+        let _ = error;
+        false
+    }
+
+    /// Receives the id of the connection, called once when the pipeline dispatcher
+    /// is created.
+    ///
+    /// Errors originating in the pipeline dispatcher mention the same id, so it can be
+    /// used to correlate the transport's own logs with them.
+    fn on_connection(&mut self, id: ConnectionId) {
+        let _ = id;
+    }
+
+    /// Receives a handle to the event loop driving the connection, called
+    /// once by the bind functions of the pipelined protocols before the dispatcher
+    /// is created.
+    ///
+    /// Lets the transport set up timers, e.g. those of a
+    /// `keepalive::KeepAlive`. By default the handle is ignored.
+    fn on_handle(&mut self, handle: &Handle) {
+        let _ = handle;
+    }
+
+    /// Receives the provider of the buffers body chunks are decoded into,
+    /// called once when the pipeline dispatcher is created.
+    ///
+    /// Codecs taking their buffers from the provider and wrapping them in
+    /// `PooledChunk`s get them back for reuse once the service drops the
+    /// chunks. By default the provider is ignored.
+    fn on_buffer_provider(&mut self, provider: Arc<BufferProvider>) {
+        let _ = provider;
+    }
+
+    /// Send a keep-alive ping to the peer.
+    ///
+    /// Called by `keepalive::KeepAlive` at its configured interval. By
+    /// default nothing is sent.
+    fn ping(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Receives statistics observed by the pipeline dispatcher, called at the
+    /// end of every tick.
+    fn on_stats(&mut self, stats: Stats) {
+        let _ = stats;
+    }
+
+    /// Receives the content encodings this end advertises, called once when
+    /// the dispatcher is created if the protocol advertises any.
+    ///
+    /// The transport is expected to write them to the peer ahead of any
+    /// other frame. By default nothing is written.
+    fn advertise_encodings(&mut self, encodings: &Encodings) {
+        let _ = encodings;
+    }
+
+    /// Returns the content encodings advertised by the peer, once read.
+    ///
+    /// Polled by the dispatcher after reading frames until it returns `Some`,
+    /// as long as an advertisement is pending. Defaults to `None`.
+    fn peer_encodings(&mut self) -> Option<Encodings> {
+        None
+    }
+
+    /// Receives the content encoding agreed on with the peer, or `None` if
+    /// the connection stays unencoded.
+    ///
+    /// Called once by the dispatcher after `peer_encodings` returned the
+    /// peer's advertisement. Compression wrappers and codecs apply the
+    /// encoding to the frames written afterwards. By default the encoding is
+    /// ignored.
+    fn on_encoding(&mut self, encoding: Option<&str>) {
+        let _ = encoding;
+    }
+
+    /// Merge the body frame `next` into `buffered`, the body frame about to
+    /// be written, or return `next` unchanged to write it as a frame of its
+    /// own.
+    ///
+    /// The dispatcher offers the chunks of a body that are ready at the same
+    /// time, so that chatty body producers can be written with fewer, larger
+    /// frames; a chunk is never held back waiting for more. Both frames are
+    /// `Frame::Body` frames carrying a chunk, and the size threshold is up to
+    /// the implementation, which should not exceed `max_body_frame_len`. By
+    /// default frames are never merged.
+    fn coalesce_body(&mut self, buffered: &mut Self::SinkItem, next: Self::SinkItem)
+                     -> Option<Self::SinkItem>
+    {
+        let _ = buffered;
+        Some(next)
+    }
+    /// Hint for the largest body chunk the transport writes in a single
+    /// frame. Larger chunks are split with `split_body` before being handed
+    /// to the transport, so that body producers can use buffers of any size.
+    ///
+    /// Defaults to `None`, no limit.
+    fn max_body_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    /// Split the body frame `frame` so that its chunk is at most `max_len`
+    /// long, returning the remainder as a body frame of its own.
+    ///
+    /// The remainder is split again as needed. Return `None` to write the
+    /// frame whole. Transports with `Chunk` bodies can use
+    /// `Frame::split_chunk`. By default frames are never split.
+    fn split_body(&mut self, frame: &mut Self::SinkItem, max_len: usize)
+                  -> Option<Self::SinkItem>
+    {
+        let _ = (frame, max_len);
+        None
+    }
+
+    /// Returns the length of the message being read but not decoded yet, as
+    /// far as it has been buffered, or as announced by the peer if longer.
+    ///
+    /// Checked by the dispatcher against the `max_message_len` of the
+    /// connection config after polling the transport, so that an over-limit
+    /// message fails the connection before it is buffered whole. See
+    /// `util::framed::framed_chunked`. Defaults to `None`, unknown.
+    fn pending_read_len(&self) -> Option<usize> {
+        None
+    }
 }
 
 impl<T:Io + 'static, C: Codec + 'static> Transport for Framed<T,C> {}
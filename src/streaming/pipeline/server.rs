@@ -1,11 +1,17 @@
-use BindServer;
+use {BindConfig, BindServer, ProtocolKind};
 use futures::stream::Stream;
 use futures::{Future, IntoFuture, Poll, Async};
 use std::collections::VecDeque;
 use std::io;
-use streaming::{Message, Body};
-use super::advanced::{Pipeline, PipelineMessage};
-use super::{Frame, Transport};
+use std::sync::Arc;
+use std::time::Duration;
+use streaming::{budget, Message, Body, Committer, Encodings, Negotiation};
+use streaming::commit::Commit;
+use streaming::interim::{Interim, Interims};
+use super::advanced::PipelineMessage;
+use super::{StreamingPipeline, Frame, Transport, PipelineConfig};
+use timeout::Deadline;
+use instrument::ConnectionObserver;
 use tokio_core::reactor::Handle;
 use tokio_service::Service;
 
@@ -51,6 +57,71 @@ pub trait ServerProto<T: 'static>: 'static {
     /// Build a transport from the given I/O object, using `self` for any
     /// configuration.
     fn bind_transport(&self, io: T) -> Self::BindTransport;
+
+    /// Tuning knobs applied to every connection bound by this protocol.
+    ///
+    /// Defaults to `PipelineConfig::default()`.
+    fn config(&self) -> PipelineConfig {
+        PipelineConfig::default()
+    }
+
+    /// Content encodings this end supports, most preferred first.
+    ///
+    /// When not empty, connections negotiate their content encoding: the
+    /// transport advertises the list to the peer and is told the agreed
+    /// encoding through its `on_encoding` hook. See `Negotiation`. Defaults
+    /// to none, leaving connections unencoded.
+    fn encodings(&self) -> Encodings {
+        Encodings::new()
+    }
+
+    /// Lets the transport answer `request` itself, without dispatching it to
+    /// the service.
+    ///
+    /// Called for every request read from the transport. Returning a
+    /// response writes it in place of one from the service, keeping protocol
+    /// plumbing such as PINGs out of services. The body of an answered
+    /// request, if any, is dropped. Defaults to dispatching every request.
+    fn answer_inline(transport: &mut Self::Transport, request: &Self::Request)
+                     -> Option<Self::Response>
+    {
+        let _ = (transport, request);
+        None
+    }
+
+    /// Returns true if the connection ends with `response`, e.g. the answer
+    /// to a QUIT command.
+    ///
+    /// Called for every response before it is written. Once one closes the
+    /// connection, the requests read after it are dropped and no more are
+    /// read; the response and its body are written out and the transport is
+    /// shut down. Defaults to keeping the connection open.
+    fn closes_connection(response: &Self::Response) -> bool {
+        let _ = response;
+        false
+    }
+
+    /// Receives the `Interim` handle of `request`, called for every request
+    /// dispatched to the service.
+    ///
+    /// Keep the handle, e.g. in the request, to write interim responses such
+    /// as resource hints ahead of the final response. Those sent while
+    /// earlier responses are outstanding are held back until the request is
+    /// at the head of the pipeline. By default the handle is dropped.
+    fn on_interim(request: &mut Self::Request, interim: Interim<Self::Response>) {
+        let _ = (request, interim);
+    }
+
+    /// Create the `Committer` holding back the responses of a connection
+    /// until they are confirmed, e.g. durable.
+    ///
+    /// Responses are still written in the order of their requests, so a
+    /// response waiting for its commit holds back the next ones. Responses
+    /// answered by the transport are not committed. Defaults to none,
+    /// writing responses as soon as they are ready.
+    fn committer(&self) -> Option<Box<Committer<Self::Response>>> {
+        None
+    }
 }
 
 impl<P, T, B> BindServer<super::StreamingPipeline<B>, T> for P where
@@ -67,18 +138,75 @@ impl<P, T, B> BindServer<super::StreamingPipeline<B>, T> for P where
                          Response = Self::ServiceResponse,
                          Error = Self::ServiceError> + 'static
     {
-        let task = self.bind_transport(io).into_future().and_then(|transport| {
-            let dispatch: Dispatch<S, T, P> = Dispatch {
-                service: service,
-                transport: transport,
-                in_flight: VecDeque::with_capacity(32),
-            };
-            Pipeline::new(dispatch)
-        });
-
-        // Spawn the pipeline dispatcher
-        handle.spawn(task.map_err(|_| ()))
+        bind::<P, T, B, S, ()>(self, handle, io, service, &BindConfig::default(), ())
     }
+
+    fn bind_server_timeout<S>(&self, handle: &Handle, io: T, service: S, timeout: Duration)
+        where S: Service<Request = Self::ServiceRequest,
+                         Response = Self::ServiceResponse,
+                         Error = Self::ServiceError> + 'static
+    {
+        bind::<P, T, B, S, ()>(self, handle, io, service, &BindConfig { timeout: Some(timeout), ..BindConfig::default() }, ())
+    }
+
+    fn bind_server_guarded<S, G>(&self, handle: &Handle, io: T, service: S,
+                                 config: &BindConfig, guard: G)
+        where S: Service<Request = Self::ServiceRequest,
+                         Response = Self::ServiceResponse,
+                         Error = Self::ServiceError> + 'static,
+              G: 'static,
+    {
+        bind::<P, T, B, S, G>(self, handle, io, service, config, guard)
+    }
+}
+
+fn bind<P, T, B, S, G>(proto: &P, handle: &Handle, io: T, service: S,
+                       binding: &BindConfig, guard: G)
+    where P: ServerProto<T>,
+          T: 'static,
+          B: Stream<Item = P::ResponseBody, Error = P::Error>,
+          S: Service<Request = Message<P::Request, Body<P::RequestBody, P::Error>>,
+                     Response = Message<P::Response, B>,
+                     Error = P::Error> + 'static,
+          G: 'static,
+{
+    let mut config = binding.pipeline.clone().unwrap_or_else(|| proto.config());
+    config.max_frames_per_poll = budget::capped(config.max_frames_per_poll,
+                                                binding.max_frames_per_poll);
+    let committer = proto.committer();
+    let negotiation = Negotiation::server(proto.encodings());
+    let observer = binding.observer.clone();
+    let reactor = handle.clone();
+
+    let transport = Deadline::new(proto.bind_transport(io).into_future(), binding.timeout, handle);
+
+    // Binding is over either way
+    let transport = transport.then(move |res| {
+        drop(guard);
+        res
+    });
+
+    let task = transport.and_then(move |mut transport| {
+        transport.on_handle(&reactor);
+
+        let dispatch: Dispatch<S, T, P> = Dispatch {
+            service: service,
+            transport: transport,
+            in_flight: VecDeque::with_capacity(config.in_flight_capacity),
+            interims: Interims::new(),
+            progress: VecDeque::new(),
+            committer: committer,
+            commit_timeout: config.commit_timeout,
+            handle: reactor.clone(),
+            negotiation: Some(negotiation),
+            observer: observer,
+            closing: false,
+        };
+        ::unwind::isolate(StreamingPipeline::<B>::drive(dispatch, &config))
+    });
+
+    // Spawn the pipeline dispatcher
+    handle.spawn(task.map_err(|_| ()))
 }
 
 struct Dispatch<S, T, P> where
@@ -87,11 +215,27 @@ struct Dispatch<S, T, P> where
     // The service handling the connection
     service: S,
     transport: P::Transport,
-    in_flight: VecDeque<InFlight<S::Future>>,
+    // With the key of their interim responses, if handed to the service
+    in_flight: VecDeque<(Option<u64>, InFlight<S::Future>)>,
+    // Interim responses sent by the service
+    interims: Interims<P::Response>,
+    // Interim responses waiting for their request to reach the head
+    progress: VecDeque<(u64, P::Response)>,
+    // Confirms responses before they are written
+    committer: Option<Box<Committer<P::Response>>>,
+    commit_timeout: Option<Duration>,
+    handle: Handle,
+    negotiation: Option<Negotiation>,
+    // Observer from the bind config
+    observer: Option<Arc<ConnectionObserver>>,
+    // Set once a response closed the connection
+    closing: bool,
 }
 
 enum InFlight<F: Future> {
     Active(F),
+    // The response waits for its commit
+    Committing(Option<F::Item>, Commit),
     Done(Result<F::Item, F::Error>),
 }
 
@@ -116,13 +260,35 @@ impl<P, T, B, S> super::advanced::Dispatch for Dispatch<S, T, P> where
         &mut self.transport
     }
 
+    fn negotiation(&mut self) -> Option<Negotiation> {
+        self.negotiation.take()
+    }
+
+    fn observer(&self) -> Option<Arc<ConnectionObserver>> {
+        self.observer.clone()
+    }
+
     fn dispatch(&mut self,
                 request: PipelineMessage<Self::Out, Body<Self::BodyOut, Self::Error>, Self::Error>)
                 -> io::Result<()>
     {
-        if let Ok(request) = request {
+        if self.closing {
+            trace!("connection closing; dropping request");
+            return Ok(());
+        }
+
+        if let Ok(mut request) = request {
+            if let Some(response) = P::answer_inline(&mut self.transport, request.get_ref()) {
+                trace!("request answered by the transport");
+                self.in_flight.push_back((None, InFlight::Done(Ok(Message::WithoutBody(response)))));
+                return Ok(());
+            }
+
+            let (key, interim) = self.interims.handle();
+            P::on_interim(request.get_mut(), interim);
+
             let response = self.service.call(request);
-            self.in_flight.push_back(InFlight::Active(response));
+            self.in_flight.push_back((Some(key), InFlight::Active(response)));
         }
 
         // TODO: Should the error be handled differently?
@@ -131,33 +297,116 @@ impl<P, T, B, S> super::advanced::Dispatch for Dispatch<S, T, P> where
     }
 
     fn poll(&mut self) -> Poll<Option<PipelineMessage<Self::In, Self::Stream, Self::Error>>, io::Error> {
-        for slot in self.in_flight.iter_mut() {
-            slot.poll();
+        let committer = &mut self.committer;
+        let commit_timeout = self.commit_timeout;
+        let handle = &self.handle;
+
+        for &mut (_, ref mut slot) in self.in_flight.iter_mut() {
+            slot.poll(|response| {
+                let future = match *committer {
+                    Some(ref mut committer) => committer.commit(response.get_ref()),
+                    None => None,
+                };
+
+                match future {
+                    Some(future) => Commit::new(future, commit_timeout, handle).map(Some),
+                    None => Ok(None),
+                }
+            });
         }
 
         match self.in_flight.front() {
-            Some(&InFlight::Done(_)) => {}
+            Some(&(_, InFlight::Done(_))) => {}
+            None if self.closing => return Ok(Async::Ready(None)),
             _ => return Ok(Async::NotReady)
         }
 
         match self.in_flight.pop_front() {
-            Some(InFlight::Done(res)) => Ok(Async::Ready(Some(res))),
+            Some((_, InFlight::Done(res))) => {
+                if let Ok(ref response) = res {
+                    if P::closes_connection(response.get_ref()) {
+                        trace!("response closes the connection; dropping {} requests",
+                               self.in_flight.len());
+                        self.in_flight.clear();
+                        self.closing = true;
+                    }
+                }
+
+                Ok(Async::Ready(Some(res)))
+            }
             _ => panic!(),
         }
     }
 
+    fn poll_progress(&mut self) -> Option<Self::In> {
+        while let Some(next) = self.interims.poll() {
+            self.progress.push_back(next);
+        }
+
+        let head = match self.in_flight.front() {
+            Some(&(Some(key), _)) => key,
+            // Answered by the transport, the next requests may still have
+            // some queued
+            Some(&(None, _)) => return None,
+            None => {
+                // Sent once the final responses were written
+                self.progress.clear();
+                return None;
+            }
+        };
+
+        // Keys grow with the requests, lower ones belong to answered ones
+        self.progress.retain(|&(key, _)| key >= head);
+
+        match self.progress.iter().position(|&(key, _)| key == head) {
+            Some(idx) => self.progress.remove(idx).map(|(_, message)| message),
+            None => None,
+        }
+    }
+
     fn has_in_flight(&self) -> bool {
         !self.in_flight.is_empty()
     }
+
+    fn is_closing(&self) -> bool {
+        self.closing
+    }
 }
 
-impl<F: Future> InFlight<F> {
-    fn poll(&mut self) {
-        let res = match *self {
+impl<F: Future> InFlight<F> where F::Error: From<io::Error> {
+    // Polls the response, then its commit if `commit` starts one
+    fn poll<C>(&mut self, commit: C)
+        where C: FnOnce(&F::Item) -> io::Result<Option<Commit>>,
+    {
+        let next = match *self {
             InFlight::Active(ref mut f) => {
                 match f.poll() {
-                    Ok(Async::Ready(e)) => Ok(e),
-                    Err(e) => Err(e),
+                    Ok(Async::Ready(response)) => {
+                        match commit(&response) {
+                            Ok(Some(commit)) => InFlight::Committing(Some(response), commit),
+                            Ok(None) => InFlight::Done(Ok(response)),
+                            Err(e) => InFlight::Done(Err(e.into())),
+                        }
+                    }
+                    Err(e) => InFlight::Done(Err(e)),
+                    Ok(Async::NotReady) => return,
+                }
+            }
+            _ => return self.poll_commit(),
+        };
+        *self = next;
+        self.poll_commit();
+    }
+
+    fn poll_commit(&mut self) {
+        let res = match *self {
+            InFlight::Committing(ref mut response, ref mut commit) => {
+                match commit.poll() {
+                    Ok(Async::Ready(())) => Ok(response.take().unwrap()),
+                    Err(e) => {
+                        debug!("response commit failed; err={}", e);
+                        Err(e.into())
+                    }
                     Ok(Async::NotReady) => return,
                 }
             }
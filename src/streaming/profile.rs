@@ -0,0 +1,117 @@
+use std::{error, fmt};
+use std::str::FromStr;
+
+use streaming::multiplex::MultiplexConfig;
+use streaming::pipeline::PipelineConfig;
+
+/// Named presets for the buffering knobs of both dispatchers.
+///
+/// Picking a profile sets the buffering related fields of `PipelineConfig`
+/// and `MultiplexConfig` to values that go well together, for users who
+/// don't want to tune each of them. The remaining fields keep their
+/// defaults, and any field may still be overridden afterwards:
+///
+/// ```rust,ignore
+/// fn config(&self) -> MultiplexConfig {
+///     MultiplexConfig {
+///         max_in_flight: 128,
+///         .. BufferProfile::Throughput.multiplex_config()
+///     }
+/// }
+/// ```
+///
+/// Profiles parse from, and with the `serde` feature deserialize from, their
+/// names: `"low-memory"`, `"throughput"` and `"latency"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum BufferProfile {
+    /// Keeps as little buffered per connection as possible, for servers
+    /// holding many mostly idle connections.
+    LowMemory,
+
+    /// Buffers generously and processes as many frames as are ready at once,
+    /// for bulk transfers over few connections.
+    Throughput,
+
+    /// Buffers moderately and yields to the other connections of the event
+    /// loop often, keeping the wait of each request short.
+    Latency,
+}
+
+impl BufferProfile {
+    /// Returns the pipeline configuration of this profile.
+    pub fn pipeline_config(&self) -> PipelineConfig {
+        let (in_flight_capacity, max_frames_per_poll, max_pooled_buffers) = match *self {
+            BufferProfile::LowMemory => (4, Some(16), 2),
+            BufferProfile::Throughput => (128, None, 64),
+            BufferProfile::Latency => (32, Some(8), 16),
+        };
+
+        PipelineConfig {
+            in_flight_capacity: in_flight_capacity,
+            max_frames_per_poll: max_frames_per_poll,
+            max_pooled_buffers: max_pooled_buffers,
+            .. PipelineConfig::default()
+        }
+    }
+
+    /// Returns the multiplex configuration of this profile.
+    pub fn multiplex_config(&self) -> MultiplexConfig {
+        let (max_buffered_frames, max_frames_per_poll, max_pooled_buffers) = match *self {
+            BufferProfile::LowMemory => (16, Some(16), 2),
+            BufferProfile::Throughput => (1024, None, 64),
+            BufferProfile::Latency => (64, Some(8), 16),
+        };
+
+        MultiplexConfig {
+            max_buffered_frames: max_buffered_frames,
+            max_frames_per_poll: max_frames_per_poll,
+            max_pooled_buffers: max_pooled_buffers,
+            .. MultiplexConfig::default()
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            BufferProfile::LowMemory => "low-memory",
+            BufferProfile::Throughput => "throughput",
+            BufferProfile::Latency => "latency",
+        }
+    }
+}
+
+impl fmt::Display for BufferProfile {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(self.name())
+    }
+}
+
+impl FromStr for BufferProfile {
+    type Err = ParseBufferProfileError;
+
+    fn from_str(s: &str) -> Result<BufferProfile, ParseBufferProfileError> {
+        match s {
+            "low-memory" => Ok(BufferProfile::LowMemory),
+            "throughput" => Ok(BufferProfile::Throughput),
+            "latency" => Ok(BufferProfile::Latency),
+            _ => Err(ParseBufferProfileError(())),
+        }
+    }
+}
+
+/// Error returned when parsing an unknown `BufferProfile` name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseBufferProfileError(());
+
+impl fmt::Display for ParseBufferProfileError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("unknown buffer profile")
+    }
+}
+
+impl error::Error for ParseBufferProfileError {
+    fn description(&self) -> &str {
+        "unknown buffer profile"
+    }
+}
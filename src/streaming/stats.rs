@@ -0,0 +1,17 @@
+use std::time::Duration;
+
+/// Statistics reported by a dispatcher to its transport.
+///
+/// Handed to the transport's `on_stats` hook at the end of every dispatcher
+/// tick so that adaptive codecs can change their behavior at runtime, e.g.
+/// by switching compression levels or shrinking chunk sizes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of body chunks read from the transport that are buffered,
+    /// waiting for slow body consumers.
+    pub consumer_lag: usize,
+
+    /// Time it took the transport to flush pending writes, if a flush that
+    /// could not complete immediately finished during this tick.
+    pub flush_latency: Option<Duration>,
+}
@@ -0,0 +1,194 @@
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+
+use futures::{Async, Future, Poll, Stream};
+use tokio_service::Service;
+
+use streaming::{Body, Message};
+
+/// A streaming service viewed as a simple one.
+///
+/// Requests are sent without a body, and the body of a response is buffered
+/// in full before the response is yielded along with its chunks. Responses
+/// with more than `max_chunks` body chunks fail with
+/// `ErrorKind::InvalidData`.
+///
+/// Usually obtained from `ClientProxy::into_simple_service`.
+pub struct SimpleView<S> {
+    inner: S,
+    max_chunks: usize,
+}
+
+/// The future returned by `SimpleView`.
+pub struct SimpleViewFuture<F, U, C, E> {
+    state: ViewState<F, U, C, E>,
+    max_chunks: usize,
+}
+
+enum ViewState<F, U, C, E> {
+    Response(F),
+    Body(Option<U>, Body<C, E>, Vec<C>),
+    Done,
+}
+
+/// A simple service viewed as a streaming one.
+///
+/// Requests with a body fail with `ErrorKind::InvalidInput`, responses never
+/// carry one. `B` and `C` are the request and response body chunk types of
+/// the view.
+///
+/// Usually obtained from the `into_streaming_service` method of the simple
+/// clients.
+pub struct StreamingView<S, B, C> {
+    inner: S,
+    _marker: PhantomData<fn() -> (B, C)>,
+}
+
+/// The future returned by `StreamingView`.
+pub struct StreamingViewFuture<F, C, E> {
+    inner: Result<F, Option<E>>,
+    _marker: PhantomData<fn() -> C>,
+}
+
+impl<S> SimpleView<S> {
+    /// Views `service` as a simple service, buffering response bodies of up
+    /// to `max_chunks` chunks.
+    pub fn new(service: S, max_chunks: usize) -> SimpleView<S> {
+        SimpleView {
+            inner: service,
+            max_chunks: max_chunks,
+        }
+    }
+
+    /// Returns a reference to the viewed service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Consumes the view, returning the viewed service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, T, B, U, C, E> Service for SimpleView<S>
+    where S: Service<Request = Message<T, B>, Response = Message<U, Body<C, E>>, Error = E>,
+          E: From<io::Error>,
+{
+    type Request = T;
+    type Response = (U, Vec<C>);
+    type Error = E;
+    type Future = SimpleViewFuture<S::Future, U, C, E>;
+
+    fn call(&self, req: T) -> Self::Future {
+        SimpleViewFuture {
+            state: ViewState::Response(self.inner.call(Message::WithoutBody(req))),
+            max_chunks: self.max_chunks,
+        }
+    }
+}
+
+impl<F, U, C, E> Future for SimpleViewFuture<F, U, C, E>
+    where F: Future<Item = Message<U, Body<C, E>>, Error = E>,
+          E: From<io::Error>,
+{
+    type Item = (U, Vec<C>);
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<(U, Vec<C>), E> {
+        loop {
+            let next = match self.state {
+                ViewState::Response(ref mut response) => {
+                    match try_ready!(response.poll()) {
+                        Message::WithoutBody(response) => {
+                            self.state = ViewState::Done;
+                            return Ok(Async::Ready((response, vec![])));
+                        }
+                        Message::WithBody(response, body) => {
+                            ViewState::Body(Some(response), body, vec![])
+                        }
+                    }
+                }
+                ViewState::Body(ref mut response, ref mut body, ref mut chunks) => {
+                    while let Some(chunk) = try_ready!(body.poll()) {
+                        if chunks.len() == self.max_chunks {
+                            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                                      "response body exceeds the buffering limit").into());
+                        }
+
+                        chunks.push(chunk);
+                    }
+
+                    let response = response.take().unwrap();
+                    return Ok(Async::Ready((response, mem::take(chunks))));
+                }
+                ViewState::Done => panic!("cannot poll SimpleViewFuture twice"),
+            };
+
+            self.state = next;
+        }
+    }
+}
+
+impl<S, B, C> StreamingView<S, B, C> {
+    /// Views `service` as a streaming service.
+    pub fn new(service: S) -> StreamingView<S, B, C> {
+        StreamingView {
+            inner: service,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the viewed service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Consumes the view, returning the viewed service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, B, C> Service for StreamingView<S, B, C>
+    where S: Service,
+          S::Error: From<io::Error>,
+{
+    type Request = Message<S::Request, B>;
+    type Response = Message<S::Response, Body<C, S::Error>>;
+    type Error = S::Error;
+    type Future = StreamingViewFuture<S::Future, C, S::Error>;
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        let inner = match req {
+            Message::WithoutBody(req) => Ok(self.inner.call(req)),
+            Message::WithBody(..) => {
+                Err(Some(io::Error::new(io::ErrorKind::InvalidInput,
+                                        "the service does not take request bodies").into()))
+            }
+        };
+
+        StreamingViewFuture {
+            inner: inner,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<F, C> Future for StreamingViewFuture<F, C, F::Error>
+    where F: Future,
+{
+    type Item = Message<F::Item, Body<C, F::Error>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, F::Error> {
+        match self.inner {
+            Ok(ref mut response) => {
+                let response = try_ready!(response.poll());
+                Ok(Async::Ready(Message::WithoutBody(response)))
+            }
+            Err(ref mut error) => Err(error.take().expect("cannot poll StreamingViewFuture twice")),
+        }
+    }
+}
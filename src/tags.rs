@@ -0,0 +1,197 @@
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use futures::{Async, task};
+use futures::task::Task;
+use tokio_core::io::Io;
+
+/// Tracks the tags assigned to connections and shuts them down by tag.
+///
+/// Pass a `Tags` handle to `TcpServer::tag` and keep a clone of it
+/// around; calling `shutdown_tag` then drains and closes only the connections
+/// carrying that tag, e.g. to evict a tenant.
+pub struct Tags<T> {
+    inner: Arc<Mutex<Vec<(T, Weak<Conn>)>>>,
+}
+
+/// An I/O object tagged at accept time.
+///
+/// Once its tag has been shut down, reads report EOF. The protocol dispatcher
+/// then stops reading new requests, finishes the in-flight ones and closes
+/// the connection.
+pub struct Tagged<I, T> {
+    io: I,
+    tag: T,
+    conn: Arc<Conn>,
+}
+
+struct Conn {
+    shutdown: AtomicBool,
+    task: Mutex<Option<Task>>,
+}
+
+impl<T: PartialEq + Clone> Tags<T> {
+    /// Create a new, empty, set of tags
+    pub fn new() -> Tags<T> {
+        Tags { inner: Arc::new(Mutex::new(Vec::new())) }
+    }
+
+    /// Tag the given I/O object and track it.
+    pub fn tag<I: Io>(&self, io: I, tag: T) -> Tagged<I, T> {
+        let conn = Arc::new(Conn {
+            shutdown: AtomicBool::new(false),
+            task: Mutex::new(None),
+        });
+
+        let mut conns = self.inner.lock().unwrap();
+
+        // Forget about connections that have been closed in the meantime
+        conns.retain(|entry| entry.1.upgrade().is_some());
+        conns.push((tag.clone(), Arc::downgrade(&conn)));
+
+        Tagged {
+            io: io,
+            tag: tag,
+            conn: conn,
+        }
+    }
+
+    /// Drain and close all connections tagged with `tag`.
+    ///
+    /// Returns the number of connections that were signaled.
+    pub fn shutdown_tag(&self, tag: &T) -> usize {
+        let mut conns = self.inner.lock().unwrap();
+        let mut signaled = 0;
+
+        conns.retain(|entry| {
+            let conn = match entry.1.upgrade() {
+                Some(conn) => conn,
+                None => return false,
+            };
+
+            if entry.0 != *tag {
+                return true;
+            }
+
+            conn.shutdown();
+            signaled += 1;
+            false
+        });
+
+        signaled
+    }
+
+    /// Returns the number of open connections tagged with `tag`.
+    pub fn count(&self, tag: &T) -> usize {
+        let conns = self.inner.lock().unwrap();
+
+        conns.iter()
+            .filter(|entry| entry.0 == *tag && entry.1.upgrade().is_some())
+            .count()
+    }
+
+    /// Returns the number of open connections of every tag with at least
+    /// one, in the order the tags were first assigned.
+    pub fn counts(&self) -> Vec<(T, usize)> {
+        let conns = self.inner.lock().unwrap();
+        let mut counts: Vec<(T, usize)> = Vec::new();
+
+        for entry in conns.iter().filter(|entry| entry.1.upgrade().is_some()) {
+            match counts.iter().position(|count| count.0 == entry.0) {
+                Some(i) => counts[i].1 += 1,
+                None => counts.push((entry.0.clone(), 1)),
+            }
+        }
+
+        counts
+    }
+}
+
+impl<T> Clone for Tags<T> {
+    fn clone(&self) -> Tags<T> {
+        Tags { inner: self.inner.clone() }
+    }
+}
+
+impl<I, T> Tagged<I, T> {
+    /// Returns the tag assigned to this connection
+    pub fn tag(&self) -> &T {
+        &self.tag
+    }
+
+    /// Returns a reference to the underlying I/O object
+    pub fn get_ref(&self) -> &I {
+        &self.io
+    }
+
+    /// Returns a mutable reference to the underlying I/O object
+    pub fn get_mut(&mut self) -> &mut I {
+        &mut self.io
+    }
+
+    fn is_shutdown(&self) -> bool {
+        self.conn.shutdown.load(Ordering::SeqCst)
+    }
+}
+
+impl<I: Read, T> Read for Tagged<I, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.is_shutdown() {
+            return Ok(0);
+        }
+
+        match self.io.read(buf) {
+            Err(e) => {
+                if e.kind() == io::ErrorKind::WouldBlock {
+                    // Register interest so that `shutdown_tag` can wake the
+                    // task
+                    *self.conn.task.lock().unwrap() = Some(task::park());
+
+                    // Check again, the tag may have been shut down
+                    // concurrently
+                    if self.is_shutdown() {
+                        return Ok(0);
+                    }
+                }
+
+                Err(e)
+            }
+            res => res,
+        }
+    }
+}
+
+impl<I: Write, T> Write for Tagged<I, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<I: Io, T> Io for Tagged<I, T> {
+    fn poll_read(&mut self) -> Async<()> {
+        if self.is_shutdown() {
+            return Async::Ready(());
+        }
+
+        self.io.poll_read()
+    }
+
+    fn poll_write(&mut self) -> Async<()> {
+        self.io.poll_write()
+    }
+}
+
+impl Conn {
+    fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+
+        if let Some(task) = self.task.lock().unwrap().take() {
+            task.unpark();
+        }
+    }
+}
@@ -1,17 +1,24 @@
+use std::error::Error;
 use std::io;
+use std::mem;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
 use std::sync::Arc;
 use std::net::SocketAddr;
 use std::marker::PhantomData;
+use std::time::Duration;
 
-use BindClient;
+use {BindClient, BindConfig, KindConfig};
+use instrument::{ConnectionObserver, IoMetrics};
+use timeout::IoTimeouts;
+use wrap::{Chain, Connection, Instrument, Plain, Timeouts, Wrap};
+use util::client_proxy::NotSent;
 use tokio_core::reactor::Handle;
 use tokio_core::net::{TcpStream, TcpStreamNew};
+use tokio_service::Service;
 use futures::{Future, Poll, Async};
-
-// TODO: add configuration, e.g.:
-// - connection timeout
-// - multiple addresses
-// - request timeout
+use futures::future::{self, JoinAll};
+use futures::sync::oneshot;
 
 // TODO: consider global event loop handle, so that providing one in the builder
 // is optional
@@ -23,28 +30,53 @@ use futures::{Future, Poll, Async};
 ///
 /// At the moment, this builder offers minimal configuration, but more will be
 /// added over time.
-pub struct TcpClient<Kind, P> {
+pub struct TcpClient<Kind, P, W = Plain> {
     _kind: PhantomData<Kind>,
     proto: Arc<P>,
+    binding: BindConfig,
+    wrap: W,
 }
 
 /// A future for establishing a client connection.
 ///
 /// Yields a service for interacting with the server.
-pub struct Connect<Kind, P> {
+pub struct Connect<Kind, P, W = Plain> where W: Wrap<TcpStream> {
     _kind: PhantomData<Kind>,
     proto: Arc<P>,
-    socket: TcpStreamNew,
+    state: ConnectState<W>,
+    peer: SocketAddr,
     handle: Handle,
+    binding: BindConfig,
+}
+
+enum ConnectState<W> where W: Wrap<TcpStream> {
+    Connecting(TcpStreamNew, W),
+    Wrapping(W::Future),
 }
 
-impl<Kind, P> Future for Connect<Kind, P> where P: BindClient<Kind, TcpStream> {
+impl<Kind, P, W> Future for Connect<Kind, P, W>
+    where W: Wrap<TcpStream>,
+          P: BindClient<Kind, W::Io>,
+{
     type Item = P::BindClient;
     type Error = io::Error;
 
     fn poll(&mut self) -> Poll<P::BindClient, io::Error> {
-        let socket = try_ready!(self.socket.poll());
-        Ok(Async::Ready(self.proto.bind_client(&self.handle, socket)))
+        loop {
+            let wrapping = match self.state {
+                ConnectState::Connecting(ref mut socket, ref wrap) => {
+                    let socket = try_ready!(socket.poll());
+                    wrap.wrap(socket, Connection::new(self.peer), &self.handle)
+                }
+                ConnectState::Wrapping(ref mut wrapping) => {
+                    let (io, _) = try_ready!(wrapping.poll());
+                    let service = self.proto.bind_client_with(&self.handle, io, &self.binding);
+                    return Ok(Async::Ready(service));
+                }
+            };
+
+            self.state = ConnectState::Wrapping(wrapping);
+        }
     }
 }
 
@@ -56,9 +88,101 @@ impl<Kind, P> TcpClient<Kind, P> where P: BindClient<Kind, TcpStream> {
     pub fn new(protocol: P) -> TcpClient<Kind, P> {
         TcpClient {
             _kind: PhantomData,
-            proto: Arc::new(protocol)
+            proto: Arc::new(protocol),
+            binding: BindConfig::default(),
+            wrap: Plain,
         }
     }
+}
+
+impl<Kind, P, W> TcpClient<Kind, P, W> {
+
+    /// Set the max time binding the transport of a connection may take,
+    /// including any handshake done by the protocol.
+    ///
+    /// Connections not bound in time are closed, reporting
+    /// `ErrorKind::TimedOut` as the connection error. Defaults to no limit.
+    pub fn bind_timeout(&mut self, timeout: Duration) {
+        self.binding.timeout = Some(timeout);
+    }
+
+    /// Set the observer told about the activity of every connection.
+    ///
+    /// See `instrument::ConnectionObserver`.
+    pub fn observe(&mut self, observer: Arc<ConnectionObserver>) {
+        self.binding.observer = Some(observer);
+    }
+
+    /// Set the config of every connection, a `PipelineConfig` or
+    /// `MultiplexConfig` used instead of the one of the protocol.
+    ///
+    /// Lets applications tune a protocol, e.g. from a configuration file,
+    /// without wrapping it. Defaults to the protocol's config.
+    pub fn config<C: KindConfig>(&mut self, config: C) {
+        config.set(&mut self.binding);
+    }
+
+    /// Wrap the I/O object of every connection with `wrapper`, after the
+    /// wrappers installed so far.
+    ///
+    /// The protocol is bound to the I/O object of the last wrapper; see the
+    /// `wrap` module for details.
+    pub fn wrap<V>(self, wrapper: V) -> TcpClient<Kind, P, Chain<W, V>> {
+        TcpClient {
+            _kind: PhantomData,
+            proto: self.proto,
+            binding: self.binding,
+            wrap: Chain::new(self.wrap, wrapper),
+        }
+    }
+
+    /// Record the reads and writes of every connection into `metrics`,
+    /// binding the protocol over an `Instrumented` I/O object.
+    ///
+    /// See the `instrument` module for details.
+    pub fn instrument(self, metrics: &IoMetrics) -> TcpClient<Kind, P, Chain<W, Instrument>> {
+        self.wrap(Instrument::new(metrics))
+    }
+
+    /// Fail the reads and writes of every connection that block for longer
+    /// than `timeouts`, binding the protocol over a `TimeoutIo` I/O object.
+    ///
+    /// See the `timeout` module for details.
+    pub fn io_timeouts(self, timeouts: &IoTimeouts) -> TcpClient<Kind, P, Chain<W, Timeouts>> {
+        self.wrap(Timeouts::new(timeouts))
+    }
+
+    /// Establish `connections` connections on every connect, striping
+    /// requests across them.
+    ///
+    /// This works around per-connection throughput limits, such as TCP
+    /// windows or middlebox policers. Striping is per request: requests are
+    /// assigned to connections round-robin, and a request and its body
+    /// always travel over a single connection, so a single huge body is
+    /// still bound by the limits of one connection. Splitting a body across
+    /// connections would need the peer to reassemble it, which the protocols
+    /// of this crate have no means for.
+    ///
+    /// Call it last: the other options are set on the `TcpClient`
+    /// beforehand, and apply to every connection.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `connections` is zero.
+    pub fn multipath(self, connections: usize) -> MultipathClient<Kind, P, W> {
+        assert!(connections > 0, "at least one connection is required");
+
+        MultipathClient {
+            client: self,
+            connections: connections,
+        }
+    }
+}
+
+impl<Kind, P, W> TcpClient<Kind, P, W>
+    where W: Wrap<TcpStream> + Clone,
+          P: BindClient<Kind, W::Io>,
+{
 
     /// Establish a connection to the given address.
     ///
@@ -67,12 +191,452 @@ impl<Kind, P> TcpClient<Kind, P> where P: BindClient<Kind, TcpStream> {
     /// Returns a future for the establishment of the connection. When the
     /// future completes, it yields an instance of `Service` for interacting
     /// with the server.
-    pub fn connect(&self, addr: &SocketAddr, handle: &Handle) -> Connect<Kind, P> {
+    pub fn connect(&self, addr: &SocketAddr, handle: &Handle) -> Connect<Kind, P, W> {
         Connect {
             _kind: PhantomData,
             proto: self.proto.clone(),
-            socket: TcpStream::connect(addr, handle),
+            state: ConnectState::Connecting(TcpStream::connect(addr, handle), self.wrap.clone()),
+            peer: *addr,
             handle: handle.clone(),
+            binding: self.binding.clone(),
+        }
+    }
+
+    /// Return a service for the given address without connecting yet.
+    ///
+    /// The connection is established on the first call to the service. Up to
+    /// `max_queued` requests made while connecting are queued and dispatched
+    /// once the connection is up; further requests fail immediately. If
+    /// connecting fails, the queued requests fail with a `NotSent` error of
+    /// the kind of the connect error and the next call tries again.
+    ///
+    /// The connection counts as lost once a call fails with an I/O error
+    /// such as a broken pipe or a reset connection; the next call then
+    /// reconnects.
+    pub fn lazy(&self, addr: &SocketAddr, handle: &Handle, max_queued: usize) -> LazyClient<Kind, P, W> {
+        let client = TcpClient {
+            _kind: PhantomData,
+            proto: self.proto.clone(),
+            binding: self.binding.clone(),
+            wrap: self.wrap.clone(),
+        };
+
+        LazyClient {
+            inner: Rc::new(Lazy {
+                client: client,
+                addr: *addr,
+                handle: handle.clone(),
+                max_queued: max_queued,
+                state: RefCell::new(State::Idle),
+                generation: Cell::new(0),
+                watchers: RefCell::new(Vec::new()),
+                busy: RefCell::new(None),
+                _kind: PhantomData,
+            }),
+        }
+    }
+}
+
+/// Builds clients striping requests over several connections to the same
+/// service.
+///
+/// Returned by `TcpClient::multipath`.
+pub struct MultipathClient<Kind, P, W = Plain> {
+    client: TcpClient<Kind, P, W>,
+    connections: usize,
+}
+
+impl<Kind, P, W> MultipathClient<Kind, P, W>
+    where W: Wrap<TcpStream> + Clone,
+          P: BindClient<Kind, W::Io>,
+{
+    /// Establish the connections to the given address.
+    ///
+    /// # Return value
+    ///
+    /// Returns a future for the establishment of the connections. When the
+    /// future completes, it yields a `Multipath` service striping requests
+    /// across them. The future fails if any of the connections fails.
+    pub fn connect(&self, addr: &SocketAddr, handle: &Handle) -> ConnectMultipath<Kind, P, W> {
+        let connects = (0..self.connections).map(|_| self.client.connect(addr, handle));
+
+        ConnectMultipath { inner: future::join_all(connects.collect()) }
+    }
+}
+
+/// A future for establishing several connections to the same service.
+///
+/// Yields a `Multipath` service striping requests across the connections.
+pub struct ConnectMultipath<Kind, P, W = Plain>
+    where W: Wrap<TcpStream>,
+          P: BindClient<Kind, W::Io>,
+{
+    inner: JoinAll<Vec<Connect<Kind, P, W>>>,
+}
+
+impl<Kind, P, W> Future for ConnectMultipath<Kind, P, W>
+    where W: Wrap<TcpStream>,
+          P: BindClient<Kind, W::Io>,
+{
+    type Item = Multipath<P::BindClient>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Multipath<P::BindClient>, io::Error> {
+        let services = try_ready!(self.inner.poll());
+        Ok(Async::Ready(Multipath::new(services)))
+    }
+}
+
+/// A service striping requests round-robin across several services.
+///
+/// Usually obtained from `TcpClient::multipath`, with every service being a
+/// connection to the same endpoint. Requests are striped whole, see
+/// `TcpClient::multipath`.
+pub struct Multipath<S> {
+    services: Vec<S>,
+    next: Cell<usize>,
+}
+
+impl<S> Multipath<S> {
+    /// Create a new `Multipath` striping across the given services.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `services` is empty.
+    pub fn new(services: Vec<S>) -> Multipath<S> {
+        assert!(!services.is_empty(), "at least one service is required");
+
+        Multipath {
+            services: services,
+            next: Cell::new(0),
+        }
+    }
+
+    /// Returns the services requests are striped across.
+    pub fn get_ref(&self) -> &[S] {
+        &self.services
+    }
+}
+
+impl<S: Service> Service for Multipath<S> {
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn call(&self, req: S::Request) -> S::Future {
+        let idx = self.next.get();
+        self.next.set((idx + 1) % self.services.len());
+
+        self.services[idx].call(req)
+    }
+}
+
+/// A client service connecting on first use.
+///
+/// Returned by `TcpClient::lazy`.
+pub struct LazyClient<Kind, P, W = Plain>
+    where W: Wrap<TcpStream>,
+          P: BindClient<Kind, W::Io>,
+{
+    inner: Rc<Lazy<Kind, P, W>>,
+}
+
+/// The future returned by `LazyClient::call`.
+pub struct LazyResponse<Kind, P, W = Plain>
+    where W: Wrap<TcpStream>,
+          P: BindClient<Kind, W::Io>,
+{
+    inner: Response<Kind, P, W>,
+    lazy: Rc<Lazy<Kind, P, W>>,
+}
+
+/// Connection lifecycle callbacks of a `LazyClient`.
+///
+/// Lets middleware layers restore session state, such as re-sending
+/// authentication or re-registering subscriptions, whenever the underlying
+/// connection is re-established.
+pub trait ConnectionEvents<S> {
+    /// Called once a connection has been established, before any queued
+    /// requests are dispatched on it.
+    ///
+    /// Requests made on `client` are sent ahead of the queued ones.
+    fn on_connect(&self, client: &S) {
+        let _ = client;
+    }
+
+    /// Called when the connection has been lost.
+    ///
+    /// The next call to the client establishes a new connection.
+    fn on_disconnect(&self) {}
+}
+
+struct Lazy<Kind, P, W>
+    where W: Wrap<TcpStream>,
+          P: BindClient<Kind, W::Io>,
+{
+    client: TcpClient<Kind, P, W>,
+    addr: SocketAddr,
+    handle: Handle,
+    max_queued: usize,
+    state: RefCell<State<Kind, P, W>>,
+    // Incremented on every connection, so that failures of old connections
+    // are not mistaken for the loss of the current one
+    generation: Cell<usize>,
+    watchers: RefCell<Vec<Box<ConnectionEvents<P::BindClient>>>>,
+    // Synthesizes the response to calls rejected for lack of capacity
+    busy: RefCell<Option<Box<Fn() -> P::ServiceResponse>>>,
+    _kind: PhantomData<Kind>,
+}
+
+enum State<Kind, P, W>
+    where W: Wrap<TcpStream>,
+          P: BindClient<Kind, W::Io>,
+{
+    Idle,
+    Connecting(Vec<(P::ServiceRequest, Complete<Kind, P, W>)>),
+    Connected(P::BindClient),
+}
+
+enum Response<Kind, P, W>
+    where W: Wrap<TcpStream>,
+          P: BindClient<Kind, W::Io>,
+{
+    Connected(<P::BindClient as Service>::Future, usize),
+    // Queued requests are dispatched on the next connection, of the given
+    // generation
+    Queued(oneshot::Receiver<Result<P::ServiceResponse, P::ServiceError>>, usize),
+    Failed(Option<P::ServiceError>),
+    Busy(Option<P::ServiceResponse>),
+}
+
+type Complete<Kind, P, W> = oneshot::Sender<Result<<P as BindClient<Kind, Wrapped<W>>>::ServiceResponse,
+                                                   <P as BindClient<Kind, Wrapped<W>>>::ServiceError>>;
+
+// The I/O object a `LazyClient` binds its connections to
+type Wrapped<W> = <W as Wrap<TcpStream>>::Io;
+
+impl<Kind, P, W> Service for LazyClient<Kind, P, W>
+    where Kind: 'static,
+          W: Wrap<TcpStream> + Clone + 'static,
+          P: BindClient<Kind, W::Io>,
+          P::ServiceError: Error + From<io::Error> + 'static,
+{
+    type Request = P::ServiceRequest;
+    type Response = P::ServiceResponse;
+    type Error = P::ServiceError;
+    type Future = LazyResponse<Kind, P, W>;
+
+    fn call(&self, req: P::ServiceRequest) -> LazyResponse<Kind, P, W> {
+        let mut state = self.inner.state.borrow_mut();
+
+        let inner = match *state {
+            State::Connected(ref service) => {
+                Response::Connected(service.call(req), self.inner.generation.get())
+            }
+            State::Connecting(ref mut queued) => {
+                if queued.len() < self.inner.max_queued {
+                    let (tx, rx) = oneshot::channel();
+                    queued.push((req, tx));
+                    Response::Queued(rx, self.inner.generation.get() + 1)
+                } else if let Some(ref busy) = *self.inner.busy.borrow() {
+                    Response::Busy(Some(busy()))
+                } else {
+                    let err = io::Error::new(io::ErrorKind::Other, "too many requests queued while connecting");
+                    Response::Failed(Some(err.into()))
+                }
+            }
+            State::Idle => {
+                let (tx, rx) = oneshot::channel();
+                *state = State::Connecting(vec![(req, tx)]);
+                self.inner.handle.spawn(connect(self.inner.clone()));
+                Response::Queued(rx, self.inner.generation.get() + 1)
+            }
+        };
+
+        LazyResponse {
+            inner: inner,
+            lazy: self.inner.clone(),
+        }
+    }
+}
+
+impl<Kind, P, W> LazyClient<Kind, P, W>
+    where W: Wrap<TcpStream>,
+          P: BindClient<Kind, W::Io>,
+{
+    /// Register callbacks for the connection being established or lost.
+    pub fn watch<E>(&self, events: E)
+        where E: ConnectionEvents<P::BindClient> + 'static,
+    {
+        self.inner.watchers.borrow_mut().push(Box::new(events));
+    }
+}
+
+impl<Kind, P, W> LazyClient<Kind, P, W>
+    where W: Wrap<TcpStream>,
+          P: BindClient<Kind, W::Io>,
+{
+    /// Answer the calls rejected because `max_queued` requests are already
+    /// queued with the response returned by `busy`, instead of failing them.
+    ///
+    /// Protocols with a "try again later" response of their own can use it
+    /// so that callers handle the client being at capacity like any other
+    /// response. The rejected requests are not sent.
+    pub fn busy_response<F>(&self, busy: F)
+        where F: Fn() -> P::ServiceResponse + 'static,
+    {
+        *self.inner.busy.borrow_mut() = Some(Box::new(busy));
+    }
+}
+
+fn is_disconnect<E: Error + 'static>(err: &E) -> bool {
+    match (err as &Error).downcast_ref::<io::Error>() {
+        Some(err) => {
+            match err.kind() {
+                io::ErrorKind::BrokenPipe |
+                io::ErrorKind::ConnectionReset |
+                io::ErrorKind::ConnectionAborted |
+                io::ErrorKind::UnexpectedEof => true,
+                _ => false,
+            }
+        }
+        None => false,
+    }
+}
+
+impl<Kind, P, W> Clone for LazyClient<Kind, P, W>
+    where W: Wrap<TcpStream>,
+          P: BindClient<Kind, W::Io>,
+{
+    fn clone(&self) -> LazyClient<Kind, P, W> {
+        LazyClient { inner: self.inner.clone() }
+    }
+}
+
+// Establishes the connection and dispatches the requests queued meanwhile
+fn connect<Kind, P, W>(lazy: Rc<Lazy<Kind, P, W>>) -> Box<Future<Item = (), Error = ()>>
+    where Kind: 'static,
+          W: Wrap<TcpStream> + Clone + 'static,
+          P: BindClient<Kind, W::Io>,
+          P::ServiceError: From<io::Error>,
+{
+    let connect = lazy.client.connect(&lazy.addr, &lazy.handle);
+
+    Box::new(connect.then(move |res| {
+        let queued = match mem::replace(&mut *lazy.state.borrow_mut(), State::Idle) {
+            State::Connecting(queued) => queued,
+            _ => unreachable!(),
+        };
+
+        match res {
+            Ok(service) => {
+                lazy.generation.set(lazy.generation.get() + 1);
+
+                for watcher in lazy.watchers.borrow().iter() {
+                    watcher.on_connect(&service);
+                }
+
+                for (req, complete) in queued {
+                    lazy.handle.spawn(service.call(req).then(move |res| {
+                        complete.complete(res);
+                        Ok(())
+                    }));
+                }
+
+                *lazy.state.borrow_mut() = State::Connected(service);
+            }
+            Err(e) => {
+                debug!("lazy connect failed; err={}", e);
+
+                // Leave the state idle so that the next call tries again.
+                // The queued requests never went out.
+                for (_, complete) in queued {
+                    let err = io::Error::new(e.kind(), NotSent);
+                    complete.complete(Err(err.into()));
+                }
+            }
+        }
+
+        Ok(())
+    }))
+}
+
+impl<Kind, P, W> Future for LazyResponse<Kind, P, W>
+    where W: Wrap<TcpStream>,
+          P: BindClient<Kind, W::Io>,
+          P::ServiceError: Error + From<io::Error> + 'static,
+{
+    type Item = P::ServiceResponse;
+    type Error = P::ServiceError;
+
+    fn poll(&mut self) -> Poll<P::ServiceResponse, P::ServiceError> {
+        match self.inner {
+            Response::Connected(ref mut f, generation) => {
+                let err = match f.poll() {
+                    Err(err) => err,
+                    res => return res,
+                };
+
+                self.lazy.check_disconnect(&err, generation);
+                Err(err)
+            }
+            Response::Queued(ref mut rx, generation) => {
+                match rx.poll() {
+                    Ok(Async::Ready(Ok(res))) => Ok(Async::Ready(res)),
+                    Ok(Async::Ready(Err(e))) => {
+                        self.lazy.check_disconnect(&e, generation);
+                        Err(e)
+                    }
+                    Ok(Async::NotReady) => Ok(Async::NotReady),
+                    Err(_) => {
+                        let err = io::Error::new(io::ErrorKind::Other, "lazy client dropped");
+                        Err(err.into())
+                    }
+                }
+            }
+            Response::Failed(ref mut err) => {
+                Err(err.take().expect("cannot poll LazyResponse twice"))
+            }
+            Response::Busy(ref mut res) => {
+                Ok(Async::Ready(res.take().expect("cannot poll LazyResponse twice")))
+            }
+        }
+    }
+}
+
+impl<Kind, P, W> Lazy<Kind, P, W>
+    where W: Wrap<TcpStream>,
+          P: BindClient<Kind, W::Io>,
+{
+    // Drops the connection of the given generation if `err` tells it is lost
+    fn check_disconnect(&self, err: &P::ServiceError, generation: usize)
+        where P::ServiceError: Error + 'static,
+    {
+        if is_disconnect(err) {
+            self.disconnected(generation);
+        }
+    }
+
+    // Drops the connection of the given generation, if still current
+    fn disconnected(&self, generation: usize) {
+        if generation != self.generation.get() {
+            return;
+        }
+
+        {
+            let mut state = self.state.borrow_mut();
+
+            match *state {
+                State::Connected(..) => *state = State::Idle,
+                _ => return,
+            }
+        }
+
+        debug!("lazy client lost connection; generation={}", generation);
+
+        for watcher in self.watchers.borrow().iter() {
+            watcher.on_disconnect();
         }
     }
 }
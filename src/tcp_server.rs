@@ -1,22 +1,28 @@
+use std::collections::HashMap;
 use std::io;
 use std::marker::PhantomData;
-use std::net::SocketAddr;
-use std::sync::Arc;
+use std::net::{self, SocketAddr};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
-use BindServer;
+use {BindConfig, BindServer, KindConfig};
+use instrument::{ConnectionObserver, IoMetrics};
+use timeout::IoTimeouts;
+use tags::Tags;
+use wrap::{Chain, Connection, Instrument, Peek, Plain, Tag, Timeouts, Wrap};
+use timeout::Deadline;
 use futures::stream::Stream;
 use futures::future::{Then, Future};
+use futures::{task, Async, Poll};
+use futures::sync::mpsc;
 use net2;
 use tokio_core::net::{TcpStream, TcpListener};
-use tokio_core::reactor::{Core, Handle};
+use tokio_core::reactor::{Core, Handle, Timeout};
 use tokio_service::{NewService, Service};
 
 // TODO: Add more options, e.g.:
-// - max concurrent requests
-// - request timeout
-// - read timeout
-// - write timeout
 // - max idle time
 // - max lifetime
 
@@ -32,11 +38,107 @@ use tokio_service::{NewService, Service};
 /// configuration, which is expected to grow over time.
 ///
 /// See the crate docs for an example.
-pub struct TcpServer<Kind, P> {
+pub struct TcpServer<Kind, P, W = Plain> {
     _kind: PhantomData<Kind>,
     proto: Arc<P>,
     threads: usize,
     addr: SocketAddr,
+    binding: BindConfig,
+    max_handshakes: Option<usize>,
+    max_connections: Option<usize>,
+    at_capacity: AtCapacity,
+    max_accept_rate: Option<(usize, Duration)>,
+    wrap: W,
+}
+
+/// What a `TcpServer` does with new connections once `max_connections` are
+/// open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AtCapacity {
+    /// Stop accepting until a connection closes. Further connections queue
+    /// up in the listen backlog of the OS meanwhile.
+    Pause,
+
+    /// Keep accepting, closing the surplus connections right away, so that
+    /// peers find out instead of waiting in the backlog.
+    Close,
+}
+
+/// Creates the service of every connection accepted by a `TcpServer`.
+///
+/// Implemented for every `NewService`, which is told nothing about the
+/// connection. Wrap a closure in a `PerConnection` to create services from
+/// the `Connection` instead.
+pub trait NewConnectionService {
+    /// Requests handled by the service
+    type Request;
+
+    /// Responses given by the service
+    type Response;
+
+    /// Errors produced by the service
+    type Error;
+
+    /// The `Service` value created by this factory
+    type Instance: Service<Request = Self::Request,
+                           Response = Self::Response,
+                           Error = Self::Error>;
+
+    /// Create the service of `conn`, or `None` to refuse the connection,
+    /// which is then closed without being bound.
+    ///
+    /// Returning an error stops the event loop accepting the connection.
+    fn new_connection_service(&self, conn: &Connection) -> io::Result<Option<Self::Instance>>;
+}
+
+/// Creates the service of every connection from what is known about it.
+///
+/// Unlike `NewService::new_service`, the closure learns who is connecting,
+/// so that services can keep per-peer state or check the address of the
+/// peer against an access list. Returning an error refuses the connection,
+/// which is closed without being bound; the server keeps accepting other
+/// connections.
+///
+/// ```rust,ignore
+/// TcpServer::new(proto, addr).serve(PerConnection::new(|conn: &Connection| {
+///     Ok(Greeter::new(conn.peer()))
+/// }));
+/// ```
+pub struct PerConnection<F> {
+    new_service: F,
+}
+
+/// Picks the factory of every connection's service by the route of the
+/// connection.
+///
+/// Routes are set before binding, usually by the peeker of
+/// `TcpServer::peek` with `Connection::set_route`, e.g. to the server name
+/// of a TLS ClientHello or to the protocol sniffed, so that one port serves
+/// several services. Connections without a route, or with one that wasn't
+/// added, get their service from the fallback factory, or are refused if
+/// there is none.
+///
+/// ```rust,ignore
+/// let mut routes = Route::new();
+/// routes.add("api.example.com", api);
+/// routes.add("admin.example.com", admin);
+///
+/// TcpServer::new(proto, addr)
+///     .peek(512, |conn: &mut Connection, buf: &mut Vec<u8>| {
+///         match server_name(buf) {
+///             Some(name) => conn.set_route(name),
+///             None => return Ok(false),
+///         }
+///         Ok(true)
+///     })
+///     .serve(routes);
+/// ```
+///
+/// All routes are served with the protocol of the server, by factories of
+/// the same type.
+pub struct Route<S> {
+    routes: HashMap<String, S>,
+    fallback: Option<S>,
 }
 
 impl<Kind, P> TcpServer<Kind, P> where
@@ -61,27 +163,197 @@ impl<Kind, P> TcpServer<Kind, P> where
             proto: Arc::new(protocol),
             threads: 1,
             addr: addr,
+            binding: BindConfig::default(),
+            max_handshakes: None,
+            max_connections: None,
+            at_capacity: AtCapacity::Pause,
+            max_accept_rate: None,
+            wrap: Plain,
         }
     }
+}
 
+impl<Kind, P, W> TcpServer<Kind, P, W> {
     /// Set the address for the server.
     pub fn addr(&mut self, addr: SocketAddr) {
         self.addr = addr;
     }
 
-    /// Set the number of threads running simultaneous event loops (Unix only).
+    /// Set the number of threads running simultaneous event loops.
+    ///
+    /// Every thread accepts connections on a listener of its own, bound to
+    /// the same address with `SO_REUSEPORT`, so that the OS spreads the
+    /// connections across threads. Where `SO_REUSEPORT` is not available, a
+    /// dedicated thread accepts the connections on a single listener and
+    /// deals them out to the event loops round-robin instead. Defaults to 1.
     pub fn threads(&mut self, threads: usize) {
         assert!(threads > 0);
-        if cfg!(unix) {
-            self.threads = threads;
+        self.threads = threads;
+    }
+
+    /// Set the max time binding the transport of a connection may take,
+    /// including any handshake done by the protocol.
+    ///
+    /// Connections not bound in time are closed. Defaults to no limit.
+    pub fn bind_timeout(&mut self, timeout: Duration) {
+        self.binding.timeout = Some(timeout);
+    }
+
+    /// Set the max number of connections binding their transport at once,
+    /// e.g. running an expensive TLS or authentication handshake.
+    ///
+    /// Once reached, no more connections are accepted until one of them is
+    /// bound or fails; further connections queue up in the listen backlog of
+    /// the OS meanwhile. The limit is shared by all threads. Defaults to no
+    /// limit.
+    pub fn max_handshakes(&mut self, max: usize) {
+        assert!(max > 0);
+        self.max_handshakes = Some(max);
+    }
+
+    /// Set the max number of connections open at once, keeping a flood of
+    /// connections from exhausting the file descriptors of the process.
+    ///
+    /// What happens to further connections is up to `at_capacity`. The limit
+    /// is shared by all threads. Defaults to no limit.
+    pub fn max_connections(&mut self, max: usize) {
+        assert!(max > 0);
+        self.max_connections = Some(max);
+    }
+
+    /// Set what happens to new connections once `max_connections` are open.
+    ///
+    /// Defaults to `AtCapacity::Pause`.
+    pub fn at_capacity(&mut self, policy: AtCapacity) {
+        self.at_capacity = policy;
+    }
+
+    /// Set the max number of connections accepted per `period`.
+    ///
+    /// Once reached, no more connections are accepted until the period is
+    /// over; further connections queue up in the listen backlog of the OS
+    /// meanwhile. Periods follow each other, starting with the first accept
+    /// after the previous one ended. The limit is shared by all threads.
+    /// Defaults to no limit.
+    pub fn max_accept_rate(&mut self, accepts: usize, period: Duration) {
+        assert!(accepts > 0);
+        self.max_accept_rate = Some((accepts, period));
+    }
+
+    /// Set the max number of frames any connection processes in a single
+    /// poll, on top of the limit configured by the protocol.
+    ///
+    /// Keeps a chatty connection from holding up the other connections of
+    /// its event loop, whatever the protocol configured; see
+    /// `BindConfig::max_frames_per_poll`. Defaults to no limit beyond the
+    /// protocol's.
+    pub fn max_frames_per_poll(&mut self, max: usize) {
+        assert!(max > 0);
+        self.binding.max_frames_per_poll = Some(max);
+    }
+
+    /// Set the observer told about the activity of every connection.
+    ///
+    /// See `instrument::ConnectionObserver`.
+    pub fn observe(&mut self, observer: Arc<ConnectionObserver>) {
+        self.binding.observer = Some(observer);
+    }
+
+    /// Set the config of every connection, a `PipelineConfig` or
+    /// `MultiplexConfig` used instead of the one of the protocol.
+    ///
+    /// Lets applications tune a protocol, e.g. from a configuration file,
+    /// without wrapping it. Defaults to the protocol's config.
+    pub fn config<C: KindConfig>(&mut self, config: C) {
+        config.set(&mut self.binding);
+    }
+
+    /// Wrap the I/O object of every accepted connection with `wrapper`,
+    /// after the wrappers installed so far.
+    ///
+    /// The protocol is bound to the I/O object of the last wrapper; see the
+    /// `wrap` module for details.
+    pub fn wrap<V>(self, wrapper: V) -> TcpServer<Kind, P, Chain<W, V>> {
+        TcpServer {
+            _kind: PhantomData,
+            proto: self.proto,
+            threads: self.threads,
+            addr: self.addr,
+            binding: self.binding,
+            max_handshakes: self.max_handshakes,
+            max_connections: self.max_connections,
+            at_capacity: self.at_capacity,
+            max_accept_rate: self.max_accept_rate,
+            wrap: Chain::new(self.wrap, wrapper),
         }
     }
 
+    /// Tag every accepted connection, binding the protocol over a `Tagged`
+    /// I/O object.
+    ///
+    /// The `tagger` closure computes the tag of a connection, e.g. from the
+    /// address of the peer. Connections are tracked by `tags`; keep a clone
+    /// of it to shut down connections by tag with `Tags::shutdown_tag`.
+    pub fn tag<T, G>(self, tags: Tags<T>, tagger: G) -> TcpServer<Kind, P, Chain<W, Tag<T, G>>>
+        where T: PartialEq + Clone,
+              G: Fn(&Connection) -> T,
+    {
+        self.wrap(Tag::new(tags, tagger))
+    }
+
+    /// Record the reads and writes of every accepted connection into
+    /// `metrics`, binding the protocol over an `Instrumented` I/O object.
+    ///
+    /// See the `instrument` module for details.
+    pub fn instrument(self, metrics: &IoMetrics) -> TcpServer<Kind, P, Chain<W, Instrument>> {
+        self.wrap(Instrument::new(metrics))
+    }
+
+    /// Fail the reads and writes of every accepted connection that block for
+    /// longer than `timeouts`, binding the protocol over a `TimeoutIo` I/O
+    /// object.
+    ///
+    /// See the `timeout` module for details.
+    pub fn io_timeouts(self, timeouts: &IoTimeouts) -> TcpServer<Kind, P, Chain<W, Timeouts>> {
+        self.wrap(Timeouts::new(timeouts))
+    }
+
+    /// Peek at the first bytes of every accepted connection before binding
+    /// it, binding the protocol over a `Rewind` I/O object.
+    ///
+    /// After every read, `peeker` is called with the connection and the
+    /// bytes read so far, up to `max_len` of them, returning `false` to have
+    /// more read. The bytes it leaves in the buffer are replayed to the
+    /// transport; see `util::framed::peek`. This way a protocol can be
+    /// sniffed, or a PROXY header parsed, without taking any bytes away from
+    /// the codec. Changes `peeker` makes to the connection, e.g. with
+    /// `Connection::set_peer` or `Connection::set_route`, are seen by the
+    /// wrappers after it and by the factory of the connection's service.
+    ///
+    /// Returning an error, or not recognizing the bytes, refuses the
+    /// connection, which is closed without being bound. The bind timeout
+    /// applies to peeking as well.
+    pub fn peek<F>(self, max_len: usize, peeker: F) -> TcpServer<Kind, P, Chain<W, Peek<F>>>
+        where F: Fn(&mut Connection, &mut Vec<u8>) -> io::Result<bool>,
+    {
+        self.wrap(Peek::new(max_len, peeker))
+    }
+}
+
+impl<Kind, P, W> TcpServer<Kind, P, W> where
+    W: Wrap<TcpStream> + Clone + Send + Sync + 'static,
+    W::Future: 'static,
+    P: BindServer<Kind, W::Io> + Send + Sync + 'static
+{
+
     /// Start up the server, providing the given service on it.
     ///
+    /// `new_service` is usually a `NewService`; see `PerConnection` to
+    /// create services from the connection instead.
+    ///
     /// This method will block the current thread until the server is shut down.
     pub fn serve<S>(&self, new_service: S) where
-        S: NewService + Send + Sync + 'static,
+        S: NewConnectionService + Send + Sync + 'static,
         S::Instance: 'static,
         P::ServiceError: 'static,
         P::ServiceResponse: 'static,
@@ -91,20 +363,26 @@ impl<Kind, P> TcpServer<Kind, P> where
         S::Error: Into<P::ServiceError>,
     {
         let new_service = Arc::new(new_service);
-        self.with_handle(move |_| new_service.clone())
+
+        run(self.proto.clone(), self.addr, self.threads, self.binding(),
+            self.wrap.clone(), move |_| {
+                let new_service = new_service.clone();
+                move |conn: &Connection| new_service.new_connection_service(conn)
+            })
     }
 
     /// Start up the server, providing the given service on it, and providing
     /// access to the event loop handle.
     ///
     /// The `new_service` argument is a closure that is given an event loop
-    /// handle, and produces a value implementing `NewService`. That value is in
-    /// turn used to make a new service instance for each incoming connection.
+    /// handle, and produces a value implementing `NewService`, or more
+    /// generally `NewConnectionService`. That value is in turn used to make a
+    /// new service instance for each incoming connection.
     ///
     /// This method will block the current thread until the server is shut down.
     pub fn with_handle<F, S>(&self, new_service: F) where
         F: Fn(&Handle) -> S + Send + Sync + 'static,
-        S: NewService + Send + Sync + 'static,
+        S: NewConnectionService + 'static,
         S::Instance: 'static,
         P::ServiceError: 'static,
         P::ServiceResponse: 'static,
@@ -113,33 +391,416 @@ impl<Kind, P> TcpServer<Kind, P> where
         S::Response: Into<P::ServiceResponse>,
         S::Error: Into<P::ServiceError>,
     {
-        let proto = self.proto.clone();
-        let new_service = Arc::new(new_service);
-        let addr = self.addr;
-        let workers = self.threads;
+        run(self.proto.clone(), self.addr, self.threads, self.binding(),
+            self.wrap.clone(), move |handle| {
+                let new_service = new_service(handle);
+                move |conn: &Connection| new_service.new_connection_service(conn)
+            })
+    }
+}
+
+impl<S: NewService> NewConnectionService for S {
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Instance = S::Instance;
+
+    fn new_connection_service(&self, _: &Connection) -> io::Result<Option<S::Instance>> {
+        self.new_service().map(Some)
+    }
+}
+
+impl<F> PerConnection<F> {
+    /// Create a factory calling `new_service` for every connection.
+    pub fn new(new_service: F) -> PerConnection<F> {
+        PerConnection { new_service: new_service }
+    }
+}
+
+impl<F, S> NewConnectionService for PerConnection<F>
+    where F: Fn(&Connection) -> io::Result<S>,
+          S: Service,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Instance = S;
+
+    fn new_connection_service(&self, conn: &Connection) -> io::Result<Option<S>> {
+        match (self.new_service)(conn) {
+            Ok(service) => Ok(Some(service)),
+            Err(e) => {
+                debug!("refused connection; peer={}, err={}", conn.peer(), e);
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl<S> Route<S> {
+    /// Create a router without any routes, refusing every connection.
+    pub fn new() -> Route<S> {
+        Route {
+            routes: HashMap::new(),
+            fallback: None,
+        }
+    }
+
+    /// Create the services of the connections routed to `route` with
+    /// `new_service`.
+    pub fn add(&mut self, route: &str, new_service: S) {
+        self.routes.insert(route.to_string(), new_service);
+    }
+
+    /// Create the services of the connections not routed to any added route
+    /// with `new_service`.
+    pub fn fallback(&mut self, new_service: S) {
+        self.fallback = Some(new_service);
+    }
+}
+
+impl<S> Default for Route<S> {
+    fn default() -> Route<S> {
+        Route::new()
+    }
+}
+
+impl<S: NewConnectionService> NewConnectionService for Route<S> {
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Instance = S::Instance;
+
+    fn new_connection_service(&self, conn: &Connection) -> io::Result<Option<S::Instance>> {
+        let new_service = conn.route()
+            .and_then(|route| self.routes.get(route))
+            .or(self.fallback.as_ref());
+
+        match new_service {
+            Some(new_service) => new_service.new_connection_service(conn),
+            None => {
+                debug!("refused unrouted connection; peer={}, route={:?}",
+                       conn.peer(), conn.route());
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl<Kind, P, W> TcpServer<Kind, P, W> {
+    fn binding(&self) -> Binding {
+        Binding {
+            config: self.binding.clone(),
+            handshakes: self.max_handshakes.map(Slots::new),
+            connections: self.max_connections.map(Slots::new),
+            at_capacity: self.at_capacity,
+            accept_rate: self.max_accept_rate.map(|(max, period)| {
+                Arc::new(AcceptRate {
+                    max: max,
+                    period: period,
+                    state: Mutex::new(RateState { start: Instant::now(), accepted: 0 }),
+                })
+            }),
+        }
+    }
+}
+
+// How connections are bound, shared by the workers
+#[derive(Clone)]
+struct Binding {
+    config: BindConfig,
+    handshakes: Option<Arc<Slots>>,
+    connections: Option<Arc<Slots>>,
+    at_capacity: AtCapacity,
+    accept_rate: Option<Arc<AcceptRate>>,
+}
+
+// A limited number of connections, e.g. those binding their transport
+struct Slots {
+    max: usize,
+    state: Mutex<SlotsState>,
+}
+
+struct SlotsState {
+    active: usize,
+    // Accept tasks waiting for a slot to be released
+    waiting: Vec<task::Task>,
+}
+
+// Held by a connection while it counts towards a limit, e.g. until its
+// transport is bound
+struct Slot {
+    slots: Arc<Slots>,
+}
+
+// Connections accepted in the current period
+struct AcceptRate {
+    max: usize,
+    period: Duration,
+    state: Mutex<RateState>,
+}
+
+struct RateState {
+    start: Instant,
+    accepted: usize,
+}
+
+// Accepts connections within the limits of the server
+struct Throttle<S> {
+    incoming: S,
+    handle: Handle,
+    handshakes: Option<Arc<Slots>>,
+    connections: Option<Arc<Slots>>,
+    at_capacity: AtCapacity,
+    accept_rate: Option<Arc<AcceptRate>>,
+    // Acquired for the next connection
+    handshake: Option<Slot>,
+    connection: Option<Slot>,
+    reserved: bool,
+    // Fires once the accept rate period is over
+    timer: Option<Timeout>,
+}
+
+impl Slots {
+    fn new(max: usize) -> Arc<Slots> {
+        Arc::new(Slots {
+            max: max,
+            state: Mutex::new(SlotsState { active: 0, waiting: vec![] }),
+        })
+    }
+
+    fn try_acquire(slots: &Arc<Slots>) -> Option<Slot> {
+        let mut state = slots.state.lock().unwrap();
+
+        if state.active == slots.max {
+            return None;
+        }
+
+        state.active += 1;
+        Some(Slot { slots: slots.clone() })
+    }
+
+    fn poll_acquire(slots: &Arc<Slots>) -> Async<Slot> {
+        if let Some(slot) = Slots::try_acquire(slots) {
+            return Async::Ready(slot);
+        }
+
+        let mut state = slots.state.lock().unwrap();
+
+        // A slot may have been released in between
+        if state.active < slots.max {
+            drop(state);
+            return Slots::poll_acquire(slots);
+        }
+
+        state.waiting.push(task::park());
+        Async::NotReady
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut state = self.slots.state.lock().unwrap();
+        state.active -= 1;
+
+        for task in state.waiting.drain(..) {
+            task.unpark();
+        }
+    }
+}
+
+impl AcceptRate {
+    // Counts the next connection towards the current period, or returns when
+    // the period is over if it is full
+    fn reserve(&self) -> Option<Instant> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        if now >= state.start + self.period {
+            state.start = now;
+            state.accepted = 0;
+        }
+
+        if state.accepted == self.max {
+            return Some(state.start + self.period);
+        }
+
+        state.accepted += 1;
+        None
+    }
+}
+
+impl<S> Throttle<S> {
+    fn new(incoming: S, binding: Binding, handle: &Handle) -> Throttle<S> {
+        Throttle {
+            incoming: incoming,
+            handle: handle.clone(),
+            handshakes: binding.handshakes,
+            connections: binding.connections,
+            at_capacity: binding.at_capacity,
+            accept_rate: binding.accept_rate,
+            handshake: None,
+            connection: None,
+            reserved: false,
+            timer: None,
+        }
+    }
+
+    // Waits for the accept rate to allow another connection
+    fn poll_rate(&mut self) -> Poll<(), io::Error> {
+        let rate = match self.accept_rate {
+            Some(ref rate) if !self.reserved => rate.clone(),
+            _ => return Ok(Async::Ready(())),
+        };
+
+        loop {
+            if let Some(ref mut timer) = self.timer {
+                try_ready!(timer.poll());
+            }
+
+            self.timer = None;
+
+            match rate.reserve() {
+                Some(end) => {
+                    trace!("accept rate reached, pausing accept");
+                    self.timer = Some(try!(Timeout::new_at(end, &self.handle)));
+                }
+                None => {
+                    self.reserved = true;
+                    return Ok(Async::Ready(()));
+                }
+            }
+        }
+    }
+}
+
+impl<S, T> Stream for Throttle<S>
+    where S: Stream<Item = (T, SocketAddr), Error = io::Error>,
+{
+    type Item = (T, SocketAddr, Option<Slot>, Option<Slot>);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, io::Error> {
+        loop {
+            if let Some(ref handshakes) = self.handshakes {
+                if self.handshake.is_none() {
+                    match Slots::poll_acquire(handshakes) {
+                        Async::Ready(slot) => self.handshake = Some(slot),
+                        Async::NotReady => {
+                            trace!("too many handshakes, pausing accept");
+                            return Ok(Async::NotReady);
+                        }
+                    }
+                }
+            }
+
+            if let Some(ref connections) = self.connections {
+                if self.connection.is_none() && self.at_capacity == AtCapacity::Pause {
+                    match Slots::poll_acquire(connections) {
+                        Async::Ready(slot) => self.connection = Some(slot),
+                        Async::NotReady => {
+                            trace!("too many connections, pausing accept");
+                            return Ok(Async::NotReady);
+                        }
+                    }
+                }
+            }
+
+            try_ready!(self.poll_rate());
+
+            let (socket, addr) = match try_ready!(self.incoming.poll()) {
+                Some(accepted) => accepted,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            self.reserved = false;
+
+            if let Some(ref connections) = self.connections {
+                if self.connection.is_none() {
+                    match Slots::try_acquire(connections) {
+                        Some(slot) => self.connection = Some(slot),
+                        None => {
+                            // Dropping the socket closes it
+                            debug!("too many connections, closing; peer={}", addr);
+                            continue;
+                        }
+                    }
+                }
+            }
+
+            let slots = (self.handshake.take(), self.connection.take());
+            return Ok(Async::Ready(Some((socket, addr, slots.0, slots.1))));
+        }
+    }
+}
+
+// Adapts a service to the request, response and error types of a protocol
+pub struct WrapService<S, Request, Response, Error> {
+    inner: S,
+    // Counts the connection towards `max_connections` until the service,
+    // owned by the connection, is dropped
+    _connection: Option<Slot>,
+    _marker: PhantomData<fn() -> (Request, Response, Error)>,
+}
 
-        let threads = (0..self.threads - 1).map(|i| {
-            let proto = proto.clone();
-            let new_service = new_service.clone();
+impl<S, Request, Response, Error> Service for WrapService<S, Request, Response, Error>
+    where S: Service,
+          S::Request: From<Request>,
+          S::Response: Into<Response>,
+          S::Error: Into<Error>,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = Error;
+    type Future = Then<S::Future,
+                       Result<Response, Error>,
+                       fn(Result<S::Response, S::Error>) -> Result<Response, Error>>;
 
-            thread::Builder::new().name(format!("worker{}", i)).spawn(move || {
-                serve(proto, addr, workers, &*new_service)
-            }).unwrap()
-        }).collect::<Vec<_>>();
+    fn call(&self, req: Request) -> Self::Future {
+        fn change_types<A, B, C, D>(r: Result<A, B>) -> Result<C, D>
+            where A: Into<C>,
+                  B: Into<D>,
+        {
+            match r {
+                Ok(e) => Ok(e.into()),
+                Err(e) => Err(e.into()),
+            }
+        }
 
-        serve(proto, addr, workers, &*new_service);
+        self.inner.call(S::Request::from(req)).then(change_types)
+    }
+}
 
-        for thread in threads {
-            thread.join().unwrap();
+impl<S, Request, Response, Error> WrapService<S, Request, Response, Error> {
+    #[cfg(all(unix, feature = "unix"))]
+    pub fn new(inner: S) -> WrapService<S, Request, Response, Error> {
+        WrapService::holding(inner, None)
+    }
+
+    fn holding(inner: S, connection: Option<Slot>) -> WrapService<S, Request, Response, Error> {
+        WrapService {
+            inner: inner,
+            _connection: connection,
+            _marker: PhantomData,
         }
     }
 }
 
-fn serve<P, Kind, F, S>(binder: Arc<P>, addr: SocketAddr, workers: usize, new_service: &F)
-    where P: BindServer<Kind, TcpStream>,
-          F: Fn(&Handle) -> S,
-          S: NewService + Send + Sync,
-          S::Instance: 'static,
+// `new_service` is called once per worker, returning the factory of the
+// services of its connections. The factory returns `None` to refuse a
+// connection, or an error to shut down the worker.
+fn run<P, Kind, W, F, N, S>(proto: Arc<P>,
+                            addr: SocketAddr,
+                            workers: usize,
+                            binding: Binding,
+                            wrap: W,
+                            new_service: F)
+    where P: BindServer<Kind, W::Io> + Send + Sync + 'static,
+          W: Wrap<TcpStream> + Send + Sync + 'static,
+          W::Future: 'static,
+          F: Fn(&Handle) -> N + Send + Sync + 'static,
+          N: Fn(&Connection) -> io::Result<Option<S>> + 'static,
+          S: Service + 'static,
           P::ServiceError: 'static,
           P::ServiceResponse: 'static,
           P::ServiceRequest: 'static,
@@ -147,87 +808,258 @@ fn serve<P, Kind, F, S>(binder: Arc<P>, addr: SocketAddr, workers: usize, new_se
           S::Response: Into<P::ServiceResponse>,
           S::Error: Into<P::ServiceError>,
 {
-    struct WrapService<S, Request, Response, Error> {
-        inner: S,
-        _marker: PhantomData<fn() -> (Request, Response, Error)>,
-    }
+    let listen = Listen::new(&addr, workers).unwrap();
+    let wrap = Arc::new(wrap);
 
-    impl<S, Request, Response, Error> Service for WrapService<S, Request, Response, Error>
-        where S: Service,
-              S::Request: From<Request>,
-              S::Response: Into<Response>,
-              S::Error: Into<Error>,
-    {
-        type Request = Request;
-        type Response = Response;
-        type Error = Error;
-        type Future = Then<S::Future,
-                           Result<Response, Error>,
-                           fn(Result<S::Response, S::Error>) -> Result<Response, Error>>;
-
-        fn call(&self, req: Request) -> Self::Future {
-            fn change_types<A, B, C, D>(r: Result<A, B>) -> Result<C, D>
-                where A: Into<C>,
-                      B: Into<D>,
-            {
-                match r {
-                    Ok(e) => Ok(e.into()),
-                    Err(e) => Err(e.into()),
-                }
-            }
+    spawn_workers(workers, move || {
+        serve(proto.clone(), &listen, binding.clone(), wrap.clone(), &new_service)
+    })
+}
 
-            self.inner.call(S::Request::from(req)).then(change_types)
-        }
+// Runs `worker` on `workers` threads, the current one included, until all of
+// them return
+fn spawn_workers<F>(workers: usize, worker: F)
+    where F: Fn() + Send + Sync + 'static,
+{
+    let worker = Arc::new(worker);
+
+    let threads = (0..workers - 1).map(|i| {
+        let worker = worker.clone();
+
+        thread::Builder::new().name(format!("worker{}", i)).spawn(move || {
+            worker()
+        }).unwrap()
+    }).collect::<Vec<_>>();
+
+    worker();
+
+    for thread in threads {
+        thread.join().unwrap();
     }
+}
 
+fn serve<P, Kind, W, F, N, S>(binder: Arc<P>,
+                              listen: &Listen,
+                              binding: Binding,
+                              wrap: Arc<W>,
+                              new_service: &F)
+    where P: BindServer<Kind, W::Io> + 'static,
+          W: Wrap<TcpStream> + 'static,
+          W::Future: 'static,
+          F: Fn(&Handle) -> N,
+          N: Fn(&Connection) -> io::Result<Option<S>> + 'static,
+          S: Service + 'static,
+          P::ServiceError: 'static,
+          P::ServiceResponse: 'static,
+          P::ServiceRequest: 'static,
+          S::Request: From<P::ServiceRequest>,
+          S::Response: Into<P::ServiceResponse>,
+          S::Error: Into<P::ServiceError>,
+{
     let mut core = Core::new().unwrap();
     let handle = core.handle();
-    let new_service = new_service(&handle);
-    let listener = listener(&addr, workers, &handle).unwrap();
+    let new_service = Rc::new(new_service(&handle));
 
-    let server = listener.incoming().for_each(move |(socket, _)| {
-        // Create the service
-        let service = try!(new_service.new_service());
+    let config = binding.config.clone();
+    let incoming = Throttle::new(listen.incoming(&handle).unwrap(), binding, &handle);
 
-        // Bind it!
-        binder.bind_server(&handle, socket, WrapService {
-            inner: service,
-            _marker: PhantomData,
-        });
+    let server = incoming.for_each(move |(socket, addr, guard, connection)| {
+        let mut wrapped = wrap.wrap(socket, Connection::new(addr), &handle);
+
+        // Most wrappers are done right away, the others, e.g. peeking at the
+        // first bytes, finish on a task of their own
+        match wrapped.poll() {
+            Ok(Async::Ready(wrapped)) => {
+                bind_connection(&*binder, &handle, wrapped, &*new_service,
+                                &config, guard, connection)
+            }
+            Ok(Async::NotReady) => {
+                let binder = binder.clone();
+                let new_service = new_service.clone();
+                let reactor = handle.clone();
+                let config = config.clone();
+
+                // The handshake guard is held while wrapping
+                let wrapped = Deadline::new(wrapped, config.timeout, &handle);
+                let bind = wrapped.then(move |res| {
+                    let res = res.and_then(|wrapped| {
+                        bind_connection(&*binder, &reactor, wrapped, &*new_service,
+                                        &config, guard, connection)
+                    });
+
+                    if let Err(e) = res {
+                        debug!("refused connection; peer={}, err={}", addr, e);
+                    }
+
+                    Ok(())
+                });
 
-        Ok(())
+                handle.spawn(bind);
+                Ok(())
+            }
+            Err(e) => {
+                debug!("refused connection; peer={}, err={}", addr, e);
+                Ok(())
+            }
+        }
     });
 
     core.run(server).unwrap();
 }
 
-fn listener(addr: &SocketAddr,
-            workers: usize,
-            handle: &Handle) -> io::Result<TcpListener> {
+// Creates the service of a wrapped connection and binds it, unless the
+// connection is refused
+fn bind_connection<P, Kind, I, N, S>(binder: &P,
+                                     handle: &Handle,
+                                     (io, conn): (I, Connection),
+                                     new_service: &N,
+                                     config: &BindConfig,
+                                     guard: Option<Slot>,
+                                     connection: Option<Slot>) -> io::Result<()>
+    where P: BindServer<Kind, I>,
+          I: 'static,
+          N: Fn(&Connection) -> io::Result<Option<S>>,
+          S: Service + 'static,
+          P::ServiceError: 'static,
+          P::ServiceResponse: 'static,
+          P::ServiceRequest: 'static,
+          S::Request: From<P::ServiceRequest>,
+          S::Response: Into<P::ServiceResponse>,
+          S::Error: Into<P::ServiceError>,
+{
+    let service = match try!(new_service(&conn)) {
+        Some(service) => service,
+        None => return Ok(()),
+    };
+
+    let service = WrapService::holding(service, connection);
+
+    // Bind it!
+    binder.bind_server_guarded(handle, io, service, config, guard);
+
+    Ok(())
+}
+
+// Connections accepted by a worker
+type Incoming = Box<Stream<Item = (TcpStream, SocketAddr), Error = io::Error>>;
+
+// Connections accepted by the dealing thread
+type Accepted = io::Result<(net::TcpStream, SocketAddr)>;
+
+// Where the workers take their connections from
+enum Listen {
+    // Every worker listens on a socket of its own, bound with SO_REUSEPORT
+    // when there are several
+    Own {
+        // The address bound, with the port the OS picked if none was given
+        addr: SocketAddr,
+        reuse_port: bool,
+        // Bound up front so that binding errors surface before the workers
+        // start, taken by the first worker
+        first: Mutex<Option<net::TcpListener>>,
+    },
+    // A dedicated thread accepts the connections and deals them out
+    Dealt(Mutex<Vec<mpsc::UnboundedReceiver<Accepted>>>),
+}
+
+impl Listen {
+    fn new(addr: &SocketAddr, workers: usize) -> io::Result<Listen> {
+        if workers > 1 {
+            match bind(addr, true) {
+                Ok(first) => {
+                    return Ok(Listen::Own {
+                        addr: try!(first.local_addr()),
+                        reuse_port: true,
+                        first: Mutex::new(Some(first)),
+                    });
+                }
+                Err(e) => debug!("dealing out connections, SO_REUSEPORT unavailable; err={}", e),
+            }
+        }
+
+        let listener = try!(bind(addr, false));
+
+        if workers == 1 {
+            return Ok(Listen::Own {
+                addr: try!(listener.local_addr()),
+                reuse_port: false,
+                first: Mutex::new(Some(listener)),
+            });
+        }
+
+        let (txs, rxs) = (0..workers).map(|_| mpsc::unbounded()).unzip();
+
+        try!(thread::Builder::new().name("acceptor".to_string()).spawn(move || {
+            deal(listener, txs)
+        }));
+
+        Ok(Listen::Dealt(Mutex::new(rxs)))
+    }
+
+    // Returns the connections accepted for the worker running `handle`
+    fn incoming(&self, handle: &Handle) -> io::Result<Incoming> {
+        match *self {
+            Listen::Own { ref addr, reuse_port, ref first } => {
+                let listener = match first.lock().unwrap().take() {
+                    Some(listener) => listener,
+                    None => try!(bind(addr, reuse_port)),
+                };
+
+                let listener = try!(TcpListener::from_listener(listener, addr, handle));
+                Ok(Box::new(listener.incoming()))
+            }
+            Listen::Dealt(ref receivers) => {
+                let rx = receivers.lock().unwrap().pop().expect("more workers than listeners");
+                let handle = handle.clone();
+
+                let incoming = rx.then(move |dealt| {
+                    let (socket, addr) = try!(dealt.expect("acceptor dropped"));
+                    let socket = try!(TcpStream::from_stream(socket, &handle));
+                    Ok((socket, addr))
+                });
+
+                Ok(Box::new(incoming))
+            }
+        }
+    }
+}
+
+// Accepts connections, dealing them out to the workers round-robin. An
+// accept error is passed on to the next worker, stopping it, like it would
+// stop a worker accepting on its own.
+fn deal(listener: net::TcpListener, workers: Vec<mpsc::UnboundedSender<Accepted>>) {
+    for worker in workers.iter().cycle() {
+        let accepted = listener.accept();
+        let failed = accepted.is_err();
+
+        if worker.unbounded_send(accepted).is_err() || failed {
+            return;
+        }
+    }
+}
+
+fn bind(addr: &SocketAddr, reuse_port: bool) -> io::Result<net::TcpListener> {
     let listener = match *addr {
         SocketAddr::V4(_) => try!(net2::TcpBuilder::new_v4()),
         SocketAddr::V6(_) => try!(net2::TcpBuilder::new_v6()),
     };
-    try!(configure_tcp(workers, &listener));
+    if reuse_port {
+        try!(set_reuse_port(&listener));
+    }
     try!(listener.reuse_address(true));
     try!(listener.bind(addr));
-    listener.listen(1024).and_then(|l| {
-        TcpListener::from_listener(l, addr, handle)
-    })
+    listener.listen(1024)
 }
 
 #[cfg(unix)]
-fn configure_tcp(workers: usize, tcp: &net2::TcpBuilder) -> io::Result<()> {
+fn set_reuse_port(tcp: &net2::TcpBuilder) -> io::Result<()> {
     use net2::unix::*;
 
-    if workers > 1 {
-        try!(tcp.reuse_port(true));
-    }
-
+    try!(tcp.reuse_port(true));
     Ok(())
 }
 
 #[cfg(windows)]
-fn configure_tcp(_workers: usize, _tcp: &net2::TcpBuilder) -> io::Result<()> {
-    Ok(())
+fn set_reuse_port(_tcp: &net2::TcpBuilder) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Other, "SO_REUSEPORT is not supported"))
 }
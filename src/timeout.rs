@@ -0,0 +1,385 @@
+//! Read and write timeouts of connections.
+//!
+//! The sockets driven by the event loop are non-blocking, so the OS level
+//! `SO_RCVTIMEO` and `SO_SNDTIMEO` options have no effect on them. Instead,
+//! `TimeoutIo` wraps an I/O object and emulates them: once a read or a write
+//! would block, a deadline is set for the operation, and the first read or
+//! write attempted past the deadline fails with `ErrorKind::TimedOut`,
+//! failing the connection. Any progress clears the deadline.
+//!
+//! The deadlines are checked whenever the connection task polls the I/O
+//! object, and a timer of the event loop makes sure it does so once a
+//! deadline has passed. Hung peers and middleboxes are detected this way even
+//! when no request is waiting on a timer of its own.
+//!
+//! The read timeout applies whenever nothing is received, so it also bounds
+//! the time a connection may sit idle. The write timeout only applies while
+//! there is something to write, catching black-holed connections that a
+//! read timeout long enough for idle periods would miss: the peer, or a
+//! middlebox on the way, stops acknowledging data without closing the
+//! connection. The error of a timed out write carries a `StalledWrite`
+//! telling how much data was stuck. `TcpServer::io_timeouts` and
+//! `TcpClient::io_timeouts` install the wrapper on every connection.
+//!
+//! A peer can keep a connection from ever being bound, e.g. by dripping the
+//! bytes of a handshake, without any single read blocking for long. The
+//! `bind_timeout` of `TcpServer` and `TcpClient` bounds the time binding the
+//! transport of a connection may take as a whole, using `Deadline`.
+//!
+//! Protocols propagating the deadline of a request to another machine should
+//! not write it as an absolute time: the clocks of the two machines disagree,
+//! and `Instant` has no meaning outside of the process anyway. Instead,
+//! `RelativeDeadline` encodes a deadline as the time left until it passes on
+//! send, and decodes it back to an `Instant` of the local clock on receive.
+//! Only the time spent on the wire is lost, which shortens nothing but the
+//! deadline the peer sees.
+
+use std::{error, fmt};
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
+use tokio_core::io::Io;
+use tokio_core::reactor::{Handle, Timeout};
+
+/// Timeouts of the reads and writes of an I/O object.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoTimeouts {
+    /// Max time a read may wait for data. Defaults to `None`, waiting
+    /// forever.
+    pub read: Option<Duration>,
+
+    /// Max time a write may wait for the peer to make room for more data.
+    /// Every byte written counts as progress, so a slow peer only times out
+    /// once it stops reading entirely. Defaults to `None`, waiting forever.
+    pub write: Option<Duration>,
+}
+
+/// An I/O object failing reads and writes that block for too long.
+pub struct TimeoutIo<T> {
+    io: T,
+    handle: Handle,
+    read: OpDeadline,
+    write: OpDeadline,
+    // Bytes written since the last flush
+    written: u64,
+}
+
+/// The cause of a write failing with `ErrorKind::TimedOut`: the peer made no
+/// room for more data for longer than the write timeout.
+///
+/// Returned as the inner error of the `io::Error`; use `io::Error::get_ref`
+/// and `downcast_ref` to get at it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StalledWrite {
+    pending: usize,
+    written: u64,
+}
+
+/// A future failing with `ErrorKind::TimedOut` unless the wrapped future
+/// completes in time.
+///
+/// Dropping the wrapped future on timeout drops whatever it owns, such as the
+/// I/O object of a handshake, closing the connection.
+pub struct Deadline<F> {
+    future: F,
+    timer: Option<io::Result<Timeout>>,
+}
+
+/// A deadline in transit, as the time left until it passes.
+///
+/// Encode the deadline of a request with `from_instant` right before writing
+/// it, and decode it with `to_instant` right after reading it; `as_millis` and
+/// `from_millis` give it a representation on the wire. Deadlines already
+/// passed are encoded as zero time left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RelativeDeadline {
+    remaining: Duration,
+}
+
+// Deadline of the blocked read or write, if any
+struct OpDeadline {
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    timer: Option<Timeout>,
+}
+
+impl<T> TimeoutIo<T> {
+    /// Wraps `io`, applying `timeouts` to its reads and writes.
+    ///
+    /// The timers are registered with the event loop of `handle`, which must
+    /// be the one driving the I/O object.
+    pub fn new(io: T, timeouts: &IoTimeouts, handle: &Handle) -> TimeoutIo<T> {
+        TimeoutIo {
+            io: io,
+            handle: handle.clone(),
+            read: OpDeadline::new(timeouts.read),
+            write: OpDeadline::new(timeouts.write),
+            written: 0,
+        }
+    }
+
+    /// Returns a reference to the wrapped I/O object.
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+
+    /// Returns a mutable reference to the wrapped I/O object.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.io
+    }
+
+    /// Consumes the wrapper, returning the wrapped I/O object.
+    pub fn into_inner(self) -> T {
+        self.io
+    }
+}
+
+impl StalledWrite {
+    /// Returns the number of bytes the timed out write tried to write, i.e.
+    /// those stuck in the write buffer of the transport.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Returns the number of bytes written since the last flush, before the
+    /// writes stalled. Non-zero when the peer stopped reading halfway through
+    /// the data flushed.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+}
+
+impl fmt::Display for StalledWrite {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "write timed out with {} bytes pending, {} bytes written since the last flush",
+               self.pending, self.written)
+    }
+}
+
+impl error::Error for StalledWrite {
+    fn description(&self) -> &str {
+        "write timed out"
+    }
+}
+
+impl RelativeDeadline {
+    /// Encodes `deadline`, relative to now.
+    pub fn from_instant(deadline: Instant) -> RelativeDeadline {
+        RelativeDeadline::new(deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Encodes a deadline passing once `remaining` has elapsed.
+    pub fn new(remaining: Duration) -> RelativeDeadline {
+        RelativeDeadline { remaining: remaining }
+    }
+
+    /// Decodes a deadline read from the wire as milliseconds left.
+    pub fn from_millis(millis: u64) -> RelativeDeadline {
+        RelativeDeadline::new(Duration::from_millis(millis))
+    }
+
+    /// Decodes the deadline, relative to now.
+    pub fn to_instant(&self) -> Instant {
+        Instant::now() + self.remaining
+    }
+
+    /// Returns the time left until the deadline passes.
+    pub fn remaining(&self) -> Duration {
+        self.remaining
+    }
+
+    /// Returns the milliseconds left until the deadline passes, rounded down
+    /// so that the peer never waits longer than this end.
+    pub fn as_millis(&self) -> u64 {
+        self.remaining.as_secs()
+            .saturating_mul(1_000)
+            .saturating_add(self.remaining.subsec_nanos() as u64 / 1_000_000)
+    }
+
+    /// Returns true if the deadline had already passed when it was encoded.
+    pub fn is_expired(&self) -> bool {
+        self.remaining == Duration::from_secs(0)
+    }
+}
+
+impl<F> Deadline<F> {
+    /// Wraps `future`, failing it once `timeout` has passed. A `timeout` of
+    /// `None` never fails it.
+    pub fn new(future: F, timeout: Option<Duration>, handle: &Handle) -> Deadline<F> {
+        Deadline {
+            future: future,
+            timer: timeout.map(|timeout| Timeout::new(timeout, handle)),
+        }
+    }
+
+    /// Wraps `future`, failing it once `deadline` has passed, e.g. a deadline
+    /// decoded with `RelativeDeadline::to_instant`. A `deadline` of `None`
+    /// never fails it.
+    pub fn at(future: F, deadline: Option<Instant>, handle: &Handle) -> Deadline<F> {
+        Deadline {
+            future: future,
+            timer: deadline.map(|deadline| Timeout::new_at(deadline, handle)),
+        }
+    }
+
+    /// Consumes the wrapper, returning the wrapped future.
+    pub fn into_inner(self) -> F {
+        self.future
+    }
+}
+
+impl<F> Future for Deadline<F>
+    where F: Future<Error = io::Error>,
+{
+    type Item = F::Item;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, io::Error> {
+        if let Async::Ready(item) = try!(self.future.poll()) {
+            return Ok(Async::Ready(item));
+        }
+
+        let expired = match self.timer {
+            Some(Ok(ref mut timer)) => try!(timer.poll()).is_ready(),
+            Some(Err(_)) => return Err(self.timer.take().unwrap().unwrap_err()),
+            None => false,
+        };
+
+        if expired {
+            debug!("deadline passed");
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "deadline passed"));
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+impl OpDeadline {
+    fn new(timeout: Option<Duration>) -> OpDeadline {
+        OpDeadline {
+            timeout: timeout,
+            deadline: None,
+            timer: None,
+        }
+    }
+
+    /// The operation made progress
+    fn clear(&mut self) {
+        self.deadline = None;
+    }
+
+    /// The operation is blocked, returns true once it has been for longer
+    /// than the timeout.
+    fn poll_expired(&mut self, handle: &Handle) -> io::Result<bool> {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return Ok(false),
+        };
+
+        let now = Instant::now();
+
+        let deadline = match self.deadline {
+            Some(deadline) => deadline,
+            None => {
+                let deadline = now + timeout;
+
+                match self.timer {
+                    Some(ref mut timer) => timer.reset(deadline),
+                    None => self.timer = Some(try!(Timeout::new_at(deadline, handle))),
+                }
+
+                self.deadline = Some(deadline);
+                deadline
+            }
+        };
+
+        if now >= deadline {
+            return Ok(true);
+        }
+
+        // Get woken up once the deadline passes
+        let timer = self.timer.as_mut().unwrap();
+        Ok(try!(timer.poll()).is_ready())
+    }
+
+    /// Checks the result of the operation, failing it with the error made by
+    /// `timed_out` if it blocked for too long.
+    fn check<R, F>(&mut self, res: io::Result<R>, handle: &Handle, timed_out: F) -> io::Result<R>
+        where F: FnOnce() -> io::Error,
+    {
+        match res {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                if try!(self.poll_expired(handle)) {
+                    let err = timed_out();
+                    debug!("{}", err);
+                    return Err(err);
+                }
+            }
+            Ok(_) => self.clear(),
+            Err(_) => {}
+        }
+
+        res
+    }
+
+    /// Readiness of the operation; an expired deadline reports readiness so
+    /// that the operation is attempted and fails.
+    fn poll_ready(&mut self, ready: Async<()>, handle: &Handle) -> Async<()> {
+        if ready.is_ready() {
+            return ready;
+        }
+
+        // An error is reported by the operation itself
+        match self.poll_expired(handle) {
+            Ok(false) => Async::NotReady,
+            _ => Async::Ready(()),
+        }
+    }
+}
+
+impl<T: Read> Read for TimeoutIo<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let res = self.io.read(buf);
+        self.read.check(res, &self.handle, || {
+            io::Error::new(io::ErrorKind::TimedOut, "read timed out")
+        })
+    }
+}
+
+impl<T: Write> Write for TimeoutIo<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let res = self.io.write(buf);
+
+        if let Ok(n) = res {
+            self.written += n as u64;
+        }
+
+        let stalled = StalledWrite {
+            pending: buf.len(),
+            written: self.written,
+        };
+
+        self.write.check(res, &self.handle, || {
+            io::Error::new(io::ErrorKind::TimedOut, stalled)
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.io.flush());
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl<T: Io> Io for TimeoutIo<T> {
+    fn poll_read(&mut self) -> Async<()> {
+        let ready = self.io.poll_read();
+        self.read.poll_ready(ready, &self.handle)
+    }
+
+    fn poll_write(&mut self) -> Async<()> {
+        let ready = self.io.poll_write();
+        self.write.poll_ready(ready, &self.handle)
+    }
+}
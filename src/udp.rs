@@ -0,0 +1,640 @@
+//! Multiplexed request / response over UDP.
+//!
+//! Datagram protocols in the vein of DNS carry a request id in every
+//! datagram, which maps onto the `RequestId` machinery of multiplexed
+//! protocols. Instead of a protocol bound to a connection, a `UdpServer` or
+//! `UdpClient` takes a `DatagramCodec` turning single datagrams into
+//! messages and back.
+//!
+//! A server answers many peers from a single socket, so the address of the
+//! peer is part of the id a response is correlated with: peers picking the
+//! same ids do not get each other's responses. Requests retransmitted while
+//! the original is still being served are dropped. A service failing a
+//! request does not answer it, leaving the client to retransmit or give up.
+//!
+//! A client sends all of its requests to a single server, ignoring datagrams
+//! from any other address. Datagrams may be lost, so requests not answered
+//! in time are sent again, with the same id, as told by a `Retransmit`
+//! policy. Once the policy gives up, the response future fails with a
+//! `TimedOut` error.
+//!
+//! Datagrams failing to decode are dropped on either side.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use BindClient;
+use multiplex::{self, Multiplex, RequestId, RequestIdSource};
+use futures::{task, Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use tokio_core::net::{UdpCodec, UdpFramed, UdpSocket};
+use tokio_core::reactor::{Core, Handle, Timeout};
+use tokio_service::{NewService, Service};
+
+/// Encodes and decodes the messages of a datagram protocol.
+///
+/// Every datagram carries exactly one message along with the id of its
+/// exchange. On a server, `In` is the request and `Out` the response; on a
+/// client, the other way around.
+pub trait DatagramCodec: Send + Sync + 'static {
+    /// The id correlating responses to their requests.
+    type RequestId: RequestId + Send;
+
+    /// Messages decoded from received datagrams.
+    type In: 'static;
+
+    /// Messages encoded into sent datagrams.
+    type Out: 'static;
+
+    /// Decode the datagram in `buf`.
+    ///
+    /// Returning an error drops the datagram.
+    fn decode(&self, buf: &[u8]) -> io::Result<(Self::RequestId, Self::In)>;
+
+    /// Encode `msg` of the exchange `id` into `buf`, which is sent as a
+    /// single datagram.
+    fn encode(&self, id: Self::RequestId, msg: Self::Out, buf: &mut Vec<u8>) -> io::Result<()>;
+}
+
+/// Decides when a `UdpClient` sends unanswered requests again.
+pub trait Retransmit: Send + Sync + 'static {
+    /// Returns how long to wait for the response to a request sent for the
+    /// `attempt`th time, counting from 1.
+    ///
+    /// Once the wait is over, the request is sent again if the next attempt
+    /// has a timeout too, and given up on otherwise. Returning `None` for the
+    /// first attempt waits for the response forever.
+    fn timeout(&self, attempt: u32) -> Option<Duration>;
+}
+
+/// A `Retransmit` policy doubling the timeout after every attempt.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    initial: Duration,
+    attempts: u32,
+}
+
+impl Backoff {
+    /// Send requests up to `attempts` times, waiting `initial` for the
+    /// response to the first attempt.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `attempts` is zero.
+    pub fn new(initial: Duration, attempts: u32) -> Backoff {
+        assert!(attempts > 0, "at least one attempt is required");
+
+        Backoff {
+            initial: initial,
+            attempts: attempts,
+        }
+    }
+}
+
+impl Retransmit for Backoff {
+    fn timeout(&self, attempt: u32) -> Option<Duration> {
+        if attempt == 0 || attempt > self.attempts {
+            return None;
+        }
+
+        // Capped, so that many attempts do not overflow
+        Some(self.initial * (1 << cmp::min(attempt - 1, 16)))
+    }
+}
+
+/// A builder for servers answering datagrams on a UDP socket.
+///
+/// Works like `TcpServer`, except that a single service instance answers
+/// the requests of all peers, on a single event loop.
+pub struct UdpServer<C> {
+    codec: Arc<C>,
+    addr: SocketAddr,
+}
+
+impl<C: DatagramCodec> UdpServer<C> {
+    /// Starts building a server for the given codec and address.
+    pub fn new(codec: C, addr: SocketAddr) -> UdpServer<C> {
+        UdpServer {
+            codec: Arc::new(codec),
+            addr: addr,
+        }
+    }
+
+    /// Set the address for the server.
+    pub fn addr(&mut self, addr: SocketAddr) {
+        self.addr = addr;
+    }
+
+    /// Start up the server, providing the given service on it.
+    ///
+    /// This method will block the current thread until the server fails,
+    /// returning the error, e.g. if the socket cannot be bound or sending a
+    /// response fails. Requests that cannot be decoded, and responses that
+    /// cannot be encoded, are dropped without failing the server.
+    pub fn serve<S>(&self, new_service: S) -> io::Result<()> where
+        S: NewService<Request = C::In, Response = C::Out, Error = io::Error> + 'static,
+        S::Instance: 'static,
+    {
+        let new_service = Arc::new(new_service);
+        self.with_handle(move |_| new_service.clone())
+    }
+
+    /// Start up the server, providing the given service on it, and providing
+    /// access to the event loop handle.
+    ///
+    /// The `new_service` argument is a closure that is given an event loop
+    /// handle, and produces a value implementing `NewService`. That value is
+    /// in turn used to make the service answering every peer.
+    ///
+    /// This method will block the current thread until the server fails,
+    /// returning the error, e.g. if the socket cannot be bound or sending a
+    /// response fails. Requests that cannot be decoded, and responses that
+    /// cannot be encoded, are dropped without failing the server.
+    pub fn with_handle<F, S>(&self, new_service: F) -> io::Result<()> where
+        F: Fn(&Handle) -> S,
+        S: NewService<Request = C::In, Response = C::Out, Error = io::Error> + 'static,
+        S::Instance: 'static,
+    {
+        let mut core = try!(Core::new());
+        let handle = core.handle();
+        let service = try!(new_service(&handle).new_service());
+        let socket = try!(UdpSocket::bind(&self.addr, &handle));
+
+        let proto = ServerFraming { codec: self.codec.clone() };
+        let server = multiplex::server_task(&proto, &handle, socket, Unanswered(service));
+
+        core.run(server)
+    }
+}
+
+/// Builds clients sending datagrams to a UDP server.
+///
+/// Every client binds a socket of its own, on an ephemeral port.
+pub struct UdpClient<C, S> {
+    codec: Arc<C>,
+    requestid_source: S,
+    retransmit: Arc<Retransmit>,
+}
+
+impl<C, S> UdpClient<C, S> where
+    C: DatagramCodec,
+    S: RequestIdSource<C::RequestId, C::Out> + Clone,
+{
+    /// Create a builder for the given codec, with every client taking its
+    /// request ids from a clone of `requestid_source`.
+    ///
+    /// Requests are sent up to 3 times, waiting for their response 1 second
+    /// after the first attempt; see `Backoff`.
+    pub fn new(codec: C, requestid_source: S) -> UdpClient<C, S> {
+        UdpClient {
+            codec: Arc::new(codec),
+            requestid_source: requestid_source,
+            retransmit: Arc::new(Backoff::new(Duration::from_secs(1), 3)),
+        }
+    }
+
+    /// Set the policy deciding when unanswered requests are sent again.
+    pub fn retransmit<R: Retransmit>(&mut self, retransmit: R) {
+        self.retransmit = Arc::new(retransmit);
+    }
+
+    /// Return a service sending requests to the server at `addr`.
+    ///
+    /// Returns an error if no local socket can be bound.
+    pub fn connect(&self, addr: &SocketAddr, handle: &Handle) -> io::Result<UdpService<C, S>> {
+        let local = match *addr {
+            SocketAddr::V4(..) => "0.0.0.0:0",
+            SocketAddr::V6(..) => "[::]:0",
+        };
+
+        let socket = try!(UdpSocket::bind(&local.parse().unwrap(), handle));
+
+        let proto = ClientFraming {
+            codec: self.codec.clone(),
+            requestid_source: self.requestid_source.clone(),
+            retransmit: self.retransmit.clone(),
+            peer: *addr,
+            handle: handle.clone(),
+        };
+
+        Ok(UdpService {
+            inner: BindClient::<Multiplex, UdpSocket>::bind_client(&proto, handle, socket),
+        })
+    }
+}
+
+/// A service sending requests to a UDP server.
+///
+/// Returned by `UdpClient::connect`.
+pub struct UdpService<C, S> where
+    C: DatagramCodec,
+    S: RequestIdSource<C::RequestId, C::Out> + Clone,
+{
+    inner: multiplex::ClientService<UdpSocket, ClientFraming<C, S>>,
+}
+
+/// The future returned by `UdpService::call`.
+pub struct UdpResponse<C, S> where
+    C: DatagramCodec,
+    S: RequestIdSource<C::RequestId, C::Out> + Clone,
+{
+    inner: multiplex::ClientFuture<UdpSocket, ClientFraming<C, S>>,
+}
+
+impl<C, S> Service for UdpService<C, S> where
+    C: DatagramCodec,
+    S: RequestIdSource<C::RequestId, C::Out> + Clone,
+{
+    type Request = C::Out;
+    type Response = C::In;
+    type Error = io::Error;
+    type Future = UdpResponse<C, S>;
+
+    fn call(&self, req: C::Out) -> UdpResponse<C, S> {
+        UdpResponse { inner: self.inner.call(req) }
+    }
+}
+
+impl<C, S> Future for UdpResponse<C, S> where
+    C: DatagramCodec,
+    S: RequestIdSource<C::RequestId, C::Out> + Clone,
+{
+    type Item = C::In;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<C::In, io::Error> {
+        // Requests given up on are answered with the error by the transport
+        match try_ready!(self.inner.poll()) {
+            Ok(res) => Ok(Async::Ready(res)),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// Adapts a `DatagramCodec` to `UdpFramed`, tagging received messages with
+// the address of their sender. Messages are encoded ahead of sending, so
+// that clients can keep the datagram around for retransmission.
+struct Framing<C> {
+    codec: Arc<C>,
+}
+
+impl<C: DatagramCodec> UdpCodec for Framing<C> {
+    type In = Option<(SocketAddr, C::RequestId, C::In)>;
+    type Out = (SocketAddr, Vec<u8>);
+
+    fn decode(&mut self, src: &SocketAddr, buf: &[u8]) -> io::Result<Self::In> {
+        match self.codec.decode(buf) {
+            Ok((id, msg)) => Ok(Some((*src, id, msg))),
+            Err(e) => {
+                debug!("dropping undecodable datagram; peer={}, err={}", src, e);
+                Ok(None)
+            }
+        }
+    }
+
+    fn encode(&mut self, (addr, datagram): (SocketAddr, Vec<u8>), buf: &mut Vec<u8>) -> SocketAddr {
+        buf.extend_from_slice(&datagram);
+        addr
+    }
+}
+
+fn framed<C: DatagramCodec>(socket: UdpSocket, codec: &Arc<C>) -> UdpFramed<Framing<C>> {
+    socket.framed(Framing { codec: codec.clone() })
+}
+
+// The server side, with the address of the peer as part of the request id
+struct ServerFraming<C> {
+    codec: Arc<C>,
+}
+
+struct ServerTransport<C: DatagramCodec> {
+    framed: UdpFramed<Framing<C>>,
+    codec: Arc<C>,
+    // Requests being served, so that retransmissions are not served twice
+    in_flight: HashSet<(SocketAddr, C::RequestId)>,
+}
+
+impl<C: DatagramCodec> multiplex::ServerProto<UdpSocket> for ServerFraming<C> {
+    type Request = C::In;
+    // Requests failed by the service are not answered
+    type Response = Option<C::Out>;
+    type RequestId = (SocketAddr, C::RequestId);
+    type Error = io::Error;
+    type Transport = ServerTransport<C>;
+    type BindTransport = Result<ServerTransport<C>, io::Error>;
+
+    fn bind_transport(&self, socket: UdpSocket) -> Self::BindTransport {
+        Ok(ServerTransport {
+            framed: framed(socket, &self.codec),
+            codec: self.codec.clone(),
+            in_flight: HashSet::new(),
+        })
+    }
+}
+
+impl<C: DatagramCodec> Stream for ServerTransport<C> {
+    type Item = ((SocketAddr, C::RequestId), C::In);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, io::Error> {
+        loop {
+            let (addr, id, msg) = match try_ready!(self.framed.poll()) {
+                Some(Some(datagram)) => datagram,
+                Some(None) => continue,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            let id = (addr, id);
+
+            if !self.in_flight.insert(id.clone()) {
+                trace!("dropping retransmitted request; id={:?}", id);
+                continue;
+            }
+
+            return Ok(Async::Ready(Some((id, msg))));
+        }
+    }
+}
+
+impl<C: DatagramCodec> Sink for ServerTransport<C> {
+    type SinkItem = ((SocketAddr, C::RequestId), Option<C::Out>);
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, io::Error> {
+        if !try!(self.framed.poll_complete()).is_ready() {
+            return Ok(AsyncSink::NotReady(item));
+        }
+
+        let ((addr, id), msg) = item;
+        self.in_flight.remove(&(addr, id.clone()));
+
+        let msg = match msg {
+            Some(msg) => msg,
+            None => {
+                debug!("not answering failed request; peer={}, id={:?}", addr, id);
+                return Ok(AsyncSink::Ready);
+            }
+        };
+
+        // Like undecodable requests, unencodable responses only fail their
+        // peer
+        let mut datagram = Vec::new();
+        if let Err(e) = self.codec.encode(id.clone(), msg, &mut datagram) {
+            debug!("dropping unencodable response; peer={}, id={:?}, err={}", addr, id, e);
+            return Ok(AsyncSink::Ready);
+        }
+
+        // Flushed above, so the datagram is accepted
+        try!(self.framed.start_send((addr, datagram)));
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        self.framed.poll_complete()
+    }
+}
+
+// Turns the errors of a service into unanswered requests, as failing an
+// exchange would fail the socket
+struct Unanswered<S>(S);
+
+struct UnansweredFuture<F>(F);
+
+impl<S: Service<Error = io::Error>> Service for Unanswered<S> {
+    type Request = S::Request;
+    type Response = Option<S::Response>;
+    type Error = io::Error;
+    type Future = UnansweredFuture<S::Future>;
+
+    fn call(&self, req: S::Request) -> Self::Future {
+        UnansweredFuture(self.0.call(req))
+    }
+}
+
+impl<F: Future<Error = io::Error>> Future for UnansweredFuture<F> {
+    type Item = Option<F::Item>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<F::Item>, io::Error> {
+        match self.0.poll() {
+            Ok(Async::Ready(res)) => Ok(Async::Ready(Some(res))),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(e) => {
+                debug!("service failed request; err={}", e);
+                Ok(Async::Ready(None))
+            }
+        }
+    }
+}
+
+// The client side, answering requests given up on with an error
+struct ClientFraming<C, S> {
+    codec: Arc<C>,
+    requestid_source: S,
+    retransmit: Arc<Retransmit>,
+    peer: SocketAddr,
+    handle: Handle,
+}
+
+struct ClientTransport<C: DatagramCodec> {
+    framed: UdpFramed<Framing<C>>,
+    codec: Arc<C>,
+    retransmit: Arc<Retransmit>,
+    peer: SocketAddr,
+    handle: Handle,
+    // Requests awaiting their response
+    pending: HashMap<C::RequestId, Pending>,
+    // Datagrams of requests to send again
+    resend: VecDeque<Vec<u8>>,
+    // Requests given up on
+    expired: VecDeque<C::RequestId>,
+}
+
+struct Pending {
+    datagram: Vec<u8>,
+    attempt: u32,
+    timeout: Option<Timeout>,
+}
+
+impl<C, S> multiplex::ClientProto<UdpSocket> for ClientFraming<C, S> where
+    C: DatagramCodec,
+    S: RequestIdSource<C::RequestId, C::Out> + Clone,
+{
+    type Request = C::Out;
+    type Response = io::Result<C::In>;
+    type RequestId = C::RequestId;
+    type Error = io::Error;
+    type Transport = ClientTransport<C>;
+    type BindTransport = Result<ClientTransport<C>, io::Error>;
+    type RequestIdSource = S;
+
+    fn requestid_source(&self) -> S {
+        self.requestid_source.clone()
+    }
+
+    fn bind_transport(&self, socket: UdpSocket) -> Self::BindTransport {
+        Ok(ClientTransport {
+            framed: framed(socket, &self.codec),
+            codec: self.codec.clone(),
+            retransmit: self.retransmit.clone(),
+            peer: self.peer,
+            handle: self.handle.clone(),
+            pending: HashMap::new(),
+            resend: VecDeque::new(),
+            expired: VecDeque::new(),
+        })
+    }
+
+    fn cancel(transport: &mut ClientTransport<C>, request_id: C::RequestId) -> io::Result<()> {
+        // Answered with an error on the next poll, releasing the id
+        if transport.pending.remove(&request_id).is_some() {
+            transport.expired.push_back(request_id);
+            task::park().unpark();
+        }
+
+        Ok(())
+    }
+}
+
+impl<C: DatagramCodec> ClientTransport<C> {
+    fn timeout(&self, attempt: u32) -> io::Result<Option<Timeout>> {
+        match self.retransmit.timeout(attempt) {
+            Some(dur) => Timeout::new(dur, &self.handle).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // Queues the requests timed out for retransmission, or gives up on them
+    fn check_timeouts(&mut self) -> io::Result<()> {
+        let mut timed_out = vec![];
+
+        for (id, pending) in self.pending.iter_mut() {
+            let fired = match pending.timeout {
+                Some(ref mut timeout) => try!(timeout.poll()).is_ready(),
+                None => false,
+            };
+
+            if fired {
+                timed_out.push(id.clone());
+            }
+        }
+
+        for id in timed_out {
+            let attempt = self.pending[&id].attempt + 1;
+
+            match try!(self.timeout(attempt)) {
+                Some(mut timeout) => {
+                    trace!("retransmitting request; id={:?}, attempt={}", id, attempt);
+
+                    // Register interest in the new timeout
+                    let _ = try!(timeout.poll());
+
+                    let pending = self.pending.get_mut(&id).unwrap();
+                    pending.attempt = attempt;
+                    pending.timeout = Some(timeout);
+                    self.resend.push_back(pending.datagram.clone());
+                }
+                None => {
+                    debug!("request timed out; id={:?}, attempts={}", id, attempt - 1);
+                    self.pending.remove(&id);
+                    self.expired.push_back(id);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Sends the queued retransmissions, returning true once all are sent
+    fn flush_resend(&mut self) -> io::Result<bool> {
+        while !self.resend.is_empty() {
+            if !try!(self.framed.poll_complete()).is_ready() {
+                return Ok(false);
+            }
+
+            let datagram = self.resend.pop_front().unwrap();
+            try!(self.framed.start_send((self.peer, datagram)));
+        }
+
+        Ok(true)
+    }
+}
+
+impl<C: DatagramCodec> Stream for ClientTransport<C> {
+    type Item = (C::RequestId, io::Result<C::In>);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, io::Error> {
+        try!(self.check_timeouts());
+        try!(self.flush_resend());
+        try!(self.framed.poll_complete());
+
+        if let Some(id) = self.expired.pop_front() {
+            let err = io::Error::new(io::ErrorKind::TimedOut, "request timed out");
+            return Ok(Async::Ready(Some((id, Err(err)))));
+        }
+
+        loop {
+            let (addr, id, msg) = match try_ready!(self.framed.poll()) {
+                Some(Some(datagram)) => datagram,
+                Some(None) => continue,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            if addr != self.peer {
+                debug!("dropping datagram from unexpected peer; peer={}", addr);
+                continue;
+            }
+
+            if self.pending.remove(&id).is_none() {
+                trace!("dropping duplicate or late response; id={:?}", id);
+                continue;
+            }
+
+            return Ok(Async::Ready(Some((id, Ok(msg)))));
+        }
+    }
+}
+
+impl<C: DatagramCodec> Sink for ClientTransport<C> {
+    type SinkItem = (C::RequestId, C::Out);
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, io::Error> {
+        if !try!(self.flush_resend()) || !try!(self.framed.poll_complete()).is_ready() {
+            return Ok(AsyncSink::NotReady(item));
+        }
+
+        let (id, msg) = item;
+
+        let mut datagram = Vec::new();
+        try!(self.codec.encode(id.clone(), msg, &mut datagram));
+
+        let mut timeout = try!(self.timeout(1));
+
+        if let Some(ref mut timeout) = timeout {
+            let _ = try!(timeout.poll());
+        }
+
+        self.pending.insert(id, Pending {
+            datagram: datagram.clone(),
+            attempt: 1,
+            timeout: timeout,
+        });
+
+        // Flushed above, so the datagram is accepted
+        try!(self.framed.start_send((self.peer, datagram)));
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        if !try!(self.flush_resend()) {
+            return Ok(Async::NotReady);
+        }
+
+        self.framed.poll_complete()
+    }
+}
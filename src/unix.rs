@@ -0,0 +1,159 @@
+use std::io;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use {BindClient, BindConfig, BindServer};
+use tcp_server::WrapService;
+use futures::stream::Stream;
+use tokio_core::reactor::{Core, Handle};
+use tokio_service::NewService;
+use tokio_uds::{UnixListener, UnixStream};
+
+/// A builder for servers listening on a Unix domain socket.
+///
+/// Works like `TcpServer`, except that the server listens on a filesystem
+/// path and runs a single event loop. Binding fails if a file already exists
+/// at the path; removing a socket left behind by a previous run is up to the
+/// caller.
+///
+/// Only available on Unix, with the `unix` feature enabled.
+pub struct UnixServer<Kind, P> {
+    _kind: PhantomData<Kind>,
+    proto: Arc<P>,
+    path: PathBuf,
+    bind_timeout: Option<Duration>,
+}
+
+impl<Kind, P> UnixServer<Kind, P> where
+    P: BindServer<Kind, UnixStream> + 'static
+{
+    /// Starts building a server for the given protocol and socket path, with
+    /// default configuration.
+    pub fn new<T: AsRef<Path>>(protocol: P, path: T) -> UnixServer<Kind, P> {
+        UnixServer {
+            _kind: PhantomData,
+            proto: Arc::new(protocol),
+            path: path.as_ref().to_path_buf(),
+            bind_timeout: None,
+        }
+    }
+
+    /// Set the socket path for the server.
+    pub fn path<T: AsRef<Path>>(&mut self, path: T) {
+        self.path = path.as_ref().to_path_buf();
+    }
+
+    /// Set the max time binding the transport of a connection may take,
+    /// including any handshake done by the protocol.
+    ///
+    /// Connections not bound in time are closed. Defaults to no limit.
+    pub fn bind_timeout(&mut self, timeout: Duration) {
+        self.bind_timeout = Some(timeout);
+    }
+
+    /// Start up the server, providing the given service on it.
+    ///
+    /// This method will block the current thread until the server is shut
+    /// down, and returns an error if the socket cannot be bound.
+    pub fn serve<S>(&self, new_service: S) -> io::Result<()> where
+        S: NewService + 'static,
+        S::Instance: 'static,
+        P::ServiceError: 'static,
+        P::ServiceResponse: 'static,
+        P::ServiceRequest: 'static,
+        S::Request: From<P::ServiceRequest>,
+        S::Response: Into<P::ServiceResponse>,
+        S::Error: Into<P::ServiceError>,
+    {
+        let new_service = Arc::new(new_service);
+        self.with_handle(move |_| new_service.clone())
+    }
+
+    /// Start up the server, providing the given service on it, and providing
+    /// access to the event loop handle.
+    ///
+    /// The `new_service` argument is a closure that is given an event loop
+    /// handle, and produces a value implementing `NewService`. That value is in
+    /// turn used to make a new service instance for each incoming connection.
+    ///
+    /// This method will block the current thread until the server is shut
+    /// down, and returns an error if the socket cannot be bound.
+    pub fn with_handle<F, S>(&self, new_service: F) -> io::Result<()> where
+        F: Fn(&Handle) -> S,
+        S: NewService + 'static,
+        S::Instance: 'static,
+        P::ServiceError: 'static,
+        P::ServiceResponse: 'static,
+        P::ServiceRequest: 'static,
+        S::Request: From<P::ServiceRequest>,
+        S::Response: Into<P::ServiceResponse>,
+        S::Error: Into<P::ServiceError>,
+    {
+        let mut core = try!(Core::new());
+        let handle = core.handle();
+        let new_service = new_service(&handle);
+        let listener = try!(UnixListener::bind(&self.path, &handle));
+
+        let binder = self.proto.clone();
+        let config = BindConfig { timeout: self.bind_timeout, ..BindConfig::default() };
+
+        let server = listener.incoming().for_each(move |(socket, _)| {
+            // Create the service
+            let service = try!(new_service.new_service());
+            let service = WrapService::new(service);
+
+            // Bind it!
+            binder.bind_server_guarded(&handle, socket, service, &config, ());
+
+            Ok(())
+        });
+
+        core.run(server)
+    }
+}
+
+/// Builds client connections to services listening on a Unix domain socket.
+///
+/// Works like `TcpClient`. Connecting to a Unix domain socket completes
+/// immediately, so the service is returned directly rather than through a
+/// future.
+///
+/// Only available on Unix, with the `unix` feature enabled.
+pub struct UnixClient<Kind, P> {
+    _kind: PhantomData<Kind>,
+    proto: Arc<P>,
+    bind_timeout: Option<Duration>,
+}
+
+impl<Kind, P> UnixClient<Kind, P> where P: BindClient<Kind, UnixStream> {
+    /// Create a builder for the given client protocol.
+    pub fn new(protocol: P) -> UnixClient<Kind, P> {
+        UnixClient {
+            _kind: PhantomData,
+            proto: Arc::new(protocol),
+            bind_timeout: None,
+        }
+    }
+
+    /// Set the max time binding the transport of a connection may take,
+    /// including any handshake done by the protocol.
+    ///
+    /// Connections not bound in time are closed, reporting
+    /// `ErrorKind::TimedOut` as the connection error. Defaults to no limit.
+    pub fn bind_timeout(&mut self, timeout: Duration) {
+        self.bind_timeout = Some(timeout);
+    }
+
+    /// Connect to the server listening at the given socket path.
+    ///
+    /// Returns a service for interacting with the server, or the error of
+    /// connecting, e.g. when nothing listens at the path.
+    pub fn connect<T: AsRef<Path>>(&self, path: T, handle: &Handle) -> io::Result<P::BindClient> {
+        let socket = try!(UnixStream::connect(path, handle));
+
+        let config = BindConfig { timeout: self.bind_timeout, ..BindConfig::default() };
+        Ok(self.proto.bind_client_with(handle, socket, &config))
+    }
+}
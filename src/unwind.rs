@@ -0,0 +1,145 @@
+//! Isolates panics raised while driving a single connection.
+//!
+//! With the `catch-unwind` feature enabled, a panic in a codec, transport or
+//! service is caught at the boundary of the connection task and turned into
+//! an `io::Error` carrying a `Panic`. Only the offending connection is torn
+//! down; the reactor thread and other connections keep running. The
+//! dispatchers catch the panics raised while they are polled themselves, so
+//! that the connection fails like on any other error and its observer is
+//! told about it.
+
+use std::io;
+
+#[cfg(feature = "catch-unwind")]
+pub use self::imp::{Panic, CatchUnwind, catch};
+
+/// Wraps a connection task so that panics are mapped to connection errors.
+#[cfg(feature = "catch-unwind")]
+pub fn isolate<F>(task: F) -> CatchUnwind<F>
+    where F: ::futures::Future<Error = io::Error>,
+{
+    CatchUnwind::new(task)
+}
+
+/// Panics are left to unwind through the reactor when the feature is off.
+#[cfg(not(feature = "catch-unwind"))]
+pub fn isolate<F>(task: F) -> F
+    where F: ::futures::Future<Error = io::Error>,
+{
+    task
+}
+
+/// Runs `f`, leaving panics to unwind when the feature is off.
+#[cfg(not(feature = "catch-unwind"))]
+pub fn catch<F, T>(f: F) -> io::Result<T>
+    where F: FnOnce() -> io::Result<T>,
+{
+    f()
+}
+
+#[cfg(feature = "catch-unwind")]
+mod imp {
+    use std::{error, fmt, io};
+    use std::any::Any;
+    use std::panic::{self, AssertUnwindSafe};
+
+    use futures::{Future, Poll};
+
+    /// The payload of a panic caught while driving a connection.
+    ///
+    /// Returned as the inner error of the `io::Error` the connection task
+    /// fails with; use `io::Error::get_ref` and `downcast_ref` to get at it.
+    #[derive(Debug, Clone)]
+    pub struct Panic {
+        message: String,
+    }
+
+    impl Panic {
+        fn new(payload: Box<Any + Send>) -> Panic {
+            let message = if let Some(s) = payload.downcast_ref::<&'static str>() {
+                s.to_string()
+            } else if let Some(s) = payload.downcast_ref::<String>() {
+                s.clone()
+            } else {
+                "Box<Any>".to_string()
+            };
+
+            Panic { message: message }
+        }
+
+        /// The message the panic was raised with, if it was a string.
+        pub fn message(&self) -> &str {
+            &self.message
+        }
+
+        /// Returns the `Panic` carried by the given error, if any.
+        pub fn from_error(err: &io::Error) -> Option<&Panic> {
+            err.get_ref().and_then(|e| e.downcast_ref::<Panic>())
+        }
+    }
+
+    impl fmt::Display for Panic {
+        fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+            write!(fmt, "connection task panicked: {}", self.message)
+        }
+    }
+
+    impl error::Error for Panic {
+        fn description(&self) -> &str {
+            &self.message
+        }
+    }
+
+    /// Runs `f`, mapping a panic to an error carrying a `Panic`.
+    pub fn catch<F, T>(f: F) -> io::Result<T>
+        where F: FnOnce() -> io::Result<T>,
+    {
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(res) => res,
+            Err(payload) => {
+                let panic = Panic::new(payload);
+                error!("{}", panic);
+
+                Err(io::Error::new(io::ErrorKind::Other, panic))
+            }
+        }
+    }
+
+    /// Future catching panics raised by the inner connection task.
+    pub struct CatchUnwind<F> {
+        inner: Option<F>,
+    }
+
+    impl<F> CatchUnwind<F> {
+        pub fn new(inner: F) -> CatchUnwind<F> {
+            CatchUnwind { inner: Some(inner) }
+        }
+    }
+
+    impl<F> Future for CatchUnwind<F>
+        where F: Future<Error = io::Error>,
+    {
+        type Item = F::Item;
+        type Error = io::Error;
+
+        fn poll(&mut self) -> Poll<F::Item, io::Error> {
+            let res = {
+                let inner = self.inner.as_mut().expect("polled after panic");
+                catch(|| inner.poll())
+            };
+
+            // Either the task panicked or the dispatcher caught a panic of
+            // its own
+            if let Err(ref e) = res {
+                if Panic::from_error(e).is_some() {
+                    // The task state can't be trusted anymore. Dropping it
+                    // may panic again, so keep that contained as well.
+                    let inner = self.inner.take();
+                    let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(inner)));
+                }
+            }
+
+            res
+        }
+    }
+}
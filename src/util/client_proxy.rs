@@ -11,35 +11,168 @@
 // that seems to be fixed on nightly.
 #![allow(warnings)]
 
-use streaming::Message;
+use streaming::{Body, Message, SimpleView};
 use tokio_service::Service;
-use futures::{Future, Async, Poll, Stream, AsyncSink, Sink};
+use futures::{task, Future, Async, Poll, Stream, AsyncSink, Sink};
 use futures::sync::mpsc;
 use futures::sync::oneshot;
 use std::io;
 use std::cell::RefCell;
+use std::cmp;
+use std::error::Error;
+use std::fmt::{self, Debug};
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 /// Client `Service` for pipeline or multiplex protocols
 pub struct ClientProxy<R, S, E> {
     tx: RefCell<mpsc::UnboundedSender<io::Result<Envelope<R, S, E>>>>,
+    collector: Arc<Mutex<Option<Collector<S, E>>>>,
+    errors: Arc<Mutex<ErrorChannel>>,
+    outstanding: Arc<Outstanding<S, E>>,
 }
 
 impl<R, S, E> Clone for ClientProxy<R, S, E> {
     fn clone(&self) -> Self {
         ClientProxy {
             tx: RefCell::new(self.tx.borrow().clone()),
+            collector: self.collector.clone(),
+            errors: self.errors.clone(),
+            outstanding: self.outstanding.clone(),
         }
     }
 }
 
+/// A request of a client awaiting its response, see
+/// `ClientProxy::outstanding`
+///
+/// Requests have no priority in this crate, so none is reported.
+#[derive(Debug, Clone)]
+pub struct OutstandingInfo {
+    /// Id of the exchange on the wire, formatted with `Debug`, once the
+    /// request has been written.
+    pub request_id: Option<String>,
+
+    /// Time since the request was made.
+    pub age: Duration,
+
+    /// Time at which the request times out, if it does.
+    pub deadline: Option<Instant>,
+}
+
 /// Response future returned from a client
+///
+/// Dropping the future cancels the exchange: the dispatcher stops waiting
+/// for the response and frees its resources, see `Complete::poll_cancel`.
+/// Use `detach` to let the exchange complete in the background instead.
 pub struct Response<T, E> {
     inner: oneshot::Receiver<Result<T, E>>,
+    accepted: Option<oneshot::Receiver<()>>,
+    progress: Option<mpsc::UnboundedReceiver<T>>,
+    exchange: Arc<Exchange<T, E>>,
+    collector: Arc<Mutex<Option<Collector<T, E>>>>,
+}
+
+/// Future resolving once the peer accepted a request, see
+/// `Response::accepted`
+pub struct Accepted<E> {
+    inner: oneshot::Receiver<()>,
+    _marker: PhantomData<fn() -> E>,
+}
+
+/// Stream of the progress updates of a request, see `Response::progress`
+pub struct Progress<T, E> {
+    inner: mpsc::UnboundedReceiver<T>,
+    _marker: PhantomData<fn() -> E>,
+}
+
+/// Completes the response of a request submitted to the client
+pub struct Complete<T, E> {
+    inner: Option<oneshot::Sender<Result<T, E>>>,
+    ack: Option<oneshot::Sender<()>>,
+    progress: Option<mpsc::UnboundedSender<T>>,
+    exchange: Arc<Exchange<T, E>>,
+    outstanding: Arc<Outstanding<T, E>>,
+}
+
+/// Stream of the results of detached exchanges, see `ClientProxy::detached`
+pub struct Detached<T, E> {
+    inner: mpsc::UnboundedReceiver<Option<Result<T, E>>>,
+}
+
+/// Receives the results of detached exchanges. `None` is sent for exchanges
+/// dropped by the dispatcher without a result.
+type Collector<T, E> = mpsc::UnboundedSender<Option<Result<T, E>>>;
+
+/// Stream of connection level errors, see `ClientProxy::errors`
+pub struct Errors {
+    inner: mpsc::UnboundedReceiver<io::Error>,
+}
+
+/// Reports connection level errors to the client's `errors` stream
+///
+/// Held by the task dispatching the client's requests. Dropping it marks the
+/// connection as closed, ending the stream.
+pub struct ErrorSink {
+    inner: Arc<Mutex<ErrorChannel>>,
+}
+
+struct ErrorChannel {
+    tx: Option<mpsc::UnboundedSender<io::Error>>,
+    closed: bool,
+}
+
+/// State shared between a response future and its completion handle
+struct Exchange<T, E> {
+    detached: AtomicBool,
+    // Set once the request has been handed to the transport
+    written: AtomicBool,
+    // Set once detached while the client has a collector installed; the
+    // lock also orders completion against detaching.
+    collector: Mutex<Option<Collector<T, E>>>,
+    started: Instant,
+    // One of `PENDING`, `CANCELED` or `DONE`
+    state: AtomicUsize,
+    tracked: Mutex<Tracked>,
+}
+
+// States of an exchange: awaiting its response, canceled with
+// `ClientProxy::cancel_where`, or completed or dropped by the dispatcher
+const PENDING: usize = 0;
+const CANCELED: usize = 1;
+const DONE: usize = 2;
+
+/// What the dispatcher told about an exchange, for `ClientProxy::outstanding`
+struct Tracked {
+    // Only formatted when listed
+    request_id: Option<Box<Debug + Send>>,
+    deadline: Option<Instant>,
 }
 
+/// The exchanges of a client, shared by its clones and completion handles
+struct Outstanding<T, E> {
+    exchanges: Mutex<Exchanges<T, E>>,
+    // The task of the dispatcher, notified by `cancel_where`. A connection is
+    // driven by a single task, so it is only registered once.
+    dispatcher: Mutex<Option<task::Task>>,
+    registered: AtomicBool,
+}
+
+/// The exchanges made on a client, dropped or done ones included until the
+/// list grows past `prune_at`
+struct Exchanges<T, E> {
+    list: Vec<Weak<Exchange<T, E>>>,
+    prune_at: usize,
+}
+
+// Length below which the exchanges list is never pruned
+const MIN_PRUNE_AT: usize = 32;
+
 /// Message used to dispatch requests to the task managing the client
 /// connection.
-type Envelope<R, S, E> = (R, oneshot::Sender<Result<S, E>>);
+type Envelope<R, S, E> = (R, Complete<S, E>);
 
 /// A client / receiver pair
 pub type Pair<R, S, E> = (ClientProxy<R, S, E>, Receiver<R, S, E>);
@@ -53,7 +186,16 @@ pub fn pair<R, S, E>() -> Pair<R, S, E> {
     let (tx, rx) = mpsc::unbounded();
 
     // Use the sender handle to create a `Client` handle
-    let client = ClientProxy { tx: RefCell::new(tx) };
+    let client = ClientProxy {
+        tx: RefCell::new(tx),
+        collector: Arc::new(Mutex::new(None)),
+        errors: Arc::new(Mutex::new(ErrorChannel { tx: None, closed: false })),
+        outstanding: Arc::new(Outstanding {
+            exchanges: Mutex::new(Exchanges { list: Vec::new(), prune_at: MIN_PRUNE_AT }),
+            dispatcher: Mutex::new(None),
+            registered: AtomicBool::new(false),
+        }),
+    };
 
     // Return the pair
     (client, rx)
@@ -67,17 +209,427 @@ impl<R, S, E: From<io::Error>> Service for ClientProxy<R, S, E> {
 
     fn call(&self, request: R) -> Self::Future {
         let (tx, rx) = oneshot::channel();
+        let (ack_tx, ack_rx) = oneshot::channel();
+        let (progress_tx, progress_rx) = mpsc::unbounded();
+        let exchange = Arc::new(Exchange {
+            detached: AtomicBool::new(false),
+            written: AtomicBool::new(false),
+            collector: Mutex::new(None),
+            started: Instant::now(),
+            state: AtomicUsize::new(PENDING),
+            tracked: Mutex::new(Tracked { request_id: None, deadline: None }),
+        });
+        self.outstanding.track(&exchange);
+        let complete = Complete {
+            inner: Some(tx),
+            ack: Some(ack_tx),
+            progress: Some(progress_tx),
+            exchange: exchange.clone(),
+            outstanding: self.outstanding.clone(),
+        };
 
         // If send returns an Err, its because the other side has been dropped.
         // By ignoring it, we are just dropping the `tx`, which will mean the
         // rx will return Canceled when polled. In turn, that is translated
-        // into a BrokenPipe, which conveys the proper error.
+        // into a BrokenPipe, which conveys the proper error, and tells the
+        // request was not sent.
         // NOTE: If Service changes to have some sort of `try_call`, it'd
         // probably be more appropriate to return the Request.
         let _ = mpsc::UnboundedSender::send(&mut self.tx.borrow_mut(),
-                                            Ok((request, tx)));
+                                            Ok((request, complete)));
+
+        Response {
+            inner: rx,
+            accepted: Some(ack_rx),
+            progress: Some(progress_rx),
+            exchange: exchange,
+            collector: self.collector.clone(),
+        }
+    }
+}
 
-        Response { inner: rx }
+impl<T, B, U, C, E> ClientProxy<Message<T, B>, Message<U, Body<C, E>>, E> {
+    /// Returns a view of the client as a simple service, for callers that
+    /// don't stream bodies.
+    ///
+    /// Requests are sent without a body and response bodies of up to
+    /// `max_chunks` chunks are buffered in full. See `streaming::SimpleView`.
+    pub fn into_simple_service(self, max_chunks: usize) -> SimpleView<Self> {
+        SimpleView::new(self, max_chunks)
+    }
+}
+
+impl<R, S, E> ClientProxy<R, S, E> {
+    /// Returns a stream of the results of exchanges detached from now on.
+    ///
+    /// This lets fire-and-forget callers still observe failures without
+    /// holding on to the response futures. The stream is shared by all
+    /// clones of the client; calling `detached` again replaces it, ending the
+    /// previous stream once its pending exchanges complete. Without a stream,
+    /// the results of detached exchanges are discarded.
+    pub fn detached(&self) -> Detached<S, E> {
+        let (tx, rx) = mpsc::unbounded();
+        *self.collector.lock().unwrap() = Some(tx);
+
+        Detached { inner: rx }
+    }
+
+    /// Returns a stream of the errors that fail the connection as a whole.
+    ///
+    /// Such errors, like a connection reset or a protocol violation, fail
+    /// every pending call with a `BrokenPipe` error, while the stream carries
+    /// the actual cause; errors returned for individual calls are not part
+    /// of it. This lets monitoring tell endpoint health issues apart from
+    /// failed requests.
+    ///
+    /// The stream ends once the connection is closed, right away if it
+    /// already is. It is shared by all clones of the client; calling `errors`
+    /// again replaces it.
+    pub fn errors(&self) -> Errors {
+        let (tx, rx) = mpsc::unbounded();
+        let mut errors = self.errors.lock().unwrap();
+
+        if !errors.closed {
+            errors.tx = Some(tx);
+        }
+
+        Errors { inner: rx }
+    }
+
+    /// Returns the handle the dispatcher reports connection level errors
+    /// with.
+    ///
+    /// Unlike clones of the client, the sink does not keep the connection
+    /// open.
+    pub fn error_sink(&self) -> ErrorSink {
+        ErrorSink { inner: self.errors.clone() }
+    }
+
+    /// Returns the requests made on the client that are still awaiting
+    /// their response, including those not written yet.
+    ///
+    /// Meant for operational tooling, e.g. to spot stuck requests. The
+    /// requests are shared by all clones of the client.
+    pub fn outstanding(&self) -> Vec<OutstandingInfo> {
+        let exchanges = self.outstanding.exchanges.lock().unwrap();
+        let now = Instant::now();
+
+        exchanges.list.iter()
+            .filter_map(|exchange| exchange.upgrade())
+            .filter(|exchange| !exchange.is_done())
+            .map(|exchange| exchange.info(now))
+            .collect()
+    }
+
+    /// Cancel the outstanding requests matching `predicate`, returning how
+    /// many were canceled.
+    ///
+    /// The calls of the canceled requests fail with an `Other` error, the
+    /// connection and the other requests are left alone. Requests not
+    /// written yet never are; the exchanges of the others are canceled like
+    /// those of dropped response futures. Only dispatchers checking
+    /// `Complete::poll_cancel` while awaiting the response, like the
+    /// multiplexed ones, can cancel written requests.
+    pub fn cancel_where<F>(&self, mut predicate: F) -> usize
+        where F: FnMut(&OutstandingInfo) -> bool,
+    {
+        let exchanges = self.outstanding.exchanges.lock().unwrap();
+        let now = Instant::now();
+        let mut canceled = 0;
+
+        for exchange in exchanges.list.iter().filter_map(|exchange| exchange.upgrade()) {
+            if exchange.state.load(Ordering::SeqCst) != PENDING || !predicate(&exchange.info(now)) {
+                continue;
+            }
+
+            // The exchange may have completed meanwhile
+            if exchange.state.compare_exchange(PENDING, CANCELED, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                canceled += 1;
+            }
+        }
+
+        if canceled > 0 {
+            if let Some(ref dispatcher) = *self.outstanding.dispatcher.lock().unwrap() {
+                dispatcher.unpark();
+            }
+        }
+
+        canceled
+    }
+}
+
+impl<T, E> Outstanding<T, E> {
+    // Lists a new exchange, forgetting the done ones once the list grew
+    // enough since it was last pruned
+    fn track(&self, exchange: &Arc<Exchange<T, E>>) {
+        let mut exchanges = self.exchanges.lock().unwrap();
+
+        if exchanges.list.len() >= exchanges.prune_at {
+            exchanges.list.retain(|exchange| {
+                exchange.upgrade().map(|exchange| !exchange.is_done()).unwrap_or(false)
+            });
+            exchanges.prune_at = cmp::max(2 * exchanges.list.len(), MIN_PRUNE_AT);
+        }
+
+        exchanges.list.push(Arc::downgrade(exchange));
+    }
+
+    // Registers the current task as the dispatcher, unless it already is
+    fn register_dispatcher(&self) {
+        if !self.registered.load(Ordering::SeqCst) {
+            *self.dispatcher.lock().unwrap() = Some(task::park());
+            self.registered.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+impl<T, E> Exchange<T, E> {
+    fn is_done(&self) -> bool {
+        self.state.load(Ordering::SeqCst) == DONE
+    }
+
+    fn info(&self, now: Instant) -> OutstandingInfo {
+        let tracked = self.tracked.lock().unwrap();
+
+        OutstandingInfo {
+            request_id: tracked.request_id.as_ref().map(|id| format!("{:?}", id)),
+            age: now.duration_since(self.started),
+            deadline: tracked.deadline,
+        }
+    }
+}
+
+impl ErrorSink {
+    /// Report the error that failed the connection.
+    pub fn report(&self, error: io::Error) {
+        if let Some(ref tx) = self.inner.lock().unwrap().tx {
+            let _ = tx.send(error);
+        }
+    }
+}
+
+impl Drop for ErrorSink {
+    fn drop(&mut self) {
+        let mut errors = self.inner.lock().unwrap();
+        errors.tx = None;
+        errors.closed = true;
+    }
+}
+
+impl<T, E> Response<T, E> {
+    /// Returns a future resolving once the peer accepted the request.
+    ///
+    /// Protocols acknowledging requests ahead of their response, like a job
+    /// queue confirming a job was queued, resolve it on the ack frame while
+    /// the response future keeps waiting for the final response. Otherwise
+    /// it resolves along with a successful response. If the exchange fails
+    /// before being acknowledged, it fails as well; the cause is reported by
+    /// the response future.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called more than once.
+    pub fn accepted(&mut self) -> Accepted<E> {
+        Accepted {
+            inner: self.accepted.take().expect("accepted called twice"),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a stream of the progress updates of the request.
+    ///
+    /// Long-running requests may report their progress with interim
+    /// responses ahead of the final one, which is still delivered by the
+    /// response future. The stream ends once the exchange completes. Updates
+    /// received before calling `progress` are buffered, those of a detached
+    /// exchange are discarded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called more than once.
+    pub fn progress(&mut self) -> Progress<T, E> {
+        Progress {
+            inner: self.progress.take().expect("progress called twice"),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Let the exchange complete in the background, without canceling it.
+    ///
+    /// The result is delivered on the client's `detached` stream, if any, and
+    /// discarded otherwise.
+    pub fn detach(mut self) {
+        let collector = self.collector.lock().unwrap().clone();
+
+        if let Some(collector) = collector {
+            let mut lock = self.exchange.collector.lock().unwrap();
+
+            // The exchange may have been completed, or dropped, before the
+            // collector was installed
+            match self.inner.try_recv() {
+                Ok(Some(result)) => { let _ = collector.send(Some(result)); }
+                Ok(None) => *lock = Some(collector),
+                Err(_) => { let _ = collector.send(None); }
+            }
+        }
+
+        self.exchange.detached.store(true, Ordering::SeqCst);
+    }
+}
+
+impl<T, E> Complete<T, E> {
+    /// Complete the response future with the given result.
+    ///
+    /// The result is discarded if the future has been dropped without being
+    /// detached.
+    pub fn complete(mut self, result: Result<T, E>) {
+        let inner = self.inner.take().expect("completed twice");
+
+        // No longer outstanding by the time the caller has the result
+        self.exchange.state.store(DONE, Ordering::SeqCst);
+
+        if result.is_ok() {
+            self.ack();
+        }
+
+        // End the progress stream
+        self.progress = None;
+
+        let lock = self.exchange.collector.lock().unwrap();
+
+        match *lock {
+            Some(ref collector) => { let _ = collector.send(Some(result)); }
+            None => inner.complete(result),
+        }
+    }
+
+    /// Resolve the `accepted` future of the response, ahead of the response
+    /// itself.
+    ///
+    /// Acknowledging an exchange more than once has no effect.
+    pub fn ack(&mut self) {
+        if let Some(ack) = self.ack.take() {
+            ack.complete(());
+        }
+    }
+
+    /// Deliver an interim response to the `progress` stream of the response.
+    ///
+    /// A progress update implies that the request was accepted, see `ack`.
+    pub fn progress(&mut self, update: T) {
+        self.ack();
+
+        if self.exchange.detached.load(Ordering::SeqCst) {
+            return;
+        }
+
+        if let Some(ref progress) = self.progress {
+            let _ = progress.send(update);
+        }
+    }
+
+    /// Record that the request has been handed to the transport.
+    ///
+    /// Calls dropped by the dispatcher before their request is written fail
+    /// with a `NotSent` error, letting callers send them again safely.
+    pub fn set_written(&mut self) {
+        self.exchange.written.store(true, Ordering::SeqCst);
+    }
+
+    /// Record the id the request was written with, shown by
+    /// `ClientProxy::outstanding`.
+    pub fn set_request_id<I: Debug + Send + 'static>(&mut self, request_id: I) {
+        self.exchange.tracked.lock().unwrap().request_id = Some(Box::new(request_id));
+    }
+
+    /// Record the time at which the request times out, shown by
+    /// `ClientProxy::outstanding`.
+    pub fn set_deadline(&mut self, deadline: Instant) {
+        self.exchange.tracked.lock().unwrap().deadline = Some(deadline);
+    }
+
+    /// Returns true if the exchange was canceled with
+    /// `ClientProxy::cancel_where`.
+    ///
+    /// The dispatcher is expected to complete such exchanges with an error
+    /// once `poll_cancel` reports them canceled, see `canceled_error`.
+    pub fn is_canceled_by_client(&self) -> bool {
+        self.exchange.state.load(Ordering::SeqCst) == CANCELED
+    }
+
+    /// Check whether the exchange has been canceled, by dropping the response
+    /// future without detaching it, or with `ClientProxy::cancel_where`.
+    ///
+    /// Like a future, this registers the current task to be notified once
+    /// the exchange is canceled, and must therefore be called from a task.
+    /// `cancel_where` notifies the task that first called it for any of the
+    /// client's exchanges, so they must all be dispatched by the same task.
+    pub fn poll_cancel(&mut self) -> Async<()> {
+        if self.inner.is_none() {
+            return Async::NotReady;
+        }
+
+        // Registered before checking, so that `cancel_where` either sees
+        // the task or is seen
+        self.outstanding.register_dispatcher();
+
+        if self.is_canceled_by_client() {
+            return Async::Ready(());
+        }
+
+        let inner = self.inner.as_mut().unwrap();
+
+        match inner.poll_cancel() {
+            Ok(Async::Ready(())) if !self.exchange.detached.load(Ordering::SeqCst) => Async::Ready(()),
+            _ => Async::NotReady,
+        }
+    }
+}
+
+/// Error failing the calls whose request was never written to the transport
+///
+/// Found inside the `io::Error` failing such calls, e.g. those made on a
+/// closed connection or queued on one that failed to connect. As the peer
+/// never saw the requests, they can be sent again without risk of being
+/// processed twice, see the `retry` module.
+#[derive(Debug, Clone)]
+pub struct NotSent;
+
+impl fmt::Display for NotSent {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "request not sent")
+    }
+}
+
+impl Error for NotSent {
+    fn description(&self) -> &str {
+        "request not sent"
+    }
+}
+
+impl From<NotSent> for io::Error {
+    fn from(not_sent: NotSent) -> io::Error {
+        io::Error::new(io::ErrorKind::BrokenPipe, not_sent)
+    }
+}
+
+/// The error failing the calls canceled with `ClientProxy::cancel_where`
+pub fn canceled_error() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "request canceled")
+}
+
+impl<T, E> Drop for Complete<T, E> {
+    fn drop(&mut self) {
+        self.exchange.state.store(DONE, Ordering::SeqCst);
+
+        if self.inner.is_none() {
+            return;
+        }
+
+        // Let the collector report the exchange as failed
+        if let Some(ref collector) = *self.exchange.collector.lock().unwrap() {
+            let _ = collector.send(None);
+        }
     }
 }
 
@@ -92,6 +644,9 @@ impl<T, E> Future for Response<T, E>
             Ok(Async::Ready(Ok(v))) => Ok(Async::Ready(v)),
             Ok(Async::Ready(Err(e))) => Err(e),
             Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_) if !self.exchange.written.load(Ordering::SeqCst) => {
+                Err(io::Error::from(NotSent).into())
+            }
             Err(_) => {
                 let e = io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe");
                 Err(e.into())
@@ -99,3 +654,60 @@ impl<T, E> Future for Response<T, E>
         }
     }
 }
+
+impl<E> Future for Accepted<E>
+    where E: From<io::Error>,
+{
+    type Item = ();
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<(), E> {
+        match self.inner.poll() {
+            Ok(Async::Ready(())) => Ok(Async::Ready(())),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_) => {
+                let e = io::Error::new(io::ErrorKind::Other, "request not accepted");
+                Err(e.into())
+            }
+        }
+    }
+}
+
+impl<T, E> Stream for Progress<T, E> {
+    type Item = T;
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<Option<T>, E> {
+        match self.inner.poll() {
+            Ok(update) => Ok(update),
+            Err(()) => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+impl<T, E> Stream for Detached<T, E>
+    where E: From<io::Error>,
+{
+    type Item = Result<T, E>;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Result<T, E>>, ()> {
+        match try_ready!(self.inner.poll()) {
+            Some(Some(result)) => Ok(Async::Ready(Some(result))),
+            Some(None) => {
+                let e = io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe");
+                Ok(Async::Ready(Some(Err(e.into()))))
+            }
+            None => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+impl Stream for Errors {
+    type Item = io::Error;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<io::Error>, ()> {
+        self.inner.poll()
+    }
+}
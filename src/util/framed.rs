@@ -0,0 +1,671 @@
+//! Framing I/O objects that have already been read from
+//!
+//! A handshake run in `bind_transport` usually reads from the I/O object
+//! before the transport exists, and may read ahead past its own bytes. Those
+//! leftovers belong to the first frames of the protocol, but
+//! `Io::framed` starts out with an empty read buffer, so they are silently
+//! dropped. `framed_with_buffer` feeds them to the codec before anything
+//! else is read from the I/O object:
+//!
+//! ```rust,ignore
+//! fn bind_transport(&self, io: T) -> Self::BindTransport {
+//!     let handshake = read_hello(io).map(|(io, leftover)| {
+//!         framed_with_buffer(io, LineCodec, leftover)
+//!     });
+//!     Box::new(handshake)
+//! }
+//! ```
+//!
+//! `peek` reads the first bytes of a connection ahead of binding it, e.g. to
+//! sniff the protocol spoken by the peer or to parse a PROXY header, and
+//! hands back a `Rewind` replaying whatever the codec needs of them.
+//! `TcpServer::peek` runs it on every accepted connection.
+//!
+//! `framed_chunked` reads a bounded number of bytes per poll, reporting the
+//! length of the message being decoded in between. Together with the
+//! `max_message_len` of the connection config, a giant message fails the
+//! connection while it is being read rather than once it was buffered whole.
+
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::{Async, Future, Poll, Sink, StartSend, Stream};
+use futures::task;
+use tokio_core::io::{Codec, EasyBuf, Framed, Io};
+
+use streaming::{multiplex, pipeline};
+
+/// Frames `io` with `codec`, decoding `initial` before any data read from
+/// `io`.
+pub fn framed_with_buffer<T, C>(io: T, codec: C, initial: Vec<u8>) -> Framed<Rewind<T>, C>
+    where T: Io,
+          C: Codec,
+{
+    Rewind::new(io, initial).framed(codec)
+}
+
+/// An I/O object replaying already read bytes before reading on.
+///
+/// Writes go straight to the wrapped I/O object.
+pub struct Rewind<T> {
+    io: T,
+    pre: Vec<u8>,
+    pos: usize,
+}
+
+impl<T> Rewind<T> {
+    /// Wraps `io`, returning `pre` from reads until it has been consumed.
+    pub fn new(io: T, pre: Vec<u8>) -> Rewind<T> {
+        Rewind {
+            io: io,
+            pre: pre,
+            pos: 0,
+        }
+    }
+
+    /// Returns a reference to the wrapped I/O object.
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+
+    /// Returns a mutable reference to the wrapped I/O object.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.io
+    }
+
+    /// Consumes the wrapper, returning the wrapped I/O object and the bytes
+    /// not replayed yet.
+    pub fn into_inner(mut self) -> (T, Vec<u8>) {
+        let rest = self.pre.split_off(self.pos);
+        (self.io, rest)
+    }
+
+    fn has_pre(&self) -> bool {
+        self.pos < self.pre.len()
+    }
+}
+
+impl<T: Read> Read for Rewind<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.has_pre() {
+            return self.io.read(buf);
+        }
+
+        let n = try!((&self.pre[self.pos..]).read(buf));
+        self.pos += n;
+
+        if !self.has_pre() {
+            // Release the buffer, it is not needed anymore
+            self.pre = Vec::new();
+            self.pos = 0;
+        }
+
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for Rewind<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<T: Io> Io for Rewind<T> {
+    fn poll_read(&mut self) -> Async<()> {
+        if self.has_pre() {
+            return Async::Ready(());
+        }
+
+        self.io.poll_read()
+    }
+
+    fn poll_write(&mut self) -> Async<()> {
+        self.io.poll_write()
+    }
+}
+
+/// Reads the first bytes of `io`, up to `max_len` of them, until `f`
+/// recognizes them.
+///
+/// `f` is called with the bytes read so far after every read, returning
+/// `None` to have more read. Once it returns a value, the future completes
+/// with it and a `Rewind` replaying the bytes `f` left in the buffer, so a
+/// parsed PROXY header can be drained from the front while sniffed bytes stay
+/// put for the codec. The future fails if `f` fails, and with
+/// `ErrorKind::InvalidData` if the bytes are not recognized before the peer
+/// closes the connection or `max_len` bytes are read.
+///
+/// Peers may wait for a response before sending more, so `f` should decide
+/// as soon as the bytes allow.
+pub fn peek<T, F, R>(io: T, max_len: usize, f: F) -> Peek<T, F>
+    where T: Io,
+          F: FnMut(&mut Vec<u8>) -> io::Result<Option<R>>,
+{
+    Peek {
+        io: Some(io),
+        buf: Vec::new(),
+        max_len: max_len,
+        f: f,
+    }
+}
+
+/// Future returned by `peek`.
+pub struct Peek<T, F> {
+    io: Option<T>,
+    buf: Vec<u8>,
+    max_len: usize,
+    f: F,
+}
+
+impl<T, F, R> Future for Peek<T, F>
+    where T: Io,
+          F: FnMut(&mut Vec<u8>) -> io::Result<Option<R>>,
+{
+    type Item = (Rewind<T>, R);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(Rewind<T>, R), io::Error> {
+        loop {
+            if self.buf.len() >= self.max_len {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          "peeked bytes not recognized"));
+            }
+
+            let len = self.buf.len();
+            self.buf.resize(self.max_len, 0);
+
+            let res = self.io.as_mut().expect("polled Peek after completion")
+                .read(&mut self.buf[len..]);
+
+            let n = match res {
+                Ok(n) => n,
+                Err(e) => {
+                    self.buf.truncate(len);
+
+                    if e.kind() == io::ErrorKind::WouldBlock {
+                        return Ok(Async::NotReady);
+                    }
+
+                    return Err(e);
+                }
+            };
+
+            self.buf.truncate(len + n);
+
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          "connection closed before it was recognized"));
+            }
+
+            if let Some(item) = try!((self.f)(&mut self.buf)) {
+                let io = self.io.take().unwrap();
+                let buf = mem::take(&mut self.buf);
+
+                return Ok(Async::Ready((Rewind::new(io, buf), item)));
+            }
+        }
+    }
+}
+
+/// Codecs telling the length of a message ahead of decoding it, e.g. from a
+/// length prefix. Used by `framed_chunked`.
+pub trait DecodeHint: Codec {
+    /// Returns the length of the message at the front of `buf`, which
+    /// `decode` needs more bytes of, if known.
+    ///
+    /// Defaults to `None`, the message is as long as `buf` as far as the
+    /// dispatcher is concerned.
+    fn message_len(&self, buf: &EasyBuf) -> Option<usize> {
+        let _ = buf;
+        None
+    }
+}
+
+/// Frames `io` with `codec`, reading at most `chunk_len` bytes per poll.
+///
+/// Between chunks the transport reports the length of the message being
+/// decoded through `pending_read_len`, as buffered or as hinted by the codec
+/// if longer, so that the dispatcher can enforce the `max_message_len` of
+/// the connection config before the message is read whole. The transport
+/// task is notified to read the next chunk right away.
+///
+/// # Panics
+///
+/// Panics if `chunk_len` is zero.
+pub fn framed_chunked<T, C>(io: T, codec: C, chunk_len: usize) -> Chunked<T, C>
+    where T: Io,
+          C: DecodeHint,
+{
+    assert!(chunk_len > 0, "chunk length must be positive");
+
+    let pending = Arc::new(AtomicUsize::new(0));
+
+    let io = ChunkedIo {
+        io: io,
+        chunk_len: chunk_len,
+        left: chunk_len,
+    };
+
+    let codec = Measure {
+        codec: codec,
+        pending: pending.clone(),
+    };
+
+    Chunked {
+        inner: io.framed(codec),
+        pending: pending,
+    }
+}
+
+/// Transport returned by `framed_chunked`.
+pub struct Chunked<T, C> {
+    inner: Framed<ChunkedIo<T>, Measure<C>>,
+    // Length of the message being decoded, zero if none
+    pending: Arc<AtomicUsize>,
+}
+
+// Reads up to `chunk_len` bytes until reset
+struct ChunkedIo<T> {
+    io: T,
+    chunk_len: usize,
+    left: usize,
+}
+
+// Records the length of the message being decoded
+struct Measure<C> {
+    codec: C,
+    pending: Arc<AtomicUsize>,
+}
+
+impl<T, C> Chunked<T, C> {
+    /// Returns a reference to the framed I/O object.
+    pub fn get_ref(&self) -> &T {
+        &self.inner.get_ref().io
+    }
+
+    /// Returns a mutable reference to the framed I/O object.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner.get_mut().io
+    }
+}
+
+impl<T: Io, C: DecodeHint> Stream for Chunked<T, C> {
+    type Item = C::In;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<C::In>, io::Error> {
+        let io = self.inner.get_mut();
+        io.left = io.chunk_len;
+
+        self.inner.poll()
+    }
+}
+
+impl<T: Io, C: DecodeHint> Sink for Chunked<T, C> {
+    type SinkItem = C::Out;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, item: C::Out) -> StartSend<C::Out, io::Error> {
+        self.inner.start_send(item)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        self.inner.poll_complete()
+    }
+}
+
+impl<T, C> pipeline::Transport for Chunked<T, C>
+    where T: Io + 'static,
+          C: DecodeHint + 'static,
+{
+    fn pending_read_len(&self) -> Option<usize> {
+        match self.pending.load(Ordering::SeqCst) {
+            0 => None,
+            len => Some(len),
+        }
+    }
+}
+
+impl<T, C, RequestId, ReadBody> multiplex::Transport<RequestId, ReadBody> for Chunked<T, C>
+    where T: Io + 'static,
+          C: DecodeHint + 'static,
+{
+    fn pending_read_len(&self) -> Option<usize> {
+        match self.pending.load(Ordering::SeqCst) {
+            0 => None,
+            len => Some(len),
+        }
+    }
+}
+
+impl<T: Read> Read for ChunkedIo<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.left == 0 {
+            // Have the next chunk read on the next poll, once the length of
+            // the message was checked
+            task::park().unpark();
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "chunk read"));
+        }
+
+        let len = cmp::min(buf.len(), self.left);
+        let n = try!(self.io.read(&mut buf[..len]));
+        self.left -= n;
+
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for ChunkedIo<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<T: Io> Io for ChunkedIo<T> {
+    fn poll_read(&mut self) -> Async<()> {
+        self.io.poll_read()
+    }
+
+    fn poll_write(&mut self) -> Async<()> {
+        self.io.poll_write()
+    }
+}
+
+impl<C: DecodeHint> Codec for Measure<C> {
+    type In = C::In;
+    type Out = C::Out;
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<C::In>> {
+        let frame = try!(self.codec.decode(buf));
+
+        let pending = match frame {
+            Some(_) => 0,
+            None => cmp::max(buf.len(), self.codec.message_len(buf).unwrap_or(0)),
+        };
+
+        self.pending.store(pending, Ordering::SeqCst);
+        Ok(frame)
+    }
+
+    fn decode_eof(&mut self, buf: &mut EasyBuf) -> io::Result<C::In> {
+        self.codec.decode_eof(buf)
+    }
+
+    fn encode(&mut self, msg: C::Out, buf: &mut Vec<u8>) -> io::Result<()> {
+        self.codec.encode(msg, buf)
+    }
+}
+
+/// A source of bytes written straight to the I/O object of a transport,
+/// bypassing its write buffer. Returned by `RawCodec::encode_raw`.
+///
+/// Implementations specific to the I/O object, e.g. for a `TcpStream`, can
+/// use `sendfile` or vectored writes. `ReadSource` copies from any reader.
+pub trait WriteTo<W> {
+    /// Writes the next bytes of the source to `dst`, returning how many were
+    /// written, or zero once the source is exhausted.
+    ///
+    /// `WouldBlock` errors of `dst` are returned as is, the transport calls
+    /// again once `dst` is writable.
+    fn write_to(&mut self, dst: &mut W) -> io::Result<usize>;
+}
+
+/// Codecs handing some of the bytes they write to the transport as `WriteTo`
+/// sources. Used by `framed_raw`.
+pub trait RawCodec<W>: Codec {
+    /// Encode `msg` into `buf` like `encode` does, returning the source of
+    /// the bytes to write right after those, if any.
+    ///
+    /// By default `msg` is encoded with `encode`, without source.
+    fn encode_raw(&mut self, msg: Self::Out, buf: &mut Vec<u8>)
+                  -> io::Result<Option<Box<WriteTo<W>>>>
+    {
+        try!(self.encode(msg, buf));
+        Ok(None)
+    }
+}
+
+/// A `WriteTo` source copying the bytes of a reader, through a buffer of its
+/// own rather than the transport's.
+pub struct ReadSource<R> {
+    reader: R,
+    buf: Box<[u8]>,
+    // Bytes of `buf` read but not written yet
+    pos: usize,
+    end: usize,
+}
+
+impl<R: Read> ReadSource<R> {
+    /// Create a new `ReadSource` writing everything `reader` reads, see
+    /// `Read::take` to write only part of it.
+    pub fn new(reader: R) -> ReadSource<R> {
+        ReadSource {
+            reader: reader,
+            buf: vec![0; 8 * 1024].into_boxed_slice(),
+            pos: 0,
+            end: 0,
+        }
+    }
+}
+
+impl<R: Read, W: Write> WriteTo<W> for ReadSource<R> {
+    fn write_to(&mut self, dst: &mut W) -> io::Result<usize> {
+        if self.pos == self.end {
+            self.pos = 0;
+            self.end = try!(self.reader.read(&mut self.buf));
+
+            if self.end == 0 {
+                return Ok(0);
+            }
+        }
+
+        let n = try!(dst.write(&self.buf[self.pos..self.end]));
+
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write body source"));
+        }
+
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Frames `io` with `codec`, writing the sources `codec` returns from
+/// `encode_raw` straight to `io`.
+///
+/// Each source is written once the bytes encoded ahead of it have been,
+/// and before the bytes encoded after it. The transport is flushed once
+/// every source is exhausted.
+pub fn framed_raw<T, C>(io: T, codec: C) -> Raw<T, C>
+    where T: Io,
+          C: RawCodec<T>,
+{
+    let sources = Rc::new(RefCell::new(Sources {
+        written: 0,
+        pending: VecDeque::new(),
+    }));
+
+    let io = RawIo {
+        io: io,
+        sources: sources.clone(),
+    };
+
+    let codec = Split {
+        codec: codec,
+        sources: sources,
+    };
+
+    Raw { inner: io.framed(codec) }
+}
+
+/// Transport returned by `framed_raw`.
+pub struct Raw<T, C> {
+    inner: Framed<RawIo<T>, Split<T, C>>,
+}
+
+// Sources waiting to be written, along with the number of buffered bytes
+// written to the I/O object ahead of each
+struct Sources<T> {
+    // Bytes of the write buffer written so far
+    written: u64,
+    pending: VecDeque<(u64, Box<WriteTo<T>>)>,
+}
+
+// Writes the sources in between the bytes of the write buffer
+struct RawIo<T> {
+    io: T,
+    sources: Rc<RefCell<Sources<T>>>,
+}
+
+// Queues the sources returned by the codec
+struct Split<T, C> {
+    codec: C,
+    sources: Rc<RefCell<Sources<T>>>,
+}
+
+impl<T, C> Raw<T, C> {
+    /// Returns a reference to the framed I/O object.
+    pub fn get_ref(&self) -> &T {
+        &self.inner.get_ref().io
+    }
+
+    /// Returns a mutable reference to the framed I/O object.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner.get_mut().io
+    }
+}
+
+impl<T: Io, C: RawCodec<T>> Stream for Raw<T, C> {
+    type Item = C::In;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<C::In>, io::Error> {
+        self.inner.poll()
+    }
+}
+
+impl<T: Io, C: RawCodec<T>> Sink for Raw<T, C> {
+    type SinkItem = C::Out;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, item: C::Out) -> StartSend<C::Out, io::Error> {
+        self.inner.start_send(item)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        self.inner.poll_complete()
+    }
+}
+
+impl<T, C> pipeline::Transport for Raw<T, C>
+    where T: Io + 'static,
+          C: RawCodec<T> + 'static,
+{
+}
+
+impl<T, C, RequestId, ReadBody> multiplex::Transport<RequestId, ReadBody> for Raw<T, C>
+    where T: Io + 'static,
+          C: RawCodec<T> + 'static,
+{
+}
+
+impl<T: Write> RawIo<T> {
+    // Writes the sources due before the next buffered byte
+    fn write_sources(&mut self) -> io::Result<()> {
+        let mut sources = self.sources.borrow_mut();
+        let written = sources.written;
+
+        loop {
+            let n = match sources.pending.front_mut() {
+                Some(&mut (at, ref mut source)) if at == written => {
+                    try!(source.write_to(&mut self.io))
+                }
+                _ => return Ok(()),
+            };
+
+            if n == 0 {
+                sources.pending.pop_front();
+            }
+        }
+    }
+}
+
+impl<T: Read> Read for RawIo<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io.read(buf)
+    }
+}
+
+impl<T: Write> Write for RawIo<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        try!(self.write_sources());
+
+        let mut sources = self.sources.borrow_mut();
+
+        // Stop at the next source
+        let len = match sources.pending.front() {
+            Some(&(at, _)) => cmp::min(buf.len() as u64, at - sources.written) as usize,
+            None => buf.len(),
+        };
+
+        let n = try!(self.io.write(&buf[..len]));
+        sources.written += n as u64;
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Sources encoded last are due once the write buffer is empty
+        try!(self.write_sources());
+        self.io.flush()
+    }
+}
+
+impl<T: Io> Io for RawIo<T> {
+    fn poll_read(&mut self) -> Async<()> {
+        self.io.poll_read()
+    }
+
+    fn poll_write(&mut self) -> Async<()> {
+        self.io.poll_write()
+    }
+}
+
+impl<T, C: RawCodec<T>> Codec for Split<T, C> {
+    type In = C::In;
+    type Out = C::Out;
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<C::In>> {
+        self.codec.decode(buf)
+    }
+
+    fn decode_eof(&mut self, buf: &mut EasyBuf) -> io::Result<C::In> {
+        self.codec.decode_eof(buf)
+    }
+
+    fn encode(&mut self, msg: C::Out, buf: &mut Vec<u8>) -> io::Result<()> {
+        if let Some(source) = try!(self.codec.encode_raw(msg, buf)) {
+            let mut sources = self.sources.borrow_mut();
+
+            // The bytes buffered so far go out first
+            let at = sources.written + buf.len() as u64;
+            sources.pending.push_back((at, source));
+        }
+
+        Ok(())
+    }
+}
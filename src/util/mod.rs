@@ -1,3 +1,4 @@
 //! Utilities for building protocols
 
 pub mod client_proxy;
+pub mod framed;
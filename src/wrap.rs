@@ -0,0 +1,308 @@
+//! Wrapping the I/O objects of TCP connections ahead of binding them
+//!
+//! `TcpServer` and `TcpClient` hand the socket of every connection through a
+//! chain of wrappers before binding the protocol to it. Builder methods such
+//! as `TcpServer::tag` or `TcpClient::instrument` install one; plain closures
+//! taking the I/O object, the `Connection` and the event loop handle are
+//! wrappers too, installed with `wrap`.
+//!
+//! Every wrapper is appended to the chain and wraps the I/O object produced
+//! by the one before, so that options compose:
+//!
+//! ```rust,ignore
+//! TcpServer::new(proto, addr)
+//!     .instrument(&metrics)
+//!     .tag(tags, |conn| tenant_of(conn.peer()))
+//!     .serve(new_service);
+//! ```
+//!
+//! The protocol then binds to the outermost I/O object, here a
+//! `Tagged<Instrumented<TcpStream>, T>`, so it must implement `BindServer`
+//! for it.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use futures::{Future, Poll};
+use futures::future::{self, FutureResult};
+use tokio_core::io::Io;
+use tokio_core::reactor::Handle;
+
+use instrument::{Instrumented, IoMetrics};
+use tags::{Tags, Tagged};
+use timeout::{IoTimeouts, TimeoutIo};
+use util::framed::{self, Rewind};
+
+/// Wraps the I/O object of a connection.
+pub trait Wrap<I> {
+    /// The wrapped I/O object
+    type Io: 'static;
+
+    /// Future resolving to the wrapped I/O object, along with what is known
+    /// about the connection
+    type Future: Future<Item = (Self::Io, Connection), Error = io::Error>;
+
+    /// Wraps `io`, the I/O object of the connection `conn`.
+    ///
+    /// Failing the future closes the connection without binding it.
+    fn wrap(&self, io: I, conn: Connection, handle: &Handle) -> Self::Future;
+}
+
+/// What is known about a connection while it is wrapped.
+#[derive(Debug, Clone)]
+pub struct Connection {
+    peer: SocketAddr,
+    route: Option<String>,
+}
+
+/// The wrapper of a chain without any, leaving the I/O object as is.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Plain;
+
+/// Two wrappers applied one after the other.
+///
+/// Cloning a chain shares the wrappers it was created with.
+pub struct Chain<A, B> {
+    first: A,
+    second: Arc<B>,
+}
+
+/// Future returned by `Chain::wrap`.
+pub struct Chained<F, B, I> where B: Wrap<I> {
+    state: ChainState<F, B, I>,
+}
+
+enum ChainState<F, B, I> where B: Wrap<I> {
+    First(F, Arc<B>, Handle),
+    Second(B::Future),
+}
+
+/// Tags connections, see `TcpServer::tag`.
+pub struct Tag<T, G> {
+    tags: Tags<T>,
+    tagger: G,
+}
+
+/// Records the reads and writes of connections, see
+/// `TcpServer::instrument`.
+#[derive(Clone)]
+pub struct Instrument {
+    metrics: IoMetrics,
+}
+
+/// Peeks at the first bytes of connections, see `TcpServer::peek`.
+pub struct Peek<F> {
+    max_len: usize,
+    peeker: Arc<F>,
+}
+
+/// Fails the reads and writes of connections that block for too long, see
+/// `TcpServer::io_timeouts`.
+#[derive(Clone, Copy)]
+pub struct Timeouts {
+    timeouts: IoTimeouts,
+}
+
+impl Connection {
+    /// Create the description of a connection with `peer`.
+    pub fn new(peer: SocketAddr) -> Connection {
+        Connection {
+            peer: peer,
+            route: None,
+        }
+    }
+
+    /// Returns the address of the peer.
+    pub fn peer(&self) -> &SocketAddr {
+        &self.peer
+    }
+
+    /// Set the address of the peer, e.g. to that of the client a proxy
+    /// connected on behalf of, read from a PROXY header.
+    pub fn set_peer(&mut self, peer: SocketAddr) {
+        self.peer = peer;
+    }
+
+    /// Returns the route of the connection, if one was set.
+    pub fn route(&self) -> Option<&str> {
+        self.route.as_ref().map(|route| &route[..])
+    }
+
+    /// Set the route of the connection, e.g. to the server name a TLS client
+    /// asks for, read from its ClientHello while peeking.
+    ///
+    /// `Route` picks the factory of the connection's service by it.
+    pub fn set_route(&mut self, route: String) {
+        self.route = Some(route);
+    }
+}
+
+impl<I: 'static> Wrap<I> for Plain {
+    type Io = I;
+    type Future = FutureResult<(I, Connection), io::Error>;
+
+    fn wrap(&self, io: I, conn: Connection, _: &Handle) -> Self::Future {
+        future::ok((io, conn))
+    }
+}
+
+impl<F, I, J> Wrap<I> for F
+    where F: Fn(I, &Connection, &Handle) -> J,
+          J: 'static,
+{
+    type Io = J;
+    type Future = FutureResult<(J, Connection), io::Error>;
+
+    fn wrap(&self, io: I, conn: Connection, handle: &Handle) -> Self::Future {
+        future::ok((self(io, &conn, handle), conn))
+    }
+}
+
+impl<A, B> Chain<A, B> {
+    /// Create a chain applying `first`, then `second`.
+    pub fn new(first: A, second: B) -> Chain<A, B> {
+        Chain {
+            first: first,
+            second: Arc::new(second),
+        }
+    }
+}
+
+impl<A: Clone, B> Clone for Chain<A, B> {
+    fn clone(&self) -> Chain<A, B> {
+        Chain {
+            first: self.first.clone(),
+            second: self.second.clone(),
+        }
+    }
+}
+
+impl<I, A, B> Wrap<I> for Chain<A, B>
+    where A: Wrap<I>,
+          B: Wrap<A::Io>,
+{
+    type Io = B::Io;
+    type Future = Chained<A::Future, B, A::Io>;
+
+    fn wrap(&self, io: I, conn: Connection, handle: &Handle) -> Self::Future {
+        let first = self.first.wrap(io, conn, handle);
+
+        Chained {
+            state: ChainState::First(first, self.second.clone(), handle.clone()),
+        }
+    }
+}
+
+impl<F, B, I> Future for Chained<F, B, I>
+    where F: Future<Item = (I, Connection), Error = io::Error>,
+          B: Wrap<I>,
+{
+    type Item = (B::Io, Connection);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(B::Io, Connection), io::Error> {
+        loop {
+            let second = match self.state {
+                ChainState::First(ref mut first, ref second, ref handle) => {
+                    let (io, conn) = try_ready!(first.poll());
+                    second.wrap(io, conn, handle)
+                }
+                ChainState::Second(ref mut second) => return second.poll(),
+            };
+
+            self.state = ChainState::Second(second);
+        }
+    }
+}
+
+impl<T, G> Tag<T, G> {
+    /// Create a wrapper tagging every connection with the tag `tagger`
+    /// computes for it, tracked by `tags`.
+    pub fn new(tags: Tags<T>, tagger: G) -> Tag<T, G> {
+        Tag {
+            tags: tags,
+            tagger: tagger,
+        }
+    }
+}
+
+impl<I, T, G> Wrap<I> for Tag<T, G>
+    where I: Io + 'static,
+          T: PartialEq + Clone + 'static,
+          G: Fn(&Connection) -> T,
+{
+    type Io = Tagged<I, T>;
+    type Future = FutureResult<(Tagged<I, T>, Connection), io::Error>;
+
+    fn wrap(&self, io: I, conn: Connection, _: &Handle) -> Self::Future {
+        let tag = (self.tagger)(&conn);
+        future::ok((self.tags.tag(io, tag), conn))
+    }
+}
+
+impl Instrument {
+    /// Create a wrapper recording the reads and writes of every connection
+    /// into `metrics`.
+    pub fn new(metrics: &IoMetrics) -> Instrument {
+        Instrument { metrics: metrics.clone() }
+    }
+}
+
+impl<I: 'static> Wrap<I> for Instrument {
+    type Io = Instrumented<I>;
+    type Future = FutureResult<(Instrumented<I>, Connection), io::Error>;
+
+    fn wrap(&self, io: I, conn: Connection, _: &Handle) -> Self::Future {
+        future::ok((Instrumented::new(io, &self.metrics), conn))
+    }
+}
+
+impl Timeouts {
+    /// Create a wrapper failing the reads and writes of every connection
+    /// that block for longer than `timeouts`.
+    pub fn new(timeouts: &IoTimeouts) -> Timeouts {
+        Timeouts { timeouts: *timeouts }
+    }
+}
+
+impl<I: 'static> Wrap<I> for Timeouts {
+    type Io = TimeoutIo<I>;
+    type Future = FutureResult<(TimeoutIo<I>, Connection), io::Error>;
+
+    fn wrap(&self, io: I, conn: Connection, handle: &Handle) -> Self::Future {
+        future::ok((TimeoutIo::new(io, &self.timeouts, handle), conn))
+    }
+}
+
+impl<F> Peek<F> {
+    /// Create a wrapper reading the first bytes of every connection, up to
+    /// `max_len` of them, until `peeker` recognizes them.
+    pub fn new(max_len: usize, peeker: F) -> Peek<F> {
+        Peek {
+            max_len: max_len,
+            peeker: Arc::new(peeker),
+        }
+    }
+}
+
+impl<I, F> Wrap<I> for Peek<F>
+    where I: Io + 'static,
+          F: Fn(&mut Connection, &mut Vec<u8>) -> io::Result<bool> + 'static,
+{
+    type Io = Rewind<I>;
+    type Future = Box<Future<Item = (Rewind<I>, Connection), Error = io::Error>>;
+
+    fn wrap(&self, io: I, conn: Connection, _: &Handle) -> Self::Future {
+        let peeker = self.peeker.clone();
+        let mut conn = conn;
+
+        Box::new(framed::peek(io, self.max_len, move |buf| {
+            if try!(peeker(&mut conn, buf)) {
+                Ok(Some(conn.clone()))
+            } else {
+                Ok(None)
+            }
+        }))
+    }
+}
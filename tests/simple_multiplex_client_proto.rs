@@ -0,0 +1,72 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io;
+
+use futures::Future;
+use tokio_core::io::{Io, Codec, Framed, EasyBuf};
+use tokio_core::reactor::Core;
+use tokio_proto::multiplex::ClientProto;
+use tokio_proto::streaming::multiplex::Counter;
+use tokio_proto::TcpClient;
+
+#[derive(Default)]
+pub struct IdCodec;
+
+impl Codec for IdCodec {
+    type In = (u64, u64);
+    type Out = (u64, u64);
+
+    fn decode(&mut self, _: &mut EasyBuf) -> Result<Option<(u64, u64)>, io::Error> {
+        Ok(None)
+    }
+
+    fn encode(&mut self, _: (u64, u64), _: &mut Vec<u8>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub struct IdProto;
+
+impl<T: Io + 'static> ClientProto<T> for IdProto {
+    type Request = u64;
+    type Response = u64;
+    type RequestId = u64;
+    type Error = io::Error;
+    type Transport = Framed<T, IdCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+    type RequestIdSource = Counter;
+
+    fn requestid_source(&self) -> Counter {
+        Counter::new()
+    }
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(IdCodec))
+    }
+}
+
+fn is_clone<T: Clone>(_: &T) {
+}
+
+fn is_send<T: Send>(_: &T) {
+}
+
+#[test]
+fn test_remote_is_send() {
+    // Don't want the code to run, only compile
+    if false {
+        let core = Core::new().unwrap();
+        let builder = TcpClient::new(IdProto);
+        let service = builder.connect(&"127.0.0.1:12345".parse().unwrap(), &core.handle()).wait().unwrap();
+        is_clone(&service);
+
+        let remote = service.remote();
+        is_clone(&remote);
+        is_send(&remote);
+    }
+}
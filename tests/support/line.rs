@@ -0,0 +1,135 @@
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io;
+use std::str;
+
+use self::futures::future;
+use self::tokio_core::io::{Io, Codec, Framed, EasyBuf};
+use self::tokio_proto::pipeline::{ClientProto, ServerProto};
+use self::tokio_proto::multiplex;
+use self::tokio_proto::streaming::multiplex::Counter;
+use self::tokio_service::Service;
+
+/// Newline delimited UTF-8 strings
+pub struct LineCodec;
+
+impl Codec for LineCodec {
+    type In = String;
+    type Out = String;
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<String>> {
+        if let Some(i) = buf.as_slice().iter().position(|&b| b == b'\n') {
+            let line = buf.drain_to(i + 1);
+            let line = str::from_utf8(&line.as_slice()[..i]).unwrap();
+            Ok(Some(line.to_string()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn encode(&mut self, msg: String, buf: &mut Vec<u8>) -> io::Result<()> {
+        buf.extend_from_slice(msg.as_bytes());
+        buf.push(b'\n');
+        Ok(())
+    }
+}
+
+/// Pipelined line protocol, usable as both client and server
+pub struct LineProto;
+
+impl<T: Io + 'static> ServerProto<T> for LineProto {
+    type Request = String;
+    type Response = String;
+    type Transport = Framed<T, LineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(LineCodec))
+    }
+}
+
+impl<T: Io + 'static> ClientProto<T> for LineProto {
+    type Request = String;
+    type Response = String;
+    type Transport = Framed<T, LineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(LineCodec))
+    }
+}
+
+/// Newline delimited UTF-8 strings, each prefixed with a request id
+pub struct MuxLineCodec;
+
+impl Codec for MuxLineCodec {
+    type In = (u64, String);
+    type Out = (u64, String);
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<(u64, String)>> {
+        let line = match try!(LineCodec.decode(buf)) {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+
+        let mut parts = line.splitn(2, ' ');
+        let id = parts.next().unwrap().parse().unwrap();
+        let msg = parts.next().unwrap_or("").to_string();
+        Ok(Some((id, msg)))
+    }
+
+    fn encode(&mut self, (id, msg): (u64, String), buf: &mut Vec<u8>) -> io::Result<()> {
+        LineCodec.encode(format!("{} {}", id, msg), buf)
+    }
+}
+
+/// Multiplexed line protocol, usable as both client and server
+pub struct MuxLineProto;
+
+impl<T: Io + 'static> multiplex::ServerProto<T> for MuxLineProto {
+    type Request = String;
+    type Response = String;
+    type RequestId = u64;
+    type Error = io::Error;
+    type Transport = Framed<T, MuxLineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(MuxLineCodec))
+    }
+}
+
+impl<T: Io + 'static> multiplex::ClientProto<T> for MuxLineProto {
+    type Request = String;
+    type Response = String;
+    type RequestId = u64;
+    type Error = io::Error;
+    type Transport = Framed<T, MuxLineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+    type RequestIdSource = Counter;
+
+    fn requestid_source(&self) -> Counter {
+        Counter::new()
+    }
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(MuxLineCodec))
+    }
+}
+
+/// Responds with the request, prefixed with the given string
+pub struct Echo(pub String);
+
+impl Service for Echo {
+    type Request = String;
+    type Response = String;
+    type Error = io::Error;
+    type Future = future::FutureResult<String, io::Error>;
+
+    fn call(&self, req: String) -> Self::Future {
+        future::ok(format!("{}{}", self.0, req))
+    }
+}
@@ -1,3 +1,6 @@
+// The servers run by the mocks complete with ()
+#![allow(dropping_copy_types)]
+
 extern crate futures;
 extern crate tokio_core;
 extern crate tokio_proto;
@@ -6,28 +9,75 @@ extern crate env_logger;
 
 use std::any::Any;
 use std::thread;
+use std::time::Duration;
 use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
 use std::io::{self, Read, Write};
 
 use self::futures::stream::Wait;
 use self::futures::sync::mpsc;
 use self::futures::sync::oneshot;
+use self::futures::task::{self, Task};
 use self::futures::{Future, Stream, Sink, Poll, StartSend, Async};
 use self::tokio_core::io::Io;
 use self::tokio_core::reactor::Core;
 use self::tokio_proto::streaming::multiplex::{self, Counter};
 use self::tokio_proto::streaming::pipeline;
-use self::tokio_proto::streaming::{Message, Body};
-use self::tokio_proto::util::client_proxy::Response;
+use self::tokio_proto::streaming::{Message, Body, BufferProvider, ConnectionId, Encodings, Stats};
+use self::tokio_proto::util::client_proxy::{ClientProxy, Response};
 use self::tokio_proto::{BindClient, BindServer};
 use self::tokio_service::Service;
 
-struct MockProtocol<T>(RefCell<Option<MockTransport<T>>>);
+struct MockProtocol<T> {
+    transport: RefCell<Option<MockTransport<T>>>,
+    limits: Limits,
+}
+
+/// Connection limits of the mock servers and clients
+#[derive(Default)]
+pub struct Limits {
+    pub max_in_flight: Option<usize>,
+    pub max_frames_per_poll: Option<usize>,
+    pub max_error_frames: Option<usize>,
+    pub request_timeout: Option<Duration>,
+    pub ack_requests: bool,
+    pub encodings: Encodings,
+    pub tag_requests: bool,
+    pub max_buffered_frames_per_exchange: Option<usize>,
+    pub spill_policy: Option<multiplex::SpillPolicy>,
+    pub strict: bool,
+}
+
+// Tags pipelined requests as `<id> <request>`
+struct SeqTags(u64);
+
+impl pipeline::RequestIdSource<&'static str, &'static str> for SeqTags {
+    fn tag(&mut self, request: &mut &'static str) -> u64 {
+        let id = self.0;
+        self.0 += 1;
+        *request = Box::leak(format!("{} {}", id, request).into_boxed_str());
+        id
+    }
+
+    fn untag(&mut self, response: &mut &'static str) -> io::Result<u64> {
+        let space = try!(response.find(' ').ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "untagged response")
+        }));
+
+        let id = try!(response[..space].parse().map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidData, "invalid tag")
+        }));
+
+        *response = &response[space + 1..];
+        Ok(id)
+    }
+}
 
 impl<T, U, I> pipeline::ClientProto<I> for MockProtocol<pipeline::Frame<T, U, io::Error>>
     where T: 'static,
-          U: 'static,
+          U: Chunk + 'static,
           I: Io + 'static,
+          SeqTags: pipeline::RequestIdSource<T, T>,
 {
     type Request = T;
     type RequestBody = U;
@@ -39,13 +89,21 @@ impl<T, U, I> pipeline::ClientProto<I> for MockProtocol<pipeline::Frame<T, U, io
 
     fn bind_transport(&self, _io: I)
                       -> Result<MockTransport<pipeline::Frame<T, U, io::Error>>, io::Error> {
-        Ok(self.0.borrow_mut().take().unwrap())
+        Ok(self.transport.borrow_mut().take().unwrap())
+    }
+
+    fn requestid_source(&self) -> Option<Box<pipeline::RequestIdSource<T, T>>> {
+        if self.limits.tag_requests {
+            Some(Box::new(SeqTags(0)))
+        } else {
+            None
+        }
     }
 }
 
 impl<T, U, I> multiplex::ClientProto<I> for MockProtocol<multiplex::Frame<u64, T, U, io::Error>>
     where T: 'static,
-          U: 'static,
+          U: Chunk + 'static,
           I: Io + 'static,
 {
     type Request = T;
@@ -64,13 +122,21 @@ impl<T, U, I> multiplex::ClientProto<I> for MockProtocol<multiplex::Frame<u64, T
 
     fn bind_transport(&self, _io: I)
                       -> Result<MockTransport<multiplex::Frame<u64, T, U, io::Error>>, io::Error> {
-        Ok(self.0.borrow_mut().take().unwrap())
+        Ok(self.transport.borrow_mut().take().unwrap())
+    }
+
+    fn request_timeout(&self) -> Option<Duration> {
+        self.limits.request_timeout
+    }
+
+    fn encodings(&self) -> Encodings {
+        self.limits.encodings.clone()
     }
 }
 
 impl<T, U, I> pipeline::ServerProto<I> for MockProtocol<pipeline::Frame<T, U, io::Error>>
     where T: 'static,
-          U: 'static,
+          U: Chunk + 'static,
           I: Io + 'static,
 {
     type Request = T;
@@ -81,15 +147,27 @@ impl<T, U, I> pipeline::ServerProto<I> for MockProtocol<pipeline::Frame<T, U, io
     type Transport = MockTransport<pipeline::Frame<T, U, io::Error>>;
     type BindTransport = Result<Self::Transport, io::Error>;
 
+    fn config(&self) -> pipeline::PipelineConfig {
+        pipeline::PipelineConfig {
+            max_frames_per_poll: self.limits.max_frames_per_poll,
+            max_error_frames: self.limits.max_error_frames,
+            ..Default::default()
+        }
+    }
+
+    fn encodings(&self) -> Encodings {
+        self.limits.encodings.clone()
+    }
+
     fn bind_transport(&self, _io: I)
                       -> Result<MockTransport<pipeline::Frame<T, U, io::Error>>, io::Error> {
-        Ok(self.0.borrow_mut().take().unwrap())
+        Ok(self.transport.borrow_mut().take().unwrap())
     }
 }
 
 impl<T, U, I> multiplex::ServerProto<I> for MockProtocol<multiplex::Frame<u64, T, U, io::Error>>
     where T: 'static,
-          U: 'static,
+          U: Chunk + 'static,
           I: Io + 'static,
 {
     type Request = T;
@@ -101,15 +179,141 @@ impl<T, U, I> multiplex::ServerProto<I> for MockProtocol<multiplex::Frame<u64, T
     type Transport = MockTransport<multiplex::Frame<u64, T, U, io::Error>>;
     type BindTransport = Result<Self::Transport, io::Error>;
 
+    fn config(&self) -> multiplex::MultiplexConfig {
+        let defaults = multiplex::MultiplexConfig::default();
+
+        multiplex::MultiplexConfig {
+            max_in_flight: self.limits.max_in_flight.unwrap_or(defaults.max_in_flight),
+            max_frames_per_poll: self.limits.max_frames_per_poll,
+            max_error_frames: self.limits.max_error_frames,
+            max_buffered_frames_per_exchange: self.limits.max_buffered_frames_per_exchange,
+            spill_policy: self.limits.spill_policy.unwrap_or(defaults.spill_policy),
+            strict: self.limits.strict,
+            ..defaults
+        }
+    }
+
     fn bind_transport(&self, _io: I)
                       -> Result<MockTransport<multiplex::Frame<u64, T, U, io::Error>>, io::Error> {
-        Ok(self.0.borrow_mut().take().unwrap())
+        Ok(self.transport.borrow_mut().take().unwrap())
+    }
+
+    fn ack_requests(&self) -> bool {
+        self.limits.ack_requests
+    }
+
+    fn encodings(&self) -> Encodings {
+        self.limits.encodings.clone()
     }
 }
 
 struct MockTransport<T> {
     tx: mpsc::Sender<T>,
     rx: mpsc::UnboundedReceiver<io::Result<T>>,
+    shared: Arc<Mutex<Shared>>,
+}
+
+// State the transport reports back to the test
+#[derive(Default)]
+struct Shared {
+    connection: Option<ConnectionId>,
+    reads: usize,
+    stats: Option<Stats>,
+    write_shutdown: bool,
+    canceled: Vec<u64>,
+    coalesce_up_to: Option<u32>,
+    split_over: Option<u32>,
+    buffers: Option<Arc<BufferProvider>>,
+    advertised: Option<Encodings>,
+    peer_encodings: Option<Encodings>,
+    encoding: Option<Option<String>>,
+    bodies_throttled: bool,
+    // Bodies paused (true) and resumed (false) through the transport
+    pauses: Vec<(u64, bool)>,
+    resynchronize: bool,
+    // Dispatcher task to notify once body writes are allowed again
+    throttled_task: Option<Task>,
+}
+
+// Lets the mock transport coalesce and split body frames
+trait Coalesce: Sized {
+    fn coalesce(&mut self, next: Self, max: u32) -> Option<Self>;
+    fn split(&mut self, max: u32) -> Option<Self>;
+}
+
+// Body chunks of the mock protocols, coalesced by adding them up and split
+// by subtracting
+pub trait Chunk: Sized {
+    fn add(&mut self, other: Self, max: u32) -> Option<Self>;
+    fn split(&mut self, max: u32) -> Option<Self>;
+}
+
+impl Chunk for u32 {
+    fn add(&mut self, other: u32, max: u32) -> Option<u32> {
+        if *self + other <= max {
+            *self += other;
+            None
+        } else {
+            Some(other)
+        }
+    }
+
+    fn split(&mut self, max: u32) -> Option<u32> {
+        if *self > max {
+            let rest = *self - max;
+            *self = max;
+            Some(rest)
+        } else {
+            None
+        }
+    }
+}
+
+fn add_chunks<U: Chunk>(buffered: &mut Option<U>, next: Option<U>, max: u32) -> Option<Option<U>> {
+    match (buffered.as_mut(), next) {
+        (Some(buffered), Some(next)) => buffered.add(next, max).map(Some),
+        (_, next) => Some(next),
+    }
+}
+
+impl<T, U: Chunk> Coalesce for pipeline::Frame<T, U, io::Error> {
+    fn coalesce(&mut self, next: Self, max: u32) -> Option<Self> {
+        match (self, next) {
+            (&mut pipeline::Frame::Body { ref mut chunk }, pipeline::Frame::Body { chunk: next }) => {
+                add_chunks(chunk, next, max).map(|chunk| pipeline::Frame::Body { chunk: chunk })
+            }
+            (_, next) => Some(next),
+        }
+    }
+
+    fn split(&mut self, max: u32) -> Option<Self> {
+        match *self {
+            pipeline::Frame::Body { chunk: Some(ref mut chunk) } => {
+                chunk.split(max).map(|rest| pipeline::Frame::Body { chunk: Some(rest) })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<T, U: Chunk> Coalesce for multiplex::Frame<u64, T, U, io::Error> {
+    fn coalesce(&mut self, next: Self, max: u32) -> Option<Self> {
+        match (self, next) {
+            (&mut multiplex::Frame::Body { ref mut chunk, .. }, multiplex::Frame::Body { id, chunk: next }) => {
+                add_chunks(chunk, next, max).map(|chunk| multiplex::Frame::Body { id: id, chunk: chunk })
+            }
+            (_, next) => Some(next),
+        }
+    }
+
+    fn split(&mut self, max: u32) -> Option<Self> {
+        match *self {
+            multiplex::Frame::Body { id, chunk: Some(ref mut chunk) } => {
+                chunk.split(max).map(|rest| multiplex::Frame::Body { id: id, chunk: Some(rest) })
+            }
+            _ => None,
+        }
+    }
 }
 
 impl<T: 'static> Stream for MockTransport<T> {
@@ -118,7 +322,10 @@ impl<T: 'static> Stream for MockTransport<T> {
 
     fn poll(&mut self) -> Poll<Option<T>, io::Error> {
         match self.rx.poll().expect("rx cannot fail") {
-            Async::Ready(Some(Ok(e))) => Ok(Async::Ready(Some(e))),
+            Async::Ready(Some(Ok(e))) => {
+                self.shared.lock().unwrap().reads += 1;
+                Ok(Async::Ready(Some(e)))
+            }
             Async::Ready(Some(Err(e))) => Err(e),
             Async::Ready(None) => Ok(Async::Ready(None)),
             Async::NotReady => Ok(Async::NotReady),
@@ -139,8 +346,129 @@ impl<T: 'static> Sink for MockTransport<T> {
     }
 }
 
-impl<T: 'static> pipeline::Transport for MockTransport<T> {}
-impl<B, RID, T: 'static> multiplex::Transport<RID, B> for MockTransport<T> {}
+impl<T: Coalesce + 'static> pipeline::Transport for MockTransport<T> {
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.shared.lock().unwrap().write_shutdown = true;
+        Ok(())
+    }
+
+    fn resynchronize(&mut self, _error: &io::Error) -> bool {
+        self.shared.lock().unwrap().resynchronize
+    }
+
+    fn on_connection(&mut self, id: ConnectionId) {
+        self.shared.lock().unwrap().connection = Some(id);
+    }
+
+    fn on_buffer_provider(&mut self, provider: Arc<BufferProvider>) {
+        self.shared.lock().unwrap().buffers = Some(provider);
+    }
+
+    fn on_stats(&mut self, stats: Stats) {
+        self.shared.lock().unwrap().stats = Some(stats);
+    }
+
+    fn advertise_encodings(&mut self, encodings: &Encodings) {
+        self.shared.lock().unwrap().advertised = Some(encodings.clone());
+    }
+
+    fn peer_encodings(&mut self) -> Option<Encodings> {
+        self.shared.lock().unwrap().peer_encodings.take()
+    }
+
+    fn on_encoding(&mut self, encoding: Option<&str>) {
+        self.shared.lock().unwrap().encoding = Some(encoding.map(|e| e.to_string()));
+    }
+
+    fn coalesce_body(&mut self, buffered: &mut T, next: T) -> Option<T> {
+        match self.shared.lock().unwrap().coalesce_up_to {
+            Some(max) => buffered.coalesce(next, max),
+            None => Some(next),
+        }
+    }
+
+    fn max_body_frame_len(&self) -> Option<usize> {
+        self.shared.lock().unwrap().split_over.map(|max| max as usize)
+    }
+
+    fn split_body(&mut self, frame: &mut T, max_len: usize) -> Option<T> {
+        frame.split(max_len as u32)
+    }
+}
+
+impl<B, T: Coalesce + 'static> multiplex::Transport<u64, B> for MockTransport<T> {
+    fn cancel(&mut self, request_id: u64) -> io::Result<()> {
+        self.shared.lock().unwrap().canceled.push(request_id);
+        Ok(())
+    }
+
+    fn poll_write_body(&mut self, _id: u64) -> Async<()> {
+        let mut shared = self.shared.lock().unwrap();
+
+        if shared.bodies_throttled {
+            shared.throttled_task = Some(task::park());
+            Async::NotReady
+        } else {
+            Async::Ready(())
+        }
+    }
+
+    fn pause_body(&mut self, id: u64) {
+        self.shared.lock().unwrap().pauses.push((id, true));
+    }
+
+    fn resume_body(&mut self, id: u64) {
+        self.shared.lock().unwrap().pauses.push((id, false));
+    }
+
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.shared.lock().unwrap().write_shutdown = true;
+        Ok(())
+    }
+
+    fn resynchronize(&mut self, _error: &io::Error) -> bool {
+        self.shared.lock().unwrap().resynchronize
+    }
+
+    fn on_connection(&mut self, id: ConnectionId) {
+        self.shared.lock().unwrap().connection = Some(id);
+    }
+
+    fn on_buffer_provider(&mut self, provider: Arc<BufferProvider>) {
+        self.shared.lock().unwrap().buffers = Some(provider);
+    }
+
+    fn on_stats(&mut self, stats: Stats) {
+        self.shared.lock().unwrap().stats = Some(stats);
+    }
+
+    fn advertise_encodings(&mut self, encodings: &Encodings) {
+        self.shared.lock().unwrap().advertised = Some(encodings.clone());
+    }
+
+    fn peer_encodings(&mut self) -> Option<Encodings> {
+        self.shared.lock().unwrap().peer_encodings.take()
+    }
+
+    fn on_encoding(&mut self, encoding: Option<&str>) {
+        self.shared.lock().unwrap().encoding = Some(encoding.map(|e| e.to_string()));
+    }
+
+    fn coalesce_body(&mut self, buffered: &mut T, next: T) -> Option<T> {
+        match self.shared.lock().unwrap().coalesce_up_to {
+            Some(max) => buffered.coalesce(next, max),
+            None => Some(next),
+        }
+    }
+
+    fn max_body_frame_len(&self) -> Option<usize> {
+        self.shared.lock().unwrap().split_over.map(|max| max as usize)
+    }
+
+    fn split_body(&mut self, frame: &mut T, max_len: usize) -> Option<T> {
+        frame.split(max_len as u32)
+    }
+}
 
 struct MockIo;
 
@@ -167,6 +495,7 @@ pub type MockBodyStream = Box<Stream<Item = u32, Error = io::Error> + Send>;
 pub struct MockTransportCtl<T> {
     tx: Option<mpsc::UnboundedSender<io::Result<T>>>,
     rx: Wait<mpsc::Receiver<T>>,
+    shared: Arc<Mutex<Shared>>,
 }
 
 impl<T> MockTransportCtl<T> {
@@ -184,6 +513,87 @@ impl<T> MockTransportCtl<T> {
         self.rx.next().unwrap().expect("cannot error")
     }
 
+    // Returns the connection id handed to the transport by the dispatcher
+    pub fn connection_id(&self) -> ConnectionId {
+        self.shared.lock().unwrap().connection.expect("dispatcher not created")
+    }
+
+    // Returns the buffer provider the dispatcher handed to the transport
+    pub fn buffer_provider(&self) -> Arc<BufferProvider> {
+        self.shared.lock().unwrap().buffers.clone().expect("dispatcher not created")
+    }
+
+    // Returns the number of frames the dispatcher read from the transport
+    pub fn reads(&self) -> usize {
+        self.shared.lock().unwrap().reads
+    }
+
+    // Returns the most recent stats reported to the transport
+    pub fn last_stats(&self) -> Option<Stats> {
+        self.shared.lock().unwrap().stats
+    }
+
+    // Returns true once the dispatcher shut down the write half
+    pub fn is_write_shutdown(&self) -> bool {
+        self.shared.lock().unwrap().write_shutdown
+    }
+
+    // Lets the transport coalesce body chunks, as long as their sum does not
+    // exceed `max`
+    pub fn coalesce_body_up_to(&self, max: u32) {
+        self.shared.lock().unwrap().coalesce_up_to = Some(max);
+    }
+
+    // Advertises `max` as the max body frame length, splitting larger chunks
+    pub fn split_body_over(&self, max: u32) {
+        self.shared.lock().unwrap().split_over = Some(max);
+    }
+
+    // Returns the encodings the dispatcher had the transport advertise
+    pub fn advertised_encodings(&self) -> Option<Encodings> {
+        self.shared.lock().unwrap().advertised.clone()
+    }
+
+    // Makes the transport report `encodings` as advertised by the peer, the
+    // next time the dispatcher reads frames
+    pub fn peer_advertises(&self, encodings: Encodings) {
+        self.shared.lock().unwrap().peer_encodings = Some(encodings);
+    }
+
+    // Returns the encoding agreed on, once the dispatcher recorded it
+    pub fn encoding(&self) -> Option<Option<String>> {
+        self.shared.lock().unwrap().encoding.clone()
+    }
+
+    // Makes the transport refuse body frames until `release_bodies` is called
+    pub fn throttle_bodies(&self) {
+        self.shared.lock().unwrap().bodies_throttled = true;
+    }
+
+    pub fn release_bodies(&self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.bodies_throttled = false;
+
+        if let Some(task) = shared.throttled_task.take() {
+            task.unpark();
+        }
+    }
+
+    // Makes the transport skip past read errors instead of failing the
+    // connection
+    pub fn resynchronize_on_errors(&self) {
+        self.shared.lock().unwrap().resynchronize = true;
+    }
+
+    // Returns the ids of the exchanges canceled on the transport, in order
+    pub fn canceled(&self) -> Vec<u64> {
+        self.shared.lock().unwrap().canceled.clone()
+    }
+
+    pub fn pauses(&self) -> Vec<(u64, bool)> {
+        self.shared.lock().unwrap().pauses.clone()
+    }
+
     pub fn allow_and_assert_drop(&mut self) {
         drop(self.tx.take());
         assert!(self.rx.next().is_none());
@@ -193,15 +603,22 @@ impl<T> MockTransportCtl<T> {
 fn transport<T>() -> (MockTransportCtl<T>, MockProtocol<T>) {
     let (tx1, rx1) = mpsc::channel(1);
     let (tx2, rx2) = mpsc::unbounded();
+    let shared = Arc::new(Mutex::new(Shared::default()));
     let ctl = MockTransportCtl {
         tx: Some(tx2),
         rx: rx1.wait(),
+        shared: shared.clone(),
     };
     let transport = MockTransport {
         tx: tx1,
         rx: rx2,
+        shared: shared,
     };
-    (ctl, MockProtocol(RefCell::new(Some(transport))))
+    let proto = MockProtocol {
+        transport: RefCell::new(Some(transport)),
+        limits: Limits::default(),
+    };
+    (ctl, proto)
 }
 
 struct CompleteOnDrop {
@@ -224,10 +641,24 @@ pub fn pipeline_client()
                     Future = Response<Message<&'static str, Body<u32, io::Error>>,
                                               io::Error>>>,
         Box<Any>)
+{
+    pipeline_client_with_limits(Limits::default())
+}
+
+/// Like `pipeline_client`, applying the given connection limits
+pub fn pipeline_client_with_limits(limits: Limits)
+    -> (MockTransportCtl<pipeline::Frame<&'static str, u32, io::Error>>,
+        Box<Service<Request = Message<&'static str, MockBodyStream>,
+                    Response = Message<&'static str, Body<u32, io::Error>>,
+                    Error = io::Error,
+                    Future = Response<Message<&'static str, Body<u32, io::Error>>,
+                                              io::Error>>>,
+        Box<Any>)
 {
     drop(env_logger::init());
 
-    let (ctl, proto) = transport();
+    let (ctl, mut proto) = transport();
+    proto.limits = limits;
 
     let (tx, rx) = oneshot::channel();
     let (finished_tx, finished_rx) = oneshot::channel();
@@ -254,10 +685,32 @@ pub fn pipeline_server<S>(s: S)
     where S: Service<Request = Message<&'static str, Body<u32, io::Error>>,
                      Response = Message<&'static str, MockBodyStream>,
                      Error = io::Error> + Send + 'static,
+{
+    pipeline_server_with_budget(None, s)
+}
+
+/// Like `pipeline_server`, limiting the frames the server processes per poll
+pub fn pipeline_server_with_budget<S>(max_frames_per_poll: Option<usize>, s: S)
+    -> (MockTransportCtl<pipeline::Frame<&'static str, u32, io::Error>>, Box<Any>)
+    where S: Service<Request = Message<&'static str, Body<u32, io::Error>>,
+                     Response = Message<&'static str, MockBodyStream>,
+                     Error = io::Error> + Send + 'static,
+{
+    let limits = Limits { max_frames_per_poll: max_frames_per_poll, ..Default::default() };
+    pipeline_server_with_limits(limits, s)
+}
+
+/// Like `pipeline_server`, applying the given connection limits
+pub fn pipeline_server_with_limits<S>(limits: Limits, s: S)
+    -> (MockTransportCtl<pipeline::Frame<&'static str, u32, io::Error>>, Box<Any>)
+    where S: Service<Request = Message<&'static str, Body<u32, io::Error>>,
+                     Response = Message<&'static str, MockBodyStream>,
+                     Error = io::Error> + Send + 'static,
 {
     drop(env_logger::init());
 
-    let (ctl, proto) = transport();
+    let (ctl, mut proto) = transport();
+    proto.limits = limits;
 
     let (finished_tx, finished_rx) = oneshot::channel();
     let t = thread::spawn(move || {
@@ -277,16 +730,26 @@ pub fn pipeline_server<S>(s: S)
 
 pub fn multiplex_client()
     -> (MockTransportCtl<multiplex::Frame<u64, &'static str, u32, io::Error>>,
-        Box<Service<Request = Message<&'static str, MockBodyStream>,
-                    Response = Message<&'static str, Body<u32, io::Error>>,
-                    Error = io::Error,
-                    Future = Response<Message<&'static str, Body<u32, io::Error>>,
-                                              io::Error>>>,
+        ClientProxy<Message<&'static str, MockBodyStream>,
+                    Message<&'static str, Body<u32, io::Error>>,
+                    io::Error>,
+        Box<Any>)
+{
+    multiplex_client_with_limits(Limits::default())
+}
+
+/// Like `multiplex_client`, applying the given connection limits
+pub fn multiplex_client_with_limits(limits: Limits)
+    -> (MockTransportCtl<multiplex::Frame<u64, &'static str, u32, io::Error>>,
+        ClientProxy<Message<&'static str, MockBodyStream>,
+                    Message<&'static str, Body<u32, io::Error>>,
+                    io::Error>,
         Box<Any>)
 {
     drop(env_logger::init());
 
-    let (ctl, proto) = transport();
+    let (ctl, mut proto) = transport();
+    proto.limits = limits;
 
     let (tx, rx) = oneshot::channel();
     let (finished_tx, finished_rx) = oneshot::channel();
@@ -305,7 +768,7 @@ pub fn multiplex_client()
         thread: Some(t),
         tx: Some(finished_tx),
     };
-    return (ctl, Box::new(service), Box::new(srv));
+    return (ctl, service, Box::new(srv));
 }
 
 pub fn multiplex_server<S>(s: S)
@@ -313,10 +776,32 @@ pub fn multiplex_server<S>(s: S)
     where S: Service<Request = Message<&'static str, Body<u32, io::Error>>,
                      Response = Message<&'static str, MockBodyStream>,
                      Error = io::Error> + Send + 'static,
+{
+    multiplex_server_with_budget(None, s)
+}
+
+/// Like `multiplex_server`, limiting the frames the server processes per poll
+pub fn multiplex_server_with_budget<S>(max_frames_per_poll: Option<usize>, s: S)
+    -> (MockTransportCtl<multiplex::Frame<u64, &'static str, u32, io::Error>>, Box<Any>)
+    where S: Service<Request = Message<&'static str, Body<u32, io::Error>>,
+                     Response = Message<&'static str, MockBodyStream>,
+                     Error = io::Error> + Send + 'static,
+{
+    let limits = Limits { max_frames_per_poll: max_frames_per_poll, ..Default::default() };
+    multiplex_server_with_limits(limits, s)
+}
+
+/// Like `multiplex_server`, applying the given connection limits
+pub fn multiplex_server_with_limits<S>(limits: Limits, s: S)
+    -> (MockTransportCtl<multiplex::Frame<u64, &'static str, u32, io::Error>>, Box<Any>)
+    where S: Service<Request = Message<&'static str, Body<u32, io::Error>>,
+                     Response = Message<&'static str, MockBodyStream>,
+                     Error = io::Error> + Send + 'static,
 {
     drop(env_logger::init());
 
-    let (ctl, proto) = transport();
+    let (ctl, mut proto) = transport();
+    proto.limits = limits;
 
     let (finished_tx, finished_rx) = oneshot::channel();
     let t = thread::spawn(move || {
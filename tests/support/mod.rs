@@ -1,4 +1,30 @@
 #![allow(dead_code)]
 
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+pub mod line;
 pub mod mock;
 pub mod service;
+
+/// How long a test waits on a socket or another thread before giving up.
+pub const DEADLINE: Duration = Duration::from_secs(5);
+
+/// Connects to a server started on another thread.
+///
+/// Reads on the socket fail once `DEADLINE` passes, so that a test fails
+/// instead of hanging.
+pub fn connect(addr: &SocketAddr) -> TcpStream {
+    // The server may still be starting up
+    for _ in 0..100 {
+        if let Ok(socket) = TcpStream::connect(addr) {
+            socket.set_read_timeout(Some(DEADLINE)).unwrap();
+            return socket;
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    panic!("server did not start");
+}
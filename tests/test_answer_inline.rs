@@ -0,0 +1,118 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io;
+use std::net::SocketAddr;
+
+use futures::{Future, Stream};
+use tokio_core::io::{Framed, Io};
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::Core;
+use tokio_proto::{multiplex, pipeline, BindClient, BindServer};
+use tokio_service::Service;
+
+mod support;
+use support::line::{LineCodec, LineProto, MuxLineCodec, MuxLineProto, Echo};
+
+// Answers `PING` requests without involving the service
+struct PingProto;
+
+impl<T: Io + 'static> pipeline::ServerProto<T> for PingProto {
+    type Request = String;
+    type Response = String;
+    type Transport = Framed<T, LineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(LineCodec))
+    }
+
+    fn answer_inline(_: &mut Self::Transport, request: &String) -> Option<String> {
+        if request == "PING" {
+            Some("PONG".to_string())
+        } else {
+            None
+        }
+    }
+}
+
+impl<T: Io + 'static> multiplex::ServerProto<T> for PingProto {
+    type Request = String;
+    type Response = String;
+    type RequestId = u64;
+    type Error = io::Error;
+    type Transport = Framed<T, MuxLineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(MuxLineCodec))
+    }
+
+    fn answer_inline(_: &mut Self::Transport, request: &String) -> Option<String> {
+        if request == "PING" {
+            Some("PONG".to_string())
+        } else {
+            None
+        }
+    }
+}
+
+fn serve<K: 'static>(core: &Core) -> SocketAddr
+    where PingProto: BindServer<K, TcpStream,
+                                ServiceRequest = String,
+                                ServiceResponse = String,
+                                ServiceError = io::Error>
+{
+    let handle = core.handle();
+
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_handle = handle.clone();
+    let server = listener.incoming().for_each(move |(socket, _)| {
+        PingProto.bind_server(&server_handle, socket, Echo("echo:".to_string()));
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    addr
+}
+
+#[test]
+fn test_pipeline_transport_answers_ping() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let addr = serve::<pipeline::Pipeline>(&core);
+
+    let socket = core.run(TcpStream::connect(&addr, &handle)).unwrap();
+    let client = BindClient::<pipeline::Pipeline, _>::bind_client(&LineProto, &handle, socket);
+
+    // Responses keep the order of the requests
+    let responses = vec!["hello", "PING", "world"].into_iter().map(|req| {
+        client.call(req.to_string())
+    }).collect::<Vec<_>>();
+
+    let responses = core.run(futures::future::join_all(responses)).unwrap();
+    assert_eq!(vec!["echo:hello", "PONG", "echo:world"], responses);
+}
+
+#[test]
+fn test_multiplex_transport_answers_ping() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let addr = serve::<multiplex::Multiplex>(&core);
+
+    let socket = core.run(TcpStream::connect(&addr, &handle)).unwrap();
+    let client = BindClient::<multiplex::Multiplex, _>::bind_client(&MuxLineProto, &handle, socket);
+
+    let ping = core.run(client.call("PING".to_string())).unwrap();
+    assert_eq!("PONG", ping);
+
+    let hello = core.run(client.call("hello".to_string())).unwrap();
+    assert_eq!("echo:hello", hello);
+}
@@ -0,0 +1,157 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::cell::Cell;
+use std::io;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+use futures::{future, Future};
+use tokio_proto::balance::Balancer;
+use tokio_service::Service;
+
+mod support;
+use support::line::Echo;
+
+// Answers with its name, or fails while told to
+struct Endpoint {
+    name: &'static str,
+    fail: Rc<Cell<bool>>,
+}
+
+impl Endpoint {
+    fn new(name: &'static str) -> (Endpoint, Rc<Cell<bool>>) {
+        let fail = Rc::new(Cell::new(false));
+        (Endpoint { name: name, fail: fail.clone() }, fail)
+    }
+}
+
+impl Service for Endpoint {
+    type Request = String;
+    type Response = String;
+    type Error = io::Error;
+    type Future = future::FutureResult<String, io::Error>;
+
+    fn call(&self, _: String) -> Self::Future {
+        if self.fail.get() {
+            future::err(io::Error::new(io::ErrorKind::ConnectionRefused, self.name))
+        } else {
+            future::ok(self.name.to_string())
+        }
+    }
+}
+
+// Calls the balancer `n` times, returning how many calls each endpoint got
+fn spread(balancer: &Balancer<&'static str, Echo>, n: usize) -> (usize, usize) {
+    let mut stable = 0;
+    let mut canary = 0;
+
+    for _ in 0..n {
+        match &balancer.call(String::new()).wait().unwrap()[..] {
+            "stable" => stable += 1,
+            "canary" => canary += 1,
+            res => panic!("unexpected response: {}", res),
+        }
+    }
+
+    (stable, canary)
+}
+
+#[test]
+fn test_requests_spread_by_weight() {
+    let balancer = Balancer::new()
+        .endpoint("stable", Echo("stable".to_string()), 95)
+        .endpoint("canary", Echo("canary".to_string()), 5);
+
+    assert_eq!((95, 5), spread(&balancer, 100));
+
+    // The canary's share is spread out rather than sent in a burst
+    let mut picks = vec![];
+    for _ in 0..40 {
+        picks.push(balancer.call(String::new()).wait().unwrap());
+    }
+    assert_eq!(2, picks.iter().filter(|p| *p == "canary").count());
+}
+
+#[test]
+fn test_weights_adjusted_through_handle() {
+    let balancer = Balancer::new()
+        .endpoint("stable", Echo("stable".to_string()), 95)
+        .endpoint("canary", Echo("canary".to_string()), 5);
+
+    // Shift traffic from another thread
+    let handle = balancer.handle();
+    thread::spawn(move || {
+        assert!(handle.set_weight(&"canary", 95));
+        assert!(handle.set_weight(&"stable", 5));
+        assert!(!handle.set_weight(&"unknown", 5));
+    }).join().unwrap();
+
+    assert_eq!(Some(95), balancer.handle().weight(&"canary"));
+    assert_eq!((5, 95), spread(&balancer, 100));
+
+    // Draining an endpoint stops its traffic
+    balancer.handle().set_weight(&"canary", 0);
+    assert_eq!((10, 0), spread(&balancer, 10));
+
+    balancer.handle().set_weight(&"stable", 0);
+    let err = balancer.call(String::new()).wait().unwrap_err();
+    assert_eq!(io::ErrorKind::Other, err.kind());
+
+    assert_eq!(vec![("stable", 0), ("canary", 0)], balancer.handle().weights());
+}
+
+#[test]
+fn test_local_zone_preferred_with_spillover() {
+    let (a1, a1_fail) = Endpoint::new("a1");
+    let (a2, a2_fail) = Endpoint::new("a2");
+    let (b1, _) = Endpoint::new("b1");
+
+    let balancer = Balancer::new()
+        .local_zone("a")
+        .max_in_flight(1)
+        .eject_for(Duration::from_millis(50))
+        .endpoint_in_zone("a1", "a", a1, 1)
+        .endpoint_in_zone("a2", "a", a2, 1)
+        .endpoint_in_zone("b1", "b", b1, 10);
+
+    let call = || balancer.call(String::new()).wait();
+
+    // The other zone's larger weight does not matter while the local zone
+    // has capacity
+    let picks = (0..4).map(|_| call().unwrap()).collect::<Vec<_>>();
+    assert_eq!(vec!["a1", "a2", "a1", "a2"], picks);
+
+    // Saturated endpoints spill over to the other zone
+    let held_a1 = balancer.call(String::new());
+    let held_a2 = balancer.call(String::new());
+    assert_eq!("b1", call().unwrap());
+
+    drop(held_a1);
+    assert_eq!("a1", call().unwrap());
+
+    // Nothing is available anywhere, any endpoint is picked
+    let held_a1 = balancer.call(String::new());
+    let held_b1 = balancer.call(String::new());
+    assert!(call().is_ok());
+    drop((held_a1, held_a2, held_b1));
+
+    // Failing endpoints are avoided for a while
+    a1_fail.set(true);
+    a2_fail.set(true);
+    assert!(call().is_err());
+    assert!(call().is_err());
+    assert_eq!("b1", call().unwrap());
+    assert_eq!("b1", call().unwrap());
+
+    a1_fail.set(false);
+    a2_fail.set(false);
+    thread::sleep(Duration::from_millis(60));
+
+    let picks = (0..2).map(|_| call().unwrap()).collect::<Vec<_>>();
+    assert!(picks.iter().all(|p| p.starts_with('a')));
+}
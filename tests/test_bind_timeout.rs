@@ -0,0 +1,187 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::cell::Cell;
+use std::io;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::{Future, Stream};
+use tokio_core::io::{read_exact, read_to_end, write_all, Framed, Io};
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::{Core, Timeout};
+use tokio_proto::pipeline::{ClientProto, ServerProto, Pipeline};
+use tokio_proto::{BindClient, BindConfig, BindServer};
+use tokio_service::Service;
+
+mod support;
+use support::line::{LineCodec, LineProto, Echo};
+
+// Flags the end of binding once dropped
+struct Guard(Rc<Cell<bool>>);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.0.set(true);
+    }
+}
+
+// Waits for a `HI` line from the peer before framing the connection
+struct GreetedProto;
+
+impl GreetedProto {
+    fn bind<T: Io + 'static>(&self, io: T) -> Box<Future<Item = Framed<T, LineCodec>, Error = io::Error>> {
+        Box::new(read_exact(io, [0; 3]).and_then(|(io, buf)| {
+            if &buf != b"HI\n" {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "bad greeting"));
+            }
+
+            Ok(io.framed(LineCodec))
+        }))
+    }
+}
+
+impl<T: Io + 'static> ServerProto<T> for GreetedProto {
+    type Request = String;
+    type Response = String;
+    type Transport = Framed<T, LineCodec>;
+    type BindTransport = Box<Future<Item = Self::Transport, Error = io::Error>>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        self.bind(io)
+    }
+}
+
+impl<T: Io + 'static> ClientProto<T> for GreetedProto {
+    type Request = String;
+    type Response = String;
+    type Transport = Framed<T, LineCodec>;
+    type BindTransport = Box<Future<Item = Self::Transport, Error = io::Error>>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        self.bind(io)
+    }
+}
+
+#[test]
+fn test_server_closes_connection_stalled_in_handshake() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_handle = handle.clone();
+    let server = listener.incoming().for_each(move |(socket, _)| {
+        let timeout = Duration::from_millis(100);
+        GreetedProto.bind_server_timeout(&server_handle, socket, Echo("echo:".to_string()), timeout);
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    // Never greet the server, it hangs up once the timeout passes
+    let start = Instant::now();
+    let socket = core.run(TcpStream::connect(&addr, &handle)).unwrap();
+    let (_, buf) = core.run(read_to_end(socket, vec![])).unwrap();
+
+    assert!(buf.is_empty());
+    assert!(start.elapsed() >= Duration::from_millis(100));
+}
+
+#[test]
+fn test_client_fails_connection_stalled_in_handshake() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // Accept connections, but never greet the clients
+    let mut sockets = vec![];
+    let server = listener.incoming().for_each(move |(socket, _)| {
+        sockets.push(socket);
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    let socket = core.run(TcpStream::connect(&addr, &handle)).unwrap();
+    let client = GreetedProto.bind_client_timeout(&handle, socket, Duration::from_millis(100));
+    let errors = client.errors();
+
+    let err = core.run(client.call("hello".to_string())).unwrap_err();
+    assert_eq!(io::ErrorKind::BrokenPipe, err.kind());
+
+    let errors = core.run(errors.collect()).unwrap();
+    assert_eq!(1, errors.len());
+    assert_eq!(io::ErrorKind::TimedOut, errors[0].kind());
+}
+
+#[test]
+fn test_bound_connections_outlive_bind_timeout() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_handle = handle.clone();
+    let server = listener.incoming().for_each(move |(socket, _)| {
+        let timeout = Duration::from_millis(50);
+        GreetedProto.bind_server_timeout(&server_handle, socket, Echo("echo:".to_string()), timeout);
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    let socket = core.run(TcpStream::connect(&addr, &handle)).unwrap();
+    // Greet the server, then idle past the timeout
+    let socket = core.run(write_all(socket, b"HI\n")).unwrap().0;
+    let client = BindClient::<Pipeline, _>::bind_client(&LineProto, &handle, socket);
+
+    let sleep = Timeout::new(Duration::from_millis(150), &handle).unwrap();
+    core.run(sleep).unwrap();
+
+    let res = core.run(client.call("hello".to_string())).unwrap();
+    assert_eq!("echo:hello", res);
+}
+
+#[test]
+fn test_bind_guard_held_until_bound() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let released = Rc::new(Cell::new(false));
+
+    let server_handle = handle.clone();
+    let guard_released = released.clone();
+    let server = listener.incoming().for_each(move |(socket, _)| {
+        let guard = Guard(guard_released.clone());
+        GreetedProto.bind_server_guarded(&server_handle, socket, Echo("echo:".to_string()), &BindConfig::default(), guard);
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    let socket = core.run(TcpStream::connect(&addr, &handle)).unwrap();
+
+    // Still handshaking
+    let sleep = Timeout::new(Duration::from_millis(50), &handle).unwrap();
+    core.run(sleep).unwrap();
+    assert!(!released.get());
+
+    let socket = core.run(write_all(socket, b"HI\n")).unwrap().0;
+    let client = BindClient::<Pipeline, _>::bind_client(&LineProto, &handle, socket);
+
+    let res = core.run(client.call("hello".to_string())).unwrap();
+    assert_eq!("echo:hello", res);
+    assert!(released.get());
+}
@@ -0,0 +1,67 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::sync::Arc;
+
+use futures::future;
+use tokio_proto::streaming::{BufferPool, Message, PooledChunk};
+use tokio_proto::streaming::pipeline::Frame;
+
+mod support;
+use support::mock;
+use support::service::simple_service;
+
+#[test]
+fn test_dropped_chunks_are_recycled() {
+    let pool = Arc::new(BufferPool::new(1));
+
+    let chunk = PooledChunk::copy_from(b"hello", pool.clone());
+    assert_eq!(b"hello", chunk.as_ref());
+    assert_eq!(0, pool.pooled());
+
+    let ptr = chunk.as_ptr();
+    drop(chunk);
+    assert_eq!(1, pool.pooled());
+
+    // The next chunk reuses the buffer
+    let chunk = PooledChunk::copy_from(b"world", pool.clone());
+    assert_eq!(ptr, chunk.as_ptr());
+    assert_eq!(0, pool.pooled());
+
+    // Detached buffers are not recycled
+    let buf = chunk.into_vec();
+    assert_eq!(b"world", &buf[..]);
+    assert_eq!(0, pool.pooled());
+}
+
+#[test]
+fn test_pool_keeps_at_most_max_buffers() {
+    let pool = Arc::new(BufferPool::new(1));
+
+    let one = PooledChunk::copy_from(b"one", pool.clone());
+    let two = PooledChunk::copy_from(b"two", pool.clone());
+
+    drop(one);
+    drop(two);
+    assert_eq!(1, pool.pooled());
+}
+
+#[test]
+fn test_dispatcher_hands_provider_to_transport() {
+    let service = simple_service(|_| future::ok(Message::WithoutBody("pong")));
+
+    let (mut mock, _other) = mock::pipeline_server(service);
+    mock.send(Frame::Message { message: "ping", body: false });
+    assert_eq!("pong", mock.next_write().unwrap_msg());
+
+    let provider = mock.buffer_provider();
+    let buf = provider.take(16);
+    assert!(buf.capacity() >= 16);
+    assert!(buf.is_empty());
+
+    mock.allow_and_assert_drop();
+}
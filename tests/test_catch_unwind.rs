@@ -0,0 +1,97 @@
+#![cfg(feature = "catch-unwind")]
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+extern crate rand;
+
+extern crate log;
+extern crate env_logger;
+
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::future::{self, FutureResult};
+use tokio_core::io::write_all;
+use tokio_core::reactor::Core;
+use tokio_proto::{conformance, BindConfig, BindServer, Panic};
+use tokio_proto::instrument::ConnectionObserver;
+use tokio_proto::streaming::pipeline::Frame;
+use tokio_proto::streaming::{ConnectionId, Message, Body};
+use tokio_service::Service;
+
+mod support;
+use support::line::LineProto;
+use support::service::simple_service;
+use support::mock;
+
+// Records the panic messages of the errors connections fail with
+#[derive(Default)]
+struct Errors(Mutex<Vec<Option<String>>>);
+
+impl ConnectionObserver for Errors {
+    fn on_error(&self, _: ConnectionId, error: &io::Error) {
+        let panic = Panic::from_error(error).map(|panic| panic.message().to_string());
+        self.0.lock().unwrap().push(panic);
+    }
+}
+
+struct Panicking;
+
+impl Service for Panicking {
+    type Request = String;
+    type Response = String;
+    type Error = io::Error;
+    type Future = FutureResult<String, io::Error>;
+
+    fn call(&self, _: String) -> Self::Future {
+        panic!("boom");
+    }
+}
+
+#[test]
+fn test_service_panic_closes_connection() {
+    let service = simple_service(|_: Message<&'static str, Body<u32, io::Error>>| {
+        if true {
+            panic!("boom");
+        }
+
+        future::ok(Message::WithoutBody("pong"))
+    });
+
+    let (mut mock, _other) = mock::pipeline_server(service);
+    mock.send(msg("ping"));
+
+    // The connection is torn down, but the reactor thread keeps running and
+    // shuts down cleanly when `_other` is dropped.
+    mock.allow_and_assert_drop();
+}
+
+fn msg(msg: &'static str) -> Frame<&'static str, u32, io::Error> {
+    Frame::Message { message: msg, body: false }
+}
+
+#[test]
+fn test_service_panic_reported_to_observer() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (client, server) = conformance::pipe();
+
+    let errors = Arc::new(Errors::default());
+    let config = BindConfig { observer: Some(errors.clone()), ..BindConfig::default() };
+    LineProto.bind_server_guarded(&handle, server, Panicking, &config, ());
+
+    let _client = core.run(write_all(client, b"ping\n")).unwrap();
+
+    let deadline = Instant::now() + support::DEADLINE;
+
+    while errors.0.lock().unwrap().is_empty() {
+        assert!(Instant::now() < deadline, "connection error not observed");
+        core.turn(Some(Duration::from_millis(10)));
+    }
+
+    assert_eq!(vec![Some("boom".to_string())], *errors.0.lock().unwrap());
+}
@@ -0,0 +1,68 @@
+#![allow(deprecated)]
+
+#[cfg(feature = "bytes")]
+extern crate bytes;
+extern crate tokio_core;
+extern crate tokio_proto;
+
+use std::io;
+
+use tokio_core::io::EasyBuf;
+use tokio_proto::streaming::{multiplex, pipeline};
+
+type PipeFrame<B> = pipeline::Frame<(), B, io::Error>;
+type MuxFrame<B> = multiplex::Frame<u64, (), B, io::Error>;
+
+#[test]
+fn test_easy_buf_chunks_split_without_copying() {
+    let chunk = EasyBuf::from(b"hello world".to_vec());
+    let base = chunk.as_slice().as_ptr() as usize;
+
+    let mut frame: PipeFrame<EasyBuf> = pipeline::Frame::Body { chunk: Some(chunk) };
+    let rest = frame.split_chunk(5).unwrap().unwrap_body().unwrap();
+
+    assert_eq!(b"hello", frame.unwrap_body().unwrap().as_slice());
+    assert_eq!(b" world", rest.as_slice());
+
+    // Still pointing into the buffer the chunk was read into
+    assert_eq!(base + 5, rest.as_slice().as_ptr() as usize);
+}
+
+#[test]
+fn test_short_chunks_and_other_frames_are_not_split() {
+    let mut frame: MuxFrame<Vec<u8>> = multiplex::Frame::Body { id: 3, chunk: Some(b"hello".to_vec()) };
+    assert!(frame.split_chunk(5).is_none());
+
+    let mut frame: MuxFrame<Vec<u8>> = multiplex::Frame::Body { id: 3, chunk: None };
+    assert!(frame.split_chunk(5).is_none());
+
+    let mut frame: MuxFrame<Vec<u8>> = multiplex::Frame::Message { id: 3, message: (), body: true, solo: false };
+    assert!(frame.split_chunk(5).is_none());
+}
+
+#[test]
+fn test_multiplex_split_keeps_request_id() {
+    let mut frame: MuxFrame<Vec<u8>> = multiplex::Frame::Body { id: 7, chunk: Some(b"hello world".to_vec()) };
+
+    match frame.split_chunk(5) {
+        Some(multiplex::Frame::Body { id: 7, chunk: Some(ref rest) }) => assert_eq!(b" world", &rest[..]),
+        _ => panic!("unexpected split"),
+    }
+
+    assert_eq!(b"hello", &frame.unwrap_body().unwrap()[..]);
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn test_bytes_chunks_split_without_copying() {
+    use bytes::Bytes;
+
+    let chunk = Bytes::from(b"hello world".to_vec());
+    let base = chunk.as_ptr() as usize;
+
+    let mut frame: PipeFrame<Bytes> = pipeline::Frame::Body { chunk: Some(chunk) };
+    let rest = frame.split_chunk(5).unwrap().unwrap_body().unwrap();
+
+    assert_eq!(&b" world"[..], &rest[..]);
+    assert_eq!(base + 5, rest.as_ptr() as usize);
+}
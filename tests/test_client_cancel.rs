@@ -0,0 +1,108 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io::{self, BufRead, BufReader};
+use std::net;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::{AsyncSink, Sink};
+use tokio_core::io::{Framed, Io};
+use tokio_core::reactor::Core;
+use tokio_proto::TcpClient;
+use tokio_proto::multiplex::ClientProto;
+use tokio_proto::streaming::multiplex::Counter;
+use tokio_service::Service;
+
+mod support;
+use support::line::MuxLineCodec;
+
+// Writes a `CANCEL` message for the exchanges the caller lost interest in
+struct CancelingProto;
+
+impl<T: Io + 'static> ClientProto<T> for CancelingProto {
+    type Request = String;
+    type Response = String;
+    type RequestId = u64;
+    type Error = io::Error;
+    type Transport = Framed<T, MuxLineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+    type RequestIdSource = Counter;
+
+    fn requestid_source(&self) -> Counter {
+        Counter::new()
+    }
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(MuxLineCodec))
+    }
+
+    fn cancel(transport: &mut Self::Transport, request_id: u64) -> io::Result<()> {
+        match try!(transport.start_send((request_id, "CANCEL".to_string()))) {
+            AsyncSink::Ready => Ok(()),
+            AsyncSink::NotReady(_) => Err(io::Error::new(io::ErrorKind::Other, "transport full")),
+        }
+    }
+}
+
+// Accepts a single connection, forwarding the lines it receives without
+// ever responding
+fn serve_silently() -> (net::SocketAddr, mpsc::Receiver<String>) {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let (socket, _) = listener.accept().unwrap();
+
+        for line in BufReader::new(socket).lines() {
+            if tx.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+
+    (addr, rx)
+}
+
+// Drives the connection until the server received another line
+fn next_line(core: &mut Core, lines: &mpsc::Receiver<String>) -> String {
+    let deadline = Instant::now() + support::DEADLINE;
+
+    while Instant::now() < deadline {
+        if let Ok(line) = lines.try_recv() {
+            return line;
+        }
+
+        core.turn(Some(Duration::from_millis(10)));
+    }
+
+    panic!("no line received from the client");
+}
+
+#[test]
+fn test_dropped_response_future_cancels_on_the_wire() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let (addr, lines) = serve_silently();
+    let client = core.run(TcpClient::new(CancelingProto).connect(&addr, &handle)).unwrap();
+
+    let response = client.call("slow".to_string());
+
+    // The request is on the wire
+    assert_eq!("0 slow", next_line(&mut core, &lines));
+
+    drop(response);
+
+    assert_eq!("0 CANCEL", next_line(&mut core, &lines));
+
+    // The connection is still usable
+    let _next = client.call("next".to_string());
+    assert_eq!("1 next", next_line(&mut core, &lines));
+}
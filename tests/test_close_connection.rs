@@ -0,0 +1,140 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io::{self, Read, Write};
+use std::net::{self, SocketAddr};
+use std::sync::mpsc;
+use std::thread;
+
+use futures::{Future, Stream};
+use futures::future::{self, FutureResult};
+use futures::sync::oneshot;
+use tokio_core::io::{Framed, Io};
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::{Core, Handle};
+use tokio_proto::BindServer;
+use tokio_proto::multiplex;
+use tokio_proto::pipeline;
+use tokio_service::Service;
+
+mod support;
+use support::line::{LineCodec, MuxLineCodec};
+
+// Line protocols ending the connection with a `bye` response
+struct QuitProto;
+
+impl<T: Io + 'static> pipeline::ServerProto<T> for QuitProto {
+    type Request = String;
+    type Response = String;
+    type Transport = Framed<T, LineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(LineCodec))
+    }
+
+    fn closes_connection(response: &String) -> bool {
+        response == "bye"
+    }
+}
+
+impl<T: Io + 'static> multiplex::ServerProto<T> for QuitProto {
+    type Request = String;
+    type Response = String;
+    type RequestId = u64;
+    type Error = io::Error;
+    type Transport = Framed<T, MuxLineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(MuxLineCodec))
+    }
+
+    fn closes_connection(response: &String) -> bool {
+        response == "bye"
+    }
+}
+
+// Echoes requests, answering `quit` with `bye`
+struct Quit;
+
+impl Service for Quit {
+    type Request = String;
+    type Response = String;
+    type Error = io::Error;
+    type Future = FutureResult<String, io::Error>;
+
+    fn call(&self, req: String) -> Self::Future {
+        if req == "quit" {
+            future::ok("bye".to_string())
+        } else {
+            future::ok(req)
+        }
+    }
+}
+
+// Serves every connection with `bind` until `stop` completes
+fn serve<F>(bind: F) -> (SocketAddr, oneshot::Sender<()>, thread::JoinHandle<()>)
+    where F: Fn(&Handle, TcpStream) + Send + 'static,
+{
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+
+    let t = thread::spawn(move || {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let listener = TcpListener::bind(&addr, &handle).unwrap();
+        addr_tx.send(listener.local_addr().unwrap()).unwrap();
+
+        let server = listener.incoming().for_each(move |(socket, _)| {
+            bind(&handle, socket);
+            Ok(())
+        });
+
+        drop(core.run(server.select(stop_rx.then(|_| Ok(()))).map_err(|_| ())));
+    });
+
+    (addr_rx.recv().unwrap(), stop_tx, t)
+}
+
+// Writes `requests` at once and reads until the server closes the connection
+fn exchange(addr: SocketAddr, requests: &str) -> String {
+    let mut conn = net::TcpStream::connect(addr).unwrap();
+    conn.set_read_timeout(Some(support::DEADLINE)).unwrap();
+    conn.write_all(requests.as_bytes()).unwrap();
+
+    let mut responses = String::new();
+    conn.read_to_string(&mut responses).unwrap();
+    responses
+}
+
+#[test]
+fn test_pipeline_response_closes_connection() {
+    let (addr, stop, t) = serve(|handle, socket| {
+        BindServer::<pipeline::Pipeline, _>::bind_server(&QuitProto, handle, socket, Quit);
+    });
+
+    // The request pipelined after `quit` is dropped
+    assert_eq!("hello\nbye\n", exchange(addr, "hello\nquit\nagain\n"));
+
+    stop.complete(());
+    t.join().unwrap();
+}
+
+#[test]
+fn test_multiplex_response_closes_connection() {
+    let (addr, stop, t) = serve(|handle, socket| {
+        BindServer::<multiplex::Multiplex, _>::bind_server(&QuitProto, handle, socket, Quit);
+    });
+
+    assert_eq!("1 hello\n2 bye\n", exchange(addr, "1 hello\n2 quit\n"));
+
+    stop.complete(());
+    t.join().unwrap();
+}
@@ -0,0 +1,102 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+use futures::Future;
+use futures::sync::oneshot;
+use tokio_proto::Coalesce;
+use tokio_service::Service;
+
+// Answers requests once completed by the test
+struct Backend {
+    pending: Rc<RefCell<Vec<(String, oneshot::Sender<String>)>>>,
+}
+
+impl Service for Backend {
+    type Request = String;
+    type Response = String;
+    type Error = io::Error;
+    type Future = Box<Future<Item = String, Error = io::Error>>;
+
+    fn call(&self, req: String) -> Self::Future {
+        let (tx, rx) = oneshot::channel();
+        self.pending.borrow_mut().push((req, tx));
+
+        Box::new(rx.map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "request dropped")))
+    }
+}
+
+// Coalesces reads, keyed by what they read
+fn read_key(req: &str) -> Option<String> {
+    if req.starts_with("get ") {
+        Some(req.to_string())
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_identical_requests_share_exchange() {
+    let pending = Rc::new(RefCell::new(vec![]));
+    let client = Coalesce::new(Backend { pending: pending.clone() }, |req: &String| read_key(req));
+
+    let a = client.call("get a".to_string());
+    let b = client.call("get a".to_string());
+    let c = client.call("get a".to_string());
+    let other = client.call("get b".to_string());
+
+    // Writes are always sent
+    let w1 = client.call("set a".to_string());
+    let w2 = client.call("set a".to_string());
+
+    let sent: Vec<String> = pending.borrow().iter().map(|p| p.0.clone()).collect();
+    assert_eq!(vec!["get a", "get b", "set a", "set a"], sent);
+    assert_eq!(2, client.coalesced());
+    assert_eq!(2, client.in_flight());
+
+    for (req, tx) in pending.borrow_mut().drain(..) {
+        tx.complete(format!("{}!", req));
+    }
+
+    assert_eq!("get a!", a.wait().unwrap());
+    assert_eq!("get a!", b.wait().unwrap());
+    assert_eq!("get a!", c.wait().unwrap());
+    assert_eq!("get b!", other.wait().unwrap());
+    assert_eq!("set a!", w1.wait().unwrap());
+    assert_eq!("set a!", w2.wait().unwrap());
+
+    // Responses are not cached
+    assert_eq!(0, client.in_flight());
+
+    drop(client.call("get a".to_string()));
+    assert_eq!(1, pending.borrow().len());
+}
+
+#[test]
+fn test_errors_and_dropped_callers_release_exchange() {
+    let pending = Rc::new(RefCell::new(vec![]));
+    let client = Coalesce::new(Backend { pending: pending.clone() }, |req: &String| read_key(req));
+
+    let a = client.call("get a".to_string());
+    let b = client.call("get a".to_string());
+
+    // Failing the exchange fails every caller
+    pending.borrow_mut().clear();
+
+    assert_eq!(io::ErrorKind::BrokenPipe, a.wait().unwrap_err().kind());
+    assert_eq!(io::ErrorKind::BrokenPipe, b.wait().unwrap_err().kind());
+    assert_eq!(0, client.in_flight());
+
+    // Once every caller gave up, the next request is sent again
+    drop(client.call("get a".to_string()));
+    drop(client.call("get a".to_string()));
+
+    assert_eq!(2, pending.borrow().len());
+    assert_eq!(0, client.in_flight());
+}
@@ -0,0 +1,115 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::Future;
+use futures::future::Either;
+use futures::sync::oneshot;
+use tokio_core::io::{Framed, Io};
+use tokio_core::reactor::{Core, Timeout};
+use tokio_proto::{conformance, BindClient, BindServer};
+use tokio_proto::multiplex::{CommitFuture, Committer, Multiplex, MultiplexConfig, ServerProto};
+use tokio_service::Service;
+
+mod support;
+use support::line::{Echo, MuxLineCodec, MuxLineProto};
+
+type Commits = Rc<RefCell<VecDeque<oneshot::Receiver<()>>>>;
+
+// Multiplexed line protocol whose responses wait for the next of the given
+// commits, answering failed requests with an `error` line
+struct DurableProto {
+    commits: Commits,
+    commit_timeout: Option<Duration>,
+}
+
+impl<T: Io + 'static> ServerProto<T> for DurableProto {
+    type Request = String;
+    type Response = String;
+    type RequestId = u64;
+    type Error = io::Error;
+    type Transport = Framed<T, MuxLineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(MuxLineCodec))
+    }
+
+    fn config(&self) -> MultiplexConfig {
+        MultiplexConfig {
+            commit_timeout: self.commit_timeout,
+            ..MultiplexConfig::default()
+        }
+    }
+
+    fn committer(&self) -> Option<Box<Committer<String>>> {
+        let commits = self.commits.clone();
+
+        Some(Box::new(move |_: &String| {
+            let commit = commits.borrow_mut().pop_front().unwrap();
+            let commit = commit.map_err(|_| io::Error::new(io::ErrorKind::Other, "commit aborted"));
+            Some(Box::new(commit) as CommitFuture)
+        }))
+    }
+
+    fn error_response(error: io::Error) -> io::Result<String> {
+        Ok(format!("error: {}", error))
+    }
+}
+
+fn durable(commit_timeout: Option<Duration>) -> (DurableProto, oneshot::Sender<()>) {
+    let (tx, rx) = oneshot::channel();
+
+    let proto = DurableProto {
+        commits: Rc::new(RefCell::new(vec![rx].into_iter().collect())),
+        commit_timeout: commit_timeout,
+    };
+
+    (proto, tx)
+}
+
+#[test]
+fn test_response_waits_for_commit() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (client, server) = conformance::pipe();
+
+    let (proto, commit) = durable(None);
+    BindServer::<Multiplex, _>::bind_server(&proto, &handle, server, Echo("echo:".to_string()));
+    let service = BindClient::<Multiplex, _>::bind_client(&MuxLineProto, &handle, client);
+
+    let pong = service.call("ping".to_string());
+
+    // Not written until committed
+    let wait = Timeout::new(Duration::from_millis(50), &handle).unwrap();
+    let pong = match core.run(wait.select2(pong)) {
+        Ok(Either::A((_, pong))) => pong,
+        _ => panic!("response written before its commit"),
+    };
+
+    commit.complete(());
+    assert_eq!("echo:ping", core.run(pong).unwrap());
+}
+
+#[test]
+fn test_commit_timeout_fails_request() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (client, server) = conformance::pipe();
+
+    let (proto, _commit) = durable(Some(Duration::from_millis(20)));
+    BindServer::<Multiplex, _>::bind_server(&proto, &handle, server, Echo("echo:".to_string()));
+    let service = BindClient::<Multiplex, _>::bind_client(&MuxLineProto, &handle, client);
+
+    let res = core.run(service.call("ping".to_string())).unwrap();
+    assert_eq!("error: response commit timed out", res);
+}
@@ -0,0 +1,75 @@
+#![cfg(feature = "serde")]
+
+extern crate serde;
+extern crate tokio_proto;
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde::de::IntoDeserializer;
+use serde::de::value::Error;
+use tokio_proto::streaming::BufferProfile;
+use tokio_proto::streaming::multiplex::{MultiplexConfig, SpillPolicy};
+use tokio_proto::streaming::pipeline::PipelineConfig;
+
+#[test]
+fn test_deserialize_multiplex_config() {
+    let mut map = HashMap::new();
+    map.insert("max_in_flight", 8usize);
+
+    let config = MultiplexConfig::deserialize(map.into_deserializer())
+        .map_err(|e: Error| e)
+        .unwrap();
+
+    assert_eq!(8, config.max_in_flight);
+
+    // Missing fields use the defaults
+    assert_eq!(MultiplexConfig::default().max_buffered_frames,
+               config.max_buffered_frames);
+}
+
+#[test]
+fn test_deserialize_spill_policy() {
+    let policy = SpillPolicy::deserialize("error-exchange".into_deserializer())
+        .map_err(|e: Error| e)
+        .unwrap();
+
+    assert_eq!(SpillPolicy::ErrorExchange, policy);
+    assert_eq!(SpillPolicy::Block, MultiplexConfig::default().spill_policy);
+}
+
+#[test]
+fn test_deserialize_pipeline_config() {
+    let mut map = HashMap::new();
+    map.insert("in_flight_capacity", 4usize);
+
+    let config = PipelineConfig::deserialize(map.into_deserializer())
+        .map_err(|e: Error| e)
+        .unwrap();
+
+    assert_eq!(4, config.in_flight_capacity);
+}
+
+#[test]
+fn test_deserialize_buffer_profile() {
+    let profile = BufferProfile::deserialize("low-memory".into_deserializer())
+        .map_err(|e: Error| e)
+        .unwrap();
+
+    assert_eq!(BufferProfile::LowMemory, profile);
+    assert_eq!(Ok(profile), profile.to_string().parse());
+    assert!("huge".parse::<BufferProfile>().is_err());
+}
+
+#[test]
+fn test_buffer_profiles_keep_other_defaults() {
+    let config = BufferProfile::LowMemory.multiplex_config();
+
+    assert!(config.max_buffered_frames < MultiplexConfig::default().max_buffered_frames);
+    assert_eq!(MultiplexConfig::default().max_in_flight, config.max_in_flight);
+
+    let config = BufferProfile::Throughput.pipeline_config();
+
+    assert!(config.in_flight_capacity > PipelineConfig::default().in_flight_capacity);
+    assert_eq!(PipelineConfig::default().max_error_frames, config.max_error_frames);
+}
@@ -0,0 +1,333 @@
+#![allow(deprecated)]
+
+extern crate tokio_core;
+extern crate tokio_proto;
+
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use tokio_core::io::{Codec, EasyBuf, Framed, Io};
+use tokio_proto::conformance::{self, Outcome};
+use tokio_proto::conformance::multiplex::{self, Fixture};
+use tokio_proto::conformance::pipeline;
+use tokio_proto::streaming::multiplex::{ClientProto, Counter, Frame, ServerProto};
+use tokio_proto::streaming::pipeline as streaming_pipeline;
+
+mod support;
+use support::line::LineCodec;
+
+type LineFrame = Frame<u64, String, String, io::Error>;
+
+// Frames as `id kind text` lines. A lossy codec writes error frames as
+// empty messages.
+struct FrameCodec {
+    lossy: bool,
+}
+
+impl Codec for FrameCodec {
+    type In = LineFrame;
+    type Out = LineFrame;
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<LineFrame>> {
+        let line = match try!(LineCodec.decode(buf)) {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+
+        let mut parts = line.splitn(3, ' ');
+        let id = parts.next().unwrap().parse().unwrap();
+        let kind = parts.next().unwrap();
+        let text = parts.next().unwrap_or("").to_string();
+
+        let frame = match kind {
+            "m" => Frame::Message { id: id, message: text, body: false, solo: false },
+            "M" => Frame::Message { id: id, message: text, body: true, solo: false },
+            "b" => Frame::Body { id: id, chunk: Some(text) },
+            "e" => Frame::Body { id: id, chunk: None },
+            "x" => Frame::Error { id: id, error: io::Error::new(io::ErrorKind::Other, text) },
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unknown frame")),
+        };
+
+        Ok(Some(frame))
+    }
+
+    fn encode(&mut self, frame: LineFrame, buf: &mut Vec<u8>) -> io::Result<()> {
+        let line = match frame {
+            Frame::Message { id, message, body: false, .. } => format!("{} m {}", id, message),
+            Frame::Message { id, message, body: true, .. } => format!("{} M {}", id, message),
+            Frame::Body { id, chunk: Some(chunk) } => format!("{} b {}", id, chunk),
+            Frame::Body { id, chunk: None } => format!("{} e", id),
+            Frame::Error { id, .. } if self.lossy => format!("{} m ", id),
+            Frame::Error { id, error } => format!("{} x {}", id, error),
+            _ => return Err(io::Error::new(io::ErrorKind::Other, "unsupported frame")),
+        };
+
+        LineCodec.encode(line, buf)
+    }
+}
+
+struct LineStreamProto {
+    lossy: bool,
+}
+
+impl<T: Io + 'static> ClientProto<T> for LineStreamProto {
+    type Request = String;
+    type RequestBody = String;
+    type Response = String;
+    type ResponseBody = String;
+    type RequestId = u64;
+    type Error = io::Error;
+    type Transport = Framed<T, FrameCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+    type RequestIdSource = Counter;
+
+    fn requestid_source(&self) -> Counter {
+        Counter::new()
+    }
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(FrameCodec { lossy: self.lossy }))
+    }
+}
+
+impl<T: Io + 'static> ServerProto<T> for LineStreamProto {
+    type Request = String;
+    type RequestBody = String;
+    type Response = String;
+    type ResponseBody = String;
+    type RequestId = u64;
+    type Error = io::Error;
+    type Transport = Framed<T, FrameCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(FrameCodec { lossy: self.lossy }))
+    }
+}
+
+struct Lines {
+    lossy: bool,
+    large_ids: bool,
+}
+
+impl Fixture for Lines {
+    type Client = LineStreamProto;
+    type Server = LineStreamProto;
+
+    fn client(&self) -> LineStreamProto {
+        LineStreamProto { lossy: self.lossy }
+    }
+
+    fn server(&self) -> LineStreamProto {
+        LineStreamProto { lossy: self.lossy }
+    }
+
+    fn request(&self, n: u64) -> String {
+        format!("req{}", n)
+    }
+
+    fn request_chunk(&self, n: u64) -> String {
+        format!("chunk{}", n)
+    }
+
+    fn respond(&self, request: String) -> String {
+        format!("re:{}", request)
+    }
+
+    fn echo_chunk(&self, chunk: String) -> String {
+        format!("echo:{}", chunk)
+    }
+
+    fn check_response(&self, n: u64, response: &String) -> bool {
+        *response == format!("re:req{}", n)
+    }
+
+    fn check_chunk(&self, n: u64, chunk: &String) -> bool {
+        *chunk == format!("echo:chunk{}", n)
+    }
+
+    fn large_id(&self, n: u64) -> Option<u64> {
+        if self.large_ids {
+            Some(u64::MAX - n)
+        } else {
+            None
+        }
+    }
+
+    fn keepalive_idle(&self) -> Option<Duration> {
+        Some(Duration::from_millis(20))
+    }
+}
+
+type PipeFrame = streaming_pipeline::Frame<String, String, io::Error>;
+
+// Frames as `kind text` lines, the pipelined flavor of `FrameCodec`
+struct PipeFrameCodec {
+    lossy: bool,
+}
+
+impl Codec for PipeFrameCodec {
+    type In = PipeFrame;
+    type Out = PipeFrame;
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<PipeFrame>> {
+        let line = match try!(LineCodec.decode(buf)) {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+
+        let mut parts = line.splitn(2, ' ');
+        let kind = parts.next().unwrap();
+        let text = parts.next().unwrap_or("").to_string();
+
+        let frame = match kind {
+            "m" => streaming_pipeline::Frame::Message { message: text, body: false },
+            "M" => streaming_pipeline::Frame::Message { message: text, body: true },
+            "b" => streaming_pipeline::Frame::Body { chunk: Some(text) },
+            "e" => streaming_pipeline::Frame::Body { chunk: None },
+            "x" => streaming_pipeline::Frame::Error { error: io::Error::new(io::ErrorKind::Other, text) },
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unknown frame")),
+        };
+
+        Ok(Some(frame))
+    }
+
+    fn encode(&mut self, frame: PipeFrame, buf: &mut Vec<u8>) -> io::Result<()> {
+        let line = match frame {
+            streaming_pipeline::Frame::Message { message, body: false } => format!("m {}", message),
+            streaming_pipeline::Frame::Message { message, body: true } => format!("M {}", message),
+            streaming_pipeline::Frame::Body { chunk: Some(chunk) } => format!("b {}", chunk),
+            streaming_pipeline::Frame::Body { chunk: None } => "e".to_string(),
+            streaming_pipeline::Frame::Error { .. } if self.lossy => "m ".to_string(),
+            streaming_pipeline::Frame::Error { error } => format!("x {}", error),
+            streaming_pipeline::Frame::Progress { message } => format!("p {}", message),
+            streaming_pipeline::Frame::Inline { message, chunk } => format!("i {} {}", message, chunk),
+        };
+
+        LineCodec.encode(line, buf)
+    }
+}
+
+struct LinePipeProto {
+    lossy: bool,
+}
+
+impl<T: Io + 'static> streaming_pipeline::ClientProto<T> for LinePipeProto {
+    type Request = String;
+    type RequestBody = String;
+    type Response = String;
+    type ResponseBody = String;
+    type Error = io::Error;
+    type Transport = Framed<T, PipeFrameCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(PipeFrameCodec { lossy: self.lossy }))
+    }
+}
+
+impl<T: Io + 'static> streaming_pipeline::ServerProto<T> for LinePipeProto {
+    type Request = String;
+    type RequestBody = String;
+    type Response = String;
+    type ResponseBody = String;
+    type Error = io::Error;
+    type Transport = Framed<T, PipeFrameCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(PipeFrameCodec { lossy: self.lossy }))
+    }
+}
+
+struct PipeLines {
+    lossy: bool,
+}
+
+impl pipeline::Fixture for PipeLines {
+    type Client = LinePipeProto;
+    type Server = LinePipeProto;
+
+    fn client(&self) -> LinePipeProto {
+        LinePipeProto { lossy: self.lossy }
+    }
+
+    fn server(&self) -> LinePipeProto {
+        LinePipeProto { lossy: self.lossy }
+    }
+
+    fn request(&self, n: u64) -> String {
+        format!("req{}", n)
+    }
+
+    fn request_chunk(&self, n: u64) -> String {
+        format!("chunk{}", n)
+    }
+
+    fn respond(&self, request: String) -> String {
+        format!("re:{}", request)
+    }
+
+    fn echo_chunk(&self, chunk: String) -> String {
+        format!("echo:{}", chunk)
+    }
+
+    fn check_response(&self, n: u64, response: &String) -> bool {
+        *response == format!("re:req{}", n)
+    }
+
+    fn check_chunk(&self, n: u64, chunk: &String) -> bool {
+        *chunk == format!("echo:chunk{}", n)
+    }
+}
+
+#[test]
+fn test_conforming_protocol_passes() {
+    let report = multiplex::run(Lines { lossy: false, large_ids: true });
+
+    assert!(report.is_ok(), "{}", report);
+    assert_eq!(6, report.cases().len());
+    assert!(report.cases().iter().all(|case| case.1 == Outcome::Passed), "{}", report);
+}
+
+#[test]
+fn test_failures_reported() {
+    let report = multiplex::run(Lines { lossy: true, large_ids: false });
+
+    assert!(!report.is_ok());
+    assert_eq!(vec!["error frames"],
+               report.failures().iter().map(|f| f.0).collect::<Vec<_>>());
+    assert_eq!(Some(&Outcome::Skipped), report.outcome("large ids"));
+    assert_eq!(Some(&Outcome::Passed), report.outcome("interleaved bodies"));
+    assert!(format!("{}", report).contains("error frames: FAILED"));
+}
+
+#[test]
+fn test_conforming_pipeline_protocol_passes() {
+    let report = pipeline::run(PipeLines { lossy: false });
+
+    assert!(report.is_ok(), "{}", report);
+    assert_eq!(5, report.cases().len());
+}
+
+#[test]
+fn test_pipeline_failures_reported() {
+    let report = pipeline::run(PipeLines { lossy: true });
+
+    assert_eq!(vec!["error frames"],
+               report.failures().iter().map(|f| f.0).collect::<Vec<_>>());
+    assert_eq!(Some(&Outcome::Passed), report.outcome("ordering"));
+}
+
+#[test]
+fn test_pipe_closes() {
+    let (mut a, mut b) = conformance::pipe();
+    a.write_all(b"hi").unwrap();
+
+    let mut buf = [0; 8];
+    assert_eq!(2, b.read(&mut buf).unwrap());
+
+    drop(a);
+    assert_eq!(0, b.read(&mut buf).unwrap());
+    assert_eq!(io::ErrorKind::BrokenPipe, b.write(b"x").unwrap_err().kind());
+}
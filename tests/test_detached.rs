@@ -0,0 +1,53 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::net::SocketAddr;
+
+use futures::{Future, Stream};
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::{Core, Handle};
+use tokio_proto::{BindClient, BindServer};
+use tokio_service::Service;
+
+mod support;
+use support::line::{MuxLineProto, Echo};
+
+fn serve(handle: &Handle) -> SocketAddr {
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_handle = handle.clone();
+    let server = listener.incoming().for_each(move |(socket, _)| {
+        MuxLineProto.bind_server(&server_handle, socket, Echo(String::new()));
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    addr
+}
+
+#[test]
+fn test_detached_results_are_collected() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = serve(&handle);
+
+    let socket = core.run(TcpStream::connect(&addr, &handle)).unwrap();
+    let client = MuxLineProto.bind_client(&handle, socket);
+    let detached = client.detached();
+
+    client.call("one".to_string()).detach();
+
+    // Calls that are not detached are not collected
+    let two = core.run(client.call("two".to_string())).unwrap();
+    assert_eq!("two", two);
+
+    let (one, _) = core.run(detached.into_future()).ok().unwrap();
+    assert_eq!("one", one.unwrap().unwrap());
+}
@@ -0,0 +1,46 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net;
+use std::thread;
+
+use tokio_proto::TcpServer;
+
+mod support;
+use support::line::{LineProto, Echo};
+
+#[test]
+fn test_capped_connections_answer_everything() {
+    let addr = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut server = TcpServer::new(LineProto, addr);
+        server.max_frames_per_poll(1);
+        server.serve(|| Ok(Echo("echo:".to_string())));
+    });
+
+    // A chatty connection pipelining many requests at once...
+    let mut chatty = support::connect(&addr);
+    let requests = (0..200).map(|i| format!("{}\n", i)).collect::<String>();
+    chatty.write_all(requests.as_bytes()).unwrap();
+
+    // ...and a quiet one sharing its event loop
+    let mut quiet = support::connect(&addr);
+    quiet.write_all(b"hello\n").unwrap();
+
+    let mut line = String::new();
+    BufReader::new(quiet).read_line(&mut line).unwrap();
+    assert_eq!("echo:hello\n", line);
+
+    let mut reader = BufReader::new(chatty);
+    for i in 0..200 {
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(format!("echo:{}\n", i), line);
+    }
+}
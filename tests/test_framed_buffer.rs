@@ -0,0 +1,78 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io;
+
+use futures::{Future, Stream};
+use tokio_core::io::{read_exact, write_all, Framed, Io};
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Core;
+use tokio_proto::pipeline::{ClientProto, ServerProto};
+use tokio_proto::util::framed::{framed_with_buffer, Rewind};
+use tokio_proto::{BindServer, TcpClient};
+use tokio_service::Service;
+
+mod support;
+use support::line::{LineCodec, Echo};
+
+// Opens each connection with a `HI` line
+struct HelloProto;
+
+impl<T: Io + 'static> ServerProto<T> for HelloProto {
+    type Request = String;
+    type Response = String;
+    type Transport = Framed<Rewind<T>, LineCodec>;
+    type BindTransport = Box<Future<Item = Self::Transport, Error = io::Error>>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        // Reads one byte past the greeting, into the first request
+        let handshake = read_exact(io, [0; 4]).and_then(|(io, buf)| {
+            if &buf[..3] != b"HI\n" {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "bad greeting"));
+            }
+
+            Ok(framed_with_buffer(io, LineCodec, buf[3..].to_vec()))
+        });
+
+        Box::new(handshake)
+    }
+}
+
+impl<T: Io + 'static> ClientProto<T> for HelloProto {
+    type Request = String;
+    type Response = String;
+    type Transport = Framed<T, LineCodec>;
+    type BindTransport = Box<Future<Item = Self::Transport, Error = io::Error>>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Box::new(write_all(io, b"HI\n").map(|(io, _)| io.framed(LineCodec)))
+    }
+}
+
+#[test]
+fn test_handshake_leftovers_are_decoded() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_handle = handle.clone();
+    let server = listener.incoming().for_each(move |(socket, _)| {
+        HelloProto.bind_server(&server_handle, socket, Echo("echo:".to_string()));
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    let client = core.run(TcpClient::new(HelloProto).connect(&addr, &handle)).unwrap();
+
+    for req in &["ping", "pong"] {
+        let res = core.run(client.call(req.to_string())).unwrap();
+        assert_eq!(format!("echo:{}", req), res);
+    }
+}
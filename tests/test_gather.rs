@@ -0,0 +1,90 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use futures::{Future, Stream};
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Core;
+use tokio_proto::{BindServer, TcpClient};
+use tokio_proto::multiplex::gather;
+
+mod support;
+use support::line::{MuxLineProto, Echo};
+use support::service::simple_service;
+
+#[test]
+fn test_gathers_segments_in_order_over_one_connection() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let accepted = Rc::new(Cell::new(0));
+    let server_accepted = accepted.clone();
+    let server_handle = handle.clone();
+    let server = listener.incoming().for_each(move |(socket, _)| {
+        server_accepted.set(server_accepted.get() + 1);
+        MuxLineProto.bind_server(&server_handle, socket, Echo("range:".to_string()));
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    let client = core.run(TcpClient::new(MuxLineProto).connect(&addr, &handle)).unwrap();
+
+    let segments = vec!["0-9".to_string(), "10-19".to_string(), "20-29".to_string()];
+    let body = core.run(gather(client, segments, 0).collect()).unwrap();
+
+    assert_eq!(vec!["range:0-9", "range:10-19", "range:20-29"], body);
+    assert_eq!(1, accepted.get());
+}
+
+#[test]
+fn test_retries_failed_segments() {
+    let attempts = Arc::new(Mutex::new(HashMap::new()));
+
+    // Every segment fails on its first attempt
+    let service_attempts = attempts.clone();
+    let service = simple_service(move |segment: u32| {
+        let mut attempts = service_attempts.lock().unwrap();
+        let n = attempts.entry(segment).or_insert(0);
+        *n += 1;
+
+        if *n == 1 {
+            Err(io::Error::new(io::ErrorKind::Other, "flaky"))
+        } else {
+            Ok(segment * 10)
+        }
+    });
+
+    let body = gather(service, vec![1, 2, 3], 1).collect().wait().unwrap();
+
+    assert_eq!(vec![10, 20, 30], body);
+    assert_eq!(6, attempts.lock().unwrap().values().sum::<u32>());
+}
+
+#[test]
+fn test_fails_once_retries_are_exhausted() {
+    let service = simple_service(|segment: u32| {
+        if segment == 2 {
+            Err(io::Error::new(io::ErrorKind::Other, "missing range"))
+        } else {
+            Ok(segment)
+        }
+    });
+
+    let mut body = gather(service, vec![1, 2, 3], 2).wait();
+
+    assert_eq!(1, body.next().unwrap().unwrap());
+    assert_eq!("missing range", body.next().unwrap().unwrap_err().to_string());
+}
@@ -0,0 +1,198 @@
+#![allow(deprecated)]
+
+#[macro_use]
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{self, SocketAddr};
+use std::thread;
+
+use futures::{Async, Poll, Sink, StartSend, Stream};
+use futures::future;
+use tokio_core::io::{Codec, EasyBuf, Framed, Io};
+use tokio_core::reactor::Core;
+use tokio_proto::{pool, TcpClient};
+use tokio_proto::multiplex::{ClientProto, GoAway, NotProcessed};
+use tokio_proto::streaming::multiplex::Counter;
+use tokio_proto::reissue::Reissue;
+use tokio_service::Service;
+
+mod support;
+use support::line::LineCodec;
+
+// Multiplexed line protocol whose servers announce going away with a
+// `goaway <last processed id>` line
+struct GoAwayProto;
+
+enum Line {
+    Message(u64, String),
+    GoAway(u64),
+}
+
+struct GoAwayCodec;
+
+// Keeps the announcement aside for `peer_going_away`
+struct GoAwayTransport<T> {
+    inner: Framed<T, GoAwayCodec>,
+    go_away: Option<GoAway<u64>>,
+}
+
+impl Codec for GoAwayCodec {
+    type In = Line;
+    type Out = (u64, String);
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<Line>> {
+        let line = match try!(LineCodec.decode(buf)) {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+
+        let mut parts = line.splitn(2, ' ');
+        let head = parts.next().unwrap();
+        let rest = parts.next().unwrap_or("");
+
+        if head == "goaway" {
+            return Ok(Some(Line::GoAway(rest.parse().unwrap())));
+        }
+
+        Ok(Some(Line::Message(head.parse().unwrap(), rest.to_string())))
+    }
+
+    fn encode(&mut self, (id, msg): (u64, String), buf: &mut Vec<u8>) -> io::Result<()> {
+        LineCodec.encode(format!("{} {}", id, msg), buf)
+    }
+}
+
+impl<T: Io> Stream for GoAwayTransport<T> {
+    type Item = (u64, String);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<(u64, String)>, io::Error> {
+        loop {
+            match try_ready!(self.inner.poll()) {
+                Some(Line::Message(id, msg)) => return Ok(Async::Ready(Some((id, msg)))),
+                Some(Line::GoAway(last)) => {
+                    self.go_away = Some(GoAway { last_processed: Some(last) });
+                }
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+}
+
+impl<T: Io> Sink for GoAwayTransport<T> {
+    type SinkItem = (u64, String);
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, item: (u64, String)) -> StartSend<(u64, String), io::Error> {
+        self.inner.start_send(item)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        self.inner.poll_complete()
+    }
+}
+
+impl<T: Io + 'static> ClientProto<T> for GoAwayProto {
+    type Request = String;
+    type Response = String;
+    type RequestId = u64;
+    type Error = io::Error;
+    type Transport = GoAwayTransport<T>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+    type RequestIdSource = Counter;
+
+    fn requestid_source(&self) -> Counter {
+        Counter::new()
+    }
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(GoAwayTransport {
+            inner: io.framed(GoAwayCodec),
+            go_away: None,
+        })
+    }
+
+    fn peer_going_away(transport: &mut GoAwayTransport<T>) -> Option<GoAway<u64>> {
+        transport.go_away.take()
+    }
+}
+
+// Reads three requests on the first connection, answers the first one and
+// goes away after processing the second one. Requests on the next connection
+// are echoed, the thread yields them once it is closed.
+fn serve_draining() -> (SocketAddr, thread::JoinHandle<Vec<String>>) {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let t = thread::spawn(move || {
+        {
+            let (conn, _) = listener.accept().unwrap();
+            conn.set_read_timeout(Some(support::DEADLINE)).unwrap();
+            let mut reader = BufReader::new(conn.try_clone().unwrap());
+            let mut conn = conn;
+
+            let mut line = String::new();
+            for _ in 0..3 {
+                reader.read_line(&mut line).unwrap();
+            }
+            assert_eq!("0 a\n1 b\n2 c\n", line);
+
+            conn.write_all(b"0 a\ngoaway 1\n1 b\n").unwrap();
+        }
+
+        let (conn, _) = listener.accept().unwrap();
+        conn.set_read_timeout(Some(support::DEADLINE)).unwrap();
+        let reader = BufReader::new(conn.try_clone().unwrap());
+        let mut conn = conn;
+
+        reader.lines().map(|line| {
+            let line = line.unwrap();
+            conn.write_all(format!("{}\n", line).as_bytes()).unwrap();
+            line
+        }).collect()
+    });
+
+    (addr, t)
+}
+
+#[test]
+fn test_reissuing_requests_not_processed() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let (addr, t) = serve_draining();
+
+    let client = Reissue::new(pool::Client::new(&TcpClient::new(GoAwayProto), &addr, &handle, 1, 4));
+
+    let responses = ["a", "b", "c"].iter().map(|req| client.call(req.to_string())).collect::<Vec<_>>();
+    let responses = core.run(future::join_all(responses)).unwrap();
+    assert_eq!(vec!["a", "b", "c"], responses);
+
+    // Only the request that was not processed went to the new connection
+    drop((client, core));
+    assert_eq!(vec!["0 c"], t.join().unwrap());
+}
+
+#[test]
+fn test_failing_requests_not_processed() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let (addr, _t) = serve_draining();
+
+    let client = pool::Client::new(&TcpClient::new(GoAwayProto), &addr, &handle, 1, 4);
+
+    let a = client.call("a".to_string());
+    let b = client.call("b".to_string());
+    let c = client.call("c".to_string());
+
+    assert_eq!("a", core.run(a).unwrap());
+    assert_eq!("b", core.run(b).unwrap());
+
+    let err = core.run(c).unwrap_err();
+    assert!(err.get_ref().unwrap().is::<NotProcessed>());
+}
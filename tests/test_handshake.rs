@@ -0,0 +1,120 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io;
+
+use futures::{Future, Sink, Stream};
+use tokio_core::io::{Framed, Io};
+use tokio_core::reactor::Core;
+use tokio_proto::{conformance, BindClient, BindServer};
+use tokio_proto::pipeline::{ClientProto, HandshakeFuture, Pipeline, ServerProto};
+use tokio_service::Service;
+
+mod support;
+use support::line::{Echo, LineCodec};
+
+// Line protocol opening connections with a `hello <version>` line, answered
+// with `welcome` by servers speaking that version
+struct HelloProto;
+
+// A client still speaking an older version
+struct StaleProto;
+
+impl<T: Io + 'static> ServerProto<T> for HelloProto {
+    type Request = String;
+    type Response = String;
+    type Transport = Framed<T, LineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(LineCodec))
+    }
+
+    fn handshake(transport: Self::Transport) -> HandshakeFuture<Self::Transport> {
+        let handshake = transport.into_future().map_err(|(e, _)| e).and_then(|(hello, transport)| {
+            if hello.as_ref().map(|s| &s[..]) != Some("hello v1") {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported version"));
+            }
+
+            Ok(transport)
+        });
+
+        Box::new(handshake.and_then(|transport| transport.send("welcome".to_string())))
+    }
+}
+
+impl<T: Io + 'static> ClientProto<T> for HelloProto {
+    type Request = String;
+    type Response = String;
+    type Transport = Framed<T, LineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(LineCodec))
+    }
+
+    fn handshake(transport: Self::Transport) -> HandshakeFuture<Self::Transport> {
+        client_handshake(transport, "hello v1")
+    }
+}
+
+impl<T: Io + 'static> ClientProto<T> for StaleProto {
+    type Request = String;
+    type Response = String;
+    type Transport = Framed<T, LineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(LineCodec))
+    }
+
+    fn handshake(transport: Self::Transport) -> HandshakeFuture<Self::Transport> {
+        client_handshake(transport, "hello v0")
+    }
+}
+
+fn client_handshake<T>(transport: Framed<T, LineCodec>, hello: &str)
+                       -> HandshakeFuture<Framed<T, LineCodec>>
+    where T: Io + 'static,
+{
+    let handshake = transport.send(hello.to_string()).and_then(|transport| {
+        transport.into_future().map_err(|(e, _)| e)
+    });
+
+    Box::new(handshake.and_then(|(welcome, transport)| {
+        if welcome.as_ref().map(|s| &s[..]) != Some("welcome") {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "handshake refused"));
+        }
+
+        Ok(transport)
+    }))
+}
+
+#[test]
+fn test_requests_follow_handshake() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (client, server) = conformance::pipe();
+
+    BindServer::<Pipeline, _>::bind_server(&HelloProto, &handle, server, Echo("echo:".to_string()));
+    let service = BindClient::<Pipeline, _>::bind_client(&HelloProto, &handle, client);
+
+    assert_eq!("echo:ping", core.run(service.call("ping".to_string())).unwrap());
+    assert_eq!("echo:pong", core.run(service.call("pong".to_string())).unwrap());
+}
+
+#[test]
+fn test_failed_handshake_closes_connection() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (client, server) = conformance::pipe();
+
+    BindServer::<Pipeline, _>::bind_server(&HelloProto, &handle, server, Echo("echo:".to_string()));
+    let service = BindClient::<Pipeline, _>::bind_client(&StaleProto, &handle, client);
+
+    assert!(core.run(service.call("ping".to_string())).is_err());
+}
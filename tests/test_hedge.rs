@@ -0,0 +1,149 @@
+#[macro_use]
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
+use tokio_core::reactor::{Core, Handle, Timeout};
+use tokio_proto::hedge::Hedge;
+use tokio_service::Service;
+
+// Answers each call with its index after the next of the given delays,
+// counting the calls dropped before answering
+struct Backend {
+    handle: Handle,
+    delays: Rc<RefCell<VecDeque<Duration>>>,
+    calls: Rc<Cell<usize>>,
+    canceled: Rc<Cell<usize>>,
+}
+
+struct Delayed {
+    timer: Timeout,
+    call: usize,
+    done: bool,
+    canceled: Rc<Cell<usize>>,
+}
+
+impl Service for Backend {
+    type Request = String;
+    type Response = usize;
+    type Error = io::Error;
+    type Future = Delayed;
+
+    fn call(&self, _: String) -> Delayed {
+        let call = self.calls.get();
+        self.calls.set(call + 1);
+
+        let delay = self.delays.borrow_mut().pop_front().unwrap();
+
+        Delayed {
+            timer: Timeout::new(delay, &self.handle).unwrap(),
+            call: call,
+            done: false,
+            canceled: self.canceled.clone(),
+        }
+    }
+}
+
+impl Future for Delayed {
+    type Item = usize;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<usize, io::Error> {
+        try_ready!(self.timer.poll());
+        self.done = true;
+        Ok(Async::Ready(self.call))
+    }
+}
+
+impl Drop for Delayed {
+    fn drop(&mut self) {
+        if !self.done {
+            self.canceled.set(self.canceled.get() + 1);
+        }
+    }
+}
+
+fn backend(core: &Core, delays: &[u64]) -> (Backend, Rc<Cell<usize>>) {
+    let canceled = Rc::new(Cell::new(0));
+    let delays = delays.iter().map(|&ms| Duration::from_millis(ms)).collect();
+
+    let backend = Backend {
+        handle: core.handle(),
+        delays: Rc::new(RefCell::new(delays)),
+        calls: Rc::new(Cell::new(0)),
+        canceled: canceled.clone(),
+    };
+
+    (backend, canceled)
+}
+
+#[test]
+fn test_hedging_slow_request() {
+    let mut core = Core::new().unwrap();
+
+    // Four quick requests fill the window, the fifth is slow and its hedge
+    // quick again
+    let (backend, canceled) = backend(&core, &[10, 10, 10, 10, 5_000, 10]);
+    let client = Hedge::new(backend, 1.0, &core.handle()).window(4);
+
+    for call in 0..4 {
+        assert_eq!(call, core.run(client.call("hello".to_string())).unwrap());
+    }
+
+    let start = Instant::now();
+
+    // Answered by the hedge, the slow request is canceled
+    assert_eq!(5, core.run(client.call("hello".to_string())).unwrap());
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert_eq!(1, canceled.get());
+
+    let stats = client.stats();
+    assert_eq!(5, stats.requests);
+    assert_eq!(1, stats.hedged);
+    assert_eq!(1, stats.hedges_won);
+}
+
+#[test]
+fn test_no_hedging_before_window_is_full() {
+    let mut core = Core::new().unwrap();
+
+    let (backend, canceled) = backend(&core, &[10, 200]);
+    let client = Hedge::new(backend, 0.5, &core.handle()).window(4);
+
+    for call in 0..2 {
+        assert_eq!(call, core.run(client.call("hello".to_string())).unwrap());
+    }
+
+    assert_eq!(0, canceled.get());
+
+    let stats = client.stats();
+    assert_eq!(2, stats.requests);
+    assert_eq!(0, stats.hedged);
+}
+
+#[test]
+fn test_hedged_latency_counts_from_primary() {
+    let mut core = Core::new().unwrap();
+
+    // The second request is answered by its hedge 200ms + 300ms after being
+    // sent, which the third request then waits for before hedging
+    let (backend, _) = backend(&core, &[200, 5_000, 300, 5_000, 10]);
+    let client = Hedge::new(backend, 1.0, &core.handle()).window(1);
+
+    assert_eq!(0, core.run(client.call("hello".to_string())).unwrap());
+    assert_eq!(2, core.run(client.call("hello".to_string())).unwrap());
+
+    let start = Instant::now();
+
+    assert_eq!(4, core.run(client.call("hello".to_string())).unwrap());
+    assert!(start.elapsed() >= Duration::from_millis(450));
+    assert_eq!(2, client.stats().hedges_won);
+}
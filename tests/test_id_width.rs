@@ -0,0 +1,39 @@
+extern crate tokio_proto;
+
+use std::io;
+use std::mem;
+
+use tokio_proto::streaming::multiplex::{Counter, Frame, RequestIdSource};
+
+type LineFrame<Id> = Frame<Id, String, Vec<u8>, io::Error>;
+
+#[test]
+fn test_narrow_ids_shrink_frames() {
+    assert!(mem::size_of::<LineFrame<u16>>() < mem::size_of::<LineFrame<u64>>());
+    assert!(mem::size_of::<Frame<u16, u32, u32, ()>>() < mem::size_of::<Frame<u64, u32, u32, ()>>());
+}
+
+#[test]
+fn test_counter_wraps_around() {
+    let mut counter = Counter::<u16>::new();
+
+    for expected in 0..u16::MAX {
+        assert_eq!(expected, counter.next(&()));
+    }
+
+    assert_eq!(u16::MAX, counter.next(&()));
+    assert_eq!(0, counter.next(&()));
+}
+
+#[test]
+fn test_ids_issued_after_wrapping_around() {
+    let counter = Counter::<u16>::new();
+
+    assert!(RequestIdSource::<u16, ()>::issued_after(&counter, &5, &3));
+    assert!(!RequestIdSource::<u16, ()>::issued_after(&counter, &3, &5));
+    assert!(!RequestIdSource::<u16, ()>::issued_after(&counter, &3, &3));
+
+    // 2 was handed out after the counter wrapped around
+    assert!(RequestIdSource::<u16, ()>::issued_after(&counter, &2, &65530));
+    assert!(!RequestIdSource::<u16, ()>::issued_after(&counter, &65530, &2));
+}
@@ -0,0 +1,219 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::{Future, Stream};
+use futures::future;
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Core;
+use tokio_proto::{BindConfig, BindServer, Tags, TcpClient, TcpServer};
+use tokio_proto::instrument::{ConnectionObserver, Instrumented, IoMetrics};
+use tokio_proto::streaming::ConnectionId;
+use tokio_service::Service;
+
+mod support;
+use support::line::{LineProto, MuxLineProto, Echo};
+
+#[derive(Default)]
+struct Counts {
+    frames_read: AtomicUsize,
+    frames_written: AtomicUsize,
+    started: AtomicUsize,
+    completed: AtomicUsize,
+    max_depth: AtomicUsize,
+}
+
+impl ConnectionObserver for Counts {
+    fn on_frame_read(&self, _: ConnectionId) {
+        self.frames_read.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn on_frame_written(&self, _: ConnectionId) {
+        self.frames_written.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn on_request_started(&self, _: ConnectionId) {
+        self.started.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn on_request_completed(&self, _: ConnectionId, _: Duration) {
+        self.completed.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn on_queue_depth(&self, _: ConnectionId, depth: usize) {
+        if depth > self.max_depth.load(Ordering::SeqCst) {
+            self.max_depth.store(depth, Ordering::SeqCst);
+        }
+    }
+}
+
+impl Counts {
+    fn get(&self) -> (usize, usize, usize, usize) {
+        (self.frames_read.load(Ordering::SeqCst),
+         self.frames_written.load(Ordering::SeqCst),
+         self.started.load(Ordering::SeqCst),
+         self.completed.load(Ordering::SeqCst))
+    }
+}
+
+#[test]
+fn test_instrumented_connections() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_metrics = IoMetrics::new();
+
+    let server_handle = handle.clone();
+    let metrics = server_metrics.clone();
+    let server = listener.incoming().for_each(move |(socket, _)| {
+        let socket = Instrumented::new(socket, &metrics);
+        LineProto.bind_server(&server_handle, socket, Echo("echo:".to_string()));
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    // Only every other call of the client is timed
+    let client_metrics = IoMetrics::sample_every(2);
+    let connect = TcpClient::new(LineProto).instrument(&client_metrics).connect(&addr, &handle);
+    let client = core.run(connect).unwrap();
+
+    for req in &["one", "two", "three"] {
+        let res = core.run(client.call(req.to_string())).unwrap();
+        assert_eq!(format!("echo:{}", req), res);
+    }
+
+    let client = client_metrics.snapshot();
+    assert_eq!(14, client.write.bytes);
+    assert_eq!(29, client.read.bytes);
+    assert!(client.write.calls >= 3);
+    let attempts = client.read.calls + client.read.would_block + client.read.errors;
+    assert!(client.read.sampled <= attempts.div_ceil(2));
+
+    let server = server_metrics.snapshot();
+    assert_eq!(14, server.read.bytes);
+    assert_eq!(29, server.write.bytes);
+    assert_eq!(server.write.calls, server.write.sampled);
+    assert!(server.write.mean_latency().unwrap() <= server.write.max_latency);
+    assert_eq!(0, server.read.errors);
+}
+
+#[test]
+fn test_server_options_compose() {
+    let addr = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+    let metrics = IoMetrics::new();
+    let tags = Tags::new();
+
+    let server_metrics = metrics.clone();
+    let server_tags = tags.clone();
+
+    thread::spawn(move || {
+        // The tag wraps the instrumented socket
+        TcpServer::new(LineProto, addr)
+            .instrument(&server_metrics)
+            .tag(server_tags, |_| "tenant")
+            .serve(|| Ok(Echo("echo:".to_string())));
+    });
+
+    let mut conn = BufReader::new(support::connect(&addr));
+    conn.get_mut().write_all(b"one\n").unwrap();
+
+    let mut line = String::new();
+    conn.read_line(&mut line).unwrap();
+    assert_eq!("echo:one\n", line);
+
+    assert_eq!(1, tags.count(&"tenant"));
+
+    // The write is recorded once it returned, maybe after the client read it
+    let deadline = Instant::now() + support::DEADLINE;
+
+    while metrics.snapshot().write.bytes < 9 {
+        assert!(Instant::now() < deadline, "write not recorded");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let snapshot = metrics.snapshot();
+    assert_eq!(4, snapshot.read.bytes);
+    assert_eq!(9, snapshot.write.bytes);
+}
+
+#[test]
+fn test_observed_pipeline_connections() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // The server connections are observed through their bind config
+    let server_counts = Arc::new(Counts::default());
+    let config = BindConfig { observer: Some(server_counts.clone()), ..BindConfig::default() };
+
+    let server_handle = handle.clone();
+    let server = listener.incoming().for_each(move |(socket, _)| {
+        LineProto.bind_server_guarded(&server_handle, socket, Echo(String::new()), &config, ());
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    let client_counts = Arc::new(Counts::default());
+    let mut client = TcpClient::new(LineProto);
+    client.observe(client_counts.clone());
+
+    let client = core.run(client.connect(&addr, &handle)).unwrap();
+
+    for req in &["one", "two", "three"] {
+        assert_eq!(*req, core.run(client.call(req.to_string())).unwrap());
+    }
+
+    assert_eq!((3, 3, 3, 3), client_counts.get());
+    assert_eq!(1, client_counts.max_depth.load(Ordering::SeqCst));
+    assert_eq!((3, 3, 3, 3), server_counts.get());
+}
+
+#[test]
+fn test_observed_multiplex_client() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_handle = handle.clone();
+    let server = listener.incoming().for_each(move |(socket, _)| {
+        MuxLineProto.bind_server(&server_handle, socket, Echo(String::new()));
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    let counts = Arc::new(Counts::default());
+    let mut client = TcpClient::new(MuxLineProto);
+    client.observe(counts.clone());
+
+    let client = core.run(client.connect(&addr, &handle)).unwrap();
+
+    let calls = vec![client.call("a".to_string()),
+                     client.call("b".to_string()),
+                     client.call("c".to_string())];
+    let responses = core.run(future::join_all(calls)).unwrap();
+    assert_eq!(vec!["a", "b", "c"], responses);
+
+    assert_eq!((3, 3, 3, 3), counts.get());
+    assert_eq!(3, counts.max_depth.load(Ordering::SeqCst));
+}
@@ -0,0 +1,209 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::cell::RefCell;
+use std::io;
+
+use futures::{future, Future, Sink, Stream};
+use futures::sync::oneshot;
+use tokio_core::io::{Codec, EasyBuf, Framed, Io};
+use tokio_core::reactor::Core;
+use tokio_proto::{conformance, BindServer};
+use tokio_proto::streaming::{Body, Interim, Message};
+use tokio_proto::streaming::{multiplex, pipeline};
+use tokio_service::Service;
+
+mod support;
+use support::line::LineCodec;
+
+// A request keeping its `Interim` handle for the service
+struct Hinted {
+    line: String,
+    interim: Option<Interim<String>>,
+}
+
+// Multiplexed lines, interim responses are written as `<id> hint:<line>`
+struct MuxHintCodec;
+
+impl Codec for MuxHintCodec {
+    type In = multiplex::Frame<u64, Hinted, (), io::Error>;
+    type Out = multiplex::Frame<u64, String, (), io::Error>;
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<Self::In>> {
+        let line = match try!(LineCodec.decode(buf)) {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+
+        let mut parts = line.splitn(2, ' ');
+        let id = parts.next().unwrap().parse().unwrap();
+        let line = parts.next().unwrap_or("").to_string();
+
+        Ok(Some(multiplex::Frame::Message {
+            id: id,
+            message: Hinted { line: line, interim: None },
+            body: false,
+            solo: false,
+        }))
+    }
+
+    fn encode(&mut self, frame: Self::Out, buf: &mut Vec<u8>) -> io::Result<()> {
+        let line = match frame {
+            multiplex::Frame::Message { id, message, .. } => format!("{} {}", id, message),
+            multiplex::Frame::Progress { id, message } => format!("{} hint:{}", id, message),
+            frame => panic!("unexpected frame; {:?}", frame),
+        };
+
+        LineCodec.encode(line, buf)
+    }
+}
+
+struct MuxHintProto;
+
+impl<T: Io + 'static> multiplex::ServerProto<T> for MuxHintProto {
+    type Request = Hinted;
+    type RequestBody = ();
+    type Response = String;
+    type ResponseBody = ();
+    type RequestId = u64;
+    type Error = io::Error;
+    type Transport = Framed<T, MuxHintCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(MuxHintCodec))
+    }
+
+    fn on_interim(request: &mut Hinted, interim: Interim<String>) {
+        request.interim = Some(interim);
+    }
+}
+
+// Pipelined lines, interim responses are written as `hint:<line>`
+struct HintCodec;
+
+impl Codec for HintCodec {
+    type In = pipeline::Frame<Hinted, (), io::Error>;
+    type Out = pipeline::Frame<String, (), io::Error>;
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<Self::In>> {
+        let line = match try!(LineCodec.decode(buf)) {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+
+        Ok(Some(pipeline::Frame::Message {
+            message: Hinted { line: line, interim: None },
+            body: false,
+        }))
+    }
+
+    fn encode(&mut self, frame: Self::Out, buf: &mut Vec<u8>) -> io::Result<()> {
+        let line = match frame {
+            pipeline::Frame::Message { message, .. } => message,
+            pipeline::Frame::Progress { message } => format!("hint:{}", message),
+            frame => panic!("unexpected frame; {:?}", frame),
+        };
+
+        LineCodec.encode(line, buf)
+    }
+}
+
+struct HintProto;
+
+impl<T: Io + 'static> pipeline::ServerProto<T> for HintProto {
+    type Request = Hinted;
+    type RequestBody = ();
+    type Response = String;
+    type ResponseBody = ();
+    type Error = io::Error;
+    type Transport = Framed<T, HintCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(HintCodec))
+    }
+
+    fn on_interim(request: &mut Hinted, interim: Interim<String>) {
+        request.interim = Some(interim);
+    }
+}
+
+// Hints the line of every request before echoing it; the response to a
+// `slow` request waits for the given signal
+struct HintEcho {
+    slow: RefCell<Option<oneshot::Receiver<()>>>,
+}
+
+impl Service for HintEcho {
+    type Request = Message<Hinted, Body<(), io::Error>>;
+    type Response = Message<String, Body<(), io::Error>>;
+    type Error = io::Error;
+    type Future = Box<Future<Item = Self::Response, Error = io::Error>>;
+
+    fn call(&self, request: Self::Request) -> Self::Future {
+        let request = request.into_inner();
+        let line = request.line;
+
+        request.interim.unwrap().send(format!("preload {}", line)).unwrap();
+
+        let ready: Box<Future<Item = (), Error = io::Error>> = if line == "slow" {
+            let slow = self.slow.borrow_mut().take().unwrap();
+            Box::new(slow.map_err(|_| io::Error::new(io::ErrorKind::Other, "canceled")))
+        } else {
+            Box::new(future::ok(()))
+        };
+
+        Box::new(ready.map(move |()| Message::WithoutBody(format!("echo:{}", line))))
+    }
+}
+
+fn hint_echo() -> (HintEcho, oneshot::Sender<()>) {
+    let (tx, rx) = oneshot::channel();
+    (HintEcho { slow: RefCell::new(Some(rx)) }, tx)
+}
+
+#[test]
+fn test_multiplex_interim_before_response() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (client, server) = conformance::pipe();
+
+    let (service, _slow) = hint_echo();
+    BindServer::<multiplex::StreamingMultiplex<Body<(), io::Error>>, _>::bind_server(
+        &MuxHintProto, &handle, server, service);
+
+    let client = client.framed(LineCodec);
+    let client = core.run(client.send("0 ping".to_string())).unwrap();
+
+    let lines = core.run(client.take(2).collect()).unwrap();
+    assert_eq!(vec!["0 hint:preload ping", "0 echo:ping"], lines);
+}
+
+#[test]
+fn test_pipeline_interim_waits_for_earlier_responses() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (client, server) = conformance::pipe();
+
+    let (service, slow) = hint_echo();
+    BindServer::<pipeline::StreamingPipeline<Body<(), io::Error>>, _>::bind_server(
+        &HintProto, &handle, server, service);
+
+    let client = client.framed(LineCodec);
+    let client = core.run(client.send("slow".to_string())).unwrap();
+    let client = core.run(client.send("fast".to_string())).unwrap();
+
+    let (first, client) = core.run(client.into_future()).map_err(|(e, _)| e).unwrap();
+    assert_eq!(Some("hint:preload slow".to_string()), first);
+
+    // The hint of `fast` is held back until `slow` is answered
+    slow.send(()).unwrap();
+
+    let lines = core.run(client.take(3).collect()).unwrap();
+    assert_eq!(vec!["echo:slow", "hint:preload fast", "echo:fast"], lines);
+}
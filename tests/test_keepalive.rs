@@ -0,0 +1,192 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+
+use std::cell::Cell;
+use std::io;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use futures::future::poll_fn;
+use futures::sync::mpsc;
+use tokio_core::reactor::Core;
+use tokio_proto::keepalive::KeepAlive;
+use tokio_proto::streaming::multiplex::{self, GoAway};
+use tokio_proto::streaming::pipeline::{Frame, Transport};
+
+type LineFrame = Frame<String, (), io::Error>;
+
+// Yields the lines sent on `rx`, counting the pings
+struct Pinged {
+    rx: mpsc::UnboundedReceiver<String>,
+    pings: Rc<Cell<usize>>,
+}
+
+impl Stream for Pinged {
+    type Item = LineFrame;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<LineFrame>, io::Error> {
+        match self.rx.poll().unwrap() {
+            Async::Ready(Some(line)) => {
+                Ok(Async::Ready(Some(Frame::Message { message: line, body: false })))
+            }
+            Async::Ready(None) => Ok(Async::Ready(None)),
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl Sink for Pinged {
+    type SinkItem = LineFrame;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, _: LineFrame) -> StartSend<LineFrame, io::Error> {
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Transport for Pinged {
+    fn ping(&mut self) -> io::Result<()> {
+        self.pings.set(self.pings.get() + 1);
+        Ok(())
+    }
+}
+
+// A multiplexed transport whose peer announced going away
+struct GoingAway;
+
+type MultiplexFrame = multiplex::Frame<u64, String, (), io::Error>;
+
+impl Stream for GoingAway {
+    type Item = MultiplexFrame;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<MultiplexFrame>, io::Error> {
+        Ok(Async::NotReady)
+    }
+}
+
+impl Sink for GoingAway {
+    type SinkItem = MultiplexFrame;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, _: MultiplexFrame) -> StartSend<MultiplexFrame, io::Error> {
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+impl multiplex::Transport<u64, ()> for GoingAway {
+    fn peer_going_away(&mut self) -> Option<GoAway<u64>> {
+        Some(GoAway { last_processed: Some(3) })
+    }
+}
+
+fn keepalive(interval: u64, idle_timeout: u64)
+    -> (KeepAlive<Pinged>, mpsc::UnboundedSender<String>, Rc<Cell<usize>>)
+{
+    let (tx, rx) = mpsc::unbounded();
+    let pings = Rc::new(Cell::new(0));
+    let transport = Pinged { rx: rx, pings: pings.clone() };
+
+    let transport = KeepAlive::new(transport,
+                                   Duration::from_millis(interval),
+                                   Duration::from_millis(idle_timeout));
+    (transport, tx, pings)
+}
+
+// Drives the transport like a dispatcher would, reading every frame
+fn drive(transport: &mut KeepAlive<Pinged>) -> Poll<(), io::Error> {
+    transport.tick();
+
+    loop {
+        match try!(transport.poll()) {
+            Async::Ready(Some(_)) => {}
+            Async::Ready(None) => return Ok(Async::Ready(())),
+            Async::NotReady => return Ok(Async::NotReady),
+        }
+    }
+}
+
+#[test]
+fn test_pings_at_interval() {
+    let mut core = Core::new().unwrap();
+    let (mut transport, _tx, pings) = keepalive(20, 10_000);
+    transport.on_handle(&core.handle());
+
+    let start = Instant::now();
+
+    core.run(poll_fn(|| {
+        assert!(!drive(&mut transport).unwrap().is_ready());
+
+        if pings.get() == 3 {
+            Ok(Async::Ready(()))
+        } else {
+            Ok::<_, ()>(Async::NotReady)
+        }
+    })).unwrap();
+
+    assert!(start.elapsed() >= Duration::from_millis(60));
+}
+
+#[test]
+fn test_idle_connection_times_out() {
+    let mut core = Core::new().unwrap();
+    let (mut transport, tx, pings) = keepalive(10_000, 50);
+    transport.on_handle(&core.handle());
+
+    // Frames keep the connection alive for a while...
+    thread::spawn(move || {
+        let mut tx = tx;
+
+        for i in 0..10 {
+            mpsc::UnboundedSender::send(&mut tx, i.to_string()).unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // ...but not once they stop, even with the sender still around
+        thread::sleep(Duration::from_secs(10));
+        drop(tx);
+    });
+
+    let start = Instant::now();
+
+    let err = core.run(poll_fn(|| drive(&mut transport))).unwrap_err();
+
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+    assert!(start.elapsed() >= Duration::from_millis(140));
+    assert_eq!(0, pings.get());
+}
+
+#[test]
+fn test_unarmed_without_handle() {
+    let mut core = Core::new().unwrap();
+    let (mut transport, _tx, pings) = keepalive(0, 0);
+
+    core.run(poll_fn(|| {
+        assert!(!drive(&mut transport).unwrap().is_ready());
+        Ok::<_, ()>(Async::Ready(()))
+    })).unwrap();
+
+    assert_eq!(0, pings.get());
+}
+
+#[test]
+fn test_forwards_peer_going_away() {
+    let mut transport = KeepAlive::new(GoingAway, Duration::from_secs(10), Duration::from_secs(10));
+
+    let go_away = multiplex::Transport::<u64, ()>::peer_going_away(&mut transport);
+    assert_eq!(Some(GoAway { last_processed: Some(3) }), go_away);
+}
@@ -0,0 +1,71 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net;
+use std::thread;
+use std::time::Duration;
+
+use futures::Future;
+use futures::sync::oneshot;
+use tokio_proto::TcpServer;
+use tokio_proto::streaming::multiplex::MultiplexConfig;
+use tokio_service::Service;
+
+mod support;
+use support::line::MuxLineProto;
+
+/// Answers "slow" requests after a while, the others right away
+struct Slow;
+
+impl Service for Slow {
+    type Request = String;
+    type Response = String;
+    type Error = io::Error;
+    type Future = Box<Future<Item = String, Error = io::Error>>;
+
+    fn call(&self, req: String) -> Self::Future {
+        if req != "slow" {
+            return Box::new(futures::finished(req));
+        }
+
+        let (tx, rx) = oneshot::channel();
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            tx.complete(req);
+        });
+
+        Box::new(rx.map_err(|_| io::Error::new(io::ErrorKind::Other, "canceled")))
+    }
+}
+
+#[test]
+fn test_server_config_overrides_protocol_config() {
+    let addr = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut server = TcpServer::new(MuxLineProto, addr);
+        server.config(MultiplexConfig { max_in_flight: 1, ..MultiplexConfig::default() });
+        server.serve(|| Ok(Slow));
+    });
+
+    let mut socket = support::connect(&addr);
+    socket.write_all(b"1 slow\n2 fast\n").unwrap();
+
+    // With the protocol's limit of 32, the fast request would be answered
+    // first
+    let mut reader = BufReader::new(socket);
+    let mut line = String::new();
+
+    reader.read_line(&mut line).unwrap();
+    assert_eq!("1 slow\n", line);
+
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!("2 fast\n", line);
+}
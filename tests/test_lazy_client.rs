@@ -0,0 +1,213 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures::{Future, Stream};
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::{Core, Handle, Timeout};
+use tokio_proto::{BindServer, ConnectionEvents, Resubscribe, Tags, TcpClient};
+use tokio_service::Service;
+
+mod support;
+use support::line::{LineProto, Echo};
+use support::service::simple_service;
+
+#[test]
+fn test_lazy_connect_on_first_call() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // Nothing is connected until the service is called
+    let client = TcpClient::new(LineProto).lazy(&addr, &handle, 2);
+
+    let one = client.call("one".to_string());
+    let two = client.call("two".to_string());
+
+    // The queue is full
+    assert!(client.call("three".to_string()).wait().is_err());
+
+    let server_handle = handle.clone();
+    let server = listener.incoming().take(1).for_each(move |(socket, _)| {
+        LineProto.bind_server(&server_handle, socket, Echo(String::new()));
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    let (one, two) = core.run(one.join(two)).unwrap();
+    assert_eq!("one", one);
+    assert_eq!("two", two);
+
+    // Once connected, calls go straight to the connection
+    let four = core.run(client.call("four".to_string())).unwrap();
+    assert_eq!("four", four);
+}
+
+#[test]
+fn test_lazy_connect_failure() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    // Find an address nobody listens on
+    let addr = {
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+        listener.local_addr().unwrap()
+    };
+
+    let client = TcpClient::new(LineProto).lazy(&addr, &handle, 4);
+
+    assert!(core.run(client.call("one".to_string())).is_err());
+
+    // The next call tries connecting again
+    assert!(core.run(client.call("two".to_string())).is_err());
+}
+
+// Echoes lines on connections tagged with `()`, recording the requests
+fn serve_recording(handle: &Handle) -> (SocketAddr, Tags<()>, Arc<Mutex<Vec<String>>>) {
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let tags = Tags::new();
+    let received = Arc::new(Mutex::new(vec![]));
+
+    let server_tags = tags.clone();
+    let server_received = received.clone();
+    let server_handle = handle.clone();
+    let server = listener.incoming().for_each(move |(socket, _)| {
+        let received = server_received.clone();
+        let service = simple_service(move |req: String| {
+            received.lock().unwrap().push(req.clone());
+            Ok(req)
+        });
+
+        LineProto.bind_server(&server_handle, server_tags.tag(socket, ()), service);
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    (addr, tags, received)
+}
+
+// Closes the connections from the server side and gives the client time to
+// notice
+fn disconnect(core: &mut Core, tags: &Tags<()>) {
+    assert_eq!(1, tags.shutdown_tag(&()));
+
+    let timeout = Timeout::new(Duration::from_millis(100), &core.handle()).unwrap();
+    core.run(timeout).unwrap();
+}
+
+#[test]
+fn test_lazy_reconnect_unwatched() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let (addr, tags, received) = serve_recording(&handle);
+
+    let client = TcpClient::new(LineProto).lazy(&addr, &handle, 4);
+
+    core.run(client.call("one".to_string())).unwrap();
+
+    disconnect(&mut core, &tags);
+    assert!(core.run(client.call("lost".to_string())).is_err());
+
+    // Losing the connection is detected without watching it
+    let two = core.run(client.call("two".to_string())).unwrap();
+    assert_eq!("two", two);
+    assert_eq!(vec!["one", "two"], *received.lock().unwrap());
+}
+
+struct Session {
+    handle: Handle,
+    events: Rc<RefCell<Vec<&'static str>>>,
+}
+
+impl<S> ConnectionEvents<S> for Session
+    where S: Service<Request = String>,
+          S::Future: 'static,
+{
+    fn on_connect(&self, client: &S) {
+        self.events.borrow_mut().push("connect");
+
+        // Restore the session ahead of any queued requests; dropping the
+        // response would cancel the request
+        self.handle.spawn(client.call("login".to_string()).then(|_| Ok(())));
+    }
+
+    fn on_disconnect(&self) {
+        self.events.borrow_mut().push("disconnect");
+    }
+}
+
+#[test]
+fn test_lazy_connection_events() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let (addr, tags, received) = serve_recording(&handle);
+
+    let events = Rc::new(RefCell::new(vec![]));
+    let client = TcpClient::new(LineProto).lazy(&addr, &handle, 4);
+    client.watch(Session { handle: handle.clone(), events: events.clone() });
+
+    let one = core.run(client.call("one".to_string())).unwrap();
+    assert_eq!("one", one);
+    assert_eq!(vec!["connect"], *events.borrow());
+
+    disconnect(&mut core, &tags);
+
+    let err = core.run(client.call("two".to_string())).unwrap_err();
+    assert_eq!(io::ErrorKind::BrokenPipe, err.kind());
+    assert_eq!(vec!["connect", "disconnect"], *events.borrow());
+
+    // The next call reconnects
+    let three = core.run(client.call("three".to_string())).unwrap();
+    assert_eq!("three", three);
+    assert_eq!(vec!["connect", "disconnect", "connect"], *events.borrow());
+    assert_eq!(vec!["login", "one", "login", "three"], *received.lock().unwrap());
+}
+
+#[test]
+fn test_resubscribe_replays_setup_requests() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let (addr, tags, received) = serve_recording(&handle);
+
+    let resubscribe = Resubscribe::new(&handle);
+    resubscribe.register("auth secret".to_string());
+    resubscribe.register("subscribe news".to_string());
+
+    let client = TcpClient::new(LineProto).lazy(&addr, &handle, 4);
+    client.watch(resubscribe.clone());
+
+    core.run(client.call("one".to_string())).unwrap();
+
+    disconnect(&mut core, &tags);
+    assert!(core.run(client.call("lost".to_string())).is_err());
+
+    // Registered on the shared list, so replayed from now on
+    resubscribe.register("subscribe sports".to_string());
+
+    core.run(client.call("two".to_string())).unwrap();
+
+    let expected = vec![
+        "auth secret", "subscribe news", "one",
+        "auth secret", "subscribe news", "subscribe sports", "two",
+    ];
+    assert_eq!(expected, *received.lock().unwrap());
+}
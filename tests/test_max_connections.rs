@@ -0,0 +1,126 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{self, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tokio_proto::{AtCapacity, TcpServer};
+use tokio_proto::pipeline::Pipeline;
+
+mod support;
+use support::line::{Echo, LineProto};
+
+fn serve<F>(configure: F) -> net::SocketAddr
+    where F: FnOnce(&mut TcpServer<Pipeline, LineProto>) + Send + 'static
+{
+    let addr = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut server = TcpServer::new(LineProto, addr);
+        configure(&mut server);
+        server.serve(|| Ok(Echo("echo:".to_string())));
+    });
+
+    addr
+}
+
+fn echo(socket: &mut TcpStream, line: &str) -> String {
+    socket.write_all(format!("{}\n", line).as_bytes()).unwrap();
+
+    let mut reader = BufReader::new(socket.try_clone().unwrap());
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    line
+}
+
+#[test]
+fn test_accept_paused_at_capacity() {
+    let addr = serve(|server| server.max_connections(1));
+
+    let mut open = support::connect(&addr);
+    assert_eq!("echo:first\n", echo(&mut open, "first"));
+
+    let mut queued = support::connect(&addr);
+    queued.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+    queued.write_all(b"second\n").unwrap();
+
+    let mut reader = BufReader::new(queued.try_clone().unwrap());
+    let mut line = String::new();
+
+    let err = reader.read_line(&mut line).unwrap_err();
+    assert!(err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut);
+
+    // Closing the connection frees its slot
+    drop(open);
+
+    queued.set_read_timeout(Some(support::DEADLINE)).unwrap();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!("echo:second\n", line);
+}
+
+#[test]
+fn test_surplus_connections_closed_at_capacity() {
+    let addr = serve(|server| {
+        server.max_connections(1);
+        server.at_capacity(AtCapacity::Close);
+    });
+
+    let mut open = support::connect(&addr);
+    assert_eq!("echo:first\n", echo(&mut open, "first"));
+
+    let mut surplus = support::connect(&addr);
+
+    // Either end of stream or reset, depending on what was sent already
+    let mut buf = [0; 16];
+    match surplus.read(&mut buf) {
+        Ok(n) => assert_eq!(0, n),
+        Err(e) => assert!(e.kind() != ErrorKind::WouldBlock && e.kind() != ErrorKind::TimedOut),
+    }
+
+    drop(open);
+
+    // The slot is freed once the server notices the connection closed,
+    // connections are closed until then
+    for _ in 0..100 {
+        let mut next = support::connect(&addr);
+        // Fails too if the connection was closed already
+        let _ = next.write_all(b"third\n");
+
+        let mut reader = BufReader::new(next);
+        let mut line = String::new();
+
+        if let Ok(n) = reader.read_line(&mut line) {
+            if n > 0 {
+                assert_eq!("echo:third\n", line);
+                return;
+            }
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    panic!("connection slot was not freed");
+}
+
+#[test]
+fn test_accept_rate_limited() {
+    let addr = serve(|server| server.max_accept_rate(2, Duration::from_millis(300)));
+
+    let mut first = support::connect(&addr);
+    assert_eq!("echo:1\n", echo(&mut first, "1"));
+
+    let mut second = support::connect(&addr);
+    assert_eq!("echo:2\n", echo(&mut second, "2"));
+
+    // The third connection waits for the next period
+    let start = Instant::now();
+    let mut third = support::connect(&addr);
+    assert_eq!("echo:3\n", echo(&mut third, "3"));
+    assert!(start.elapsed() >= Duration::from_millis(100));
+}
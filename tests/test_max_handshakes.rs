@@ -0,0 +1,64 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::net;
+use std::thread;
+use std::time::Duration;
+
+use futures::Future;
+use tokio_core::io::{read_exact, Framed, Io};
+use tokio_proto::TcpServer;
+use tokio_proto::pipeline::ServerProto;
+
+mod support;
+use support::line::{LineCodec, Echo};
+
+// Waits for a `HI` line from the client before framing the connection
+struct GreetedProto;
+
+impl<T: Io + 'static> ServerProto<T> for GreetedProto {
+    type Request = String;
+    type Response = String;
+    type Transport = Framed<T, LineCodec>;
+    type BindTransport = Box<Future<Item = Self::Transport, Error = io::Error>>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Box::new(read_exact(io, [0; 3]).map(|(io, _)| io.framed(LineCodec)))
+    }
+}
+
+#[test]
+fn test_accept_paused_while_handshaking() {
+    let addr = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut server = TcpServer::new(GreetedProto, addr);
+        server.max_handshakes(1);
+        server.serve(|| Ok(Echo("echo:".to_string())));
+    });
+
+    // Takes the only handshake slot
+    let mut stalled = support::connect(&addr);
+
+    let mut queued = support::connect(&addr);
+    queued.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+    queued.write_all(b"HI\nhello\n").unwrap();
+
+    let mut reader = BufReader::new(queued.try_clone().unwrap());
+    let mut line = String::new();
+
+    let err = reader.read_line(&mut line).unwrap_err();
+    assert!(err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut);
+
+    // Completing the handshake frees the slot
+    stalled.write_all(b"HI\n").unwrap();
+
+    queued.set_read_timeout(Some(support::DEADLINE)).unwrap();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!("echo:hello\n", line);
+}
@@ -0,0 +1,121 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::future::{self, FutureResult};
+use tokio_core::reactor::Core;
+use tokio_proto::Mirror;
+use tokio_service::Service;
+
+mod support;
+use support::line::Echo;
+
+// Records the requests it is called with, failing them if asked to
+struct Shadow {
+    calls: Rc<RefCell<Vec<String>>>,
+    fail: bool,
+}
+
+impl Service for Shadow {
+    type Request = String;
+    type Response = String;
+    type Error = io::Error;
+    type Future = FutureResult<String, io::Error>;
+
+    fn call(&self, req: String) -> Self::Future {
+        self.calls.borrow_mut().push(req.clone());
+
+        if self.fail {
+            future::err(io::Error::new(io::ErrorKind::Other, "shadow failed"))
+        } else {
+            future::ok(req)
+        }
+    }
+}
+
+#[test]
+fn test_mirror_fraction_of_requests() {
+    let mut core = Core::new().unwrap();
+    let calls = Rc::new(RefCell::new(vec![]));
+
+    let shadow = Shadow { calls: calls.clone(), fail: false };
+    let client = Mirror::new(Echo("primary:".to_string()), shadow, 0.5, &core.handle());
+
+    for req in &["a", "b", "c", "d"] {
+        let res = core.run(client.call(req.to_string())).unwrap();
+        assert_eq!(format!("primary:{}", req), res);
+    }
+
+    // Let the spawned shadow requests complete
+    core.turn(Some(Duration::from_millis(0)));
+
+    // Every other request is mirrored
+    assert_eq!(vec!["b".to_string(), "d".to_string()], *calls.borrow());
+
+    let stats = client.stats();
+    assert_eq!(2, stats.mirrored);
+    assert_eq!(2, stats.succeeded);
+    assert_eq!(0, stats.failed);
+    assert!(stats.total_latency >= stats.max_latency);
+}
+
+#[test]
+fn test_mirror_failures_do_not_reach_caller() {
+    let mut core = Core::new().unwrap();
+    let calls = Rc::new(RefCell::new(vec![]));
+
+    let shadow = Shadow { calls: calls.clone(), fail: true };
+    let client = Mirror::new(Echo("primary:".to_string()), shadow, 1.0, &core.handle());
+
+    let res = core.run(client.call("hello".to_string())).unwrap();
+    assert_eq!("primary:hello", res);
+
+    // Let the spawned shadow request complete
+    core.turn(Some(Duration::from_millis(0)));
+
+    let stats = client.stats();
+    assert_eq!(1, stats.mirrored);
+    assert_eq!(0, stats.succeeded);
+    assert_eq!(1, stats.failed);
+}
+
+#[test]
+fn test_mirror_compares_responses() {
+    let mut core = Core::new().unwrap();
+    let calls = Rc::new(RefCell::new(vec![]));
+
+    // The shadow answers with the bare request, which is all the primary
+    // responses are expected to differ by
+    let shadow = Shadow { calls: calls.clone(), fail: false };
+    let client = Mirror::new(Echo("primary:".to_string()), shadow, 1.0, &core.handle())
+        .compare(|primary: &String, shadow: &String| primary == &format!("primary:{}", shadow));
+
+    for req in &["a", "b"] {
+        let res = core.run(client.call(req.to_string())).unwrap();
+        assert_eq!(format!("primary:{}", req), res);
+    }
+
+    core.turn(Some(Duration::from_millis(0)));
+
+    let stats = client.stats();
+    assert_eq!(2, stats.compared);
+    assert_eq!(0, stats.diverged);
+
+    let client = Mirror::new(Echo("primary:".to_string()), Echo("shadow:".to_string()), 1.0, &core.handle())
+        .compare(|primary: &String, shadow: &String| primary == shadow);
+
+    core.run(client.call("a".to_string())).unwrap();
+    core.turn(Some(Duration::from_millis(0)));
+
+    let stats = client.stats();
+    assert_eq!(1, stats.compared);
+    assert_eq!(1, stats.diverged);
+}
@@ -0,0 +1,63 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use futures::{Future, Stream};
+use futures::future;
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Core;
+use tokio_proto::{BindServer, TcpClient};
+use tokio_service::Service;
+
+mod support;
+use support::line::{LineProto, Echo};
+
+#[test]
+fn test_stripes_requests_across_connections() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // Every connection prefixes responses with the order it was accepted in
+    let accepted = Rc::new(Cell::new(0));
+    let server_handle = handle.clone();
+    let server = listener.incoming().for_each(move |(socket, _)| {
+        let n = accepted.get();
+        accepted.set(n + 1);
+
+        LineProto.bind_server(&server_handle, socket, Echo(format!("{}:", n)));
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    let connect = TcpClient::new(LineProto).multipath(3).connect(&addr, &handle);
+    let client = core.run(connect).unwrap();
+    assert_eq!(3, client.get_ref().len());
+
+    let calls = (0..6).map(|i| client.call(i.to_string())).collect::<Vec<_>>();
+    let responses = core.run(future::join_all(calls)).unwrap();
+
+    // Each connection served every third request
+    for conn in 0..3 {
+        let served = responses.iter()
+            .filter(|r| r.starts_with(&format!("{}:", conn)))
+            .count();
+
+        assert_eq!(2, served);
+    }
+
+    // Requests are assigned round-robin
+    for i in 0..3 {
+        let conn = |r: &String| r.split(':').next().unwrap().to_string();
+        assert_eq!(conn(&responses[i]), conn(&responses[i + 3]));
+    }
+}
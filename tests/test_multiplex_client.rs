@@ -6,19 +6,24 @@ extern crate tokio_proto;
 extern crate tokio_service;
 extern crate rand;
 
-#[macro_use]
 extern crate log;
 extern crate env_logger;
 
 use std::io;
+use std::thread;
+use std::time::Duration;
 
-use futures::stream::{Stream};
+use futures::stream::{self, Stream};
 use futures::{Future};
-use tokio_proto::streaming::Message;
+use tokio_core::reactor::Core;
+use tokio_proto::{conformance, BindClient, BindServer};
+use tokio_proto::multiplex::Multiplex;
+use tokio_proto::streaming::{Body, Encodings, Message};
 use tokio_proto::streaming::multiplex::Frame;
 use tokio_service::Service;
 
 mod support;
+use support::line::{Echo, MuxLineProto};
 use support::mock;
 
 #[test]
@@ -89,6 +94,416 @@ fn drop_client_while_streaming_body() {
     mock.allow_and_assert_drop();
 }
 
+#[test]
+fn reports_consumer_lag_to_transport() {
+    let (mut mock, service, _other) = mock::multiplex_client();
+
+    let pong = service.call(Message::WithoutBody("ping"));
+
+    let wr = mock.next_write();
+    assert_eq!(&0, wr.request_id());
+
+    mock.send(msg_with_body(0, "pong"));
+
+    let mut pong = pong.wait().unwrap();
+    let rx = pong.take_body().unwrap();
+
+    // Nobody reads the body, so chunks pile up in the dispatcher
+    for i in 0..5 {
+        mock.send(body(0, Some(i)));
+    }
+
+    mock.send(body(0, None));
+
+    assert!(wait_for_lag(&mock, |lag| lag > 0));
+
+    // Draining the body clears the lag
+    let chunks: Vec<u32> = rx.wait().map(|c| c.unwrap()).collect();
+    assert_eq!(vec![0, 1, 2, 3, 4], chunks);
+
+    let pong = service.call(Message::WithoutBody("ping"));
+
+    let wr = mock.next_write();
+    assert_eq!(&1, wr.request_id());
+
+    mock.send(msg(1, "pong"));
+    assert_eq!("pong", pong.wait().unwrap().into_inner());
+    assert!(wait_for_lag(&mock, |lag| lag == 0));
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn drop_response_body_mid_stream() {
+    let (mut mock, service, _other) = mock::multiplex_client();
+
+    let pong = service.call(Message::WithoutBody("ping"));
+
+    let wr = mock.next_write();
+    assert_eq!(&0, wr.request_id());
+
+    mock.send(msg_with_body(0, "pong"));
+
+    let mut pong = pong.wait().unwrap();
+    let rx = pong.take_body().unwrap();
+
+    mock.send(body(0, Some(0)));
+
+    let mut rx = rx.wait();
+    assert_eq!(0, rx.next().unwrap().unwrap());
+
+    // Lose interest in the rest of the body
+    drop(rx);
+
+    // The next chunk finds the body dropped, the peer is told to stop
+    // sending the body
+    mock.send(body(0, Some(1)));
+    assert!(wait_for(|| mock.canceled() == vec![0]));
+
+    // Remaining chunks are discarded
+    mock.send(body(0, Some(2)));
+
+    mock.send(body(0, None));
+
+    // The connection is still usable
+    let pong = service.call(Message::WithoutBody("ping"));
+
+    let wr = mock.next_write();
+    assert_eq!(&1, wr.request_id());
+
+    mock.send(msg(1, "pong"));
+    assert_eq!("pong", pong.wait().unwrap().into_inner());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn pause_and_resume_response_body() {
+    let (mut mock, service, _other) = mock::multiplex_client();
+
+    let pong = service.call(Message::WithoutBody("ping"));
+
+    let wr = mock.next_write();
+    assert_eq!(&0, wr.request_id());
+
+    mock.send(msg_with_body(0, "pong"));
+
+    let mut pong = pong.wait().unwrap();
+    let rx = pong.take_body().unwrap();
+
+    // Once the connection is idle, the transport is told right away
+    thread::sleep(Duration::from_millis(50));
+    rx.pause();
+    assert!(rx.is_paused());
+    assert!(wait_for(|| mock.pauses() == vec![(0, true)]));
+
+    // Chunks are buffered by the dispatcher while the body is paused
+    for i in 0..3 {
+        mock.send(body(0, Some(i)));
+    }
+
+    rx.resume();
+    assert!(!rx.is_paused());
+    assert!(wait_for(|| mock.pauses() == vec![(0, true), (0, false)]));
+
+    mock.send(body(0, None));
+
+    let body: Vec<u32> = rx.wait().map(|i| i.unwrap()).collect();
+    assert_eq!(&[0, 1, 2], &body[..]);
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn drop_response_future_cancels_exchange() {
+    let (mut mock, service, _other) = mock::multiplex_client();
+
+    let pong = service.call(Message::WithoutBody("ping"));
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+
+    drop(pong);
+    assert!(wait_for(|| mock.canceled() == vec![0]));
+
+    // The late response is discarded without tearing down the connection
+    mock.send(msg(0, "pong"));
+
+    let pong = service.call(Message::WithoutBody("ping"));
+    let wr = mock.next_write();
+    assert_eq!(&1, wr.request_id());
+
+    mock.send(msg(1, "pong"));
+    assert_eq!("pong", pong.wait().unwrap().into_inner());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn cancel_where_fails_matching_requests() {
+    let (mut mock, service, _other) = mock::multiplex_client();
+
+    let stuck = service.call(Message::WithoutBody("stuck"));
+    assert_eq!("stuck", mock.next_write().unwrap_msg());
+
+    let ping = service.call(Message::WithoutBody("ping"));
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+
+    let mut ids = service.outstanding().into_iter().map(|info| info.request_id).collect::<Vec<_>>();
+    ids.sort();
+    assert_eq!(vec![Some("0".to_string()), Some("1".to_string())], ids);
+
+    let canceled = service.cancel_where(|info| info.request_id == Some("0".to_string()));
+    assert_eq!(1, canceled);
+
+    assert_eq!(io::ErrorKind::Other, stuck.wait().unwrap_err().kind());
+    assert!(wait_for(|| mock.canceled() == vec![0]));
+
+    // The other request is left alone, the late response is discarded
+    mock.send(msg(0, "unstuck"));
+    mock.send(msg(1, "pong"));
+    assert_eq!("pong", ping.wait().unwrap().into_inner());
+    assert!(service.outstanding().is_empty());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn outstanding_skips_completed_requests() {
+    let (mut mock, service, _other) = mock::multiplex_client();
+
+    for i in 0..40 {
+        let pong = service.call(Message::WithoutBody("ping"));
+        assert_eq!(&i, mock.next_write().request_id());
+
+        mock.send(msg(i, "pong"));
+        assert_eq!("pong", pong.wait().unwrap().into_inner());
+    }
+
+    let dropped = service.call(Message::WithoutBody("dropped"));
+    assert_eq!("dropped", mock.next_write().unwrap_msg());
+    drop(dropped);
+    assert!(wait_for(|| mock.canceled() == vec![40]));
+
+    let stuck = service.call(Message::WithoutBody("stuck"));
+    assert_eq!("stuck", mock.next_write().unwrap_msg());
+
+    let ids = service.outstanding().into_iter().map(|info| info.request_id).collect::<Vec<_>>();
+    assert_eq!(vec![Some("41".to_string())], ids);
+
+    assert_eq!(1, service.cancel_where(|_| true));
+    assert_eq!(io::ErrorKind::Other, stuck.wait().unwrap_err().kind());
+
+    mock.send(msg(40, "late"));
+    mock.send(msg(41, "late"));
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn detached_response_future_is_not_canceled() {
+    let (mut mock, service, _other) = mock::multiplex_client();
+
+    service.call(Message::WithoutBody("ping")).detach();
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+
+    mock.send(msg(0, "pong"));
+
+    let pong = service.call(Message::WithoutBody("ping"));
+    let wr = mock.next_write();
+    assert_eq!(&1, wr.request_id());
+
+    mock.send(msg(1, "pong"));
+    assert_eq!("pong", pong.wait().unwrap().into_inner());
+    assert!(mock.canceled().is_empty());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn detached_failure_is_collected() {
+    let (mut mock, service, _other) = mock::multiplex_client();
+    let detached = service.detached();
+
+    service.call(Message::WithoutBody("ping")).detach();
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+
+    // The exchange is dropped along with the connection
+    mock.error(io::Error::new(io::ErrorKind::Other, "boom"));
+
+    let res = detached.wait().next().unwrap().unwrap();
+    assert_eq!(io::ErrorKind::BrokenPipe, res.unwrap_err().kind());
+}
+
+#[test]
+fn connection_errors_are_reported_separately() {
+    let (mut mock, service, _other) = mock::multiplex_client();
+    let mut errors = service.errors().wait();
+
+    let pong = service.call(Message::WithoutBody("ping"));
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+
+    // Errors of individual calls are not reported
+    mock.send(Frame::Error {
+        id: 0,
+        error: io::Error::new(io::ErrorKind::Other, "nope"),
+    });
+    assert_eq!(io::ErrorKind::Other, pong.wait().unwrap_err().kind());
+
+    let pong = service.call(Message::WithoutBody("ping"));
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+
+    mock.error(io::Error::new(io::ErrorKind::ConnectionReset, "reset"));
+    assert_eq!(io::ErrorKind::BrokenPipe, pong.wait().unwrap_err().kind());
+
+    let err = errors.next().unwrap().unwrap();
+    assert_eq!(io::ErrorKind::ConnectionReset, err.kind());
+
+    // The stream ends with the connection
+    assert!(errors.next().is_none());
+}
+
+#[test]
+fn coalesce_ready_request_body_chunks() {
+    let (mut mock, service, _other) = mock::multiplex_client();
+    mock.coalesce_body_up_to(6);
+
+    let body = stream::iter_ok(vec![1, 2, 3, 4]).boxed();
+    let pong = service.call(Message::WithBody("ping", body));
+
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+    assert_eq!(Some(6), mock.next_write().unwrap_body());
+    assert_eq!(Some(4), mock.next_write().unwrap_body());
+    assert_eq!(None, mock.next_write().unwrap_body());
+
+    mock.send(msg(0, "pong"));
+    assert_eq!("pong", pong.wait().unwrap().into_inner());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn split_oversized_request_body_chunks() {
+    let (mut mock, service, _other) = mock::multiplex_client();
+    mock.split_body_over(4);
+
+    let body = stream::iter_ok(vec![10, 3]).boxed();
+    let pong = service.call(Message::WithBody("ping", body));
+
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+    assert_eq!(Some(4), mock.next_write().unwrap_body());
+    assert_eq!(Some(4), mock.next_write().unwrap_body());
+    assert_eq!(Some(2), mock.next_write().unwrap_body());
+    assert_eq!(Some(3), mock.next_write().unwrap_body());
+    assert_eq!(None, mock.next_write().unwrap_body());
+
+    mock.send(msg(0, "pong"));
+    assert_eq!("pong", pong.wait().unwrap().into_inner());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn request_body_completion() {
+    let (mut mock, service, _other) = mock::multiplex_client();
+
+    let mut body = Body::from(7);
+    let complete = body.completion();
+
+    let pong = service.call(Message::WithBody("ping", body.boxed()));
+
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+    assert_eq!(Some(7), mock.next_write().unwrap_body());
+    assert_eq!(None, mock.next_write().unwrap_body());
+
+    complete.wait().unwrap();
+
+    mock.send(msg(0, "pong"));
+    assert_eq!("pong", pong.wait().unwrap().into_inner());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_negotiating_encoding() {
+    let encodings = Encodings::new().with("br").with("deflate");
+    let limits = mock::Limits { encodings: encodings.clone(), ..Default::default() };
+    let (mut mock, service, _other) = mock::multiplex_client_with_limits(limits);
+
+    let pong = service.call(Message::WithoutBody("ping"));
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+
+    mock.peer_advertises(Encodings::new().with("gzip").with("deflate"));
+    mock.send(msg(0, "pong"));
+    assert_eq!("pong", pong.wait().unwrap().into_inner());
+
+    // The encoding is recorded right after the frame is read, so it is
+    // settled once the next exchange completes
+    let pong = service.call(Message::WithoutBody("ping"));
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+    mock.send(msg(1, "pong"));
+    assert_eq!("pong", pong.wait().unwrap().into_inner());
+
+    // Agrees with the server on its most preferred encoding
+    assert_eq!(Some(encodings), mock.advertised_encodings());
+    assert_eq!(Some(Some("deflate".to_string())), mock.encoding());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_unencoded_without_encodings() {
+    let (mut mock, service, _other) = mock::multiplex_client();
+
+    let pong = service.call(Message::WithoutBody("ping"));
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+
+    mock.peer_advertises(Encodings::new().with("gzip"));
+    mock.send(msg(0, "pong"));
+    assert_eq!("pong", pong.wait().unwrap().into_inner());
+
+    // Nothing is advertised nor agreed on
+    assert_eq!(None, mock.advertised_encodings());
+    assert_eq!(None, mock.encoding());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn dropping_many_response_futures_cancels_them_all() {
+    let (mut mock, service, _other) = mock::multiplex_client();
+
+    let pongs: Vec<_> = (0..3).map(|_| service.call(Message::WithoutBody("ping"))).collect();
+
+    for _ in 0..3 {
+        assert_eq!("ping", mock.next_write().unwrap_msg());
+    }
+
+    drop(pongs);
+    assert!(wait_for(|| mock.canceled().len() == 3));
+
+    for id in 0..3 {
+        mock.send(msg(id, "pong"));
+    }
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn backlog_of_canceled_requests_is_skipped() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (client, server) = conformance::pipe();
+
+    BindServer::<Multiplex, _>::bind_server(&MuxLineProto, &handle, server, Echo("echo:".to_string()));
+    let service = BindClient::<Multiplex, _>::bind_client(&MuxLineProto, &handle, client);
+
+    // Dropped before the connection got to write any of them
+    for _ in 0..100_000 {
+        drop(service.call("ping".to_string()));
+    }
+
+    let pong = core.run(service.call("ping".to_string())).unwrap();
+    assert_eq!("echo:ping", pong);
+}
+
 fn msg(id: u64, msg: &'static str) -> Frame<u64, &'static str, u32, io::Error> {
     Frame::Message {
         id: id,
@@ -113,3 +528,163 @@ fn body(id: u64, body: Option<u32>) -> Frame<u64, &'static str, u32, io::Error>
         chunk: body,
     }
 }
+
+// Stats are reported from the dispatcher thread, so poll for a while
+fn wait_for_lag<T, F>(mock: &mock::MockTransportCtl<T>, f: F) -> bool
+    where F: Fn(usize) -> bool
+{
+    wait_for(|| mock.last_stats().map(|stats| f(stats.consumer_lag)).unwrap_or(false))
+}
+
+// Polls the condition for a while, as the dispatcher runs on its own thread
+fn wait_for<F>(f: F) -> bool
+    where F: Fn() -> bool
+{
+    for _ in 0..100 {
+        if f() {
+            return true;
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    false
+}
+
+#[test]
+fn dispatcher_errors_name_connection_and_request() {
+    let (mut mock, service, _other) = mock::multiplex_client();
+    let mut errors = service.errors().wait();
+
+    let pong = service.call(Message::WithoutBody("ping"));
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+
+    // A response to a request that was never sent
+    mock.send(msg(7, "pong"));
+    assert_eq!(io::ErrorKind::BrokenPipe, pong.wait().unwrap_err().kind());
+
+    let err = errors.next().unwrap().unwrap();
+    assert_eq!(io::ErrorKind::Other, err.kind());
+
+    let expect = format!("request / response mismatch; conn={}, id=7", mock.connection_id());
+    assert_eq!(expect, err.to_string());
+}
+
+#[test]
+fn connection_ids_increase() {
+    let mut ids = vec![];
+
+    for _ in 0..2 {
+        let (mut mock, service, _other) = mock::multiplex_client();
+
+        // The dispatcher is created once the connection is driven
+        let pong = service.call(Message::WithoutBody("ping"));
+        assert_eq!("ping", mock.next_write().unwrap_msg());
+        mock.send(msg(0, "pong"));
+        assert_eq!("pong", pong.wait().unwrap().into_inner());
+
+        ids.push(mock.connection_id());
+        mock.allow_and_assert_drop();
+    }
+
+    assert!(ids[0] < ids[1]);
+}
+
+#[test]
+fn timed_out_request_is_canceled() {
+    let limits = mock::Limits {
+        request_timeout: Some(Duration::from_millis(50)),
+        ..Default::default()
+    };
+    let (mut mock, service, _other) = mock::multiplex_client_with_limits(limits);
+
+    let pong = service.call(Message::WithoutBody("ping"));
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+
+    // The response never arrives
+    assert_eq!(io::ErrorKind::TimedOut, pong.wait().unwrap_err().kind());
+
+    // The late response is discarded, the connection is still usable
+    mock.send(msg(0, "pong"));
+
+    let pong = service.call(Message::WithoutBody("ping"));
+    let wr = mock.next_write();
+    assert_eq!(&1, wr.request_id());
+    assert_eq!(vec![0], mock.canceled());
+
+    mock.send(msg(1, "pong"));
+    assert_eq!("pong", pong.wait().unwrap().into_inner());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_ack_resolves_accepted_before_response() {
+    let (mut mock, service, _other) = mock::multiplex_client();
+
+    let mut pong = service.call(Message::WithoutBody("ping"));
+    let accepted = pong.accepted();
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+
+    mock.send(Frame::Ack { id: 0 });
+    accepted.wait().unwrap();
+
+    // The response is still pending
+    mock.send(msg(0, "pong"));
+    assert_eq!("pong", pong.wait().unwrap().into_inner());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_accepted_follows_unacknowledged_response() {
+    let (mut mock, service, _other) = mock::multiplex_client();
+
+    let mut pong = service.call(Message::WithoutBody("ping"));
+    let accepted = pong.accepted();
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+
+    let mut fail = service.call(Message::WithoutBody("ping"));
+    let rejected = fail.accepted();
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+
+    // A successful response implies the request was accepted...
+    mock.send(msg(0, "pong"));
+    accepted.wait().unwrap();
+    assert_eq!("pong", pong.wait().unwrap().into_inner());
+
+    // ...a failed one that it was not
+    mock.send(Frame::Error {
+        id: 1,
+        error: io::Error::new(io::ErrorKind::Other, "nope"),
+    });
+    assert!(rejected.wait().is_err());
+    assert_eq!("nope", fail.wait().unwrap_err().to_string());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_progress_updates_before_response() {
+    let (mut mock, service, _other) = mock::multiplex_client();
+
+    let mut pong = service.call(Message::WithoutBody("ping"));
+    let accepted = pong.accepted();
+    let progress = pong.progress();
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+
+    mock.send(Frame::Progress { id: 0, message: "25%" });
+    mock.send(Frame::Progress { id: 0, message: "75%" });
+
+    // Progress implies the request was accepted
+    accepted.wait().unwrap();
+
+    mock.send(msg(0, "pong"));
+    assert_eq!("pong", pong.wait().unwrap().into_inner());
+
+    // The stream ends with the exchange
+    let updates = progress.map(|update| update.into_inner()).collect().wait().unwrap();
+    assert_eq!(vec!["25%", "75%"], updates);
+
+    mock.allow_and_assert_drop();
+}
@@ -0,0 +1,124 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io;
+
+use futures::Future;
+use futures::future::{self, FutureResult};
+use tokio_core::io::{Framed, Io};
+use tokio_core::reactor::Core;
+use tokio_proto::{conformance, BindClient, BindServer};
+use tokio_proto::multiplex::{ClientProto, Multiplex, ServerProto};
+use tokio_proto::streaming::multiplex::Counter;
+use tokio_service::Service;
+
+mod support;
+use support::line::MuxLineCodec;
+
+// Errors of a key lookup, written as `!missing key` lines
+#[derive(Debug)]
+enum LookupError {
+    Missing(String),
+    Io(io::Error),
+}
+
+impl From<io::Error> for LookupError {
+    fn from(err: io::Error) -> LookupError {
+        LookupError::Io(err)
+    }
+}
+
+struct LookupProto;
+
+impl<T: Io + 'static> ServerProto<T> for LookupProto {
+    type Request = String;
+    type Response = String;
+    type RequestId = u64;
+    type Error = LookupError;
+    type Transport = Framed<T, MuxLineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(MuxLineCodec))
+    }
+
+    fn error_response(error: LookupError) -> io::Result<String> {
+        match error {
+            LookupError::Missing(key) => Ok(format!("!missing {}", key)),
+            LookupError::Io(err) => Err(err),
+        }
+    }
+}
+
+impl<T: Io + 'static> ClientProto<T> for LookupProto {
+    type Request = String;
+    type Response = String;
+    type RequestId = u64;
+    type Error = LookupError;
+    type Transport = Framed<T, MuxLineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+    type RequestIdSource = Counter;
+
+    fn requestid_source(&self) -> Counter {
+        Counter::new()
+    }
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(MuxLineCodec))
+    }
+
+    fn response_error(response: String) -> Result<String, LookupError> {
+        if let Some(key) = response.strip_prefix("!missing ") {
+            return Err(LookupError::Missing(key.to_string()));
+        }
+
+        Ok(response)
+    }
+}
+
+struct Lookup;
+
+impl Service for Lookup {
+    type Request = String;
+    type Response = String;
+    type Error = LookupError;
+    type Future = FutureResult<String, LookupError>;
+
+    fn call(&self, key: String) -> Self::Future {
+        if key == "answer" {
+            future::ok("42".to_string())
+        } else {
+            future::err(LookupError::Missing(key))
+        }
+    }
+}
+
+#[test]
+fn test_typed_errors_reach_caller() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (client, server) = conformance::pipe();
+
+    BindServer::<Multiplex, _>::bind_server(&LookupProto, &handle, server, Lookup);
+    let service = BindClient::<Multiplex, _>::bind_client(&LookupProto, &handle, client);
+
+    let (found, missing) = core.run(service.call("answer".to_string())
+                                           .then(Ok::<_, ()>)
+                                           .join(service.call("question".to_string())
+                                                        .then(Ok::<_, ()>)))
+                               .unwrap();
+
+    assert_eq!("42", found.unwrap());
+
+    match missing {
+        Err(LookupError::Missing(key)) => assert_eq!("question", key),
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    // The connection is still usable
+    assert_eq!("42", core.run(service.call("answer".to_string())).unwrap());
+}
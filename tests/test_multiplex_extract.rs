@@ -0,0 +1,106 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net;
+use std::thread;
+
+use futures::Future;
+use tokio_core::io::{Codec, EasyBuf, Framed, Io};
+use tokio_core::reactor::Core;
+use tokio_proto::TcpClient;
+use tokio_proto::multiplex::{ClientProto, ExtractIds, ResponseIdExtractor};
+use tokio_proto::streaming::multiplex::Counter;
+use tokio_service::Service;
+
+mod support;
+use support::line::{LineCodec, MuxLineCodec};
+
+// Writes requests as `id msg` lines, reads responses as bare `msg#id` lines
+struct TokenCodec;
+
+impl Codec for TokenCodec {
+    type In = String;
+    type Out = (u64, String);
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<String>> {
+        LineCodec.decode(buf)
+    }
+
+    fn encode(&mut self, msg: (u64, String), buf: &mut Vec<u8>) -> io::Result<()> {
+        MuxLineCodec.encode(msg, buf)
+    }
+}
+
+// Finds the id copied after the `#` of a response
+struct Token;
+
+impl ResponseIdExtractor<String> for Token {
+    type Id = u64;
+
+    fn extract(&mut self, response: &String) -> u64 {
+        response.rsplit('#').next().unwrap().parse().unwrap()
+    }
+}
+
+struct TokenProto;
+
+impl<T: Io + 'static> ClientProto<T> for TokenProto {
+    type Request = String;
+    type Response = String;
+    type RequestId = u64;
+    type Error = io::Error;
+    type Transport = ExtractIds<Framed<T, TokenCodec>, Token>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+    type RequestIdSource = Counter;
+
+    fn requestid_source(&self) -> Counter {
+        Counter::new()
+    }
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(ExtractIds::new(io.framed(TokenCodec), Token))
+    }
+}
+
+// Answers pairs of requests in reverse order, copying the id of each into
+// its response
+fn serve_reversed() -> net::SocketAddr {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let (socket, _) = listener.accept().unwrap();
+        let mut writer = socket.try_clone().unwrap();
+        let mut lines = BufReader::new(socket).lines();
+
+        while let (Some(first), Some(second)) = (lines.next(), lines.next()) {
+            for line in &[second.unwrap(), first.unwrap()] {
+                let (id, msg) = line.split_once(' ').unwrap();
+                writer.write_all(format!("{}#{}\n", msg, id).as_bytes()).unwrap();
+            }
+        }
+    });
+
+    addr
+}
+
+#[test]
+fn test_responses_correlated_by_extracted_id() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = serve_reversed();
+    let client = core.run(TcpClient::new(TokenProto).connect(&addr, &handle)).unwrap();
+
+    let first = client.call("first".to_string());
+    let second = client.call("second".to_string());
+
+    let (first, second) = core.run(first.join(second)).unwrap();
+    assert_eq!("first#0", first);
+    assert_eq!("second#1", second);
+}
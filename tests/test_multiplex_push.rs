@@ -0,0 +1,119 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::cell::RefCell;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{self, SocketAddr};
+use std::rc::Rc;
+use std::thread;
+
+use futures::{future, Future, Stream};
+use futures::sync::oneshot;
+use tokio_core::io::{Framed, Io};
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::{Core, Handle};
+use tokio_proto::BindServer;
+use tokio_proto::multiplex::{Push, ServerProto};
+use tokio_service::Service;
+
+mod support;
+use support::line::MuxLineCodec;
+
+// Id reserved for pushed messages
+const PUSH_ID: u64 = 100;
+
+type Subscribers = Rc<RefCell<Vec<Push<u64, String>>>>;
+
+// Greets every connection with a pushed message and subscribes it
+struct PushLineProto {
+    subscribers: Subscribers,
+}
+
+impl<T: Io + 'static> ServerProto<T> for PushLineProto {
+    type Request = String;
+    type Response = String;
+    type RequestId = u64;
+    type Error = io::Error;
+    type Transport = Framed<T, MuxLineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(MuxLineCodec))
+    }
+
+    fn on_bind(&self, push: Push<u64, String>) {
+        push.push(PUSH_ID, "welcome".to_string()).unwrap();
+        self.subscribers.borrow_mut().push(push);
+    }
+}
+
+// Pushes every request to all the subscribers
+struct Publish {
+    subscribers: Subscribers,
+}
+
+impl Service for Publish {
+    type Request = String;
+    type Response = String;
+    type Error = io::Error;
+    type Future = future::FutureResult<String, io::Error>;
+
+    fn call(&self, req: String) -> Self::Future {
+        for push in self.subscribers.borrow().iter() {
+            push.push(PUSH_ID, req.clone()).unwrap();
+        }
+
+        future::ok("published".to_string())
+    }
+}
+
+fn serve(handle: &Handle) -> SocketAddr {
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let subscribers = Subscribers::default();
+    let proto = PushLineProto { subscribers: subscribers.clone() };
+
+    let server_handle = handle.clone();
+    let server = listener.incoming().for_each(move |(socket, _)| {
+        let service = Publish { subscribers: subscribers.clone() };
+        proto.bind_server(&server_handle, socket, service);
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    addr
+}
+
+#[test]
+fn test_pushed_messages_reach_the_client() {
+    let mut core = Core::new().unwrap();
+    let addr = serve(&core.handle());
+
+    let (tx, rx) = oneshot::channel();
+
+    thread::spawn(move || {
+        let socket = net::TcpStream::connect(addr).unwrap();
+        socket.set_read_timeout(Some(support::DEADLINE)).unwrap();
+        let mut writer = socket.try_clone().unwrap();
+        let mut lines = BufReader::new(socket).lines();
+
+        let mut read = vec![lines.next().unwrap().unwrap()];
+
+        writer.write_all(b"0 news\n").unwrap();
+        read.push(lines.next().unwrap().unwrap());
+        read.push(lines.next().unwrap().unwrap());
+
+        tx.complete(read);
+    });
+
+    let lines = core.run(rx).unwrap();
+
+    // Pushed on bind, then pushed by the service ahead of its response
+    assert_eq!(vec!["100 welcome", "100 news", "0 published"], lines);
+}
@@ -6,7 +6,6 @@ extern crate tokio_proto;
 extern crate tokio_service;
 extern crate rand;
 
-#[macro_use]
 extern crate log;
 
 use std::io;
@@ -15,13 +14,15 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use std::cell::RefCell;
 use std::thread;
 use std::time::Duration;
+use std::sync::mpsc as std_mpsc;
 
 use futures::{Future, Stream, Sink};
 use futures::future;
+use futures::stream;
 use futures::sync::oneshot;
 use futures::sync::mpsc;
-use tokio_proto::streaming::{Message, Body};
-use tokio_proto::streaming::multiplex::Frame;
+use tokio_proto::streaming::{Message, Body, BodySender, Encodings, FetchBody};
+use tokio_proto::streaming::multiplex::{Frame, SpillPolicy};
 use rand::Rng;
 
 mod support;
@@ -272,7 +273,7 @@ fn test_reaching_max_in_flight_requests() {
 
     // Complete pending requests
     for (i, c) in responses.drain(..) {
-        c.complete((Ok(Message::WithoutBody("zomg"))));
+        c.complete(Ok(Message::WithoutBody("zomg")));
 
         let wr = mock.next_write();
         assert_eq!(&i, wr.request_id());
@@ -282,6 +283,106 @@ fn test_reaching_max_in_flight_requests() {
     mock.allow_and_assert_drop();
 }
 
+#[test]
+fn test_reads_paused_at_max_in_flight_requests() {
+    let (mut tx, rx) = mpsc::unbounded();
+    let rx = RefCell::new(rx.wait());
+
+    let c1 = Arc::new(AtomicUsize::new(0));
+    let c2 = c1.clone();
+
+    let service = simple_service(move |_| {
+        c2.fetch_add(1, Ordering::SeqCst);
+        let fut = rx.borrow_mut().next().unwrap().unwrap();
+        let fut: oneshot::Receiver<_> = fut;
+        fut.map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"))
+           .and_then(|res| res)
+    });
+
+    let mut responses = vec![];
+
+    let (mut mock, _other) = mock::multiplex_server(service);
+    for i in 0..35 {
+        let (c, resp) = oneshot::channel();
+        mpsc::UnboundedSender::send(&mut tx, resp).unwrap();
+        responses.push((i, c));
+        mock.send(msg(i, "request"));
+    }
+
+    while c1.load(Ordering::SeqCst) < 32 {
+        thread::yield_now();
+    }
+
+    // Only the request waiting for a slot and the one after it are read past
+    // the limit
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(34, mock.reads());
+
+    let (i, c) = responses.remove(0);
+    c.complete(Ok(Message::WithoutBody("zomg")));
+    assert_eq!(&i, mock.next_write().request_id());
+
+    while c1.load(Ordering::SeqCst) < 33 {
+        thread::yield_now();
+    }
+
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(35, mock.reads());
+
+    for (i, c) in responses.drain(..) {
+        c.complete(Ok(Message::WithoutBody("zomg")));
+        assert_eq!(&i, mock.next_write().request_id());
+    }
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_request_bodies_read_at_max_in_flight_requests() {
+    let (tx, rx) = mpsc::unbounded();
+
+    // Responds once the whole body was received
+    let service = simple_service(move |mut req: Message<&'static str, Body<u32, io::Error>>| {
+        let body = req.take_body().unwrap();
+        let mut tx = tx.clone();
+
+        body.for_each(move |chunk| {
+            mpsc::UnboundedSender::send(&mut tx, chunk).unwrap();
+            Ok(())
+        }).and_then(move |_| {
+            Ok(Message::WithoutBody(*req.get_ref()))
+        })
+    });
+
+    let limits = mock::Limits { max_in_flight: Some(1), ..Default::default() };
+    let (mut mock, _other) = mock::multiplex_server_with_limits(limits, service);
+    mock.send(msg_with_body(0, "first"));
+    mock.send(msg_with_body(1, "second"));
+
+    // The body of the request in flight is read past the waiting request
+    let mut rx = rx.wait();
+    mock.send(Frame::Body { id: 0, chunk: Some(7) });
+    assert_eq!(7, rx.next().unwrap().unwrap());
+
+    mock.send(Frame::Body { id: 0, chunk: None });
+
+    let wr = mock.next_write();
+    assert_eq!(&0, wr.request_id());
+    assert_eq!("first", wr.unwrap_msg());
+
+    // The waiting request takes the free slot
+    mock.send(Frame::Body { id: 1, chunk: Some(8) });
+    assert_eq!(8, rx.next().unwrap().unwrap());
+
+    mock.send(Frame::Body { id: 1, chunk: None });
+
+    let wr = mock.next_write();
+    assert_eq!(&1, wr.request_id());
+    assert_eq!("second", wr.unwrap_msg());
+
+    mock.allow_and_assert_drop();
+}
+
 #[test]
 fn test_basic_streaming_response_body() {
     let (tx, rx) = mpsc::channel(1);
@@ -324,6 +425,75 @@ fn test_basic_streaming_response_body() {
     mock.allow_and_assert_drop();
 }
 
+#[test]
+fn test_inline_request_body() {
+    let service = simple_service(|mut req: Message<&'static str, Body<u32, io::Error>>| {
+        assert_eq!(req, "omg");
+
+        // Echo the body back
+        let body = req.take_body().unwrap();
+        future::ok(Message::WithBody("hi2u", body.boxed()))
+    });
+
+    let (mut mock, _other) = mock::multiplex_server(service);
+    mock.send(Frame::Inline { id: 3, message: "omg", chunk: 7, solo: false });
+
+    let wr = mock.next_write();
+    assert_eq!(&3, wr.request_id());
+    assert_eq!(wr.unwrap_msg(), "hi2u");
+
+    assert_eq!(Some(7), mock.next_write().unwrap_body());
+    assert_eq!(None, mock.next_write().unwrap_body());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_throttled_response_body_not_polled() {
+    let (body_tx, body_rx) = std_mpsc::channel();
+
+    let service = simple_service(move |_| {
+        let (sender, body): (BodySender<u32, io::Error>, _) = Body::channel();
+        body_tx.send(sender).unwrap();
+        future::ok(Message::WithBody("hi2u", body.boxed()))
+    });
+
+    let (mut mock, _other) = mock::multiplex_server(service);
+    mock.throttle_bodies();
+    mock.send(msg(3, "want-body"));
+
+    let wr = mock.next_write();
+    assert_eq!(&3, wr.request_id());
+    assert_eq!(wr.unwrap_msg(), "hi2u");
+
+    // Sending resolves once the dispatcher has taken the chunk
+    let sender = body_rx.recv().unwrap();
+    let (taken_tx, taken_rx) = std_mpsc::channel();
+    thread::spawn(move || {
+        let sender = sender.send(Ok(1)).wait().unwrap();
+        taken_tx.send(sender).unwrap();
+    });
+
+    // Throttled by the transport, the body is not polled
+    assert!(taken_rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+    mock.release_bodies();
+
+    let wr = mock.next_write();
+    assert_eq!(&3, wr.request_id());
+    assert_eq!(Some(1), wr.unwrap_body());
+
+    let mut sender = taken_rx.recv().unwrap();
+    assert!(future::poll_fn(|| sender.poll_ready()).wait().is_ok());
+    drop(sender);
+
+    let wr = mock.next_write();
+    assert_eq!(&3, wr.request_id());
+    assert_eq!(None, wr.unwrap_body());
+
+    mock.allow_and_assert_drop();
+}
+
 #[test]
 fn test_basic_streaming_request_body_read_then_respond() {
     let (tx, rx) = mpsc::unbounded();
@@ -365,6 +535,50 @@ fn test_basic_streaming_request_body_read_then_respond() {
     mock.allow_and_assert_drop();
 }
 
+#[test]
+fn test_fetching_response_body_on_demand() {
+    let fetches = Arc::new(AtomicUsize::new(0));
+    let service_fetches = fetches.clone();
+
+    let service = simple_service(move |_| {
+        // A cursor over three rows
+        let fetches = service_fetches.clone();
+        let body = FetchBody::new(move || {
+            let row = fetches.fetch_add(1, Ordering::SeqCst) as u32;
+            future::ok::<_, io::Error>(if row < 3 { Some(row) } else { None })
+        });
+
+        future::ok(Message::WithBody("rows", body.boxed()))
+    });
+
+    let (mut mock, _other) = mock::multiplex_server(service);
+    mock.throttle_bodies();
+    mock.send(msg(3, "select"));
+
+    let wr = mock.next_write();
+    assert_eq!(&3, wr.request_id());
+    assert_eq!(wr.unwrap_msg(), "rows");
+
+    // Throttled by the transport, nothing is fetched
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(0, fetches.load(Ordering::SeqCst));
+
+    mock.release_bodies();
+
+    for row in 0..3 {
+        let wr = mock.next_write();
+        assert_eq!(&3, wr.request_id());
+        assert_eq!(Some(row), wr.unwrap_body());
+    }
+
+    let wr = mock.next_write();
+    assert_eq!(&3, wr.request_id());
+    assert_eq!(None, wr.unwrap_body());
+    assert_eq!(4, fetches.load(Ordering::SeqCst));
+
+    mock.allow_and_assert_drop();
+}
+
 #[test]
 fn test_interleaving_request_body_chunks() {
     let (tx, rx) = mpsc::unbounded();
@@ -469,6 +683,313 @@ fn test_read_error_during_stream() {
 fn test_error_handling_before_message_dispatched() {
 }
 
+#[test]
+fn test_frame_budget_yields_and_resumes() {
+    let service = simple_service(|req: Message<&'static str, Body<u32, io::Error>>| {
+        let body = stream::iter(vec![Ok(1), Ok(2)]).boxed();
+        future::finished(Message::WithBody(*req.get_ref(), body))
+    });
+
+    // Only two frames are processed per poll, the queued requests and the
+    // response bodies are picked up on the following ticks
+    let (mut mock, _other) = mock::multiplex_server_with_budget(Some(2), service);
+
+    for id in 0..3 {
+        mock.send(msg(id, "hello"));
+    }
+
+    let mut bodies = vec![vec![]; 3];
+    let mut responses = 0;
+
+    while bodies.iter().any(|body| body.last() != Some(&None)) {
+        let wr = mock.next_write();
+        let id = *wr.request_id() as usize;
+
+        match wr {
+            Frame::Message { message, .. } => {
+                assert_eq!("hello", message);
+                responses += 1;
+            }
+            Frame::Body { chunk, .. } => bodies[id].push(chunk),
+            _ => panic!("unexpected frame"),
+        }
+    }
+
+    assert_eq!(3, responses);
+
+    for body in bodies {
+        assert_eq!(vec![Some(1), Some(2), None], body);
+    }
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_closing_connection_on_too_many_errors() {
+    let service = simple_service(move |_| {
+        future::err(io::Error::new(io::ErrorKind::Other, "nope"))
+    });
+
+    let limits = mock::Limits { max_error_frames: Some(2), ..Default::default() };
+    let (mut mock, _other) = mock::multiplex_server_with_limits(limits, service);
+
+    for id in 0..3 {
+        mock.send(msg(id, "hello"));
+    }
+
+    // The third error closes the connection instead of being written
+    for _ in 0..2 {
+        assert_eq!(io::ErrorKind::Other, mock.next_write().unwrap_err().kind());
+    }
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_acknowledging_requests() {
+    let (tx, rx) = oneshot::channel();
+    let rx = RefCell::new(Some(rx));
+
+    let service = simple_service(move |req| {
+        assert_eq!(req, "ping");
+        rx.borrow_mut().take().unwrap().then(|res| res.unwrap())
+    });
+
+    let limits = mock::Limits { ack_requests: true, ..Default::default() };
+    let (mut mock, _other) = mock::multiplex_server_with_limits(limits, service);
+
+    mock.send(msg(0, "ping"));
+
+    // The request is acknowledged while the service is still busy with it
+    let wr = mock.next_write();
+    assert_eq!(&0, wr.request_id());
+    assert!(match wr { Frame::Ack { .. } => true, _ => false });
+
+    tx.complete(Ok(Message::WithoutBody("pong")));
+
+    let wr = mock.next_write();
+    assert_eq!(&0, wr.request_id());
+    assert_eq!("pong", wr.unwrap_msg());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_negotiating_encoding() {
+    let service = simple_service(|req| {
+        assert_eq!(req, "ping");
+        future::ok(Message::WithoutBody("pong"))
+    });
+
+    let encodings = Encodings::new().with("gzip").with("deflate");
+    let limits = mock::Limits { encodings: encodings.clone(), ..Default::default() };
+    let (mut mock, _other) = mock::multiplex_server_with_limits(limits, service);
+
+    mock.peer_advertises(Encodings::new().with("br").with("deflate"));
+    mock.send(msg(0, "ping"));
+
+    assert_eq!("pong", mock.next_write().unwrap_msg());
+
+    // The server's most preferred encoding the client supports
+    assert_eq!(Some(encodings), mock.advertised_encodings());
+    assert_eq!(Some(Some("deflate".to_string())), mock.encoding());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_blocking_on_full_frame_buffer() {
+    let (tx, rx) = std_mpsc::channel();
+    let tx = RefCell::new(tx);
+
+    // Hands the request body to the test without consuming it
+    let service = simple_service(move |mut req: Message<&'static str, Body<u32, io::Error>>| {
+        tx.borrow_mut().send(req.take_body().unwrap()).unwrap();
+        future::ok(Message::WithoutBody("ok"))
+    });
+
+    let limits = mock::Limits {
+        max_buffered_frames_per_exchange: Some(2),
+        spill_policy: Some(SpillPolicy::Block),
+        ..Default::default()
+    };
+    let (mut mock, _other) = mock::multiplex_server_with_limits(limits, service);
+
+    mock.send(msg_with_body(0, "upload"));
+    assert_eq!("ok", mock.next_write().unwrap_msg());
+
+    let body = rx.recv().unwrap();
+
+    for i in 0..10 {
+        mock.send(Frame::Body { id: 0, chunk: Some(i) });
+    }
+    mock.send(Frame::Body { id: 0, chunk: None });
+
+    // The frames beyond the cap are left with the transport
+    thread::sleep(Duration::from_millis(100));
+    assert!(mock.reads() < 12, "read {} frames", mock.reads());
+
+    // Reading resumes as the body is consumed
+    let chunks = body.wait().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!((0..10).collect::<Vec<_>>(), chunks);
+    assert!(mock.canceled().is_empty());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_erroring_exchange_on_full_frame_buffer() {
+    let (tx, rx) = std_mpsc::channel();
+    let tx = RefCell::new(tx);
+
+    let service = simple_service(move |mut req: Message<&'static str, Body<u32, io::Error>>| {
+        if let Some(body) = req.take_body() {
+            tx.borrow_mut().send(body).unwrap();
+        }
+
+        future::ok(Message::WithoutBody("ok"))
+    });
+
+    let limits = mock::Limits {
+        max_buffered_frames_per_exchange: Some(2),
+        spill_policy: Some(SpillPolicy::ErrorExchange),
+        ..Default::default()
+    };
+    let (mut mock, _other) = mock::multiplex_server_with_limits(limits, service);
+
+    mock.send(msg_with_body(0, "upload"));
+    assert_eq!("ok", mock.next_write().unwrap_msg());
+
+    let body = rx.recv().unwrap();
+
+    for i in 0..10 {
+        mock.send(Frame::Body { id: 0, chunk: Some(i) });
+    }
+
+    // The other exchanges carry on
+    mock.send(msg(2, "ping"));
+    let wr = mock.next_write();
+    assert_eq!(&2, wr.request_id());
+    assert_eq!("ok", wr.unwrap_msg());
+
+    // The body ends with an error in place of the chunks that didn't fit
+    let mut body = body.wait();
+    let mut chunks = 0;
+
+    let err = loop {
+        match body.next() {
+            Some(Ok(_)) => chunks += 1,
+            Some(Err(e)) => break e,
+            None => panic!("body completed"),
+        }
+    };
+
+    assert!(chunks < 10);
+    assert_eq!(io::ErrorKind::Other, err.kind());
+    assert!(body.next().is_none());
+    assert_eq!(1, mock.canceled().len());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_dropping_connection_on_full_frame_buffer() {
+    let (tx, rx) = std_mpsc::channel();
+    let tx = RefCell::new(tx);
+
+    let service = simple_service(move |mut req: Message<&'static str, Body<u32, io::Error>>| {
+        tx.borrow_mut().send(req.take_body().unwrap()).unwrap();
+        future::ok(Message::WithoutBody("ok"))
+    });
+
+    let limits = mock::Limits {
+        max_buffered_frames_per_exchange: Some(2),
+        spill_policy: Some(SpillPolicy::DropConnection),
+        ..Default::default()
+    };
+    let (mut mock, _other) = mock::multiplex_server_with_limits(limits, service);
+
+    mock.send(msg_with_body(0, "upload"));
+    assert_eq!("ok", mock.next_write().unwrap_msg());
+
+    let _body = rx.recv().unwrap();
+
+    for i in 0..10 {
+        mock.send(Frame::Body { id: 0, chunk: Some(i) });
+    }
+
+    // The connection is closed while the body is still held
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_resynchronizing_after_read_error() {
+    let service = simple_service(|req| {
+        assert_eq!(req, "ping");
+        future::ok(Message::WithoutBody("pong"))
+    });
+
+    let (mut mock, _other) = mock::multiplex_server(service);
+    mock.resynchronize_on_errors();
+
+    mock.send(msg(0, "ping"));
+    assert_eq!("pong", mock.next_write().unwrap_msg());
+
+    // The transport skips the bad frame, the connection carries on
+    mock.error(io::Error::new(io::ErrorKind::InvalidData, "bad frame"));
+    mock.send(msg(2, "ping"));
+
+    let wr = mock.next_write();
+    assert_eq!(&2, wr.request_id());
+    assert_eq!("pong", wr.unwrap_msg());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_closing_connection_on_reused_request_id() {
+    let (tx, rx) = std_mpsc::channel();
+    let tx = RefCell::new(tx);
+
+    let service = simple_service(move |mut req: Message<&'static str, Body<u32, io::Error>>| {
+        tx.borrow_mut().send(req.take_body().unwrap()).unwrap();
+        future::ok(Message::WithoutBody("ok"))
+    });
+
+    let limits = mock::Limits { strict: true, ..Default::default() };
+    let (mut mock, _other) = mock::multiplex_server_with_limits(limits, service);
+
+    mock.send(msg_with_body(0, "upload"));
+    assert_eq!("ok", mock.next_write().unwrap_msg());
+
+    let _body = rx.recv().unwrap();
+
+    // The body of the first request is still streaming
+    mock.send(msg(0, "again"));
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_closing_connection_on_solo_request() {
+    let service = simple_service(|req| {
+        assert_eq!(req, "ping");
+        future::ok(Message::WithoutBody("pong"))
+    });
+
+    let limits = mock::Limits { strict: true, ..Default::default() };
+    let (mut mock, _other) = mock::multiplex_server_with_limits(limits, service);
+
+    mock.send(Frame::Message {
+        id: 0,
+        message: "ping",
+        body: false,
+        solo: true,
+    });
+
+    mock.allow_and_assert_drop();
+}
+
 fn msg(id: u64, msg: &'static str) -> Frame<u64, &'static str, u32, io::Error> {
     Frame::Message {
         id: id,
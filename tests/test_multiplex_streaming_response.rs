@@ -0,0 +1,119 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net;
+use std::thread;
+
+use futures::Stream;
+use tokio_core::io::{Codec, EasyBuf, Framed, Io};
+use tokio_core::reactor::Core;
+use tokio_proto::TcpClient;
+use tokio_proto::multiplex::{ResponseFrame, StreamingResponseProto};
+use tokio_proto::streaming::multiplex::Counter;
+use tokio_service::Service;
+
+mod support;
+use support::line::MuxLineCodec;
+
+// Lines of the form `id *msg` start a response with a body, `id +chunk`
+// carry a chunk of it and `id .` end it
+struct BodyLineCodec;
+
+impl Codec for BodyLineCodec {
+    type In = (u64, ResponseFrame<String, String>);
+    type Out = (u64, String);
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<Self::In>> {
+        let (id, line) = match try!(MuxLineCodec.decode(buf)) {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+
+        let frame = if let Some(message) = line.strip_prefix('*') {
+            ResponseFrame::Message { message: message.to_string(), body: true }
+        } else if let Some(chunk) = line.strip_prefix('+') {
+            ResponseFrame::Body { chunk: Some(chunk.to_string()) }
+        } else if line == "." {
+            ResponseFrame::Body { chunk: None }
+        } else {
+            ResponseFrame::Message { message: line, body: false }
+        };
+
+        Ok(Some((id, frame)))
+    }
+
+    fn encode(&mut self, msg: (u64, String), buf: &mut Vec<u8>) -> io::Result<()> {
+        MuxLineCodec.encode(msg, buf)
+    }
+}
+
+struct BodyLineProto;
+
+impl<T: Io + 'static> StreamingResponseProto<T> for BodyLineProto {
+    type Request = String;
+    type Response = String;
+    type ResponseBody = String;
+    type RequestId = u64;
+    type Transport = Framed<T, BodyLineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+    type RequestIdSource = Counter;
+
+    fn requestid_source(&self) -> Counter {
+        Counter::new()
+    }
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(BodyLineCodec))
+    }
+}
+
+// Answers `list` with a streamed listing and anything else without a body
+fn serve() -> net::SocketAddr {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let (socket, _) = listener.accept().unwrap();
+        let mut writer = socket.try_clone().unwrap();
+
+        for line in BufReader::new(socket).lines() {
+            let line = line.unwrap();
+            let mut parts = line.splitn(2, ' ');
+            let id = parts.next().unwrap();
+
+            let response = match parts.next().unwrap() {
+                "list" => format!("{0} *files\n{0} +a\n{0} +b\n{0} .\n", id),
+                other => format!("{} {}\n", id, other),
+            };
+
+            writer.write_all(response.as_bytes()).unwrap();
+        }
+    });
+
+    addr
+}
+
+#[test]
+fn test_streamed_response_body() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = serve();
+    let client = core.run(TcpClient::new(BodyLineProto).connect(&addr, &handle)).unwrap();
+
+    let (msg, body) = core.run(client.call("list".to_string())).unwrap();
+    assert_eq!("files", msg);
+
+    let chunks = core.run(body.collect()).unwrap();
+    assert_eq!(vec!["a", "b"], chunks);
+
+    // Responses without a body come with an empty one
+    let (msg, body) = core.run(client.call("hello".to_string())).unwrap();
+    assert_eq!("hello", msg);
+    assert!(core.run(body.collect()).unwrap().is_empty());
+}
@@ -0,0 +1,68 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io;
+
+use futures::{Future, Stream};
+use futures::future;
+use tokio_core::io::{read_exact, write_all};
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::Core;
+use tokio_proto::{BindClient, BindServer};
+use tokio_proto::negotiate::{Mode, Negotiate};
+use tokio_service::Service;
+
+mod support;
+use support::line::{LineProto, MuxLineProto, Echo};
+
+// The client announces the mode with a single byte
+fn announce(mode: Mode) -> Box<Fn(TcpStream) -> Box<Future<Item = (TcpStream, Mode), Error = io::Error>>> {
+    Box::new(move |io| {
+        let byte = match mode {
+            Mode::Pipeline => b'P',
+            Mode::Multiplex => b'M',
+        };
+
+        Box::new(write_all(io, [byte]).map(move |(io, _)| (io, mode)))
+    })
+}
+
+fn accept(io: TcpStream) -> Box<Future<Item = (TcpStream, Mode), Error = io::Error>> {
+    Box::new(read_exact(io, [0; 1]).map(|(io, byte)| {
+        let mode = if byte[0] == b'M' { Mode::Multiplex } else { Mode::Pipeline };
+        (io, mode)
+    }))
+}
+
+#[test]
+fn test_binds_negotiated_dispatcher() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let proto = Negotiate::new(accept, LineProto, MuxLineProto);
+    let server_handle = handle.clone();
+    let server = listener.incoming().for_each(move |(socket, _)| {
+        proto.bind_server(&server_handle, socket, Echo("echo:".to_string()));
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    for &mode in &[Mode::Pipeline, Mode::Multiplex] {
+        let proto = Negotiate::new(announce(mode), LineProto, MuxLineProto);
+        let socket = core.run(TcpStream::connect(&addr, &handle)).unwrap();
+        let client = proto.bind_client(&handle, socket);
+
+        let calls = (0..3).map(|i| client.call(i.to_string())).collect::<Vec<_>>();
+        let responses = core.run(future::join_all(calls)).unwrap();
+
+        assert_eq!(vec!["echo:0", "echo:1", "echo:2"], responses);
+    }
+}
@@ -0,0 +1,102 @@
+extern crate futures;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use futures::{stream, Async, Future};
+use futures::executor::{self, Notify};
+use futures::sync::oneshot;
+use tokio_proto::pipeline::pipeline_all;
+use tokio_service::Service;
+
+// Service whose responses are completed by the test
+#[derive(Clone)]
+struct Manual {
+    pending: Rc<RefCell<Vec<(u32, oneshot::Sender<u32>)>>>,
+}
+
+impl Service for Manual {
+    type Request = u32;
+    type Response = u32;
+    type Error = io::Error;
+    type Future = Box<Future<Item = u32, Error = io::Error>>;
+
+    fn call(&self, req: u32) -> Self::Future {
+        let (tx, rx) = oneshot::channel();
+        self.pending.borrow_mut().push((req, tx));
+        Box::new(rx.map_err(|_| io::Error::new(io::ErrorKind::Other, "canceled")))
+    }
+}
+
+struct Noop;
+
+impl Notify for Noop {
+    fn notify(&self, _id: usize) {}
+}
+
+fn complete(service: &Manual, req: u32) {
+    let mut pending = service.pending.borrow_mut();
+    let idx = pending.iter().position(|&(r, _)| r == req).unwrap();
+    let (_, tx) = pending.remove(idx);
+    tx.send(req * 10).unwrap();
+}
+
+#[test]
+fn test_responses_in_request_order() {
+    let service = Manual { pending: Rc::new(RefCell::new(vec![])) };
+    let requests = stream::iter_ok::<_, io::Error>(0..4);
+
+    let mut responses = executor::spawn(pipeline_all(service.clone(), requests, 2));
+    let notify = Arc::new(Noop);
+    let mut poll = || responses.poll_stream_notify(&notify, 0).unwrap();
+
+    // Only two requests may be in flight at once
+    assert!(poll().is_not_ready());
+    assert_eq!(2, service.pending.borrow().len());
+
+    // Completing the second request first yields nothing yet
+    complete(&service, 1);
+    assert!(poll().is_not_ready());
+
+    complete(&service, 0);
+    assert_eq!(Async::Ready(Some(0)), poll().map(|r| r.map(|r| r.unwrap())));
+    assert_eq!(Async::Ready(Some(10)), poll().map(|r| r.map(|r| r.unwrap())));
+
+    // The window refilled with the remaining requests
+    assert!(poll().is_not_ready());
+    assert_eq!(2, service.pending.borrow().len());
+
+    complete(&service, 3);
+    complete(&service, 2);
+    assert_eq!(Async::Ready(Some(20)), poll().map(|r| r.map(|r| r.unwrap())));
+    assert_eq!(Async::Ready(Some(30)), poll().map(|r| r.map(|r| r.unwrap())));
+    assert_eq!(Async::Ready(None), poll().map(|r| r.map(|r| r.unwrap())));
+}
+
+#[test]
+fn test_failed_request_does_not_end_batch() {
+    let service = Manual { pending: Rc::new(RefCell::new(vec![])) };
+    let requests = stream::iter_ok::<_, io::Error>(0..2);
+
+    let mut responses = executor::spawn(pipeline_all(service.clone(), requests, 2));
+    let notify = Arc::new(Noop);
+    let mut poll = || responses.poll_stream_notify(&notify, 0).unwrap();
+
+    assert!(poll().is_not_ready());
+
+    // Dropping the sender fails the first request
+    service.pending.borrow_mut().remove(0);
+    complete(&service, 1);
+
+    match poll() {
+        Async::Ready(Some(Err(e))) => assert_eq!(io::ErrorKind::Other, e.kind()),
+        _ => panic!("expected an error response"),
+    }
+
+    assert_eq!(Async::Ready(Some(10)), poll().map(|r| r.map(|r| r.unwrap())));
+    assert_eq!(Async::Ready(None), poll().map(|r| r.map(|r| r.unwrap())));
+}
@@ -6,21 +6,26 @@ extern crate tokio_proto;
 extern crate tokio_service;
 extern crate rand;
 
-#[macro_use]
 extern crate log;
 extern crate env_logger;
 
 use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
 use std::time::Duration;
 
 use futures::sync::mpsc;
-use futures::{Future, Stream, Sink};
-use tokio_proto::streaming::Message;
+use futures::{future, stream, Future, Stream, Sink};
+use tokio_core::reactor::Core;
+use tokio_proto::{conformance, BindClient, BindServer};
+use tokio_proto::pipeline::Pipeline;
+use tokio_proto::streaming::{Body, Message};
 use tokio_proto::streaming::pipeline::Frame;
 use tokio_service::Service;
 
 mod support;
+use support::line::{Echo, LineProto};
 use support::mock;
 
 #[test]
@@ -35,6 +40,56 @@ fn test_ping_pong_close() {
 }
 
 
+#[test]
+fn test_tagged_requests() {
+    let limits = mock::Limits { tag_requests: true, ..mock::Limits::default() };
+    let (mut mock, service, _other) = mock::pipeline_client_with_limits(limits);
+
+    let first = service.call(Message::WithoutBody("ping"));
+    let second = service.call(Message::WithoutBody("ping"));
+    assert_eq!("0 ping", mock.next_write().unwrap_msg());
+    assert_eq!("1 ping", mock.next_write().unwrap_msg());
+
+    // Tags are stripped from responses
+    mock.send(msg("0 pong"));
+    assert_eq!("pong", first.wait().unwrap().into_inner());
+
+    // An out of order response fails the connection
+    mock.send(msg("2 pong"));
+    let err = second.wait().unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+    mock.allow_and_assert_drop();
+}
+
+
+#[test]
+fn test_shutdown_write_after_drain() {
+    let (mut mock, service, _other) = mock::pipeline_client();
+
+    let pong = service.call(Message::WithoutBody("ping"));
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+    assert!(!mock.is_write_shutdown());
+
+    // No more requests will be sent
+    drop(service);
+
+    for _ in 0..100 {
+        if mock.is_write_shutdown() {
+            break;
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    assert!(mock.is_write_shutdown());
+
+    // Responses are still read
+    mock.send(msg("pong"));
+    assert_eq!("pong", pong.wait().unwrap().into_inner());
+    mock.allow_and_assert_drop();
+}
+
 #[test]
 #[ignore]
 fn test_response_ready_before_request_sent() {
@@ -74,6 +129,113 @@ fn test_streaming_request_body() {
     mock.allow_and_assert_drop();
 }
 
+#[test]
+fn test_request_body_completion() {
+    let (mut mock, service, _other) = mock::pipeline_client();
+
+    let (tx, mut body) = Body::pair();
+    let mut complete = body.completion();
+
+    let pong = service.call(Message::WithBody("ping", body.boxed()));
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+
+    let tx = tx.send(Ok(1)).wait().unwrap();
+    assert_eq!(Some(1), mock.next_write().unwrap_body());
+    assert!(!is_ready(&mut complete));
+
+    drop(tx);
+    assert_eq!(None, mock.next_write().unwrap_body());
+
+    // Resolves once the final chunk has been flushed, before the response
+    complete.wait().unwrap();
+
+    mock.send(msg("pong"));
+    assert_eq!("pong", pong.wait().unwrap().into_inner());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_request_body_progress() {
+    let (mut mock, service, _other) = mock::pipeline_client();
+
+    let sent = Arc::new(AtomicUsize::new(0));
+    let progress = sent.clone();
+
+    let mut body = Body::from(7);
+    body.on_chunk(move |chunk| {
+        progress.fetch_add(*chunk as usize, Ordering::SeqCst);
+    });
+
+    let pong = service.call(Message::WithBody("ping", body.boxed()));
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+    assert_eq!(Some(7), mock.next_write().unwrap_body());
+    assert_eq!(None, mock.next_write().unwrap_body());
+    assert_eq!(7, sent.load(Ordering::SeqCst));
+
+    mock.send(msg("pong"));
+    assert_eq!("pong", pong.wait().unwrap().into_inner());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_request_body_completion_fails_on_error() {
+    let (mut mock, service, _other) = mock::pipeline_client();
+
+    let (tx, mut body) = Body::pair();
+    let complete = body.completion();
+
+    let pong = service.call(Message::WithBody("ping", body.boxed()));
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+
+    mock.error(io::Error::new(io::ErrorKind::Other, "boom"));
+
+    assert!(complete.wait().is_err());
+    assert!(pong.wait().is_err());
+    drop(tx);
+}
+
+#[test]
+fn test_coalesce_ready_request_body_chunks() {
+    let (mut mock, service, _other) = mock::pipeline_client();
+    mock.coalesce_body_up_to(6);
+
+    let body = stream::iter_ok(vec![1, 2, 3, 4]).boxed();
+    let pong = service.call(Message::WithBody("ping", body));
+
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+    assert_eq!(Some(6), mock.next_write().unwrap_body());
+    assert_eq!(Some(4), mock.next_write().unwrap_body());
+    assert_eq!(None, mock.next_write().unwrap_body());
+
+    mock.send(msg("pong"));
+    assert_eq!("pong", pong.wait().unwrap().into_inner());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_split_oversized_request_body_chunks() {
+    let (mut mock, service, _other) = mock::pipeline_client();
+    mock.split_body_over(4);
+
+    let body = stream::iter_ok(vec![10, 3]).boxed();
+    let pong = service.call(Message::WithBody("ping", body));
+
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+    assert_eq!(Some(4), mock.next_write().unwrap_body());
+    assert_eq!(Some(4), mock.next_write().unwrap_body());
+    assert_eq!(Some(2), mock.next_write().unwrap_body());
+    assert_eq!(Some(3), mock.next_write().unwrap_body());
+    assert_eq!(None, mock.next_write().unwrap_body());
+
+    mock.send(msg("pong"));
+    assert_eq!("pong", pong.wait().unwrap().into_inner());
+
+    mock.allow_and_assert_drop();
+}
+
 #[test]
 #[ignore]
 fn test_streaming_response_body() {
@@ -117,6 +279,28 @@ fn test_streaming_client_transport_dropped() {
     mock.allow_and_assert_drop();
 }
 
+#[test]
+fn test_backlog_of_canceled_requests_is_skipped() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (client, server) = conformance::pipe();
+
+    BindServer::<Pipeline, _>::bind_server(&LineProto, &handle, server, Echo("echo:".to_string()));
+    let service = BindClient::<Pipeline, _>::bind_client(&LineProto, &handle, client);
+
+    // Dropped before the connection got to write any of them
+    for _ in 0..100_000 {
+        drop(service.call("ping".to_string()));
+    }
+
+    let pong = core.run(service.call("ping".to_string())).unwrap();
+    assert_eq!("echo:ping", pong);
+}
+
+fn is_ready<F: Future>(f: &mut F) -> bool {
+    future::lazy(|| Ok::<_, ()>(f.poll().map(|a| a.is_ready()).unwrap_or(true))).wait().unwrap()
+}
+
 fn msg(msg: &'static str) -> Frame<&'static str, u32, io::Error> {
     Frame::Message {
         message: msg,
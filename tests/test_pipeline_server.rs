@@ -12,7 +12,8 @@ extern crate env_logger;
 
 use std::cell::RefCell;
 use std::io;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
 use std::time::Duration;
 
@@ -173,6 +174,25 @@ fn test_returning_error_from_service() {
     mock.allow_and_assert_drop();
 }
 
+#[test]
+fn test_closing_connection_on_too_many_errors() {
+    let service = simple_service(move |_| {
+        future::err(io::Error::new(io::ErrorKind::Other, "nope"))
+    });
+
+    let limits = mock::Limits { max_error_frames: Some(2), ..Default::default() };
+    let (mut mock, _other) = mock::pipeline_server_with_limits(limits, service);
+
+    for req in &["one", "two", "three"] {
+        mock.send(msg(req));
+    }
+
+    // The third error closes the connection instead of being written
+    assert_eq!(io::ErrorKind::Other, mock.next_write().unwrap_err().kind());
+    assert_eq!(io::ErrorKind::Other, mock.next_write().unwrap_err().kind());
+    mock.allow_and_assert_drop();
+}
+
 #[test]
 fn test_reading_error_frame_from_transport() {
     let service = simple_service(move |_| {
@@ -197,6 +217,23 @@ fn test_reading_io_error_from_transport() {
     mock.allow_and_assert_drop();
 }
 
+#[test]
+fn test_resynchronizing_after_read_error() {
+    let service = simple_service(move |req| {
+        assert_eq!(req, "hello");
+        future::finished(Message::WithoutBody("goodbye"))
+    });
+
+    let (mut mock, _other) = mock::pipeline_server(service);
+    mock.resynchronize_on_errors();
+
+    mock.error(io::Error::new(io::ErrorKind::InvalidData, "bad frame"));
+    mock.send(msg("hello"));
+    assert_eq!("goodbye", mock.next_write().unwrap_msg());
+
+    mock.allow_and_assert_drop();
+}
+
 #[test]
 #[ignore]
 fn test_reading_error_while_pipelining_from_transport() {
@@ -243,6 +280,57 @@ fn test_streaming_request_body_then_responding() {
     mock.allow_and_assert_drop();
 }
 
+#[test]
+fn test_inline_request_body() {
+    let service = simple_service(|mut req: Message<&'static str, Body<u32, io::Error>>| {
+        assert_eq!(req, "omg");
+
+        // Echo the body back
+        let body = req.take_body().unwrap();
+        future::ok(Message::WithBody("hi2u", body.boxed()))
+    });
+
+    let (mut mock, _other) = mock::pipeline_server(service);
+    mock.send(Frame::Inline { message: "omg", chunk: 7 });
+
+    assert_eq!(mock.next_write().unwrap_msg(), "hi2u");
+    assert_eq!(Some(7), mock.next_write().unwrap_body());
+    assert_eq!(None, mock.next_write().unwrap_body());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_request_body_progress() {
+    let received = Arc::new(AtomicUsize::new(0));
+    let progress = received.clone();
+
+    let service = simple_service(move |mut req: Message<&'static str, Body<u32, io::Error>>| {
+        let mut body = req.take_body().unwrap();
+        let progress = progress.clone();
+
+        body.on_chunk(move |chunk| {
+            progress.fetch_add(*chunk as usize, Ordering::SeqCst);
+        });
+
+        body.for_each(|_| Ok(()))
+            .and_then(|_| future::finished(Message::WithoutBody("done")))
+    });
+
+    let (mut mock, _other) = mock::pipeline_server(service);
+    mock.send(msg_with_body("upload"));
+
+    for i in 0..5 {
+        mock.send(Frame::Body { chunk: Some(i) });
+    }
+    mock.send(Frame::Body { chunk: None });
+
+    assert_eq!(mock.next_write().unwrap_msg(), "done");
+    assert_eq!(10, received.load(Ordering::SeqCst));
+
+    mock.allow_and_assert_drop();
+}
+
 #[test]
 fn test_responding_then_streaming_request_body() {
     let (tx, rx) = mpsc::unbounded();
@@ -382,6 +470,27 @@ fn test_streaming_response_body() {
     mock.allow_and_assert_drop();
 }
 
+#[test]
+fn test_frame_budget_yields_and_resumes() {
+    let service = simple_service(|req: Message<&'static str, Body<u32, io::Error>>| {
+        future::finished(Message::WithoutBody(*req.get_ref()))
+    });
+
+    // Only a single frame is processed per poll, the queued requests are
+    // picked up on the following ticks
+    let (mut mock, _other) = mock::pipeline_server_with_budget(Some(1), service);
+
+    for req in &["one", "two", "three", "four"] {
+        mock.send(msg(req));
+    }
+
+    for req in &["one", "two", "three", "four"] {
+        assert_eq!(*req, mock.next_write().unwrap_msg());
+    }
+
+    mock.allow_and_assert_drop();
+}
+
 fn msg(msg: &'static str) -> Frame<&'static str, u32, io::Error> {
     Frame::Message { message: msg, body: false }
 }
@@ -0,0 +1,115 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::{Future, Stream};
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::{Core, Handle, Timeout};
+use tokio_proto::{pool, BindServer, Tags, TcpClient};
+use tokio_service::Service;
+
+mod support;
+use support::line::{LineProto, Echo};
+
+// Echoes lines on connections tagged with `()`, counting the connections
+fn serve_counting(handle: &Handle) -> (SocketAddr, Tags<()>, Arc<Mutex<usize>>) {
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let tags = Tags::new();
+    let accepted = Arc::new(Mutex::new(0));
+
+    let server_tags = tags.clone();
+    let server_accepted = accepted.clone();
+    let server_handle = handle.clone();
+    let server = listener.incoming().for_each(move |(socket, _)| {
+        *server_accepted.lock().unwrap() += 1;
+        LineProto.bind_server(&server_handle, server_tags.tag(socket, ()), Echo(String::new()));
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    (addr, tags, accepted)
+}
+
+#[test]
+fn test_pool_spreads_requests_across_connections() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let (addr, _tags, accepted) = serve_counting(&handle);
+    let client = pool::Client::new(&TcpClient::new(LineProto), &addr, &handle, 2, 4);
+
+    let responses = vec!["a", "b", "c", "d"].into_iter().map(|req| {
+        client.call(req.to_string())
+    }).collect::<Vec<_>>();
+
+    let responses = core.run(futures::future::join_all(responses)).unwrap();
+    assert_eq!(vec!["a", "b", "c", "d"], responses);
+
+    // Every connection of the pool took requests
+    assert_eq!(2, *accepted.lock().unwrap());
+}
+
+#[test]
+fn test_pool_reconnects_dead_connections() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let (addr, tags, accepted) = serve_counting(&handle);
+    let client = pool::Client::new(&TcpClient::new(LineProto), &addr, &handle, 2, 4);
+
+    for req in &["a", "b"] {
+        core.run(client.call(req.to_string())).unwrap();
+    }
+
+    // Close both connections from the server side and give the client time
+    // to notice
+    assert_eq!(2, tags.shutdown_tag(&()));
+    let timeout = Timeout::new(Duration::from_millis(100), &handle).unwrap();
+    core.run(timeout).unwrap();
+
+    // The requests on the dead connections fail...
+    for req in &["c", "d"] {
+        let err = core.run(client.call(req.to_string())).unwrap_err();
+        assert_eq!(io::ErrorKind::BrokenPipe, err.kind());
+    }
+
+    // ...and the next ones reconnect
+    for req in &["e", "f"] {
+        let res = core.run(client.call(req.to_string())).unwrap();
+        assert_eq!(*req, res);
+    }
+
+    assert_eq!(4, *accepted.lock().unwrap());
+}
+
+#[test]
+fn test_pool_answers_rejected_calls_with_busy_response() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let (addr, _tags, _) = serve_counting(&handle);
+    let client = pool::Client::new(&TcpClient::new(LineProto), &addr, &handle, 1, 1);
+    client.busy_response(|| "busy".to_string());
+
+    // The second call finds the queue of the connecting connection full
+    let first = client.call("a".to_string());
+    let second = client.call("b".to_string());
+
+    let (first, second) = core.run(first.join(second)).unwrap();
+    assert_eq!("a", first);
+    assert_eq!("busy", second);
+
+    // Once connected, calls go through again
+    assert_eq!("c", core.run(client.call("c".to_string())).unwrap());
+}
@@ -0,0 +1,157 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::collections::VecDeque;
+use std::io;
+
+use futures::stream::Empty;
+use futures::{Async, Future, Poll, Stream};
+use tokio_core::io::{Codec, EasyBuf, Framed, Io};
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::{Core, Handle};
+use tokio_proto::streaming::pipeline::advanced::{self, Pipeline, PipelineMessage};
+use tokio_proto::streaming::pipeline::{Frame, PipelineConfig};
+use tokio_proto::streaming::{Body, Message};
+use tokio_proto::{BindServer, ProtocolKind, TcpClient};
+use tokio_service::Service;
+
+mod support;
+use support::line::{LineCodec, LineProto, Echo};
+
+// A protocol kind of its own: pipelined requests without bodies, driven by
+// the pipeline driver of the crate
+struct Plain;
+
+struct PlainProto;
+
+struct PlainCodec;
+
+type PlainFrame = Frame<String, (), io::Error>;
+
+struct Dispatch<T, S: Service> {
+    transport: Framed<T, PlainCodec>,
+    service: S,
+    in_flight: VecDeque<S::Future>,
+}
+
+impl Codec for PlainCodec {
+    type In = PlainFrame;
+    type Out = PlainFrame;
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<PlainFrame>> {
+        let line = try!(LineCodec.decode(buf));
+        Ok(line.map(|line| Frame::Message { message: line, body: false }))
+    }
+
+    fn encode(&mut self, frame: PlainFrame, buf: &mut Vec<u8>) -> io::Result<()> {
+        match frame {
+            Frame::Message { message, .. } => LineCodec.encode(message, buf),
+            _ => Err(io::Error::new(io::ErrorKind::Other, "unexpected frame")),
+        }
+    }
+}
+
+impl<T, S> advanced::Dispatch for Dispatch<T, S>
+    where T: Io + 'static,
+          S: Service<Request = String, Response = String, Error = io::Error>,
+{
+    type Io = T;
+    type In = String;
+    type BodyIn = ();
+    type Out = String;
+    type BodyOut = ();
+    type Error = io::Error;
+    type Stream = Empty<(), io::Error>;
+    type Transport = Framed<T, PlainCodec>;
+
+    fn transport(&mut self) -> &mut Framed<T, PlainCodec> {
+        &mut self.transport
+    }
+
+    fn dispatch(&mut self,
+                message: PipelineMessage<String, Body<(), io::Error>, io::Error>)
+                -> io::Result<()> {
+        let message = try!(message);
+        self.in_flight.push_back(self.service.call(message.into_inner()));
+        Ok(())
+    }
+
+    fn poll(&mut self) -> Poll<Option<PipelineMessage<String, Self::Stream, io::Error>>, io::Error> {
+        let res = match self.in_flight.front_mut() {
+            Some(response) => match response.poll() {
+                Ok(Async::Ready(response)) => Ok(Message::WithoutBody(response)),
+                Err(e) => Err(e),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+            },
+            None => return Ok(Async::NotReady),
+        };
+
+        self.in_flight.pop_front();
+        Ok(Async::Ready(Some(res)))
+    }
+
+    fn has_in_flight(&self) -> bool {
+        !self.in_flight.is_empty()
+    }
+}
+
+impl<T, S> ProtocolKind<Dispatch<T, S>> for Plain
+    where T: Io + 'static,
+          S: Service<Request = String, Response = String, Error = io::Error>,
+{
+    type Frame = PlainFrame;
+    type Config = PipelineConfig;
+    type Driver = Pipeline<Dispatch<T, S>>;
+
+    fn drive(dispatch: Dispatch<T, S>, config: &PipelineConfig) -> Pipeline<Dispatch<T, S>> {
+        Pipeline::with_config(dispatch, config)
+    }
+}
+
+impl<T: Io + 'static> BindServer<Plain, T> for PlainProto {
+    type ServiceRequest = String;
+    type ServiceResponse = String;
+    type ServiceError = io::Error;
+
+    fn bind_server<S>(&self, handle: &Handle, io: T, service: S)
+        where S: Service<Request = String, Response = String, Error = io::Error> + 'static
+    {
+        let dispatch = Dispatch {
+            transport: io.framed(PlainCodec),
+            service: service,
+            in_flight: VecDeque::new(),
+        };
+
+        let task = Plain::drive(dispatch, &PipelineConfig::default());
+        handle.spawn(task.map_err(|_| ()));
+    }
+}
+
+#[test]
+fn test_custom_protocol_kind() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_handle = handle.clone();
+    let server = listener.incoming().for_each(move |(socket, _)| {
+        PlainProto.bind_server(&server_handle, socket, Echo("plain:".to_string()));
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    // Speaks the same wire format as the line protocol
+    let client = core.run(TcpClient::new(LineProto).connect(&addr, &handle)).unwrap();
+
+    for req in &["one", "two"] {
+        let res = core.run(client.call(req.to_string())).unwrap();
+        assert_eq!(format!("plain:{}", req), res);
+    }
+}
@@ -0,0 +1,78 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use futures::{Future, Stream};
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Core;
+use tokio_proto::{BindServer, Tags, TcpClient};
+use tokio_proto::instrument::IoMetrics;
+use tokio_proto::protos::admin::{Admin, AdminProto};
+use tokio_proto::protos::text_line::TextLineProto;
+use tokio_service::Service;
+
+mod support;
+use support::line::{LineProto, Echo};
+
+#[test]
+fn test_metrics_snapshot() {
+    let admin = Admin::new().io_metrics("api \"v1\"", &IoMetrics::new());
+
+    let zero = "{\"calls\":0,\"would_block\":0,\"errors\":0,\"bytes\":0,\"max_bytes\":0,\
+                \"sampled\":0,\"sampled_time_us\":0,\"max_latency_us\":0}";
+    let expected = format!("{{\"api \\\"v1\\\"\":{{\"read\":{0},\"write\":{0}}}}}", zero);
+    assert_eq!(expected, admin.answer("metrics"));
+
+    assert_eq!("{\"error\":\"unknown command\"}", admin.answer("shutdown"));
+}
+
+#[test]
+fn test_serves_connection_tables() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, &handle).unwrap();
+    let admin_listener = TcpListener::bind(&addr, &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let admin_addr = admin_listener.local_addr().unwrap();
+
+    // Tags connections by their order of arrival, odd or even
+    let tags = Tags::new();
+    let server_tags = tags.clone();
+    let server_handle = handle.clone();
+    let mut accepted = 0;
+    let server = listener.incoming().for_each(move |(socket, _)| {
+        let tag = if accepted % 2 == 0 { "even" } else { "odd" };
+        accepted += 1;
+        let socket = server_tags.tag(socket, tag.to_string());
+        LineProto.bind_server(&server_handle, socket, Echo(String::new()));
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    let admin = Admin::new().connections("lines", &tags);
+    let admin_handle = handle.clone();
+    let server = admin_listener.incoming().for_each(move |(socket, _)| {
+        AdminProto.bind_server(&admin_handle, socket, admin.clone());
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    let mut clients = vec![];
+
+    for _ in 0..3 {
+        let client = core.run(TcpClient::new(LineProto).connect(&addr, &handle)).unwrap();
+        assert_eq!("hi", core.run(client.call("hi".to_string())).unwrap());
+        clients.push(client);
+    }
+
+    let client = TcpClient::new(TextLineProto::default());
+    let client = core.run(client.connect(&admin_addr, &handle)).unwrap();
+
+    let stats = core.run(client.call("stats".to_string())).unwrap();
+    assert_eq!("{\"metrics\":{},\"connections\":{\"lines\":{\"even\":2,\"odd\":1}}}", stats);
+}
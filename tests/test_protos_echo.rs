@@ -0,0 +1,93 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io;
+
+use futures::{Future, Stream};
+use futures::future;
+use tokio_core::io::{Codec, EasyBuf};
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Core;
+use tokio_proto::{BindServer, TcpClient};
+use tokio_proto::protos::echo::{Echo, LengthCodec, MultiplexCodec, MultiplexProto, PipelineProto};
+use tokio_service::Service;
+
+#[test]
+fn test_pipeline_echo() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_handle = handle.clone();
+    let server = listener.incoming().for_each(move |(socket, _)| {
+        PipelineProto.bind_server(&server_handle, socket, Echo);
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    let client = core.run(TcpClient::new(PipelineProto).connect(&addr, &handle)).unwrap();
+
+    let calls = vec![b"hello".to_vec(), vec![], vec![0; 100_000]].into_iter()
+        .map(|req| client.call(req))
+        .collect::<Vec<_>>();
+    let responses = core.run(future::join_all(calls)).unwrap();
+
+    assert_eq!(vec![b"hello".to_vec(), vec![], vec![0; 100_000]], responses);
+}
+
+#[test]
+fn test_multiplex_echo() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_handle = handle.clone();
+    let server = listener.incoming().for_each(move |(socket, _)| {
+        MultiplexProto.bind_server(&server_handle, socket, Echo);
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    let client = core.run(TcpClient::new(MultiplexProto).connect(&addr, &handle)).unwrap();
+
+    let calls = (0..10u8).map(|i| client.call(vec![i; i as usize])).collect::<Vec<_>>();
+    let responses = core.run(future::join_all(calls)).unwrap();
+
+    for (i, response) in responses.into_iter().enumerate() {
+        assert_eq!(vec![i as u8; i], response);
+    }
+}
+
+#[test]
+fn test_codecs_wait_for_complete_frames() {
+    let mut encoded = vec![];
+    MultiplexCodec.encode((7, b"ping".to_vec()), &mut encoded).unwrap();
+    assert_eq!(&[0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 4], &encoded[..12]);
+
+    let mut buf = EasyBuf::new();
+    buf.get_mut().extend_from_slice(&encoded[..10]);
+    assert_eq!(None, MultiplexCodec.decode(&mut buf).unwrap());
+
+    buf.get_mut().extend_from_slice(&encoded[10..]);
+    assert_eq!(Some((7, b"ping".to_vec())), MultiplexCodec.decode(&mut buf).unwrap());
+    assert_eq!(0, buf.len());
+}
+
+#[test]
+fn test_rejects_oversized_frames() {
+    let mut buf = EasyBuf::new();
+    buf.get_mut().extend_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+
+    let err = LengthCodec.decode(&mut buf).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+}
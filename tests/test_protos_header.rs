@@ -0,0 +1,119 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use futures::{Future, Stream};
+use futures::future;
+use tokio_core::io::EasyBuf;
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Core;
+use tokio_proto::{BindServer, TcpClient};
+use tokio_proto::protos::header::{HeaderFrame, HeaderProto, MultiplexHeaderProto};
+use tokio_service::Service;
+
+mod support;
+use support::service::simple_service;
+
+#[test]
+fn test_header_access() {
+    let mut frame = HeaderFrame::with_payload(b"hello".to_vec());
+    frame.append("Accept", "text/plain");
+    frame.append("accept", "text/html");
+    frame.insert("Destination", "/queue/a");
+
+    assert_eq!(Some("text/plain"), frame.header("ACCEPT"));
+    assert_eq!(3, frame.headers().count());
+
+    assert_eq!(Some("text/plain".to_string()), frame.remove("accept"));
+    assert_eq!(None, frame.header("accept"));
+    assert_eq!(b"hello", frame.payload());
+}
+
+#[test]
+#[should_panic]
+fn test_content_length_is_reserved() {
+    HeaderFrame::new().append("Content-Length", "3");
+}
+
+#[test]
+fn test_encode_decode() {
+    let mut frame = HeaderFrame::with_payload(b"a\r\n\r\nb".to_vec());
+    frame.append("destination", "/queue/a");
+
+    let mut encoded = vec![];
+    frame.encode(&mut encoded);
+    HeaderFrame::new().encode(&mut encoded);
+
+    assert_eq!(frame.encoded_len() + 2, encoded.len());
+    assert!(encoded.starts_with(b"destination: /queue/a\r\ncontent-length: 6\r\n\r\na\r\n\r\nb"));
+
+    let mut buf = EasyBuf::new();
+    buf.get_mut().extend_from_slice(&encoded[..30]);
+    assert_eq!(None, HeaderFrame::decode(&mut buf).unwrap());
+
+    buf.get_mut().extend_from_slice(&encoded[30..]);
+    assert_eq!(Some(frame), HeaderFrame::decode(&mut buf).unwrap());
+    assert_eq!(Some(HeaderFrame::new()), HeaderFrame::decode(&mut buf).unwrap());
+    assert_eq!(0, buf.len());
+}
+
+#[test]
+fn test_decode_rejects_malformed_headers() {
+    let mut buf = EasyBuf::new();
+    buf.get_mut().extend_from_slice(b"no colon\r\n\r\n");
+    assert!(HeaderFrame::decode(&mut buf).is_err());
+
+    let mut buf = EasyBuf::new();
+    buf.get_mut().extend_from_slice(b"content-length: x\r\n\r\n");
+    assert!(HeaderFrame::decode(&mut buf).is_err());
+}
+
+fn reply(req: HeaderFrame) -> Result<HeaderFrame, ::std::io::Error> {
+    let mut res = HeaderFrame::with_payload(req.into_payload());
+    res.append("status", "ok");
+    Ok(res)
+}
+
+#[test]
+fn test_pipeline_and_multiplex_protos() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let pipeline = TcpListener::bind(&addr, &handle).unwrap();
+    let multiplex = TcpListener::bind(&addr, &handle).unwrap();
+    let pipeline_addr = pipeline.local_addr().unwrap();
+    let multiplex_addr = multiplex.local_addr().unwrap();
+
+    let server_handle = handle.clone();
+    let server = pipeline.incoming().for_each(move |(socket, _)| {
+        HeaderProto.bind_server(&server_handle, socket, simple_service(reply));
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    let server_handle = handle.clone();
+    let server = multiplex.incoming().for_each(move |(socket, _)| {
+        MultiplexHeaderProto::new("receipt").bind_server(&server_handle, socket, simple_service(reply));
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    let client = core.run(TcpClient::new(HeaderProto).connect(&pipeline_addr, &handle)).unwrap();
+    let res = core.run(client.call(HeaderFrame::with_payload(b"one".to_vec()))).unwrap();
+    assert_eq!(Some("ok"), res.header("status"));
+    assert_eq!(b"one", res.payload());
+
+    let client = TcpClient::new(MultiplexHeaderProto::new("receipt"));
+    let client = core.run(client.connect(&multiplex_addr, &handle)).unwrap();
+    let calls = (0..3).map(|i| client.call(HeaderFrame::with_payload(vec![i]))).collect::<Vec<_>>();
+    let responses = core.run(future::join_all(calls)).unwrap();
+
+    for (i, res) in responses.into_iter().enumerate() {
+        assert_eq!(None, res.header("receipt"));
+        assert_eq!(vec![i as u8], res.into_payload());
+    }
+}
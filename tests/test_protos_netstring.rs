@@ -0,0 +1,119 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io;
+use std::str;
+
+use futures::{Future, Stream};
+use futures::future;
+use tokio_core::io::{Codec, EasyBuf};
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Core;
+use tokio_proto::{BindServer, TcpClient};
+use tokio_proto::protos::echo::Echo;
+use tokio_proto::protos::netstring::{Mode, MultiplexNetstringProto, NetstringCodec, NetstringProto};
+use tokio_service::Service;
+
+fn decode_all(codec: &mut NetstringCodec, bytes: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let mut buf = EasyBuf::new();
+    buf.get_mut().extend_from_slice(bytes);
+
+    let mut payloads = vec![];
+
+    while let Some(payload) = try!(codec.decode(&mut buf)) {
+        payloads.push(payload);
+    }
+
+    Ok(payloads)
+}
+
+#[test]
+fn test_strict_parsing() {
+    let mut codec = NetstringCodec::new();
+
+    let payloads = decode_all(&mut codec, b"5:hello,0:,3:ab").unwrap();
+    assert_eq!(vec![b"hello".to_vec(), vec![]], payloads);
+
+    assert!(decode_all(&mut codec, b"05:hello,").is_err());
+    assert!(decode_all(&mut codec, b"5:hello,\n5:world,").is_err());
+    assert!(decode_all(&mut codec, b"5:hello;").is_err());
+    assert!(decode_all(&mut codec, b":,").is_err());
+
+    let mut encoded = vec![];
+    codec.encode(b"hello".to_vec(), &mut encoded).unwrap();
+    assert_eq!(b"5:hello,".to_vec(), encoded);
+}
+
+#[test]
+fn test_lenient_parsing() {
+    let mut codec = NetstringCodec::new().mode(Mode::Lenient);
+
+    let payloads = decode_all(&mut codec, b"05:hello,\r\n 5:world,\n").unwrap();
+    assert_eq!(vec![b"hello".to_vec(), b"world".to_vec()], payloads);
+
+    // Still needs the trailing comma
+    assert!(decode_all(&mut codec, b"5:hello\n").is_err());
+}
+
+#[test]
+fn test_max_len() {
+    let mut codec = NetstringCodec::new().max_len(4);
+
+    let err = decode_all(&mut codec, b"5:").unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+    // Overlong lengths are rejected before the payload arrives
+    assert!(decode_all(&mut codec, b"99999999999999999999999").is_err());
+
+    assert!(codec.encode(b"hello".to_vec(), &mut vec![]).is_err());
+}
+
+// Payloads start with a decimal id, followed by a space
+fn leading_id(payload: &[u8]) -> Option<u32> {
+    let end = payload.iter().position(|&b| b == b' ').unwrap_or(payload.len());
+    str::from_utf8(&payload[..end]).ok().and_then(|id| id.parse().ok())
+}
+
+#[test]
+fn test_pipeline_and_multiplex_protos() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let pipeline = TcpListener::bind(&addr, &handle).unwrap();
+    let multiplex = TcpListener::bind(&addr, &handle).unwrap();
+    let pipeline_addr = pipeline.local_addr().unwrap();
+    let multiplex_addr = multiplex.local_addr().unwrap();
+
+    let server_handle = handle.clone();
+    let server = pipeline.incoming().for_each(move |(socket, _)| {
+        NetstringProto::default().bind_server(&server_handle, socket, Echo);
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    let server_handle = handle.clone();
+    let server = multiplex.incoming().for_each(move |(socket, _)| {
+        let proto = MultiplexNetstringProto::new(NetstringCodec::new(), leading_id);
+        proto.bind_server(&server_handle, socket, Echo);
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    let client = TcpClient::new(NetstringProto::default());
+    let client = core.run(client.connect(&pipeline_addr, &handle)).unwrap();
+    let response = core.run(client.call(b"hello".to_vec())).unwrap();
+    assert_eq!(b"hello".to_vec(), response);
+
+    let client = TcpClient::new(MultiplexNetstringProto::new(NetstringCodec::new(), leading_id));
+    let client = core.run(client.connect(&multiplex_addr, &handle)).unwrap();
+    let calls = vec![b"7 get".to_vec(), b"3 put".to_vec()].into_iter()
+        .map(|req| client.call(req))
+        .collect::<Vec<_>>();
+    let responses = core.run(future::join_all(calls)).unwrap();
+    assert_eq!(vec![b"7 get".to_vec(), b"3 put".to_vec()], responses);
+}
@@ -0,0 +1,120 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io;
+
+use futures::{Future, Stream};
+use futures::future;
+use tokio_core::io::{Codec, EasyBuf};
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Core;
+use tokio_proto::{BindServer, TcpClient};
+use tokio_proto::protos::text_line::{Charset, LineCodec, TaggedLineProto, TextLineProto};
+use tokio_service::Service;
+
+mod support;
+use support::line::Echo;
+
+fn decode(codec: &mut LineCodec, bytes: &[u8]) -> io::Result<Option<String>> {
+    let mut buf = EasyBuf::new();
+    buf.get_mut().extend_from_slice(bytes);
+    codec.decode(&mut buf)
+}
+
+#[test]
+fn test_custom_delimiter() {
+    let mut codec = LineCodec::new().delimiter(b"\r\n");
+
+    let mut buf = EasyBuf::new();
+    buf.get_mut().extend_from_slice(b"EHLO example.com\r\nQU");
+
+    assert_eq!("EHLO example.com", codec.decode(&mut buf).unwrap().unwrap());
+    assert_eq!(None, codec.decode(&mut buf).unwrap());
+
+    buf.get_mut().extend_from_slice(b"IT\r\n");
+    assert_eq!("QUIT", codec.decode(&mut buf).unwrap().unwrap());
+
+    let mut encoded = vec![];
+    codec.encode("250 OK".to_string(), &mut encoded).unwrap();
+    assert_eq!(b"250 OK\r\n".to_vec(), encoded);
+
+    let err = codec.encode("two\r\nlines".to_string(), &mut encoded).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+}
+
+#[test]
+fn test_max_line_len() {
+    let mut codec = LineCodec::new().max_line_len(4);
+
+    assert_eq!("abcd", decode(&mut codec, b"abcd\n").unwrap().unwrap());
+    assert_eq!(None, decode(&mut codec, b"abcd").unwrap());
+
+    let err = decode(&mut codec, b"abcde").unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+}
+
+#[test]
+fn test_charsets() {
+    let mut ascii = LineCodec::new().charset(Charset::Ascii);
+    assert!(decode(&mut ascii, "caf\u{e9}\n".as_bytes()).is_err());
+    assert!(ascii.encode("caf\u{e9}".to_string(), &mut vec![]).is_err());
+
+    let mut latin1 = LineCodec::new().charset(Charset::Latin1);
+    assert_eq!("caf\u{e9}", decode(&mut latin1, b"caf\xe9\n").unwrap().unwrap());
+
+    let mut encoded = vec![];
+    latin1.encode("caf\u{e9}".to_string(), &mut encoded).unwrap();
+    assert_eq!(b"caf\xe9\n".to_vec(), encoded);
+
+    let mut utf8 = LineCodec::new();
+    assert!(decode(&mut utf8, b"caf\xe9\n").is_err());
+}
+
+#[test]
+fn test_pipeline_and_tagged_protos() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let codec = LineCodec::new().delimiter(b"\r\n");
+
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let pipeline = TcpListener::bind(&addr, &handle).unwrap();
+    let tagged = TcpListener::bind(&addr, &handle).unwrap();
+    let pipeline_addr = pipeline.local_addr().unwrap();
+    let tagged_addr = tagged.local_addr().unwrap();
+
+    let server_handle = handle.clone();
+    let server_codec = codec.clone();
+    let server = pipeline.incoming().for_each(move |(socket, _)| {
+        let proto = TextLineProto::new(server_codec.clone());
+        proto.bind_server(&server_handle, socket, Echo("+".to_string()));
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    let server_handle = handle.clone();
+    let server_codec = codec.clone();
+    let server = tagged.incoming().for_each(move |(socket, _)| {
+        let proto = TaggedLineProto::new(server_codec.clone(), "S");
+        proto.bind_server(&server_handle, socket, Echo("OK ".to_string()));
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    let client = TcpClient::new(TextLineProto::new(codec.clone()));
+    let client = core.run(client.connect(&pipeline_addr, &handle)).unwrap();
+    let pong = core.run(client.call("PING".to_string())).unwrap();
+    assert_eq!("+PING", pong);
+
+    let client = TcpClient::new(TaggedLineProto::new(codec, "A"));
+    let client = core.run(client.connect(&tagged_addr, &handle)).unwrap();
+    let calls = vec!["NOOP", "LOGOUT"].into_iter()
+        .map(|cmd| client.call(cmd.to_string()))
+        .collect::<Vec<_>>();
+    let responses = core.run(future::join_all(calls)).unwrap();
+    assert_eq!(vec!["OK NOOP", "OK LOGOUT"], responses);
+}
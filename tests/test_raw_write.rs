@@ -0,0 +1,133 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io::{self, Cursor, Write};
+
+use futures::{future, stream, Stream};
+use tokio_core::io::{read_exact, write_all, Codec, EasyBuf, Io};
+use tokio_core::reactor::Core;
+use tokio_proto::conformance;
+use tokio_proto::streaming::pipeline::{Frame, ServerProto, StreamingPipeline};
+use tokio_proto::streaming::{Body, Message};
+use tokio_proto::util::framed::{framed_raw, Raw, RawCodec, ReadSource, WriteTo};
+use tokio_proto::BindServer;
+use tokio_service::Service;
+
+mod support;
+use support::line::LineCodec;
+
+// Body chunks of responses, either bytes or the contents of a "file"
+enum Chunk {
+    Bytes(Vec<u8>),
+    File(Cursor<Vec<u8>>),
+}
+
+type FileBody = Box<Stream<Item = Chunk, Error = io::Error>>;
+
+// Requests are lines, responses a line followed by their body and a `.` line
+struct FileCodec;
+
+impl Codec for FileCodec {
+    type In = Frame<String, (), io::Error>;
+    type Out = Frame<String, Chunk, io::Error>;
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<Self::In>> {
+        let line = try!(LineCodec.decode(buf));
+        Ok(line.map(|line| Frame::Message { message: line, body: false }))
+    }
+
+    fn encode(&mut self, frame: Self::Out, buf: &mut Vec<u8>) -> io::Result<()> {
+        match frame {
+            Frame::Message { message, .. } => LineCodec.encode(message, buf),
+            Frame::Body { chunk: Some(Chunk::Bytes(bytes)) } => buf.write_all(&bytes),
+            Frame::Body { chunk: None } => LineCodec.encode(".".to_string(), buf),
+            _ => Err(io::Error::new(io::ErrorKind::Other, "unexpected frame")),
+        }
+    }
+}
+
+impl<W: Write> RawCodec<W> for FileCodec {
+    fn encode_raw(&mut self, frame: Self::Out, buf: &mut Vec<u8>)
+                  -> io::Result<Option<Box<WriteTo<W>>>>
+    {
+        match frame {
+            Frame::Body { chunk: Some(Chunk::File(file)) } => {
+                Ok(Some(Box::new(ReadSource::new(file))))
+            }
+            frame => {
+                try!(self.encode(frame, buf));
+                Ok(None)
+            }
+        }
+    }
+}
+
+struct FileProto;
+
+impl<T: Io + 'static> ServerProto<T> for FileProto {
+    type Request = String;
+    type RequestBody = ();
+    type Response = String;
+    type ResponseBody = Chunk;
+    type Error = io::Error;
+    type Transport = Raw<T, FileCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(framed_raw(io, FileCodec))
+    }
+}
+
+// Serves the request line as a file, surrounded by a header and a footer
+struct FileService;
+
+impl Service for FileService {
+    type Request = Message<String, Body<(), io::Error>>;
+    type Response = Message<String, FileBody>;
+    type Error = io::Error;
+    type Future = future::FutureResult<Self::Response, io::Error>;
+
+    fn call(&self, request: Self::Request) -> Self::Future {
+        let name = request.into_inner();
+        let file = Cursor::new(name.clone().into_bytes().repeat(3000));
+
+        let chunks = vec![
+            Ok(Chunk::Bytes(b"[".to_vec())),
+            Ok(Chunk::File(file)),
+            Ok(Chunk::Bytes(b"]\n".to_vec())),
+        ];
+
+        let body: FileBody = Box::new(stream::iter_result(chunks));
+        future::ok(Message::WithBody(format!("file {}", name), body))
+    }
+}
+
+fn expected(name: &str) -> Vec<u8> {
+    let mut response = format!("file {}\n[", name).into_bytes();
+    response.extend(name.as_bytes().repeat(3000));
+    response.extend_from_slice(b"]\n.\n");
+    response
+}
+
+#[test]
+fn test_sources_are_written_in_place() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (client, server) = conformance::pipe();
+
+    BindServer::<StreamingPipeline<FileBody>, _>::bind_server(
+        &FileProto, &handle, server, FileService);
+
+    let (client, _) = core.run(write_all(client, b"abc\nxyz\n")).unwrap();
+
+    // Larger than the write buffer, interleaved with encoded bytes
+    let mut response = expected("abc");
+    response.extend(expected("xyz"));
+
+    let (_, output) = core.run(read_exact(client, vec![0; response.len()])).unwrap();
+    assert!(output == response, "unexpected response");
+}
@@ -0,0 +1,118 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io;
+
+use futures::future;
+use tokio_core::io::{read_exact, read_to_end, write_all, Codec, EasyBuf, Io};
+use tokio_core::reactor::Core;
+use tokio_proto::conformance;
+use tokio_proto::protos::netstring::NetstringCodec;
+use tokio_proto::streaming::pipeline::{Frame, PipelineConfig, ServerProto, StreamingPipeline};
+use tokio_proto::streaming::{Body, Message};
+use tokio_proto::util::framed::{framed_chunked, Chunked, DecodeHint};
+use tokio_proto::BindServer;
+use tokio_service::Service;
+
+type NetFrame = Frame<Vec<u8>, (), io::Error>;
+
+// Netstrings as frames without bodies
+struct NetFrameCodec(NetstringCodec);
+
+impl Codec for NetFrameCodec {
+    type In = NetFrame;
+    type Out = NetFrame;
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<NetFrame>> {
+        let payload = try!(self.0.decode(buf));
+        Ok(payload.map(|payload| Frame::Message { message: payload, body: false }))
+    }
+
+    fn encode(&mut self, frame: NetFrame, buf: &mut Vec<u8>) -> io::Result<()> {
+        match frame {
+            Frame::Message { message, .. } => self.0.encode(message, buf),
+            _ => Err(io::Error::new(io::ErrorKind::Other, "unexpected frame")),
+        }
+    }
+}
+
+impl DecodeHint for NetFrameCodec {
+    fn message_len(&self, buf: &EasyBuf) -> Option<usize> {
+        self.0.message_len(buf)
+    }
+}
+
+// Reads 8 bytes at a time, failing messages longer than 32 bytes
+struct LimitedProto;
+
+impl<T: Io + 'static> ServerProto<T> for LimitedProto {
+    type Request = Vec<u8>;
+    type RequestBody = ();
+    type Response = Vec<u8>;
+    type ResponseBody = ();
+    type Error = io::Error;
+    type Transport = Chunked<T, NetFrameCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(framed_chunked(io, NetFrameCodec(NetstringCodec::new()), 8))
+    }
+
+    fn config(&self) -> PipelineConfig {
+        PipelineConfig {
+            max_message_len: Some(32),
+            ..PipelineConfig::default()
+        }
+    }
+}
+
+struct Echo;
+
+impl Service for Echo {
+    type Request = Message<Vec<u8>, Body<(), io::Error>>;
+    type Response = Message<Vec<u8>, Body<(), io::Error>>;
+    type Error = io::Error;
+    type Future = future::FutureResult<Self::Response, io::Error>;
+
+    fn call(&self, request: Self::Request) -> Self::Future {
+        future::ok(request)
+    }
+}
+
+// Writes `input` to a server, then reads `len` bytes or until the server
+// closes the connection if `None`
+fn serve(input: &'static [u8], len: Option<usize>) -> Vec<u8> {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (client, server) = conformance::pipe();
+
+    BindServer::<StreamingPipeline<Body<(), io::Error>>, _>::bind_server(
+        &LimitedProto, &handle, server, Echo);
+
+    // Written before the server task first runs, read in chunks regardless
+    let (client, _) = core.run(write_all(client, input)).unwrap();
+
+    let (_, output) = match len {
+        Some(len) => core.run(read_exact(client, vec![0; len])).unwrap(),
+        None => core.run(read_to_end(client, vec![])).unwrap(),
+    };
+    output
+}
+
+#[test]
+fn test_messages_within_limit_are_read_in_chunks() {
+    let output = serve(b"26:abcdefghijklmnopqrstuvwxyz,5:hello,", Some(38));
+    assert_eq!(&b"26:abcdefghijklmnopqrstuvwxyz,5:hello,"[..], &output[..]);
+}
+
+#[test]
+fn test_announced_length_fails_connection_early() {
+    // The rest of the payload never arrives, the prefix alone is over the
+    // limit and closes the connection
+    let output = serve(b"1000:abcdefgh", None);
+    assert!(output.is_empty());
+}
@@ -0,0 +1,253 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::Future;
+use tokio_core::io::{read_exact, write_all, Codec, EasyBuf, Framed, Io};
+use tokio_core::reactor::{Core, Timeout};
+use tokio_proto::{conformance, BindClient, BindServer};
+use tokio_proto::multiplex::{ClientProto, Multiplex, ServerProto};
+use tokio_proto::streaming::multiplex::{Counter, RequestIdSource};
+use tokio_service::Service;
+
+mod support;
+use support::line::{Echo, LineCodec, MuxLineCodec};
+
+// A 16 byte correlation id, deliberately not `Copy`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Uuid([u8; 16]);
+
+struct Uuids(u8);
+
+impl RequestIdSource<Uuid, String> for Uuids {
+    fn next(&mut self, _: &String) -> Uuid {
+        self.0 += 1;
+        Uuid([self.0; 16])
+    }
+}
+
+// Lines prefixed with the hex encoded id
+struct UuidCodec;
+
+impl Codec for UuidCodec {
+    type In = (Uuid, String);
+    type Out = (Uuid, String);
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<(Uuid, String)>> {
+        let line = match try!(LineCodec.decode(buf)) {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+
+        let mut id = [0; 16];
+
+        for (i, byte) in id.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&line[i * 2..i * 2 + 2], 16).unwrap();
+        }
+
+        Ok(Some((Uuid(id), line[33..].to_string())))
+    }
+
+    fn encode(&mut self, (id, msg): (Uuid, String), buf: &mut Vec<u8>) -> io::Result<()> {
+        let hex = id.0.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        LineCodec.encode(format!("{} {}", hex, msg), buf)
+    }
+}
+
+struct UuidProto;
+
+impl<T: Io + 'static> ServerProto<T> for UuidProto {
+    type Request = String;
+    type Response = String;
+    type RequestId = Uuid;
+    type Error = io::Error;
+    type Transport = Framed<T, UuidCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(UuidCodec))
+    }
+}
+
+impl<T: Io + 'static> ClientProto<T> for UuidProto {
+    type Request = String;
+    type Response = String;
+    type RequestId = Uuid;
+    type Error = io::Error;
+    type Transport = Framed<T, UuidCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+    type RequestIdSource = Uuids;
+
+    fn requestid_source(&self) -> Uuids {
+        Uuids(0)
+    }
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(UuidCodec))
+    }
+}
+
+// Counts up, recording the ids released by the dispatcher
+struct Tracked {
+    next: u64,
+    released: Rc<RefCell<Vec<u64>>>,
+}
+
+impl RequestIdSource<u64, String> for Tracked {
+    fn next(&mut self, _: &String) -> u64 {
+        self.next += 1;
+        self.next
+    }
+
+    fn release(&mut self, id: &u64) {
+        self.released.borrow_mut().push(*id);
+    }
+}
+
+// Mux lines, failing the connection on a `boom` line
+struct BoomCodec;
+
+impl Codec for BoomCodec {
+    type In = (u64, String);
+    type Out = (u64, String);
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<(u64, String)>> {
+        if buf.as_slice().starts_with(b"boom\n") {
+            return Err(io::Error::new(io::ErrorKind::Other, "boom"));
+        }
+
+        MuxLineCodec.decode(buf)
+    }
+
+    fn encode(&mut self, msg: (u64, String), buf: &mut Vec<u8>) -> io::Result<()> {
+        MuxLineCodec.encode(msg, buf)
+    }
+}
+
+struct TrackedProto(Rc<RefCell<Vec<u64>>>, Duration);
+
+impl<T: Io + 'static> ClientProto<T> for TrackedProto {
+    type Request = String;
+    type Response = String;
+    type RequestId = u64;
+    type Error = io::Error;
+    type Transport = Framed<T, BoomCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+    type RequestIdSource = Tracked;
+
+    fn requestid_source(&self) -> Tracked {
+        Tracked { next: 0, released: self.0.clone() }
+    }
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(BoomCodec))
+    }
+
+    fn late_response_timeout(&self) -> Duration {
+        self.1
+    }
+}
+
+#[test]
+fn test_counter_is_sequential() {
+    let mut ids = Counter::new();
+
+    for i in 0..10u64 {
+        assert_eq!(i, ids.next(&()));
+    }
+}
+
+#[cfg(feature = "rand")]
+#[test]
+fn test_random_ids_are_unique_while_in_flight() {
+    use std::collections::HashSet;
+    use tokio_proto::streaming::multiplex::RandomIds;
+
+    let mut ids = RandomIds::new().unwrap();
+    let mut seen = HashSet::new();
+
+    for _ in 0..1_000 {
+        let id: u64 = ids.next(&());
+        assert!(seen.insert(id), "duplicate in-flight id {}", id);
+    }
+
+    for id in &seen {
+        RequestIdSource::<u64, ()>::release(&mut ids, id);
+    }
+}
+
+#[test]
+fn test_request_ids_need_not_be_copy() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (client, server) = conformance::pipe();
+
+    BindServer::<Multiplex, _>::bind_server(&UuidProto, &handle, server, Echo("echo:".to_string()));
+    let service = BindClient::<Multiplex, _>::bind_client(&UuidProto, &handle, client);
+
+    let responses = core.run(service.call("one".to_string())
+                                    .join(service.call("two".to_string())))
+                        .unwrap();
+
+    assert_eq!(("echo:one".to_string(), "echo:two".to_string()), responses);
+}
+
+#[test]
+fn test_ids_released_when_connection_is_lost() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (client, server) = conformance::pipe();
+    let released = Rc::new(RefCell::new(vec![]));
+
+    let service = BindClient::<Multiplex, _>::bind_client(
+        &TrackedProto(released.clone(), Duration::from_secs(30)), &handle, client);
+
+    let one = service.call("one".to_string());
+    let two = service.call("two".to_string());
+
+    // Both requests are written
+    let (server, written) = core.run(read_exact(server, [0; 12])).unwrap();
+    assert_eq!(b"1 one\n2 two\n", &written);
+
+    // The connection fails before either request is answered
+    core.run(write_all(server, b"boom\n")).unwrap();
+
+    assert!(core.run(one).is_err());
+    assert!(core.run(two).is_err());
+
+    let mut released = released.borrow().clone();
+    released.sort();
+    assert_eq!(vec![1, 2], released);
+}
+
+#[test]
+fn test_ids_of_dropped_requests_released_after_late_response_timeout() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (client, server) = conformance::pipe();
+    let released = Rc::new(RefCell::new(vec![]));
+
+    let service = BindClient::<Multiplex, _>::bind_client(
+        &TrackedProto(released.clone(), Duration::from_millis(50)), &handle, client);
+
+    let one = service.call("one".to_string());
+    let (_server, written) = core.run(read_exact(server, [0; 6])).unwrap();
+    assert_eq!(b"1 one\n", &written);
+
+    // The id is held for the late response
+    drop(one);
+    core.run(Timeout::new(Duration::from_millis(10), &handle).unwrap()).unwrap();
+    assert!(released.borrow().is_empty());
+
+    // Until the response is given up on
+    core.run(Timeout::new(Duration::from_millis(200), &handle).unwrap()).unwrap();
+    assert_eq!(vec![1], *released.borrow());
+}
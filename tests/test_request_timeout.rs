@@ -0,0 +1,124 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::Future;
+use futures::sync::oneshot;
+use tokio_core::io::{Framed, Io};
+use tokio_core::reactor::{Core, Handle, Timeout};
+use tokio_proto::{conformance, BindClient, BindServer};
+use tokio_proto::multiplex::{ClientService, Multiplex, MultiplexConfig, ServerProto};
+use tokio_service::Service;
+
+mod support;
+use support::line::{MuxLineCodec, MuxLineProto};
+
+// Multiplexed line protocol answering one request at a time, giving the
+// service 20ms per request or a second for `patient` ones
+struct DeadlineProto;
+
+impl<T: Io + 'static> ServerProto<T> for DeadlineProto {
+    type Request = String;
+    type Response = String;
+    type RequestId = u64;
+    type Error = io::Error;
+    type Transport = Framed<T, MuxLineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(MuxLineCodec))
+    }
+
+    fn config(&self) -> MultiplexConfig {
+        MultiplexConfig {
+            max_in_flight: 1,
+            request_timeout: Some(Duration::from_millis(20)),
+            ..MultiplexConfig::default()
+        }
+    }
+
+    fn request_timeout(request: &String) -> Option<Duration> {
+        if request.starts_with("patient") {
+            Some(Duration::from_secs(1))
+        } else {
+            None
+        }
+    }
+
+    fn error_response(error: io::Error) -> io::Result<String> {
+        Ok(format!("error: {}", error))
+    }
+}
+
+// Never answers `hang` requests, keeping their senders, answers the others
+// after 50ms
+struct Wedged {
+    hung: Rc<RefCell<Vec<oneshot::Sender<String>>>>,
+    handle: Handle,
+}
+
+impl Service for Wedged {
+    type Request = String;
+    type Response = String;
+    type Error = io::Error;
+    type Future = Box<Future<Item = String, Error = io::Error>>;
+
+    fn call(&self, req: String) -> Self::Future {
+        if req == "hang" {
+            let (tx, rx) = oneshot::channel();
+            self.hung.borrow_mut().push(tx);
+            return Box::new(rx.map_err(|_| io::Error::new(io::ErrorKind::Other, "canceled")));
+        }
+
+        let wait = Timeout::new(Duration::from_millis(50), &self.handle).unwrap();
+        Box::new(wait.map(move |_| format!("echo:{}", req)))
+    }
+}
+
+type Client = ClientService<conformance::Pipe, MuxLineProto>;
+
+fn serve(core: &Core) -> (Client, Rc<RefCell<Vec<oneshot::Sender<String>>>>) {
+    let handle = core.handle();
+    let (client, server) = conformance::pipe();
+    let hung = Rc::new(RefCell::new(vec![]));
+
+    let service = Wedged { hung: hung.clone(), handle: handle.clone() };
+    BindServer::<Multiplex, _>::bind_server(&DeadlineProto, &handle, server, service);
+
+    let client = BindClient::<Multiplex, _>::bind_client(&MuxLineProto, &handle, client);
+
+    (client, hung)
+}
+
+#[test]
+fn test_expired_request_fails_and_frees_its_slot() {
+    let mut core = Core::new().unwrap();
+    let (client, hung) = serve(&core);
+
+    let res = core.run(client.call("hang".to_string())).unwrap();
+    assert_eq!("error: request deadline expired", res);
+
+    // The response future was dropped
+    assert!(hung.borrow()[0].is_canceled());
+
+    // Only one request is processed at a time, the slot was reclaimed
+    let res = core.run(client.call("patient ping".to_string())).unwrap();
+    assert_eq!("echo:patient ping", res);
+}
+
+#[test]
+fn test_requests_taking_longer_than_config_fail() {
+    let mut core = Core::new().unwrap();
+    let (client, _hung) = serve(&core);
+
+    let res = core.run(client.call("ping".to_string())).unwrap();
+    assert_eq!("error: request deadline expired", res);
+}
@@ -0,0 +1,103 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use futures::{Future, Stream};
+use futures::future;
+use tokio_core::io::{Framed, Io};
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::{Core, Handle, Timeout};
+use tokio_proto::BindServer;
+use tokio_proto::multiplex::{Multiplex, ServerProto};
+use tokio_service::Service;
+
+mod support;
+use support::line::MuxLineCodec;
+
+// Multiplexed line protocol whose responses to requests naming the same
+// queue, `<queue> <message>`, keep their order
+struct QueueProto;
+
+impl<T: Io + 'static> ServerProto<T> for QueueProto {
+    type Request = String;
+    type Response = String;
+    type RequestId = u64;
+    type Error = io::Error;
+    type Transport = Framed<T, MuxLineCodec>;
+    type BindTransport = Result<Self::Transport, io::Error>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(MuxLineCodec))
+    }
+
+    fn ordering_key(request: &String) -> Option<u64> {
+        match request.split(' ').next() {
+            Some("a") => Some(0),
+            Some("b") => Some(1),
+            _ => None,
+        }
+    }
+}
+
+// Echoes requests, the slow ones after a while
+struct Echo(Handle);
+
+impl Service for Echo {
+    type Request = String;
+    type Response = String;
+    type Error = io::Error;
+    type Future = Box<Future<Item = String, Error = io::Error>>;
+
+    fn call(&self, req: String) -> Self::Future {
+        if !req.ends_with("slow") {
+            return Box::new(future::ok(req));
+        }
+
+        let timeout = Timeout::new(Duration::from_millis(200), &self.0).unwrap();
+        Box::new(timeout.map(move |_| req))
+    }
+}
+
+#[test]
+fn test_responses_sharing_key_keep_request_order() {
+    let (addr_tx, addr_rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let listener = TcpListener::bind(&addr, &handle).unwrap();
+        addr_tx.send(listener.local_addr().unwrap()).unwrap();
+
+        let server = listener.incoming().for_each(move |(socket, _)| {
+            BindServer::<Multiplex, _>::bind_server(&QueueProto, &handle, socket, Echo(handle.clone()));
+            Ok(())
+        });
+
+        drop(core.run(server));
+    });
+
+    let mut conn = net::TcpStream::connect(addr_rx.recv().unwrap()).unwrap();
+    conn.set_read_timeout(Some(support::DEADLINE)).unwrap();
+    conn.write_all(b"1 a slow\n2 a fast\n3 b fast\n4 other fast\n").unwrap();
+
+    let responses = BufReader::new(conn).lines().take(4).map(|line| line.unwrap()).collect::<Vec<_>>();
+
+    // The fast response on queue `a` waits for the slow one, the others do not
+    assert_eq!(4, responses.len());
+    assert_eq!(&responses[2..], &["1 a slow", "2 a fast"]);
+
+    let mut unordered = responses[..2].to_vec();
+    unordered.sort();
+    assert_eq!(unordered, vec!["3 b fast", "4 other fast"]);
+}
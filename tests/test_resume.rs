@@ -0,0 +1,161 @@
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::rc::Rc;
+
+use futures::{stream, Future, Sink, Stream};
+use tokio_core::reactor::Core;
+use tokio_proto::resume::{Acks, Resumable, Resume};
+use tokio_proto::streaming::{Body, Message};
+use tokio_service::Service;
+
+type Upload = Message<String, Body<Vec<u8>, io::Error>>;
+
+// Heads look like `put <token>`, resumed as `put <token> @<offset>`
+struct Put;
+
+impl Resumable for Put {
+    type Head = String;
+    type Token = u32;
+
+    fn token(&self, head: &String) -> Option<u32> {
+        head.split(' ').nth(1).and_then(|token| token.parse().ok())
+    }
+
+    fn resume(&self, head: &String, offset: u64) -> String {
+        format!("{} @{}", head, offset)
+    }
+}
+
+// Stores uploaded bytes, acking them as they arrive. Each connection fails
+// after taking the next of the given number of bytes, if any.
+struct Store {
+    stored: Rc<RefCell<Vec<u8>>>,
+    heads: Rc<RefCell<Vec<String>>>,
+    limits: RefCell<VecDeque<usize>>,
+    acks: Acks<u32>,
+}
+
+impl Service for Store {
+    type Request = Upload;
+    type Response = String;
+    type Error = io::Error;
+    type Future = Box<Future<Item = String, Error = io::Error>>;
+
+    fn call(&self, req: Upload) -> Self::Future {
+        self.heads.borrow_mut().push(req.get_ref().clone());
+
+        let token = Put.token(req.get_ref());
+        let mut limit = self.limits.borrow_mut().pop_front();
+        let stored = self.stored.clone();
+        let acks = self.acks.clone();
+
+        let body = match req {
+            Message::WithBody(_, body) => body,
+            Message::WithoutBody(_) => Body::empty(),
+        };
+
+        let upload = body.for_each(move |chunk| {
+            let take = match limit {
+                Some(left) => chunk.len().min(left),
+                None => chunk.len(),
+            };
+
+            let mut stored = stored.borrow_mut();
+            stored.extend_from_slice(&chunk[..take]);
+
+            if let Some(token) = token {
+                acks.ack(&token, stored.len() as u64);
+            }
+
+            match limit {
+                Some(left) if left == take => {
+                    Err(io::Error::new(io::ErrorKind::BrokenPipe, "connection lost"))
+                }
+                Some(ref mut left) => {
+                    *left -= take;
+                    Ok(())
+                }
+                None => Ok(()),
+            }
+        });
+
+        let stored = self.stored.clone();
+        Box::new(upload.map(move |_| String::from_utf8(stored.borrow().clone()).unwrap()))
+    }
+}
+
+fn store(limits: Vec<usize>, acks: &Acks<u32>) -> (Store, Rc<RefCell<Vec<String>>>) {
+    let heads = Rc::new(RefCell::new(Vec::new()));
+
+    let store = Store {
+        stored: Rc::new(RefCell::new(Vec::new())),
+        heads: heads.clone(),
+        limits: RefCell::new(limits.into_iter().collect()),
+        acks: acks.clone(),
+    };
+
+    (store, heads)
+}
+
+fn upload(core: &mut Core, head: &str, chunks: &[&str]) -> Upload {
+    let (tx, body) = Body::channel();
+    let chunks: Vec<_> = chunks.iter().map(|chunk| Ok(chunk.as_bytes().to_vec())).collect();
+
+    core.handle().spawn(tx.send_all(stream::iter_ok::<_, io::Error>(chunks))
+                          .then(|_| Ok(())));
+
+    Message::WithBody(head.to_string(), body)
+}
+
+#[test]
+fn test_resuming_from_acked_offset() {
+    let mut core = Core::new().unwrap();
+    let acks = Acks::new();
+
+    // The first connection is lost in the middle of the second chunk
+    let (store, heads) = store(vec![7], &acks);
+    let client = Resume::new(store, Put, acks.clone());
+
+    let req = upload(&mut core, "put 1", &["hello", " world", ", bye"]);
+    let stored = core.run(client.call(req)).unwrap();
+
+    assert_eq!("hello world, bye", stored);
+    assert_eq!(vec!["put 1".to_string(), "put 1 @7".to_string()], *heads.borrow());
+
+    // Done with the upload
+    assert_eq!(None, acks.acked(&1));
+}
+
+#[test]
+fn test_giving_up_after_max_resumes() {
+    let mut core = Core::new().unwrap();
+    let acks = Acks::new();
+
+    let (store, heads) = store(vec![2, 2, 2], &acks);
+    let client = Resume::new(store, Put, acks).max_resumes(2);
+
+    let req = upload(&mut core, "put 1", &["hello", " world"]);
+    let err = core.run(client.call(req)).unwrap_err();
+
+    assert_eq!(io::ErrorKind::BrokenPipe, err.kind());
+    assert_eq!(vec!["put 1", "put 1 @2", "put 1 @4"], *heads.borrow());
+}
+
+#[test]
+fn test_requests_without_token_are_not_resumed() {
+    let mut core = Core::new().unwrap();
+    let acks = Acks::new();
+
+    let (store, heads) = store(vec![2], &acks);
+    let client = Resume::new(store, Put, acks);
+
+    let req = upload(&mut core, "put", &["hello"]);
+    assert!(core.run(client.call(req)).is_err());
+    assert_eq!(vec!["put"], *heads.borrow());
+}
@@ -0,0 +1,112 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::future::{self, FutureResult};
+use tokio_core::reactor::{Core, Timeout};
+use tokio_proto::{conformance, BindClient};
+use tokio_proto::pipeline::Pipeline;
+use tokio_proto::retry::{self, Backoff, Retry};
+use tokio_proto::util::client_proxy::NotSent;
+use tokio_service::Service;
+
+mod support;
+use support::line::LineProto;
+
+// Answers each call with the next of the given results, counting the calls
+struct Backend {
+    results: RefCell<VecDeque<io::Result<usize>>>,
+    calls: Rc<Cell<usize>>,
+}
+
+impl Service for Backend {
+    type Request = String;
+    type Response = usize;
+    type Error = io::Error;
+    type Future = FutureResult<usize, io::Error>;
+
+    fn call(&self, _: String) -> Self::Future {
+        self.calls.set(self.calls.get() + 1);
+        future::result(self.results.borrow_mut().pop_front().unwrap())
+    }
+}
+
+fn backend(results: Vec<io::Result<usize>>) -> (Backend, Rc<Cell<usize>>) {
+    let calls = Rc::new(Cell::new(0));
+
+    let backend = Backend {
+        results: RefCell::new(results.into_iter().collect()),
+        calls: calls.clone(),
+    };
+
+    (backend, calls)
+}
+
+fn broken_pipe() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe")
+}
+
+#[test]
+fn test_retrying_requests_not_sent() {
+    let mut core = Core::new().unwrap();
+
+    let (backend, calls) = backend(vec![Err(NotSent.into()), Err(NotSent.into()), Ok(7)]);
+    let client = Retry::new(backend, Backoff::new(Duration::from_millis(10), 3), &core.handle());
+
+    assert_eq!(7, core.run(client.call("set".to_string())).unwrap());
+    assert_eq!(3, calls.get());
+}
+
+#[test]
+fn test_giving_up_after_max_retries() {
+    let mut core = Core::new().unwrap();
+
+    let (backend, calls) = backend(vec![Err(NotSent.into()), Err(NotSent.into()), Err(NotSent.into())]);
+    let client = Retry::new(backend, Backoff::new(Duration::from_millis(10), 2), &core.handle());
+
+    let err = core.run(client.call("set".to_string())).unwrap_err();
+    assert!(retry::is_unprocessed(&err));
+    assert_eq!(3, calls.get());
+}
+
+#[test]
+fn test_retrying_sent_requests_only_if_idempotent() {
+    let mut core = Core::new().unwrap();
+
+    let (backend, calls) = backend(vec![Err(broken_pipe()), Err(broken_pipe()), Ok(7)]);
+    let policy = Backoff::new(Duration::from_millis(10), 3).idempotent(|req: &String| req == "get");
+    let client = Retry::new(backend, policy, &core.handle());
+
+    // The request may have been processed, it is not sent again
+    let err = core.run(client.call("set".to_string())).unwrap_err();
+    assert_eq!(io::ErrorKind::BrokenPipe, err.kind());
+    assert_eq!(1, calls.get());
+
+    assert_eq!(7, core.run(client.call("get".to_string())).unwrap());
+    assert_eq!(3, calls.get());
+}
+
+#[test]
+fn test_calls_on_closed_connection_are_not_sent() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (client, server) = conformance::pipe();
+
+    let service = BindClient::<Pipeline, _>::bind_client(&LineProto, &handle, client);
+
+    // Let the dispatcher notice the peer is gone
+    drop(server);
+    core.run(Timeout::new(Duration::from_millis(50), &handle).unwrap()).unwrap();
+
+    let err = core.run(service.call("ping".to_string())).unwrap_err();
+    assert!(retry::is_unprocessed(&err));
+}
@@ -0,0 +1,146 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use tokio_proto::{PerConnection, Route, TcpServer};
+use tokio_proto::wrap::Connection;
+
+mod support;
+use support::line::{LineProto, Echo};
+
+#[test]
+fn test_services_built_from_peer_address() {
+    let addr = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+    thread::spawn(move || {
+        let accepted = AtomicUsize::new(0);
+
+        // Refuses the first connection, echoing the peer's ip on the others
+        TcpServer::new(LineProto, addr).serve(PerConnection::new(move |conn: &Connection| {
+            if accepted.fetch_add(1, Ordering::SeqCst) == 0 {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, "refused"));
+            }
+
+            Ok(Echo(format!("{}:", conn.peer().ip())))
+        }));
+    });
+
+    let refused = support::connect(&addr);
+    let mut line = String::new();
+    assert_eq!(0, BufReader::new(refused).read_line(&mut line).unwrap());
+
+    let mut served = support::connect(&addr);
+    served.write_all(b"hello\n").unwrap();
+
+    BufReader::new(served).read_line(&mut line).unwrap();
+    assert_eq!("127.0.0.1:hello\n", line);
+}
+
+#[test]
+fn test_peeked_bytes_seen_by_service_factory() {
+    let addr = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+    thread::spawn(move || {
+        // Takes the client from a `PROXY <ip>` line, leaving the rest to the
+        // codec
+        TcpServer::new(LineProto, addr)
+            .peek(32, |conn: &mut Connection, buf: &mut Vec<u8>| {
+                if !buf.starts_with(b"PROXY ") {
+                    return Ok(true);
+                }
+
+                let end = match buf.iter().position(|&b| b == b'\n') {
+                    Some(end) => end,
+                    None => return Ok(false),
+                };
+
+                let client = String::from_utf8(buf[6..end].to_vec()).unwrap();
+                conn.set_peer(format!("{}:0", client).parse().unwrap());
+                buf.drain(..end + 1);
+
+                Ok(true)
+            })
+            .serve(PerConnection::new(|conn: &Connection| {
+                Ok(Echo(format!("{}:", conn.peer().ip())))
+            }));
+    });
+
+    let mut proxied = support::connect(&addr);
+    proxied.write_all(b"PROXY 10.0.0.1\nhello\n").unwrap();
+
+    let mut line = String::new();
+    BufReader::new(proxied).read_line(&mut line).unwrap();
+    assert_eq!("10.0.0.1:hello\n", line);
+
+    // The peeked bytes are replayed to the codec
+    let mut direct = support::connect(&addr);
+    direct.write_all(b"hello\n").unwrap();
+
+    line.clear();
+    BufReader::new(direct).read_line(&mut line).unwrap();
+    assert_eq!("127.0.0.1:hello\n", line);
+
+    // A header longer than peeked is refused. The unread bytes may have the
+    // connection reset rather than closed.
+    let mut refused = support::connect(&addr);
+    refused.write_all(b"PROXY 0000:0000:0000:0000:0000:0000\nhello\n").unwrap();
+
+    line.clear();
+    assert_eq!(0, BufReader::new(refused).read_line(&mut line).unwrap_or(0));
+}
+
+#[test]
+fn test_connections_routed_by_peeked_bytes() {
+    let addr = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+    thread::spawn(move || {
+        let upper: fn() -> io::Result<Echo> = || Ok(Echo("upper:".to_string()));
+        let lower: fn() -> io::Result<Echo> = || Ok(Echo("lower:".to_string()));
+
+        let mut routes = Route::new();
+        routes.add("upper", upper);
+        routes.add("lower", lower);
+
+        // Routes by the first letter, leaving the bytes to the codec
+        TcpServer::new(LineProto, addr)
+            .peek(1, |conn: &mut Connection, buf: &mut Vec<u8>| {
+                match buf[0] {
+                    b'A'..=b'Z' => conn.set_route("upper".to_string()),
+                    b'a'..=b'z' => conn.set_route("lower".to_string()),
+                    _ => {}
+                }
+
+                Ok(true)
+            })
+            .serve(routes);
+    });
+
+    let mut upper = support::connect(&addr);
+    upper.write_all(b"Hello\n").unwrap();
+
+    let mut line = String::new();
+    BufReader::new(upper).read_line(&mut line).unwrap();
+    assert_eq!("upper:Hello\n", line);
+
+    let mut lower = support::connect(&addr);
+    lower.write_all(b"hello\n").unwrap();
+
+    line.clear();
+    BufReader::new(lower).read_line(&mut line).unwrap();
+    assert_eq!("lower:hello\n", line);
+
+    // Without a fallback, unrouted connections are refused
+    let mut refused = support::connect(&addr);
+    refused.write_all(b"1\n").unwrap();
+
+    line.clear();
+    assert_eq!(0, BufReader::new(refused).read_line(&mut line).unwrap_or(0));
+}
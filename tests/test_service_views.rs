@@ -0,0 +1,87 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io;
+
+use futures::{Future, Stream};
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Core;
+use tokio_proto::{BindServer, TcpClient};
+use tokio_proto::streaming::{Body, Message};
+use tokio_proto::streaming::multiplex::Frame;
+use tokio_service::Service;
+
+mod support;
+use support::line::{LineProto, Echo};
+use support::mock;
+
+#[test]
+fn test_simple_view_buffers_response_body() {
+    let (mut mock, service, _other) = mock::multiplex_client();
+    let service = service.into_simple_service(2);
+
+    let pong = service.call("ping");
+    let wr = mock.next_write();
+    assert_eq!("ping", wr.unwrap_msg());
+
+    mock.send(Frame::Message { id: 0, message: "pong", body: true, solo: false });
+    mock.send(Frame::Body { id: 0, chunk: Some(1) });
+    mock.send(Frame::Body { id: 0, chunk: Some(2) });
+    mock.send(Frame::Body { id: 0, chunk: None });
+
+    assert_eq!(("pong", vec![1, 2]), pong.wait().unwrap());
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_simple_view_limits_response_body() {
+    let (mut mock, service, _other) = mock::multiplex_client();
+    let service = service.into_simple_service(1);
+
+    let pong = service.call("ping");
+    assert_eq!("ping", mock.next_write().unwrap_msg());
+
+    mock.send(Frame::Message { id: 0, message: "pong", body: true, solo: false });
+    mock.send(Frame::Body { id: 0, chunk: Some(1) });
+    mock.send(Frame::Body { id: 0, chunk: Some(2) });
+
+    assert_eq!(io::ErrorKind::InvalidData, pong.wait().unwrap_err().kind());
+
+    // The rest of the body is discarded
+    mock.send(Frame::Body { id: 0, chunk: None });
+
+    mock.allow_and_assert_drop();
+}
+
+#[test]
+fn test_streaming_view_of_simple_client() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let listener = TcpListener::bind(&addr, &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_handle = handle.clone();
+    let server = listener.incoming().for_each(move |(socket, _)| {
+        LineProto.bind_server(&server_handle, socket, Echo(String::new()));
+        Ok(())
+    });
+    handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+    let client = core.run(TcpClient::new(LineProto).connect(&addr, &handle)).unwrap();
+    let client = client.into_streaming_service::<Body<u32, io::Error>, u32>();
+
+    let res = core.run(client.call(Message::WithoutBody("hello".to_string()))).unwrap();
+    assert_eq!("hello", res.into_inner());
+
+    // Bodies are not supported by the simple protocol
+    let req = Message::WithBody("hello".to_string(), Body::from(1));
+    let err = core.run(client.call(req)).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+}
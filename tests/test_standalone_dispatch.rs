@@ -0,0 +1,147 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+
+use std::collections::VecDeque;
+use std::io;
+
+use futures::future::Either;
+use futures::stream::Empty;
+use futures::{Async, Future, Poll, Sink, Stream};
+use tokio_core::io::{Codec, EasyBuf, Framed, Io};
+use tokio_core::reactor::Core;
+use tokio_proto::conformance;
+use tokio_proto::streaming::pipeline::advanced::{self, Pipeline, PipelineMessage};
+use tokio_proto::streaming::pipeline::Frame;
+use tokio_proto::streaming::{Body, ConnectionState, Message};
+
+mod support;
+use support::line::LineCodec;
+
+type PlainFrame = Frame<String, (), io::Error>;
+
+// Lines without bodies, failing on a `bad` line
+struct PlainCodec;
+
+impl Codec for PlainCodec {
+    type In = PlainFrame;
+    type Out = PlainFrame;
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<PlainFrame>> {
+        match try!(LineCodec.decode(buf)) {
+            Some(ref line) if line == "bad" => {
+                Err(io::Error::new(io::ErrorKind::InvalidData, "bad line"))
+            }
+            line => Ok(line.map(|line| Frame::Message { message: line, body: false })),
+        }
+    }
+
+    fn encode(&mut self, frame: PlainFrame, buf: &mut Vec<u8>) -> io::Result<()> {
+        match frame {
+            Frame::Message { message, .. } => LineCodec.encode(message, buf),
+            _ => Err(io::Error::new(io::ErrorKind::Other, "unexpected frame")),
+        }
+    }
+}
+
+// Echoes lines, counting them
+struct EchoDispatch {
+    transport: Framed<conformance::Pipe, PlainCodec>,
+    in_flight: VecDeque<String>,
+    served: usize,
+}
+
+impl advanced::Dispatch for EchoDispatch {
+    type Io = conformance::Pipe;
+    type In = String;
+    type BodyIn = ();
+    type Out = String;
+    type BodyOut = ();
+    type Error = io::Error;
+    type Stream = Empty<(), io::Error>;
+    type Transport = Framed<conformance::Pipe, PlainCodec>;
+
+    fn transport(&mut self) -> &mut Self::Transport {
+        &mut self.transport
+    }
+
+    fn dispatch(&mut self,
+                message: PipelineMessage<String, Body<(), io::Error>, io::Error>)
+                -> io::Result<()> {
+        let message = try!(message);
+        self.in_flight.push_back(format!("echo:{}", message.into_inner()));
+        Ok(())
+    }
+
+    fn poll(&mut self) -> Poll<Option<PipelineMessage<String, Self::Stream, io::Error>>, io::Error> {
+        match self.in_flight.pop_front() {
+            Some(response) => {
+                self.served += 1;
+                Ok(Async::Ready(Some(Ok(Message::WithoutBody(response)))))
+            }
+            None => Ok(Async::NotReady),
+        }
+    }
+
+    fn has_in_flight(&self) -> bool {
+        !self.in_flight.is_empty()
+    }
+}
+
+fn standalone() -> (Pipeline<EchoDispatch>, Framed<conformance::Pipe, LineCodec>) {
+    let (client, server) = conformance::pipe();
+
+    let dispatch = EchoDispatch {
+        transport: server.framed(PlainCodec),
+        in_flight: VecDeque::new(),
+        served: 0,
+    };
+
+    (Pipeline::new(dispatch), client.framed(LineCodec))
+}
+
+#[test]
+fn test_close_and_take_back_dispatch() {
+    let mut core = Core::new().unwrap();
+    let (mut pipeline, client) = standalone();
+    let control = pipeline.control();
+
+    assert_eq!(ConnectionState::Running, control.state());
+
+    let exchange = client.send("ping".to_string()).and_then(|client| {
+        client.into_future().map_err(|(e, _)| e)
+    });
+    let (line, _client) = match core.run(exchange.select2(&mut pipeline)) {
+        Ok(Either::A((res, _))) => res,
+        _ => panic!("pipeline completed early"),
+    };
+    assert_eq!(Some("echo:ping".to_string()), line);
+
+    // Closing completes the dispatcher, which can be polled by hand
+    control.close();
+    core.run(&mut pipeline).unwrap();
+
+    assert_eq!(ConnectionState::Done, control.state());
+    assert_eq!(ConnectionState::Done, pipeline.state());
+
+    let dispatch = pipeline.into_inner();
+    assert_eq!(1, dispatch.served);
+
+    // The transport is handed back along with the dispatch
+    let _io: conformance::Pipe = dispatch.transport.into_inner();
+}
+
+#[test]
+fn test_failure_is_recorded() {
+    let mut core = Core::new().unwrap();
+    let (pipeline, client) = standalone();
+    let control = pipeline.control();
+
+    let _client = core.run(client.send("bad".to_string())).unwrap();
+
+    let err = core.run(pipeline).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    assert_eq!(ConnectionState::Failed(io::ErrorKind::InvalidData), control.state());
+}
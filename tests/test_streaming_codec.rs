@@ -0,0 +1,157 @@
+#![allow(deprecated)]
+
+extern crate tokio_core;
+extern crate tokio_proto;
+
+use std::io;
+use std::str;
+
+use tokio_core::io::{Codec, EasyBuf};
+use tokio_proto::streaming::pipeline::{Frame, BodyLength, MessageDecoder, StreamingCodec};
+
+// Heads are a line holding the name and body length, e.g. `upload 5\n`; a
+// length of `-` makes the body a series of `;` terminated chunks, ended by
+// an empty one
+struct HeadDecoder;
+
+impl MessageDecoder for HeadDecoder {
+    type Head = String;
+
+    fn decode_head(&mut self, buf: &mut EasyBuf) -> io::Result<Option<(String, BodyLength)>> {
+        let i = match buf.as_slice().iter().position(|&b| b == b'\n') {
+            Some(i) => i,
+            None => return Ok(None),
+        };
+
+        let line = buf.drain_to(i + 1);
+        let line = str::from_utf8(&line.as_slice()[..i]).unwrap();
+        let mut parts = line.split(' ');
+        let name = parts.next().unwrap().to_string();
+
+        let len = match parts.next() {
+            None => BodyLength::Empty,
+            Some("-") => BodyLength::Delimited,
+            Some(n) => BodyLength::Fixed(n.parse().unwrap()),
+        };
+
+        Ok(Some((name, len)))
+    }
+
+    fn decode_chunk(&mut self, buf: &mut EasyBuf) -> io::Result<Option<Option<EasyBuf>>> {
+        match buf.as_slice().iter().position(|&b| b == b';') {
+            Some(0) => {
+                buf.drain_to(1);
+                Ok(Some(None))
+            }
+            Some(i) => {
+                let chunk = buf.drain_to(i);
+                buf.drain_to(1);
+                Ok(Some(Some(chunk)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+struct NoEncode;
+
+impl Codec for NoEncode {
+    type In = ();
+    type Out = ();
+
+    fn decode(&mut self, _: &mut EasyBuf) -> io::Result<Option<()>> {
+        Ok(None)
+    }
+
+    fn encode(&mut self, _: (), _: &mut Vec<u8>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+type Decoded = Frame<String, EasyBuf, io::Error>;
+
+fn codec() -> StreamingCodec<HeadDecoder, NoEncode> {
+    StreamingCodec::new(HeadDecoder, NoEncode)
+}
+
+fn decode_all(codec: &mut StreamingCodec<HeadDecoder, NoEncode>, buf: &mut EasyBuf) -> Vec<Decoded> {
+    let mut frames = vec![];
+
+    while let Some(frame) = codec.decode(buf).unwrap() {
+        frames.push(frame);
+    }
+
+    frames
+}
+
+fn chunk(frame: Decoded) -> Option<Vec<u8>> {
+    frame.unwrap_body().map(|chunk| chunk.as_slice().to_vec())
+}
+
+#[test]
+fn test_fixed_body_streams_as_bytes_arrive() {
+    let mut codec = codec();
+    let mut buf = EasyBuf::new();
+
+    buf.get_mut().extend_from_slice(b"upload 11\nhel");
+
+    let mut frames = decode_all(&mut codec, &mut buf);
+    assert_eq!(2, frames.len());
+    assert_eq!(Some(b"hel".to_vec()), chunk(frames.pop().unwrap()));
+
+    match frames.pop().unwrap() {
+        Frame::Message { message, body } => {
+            assert_eq!("upload", message);
+            assert!(body);
+        }
+        _ => panic!("expected message frame"),
+    }
+
+    assert!(codec.is_decoding_body());
+
+    // The rest of the body is handed out without waiting for the whole
+    // message, and following messages are decoded as usual
+    buf.get_mut().extend_from_slice(b"lo worldping\n");
+
+    let mut frames = decode_all(&mut codec, &mut buf).into_iter();
+    assert_eq!(Some(b"lo world".to_vec()), chunk(frames.next().unwrap()));
+    assert_eq!(None, chunk(frames.next().unwrap()));
+
+    match frames.next().unwrap() {
+        Frame::Message { message, body } => {
+            assert_eq!("ping", message);
+            assert!(!body);
+        }
+        _ => panic!("expected message frame"),
+    }
+
+    assert!(frames.next().is_none());
+    assert!(!codec.is_decoding_body());
+}
+
+#[test]
+fn test_delimited_body() {
+    let mut codec = codec();
+    let mut buf = EasyBuf::new();
+
+    buf.get_mut().extend_from_slice(b"stream -\nfoo;bar;;");
+
+    let mut frames = decode_all(&mut codec, &mut buf).into_iter();
+    assert_eq!("stream", frames.next().unwrap().unwrap_msg());
+    assert_eq!(Some(b"foo".to_vec()), chunk(frames.next().unwrap()));
+    assert_eq!(Some(b"bar".to_vec()), chunk(frames.next().unwrap()));
+    assert_eq!(None, chunk(frames.next().unwrap()));
+    assert!(frames.next().is_none());
+}
+
+#[test]
+fn test_eof_mid_body_is_an_error() {
+    let mut codec = codec();
+    let mut buf = EasyBuf::new();
+
+    buf.get_mut().extend_from_slice(b"upload 10\n");
+    codec.decode(&mut buf).unwrap().unwrap();
+
+    let err = codec.decode_eof(&mut buf).unwrap_err();
+    assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+}
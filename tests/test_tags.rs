@@ -0,0 +1,113 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net;
+use std::sync::mpsc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use futures::{Future, Stream};
+use futures::sync::oneshot;
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Core;
+use tokio_proto::{BindServer, Tags, TcpServer};
+
+mod support;
+use support::line::{LineProto, Echo};
+
+fn echo(conn: &mut BufReader<net::TcpStream>, line: &str) -> String {
+    writeln!(conn.get_mut(), "{}", line).unwrap();
+
+    let mut resp = String::new();
+    conn.read_line(&mut resp).unwrap();
+    resp
+}
+
+#[test]
+fn test_shutdown_tag() {
+    let tags = Tags::new();
+    let server_tags = tags.clone();
+
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+
+    let t = thread::spawn(move || {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let listener = TcpListener::bind(&addr, &handle).unwrap();
+        addr_tx.send(listener.local_addr().unwrap()).unwrap();
+
+        let server = listener.incoming().for_each(move |(socket, _)| {
+            // Tag by the order in which the peers connected
+            let tag = if server_tags.count(&"a") == 0 { "a" } else { "b" };
+
+            let socket = server_tags.tag(socket, tag);
+            LineProto.bind_server(&handle, socket, Echo(String::new()));
+            Ok(())
+        });
+
+        drop(core.run(server.select(stop_rx.then(|_| Ok(()))).map_err(|_| ())));
+    });
+
+    let addr = addr_rx.recv().unwrap();
+
+    let mut a = BufReader::new(support::connect(&addr));
+    assert_eq!("hello\n", echo(&mut a, "hello"));
+
+    let mut b = BufReader::new(support::connect(&addr));
+    assert_eq!("world\n", echo(&mut b, "world"));
+
+    assert_eq!(1, tags.count(&"a"));
+    assert_eq!(1, tags.shutdown_tag(&"a"));
+
+    // The connection tagged "a" is closed by the server
+    let mut rest = String::new();
+    assert_eq!(0, a.read_line(&mut rest).unwrap());
+
+    // The other connection is still served
+    assert_eq!("again\n", echo(&mut b, "again"));
+    assert_eq!(1, tags.count(&"b"));
+
+    stop_tx.complete(());
+    t.join().unwrap();
+}
+
+#[test]
+fn test_server_tags_accepted_connections() {
+    let addr = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+    let tags = Tags::new();
+    let server_tags = tags.clone();
+
+    thread::spawn(move || {
+        let accepted = AtomicUsize::new(0);
+
+        // Tag by the order in which the peers connected
+        TcpServer::new(LineProto, addr)
+            .tag(server_tags, move |_| {
+                if accepted.fetch_add(1, Ordering::SeqCst) == 0 { "a" } else { "b" }
+            })
+            .serve(|| Ok(Echo(String::new())));
+    });
+
+    let mut a = BufReader::new(support::connect(&addr));
+    assert_eq!("hello\n", echo(&mut a, "hello"));
+
+    let mut b = BufReader::new(support::connect(&addr));
+    assert_eq!("world\n", echo(&mut b, "world"));
+
+    assert_eq!(1, tags.shutdown_tag(&"a"));
+
+    let mut rest = String::new();
+    assert_eq!(0, a.read_line(&mut rest).unwrap());
+
+    assert_eq!("again\n", echo(&mut b, "again"));
+    assert_eq!(1, tags.count(&"b"));
+}
@@ -0,0 +1,56 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::collections::HashSet;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net;
+use std::thread;
+
+use futures::future::{self, FutureResult};
+use tokio_proto::TcpServer;
+use tokio_service::Service;
+
+mod support;
+use support::line::LineProto;
+
+// Answers with the name of the thread running the connection
+struct ThreadName;
+
+impl Service for ThreadName {
+    type Request = String;
+    type Response = String;
+    type Error = io::Error;
+    type Future = FutureResult<String, io::Error>;
+
+    fn call(&self, _: String) -> Self::Future {
+        future::ok(thread::current().name().unwrap_or("main").to_string())
+    }
+}
+
+#[test]
+fn test_connections_spread_across_threads() {
+    let addr = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut server = TcpServer::new(LineProto, addr);
+        server.threads(4);
+        server.serve(|| Ok(ThreadName));
+    });
+
+    let mut threads = HashSet::new();
+
+    for _ in 0..32 {
+        let mut conn = support::connect(&addr);
+        conn.write_all(b"hello\n").unwrap();
+
+        let mut line = String::new();
+        BufReader::new(conn).read_line(&mut line).unwrap();
+        threads.insert(line);
+    }
+
+    assert!(threads.len() > 1, "all connections served by {:?}", threads);
+}
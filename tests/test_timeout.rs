@@ -0,0 +1,170 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io::{self, Read};
+use std::net;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::{Future, Stream};
+use futures::sync::oneshot;
+use tokio_core::io::write_all;
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::Core;
+use tokio_proto::{BindServer, TcpClient};
+use tokio_proto::timeout::{Deadline, IoTimeouts, RelativeDeadline, StalledWrite, TimeoutIo};
+use tokio_service::Service;
+
+mod support;
+use support::line::{LineProto, Echo};
+
+#[test]
+fn test_client_read_timeout() {
+    // A peer that accepts the connection but never answers
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (done_tx, done_rx) = oneshot::channel::<()>();
+    let t = thread::spawn(move || {
+        let _socket = listener.accept().unwrap();
+        let _ = done_rx.wait();
+    });
+
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let timeouts = IoTimeouts {
+        read: Some(Duration::from_millis(100)),
+        write: None,
+    };
+
+    let connect = TcpClient::new(LineProto).io_timeouts(&timeouts).connect(&addr, &handle);
+    let client = core.run(connect).unwrap();
+    let errors = client.errors();
+
+    let start = Instant::now();
+    let res = core.run(client.call("hello".to_string()));
+    assert!(res.is_err());
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    let (error, _) = core.run(errors.into_future()).ok().unwrap();
+    assert_eq!(io::ErrorKind::TimedOut, error.unwrap().kind());
+
+    done_tx.complete(());
+    t.join().unwrap();
+}
+
+#[test]
+fn test_server_closes_idle_connections() {
+    let (addr_tx, addr_rx) = oneshot::channel();
+    let (done_tx, done_rx) = oneshot::channel::<()>();
+
+    let t = thread::spawn(move || {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let listener = TcpListener::bind(&addr, &handle).unwrap();
+        addr_tx.complete(listener.local_addr().unwrap());
+
+        let timeouts = IoTimeouts {
+            read: Some(Duration::from_millis(100)),
+            write: Some(Duration::from_millis(100)),
+        };
+
+        let server_handle = handle.clone();
+        let server = listener.incoming().for_each(move |(socket, _)| {
+            let socket = TimeoutIo::new(socket, &timeouts, &server_handle);
+            LineProto.bind_server(&server_handle, socket, Echo(String::new()));
+            Ok(())
+        });
+        handle.spawn(server.map_err(|e| panic!("{}", e)));
+
+        let _ = core.run(done_rx);
+    });
+
+    let addr = addr_rx.wait().unwrap();
+    let mut socket = net::TcpStream::connect(addr).unwrap();
+    socket.set_read_timeout(Some(support::DEADLINE)).unwrap();
+
+    // Requests are served as usual
+    io::Write::write_all(&mut socket, b"hello\n").unwrap();
+    let mut buf = [0; 6];
+    socket.read_exact(&mut buf).unwrap();
+    assert_eq!(b"hello\n", &buf);
+
+    // Staying silent, the server hangs up
+    let start = Instant::now();
+    assert_eq!(0, socket.read(&mut buf).unwrap());
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    done_tx.complete(());
+    t.join().unwrap();
+}
+
+#[test]
+fn test_relative_deadline_round_trip() {
+    let deadline = Instant::now() + Duration::from_secs(10);
+
+    // Sent as the time left, so the clock of the receiving end doesn't matter
+    let millis = RelativeDeadline::from_instant(deadline).as_millis();
+    assert!(millis > 9_000 && millis <= 10_000);
+
+    let decoded = RelativeDeadline::from_millis(millis).to_instant();
+    assert!(decoded <= Instant::now() + Duration::from_secs(10));
+    assert!(decoded > Instant::now() + Duration::from_secs(9));
+
+    let passed = RelativeDeadline::from_instant(Instant::now() - Duration::from_secs(1));
+    assert!(passed.is_expired());
+    assert_eq!(0, passed.as_millis());
+}
+
+#[test]
+fn test_decoded_deadline_fails_future() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let deadline = RelativeDeadline::from_millis(50).to_instant();
+    let never = futures::empty::<(), io::Error>();
+
+    let err = core.run(Deadline::at(never, Some(deadline), &handle)).unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+    assert!(Instant::now() >= deadline);
+}
+
+#[test]
+fn test_stalled_write_reports_pending_bytes() {
+    // A peer that accepts the connection but never reads
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (done_tx, done_rx) = oneshot::channel::<()>();
+    let t = thread::spawn(move || {
+        let _socket = listener.accept().unwrap();
+        let _ = done_rx.wait();
+    });
+
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let timeouts = IoTimeouts {
+        read: None,
+        write: Some(Duration::from_millis(100)),
+    };
+
+    let socket = core.run(TcpStream::connect(&addr, &handle)).unwrap();
+    let socket = TimeoutIo::new(socket, &timeouts, &handle);
+
+    // More than the socket buffers of both ends hold
+    let err = core.run(write_all(socket, vec![0; 64 * 1024 * 1024])).err().unwrap();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+
+    let stalled = err.get_ref().unwrap().downcast_ref::<StalledWrite>().unwrap();
+    assert!(stalled.written() > 0);
+    assert_eq!(64 * 1024 * 1024, stalled.written() as usize + stalled.pending());
+
+    done_tx.complete(());
+    t.join().unwrap();
+}
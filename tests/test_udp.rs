@@ -0,0 +1,198 @@
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::io;
+use std::net::{self, SocketAddr};
+use std::str;
+use std::thread;
+use std::time::Duration;
+
+use futures::Future;
+use futures::future::{self, FutureResult};
+use tokio_core::reactor::Core;
+use tokio_proto::{UdpClient, UdpServer};
+use tokio_proto::streaming::multiplex::Counter;
+use tokio_proto::udp::{Backoff, DatagramCodec};
+use tokio_service::Service;
+
+// Datagrams of the form `id text`. Responses to `unencodable` can't be
+// encoded, those to `huge` don't fit in a datagram.
+struct IdCodec;
+
+impl DatagramCodec for IdCodec {
+    type RequestId = u64;
+    type In = String;
+    type Out = String;
+
+    fn decode(&self, buf: &[u8]) -> io::Result<(u64, String)> {
+        let datagram = try!(str::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+        let mut parts = datagram.splitn(2, ' ');
+        let id = try!(parts.next().unwrap().parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "bad id")));
+
+        Ok((id, parts.next().unwrap_or("").to_string()))
+    }
+
+    fn encode(&self, id: u64, msg: String, buf: &mut Vec<u8>) -> io::Result<()> {
+        if msg == "echo:unencodable" {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unencodable"));
+        }
+
+        if msg == "echo:huge" {
+            buf.resize(100_000, b'x');
+            return Ok(());
+        }
+
+        buf.extend_from_slice(format!("{} {}", id, msg).as_bytes());
+        Ok(())
+    }
+}
+
+struct Echo;
+
+impl Service for Echo {
+    type Request = String;
+    type Response = String;
+    type Error = io::Error;
+    type Future = FutureResult<String, io::Error>;
+
+    fn call(&self, req: String) -> Self::Future {
+        if req == "fail" {
+            return future::err(io::Error::new(io::ErrorKind::Other, "failed"));
+        }
+
+        future::ok(format!("echo:{}", req))
+    }
+}
+
+fn free_addr() -> SocketAddr {
+    net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+}
+
+fn client(retransmit: Backoff) -> UdpClient<IdCodec, Counter> {
+    let mut client = UdpClient::new(IdCodec, Counter::new());
+    client.retransmit(retransmit);
+    client
+}
+
+#[test]
+fn test_udp_echo() {
+    let addr = free_addr();
+
+    thread::spawn(move || {
+        UdpServer::new(IdCodec, addr).serve(|| Ok(Echo)).unwrap();
+    });
+
+    let mut core = Core::new().unwrap();
+
+    // Retransmitting covers the server still starting up
+    let service = client(Backoff::new(Duration::from_millis(50), 20))
+        .connect(&addr, &core.handle())
+        .unwrap();
+
+    let responses = core.run(service.call("one".to_string())
+                                    .join(service.call("two".to_string())))
+                        .unwrap();
+    assert_eq!(("echo:one".to_string(), "echo:two".to_string()), responses);
+
+    // Failed requests go unanswered
+    let service = client(Backoff::new(Duration::from_millis(20), 2))
+        .connect(&addr, &core.handle())
+        .unwrap();
+
+    let err = core.run(service.call("fail".to_string())).unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+
+    let res = core.run(service.call("three".to_string())).unwrap();
+    assert_eq!("echo:three", res);
+
+    // So do unencodable responses, without failing the server
+    let err = core.run(service.call("unencodable".to_string())).unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+
+    let res = core.run(service.call("four".to_string())).unwrap();
+    assert_eq!("echo:four", res);
+}
+
+#[test]
+fn test_serve_returns_socket_error() {
+    let addr = free_addr();
+
+    let server = thread::spawn(move || {
+        UdpServer::new(IdCodec, addr).serve(|| Ok(Echo))
+    });
+
+    // Sending the response fails, which fails the server
+    let client = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    while !server.is_finished() {
+        client.send_to(b"0 huge", addr).unwrap();
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    assert!(server.join().unwrap().is_err());
+}
+
+#[test]
+fn test_retransmits_and_checks_peer() {
+    let server = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let seen = thread::spawn(move || {
+        let mut buf = [0; 512];
+        let mut seen = vec![];
+
+        // Ignores the first attempt, answers the second one from another
+        // socket first
+        for _ in 0..2 {
+            let (n, peer) = server.recv_from(&mut buf).unwrap();
+            seen.push(String::from_utf8(buf[..n].to_vec()).unwrap());
+
+            if seen.len() == 2 {
+                let other = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+                other.send_to(b"0 spoofed", peer).unwrap();
+                server.send_to(b"0 genuine", peer).unwrap();
+                server.send_to(b"0 duplicate", peer).unwrap();
+            }
+        }
+
+        seen
+    });
+
+    let mut core = Core::new().unwrap();
+    let service = client(Backoff::new(Duration::from_millis(50), 3))
+        .connect(&addr, &core.handle())
+        .unwrap();
+
+    let res = core.run(service.call("hello".to_string())).unwrap();
+    assert_eq!("genuine", res);
+
+    assert_eq!(vec!["0 hello", "0 hello"], seen.join().unwrap());
+}
+
+#[test]
+fn test_gives_up_after_attempts() {
+    let server = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    server.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let mut core = Core::new().unwrap();
+    let service = client(Backoff::new(Duration::from_millis(10), 3))
+        .connect(&addr, &core.handle())
+        .unwrap();
+
+    let err = core.run(service.call("hello".to_string())).unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+
+    let mut buf = [0; 512];
+    let mut attempts = 0;
+
+    while server.recv_from(&mut buf).is_ok() {
+        attempts += 1;
+    }
+
+    assert_eq!(3, attempts);
+}
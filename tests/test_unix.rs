@@ -0,0 +1,100 @@
+#![cfg(all(unix, feature = "unix"))]
+#![allow(deprecated)]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use tokio_core::reactor::Core;
+use tokio_proto::{multiplex, pipeline, UnixClient, UnixServer};
+use tokio_service::Service;
+
+mod support;
+use support::line::{LineProto, MuxLineProto, Echo};
+
+fn socket_path(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("tokio-proto-{}-{}.sock", name, process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+fn wait_for(path: &Path) {
+    // The server may still be starting up
+    for _ in 0..100 {
+        if path.exists() {
+            return;
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    panic!("server did not start");
+}
+
+#[test]
+fn test_pipeline_over_unix_socket() {
+    let path = socket_path("pipeline");
+
+    let server_path = path.clone();
+    thread::spawn(move || {
+        let server = UnixServer::<pipeline::Pipeline, _>::new(LineProto, server_path);
+        server.serve(|| Ok(Echo("echo:".to_string()))).unwrap();
+    });
+
+    wait_for(&path);
+
+    let mut core = Core::new().unwrap();
+    let client = UnixClient::<pipeline::Pipeline, _>::new(LineProto)
+        .connect(&path, &core.handle())
+        .unwrap();
+
+    let res = core.run(client.call("hello".to_string())).unwrap();
+    assert_eq!("echo:hello", res);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_multiplex_over_unix_socket() {
+    let path = socket_path("multiplex");
+
+    let server_path = path.clone();
+    thread::spawn(move || {
+        let server = UnixServer::<multiplex::Multiplex, _>::new(MuxLineProto, server_path);
+        server.serve(|| Ok(Echo("echo:".to_string()))).unwrap();
+    });
+
+    wait_for(&path);
+
+    let mut core = Core::new().unwrap();
+    let client = UnixClient::<multiplex::Multiplex, _>::new(MuxLineProto)
+        .connect(&path, &core.handle())
+        .unwrap();
+
+    let responses = vec!["a", "b"].into_iter().map(|req| {
+        client.call(req.to_string())
+    }).collect::<Vec<_>>();
+
+    let responses = core.run(futures::future::join_all(responses)).unwrap();
+    assert_eq!(vec!["echo:a", "echo:b"], responses);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_connect_without_server_fails() {
+    let path = socket_path("missing");
+
+    let core = Core::new().unwrap();
+    let client = UnixClient::<pipeline::Pipeline, _>::new(LineProto);
+
+    assert!(client.connect(&path, &core.handle()).is_err());
+}